@@ -0,0 +1,95 @@
+/// `master` with `base_len` commits and `stacks` branches, each `stack_len` commits off of it
+fn repo(base_len: usize, stacks: usize, stack_len: usize) -> git_stack::git::InMemoryRepo {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let commit = |repo: &mut git_stack::git::InMemoryRepo, parent_id, summary: String| {
+        let id = repo.gen_id();
+        repo.push_commit(
+            parent_id,
+            git_stack::git::Commit {
+                id,
+                tree_id: id,
+                summary: summary.into(),
+                time: std::time::SystemTime::now(),
+                author: Some(std::rc::Rc::from("bench")),
+                committer: Some(std::rc::Rc::from("bench")),
+            },
+        );
+        id
+    };
+    let branch = |name: String, id| git_stack::git::Branch {
+        name,
+        remote: None,
+        id,
+        push_id: None,
+        pull_id: None,
+    };
+
+    let mut base_ids = Vec::with_capacity(base_len);
+    let mut parent_id = None;
+    for i in 0..base_len {
+        let id = commit(&mut repo, parent_id, format!("master {}", i));
+        base_ids.push(id);
+        parent_id = Some(id);
+    }
+    repo.mark_branch(branch("master".to_owned(), parent_id.unwrap()));
+
+    for s in 0..stacks {
+        // Spread the stacks across `master`'s history, like real, stale branches
+        let mut parent_id = base_ids[s * base_len / stacks.max(1)];
+        for c in 0..stack_len {
+            parent_id = commit(&mut repo, Some(parent_id), format!("stack {} {}", s, c));
+        }
+        repo.mark_branch(branch(format!("feature{}", s), parent_id));
+    }
+
+    repo
+}
+
+fn graph(repo: &git_stack::git::InMemoryRepo) -> git_stack::graph::Graph {
+    let master = repo.find_local_branch("master").unwrap();
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master);
+    let branches = git_stack::git::Branches::new(repo.local_branches());
+
+    let mut graph = git_stack::graph::Graph::from_branches(repo, branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, repo, &protected_branches);
+    graph
+}
+
+fn bench_graph(c: &mut criterion::Criterion) {
+    let mut group = c.benchmark_group("graph");
+    for stacks in [10, 100, 1000] {
+        let repo = repo(1000, stacks, 5);
+        group.bench_with_input(
+            criterion::BenchmarkId::new("build", stacks),
+            &repo,
+            |b, repo| b.iter(|| graph(repo)),
+        );
+    }
+    group.finish();
+}
+
+fn bench_plan(c: &mut criterion::Criterion) {
+    let mut group = c.benchmark_group("plan");
+    for stacks in [10, 100, 1000] {
+        let repo = repo(1000, stacks, 5);
+        let master_id = repo.find_local_branch("master").unwrap().id;
+        let graph = graph(&repo);
+        group.bench_with_input(
+            criterion::BenchmarkId::new("rebase", stacks),
+            &graph,
+            |b, graph| {
+                b.iter(|| {
+                    let mut graph = graph.clone();
+                    git_stack::graph::rebase_development_branches(&mut graph, master_id);
+                    git_stack::graph::pushable(&mut graph);
+                    git_stack::graph::to_script(&graph)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion::criterion_group!(benches, bench_graph, bench_plan);
+criterion::criterion_main!(benches);
@@ -0,0 +1,127 @@
+/// `master` with `base_len` commits and `stacks` branches, each `stack_len` commits off of it
+///
+/// Every commit changes a file, so patch-ids have something to hash.
+fn repo(path: &std::path::Path, base_len: usize, stacks: usize, stack_len: usize) {
+    let repo = git2::Repository::init(path).unwrap();
+    let signature = git2::Signature::now("bench", "bench@example.com").unwrap();
+    let commit = |parent_id: Option<git2::Oid>, summary: String| {
+        let parent = parent_id.map(|id| repo.find_commit(id).unwrap());
+        let mut tree = repo
+            .treebuilder(parent.as_ref().map(|p| p.tree().unwrap()).as_ref())
+            .unwrap();
+        let blob = repo.blob(summary.as_bytes()).unwrap();
+        tree.insert("file.txt", blob, 0o100644).unwrap();
+        let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(None, &signature, &signature, &summary, &tree, &parents)
+            .unwrap()
+    };
+
+    let mut base_ids = Vec::with_capacity(base_len);
+    let mut parent_id = None;
+    for i in 0..base_len {
+        let id = commit(parent_id, format!("master {}", i));
+        base_ids.push(id);
+        parent_id = Some(id);
+    }
+    repo.reference("refs/heads/master", parent_id.unwrap(), true, "bench")
+        .unwrap();
+
+    for s in 0..stacks {
+        // Spread the stacks across `master`'s history, like real, stale branches
+        let mut parent_id = base_ids[s * base_len / stacks.max(1)];
+        for c in 0..stack_len {
+            parent_id = commit(Some(parent_id), format!("stack {} {}", s, c));
+        }
+        repo.reference(
+            &format!("refs/heads/feature{}", s),
+            parent_id,
+            true,
+            "bench",
+        )
+        .unwrap();
+    }
+}
+
+fn open(path: &std::path::Path, jobs: Option<usize>) -> git_stack::git::GitRepo {
+    let mut repo = git_stack::git::GitRepo::new(git2::Repository::open(path).unwrap());
+    repo.set_jobs(jobs);
+    repo
+}
+
+/// Each branch paired with `master`, like finding each branch's protected base
+fn pairs(path: &std::path::Path) -> Vec<(git2::Oid, git2::Oid)> {
+    let repo = open(path, None);
+    let master_id = repo.find_local_branch("master").unwrap().id;
+    repo.local_branches()
+        .filter(|b| b.name != "master")
+        .map(|b| (master_id, b.id))
+        .collect()
+}
+
+fn bench_merge_bases(c: &mut criterion::Criterion) {
+    let mut group = c.benchmark_group("merge_bases");
+    for stacks in [4, 16, 64] {
+        let temp = assert_fs::TempDir::new().unwrap();
+        repo(temp.path(), 1000, stacks, 3);
+        let pairs = pairs(temp.path());
+        for (name, jobs) in [("serial", Some(1)), ("parallel", Some(4))] {
+            group.bench_with_input(
+                criterion::BenchmarkId::new(name, stacks),
+                &pairs,
+                |b, pairs| {
+                    b.iter_batched(
+                        || open(temp.path(), jobs),
+                        |repo| {
+                            repo.prefetch_merge_bases(pairs);
+                            for (one, two) in pairs {
+                                repo.merge_base(*one, *two);
+                            }
+                        },
+                        criterion::BatchSize::SmallInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_patch_ids(c: &mut criterion::Criterion) {
+    let mut group = c.benchmark_group("patch_ids");
+    for commits in [4, 16, 64] {
+        let temp = assert_fs::TempDir::new().unwrap();
+        repo(temp.path(), commits, 0, 0);
+        let ids: Vec<_> = open(temp.path(), None)
+            .commits_from(
+                open(temp.path(), None)
+                    .find_local_branch("master")
+                    .unwrap()
+                    .id,
+            )
+            .map(|c| c.id)
+            .collect();
+        for (name, jobs) in [("serial", Some(1)), ("parallel", Some(4))] {
+            group.bench_with_input(
+                criterion::BenchmarkId::new(name, commits),
+                &ids,
+                |b, ids| {
+                    b.iter_batched(
+                        || open(temp.path(), jobs),
+                        |repo| {
+                            repo.prefetch_patch_ids(ids);
+                            for id in ids {
+                                repo.patch_id(*id);
+                            }
+                        },
+                        criterion::BatchSize::SmallInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion::criterion_group!(benches, bench_merge_bases, bench_patch_ids);
+criterion::criterion_main!(benches);
@@ -42,7 +42,7 @@ fn run() -> proc_exit::ExitResult {
         if schema_path == std::path::Path::new("-") {
             std::io::stdout().write_all(schema.as_bytes())?;
         } else {
-            std::fs::write(&schema_path, &schema).with_code(proc_exit::Code::FAILURE)?;
+            std::fs::write(schema_path, &schema).with_code(proc_exit::Code::FAILURE)?;
         }
     }
     Ok(())
@@ -54,7 +54,7 @@ mod test {
 
     #[test]
     fn verify_app() {
-        use clap::IntoApp;
-        Args::into_app().debug_assert()
+        use clap::CommandFactory;
+        Args::command().debug_assert()
     }
 }
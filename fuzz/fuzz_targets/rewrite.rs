@@ -0,0 +1,296 @@
+//! Plan and execute a rewrite of random branch topologies, checking nothing is lost along the way
+#![no_main]
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+use libfuzzer_sys::fuzz_target;
+
+/// Keep runs fast; interesting topologies don't need many commits
+const MAX_COMMITS: usize = 64;
+
+#[derive(Debug, arbitrary::Arbitrary)]
+struct Input {
+    commits: Vec<Commit>,
+    /// Picks the protected branch to rebase onto
+    onto: u8,
+    fixup: Fixup,
+    /// Whether to `merge_stacks` and `realign_stacks`, like `stack.stack = all`
+    stack: bool,
+}
+
+#[derive(Debug, arbitrary::Arbitrary)]
+struct Commit {
+    /// Picks among the earlier commits
+    parent: u8,
+    /// Picks among the earlier commits to be a `fixup!` of
+    fixup_of: Option<u8>,
+    branch: Branch,
+    /// Seconds since the epoch, for ordering stacks
+    time: u16,
+}
+
+#[derive(Debug, arbitrary::Arbitrary)]
+enum Branch {
+    None,
+    Development,
+    Protected,
+}
+
+#[derive(Debug, arbitrary::Arbitrary)]
+enum Fixup {
+    Ignore,
+    Move,
+    Squash,
+}
+
+impl From<&Fixup> for git_stack::config::Fixup {
+    fn from(fixup: &Fixup) -> Self {
+        match fixup {
+            Fixup::Ignore => Self::Ignore,
+            Fixup::Move => Self::Move,
+            Fixup::Squash => Self::Squash,
+        }
+    }
+}
+
+fuzz_target!(|input: Input| {
+    check(&input);
+});
+
+fn check(input: &Input) {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let mut protected_branches = git_stack::git::Branches::default();
+    let mut graphed_branches = git_stack::git::Branches::default();
+
+    let root_id = push_commit(&mut repo, None, "commit 0".into(), 0);
+    let mut ids = vec![root_id];
+    let master = mark_branch(&mut repo, "master".to_owned(), root_id);
+    protected_branches.insert(master.clone());
+    graphed_branches.insert(master);
+    for commit in input.commits.iter().take(MAX_COMMITS) {
+        let index = ids.len();
+        let parent_id = ids[usize::from(commit.parent) % index];
+        let summary = match commit.fixup_of {
+            Some(target) => format!("fixup! commit {}", usize::from(target) % index),
+            None => format!("commit {}", index),
+        };
+        let id = push_commit(&mut repo, Some(parent_id), summary, commit.time);
+        ids.push(id);
+        match commit.branch {
+            Branch::None => {}
+            Branch::Development => {
+                let branch = mark_branch(&mut repo, format!("dev{}", index), id);
+                graphed_branches.insert(branch);
+            }
+            Branch::Protected => {
+                let branch = mark_branch(&mut repo, format!("main{}", index), id);
+                protected_branches.insert(branch.clone());
+                graphed_branches.insert(branch);
+            }
+        }
+    }
+    repo.set_head(root_id);
+
+    let protected_ids: Vec<_> = protected_branches.oids().collect();
+    let onto_id = protected_ids[usize::from(input.onto) % protected_ids.len()];
+    let before = Snapshot::new(&repo, &graphed_branches, &protected_branches);
+
+    let mut graph = git_stack::graph::Graph::from_branches(&repo, graphed_branches.clone())
+        .expect("there is always `master`");
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+    git_stack::graph::rebase_development_branches(&mut graph, onto_id);
+    git_stack::graph::fixup(&mut graph, (&input.fixup).into());
+    if input.stack {
+        git_stack::graph::merge_stacks(&mut graph);
+        git_stack::graph::realign_stacks(&mut graph);
+    }
+    let script = git_stack::graph::to_script(&graph);
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    let failures = executor.run_script(&mut repo, &script);
+    assert!(failures.is_empty(), "{:?}", failures);
+    executor.close(&mut repo, "master").unwrap();
+
+    for (name, id) in before.protected.iter() {
+        let branch = repo
+            .find_local_branch(name)
+            .expect("protected branches are kept");
+        assert_eq!(branch.id, *id, "protected `{}` moved", name);
+    }
+    let protected_commits = protected_commits(&repo, &protected_branches);
+
+    let mut after = BTreeMap::new();
+    for name in before.development.keys() {
+        let branch = repo
+            .find_local_branch(name)
+            .unwrap_or_else(|| panic!("`{}` was deleted", name));
+        after.insert(name.as_str(), branch.id);
+
+        let trees = development_trees(&repo, branch.id, &protected_commits);
+        let expected = &before.development[name];
+        for tree_id in expected.iter() {
+            if matches!(input.fixup, Fixup::Squash) && before.fixups.contains(tree_id) {
+                continue;
+            }
+            assert!(trees.contains(tree_id), "`{}` lost {}", name, tree_id);
+        }
+        for tree_id in trees.iter() {
+            assert!(
+                before.all.contains(tree_id),
+                "`{}` gained unknown {}",
+                name,
+                tree_id
+            );
+        }
+        if !expected.is_empty() {
+            assert!(
+                repo.contains_commit(branch.id, onto_id).unwrap(),
+                "`{}` wasn't rebased onto {}",
+                name,
+                onto_id
+            );
+        }
+    }
+
+    for (ancestor, descendant) in before.stacked.iter() {
+        assert!(
+            repo.contains_commit(after[descendant.as_str()], after[ancestor.as_str()])
+                .unwrap(),
+            "`{}` is no longer stacked on `{}`",
+            descendant,
+            ancestor
+        );
+    }
+}
+
+struct Snapshot {
+    protected: BTreeMap<String, git2::Oid>,
+    /// Trees of each development branch's own commits
+    development: BTreeMap<String, BTreeSet<git2::Oid>>,
+    /// `(ancestor, descendant)` development branches
+    stacked: Vec<(String, String)>,
+    fixups: BTreeSet<git2::Oid>,
+    all: BTreeSet<git2::Oid>,
+}
+
+impl Snapshot {
+    fn new(
+        repo: &git_stack::git::InMemoryRepo,
+        graphed_branches: &git_stack::git::Branches,
+        protected_branches: &git_stack::git::Branches,
+    ) -> Self {
+        let protected_commits = protected_commits(repo, protected_branches);
+        let protected: BTreeMap<_, _> = protected_branches
+            .iter()
+            .flat_map(|(_, branches)| branches.iter())
+            .map(|b| (b.name.clone(), b.id))
+            .collect();
+        let development: BTreeMap<_, _> = graphed_branches
+            .iter()
+            .flat_map(|(_, branches)| branches.iter())
+            .filter(|b| !protected.contains_key(&b.name))
+            .map(|b| {
+                let trees = development_trees(repo, b.id, &protected_commits);
+                (b.name.clone(), (b.id, trees))
+            })
+            .collect();
+
+        let mut stacked = Vec::new();
+        for (ancestor, (ancestor_id, ancestor_trees)) in development.iter() {
+            // Branches on protected commits are left behind by design
+            if ancestor_trees.is_empty() {
+                continue;
+            }
+            for (descendant, (descendant_id, _)) in development.iter() {
+                if ancestor_id != descendant_id
+                    && repo.contains_commit(*descendant_id, *ancestor_id).unwrap()
+                {
+                    stacked.push((ancestor.clone(), descendant.clone()));
+                }
+            }
+        }
+
+        let mut fixups = BTreeSet::new();
+        let mut all = BTreeSet::new();
+        for (_, branches) in graphed_branches.iter() {
+            for branch in branches {
+                for commit in repo.commits_from(branch.id) {
+                    if commit.fixup_summary().is_some() {
+                        fixups.insert(commit.tree_id);
+                    }
+                    all.insert(commit.tree_id);
+                }
+            }
+        }
+
+        Self {
+            protected,
+            development: development
+                .into_iter()
+                .map(|(name, (_, trees))| (name, trees))
+                .collect(),
+            stacked,
+            fixups,
+            all,
+        }
+    }
+}
+
+fn push_commit(
+    repo: &mut git_stack::git::InMemoryRepo,
+    parent_id: Option<git2::Oid>,
+    summary: String,
+    time: u16,
+) -> git2::Oid {
+    let id = repo.gen_id();
+    let commit = git_stack::git::Commit {
+        id,
+        // Unique content, so it can be tracked across rewrites
+        tree_id: id,
+        summary: bstr::BString::from(summary),
+        time: std::time::UNIX_EPOCH + std::time::Duration::from_secs(u64::from(time)),
+        author: Some(std::rc::Rc::from("fuzz")),
+        committer: Some(std::rc::Rc::from("fuzz")),
+    };
+    repo.push_commit(parent_id, commit);
+    id
+}
+
+fn mark_branch(
+    repo: &mut git_stack::git::InMemoryRepo,
+    name: String,
+    id: git2::Oid,
+) -> git_stack::git::Branch {
+    let branch = git_stack::git::Branch {
+        name,
+        remote: None,
+        id,
+        push_id: None,
+        pull_id: None,
+    };
+    repo.mark_branch(branch.clone());
+    branch
+}
+
+fn protected_commits(
+    repo: &git_stack::git::InMemoryRepo,
+    protected_branches: &git_stack::git::Branches,
+) -> BTreeSet<git2::Oid> {
+    protected_branches
+        .oids()
+        .flat_map(|id| repo.commits_from(id))
+        .map(|c| c.id)
+        .collect()
+}
+
+fn development_trees(
+    repo: &git_stack::git::InMemoryRepo,
+    id: git2::Oid,
+    protected_commits: &BTreeSet<git2::Oid>,
+) -> BTreeSet<git2::Oid> {
+    repo.commits_from(id)
+        .take_while(|c| !protected_commits.contains(&c.id))
+        .map(|c| c.tree_id)
+        .collect()
+}
@@ -8,7 +8,10 @@ use proc_exit::WithCodeResultExt;
 mod args;
 
 fn main() {
-    human_panic::setup_panic!();
+    #[allow(deprecated)]
+    {
+        human_panic::setup_panic!();
+    }
     let result = run();
     proc_exit::exit(result);
 }
@@ -31,7 +34,8 @@ fn run() -> proc_exit::ExitResult {
     let colored_stdout = concolor::get(concolor::Stream::Stdout).ansi_color();
     let colored_stderr = concolor::get(concolor::Stream::Stderr).ansi_color();
 
-    git_stack::log::init_logging(args.verbose.clone(), colored_stderr);
+    git_stack::log::init_logging(args.verbose.clone(), colored_stderr, &[], None)
+        .with_code(proc_exit::Code::USAGE_ERR)?;
 
     let subcommand = args.subcommand;
     let push_args = args.push;
@@ -0,0 +1,226 @@
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+/// Where Graphite keeps the parent of each branch it tracks, one JSON blob per branch
+const GRAPHITE_METADATA_PREFIX: &str = "refs/branch-metadata/";
+
+pub fn adopt(
+    args: &crate::args::Args,
+    adopt_args: &crate::args::AdoptArgs,
+) -> proc_exit::ExitResult {
+    match adopt_args.from.as_str() {
+        "graphite" => graphite(args),
+        from => {
+            Err(proc_exit::Code::USAGE_ERR.with_message(format!("Can't adopt from `{}`", from)))
+        }
+    }
+}
+
+/// Record Graphite's parent branches as upstreams, the way `git-stack` knows a branch's parent
+fn graphite(args: &crate::args::Args) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let parents = graphite_parents(&repo).with_code(proc_exit::Code::FAILURE)?;
+    if parents.is_empty() {
+        log::info!("No Graphite metadata found");
+        return Ok(());
+    }
+
+    let repo_config =
+        git_stack::config::RepoConfig::from_all(&repo).with_code(proc_exit::Code::CONFIG_ERR)?;
+    if let Some(trunk) = graphite_trunk(&repo) {
+        protect_trunk(args, &repo, &repo_config, &trunk)?;
+    }
+    let ignored =
+        git_stack::git::BranchGlobs::new(repo_config.ignore_branches().iter().map(|s| s.as_str()))
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
+
+    let mut stdout = std::io::stdout();
+    let mut config = repo
+        .config()
+        .and_then(|c| c.open_level(git2::ConfigLevel::Local))
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+    let mut adopted = 0;
+    let mut restack = Vec::new();
+    for (name, parent) in parents.iter() {
+        if ignored.is_match(name) {
+            log::debug!("Skipping {}, `stack.ignore-branch` leaves it out", name);
+            continue;
+        }
+        if ignored.is_match(&parent.name) {
+            log::warn!(
+                "Skipping {}, its parent `{}` is left out by `stack.ignore-branch`",
+                name,
+                parent.name
+            );
+            continue;
+        }
+        let branch = match repo.find_branch(name, git2::BranchType::Local) {
+            Ok(branch) => branch,
+            Err(_) => {
+                log::debug!("Skipping {}, the branch no longer exists", name);
+                continue;
+            }
+        };
+        let parent_branch = match repo.find_branch(&parent.name, git2::BranchType::Local) {
+            Ok(parent_branch) => parent_branch,
+            Err(_) => {
+                log::warn!(
+                    "Skipping {}, its parent `{}` no longer exists",
+                    name,
+                    parent.name
+                );
+                continue;
+            }
+        };
+
+        let merge_key = format!("branch.{}.merge", name);
+        let remote_key = format!("branch.{}.remote", name);
+        match config.get_string(&merge_key) {
+            Ok(merge) if merge == format!("refs/heads/{}", parent.name) => {
+                log::debug!("{} already tracks {}", name, parent.name);
+            }
+            Ok(merge) => {
+                // Most likely how the branch gets pushed, which we must not break
+                log::warn!(
+                    "Leaving {} tracking `{}`, Graphite stacks it on {}",
+                    name,
+                    merge.strip_prefix("refs/heads/").unwrap_or(&merge),
+                    parent.name
+                );
+            }
+            Err(_) => {
+                log::trace!("git branch --set-upstream-to={} {}", parent.name, name);
+                if !args.dry_run {
+                    config
+                        .set_str(&remote_key, ".")
+                        .with_code(proc_exit::Code::FAILURE)?;
+                    config
+                        .set_str(&merge_key, &format!("refs/heads/{}", parent.name))
+                        .with_code(proc_exit::Code::FAILURE)?;
+                }
+                writeln!(stdout, "{} is stacked on {}", name, parent.name)?;
+                adopted += 1;
+            }
+        }
+
+        // Graphite restacks lazily, so children can still be on an old version of their parent
+        let branch_id = branch.get().target();
+        let parent_id = parent_branch.get().target();
+        if let (Some(branch_id), Some(parent_id)) = (branch_id, parent_id) {
+            let is_stacked = repo
+                .graph_descendant_of(branch_id, parent_id)
+                .unwrap_or(false)
+                || branch_id == parent_id;
+            if !is_stacked {
+                restack.push((name, parent));
+            }
+        }
+    }
+
+    if !restack.is_empty() {
+        let mut message = "These branches are not on top of their parent yet:".to_owned();
+        for (name, parent) in restack {
+            match parent.revision {
+                Some(revision) => message.push_str(&format!(
+                    "\n  git rebase --onto {} {} {}",
+                    parent.name, revision, name
+                )),
+                None => message.push_str(&format!("\n  {} (on {})", name, parent.name)),
+            }
+        }
+        log::warn!("{}", message);
+    }
+    log::info!("Adopted {} of {} Graphite branches", adopted, parents.len());
+
+    Ok(())
+}
+
+struct GraphiteParent {
+    name: String,
+    /// The parent's commit when Graphite last restacked the branch
+    revision: Option<git2::Oid>,
+}
+
+fn graphite_parents(
+    repo: &git2::Repository,
+) -> Result<std::collections::BTreeMap<String, GraphiteParent>, git2::Error> {
+    let mut parents = std::collections::BTreeMap::new();
+    for reference in repo.references_glob(&format!("{}*", GRAPHITE_METADATA_PREFIX))? {
+        let reference = reference?;
+        let name = match reference
+            .name()
+            .and_then(|n| n.strip_prefix(GRAPHITE_METADATA_PREFIX))
+        {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let metadata = match reference
+            .target()
+            .and_then(|id| repo.find_blob(id).ok())
+            .and_then(|blob| serde_json::from_slice::<serde_json::Value>(blob.content()).ok())
+        {
+            Some(metadata) => metadata,
+            None => {
+                log::warn!("Skipping {}, could not read its Graphite metadata", name);
+                continue;
+            }
+        };
+        // Trunk has no parent
+        let parent_name = match metadata.get("parentBranchName").and_then(|p| p.as_str()) {
+            Some(parent_name) => parent_name.to_owned(),
+            None => continue,
+        };
+        let revision = metadata
+            .get("parentBranchRevision")
+            .and_then(|r| r.as_str())
+            .and_then(|r| git2::Oid::from_str(r).ok());
+        parents.insert(
+            name,
+            GraphiteParent {
+                name: parent_name,
+                revision,
+            },
+        );
+    }
+    Ok(parents)
+}
+
+fn graphite_trunk(repo: &git2::Repository) -> Option<String> {
+    let path = repo.path().join(".graphite_repo_config");
+    let content = std::fs::read(&path).ok()?;
+    let config: serde_json::Value = serde_json::from_slice(&content).ok()?;
+    config.get("trunk")?.as_str().map(|t| t.to_owned())
+}
+
+fn protect_trunk(
+    args: &crate::args::Args,
+    repo: &git2::Repository,
+    repo_config: &git_stack::config::RepoConfig,
+    trunk: &str,
+) -> Result<(), proc_exit::Exit> {
+    let protected = git_stack::git::ProtectedBranches::new(
+        crate::forge::protected_patterns(repo, repo_config)
+            .iter()
+            .map(|s| s.as_str()),
+    )
+    .with_code(proc_exit::Code::CONFIG_ERR)?;
+    if protected.is_protected(trunk) {
+        return Ok(());
+    }
+
+    log::info!("Protecting Graphite's trunk, {}", trunk);
+    if !args.dry_run {
+        let mut local = git_stack::config::RepoConfig::load_for_write(repo)
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
+        local
+            .protected_branches
+            .get_or_insert_with(Vec::new)
+            .push(trunk.to_owned());
+        local.write_repo(repo).with_code(proc_exit::Code::FAILURE)?;
+    }
+    Ok(())
+}
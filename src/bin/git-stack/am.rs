@@ -0,0 +1,535 @@
+use bstr::ByteSlice;
+use eyre::WrapErr;
+use proc_exit::WithCodeResultExt;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Apply a mailed patch series as a branch stacked on `--onto`, or on top of an existing branch
+///
+/// Like `git am --message-id`, each commit records the email it came from and, when the series
+/// has a cover letter, it becomes the branch's description.
+pub fn am(args: &crate::args::Args, am_args: &crate::args::AmArgs) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let commit_template = crate::stack::commit_template(&repo, &repo_config)?;
+    let mut repo = git_stack::git::GitRepo::new(repo);
+
+    if !am_args.mbox.exists() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("No such file {}", am_args.mbox.display())));
+    }
+    let emails = read_mbox(repo.raw(), &am_args.mbox).with_code(proc_exit::Code::FAILURE)?;
+    let mut cover = None;
+    let mut patches = Vec::new();
+    for email in emails {
+        if email.diff.is_some() {
+            patches.push(email);
+        } else if cover.is_none() && patches.is_empty() && email.is_cover_letter() {
+            cover = Some(email);
+        } else {
+            log::warn!("Skipping `{}`, it has no patch", email.subject);
+        }
+    }
+    if patches.is_empty() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("No patches in {}", am_args.mbox.display())));
+    }
+
+    let existing = am_args
+        .branch
+        .as_deref()
+        .and_then(|name| repo.find_local_branch(name));
+    let adding = existing.is_some();
+    let (name, onto) = match existing {
+        Some(branch) => {
+            if am_args.onto.is_some() {
+                return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+                    "`--onto` can't be used when adding to the existing branch `{}`",
+                    branch.name
+                )));
+            }
+            (branch.name.clone(), branch)
+        }
+        None => {
+            let onto = match am_args.onto.as_deref() {
+                Some(name) => repo.find_local_branch(name).ok_or_else(|| {
+                    proc_exit::Code::USAGE_ERR.with_message(format!("No branch `{}`", name))
+                })?,
+                None => crate::stack::attached_head_branch(&repo).ok_or_else(|| {
+                    proc_exit::Code::USAGE_ERR
+                        .with_message("Must not be in a detached HEAD state, or pass `--onto`.")
+                })?,
+            };
+            let name = match am_args.branch.as_deref() {
+                Some(name) => name.to_owned(),
+                None => {
+                    let name = crate::stgit::slugify(&patches[0].subject);
+                    let mut unique = name.clone();
+                    let mut suffix = 1;
+                    while repo.find_local_branch(&unique).is_some() {
+                        suffix += 1;
+                        unique = format!("{}-{}", name, suffix);
+                    }
+                    unique
+                }
+            };
+            if !git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+                return Err(proc_exit::Code::USAGE_ERR
+                    .with_message(format!("`{}` is not a valid branch name", name)));
+            }
+            (name, onto)
+        }
+    };
+    let is_head = repo.head_branch().map(|b| b.name == name).unwrap_or(false);
+    if is_head && repo.is_dirty() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+            "Working tree is dirty, can't add patches to the checked out `{}`",
+            name
+        )));
+    }
+
+    let stack = match commit_template {
+        Some(_) => Some(stack_name(&repo, &repo_config, &onto, &name)?),
+        None => None,
+    };
+
+    let committer = repo
+        .raw()
+        .signature()
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+    let mut tip = onto.id;
+    for patch in patches.iter() {
+        let diff = patch.diff.as_deref().expect("only patches with diffs");
+        let diff = git2::Diff::from_buffer(diff).map_err(|err| {
+            proc_exit::Code::USAGE_ERR.with_message(format!(
+                "Could not parse the diff in `{}`: {}",
+                patch.subject, err
+            ))
+        })?;
+        let parent = repo
+            .raw()
+            .find_commit(tip)
+            .with_code(proc_exit::Code::FAILURE)?;
+        let mut index = repo
+            .raw()
+            .apply_to_tree(
+                &parent.tree().with_code(proc_exit::Code::FAILURE)?,
+                &diff,
+                None,
+            )
+            .map_err(|err| {
+                proc_exit::Code::FAILURE
+                    .with_message(format!("`{}` does not apply: {}", patch.subject, err))
+            })?;
+        let tree_id = index
+            .write_tree_to(repo.raw())
+            .with_code(proc_exit::Code::FAILURE)?;
+        let tree = repo
+            .raw()
+            .find_tree(tree_id)
+            .with_code(proc_exit::Code::FAILURE)?;
+        let author = patch
+            .author(&committer)
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+        let mut message = patch.message();
+        if let Some(template) = commit_template.as_ref() {
+            let provenance = git_stack::git::Provenance {
+                stack: stack.clone(),
+                layer: Some(name.clone()),
+                sources: patch.source.into_iter().collect(),
+            };
+            message = template.apply(&message, &provenance);
+        }
+        tip = repo
+            .raw()
+            .commit(None, &author, &committer, &message, &tree, &[&parent])
+            .with_code(proc_exit::Code::FAILURE)?;
+        log::debug!("{}: {}", patch.subject, tip);
+    }
+
+    log::trace!("git branch --force {} {}", name, tip);
+    if !args.dry_run {
+        if is_head {
+            // libgit2 won't force-update the checked out branch
+            repo.raw()
+                .reference(&format!("refs/heads/{}", name), tip, true, "git-stack am")
+                .with_code(proc_exit::Code::FAILURE)?;
+            repo.switch(&name).with_code(proc_exit::Code::FAILURE)?;
+        } else {
+            repo.branch(&name, tip)
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    if let Some(cover) = cover.as_ref() {
+        let key = format!("branch.{}.description", name);
+        log::trace!("git config {} <cover letter>", key);
+        if !args.dry_run {
+            let mut config = repo
+                .raw()
+                .config()
+                .and_then(|c| c.open_level(git2::ConfigLevel::Local))
+                .with_code(proc_exit::Code::FAILURE)?;
+            config
+                .set_str(&key, &cover.description())
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    if adding {
+        log::info!("Applied {} patches on top of `{}`", patches.len(), name);
+    } else {
+        log::info!(
+            "Applied {} patches as `{}`, stacked on `{}`",
+            patches.len(),
+            name,
+            onto.name
+        );
+    }
+
+    Ok(())
+}
+
+/// The branch closest to the protected base of `onto`, or `name` when it starts a new stack
+fn stack_name(
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    onto: &git_stack::git::Branch,
+    name: &str,
+) -> Result<String, proc_exit::Exit> {
+    let protected = git_stack::git::ProtectedBranches::new(
+        crate::forge::protected_patterns(repo.raw(), repo_config)
+            .iter()
+            .map(|s| s.as_str()),
+    )
+    .with_code(proc_exit::Code::CONFIG_ERR)?;
+    let ignored =
+        git_stack::git::BranchGlobs::new(repo_config.ignore_branches().iter().map(|s| s.as_str()))
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
+    let branches = git_stack::git::Branches::with_ignore(repo.local_branches(), &ignored);
+    let protected_branches = branches.protected(&protected);
+    let base_id = git_stack::git::find_protected_base(repo, &protected_branches, onto.id)
+        .and_then(|base| repo.merge_base(base.id, onto.id));
+
+    let mut stack = None;
+    let mut id = onto.id;
+    while Some(id) != base_id && !protected_branches.contains_oid(id) {
+        if let Some(branch) = branches.get(id).and_then(|b| b.first()) {
+            stack = Some(branch.name.clone());
+        }
+        match repo.raw().find_commit(id).and_then(|c| c.parent_id(0)) {
+            Ok(parent_id) => id = parent_id,
+            Err(_) => break,
+        }
+    }
+    Ok(stack.unwrap_or_else(|| name.to_owned()))
+}
+
+/// The emails in an mbox, like from `git format-patch --stdout`, or a lone email
+///
+/// Splitting them up and decoding them, from MIME to in-body headers, is left to `git mailsplit`
+/// and `git mailinfo`, as `git am` does.
+fn read_mbox(repo: &git2::Repository, mbox: &std::path::Path) -> eyre::Result<Vec<Email>> {
+    let dir = repo
+        .path()
+        .join("stack")
+        .join(format!("am-{}", std::process::id()));
+    let emails = read_mbox_in(mbox, &dir);
+    if let Err(err) = std::fs::remove_dir_all(&dir) {
+        log::debug!("Could not remove {}: {}", dir.display(), err);
+    }
+    emails
+}
+
+fn read_mbox_in(mbox: &std::path::Path, dir: &std::path::Path) -> eyre::Result<Vec<Email>> {
+    let split_dir = dir.join("split");
+    std::fs::create_dir_all(&split_dir)?;
+    let mut split_arg = std::ffi::OsString::from("-o");
+    split_arg.push(&split_dir);
+    log::trace!(
+        "git mailsplit -b -o{} {}",
+        split_dir.display(),
+        mbox.display()
+    );
+    let output = std::process::Command::new("git")
+        .arg("mailsplit")
+        // A lone email need not start with a `From ` line
+        .arg("-b")
+        .arg(split_arg)
+        .arg(mbox)
+        .output()
+        .wrap_err("Could not run `git mailsplit`")?;
+    if !output.status.success() {
+        eyre::bail!(
+            "Could not split {}: {}",
+            mbox.display(),
+            output.stderr.to_str_lossy().trim()
+        );
+    }
+    let mut paths = std::fs::read_dir(&split_dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort_unstable();
+
+    let msg_path = dir.join("msg");
+    let patch_path = dir.join("patch");
+    let mut emails = Vec::with_capacity(paths.len());
+    for path in paths {
+        // `-k` keeps the `[PATCH 0/N]` that tells apart the cover letter
+        let output = std::process::Command::new("git")
+            .arg("mailinfo")
+            .arg("-k")
+            .arg(&msg_path)
+            .arg(&patch_path)
+            .stdin(std::fs::File::open(&path)?)
+            .output()
+            .wrap_err("Could not run `git mailinfo`")?;
+        if !output.status.success() {
+            eyre::bail!(
+                "Could not read email {} of {}: {}",
+                emails.len() + 1,
+                mbox.display(),
+                output.stderr.to_str_lossy().trim()
+            );
+        }
+        emails.push(Email::new(
+            &std::fs::read(&path)?,
+            &output.stdout,
+            &std::fs::read(&msg_path)?,
+            &std::fs::read(&patch_path)?,
+        ));
+    }
+    Ok(emails)
+}
+
+struct Email {
+    /// The commit `git format-patch` made this from
+    source: Option<git2::Oid>,
+    author_name: Option<String>,
+    author_email: Option<String>,
+    date: Option<String>,
+    message_id: Option<String>,
+    /// The `[PATCH v2 1/3]` part of the subject
+    prefix: String,
+    subject: String,
+    body: String,
+    diff: Option<Vec<u8>>,
+}
+
+impl Email {
+    /// From the `raw` email and what `git mailinfo` made of it
+    fn new(raw: &[u8], info: &[u8], msg: &[u8], patch: &[u8]) -> Self {
+        let source = raw
+            .strip_prefix(b"From ")
+            .and_then(|rest| rest.split_str(" ").next())
+            .and_then(|id| git2::Oid::from_str(&id.to_str_lossy()).ok())
+            .filter(|id| !id.is_zero());
+        let message_id = raw_header(raw, "message-id");
+
+        let info = info.to_str_lossy();
+        let field = |key: &str| {
+            info.lines()
+                .find_map(|l| l.strip_prefix(key))
+                .and_then(|v| v.strip_prefix(':'))
+                .map(|v| v.trim().to_owned())
+                .filter(|v| !v.is_empty())
+        };
+        let (prefix, subject) = split_subject(&field("Subject").unwrap_or_default());
+
+        let diff = patch
+            .find(b"\ndiff --git ")
+            .map(|i| i + 1)
+            .or_else(|| patch.starts_with(b"diff --git ").then(|| 0))
+            .map(|i| {
+                let mut diff = &patch[i..];
+                // Drop the `-- ` signature `git format-patch` ends with
+                if let Some(end) = diff.rfind(b"\n-- \n") {
+                    diff = &diff[..end + 1];
+                }
+                diff.to_owned()
+            });
+
+        Self {
+            source,
+            author_name: field("Author"),
+            author_email: field("Email"),
+            date: field("Date"),
+            message_id,
+            prefix,
+            subject,
+            body: msg.to_str_lossy().trim().to_owned(),
+            diff,
+        }
+    }
+
+    /// `[PATCH 0/N]`
+    fn is_cover_letter(&self) -> bool {
+        self.prefix.split_whitespace().any(|p| {
+            p.split_once('/')
+                .map(|(n, _)| n.trim_start_matches('0').is_empty())
+                == Some(true)
+        })
+    }
+
+    /// The commit message, with a `Message-Id` trailer like `git am --message-id`
+    fn message(&self) -> String {
+        let mut message = self.subject.clone();
+        message.push('\n');
+        if !self.body.is_empty() {
+            message.push('\n');
+            message.push_str(&self.body);
+            message.push('\n');
+        }
+        if let Some(message_id) = self.message_id.as_deref() {
+            let has_trailers = !self.body.is_empty()
+                && self
+                    .body
+                    .rsplit("\n\n")
+                    .next()
+                    .unwrap_or_default()
+                    .lines()
+                    .all(is_trailer);
+            if !has_trailers {
+                message.push('\n');
+            }
+            message.push_str(&format!("Message-Id: {}\n", message_id));
+        }
+        message
+    }
+
+    /// The cover letter's subject and blurb, without the shortlog and diffstat
+    fn description(&self) -> String {
+        let shortlog = regex::Regex::new(r"^\S.* \(\d+\):$").unwrap();
+        let blurb = self
+            .body
+            .lines()
+            .take_while(|l| !shortlog.is_match(l))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let blurb = blurb.trim();
+        if blurb.is_empty() {
+            format!("{}\n", self.subject)
+        } else {
+            format!("{}\n\n{}\n", self.subject, blurb)
+        }
+    }
+
+    fn author(
+        &self,
+        committer: &git2::Signature<'_>,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        let (name, email) = match (self.author_name.as_deref(), self.author_email.as_deref()) {
+            (name, Some(email)) => (name.unwrap_or(email).to_owned(), email.to_owned()),
+            (_, None) => (
+                committer.name().unwrap_or_default().to_owned(),
+                committer.email().unwrap_or_default().to_owned(),
+            ),
+        };
+        match self.date.as_deref().and_then(parse_date) {
+            Some(when) => git2::Signature::new(&name, &email, &when),
+            None => git2::Signature::now(&name, &email),
+        }
+    }
+}
+
+fn is_trailer(line: &str) -> bool {
+    line.split_once(": ")
+        .map(|(key, _)| {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .unwrap_or(false)
+}
+
+/// Split `[PATCH v2 1/3] Add foo` into `PATCH v2 1/3` and `Add foo`, like `git mailinfo`
+fn split_subject(subject: &str) -> (String, String) {
+    let mut prefix = String::new();
+    let mut rest = subject.trim();
+    loop {
+        if let Some(stripped) = rest
+            .strip_prefix("Re:")
+            .or_else(|| rest.strip_prefix("RE:"))
+        {
+            rest = stripped.trim_start();
+        } else if let Some((bracket, stripped)) =
+            rest.strip_prefix('[').and_then(|r| r.split_once(']'))
+        {
+            if !prefix.is_empty() {
+                prefix.push(' ');
+            }
+            prefix.push_str(bracket.trim());
+            rest = stripped.trim_start();
+        } else {
+            break;
+        }
+    }
+    (prefix, rest.to_owned())
+}
+
+/// The value of the `key` header, which `git mailinfo` doesn't report
+fn raw_header(raw: &[u8], key: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+    let mut in_key = false;
+    for line in raw.lines().take_while(|l| !l.trim().is_empty()) {
+        let line = line.to_str_lossy();
+        if line.starts_with(|c: char| c == ' ' || c == '\t') {
+            if let (true, Some(value)) = (in_key, value.as_mut()) {
+                if !value.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(line.trim());
+            }
+        } else if value.is_some() {
+            break;
+        } else {
+            in_key = line
+                .split_once(':')
+                .map(|(k, _)| k.trim().eq_ignore_ascii_case(key))
+                .unwrap_or(false);
+            if in_key {
+                let (_, v) = line.split_once(':').expect("just checked");
+                value = Some(v.trim().to_owned());
+            }
+        }
+    }
+    value.filter(|v| !v.is_empty())
+}
+
+/// Parse an RFC 2822 date, like `Mon, 17 Oct 2022 10:00:00 +0200`
+fn parse_date(date: &str) -> Option<git2::Time> {
+    let date = date.split_once(',').map(|(_, d)| d).unwrap_or(date);
+    let mut parts = date.split_whitespace();
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| month.starts_with(m))? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: i64 = time.next().unwrap_or("0").parse().ok()?;
+    let zone = parts.next().unwrap_or("+0000");
+    let (sign, zone) = match zone.split_at(1) {
+        ("-", zone) => (-1, zone),
+        ("+", zone) => (1, zone),
+        _ => (1, "0000"),
+    };
+    let zone: i64 = zone.parse().ok()?;
+    let offset = sign * (zone / 100 * 60 + zone % 100);
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+        - offset * 60;
+    Some(git2::Time::new(seconds, offset as i32))
+}
+
+/// Days since the unix epoch for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if 0 <= year { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
@@ -0,0 +1,273 @@
+use proc_exit::WithCodeResultExt;
+
+/// Where archived branches are kept, out of sight of stack discovery
+pub(crate) const ARCHIVE_PREFIX: &str = "refs/stack-archive/";
+/// Where the `branch.<name>.*` config of archived branches is kept, as deleting a branch drops it
+const ARCHIVE_CONFIG_PREFIX: &str = "refs/stack-archive-config/";
+
+pub fn archive(
+    args: &crate::args::Args,
+    archive_args: &crate::args::ArchiveArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let name = archive_args.branch.as_str();
+
+    let mut branch = repo
+        .find_branch(name, git2::BranchType::Local)
+        .with_code(proc_exit::Code::USAGE_ERR)?;
+    if branch.is_head() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("Cannot archive `{}`, it is checked out", name)));
+    }
+    let id = branch
+        .get()
+        .target()
+        .expect("local branches are direct references");
+    let archive_ref = format!("{}{}", ARCHIVE_PREFIX, name);
+    if repo.find_reference(&archive_ref).is_ok() {
+        return Err(
+            proc_exit::Code::USAGE_ERR.with_message(format!("`{}` is already archived", name))
+        );
+    }
+
+    let config = branch_config(&repo, name).with_code(proc_exit::Code::CONFIG_ERR)?;
+    let config_ref = format!("{}{}", ARCHIVE_CONFIG_PREFIX, name);
+    log::trace!("git update-ref {} {}", archive_ref, id);
+    if !config.is_empty() {
+        log::trace!(
+            "git update-ref {} $(git hash-object -w <config>)",
+            config_ref
+        );
+    }
+    log::trace!("git branch -D {}", name);
+    if !args.dry_run {
+        repo.reference(&archive_ref, id, false, &format!("archive: {}", name))
+            .with_code(proc_exit::Code::FAILURE)?;
+        if !config.is_empty() {
+            let blob = repo
+                .blob(&encode_config(&config))
+                .with_code(proc_exit::Code::FAILURE)?;
+            repo.reference(&config_ref, blob, true, &format!("archive: {}", name))
+                .with_code(proc_exit::Code::FAILURE)?;
+            clear_branch_config(&repo, name, &config).with_code(proc_exit::Code::FAILURE)?;
+        }
+        branch.delete().with_code(proc_exit::Code::FAILURE)?;
+    }
+    log::info!("Archived {} ({})", name, &id.to_string()[..7]);
+
+    Ok(())
+}
+
+pub fn unarchive(
+    args: &crate::args::Args,
+    archive_args: &crate::args::ArchiveArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let name = archive_args.branch.as_str();
+
+    let archive_ref = format!("{}{}", ARCHIVE_PREFIX, name);
+    let mut reference = repo
+        .find_reference(&archive_ref)
+        .map_err(|_| eyre::eyre!("`{}` is not archived", name))
+        .with_code(proc_exit::Code::USAGE_ERR)?;
+    let commit = reference
+        .peel_to_commit()
+        .with_code(proc_exit::Code::FAILURE)?;
+    if repo.find_branch(name, git2::BranchType::Local).is_ok() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("Cannot unarchive `{}`, the branch exists", name)));
+    }
+
+    let config_ref = format!("{}{}", ARCHIVE_CONFIG_PREFIX, name);
+    let config = match repo.find_reference(&config_ref) {
+        Ok(config_ref) => {
+            let blob = config_ref
+                .peel_to_blob()
+                .with_code(proc_exit::Code::FAILURE)?;
+            decode_config(blob.content())
+        }
+        Err(_) => Vec::new(),
+    };
+    log::trace!("git branch {} {}", name, commit.id());
+    for (key, value) in config.iter() {
+        log::trace!("git config --add branch.{}.{} {}", name, key, value);
+    }
+    log::trace!("git update-ref -d {}", archive_ref);
+    if !args.dry_run {
+        repo.branch(name, &commit, false)
+            .with_code(proc_exit::Code::FAILURE)?;
+        restore_branch_config(&repo, name, &config).with_code(proc_exit::Code::FAILURE)?;
+        reference.delete().with_code(proc_exit::Code::FAILURE)?;
+        forget_config(&repo, name).with_code(proc_exit::Code::FAILURE)?;
+    }
+    log::info!("Unarchived {} ({})", name, &commit.id().to_string()[..7]);
+
+    Ok(())
+}
+
+/// Each archived branch, with the commit it was archived at
+pub(crate) fn archived_branches(repo: &git2::Repository) -> Vec<(String, git2::Oid)> {
+    let references = match repo.references_glob(&format!("{}*", ARCHIVE_PREFIX)) {
+        Ok(references) => references,
+        Err(err) => {
+            log::debug!("Could not read archived branches: {}", err);
+            return Vec::new();
+        }
+    };
+    references
+        .filter_map(|reference| {
+            let reference = reference.ok()?;
+            let name = reference.name()?.strip_prefix(ARCHIVE_PREFIX)?.to_owned();
+            Some((name, reference.target()?))
+        })
+        .collect()
+}
+
+/// Drop the config saved for the archived `branch`, if any
+pub(crate) fn forget_config(repo: &git2::Repository, branch: &str) -> Result<(), git2::Error> {
+    match repo.find_reference(&format!("{}{}", ARCHIVE_CONFIG_PREFIX, branch)) {
+        Ok(mut reference) => reference.delete(),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// `branch.<branch>.*` from the repo's own config, as `(key, value)` without the prefix
+fn branch_config(
+    repo: &git2::Repository,
+    branch: &str,
+) -> Result<Vec<(String, String)>, git2::Error> {
+    let config = crate::label::local_config(repo)?;
+    let prefix = format!("branch.{}.", branch);
+    let mut entries = Vec::new();
+    for entry in &config.entries(Some(&format!("^{}", regex::escape(&prefix))))? {
+        let entry = entry?;
+        let key = entry.name().and_then(|n| n.strip_prefix(&prefix));
+        if let (Some(key), Some(value)) = (key, entry.value()) {
+            entries.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    Ok(entries)
+}
+
+/// Remove `entries` ahead of deleting `branch`, as libgit2 refuses to when a key has several values
+fn clear_branch_config(
+    repo: &git2::Repository,
+    branch: &str,
+    entries: &[(String, String)],
+) -> Result<(), git2::Error> {
+    let mut config = crate::label::local_config(repo)?;
+    let mut keys: Vec<_> = entries.iter().map(|(key, _)| key).collect();
+    keys.dedup();
+    for key in keys {
+        config.remove_multivar(&format!("branch.{}.{}", branch, key), ".*")?;
+    }
+    Ok(())
+}
+
+fn restore_branch_config(
+    repo: &git2::Repository,
+    branch: &str,
+    entries: &[(String, String)],
+) -> Result<(), git2::Error> {
+    let mut config = crate::label::local_config(repo)?;
+    for (key, value) in entries {
+        // Appending, so multi-valued keys like `stack-depends` come back whole
+        config.set_multivar(&format!("branch.{}.{}", branch, key), "^$", value)?;
+    }
+    Ok(())
+}
+
+/// Like `git config --list -z`, a key and its value separated by a newline, each ending in a NUL
+fn encode_config(entries: &[(String, String)]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (key, value) in entries {
+        encoded.extend_from_slice(key.as_bytes());
+        encoded.push(b'\n');
+        encoded.extend_from_slice(value.as_bytes());
+        encoded.push(b'\0');
+    }
+    encoded
+}
+
+fn decode_config(encoded: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(encoded)
+        .split_terminator('\0')
+        .filter_map(|entry| entry.split_once('\n'))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_round_trip() {
+        let entries = vec![
+            ("merge".to_owned(), "refs/heads/main".to_owned()),
+            ("stack-depends".to_owned(), "api".to_owned()),
+            ("stack-depends".to_owned(), "multi\nline".to_owned()),
+            ("description".to_owned(), String::new()),
+        ];
+        assert_eq!(decode_config(&encode_config(&entries)), entries);
+    }
+
+    #[test]
+    fn archive_keeps_config() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let repo = git2::Repository::init(temp.path()).unwrap();
+        let signature = git2::Signature::now("Jane Doe", "jdoe@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let id = repo
+            .commit(None, &signature, &signature, "Initial", &tree, &[])
+            .unwrap();
+        repo.branch("feature", &repo.find_commit(id).unwrap(), false)
+            .unwrap();
+        {
+            let mut config = crate::label::local_config(&repo).unwrap();
+            config.set_str("branch.feature.remote", ".").unwrap();
+            config
+                .set_str("branch.feature.merge", "refs/heads/main")
+                .unwrap();
+            config
+                .set_multivar("branch.feature.stack-depends", "^$", "api")
+                .unwrap();
+            config
+                .set_multivar("branch.feature.stack-depends", "^$", "db")
+                .unwrap();
+        }
+        let saved = branch_config(&repo, "feature").unwrap();
+        clear_branch_config(&repo, "feature", &saved).unwrap();
+
+        repo.find_branch("feature", git2::BranchType::Local)
+            .unwrap()
+            .delete()
+            .unwrap();
+        assert!(branch_config(&repo, "feature").unwrap().is_empty());
+
+        let restored = decode_config(&encode_config(&saved));
+        restore_branch_config(&repo, "feature", &restored).unwrap();
+        let config = crate::label::local_config(&repo).unwrap();
+        assert_eq!(
+            config.get_string("branch.feature.merge").unwrap(),
+            "refs/heads/main"
+        );
+        let depends: Vec<_> = config
+            .multivar("branch.feature.stack-depends", None)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.value().map(|v| v.to_owned()))
+            .collect();
+        assert_eq!(depends, ["api", "db"]);
+
+        temp.close().unwrap();
+    }
+}
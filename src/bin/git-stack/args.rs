@@ -7,6 +7,9 @@
     )]
 #[clap(group = clap::ArgGroup::new("mode").multiple(false))]
 pub struct Args {
+    #[clap(subcommand)]
+    pub subcommand: Option<Subcommand>,
+
     /// Rebase the selected stacks
     #[clap(short, long, group = "mode")]
     pub rebase: bool,
@@ -19,6 +22,11 @@ pub struct Args {
     #[clap(long)]
     pub push: bool,
 
+    /// After pushing, wait this long (e.g. `20m`) for CI to pass on the pushed branches (see
+    /// `stack.ci-wait`)
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    pub ci_wait: Option<std::time::Duration>,
+
     /// Which branch stacks to include
     #[clap(
         short,
@@ -36,6 +44,10 @@ pub struct Args {
     #[clap(long)]
     pub onto: Option<String>,
 
+    /// Only include branches that change files under this directory, relative to the repo root
+    #[clap(long, parse(from_os_str))]
+    pub path: Option<std::path::PathBuf>,
+
     /// Action to perform with fixup-commits
     #[clap(
         long,
@@ -50,9 +62,79 @@ pub struct Args {
     #[clap(long, overrides_with("repair"), hide = true)]
     no_repair: bool,
 
+    /// Don't ask for confirmation (see `stack.confirm`)
+    #[clap(short, long)]
+    pub yes: bool,
+
     #[clap(short = 'n', long)]
     pub dry_run: bool,
 
+    /// Only plan, exiting with 1 if anything would be restacked or pushed (implies `--dry-run`)
+    #[clap(long)]
+    pub check: bool,
+
+    /// Before moving any branches, re-check that the working tree is clean and the branches haven't
+    /// changed since restacking started
+    #[clap(long)]
+    pub verify: bool,
+
+    /// Run a shell command on each replayed commit, like `git rebase --exec`, stopping the branch
+    /// when it fails
+    #[clap(short = 'x', long)]
+    pub exec: Option<String>,
+
+    /// Run a shell command once a specific commit is replayed, like an `exec` line after its
+    /// `pick` in `git rebase -i`, running in the order given and before `--exec`
+    #[clap(
+        long,
+        value_name = "REV=CMD",
+        multiple_occurrences = true,
+        parse(try_from_str = parse_exec_at)
+    )]
+    pub exec_at: Vec<(String, String)>,
+
+    /// Merge strategy option for replaying commits, like `git rebase -X`, replacing
+    /// `stack.merge-options`
+    #[clap(short = 'X', long, multiple_occurrences = true)]
+    pub strategy_option: Vec<git_stack::config::MergeOption>,
+
+    /// Stop at the next safe point after this long (e.g. `10m`), leaving branches that weren't
+    /// restacked or pushed yet as they were
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    pub timeout: Option<std::time::Duration>,
+
+    /// Write details of any conflicts to this file (markdown for `.md`, otherwise JSON)
+    #[clap(long, parse(from_os_str))]
+    pub conflict_report: Option<std::path::PathBuf>,
+
+    /// When a stack fails to restack, still restack the others, reporting every failure at the end
+    /// (see `stack.keep-going`)
+    #[clap(long, overrides_with("no-keep-going"))]
+    keep_going: bool,
+    #[clap(long, overrides_with("keep-going"), hide = true)]
+    no_keep_going: bool,
+
+    /// Print the plain `git` commands equivalent to what is done (or would be, with `--dry-run`),
+    /// or with `=rebase-todo`, the restacking as a `git rebase -i --update-refs` todo list
+    #[clap(
+        long,
+        possible_values(crate::progress::GitCommandsFormat::variants()),
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "shell"
+    )]
+    pub show_git_commands: Option<crate::progress::GitCommandsFormat>,
+
+    /// Never prompt, reporting progress as JSON lines (default when stdout isn't a terminal)
+    #[clap(long)]
+    pub non_interactive: bool,
+
+    /// Never touch the network, failing instead of pulling or pushing (see `stack.offline`)
+    #[clap(long, overrides_with("no-offline"))]
+    offline: bool,
+    #[clap(long, overrides_with("offline"), hide = true)]
+    no_offline: bool,
+
     #[clap(
         long,
         possible_values(git_stack::config::Format::variants()),
@@ -60,6 +142,22 @@ pub struct Args {
     )]
     pub format: Option<git_stack::config::Format>,
 
+    /// Only operate on stacks with a branch carrying this label (see `git stack label`)
+    #[clap(long, multiple_occurrences = true)]
+    pub label: Vec<String>,
+
+    /// Only operate on the branches matching this expression, e.g. `stack() & !pushed()`
+    #[clap(long, value_name = "EXPR")]
+    pub select: Option<git_stack::graph::Selector>,
+
+    /// Treat this protected branch as a development branch, for this run only
+    #[clap(long, value_name = "BRANCH", multiple_occurrences = true)]
+    pub unprotect: Vec<String>,
+
+    /// Run in each repository under this directory (or listed in this file, one per line)
+    #[clap(long, value_name = "DIR", parse(from_os_str))]
+    pub recurse: Option<std::path::PathBuf>,
+
     /// See what branches are protected
     #[clap(long, group = "mode")]
     pub protected: bool,
@@ -77,14 +175,428 @@ pub struct Args {
 
     #[clap(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity,
+
+    /// Only make these subsystems more verbose with `-v`
+    #[clap(
+        long,
+        possible_values(git_stack::log::LogScope::variants()),
+        ignore_case = true,
+        multiple_occurrences = true,
+        use_value_delimiter = true
+    )]
+    pub log_scope: Vec<git_stack::log::LogScope>,
+
+    /// Write the `-v` output here, keeping the terminal's output as usual
+    #[clap(long, parse(from_os_str))]
+    pub log_file: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum Subcommand {
+    /// Apply pending `fixup!` and `squash!` commits without rebasing
+    Fixups(FixupsArgs),
+    /// Fetch remotes in the background so `--pull` has less to do
+    Prefetch(PrefetchArgs),
+    /// Manage `git` hooks that keep `git stack` up to date as you work
+    Hooks(HooksArgs),
+    /// Restack and push stacks whose base moved, for scheduled CI jobs
+    Bot(BotArgs),
+    /// Finish (or roll back) moving branches after `git stack` was interrupted
+    Recover(RecoverArgs),
+    /// Tag the current branch as a checkpoint that rewrites warn before leaving behind
+    Tag(TagArgs),
+    /// Park a branch outside of the stacks without deleting it
+    Archive(ArchiveArgs),
+    /// Restore a branch parked with `git stack archive`
+    Unarchive(ArchiveArgs),
+    /// Pin bare `git stack` runs to the stack of a branch, regardless of HEAD
+    Focus(FocusArgs),
+    /// Keep a commit as-is when restacking, never squashing into, moving, or dropping it
+    Pin(PinArgs),
+    /// Label branches to select their stacks with `--label`
+    Label(LabelArgs),
+    /// Declare that a branch depends on a branch in another stack
+    Depend(DependArgs),
+    /// Check how `git stack` authenticates with the forge
+    Auth(AuthArgs),
+    /// Fix branches that track deleted branches, or each other in a cycle
+    RepairMetadata,
+    /// Check `git stack`'s own state for inconsistencies, optionally repairing them
+    Verify(VerifyArgs),
+    /// Write the current branch's commits out as an stgit patch series or an HTML page
+    Export(ExportArgs),
+    /// Recreate an stgit patch series as stacked branches
+    Import(ImportArgs),
+    /// Apply a mailed patch series as a branch in the stack
+    Am(AmArgs),
+    /// Cherry-pick a branch's commits onto release branches
+    Backport(BackportArgs),
+    /// Scaffold a stack of empty branches from a template
+    New(NewArgs),
+    /// Take over the stacks of another tool
+    Adopt(AdoptArgs),
+    /// Check out a branch from any stack, picking it with a fuzzy finder
+    Switch(SwitchArgs),
+    /// Return to where HEAD was before a `git stack` operation checked out other branches
+    Back(BackArgs),
+    /// Show what changed in each branch since it was last pushed
+    Diff(DiffArgs),
+    /// Show how branches changed across the last `git stack` rewrites
+    RangeDiff(RangeDiffArgs),
+    /// Open a pull request for each pushed branch, stacked on its parent, and request reviews
+    /// from its `CODEOWNERS`
+    Submit(SubmitArgs),
+    /// Merge the bottom pull request of the current stack through the merge queue (or merge
+    /// train), then restack the rest onto it
+    Land(LandArgs),
+    /// List the issues referenced by branches in the stacks (see `stack.issue-pattern`)
+    Issues,
+    /// Draft a changelog section from the commits in the stacks
+    Changelog(ChangelogArgs),
+    /// Summarize each stack, including the semantic version bump its commits imply
+    Stats(StatsArgs),
+    /// One line per stack, with how far behind it is and what needs pushing
+    Overview,
+    /// Delete branches that have been merged (including squash-merged) into their protected base
+    Cleanup(CleanupArgs),
+    /// One line about the current stack, for shell prompts and status lines
+    Prompt,
+    /// Answer queries and run operations over JSON-RPC, for editor integrations
+    Serve(ServeArgs),
+    /// Check the configuration for values that will be ignored
+    Config(ConfigArgs),
+    /// Time each phase of a run on the current repo
+    #[clap(hide = true)]
+    Perf(PerfArgs),
+}
+
+#[derive(clap::Args)]
+pub struct FixupsArgs {
+    /// Action to perform with fixup-commits (default: `stack.auto-fixup`)
+    #[clap(
+        long,
+        possible_values(git_stack::config::Fixup::variants()),
+        ignore_case = true
+    )]
+    pub fixup: Option<git_stack::config::Fixup>,
+}
+
+#[derive(clap::Args)]
+pub struct PrefetchArgs {
+    /// Periodically prefetch via `git maintenance`
+    #[clap(long)]
+    pub install: bool,
+}
+
+#[derive(clap::Args)]
+pub struct HooksArgs {
+    #[clap(subcommand)]
+    pub action: HooksAction,
+}
+
+#[derive(clap::Subcommand)]
+pub enum HooksAction {
+    /// Install `post-commit`, `post-checkout`, and `post-merge` hooks, chaining to existing ones
+    Install {
+        /// Also restack dependent branches after committing
+        #[clap(long)]
+        restack: bool,
+    },
+    /// Remove the hooks, restoring any that were chained to
+    Uninstall,
+    /// What the installed hooks run
+    #[clap(hide = true)]
+    Run {
+        hook: String,
+        #[clap(long)]
+        restack: bool,
+        /// Arguments `git` passed to the hook
+        hook_args: Vec<String>,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct BotArgs {
+    /// Shell command to verify each restacked branch before pushing it
+    #[clap(long)]
+    pub exec: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct RecoverArgs {
+    /// Put the branches back where they were before the interrupted rewrite
+    #[clap(long)]
+    pub rollback: bool,
+}
+
+#[derive(clap::Args)]
+pub struct TagArgs {
+    /// Name of the tag to create
+    pub name: String,
+}
+
+#[derive(clap::Args)]
+pub struct ArchiveArgs {
+    pub branch: String,
+}
+
+#[derive(clap::Args)]
+pub struct VerifyArgs {
+    /// Fix what can be fixed safely, reporting the rest
+    #[clap(long)]
+    pub repair: bool,
+}
+
+#[derive(clap::Args)]
+pub struct PinArgs {
+    /// Commit to pin (default: list pinned commits)
+    pub commit: Option<String>,
+    /// Unpin the commit
+    #[clap(long, requires = "commit")]
+    pub remove: bool,
+}
+
+#[derive(clap::Args)]
+pub struct FocusArgs {
+    /// Branch whose stack to focus on (default: show the current focus)
+    pub branch: Option<String>,
+    /// Stop focusing, going back to the stack of HEAD
+    #[clap(long, conflicts_with = "branch")]
+    pub clear: bool,
+}
+
+#[derive(clap::Args)]
+pub struct LabelArgs {
+    #[clap(subcommand)]
+    pub action: LabelAction,
+}
+
+#[derive(clap::Subcommand)]
+pub enum LabelAction {
+    /// Add a label to a branch
+    Add {
+        label: String,
+        /// Branch to label (default: the current branch)
+        branch: Option<String>,
+    },
+    /// Remove a label from a branch
+    Remove {
+        label: String,
+        /// Branch to unlabel (default: the current branch)
+        branch: Option<String>,
+    },
+    /// Show the labels of each branch
+    List,
+}
+
+#[derive(clap::Args)]
+pub struct AuthArgs {
+    #[clap(subcommand)]
+    pub action: AuthAction,
+}
+
+#[derive(clap::Subcommand)]
+pub enum AuthAction {
+    /// Show where the forge token comes from, failing if there is none
+    Status,
+}
+
+#[derive(clap::Args)]
+pub struct DependArgs {
+    #[clap(subcommand)]
+    pub action: DependAction,
+}
+
+#[derive(clap::Subcommand)]
+pub enum DependAction {
+    /// Have a branch depend on another, so it is pushed after it
+    Add {
+        /// Branch that is depended on
+        on: String,
+        /// Dependent branch (default: the current branch)
+        branch: Option<String>,
+    },
+    /// Drop a dependency
+    Remove {
+        /// Branch that is depended on
+        on: String,
+        /// Dependent branch (default: the current branch)
+        branch: Option<String>,
+    },
+    /// Show each branch's dependencies
+    List,
+}
+
+#[derive(clap::Args)]
+pub struct ExportArgs {
+    /// Write the series the way `stg export` does
+    #[clap(long)]
+    pub stgit: bool,
+    /// Write a standalone HTML page, with each commit's diff, for reviewers
+    #[clap(long, conflicts_with = "stgit")]
+    pub html: bool,
+    /// Branch to export (default: the current branch)
+    #[clap(long)]
+    pub branch: Option<String>,
+    /// Directory to write `series` and the patches to, or the file to write the HTML page to
+    #[clap(parse(from_os_str))]
+    pub path: std::path::PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct ImportArgs {
+    /// Read a series written by `stg export` (or quilt)
+    #[clap(long)]
+    pub stgit: bool,
+    /// Directory containing `series` and the patches
+    #[clap(parse(from_os_str))]
+    pub dir: std::path::PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct AmArgs {
+    /// Branch to stack the series on (default: the current branch)
+    #[clap(long)]
+    pub onto: Option<String>,
+    /// Branch to create for the series, or to add it on top of when it exists (default: named
+    /// after the first patch)
+    #[clap(long)]
+    pub branch: Option<String>,
+    /// mbox with the series, like from `git format-patch --stdout`, or a single email
+    #[clap(parse(from_os_str))]
+    pub mbox: std::path::PathBuf,
+}
+
+#[derive(clap::Args)]
+pub struct BackportArgs {
+    /// Release branch to backport to, as `backport/<release>/<branch>`
+    #[clap(long, required = true, multiple_occurrences = true)]
+    pub to: Vec<String>,
+    /// Branch whose own commits to backport
+    pub branch: String,
+}
+
+#[derive(clap::Args)]
+pub struct NewArgs {
+    /// Template in `.git-stack/templates/` or `~/.config/git-stack/templates/`, or a path to one
+    #[clap(long, required = true)]
+    pub template: String,
+    /// Name the branches `<prefix>/<layer>`
+    pub prefix: String,
+}
+
+#[derive(clap::Args)]
+pub struct AdoptArgs {
+    /// Tool whose branch metadata to read
+    #[clap(long, possible_values(["graphite"]))]
+    pub from: String,
+}
+
+#[derive(clap::Args)]
+pub struct SwitchArgs {
+    /// Narrow down the branches, switching right away if only one matches
+    pub query: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct BackArgs {
+    /// How many operations to go back
+    #[clap(default_value = "1")]
+    pub count: usize,
+    /// Show where `git stack back` can go, most recent first
+    #[clap(long, conflicts_with = "count")]
+    pub list: bool,
+}
+
+#[derive(clap::Args)]
+pub struct DiffArgs {
+    /// Compare each branch to what was pushed, as a `git range-diff`
+    #[clap(long)]
+    pub remote: bool,
+}
+
+#[derive(clap::Args)]
+pub struct RangeDiffArgs {
+    /// Compare to before this many rewrites ago
+    #[clap(long, default_value = "1")]
+    pub since: usize,
+
+    /// Only report commits whose content changed, failing if there are any
+    #[clap(long)]
+    pub check: bool,
+}
+
+#[derive(clap::Args)]
+pub struct SubmitArgs {
+    /// Don't request reviews from the owners of the files each branch changes
+    #[clap(long)]
+    pub no_assign: bool,
+}
+
+#[derive(clap::Args)]
+pub struct LandArgs {
+    /// Branch whose stack to land the bottom of (default: the current branch)
+    pub branch: Option<String>,
+    /// How long to wait for the queue to merge it
+    #[clap(long, default_value = "1h", parse(try_from_str = humantime::parse_duration))]
+    pub wait: std::time::Duration,
+}
+
+#[derive(clap::Args)]
+pub struct ChangelogArgs {
+    /// Write to this file instead of stdout
+    #[clap(short, long, parse(from_os_str))]
+    pub output: Option<std::path::PathBuf>,
+}
+
+#[derive(clap::Args)]
+pub struct StatsArgs {
+    /// Instead, show how you use `git stack`, as recorded with `stack.usage-stats`
+    #[clap(long = "self")]
+    pub usage: bool,
+}
+
+#[derive(clap::Args)]
+pub struct CleanupArgs {
+    /// Also delete the merged branches from `stack.push-remote`, overriding `stack.delete-remote`
+    #[clap(long)]
+    pub remote: bool,
+}
+
+#[derive(clap::Args)]
+pub struct ServeArgs {
+    /// Communicate over stdin/stdout
+    #[clap(long)]
+    pub stdio: bool,
+}
+
+#[derive(clap::Args)]
+pub struct ConfigArgs {
+    /// Report invalid or unknown `stack.*` values, failing if there are any
+    #[clap(long)]
+    pub validate: bool,
+
+    /// Merge a shared config file (path or `http(s)://` URL) into the repository's config
+    #[clap(long, value_name = "SOURCE", conflicts_with = "validate")]
+    pub apply: Option<String>,
+}
+
+#[derive(clap::Args)]
+pub struct PerfArgs {
+    /// Fail if the run takes longer than this (e.g. `500ms`)
+    #[clap(long, parse(try_from_str = humantime::parse_duration))]
+    pub budget: Option<std::time::Duration>,
 }
 
 impl Args {
     pub fn to_config(&self) -> git_stack::config::RepoConfig {
         git_stack::config::RepoConfig {
             protected_branches: None,
+            ignore_branches: None,
             protect_commit_count: None,
             protect_commit_age: None,
+            stale_age: None,
+            hide_age: None,
             stack: self.stack,
             push_remote: None,
             pull_remote: None,
@@ -92,14 +604,61 @@ impl Args {
             show_stacked: None,
             auto_fixup: None,
             auto_repair: None,
+            require_fresh_base: None,
+            max_rewrite_commits: None,
+            large_file_threshold: None,
+            confirm: None,
+            checkpoints: None,
+            jobs: None,
+            commit_cache: None,
+            show_max_commits: None,
+            scope_path: self.path.as_ref().map(|p| p.display().to_string()),
+            show_touched_dirs: None,
+            issue_pattern: None,
+            issue_url: None,
+            show_commit_types: None,
+            show_reviews: None,
+            push_retries: None,
+            delete_remote: None,
+            show_columns: None,
+            protection_action: None,
+            forge: None,
+            forge_command: None,
+            forge_token: None,
+            offline: self.offline(),
+            keep_going: self.keep_going(),
+            usage_stats: None,
+            squash_message: None,
+            commit_template: None,
+            empty_commits: None,
+            merge_options: (!self.strategy_option.is_empty())
+                .then(|| git_stack::config::MergeOptions::new(self.strategy_option.clone())),
+            push_refspec: None,
+            ci_trigger: None,
+            ci_wait: self.ci_wait,
+            summary: None,
+            max_commits_per_branch: None,
+            max_commits_action: None,
 
             capacity: None,
         }
     }
 
+    pub fn non_interactive(&self) -> bool {
+        self.non_interactive || !atty::is(atty::Stream::Stdout)
+    }
+
     pub fn repair(&self) -> Option<bool> {
         resolve_bool_arg(self.repair, self.no_repair)
     }
+
+    pub fn offline(&self) -> Option<bool> {
+        resolve_bool_arg(self.offline, self.no_offline)
+    }
+
+    pub fn keep_going(&self) -> Option<bool> {
+        resolve_bool_arg(self.keep_going, self.no_keep_going)
+    }
 }
 
 fn resolve_bool_arg(yes: bool, no: bool) -> Option<bool> {
@@ -111,13 +670,22 @@ fn resolve_bool_arg(yes: bool, no: bool) -> Option<bool> {
     }
 }
 
+fn parse_exec_at(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((rev, command)) if !rev.is_empty() && !command.is_empty() => {
+            Ok((rev.to_owned(), command.to_owned()))
+        }
+        _ => Err(format!("expected `REV=CMD`, got {:?}", s)),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn verify_app() {
-        use clap::IntoApp;
-        Args::into_app().debug_assert()
+        use clap::CommandFactory;
+        Args::command().debug_assert()
     }
 }
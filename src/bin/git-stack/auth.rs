@@ -0,0 +1,59 @@
+use git_stack::forge_log;
+use proc_exit::WithCodeResultExt;
+
+pub fn auth(args: &crate::args::Args, auth_args: &crate::args::AuthArgs) -> proc_exit::ExitResult {
+    forge_log!(trace, "Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+
+    match auth_args.action {
+        crate::args::AuthAction::Status => status(&repo, &repo_config),
+    }
+}
+
+fn status(
+    repo: &git2::Repository,
+    repo_config: &git_stack::config::RepoConfig,
+) -> proc_exit::ExitResult {
+    use std::io::Write;
+
+    if let Some(command) = repo_config.forge_command() {
+        writeln!(
+            std::io::stdout(),
+            "`{}` authenticates on its own (`stack.forge-command`)",
+            command
+        )?;
+        return Ok(());
+    }
+    let kind = repo_config.forge();
+    if kind == git_stack::config::Forge::None {
+        return Err(proc_exit::Code::CONFIG_ERR
+            .with_message("No forge to authenticate with, see `stack.forge`"));
+    }
+
+    let (base, _) = git_stack::forge::remote_project(repo, repo_config.pull_remote())
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+    let host = base.split_once("://").map(|(_, h)| h).unwrap_or(&base);
+    match git_stack::forge::find_token(repo, repo_config, kind, &base) {
+        Some(token) => {
+            writeln!(
+                std::io::stdout(),
+                "{}: using the {} token from {}",
+                host,
+                kind,
+                token.source
+            )?;
+            Ok(())
+        }
+        None => Err(proc_exit::Code::FAILURE.with_message(format!(
+            "{}: no {} token in `stack.forge-token`, the environment, the {} CLI's login, or the git credential helper",
+            host,
+            kind,
+            kind
+        ))),
+    }
+}
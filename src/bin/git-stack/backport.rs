@@ -0,0 +1,170 @@
+use bstr::ByteSlice;
+use proc_exit::WithCodeResultExt;
+
+/// Cherry-pick a branch's own commits onto each `--to` release branch, as a new branch per target
+///
+/// Like `git cherry-pick -x`, each commit records the commit it came from.  Targets are
+/// independent: conflicts on one are reported and the others are still backported.
+pub fn backport(
+    args: &crate::args::Args,
+    backport_args: &crate::args::BackportArgs,
+) -> proc_exit::ExitResult {
+    let exported = crate::stgit::Exported::new(args, Some(backport_args.branch.as_str()))?;
+    let branch = &exported.branch;
+    // Only the commits of this layer, not of the branches it is stacked on
+    let layer_start = exported.commits[..exported.commits.len().saturating_sub(1)]
+        .iter()
+        .rposition(|id| {
+            !exported.protected_branches.contains_oid(*id) && exported.branches.contains_oid(*id)
+        })
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let layer = &exported.commits[layer_start..];
+    if layer.is_empty() {
+        log::info!(
+            "Nothing to backport, `{}` is at `{}`",
+            branch.name,
+            exported.base.name
+        );
+        return Ok(());
+    }
+
+    let repo = exported.repo.raw();
+    let committer = repo.signature().with_code(proc_exit::Code::CONFIG_ERR)?;
+    let mut failed = Vec::new();
+    for target in backport_args.to.iter() {
+        let target_branch = match exported
+            .repo
+            .find_local_branch(target)
+            .or_else(|| exported.repo.find_remote_branch(target))
+        {
+            Some(target_branch) => target_branch,
+            None => {
+                log::error!("{}: no such branch", target);
+                failed.push(target.as_str());
+                continue;
+            }
+        };
+        let name = format!("backport/{}/{}", target_branch.local_name(), branch.name);
+        if repo.find_branch(&name, git2::BranchType::Local).is_ok() {
+            log::error!("{}: `{}` already exists", target, name);
+            failed.push(target.as_str());
+            continue;
+        }
+
+        match cherry_pick_all(repo, &committer, target_branch.id, layer)
+            .with_code(proc_exit::Code::FAILURE)?
+        {
+            Backported::Tip(tip, applied) => {
+                log::trace!("git branch {} {}", name, tip);
+                if !args.dry_run {
+                    let commit = repo.find_commit(tip).with_code(proc_exit::Code::FAILURE)?;
+                    repo.branch(&name, &commit, false)
+                        .with_code(proc_exit::Code::FAILURE)?;
+                    set_upstream(repo, &name, &target_branch)
+                        .with_code(proc_exit::Code::FAILURE)?;
+                }
+                log::info!("{}: backported {} commits as `{}`", target, applied, name);
+            }
+            Backported::Conflict(id, paths) => {
+                let summary = repo
+                    .find_commit(id)
+                    .ok()
+                    .and_then(|c| c.summary().map(|s| s.to_owned()))
+                    .unwrap_or_default();
+                log::error!(
+                    "{}: {} {} conflicts in {}",
+                    target,
+                    &id.to_string()[..7],
+                    summary,
+                    paths
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                failed.push(target.as_str());
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(proc_exit::Code::FAILURE.with_message(format!(
+            "Could not backport `{}` to {}",
+            branch.name,
+            failed.join(", ")
+        )))
+    }
+}
+
+enum Backported {
+    /// The new tip, with how many commits weren't already applied
+    Tip(git2::Oid, usize),
+    /// The commit that didn't apply, with its conflicting paths
+    Conflict(git2::Oid, Vec<std::path::PathBuf>),
+}
+
+fn cherry_pick_all(
+    repo: &git2::Repository,
+    committer: &git2::Signature<'_>,
+    onto: git2::Oid,
+    commits: &[git2::Oid],
+) -> Result<Backported, git2::Error> {
+    let mut tip = onto;
+    let mut applied = 0;
+    for id in commits.iter() {
+        let cherry = repo.find_commit(*id)?;
+        let parent = repo.find_commit(tip)?;
+        let mut index = repo.cherrypick_commit(&cherry, &parent, 0, None)?;
+        if index.has_conflicts() {
+            let paths = index
+                .conflicts()?
+                .filter_map(Result::ok)
+                .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+                .map(|entry| {
+                    std::path::PathBuf::from(String::from_utf8_lossy(&entry.path).as_ref())
+                })
+                .collect();
+            return Ok(Backported::Conflict(*id, paths));
+        }
+        let tree_id = index.write_tree_to(repo)?;
+        if tree_id == parent.tree_id() {
+            log::debug!("Skipping {}, already applied to {}", id, onto);
+            continue;
+        }
+        let tree = repo.find_tree(tree_id)?;
+        let mut message = cherry.message_bytes().trim_end().to_vec();
+        message.extend_from_slice(format!("\n\n(cherry picked from commit {})\n", id).as_bytes());
+        tip = git_stack::git::write_commit(
+            repo,
+            &cherry.author(),
+            committer,
+            &message,
+            cherry.message_encoding(),
+            &tree,
+            &[&parent],
+        )?;
+        applied += 1;
+    }
+    Ok(Backported::Tip(tip, applied))
+}
+
+/// Track the release branch, so the backport is stacked on it
+fn set_upstream(
+    repo: &git2::Repository,
+    name: &str,
+    target: &git_stack::git::Branch,
+) -> Result<(), git2::Error> {
+    let mut config = repo.config()?.open_level(git2::ConfigLevel::Local)?;
+    config.set_str(
+        &format!("branch.{}.remote", name),
+        target.remote.as_deref().unwrap_or("."),
+    )?;
+    config.set_str(
+        &format!("branch.{}.merge", name),
+        &format!("refs/heads/{}", target.local_name()),
+    )?;
+    Ok(())
+}
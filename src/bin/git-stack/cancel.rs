@@ -0,0 +1,53 @@
+/// While alive, `SIGINT` and `SIGTERM` cancel a [`git_stack::git::Cancel`] rather than exiting
+///
+/// A second signal still exits right away, in case we are stuck somewhere that doesn't check.
+pub struct CatchSignals {
+    ids: Vec<signal_hook::SigId>,
+}
+
+impl CatchSignals {
+    pub fn new(cancel: &git_stack::git::Cancel) -> Self {
+        let mut ids = Vec::new();
+        for (signal, code) in [
+            (signal_hook::consts::SIGINT, proc_exit::Code::SIGINT),
+            (signal_hook::consts::SIGTERM, proc_exit::Code::SIGTERM),
+        ] {
+            // Registered first, so it only sees the flag an earlier signal set
+            let registered =
+                signal_hook::flag::register_conditional_shutdown(signal, code.raw(), cancel.flag())
+                    .and_then(|id| {
+                        ids.push(id);
+                        signal_hook::flag::register(signal, cancel.flag())
+                    });
+            match registered {
+                Ok(id) => ids.push(id),
+                Err(err) => log::debug!("Could not catch signal {}: {}", signal, err),
+            }
+        }
+        Self { ids }
+    }
+}
+
+impl Drop for CatchSignals {
+    fn drop(&mut self) {
+        for id in self.ids.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+/// Stop because `cancel` is, with `outcome` saying what was (or wasn't) done
+pub fn exit(
+    cancel: &git_stack::git::Cancel,
+    timeout: Option<std::time::Duration>,
+    outcome: &str,
+) -> proc_exit::Exit {
+    match timeout.filter(|_| cancel.timed_out()) {
+        Some(timeout) => proc_exit::Code::FAILURE.with_message(format!(
+            "Timed out after {}, {}",
+            humantime::format_duration(timeout),
+            outcome
+        )),
+        None => proc_exit::Code::SIGINT.with_message(format!("Interrupted, {}", outcome)),
+    }
+}
@@ -25,14 +25,181 @@ pub fn dump_config(
     Ok(())
 }
 
-pub fn protect(args: &crate::args::Args, ignore: &str) -> proc_exit::ExitResult {
+pub fn config(
+    args: &crate::args::Args,
+    config_args: &crate::args::ConfigArgs,
+) -> proc_exit::ExitResult {
+    if let Some(source) = config_args.apply.as_deref() {
+        return apply(args, source);
+    }
+    if !config_args.validate {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message("Nothing to do, pass `--validate` or `--apply <source>`"));
+    }
+
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let diagnostics = git_stack::config::RepoConfig::diagnose(&repo);
+    if diagnostics.is_empty() {
+        log::info!("No problems found");
+        return Ok(());
+    }
+
+    let mut stdout = std::io::stdout();
+    for diagnostic in &diagnostics {
+        writeln!(stdout, "{}", diagnostic)?;
+    }
+    Err(proc_exit::Code::CONFIG_ERR.with_message(format!(
+        "{} config value(s) will be ignored",
+        diagnostics.len()
+    )))
+}
+
+fn apply(args: &crate::args::Args, source: &str) -> proc_exit::ExitResult {
     log::trace!("Initializing");
     let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
     let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
 
-    let mut repo_config = git_stack::config::RepoConfig::from_repo(&repo)
+    let is_url = source.starts_with("https://") || source.starts_with("http://");
+    if is_url {
+        let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+            .with_code(proc_exit::Code::CONFIG_ERR)?
+            .update(args.to_config());
+        crate::stack::require_online(&repo_config, "Applying a URL")?;
+    }
+    let path = if is_url {
+        let path = repo.path().join("stack").join("config-fragment.tmp");
+        download(&repo, source, &path)?;
+        path
+    } else {
+        std::path::PathBuf::from(source)
+    };
+    // Record where local files are, regardless of where this was run from
+    let source = if is_url {
+        source.to_owned()
+    } else {
+        std::fs::canonicalize(&path)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| source.to_owned())
+    };
+    let loaded = load_fragment(&path);
+    if is_url {
+        let _ = std::fs::remove_file(&path);
+    }
+    let (fragment, settings) = loaded?;
+
+    let mut stdout = std::io::stdout();
+    for setting in settings {
+        writeln!(stdout, "{}", setting)?;
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let repo_config = git_stack::config::RepoConfig::load_for_write(&repo)
         .with_code(proc_exit::Code::CONFIG_ERR)?
-        .update(args.to_config());
+        .update(fragment);
+    repo_config
+        .write_repo(&repo)
+        .with_code(proc_exit::Code::FAILURE)?;
+    git_stack::config::RepoConfig::record_source(&repo, &source)
+        .with_code(proc_exit::Code::FAILURE)?;
+    log::info!("Applied {}", source);
+
+    Ok(())
+}
+
+/// Parse a shared config file, warning about anything that won't carry over
+///
+/// Also returns the settings that will be applied, as written.
+fn load_fragment(
+    path: &std::path::Path,
+) -> Result<(git_stack::config::RepoConfig, Vec<String>), proc_exit::Exit> {
+    if !path.is_file() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("No config file at {}", path.display())));
+    }
+    let diagnostics = git_stack::config::RepoConfig::diagnose_path(path);
+    for diagnostic in diagnostics.iter() {
+        log::warn!("{}", diagnostic);
+    }
+
+    let config = git2::Config::open(path).with_code(proc_exit::Code::CONFIG_ERR)?;
+    let mut settings = Vec::new();
+    let entries = config
+        .entries(Some("^(stack|branch-stash)\\."))
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+    for entry in entries.into_iter().flatten() {
+        let (key, value) = match (entry.name(), entry.value()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        let ignored = diagnostics
+            .iter()
+            .any(|d| d.key == key && d.value.as_deref() == Some(value));
+        if !ignored {
+            settings.push(format!("{}={}", key, value));
+        }
+    }
+
+    let fragment =
+        git_stack::config::RepoConfig::from_path(path).with_code(proc_exit::Code::CONFIG_ERR)?;
+    Ok((fragment, settings))
+}
+
+/// Fetch with `curl`, honoring `git`'s proxy settings
+fn download(
+    repo: &git2::Repository,
+    url: &str,
+    path: &std::path::Path,
+) -> Result<(), proc_exit::Exit> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_code(proc_exit::Code::FAILURE)?;
+    }
+    let http = git_stack::git::HttpConfig::from_repo(repo);
+    let mut cmd = std::process::Command::new("curl");
+    cmd.args([
+        "--fail",
+        "--silent",
+        "--show-error",
+        "--location",
+        "--config",
+        "-",
+        "--output",
+    ])
+    .arg(path)
+    .arg(url);
+    if !http.ssl_verify {
+        cmd.arg("--insecure");
+    }
+    log::trace!("Running {:?}", cmd);
+    let curl_failed = |err: std::io::Error| {
+        proc_exit::Code::FAILURE.with_message(format!("Could not run `curl`: {}", err))
+    };
+    let mut child = cmd
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(curl_failed)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        http.write_curl_config(&mut stdin, &[])
+            .map_err(curl_failed)?;
+    }
+    let status = child.wait().map_err(curl_failed)?;
+    if !status.success() {
+        return Err(proc_exit::Code::FAILURE.with_message(format!("Could not download {}", url)));
+    }
+    Ok(())
+}
+
+pub fn protect(ignore: &str) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let mut repo_config = git_stack::config::RepoConfig::load_for_write(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
     repo_config
         .protected_branches
         .get_or_insert_with(Vec::new)
@@ -54,12 +221,18 @@ pub fn protected(args: &crate::args::Args) -> proc_exit::ExitResult {
         .with_code(proc_exit::Code::CONFIG_ERR)?
         .update(args.to_config());
     let protected = git_stack::git::ProtectedBranches::new(
-        repo_config.protected_branches().iter().map(|s| s.as_str()),
+        crate::forge::protected_patterns(&repo, &repo_config)
+            .iter()
+            .map(|s| s.as_str()),
     )
     .with_code(proc_exit::Code::CONFIG_ERR)?;
+    let ignored =
+        git_stack::git::BranchGlobs::new(repo_config.ignore_branches().iter().map(|s| s.as_str()))
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
 
-    let repo = git_stack::git::GitRepo::new(repo);
-    let branches = git_stack::git::Branches::new(repo.local_branches());
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    repo.set_pull_remote(repo_config.pull_remote());
+    let branches = git_stack::git::Branches::with_ignore(repo.local_branches(), &ignored);
     let protected_branches = branches.protected(&protected);
 
     for (branch_id, branches) in branches.iter() {
@@ -73,6 +246,9 @@ pub fn protected(args: &crate::args::Args) -> proc_exit::ExitResult {
             }
         }
     }
+    for branch in crate::stack::remote_protected_branches(&repo, &branches, &protected) {
+        writeln!(std::io::stdout(), "{}", branch.name)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,172 @@
+use std::fmt::Write as _;
+
+use bstr::ByteSlice;
+
+/// A branch that could not be restacked
+#[derive(serde::Serialize)]
+pub struct RestackFailure {
+    pub branch: String,
+    pub message: String,
+    /// Dependent branches that were not restacked because of this failure
+    pub blocked: Vec<String>,
+    /// The commit that failed to apply
+    pub commit: Option<CommitRef>,
+    /// Paths with conflicts
+    pub files: Vec<String>,
+    /// Commits from the stack that touch `files`
+    pub stack_commits: Vec<CommitRef>,
+    /// Commits from the new base that touch `files`
+    pub base_commits: Vec<CommitRef>,
+}
+
+impl RestackFailure {
+    pub fn new(branch: &str, err: &git2::Error, blocked: &[&str]) -> Self {
+        Self {
+            branch: branch.to_owned(),
+            message: err.message().to_owned(),
+            blocked: blocked.iter().map(|b| (*b).to_owned()).collect(),
+            commit: None,
+            files: Vec::new(),
+            stack_commits: Vec::new(),
+            base_commits: Vec::new(),
+        }
+    }
+
+    /// Describe the conflict between `pick` and the stack's new base, `onto_id`
+    pub fn with_conflict(
+        mut self,
+        repo: &git_stack::git::GitRepo,
+        pick: &git_stack::git::FailedPick,
+        onto_id: git2::Oid,
+    ) -> Self {
+        let files = match repo.cherry_pick_conflicts(pick.onto_id, pick.commit_id) {
+            Ok(files) => files,
+            Err(err) => {
+                log::debug!("Could not find conflicts for {}: {}", pick.commit_id, err);
+                return self;
+            }
+        };
+        self.commit = repo.find_commit(pick.commit_id).map(|c| CommitRef::new(&c));
+        self.stack_commits = repo
+            .commits_touching(pick.commit_id, onto_id, &files)
+            .unwrap_or_default()
+            .iter()
+            .map(|c| CommitRef::new(c))
+            .collect();
+        self.base_commits = repo
+            .commits_touching(onto_id, pick.commit_id, &files)
+            .unwrap_or_default()
+            .iter()
+            .map(|c| CommitRef::new(c))
+            .collect();
+        self.files = files.iter().map(|f| f.display().to_string()).collect();
+        self
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct CommitRef {
+    pub id: String,
+    pub summary: String,
+}
+
+impl CommitRef {
+    fn new(commit: &git_stack::git::Commit) -> Self {
+        Self {
+            id: commit.id.to_string(),
+            summary: commit.summary.to_str_lossy().into_owned(),
+        }
+    }
+}
+
+/// Triage information for branches that no longer apply
+#[derive(serde::Serialize)]
+pub struct ConflictReport<'f> {
+    pub conflicts: &'f [RestackFailure],
+    /// Resolve these branches first, as the others are blocked on them
+    pub resolution_order: Vec<&'f str>,
+}
+
+impl<'f> ConflictReport<'f> {
+    pub fn new(conflicts: &'f [RestackFailure]) -> Self {
+        // Failures are reported parent-first, so dependents naturally come after
+        let mut resolution_order: Vec<&str> = Vec::new();
+        for conflict in conflicts {
+            for branch in std::iter::once(&conflict.branch).chain(conflict.blocked.iter()) {
+                if !resolution_order.contains(&branch.as_str()) {
+                    resolution_order.push(branch);
+                }
+            }
+        }
+        Self {
+            conflicts,
+            resolution_order,
+        }
+    }
+
+    /// Write as markdown when `path` ends in `.md`, otherwise as JSON
+    pub fn write(&self, path: &std::path::Path) -> eyre::Result<()> {
+        let content = if path.extension().map(|e| e == "md").unwrap_or(false) {
+            self.to_markdown()
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(md, "# Conflicts");
+        let _ = writeln!(md);
+        let _ = writeln!(md, "Resolution order: {}", self.resolution_order.join(", "));
+        for conflict in self.conflicts {
+            let _ = writeln!(md);
+            let _ = writeln!(md, "## `{}`", conflict.branch);
+            let _ = writeln!(md);
+            if let Some(commit) = conflict.commit.as_ref() {
+                let _ = writeln!(md, "Failed to apply {} {}", commit.id, commit.summary);
+            } else {
+                let _ = writeln!(md, "{}", conflict.message.trim());
+            }
+            if !conflict.blocked.is_empty() {
+                let _ = writeln!(md);
+                let _ = writeln!(md, "Blocked: {}", conflict.blocked.join(", "));
+            }
+            write_list(
+                &mut md,
+                "Files",
+                conflict.files.iter().map(|f| format!("`{}`", f)),
+            );
+            write_list(
+                &mut md,
+                "Stack commits",
+                conflict
+                    .stack_commits
+                    .iter()
+                    .map(|c| format!("{} {}", c.id, c.summary)),
+            );
+            write_list(
+                &mut md,
+                "Base commits",
+                conflict
+                    .base_commits
+                    .iter()
+                    .map(|c| format!("{} {}", c.id, c.summary)),
+            );
+        }
+        md
+    }
+}
+
+fn write_list(md: &mut String, title: &str, items: impl Iterator<Item = String>) {
+    let mut items = items.peekable();
+    if items.peek().is_none() {
+        return;
+    }
+    let _ = writeln!(md);
+    let _ = writeln!(md, "{}:", title);
+    for item in items {
+        let _ = writeln!(md, "- {}", item);
+    }
+}
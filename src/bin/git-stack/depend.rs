@@ -0,0 +1,109 @@
+use proc_exit::WithCodeResultExt;
+
+/// Like labels, dependencies live alongside the branch's other settings
+const DEPENDS_KEY: &str = "stack-depends-on";
+
+pub fn depend(
+    args: &crate::args::Args,
+    depend_args: &crate::args::DependArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    match &depend_args.action {
+        crate::args::DependAction::Add { on, branch } => {
+            let branch = crate::label::resolve_branch(&repo, branch.as_deref())?;
+            repo.find_branch(on, git2::BranchType::Local)
+                .with_code(proc_exit::Code::USAGE_ERR)?;
+            if *on == branch {
+                return Err(proc_exit::Code::USAGE_ERR
+                    .with_message(format!("`{}` can't depend on itself", branch)));
+            }
+            let key = depends_key(&branch);
+            log::trace!("git config --add {} {}", key, on);
+            if !args.dry_run {
+                let mut config =
+                    crate::label::local_config(&repo).with_code(proc_exit::Code::FAILURE)?;
+                // Replacing an identical value keeps dependencies unique
+                config
+                    .set_multivar(&key, &format!("^{}$", regex::escape(on)), on)
+                    .with_code(proc_exit::Code::FAILURE)?;
+            }
+            log::info!("{} now depends on {}", branch, on);
+        }
+        crate::args::DependAction::Remove { on, branch } => {
+            let branch = crate::label::resolve_branch(&repo, branch.as_deref())?;
+            if !branch_dependencies(&repo)
+                .iter()
+                .any(|(dependent, dependency)| *dependent == branch && dependency == on)
+            {
+                return Err(proc_exit::Code::USAGE_ERR
+                    .with_message(format!("`{}` does not depend on `{}`", branch, on)));
+            }
+            let key = depends_key(&branch);
+            log::trace!("git config --unset {} {}", key, on);
+            if !args.dry_run {
+                let mut config =
+                    crate::label::local_config(&repo).with_code(proc_exit::Code::FAILURE)?;
+                config
+                    .remove_multivar(&key, &format!("^{}$", regex::escape(on)))
+                    .with_code(proc_exit::Code::FAILURE)?;
+            }
+            log::info!("{} no longer depends on {}", branch, on);
+        }
+        crate::args::DependAction::List => {
+            use std::io::Write;
+            for (dependent, dependency) in branch_dependencies(&repo) {
+                writeln!(std::io::stdout(), "{} -> {}", dependent, dependency)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Each `(dependent, dependency)` pair of branches
+pub(crate) fn branch_dependencies(repo: &git2::Repository) -> Vec<(String, String)> {
+    let mut dependencies = Vec::new();
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(err) => {
+            log::debug!("Could not read dependencies: {}", err);
+            return dependencies;
+        }
+    };
+    let pattern = format!("^branch\\..*\\.{}$", regex::escape(DEPENDS_KEY));
+    let entries = match config.entries(Some(&pattern)) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Could not read dependencies: {}", err);
+            return dependencies;
+        }
+    };
+    for entry in &entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::debug!("Could not read dependency: {}", err);
+                continue;
+            }
+        };
+        let branch = entry
+            .name()
+            .and_then(|n| n.strip_prefix("branch."))
+            .and_then(|n| n.strip_suffix(DEPENDS_KEY))
+            .and_then(|n| n.strip_suffix('.'));
+        if let (Some(branch), Some(value)) = (branch, entry.value()) {
+            let dependency = (branch.to_owned(), value.to_owned());
+            if !dependencies.contains(&dependency) {
+                dependencies.push(dependency);
+            }
+        }
+    }
+    dependencies
+}
+
+pub(crate) fn depends_key(branch: &str) -> String {
+    format!("branch.{}.{}", branch, DEPENDS_KEY)
+}
@@ -0,0 +1,60 @@
+use proc_exit::WithCodeResultExt;
+
+pub fn focus(
+    args: &crate::args::Args,
+    focus_args: &crate::args::FocusArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let path = focus_path(&repo);
+
+    if focus_args.clear {
+        log::trace!("rm {}", path.display());
+        if !args.dry_run {
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                res => res.with_code(proc_exit::Code::FAILURE)?,
+            }
+        }
+        log::info!("Cleared focus");
+    } else if let Some(branch) = focus_args.branch.as_deref() {
+        repo.find_branch(branch, git2::BranchType::Local)
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+        log::trace!("echo {} > {}", branch, path.display());
+        if !args.dry_run {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).with_code(proc_exit::Code::FAILURE)?;
+            }
+            std::fs::write(&path, format!("{}\n", branch)).with_code(proc_exit::Code::FAILURE)?;
+        }
+        log::info!("Focused on the stack of {}", branch);
+    } else {
+        use std::io::Write;
+        if let Some(branch) = read_focus(&repo) {
+            writeln!(std::io::stdout(), "{}", branch)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The branch whose stack `git stack` is pinned to, if any
+pub(crate) fn read_focus(repo: &git2::Repository) -> Option<String> {
+    let path = focus_path(repo);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            log::debug!("Could not read {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    let branch = content.trim();
+    (!branch.is_empty()).then(|| branch.to_owned())
+}
+
+/// Per-worktree, alongside the commit cache
+pub(crate) fn focus_path(repo: &git2::Repository) -> std::path::PathBuf {
+    repo.path().join("stack").join("focus")
+}
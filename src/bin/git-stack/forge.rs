@@ -0,0 +1,291 @@
+use git_stack::forge_log;
+
+/// Branches to protect: the configured patterns plus those protected on the forge
+///
+/// The forge's list is whatever was last fetched by [`refresh`], so this never hits the network.
+pub(crate) fn protected_patterns(
+    repo: &git2::Repository,
+    repo_config: &git_stack::config::RepoConfig,
+) -> Vec<String> {
+    let mut patterns = repo_config.protected_branches().to_vec();
+    if has_forge(repo_config) {
+        let path = cache_path(repo);
+        match std::fs::read_to_string(&path) {
+            Ok(content) => {
+                let forge_patterns = content.lines().filter(|l| !l.is_empty());
+                patterns.extend(forge_patterns.map(|l| l.to_owned()));
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                forge_log!(
+                    debug,
+                    "No protected branches from {} yet, run `git stack prefetch`",
+                    forge_name(repo_config)
+                );
+            }
+            Err(err) => {
+                forge_log!(warn, "Could not read {}: {}", path.display(), err);
+            }
+        }
+    }
+    patterns
+}
+
+/// Fetch the protected branches of `stack.pull-remote` from the forge
+///
+/// On failure, the last list fetched stays in use.
+pub(crate) fn refresh(
+    repo: &git2::Repository,
+    repo_config: &git_stack::config::RepoConfig,
+    dry_run: bool,
+) {
+    if !has_forge(repo_config) {
+        return;
+    }
+    if repo_config.offline() {
+        forge_log!(
+            debug,
+            "Offline, reusing the protected branches last fetched from {}",
+            forge_name(repo_config)
+        );
+        return;
+    }
+    forge_log!(
+        debug,
+        "Fetching protected branches of `{}` from {}",
+        repo_config.pull_remote(),
+        forge_name(repo_config)
+    );
+    if dry_run {
+        return;
+    }
+    let res = git_stack::forge::from_config(repo, repo_config).and_then(|forge| {
+        let mut forge = forge.expect("checked for a forge");
+        let names = forge.list_protected()?;
+        let path = cache_path(repo);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut content = names.join("\n");
+        content.push('\n');
+        std::fs::write(&path, content)?;
+        Ok(())
+    });
+    if let Err(err) = res {
+        forge_log!(
+            warn,
+            "Could not fetch protected branches from {}, {}",
+            forge_name(repo_config),
+            err
+        );
+    }
+}
+
+fn has_forge(repo_config: &git_stack::config::RepoConfig) -> bool {
+    repo_config.forge_command().is_some() || repo_config.forge() != git_stack::config::Forge::None
+}
+
+fn forge_name(repo_config: &git_stack::config::RepoConfig) -> String {
+    match repo_config.forge_command() {
+        Some(command) => format!("`{}`", command),
+        None => repo_config.forge().to_string(),
+    }
+}
+
+/// Kept with our other state, under `.git/stack/`
+fn cache_path(repo: &git2::Repository) -> std::path::PathBuf {
+    repo.path().join("stack").join("forge-protected")
+}
+
+/// How often `stack.ci-wait` asks the forge for CI results
+const CI_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// `stack.ci-trigger` and `stack.ci-wait`, run on the branches `git stack --push` pushed
+pub(crate) struct Ci {
+    trigger: git_stack::config::CiTrigger,
+    wait: Option<std::time::Duration>,
+    forge: Option<Box<dyn git_stack::forge::Forge>>,
+    webhook: Option<git_stack::forge::Webhook>,
+}
+
+impl Ci {
+    /// `None` when there is nothing to trigger or wait on
+    pub(crate) fn from_config(
+        repo: &git2::Repository,
+        repo_config: &git_stack::config::RepoConfig,
+    ) -> eyre::Result<Option<Self>> {
+        let trigger = repo_config.ci_trigger();
+        let wait = repo_config.ci_wait();
+        if trigger == git_stack::config::CiTrigger::None && wait.is_none() {
+            return Ok(None);
+        }
+        let forge = git_stack::forge::from_config(repo, repo_config)?;
+        let needs_forge = match &trigger {
+            git_stack::config::CiTrigger::Forge | git_stack::config::CiTrigger::Workflow(_) => {
+                Some("`stack.ci-trigger`")
+            }
+            _ if wait.is_some() => Some("`stack.ci-wait`"),
+            _ => None,
+        };
+        if let (Some(needs), None) = (needs_forge, &forge) {
+            eyre::bail!(
+                "{} needs `stack.forge` or `stack.forge-command` to be set",
+                needs
+            );
+        }
+        let webhook = match &trigger {
+            git_stack::config::CiTrigger::Webhook(url) => Some(git_stack::forge::Webhook::new(
+                git_stack::forge::Client::new(repo),
+                url.clone(),
+                repo_config.push_remote().to_owned(),
+            )),
+            _ => None,
+        };
+        Ok(Some(Self {
+            trigger,
+            wait,
+            forge,
+            webhook,
+        }))
+    }
+
+    /// Trigger CI on the just-pushed `branches`, then wait on the results if asked to
+    ///
+    /// Fails if CI can't be triggered or fails for any of them.
+    pub(crate) fn run(
+        &mut self,
+        repo: &git_stack::git::GitRepo,
+        progress: crate::progress::Progress,
+        cancel: &git_stack::git::Cancel,
+        branches: &[String],
+        dry_run: bool,
+    ) -> eyre::Result<()> {
+        // CI knows branches by their name on the remote, see `stack.push-refspec`
+        let mut remote_branches = Vec::new();
+        for branch in branches {
+            let remote_branch = repo
+                .push_remote_ref(branch)
+                .and_then(|r| r.strip_prefix("refs/heads/").map(|b| b.to_owned()));
+            match remote_branch {
+                Some(remote_branch) => remote_branches.push((branch.as_str(), remote_branch)),
+                None => {
+                    forge_log!(
+                        warn,
+                        "Skipping CI for {}, it isn't pushed to a branch",
+                        branch
+                    );
+                }
+            }
+        }
+
+        let mut failed = Vec::new();
+        let workflow = match &self.trigger {
+            git_stack::config::CiTrigger::Workflow(workflow) => Some(workflow.as_str()),
+            _ => None,
+        };
+        if self.trigger != git_stack::config::CiTrigger::None {
+            for (branch, remote_branch) in remote_branches.iter() {
+                if cancel.is_cancelled() {
+                    failed.push(*branch);
+                    continue;
+                }
+                let commit = match repo.find_local_branch(branch) {
+                    Some(local) => local.id,
+                    None => continue,
+                };
+                forge_log!(
+                    debug,
+                    "Triggering CI ({}) on {}",
+                    self.trigger,
+                    remote_branch
+                );
+                if dry_run {
+                    continue;
+                }
+                let run = git_stack::forge::CiRun {
+                    branch: remote_branch,
+                    commit: commit.to_string(),
+                    workflow,
+                };
+                let res = match (&mut self.webhook, &mut self.forge) {
+                    (Some(webhook), _) => webhook.trigger(&run),
+                    (None, Some(forge)) => forge.trigger_ci(&run),
+                    (None, None) => unreachable!("checked in `from_config`"),
+                };
+                if let Err(err) = res {
+                    forge_log!(error, "Could not trigger CI on {}, {}", branch, err);
+                    failed.push(*branch);
+                }
+            }
+        }
+        if !failed.is_empty() {
+            eyre::bail!("could not trigger CI on {}", failed.join(", "));
+        }
+
+        let (wait, forge) = match (self.wait, self.forge.as_mut()) {
+            (Some(wait), Some(forge)) if !dry_run => (wait, forge),
+            _ => return Ok(()),
+        };
+        let names: Vec<_> = remote_branches.iter().map(|(_, r)| r.as_str()).collect();
+        let local_name = |remote_branch: &str| {
+            remote_branches
+                .iter()
+                .find(|(_, r)| r == remote_branch)
+                .map(|(b, _)| *b)
+                .unwrap_or(remote_branch)
+                .to_owned()
+        };
+        log::info!(
+            "Waiting up to {} for CI on {}",
+            humantime::format_duration(wait),
+            remote_branches
+                .iter()
+                .map(|(b, _)| *b)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let statuses = git_stack::forge::wait_for_status(
+            forge.as_mut(),
+            &names,
+            wait,
+            CI_POLL_INTERVAL,
+            cancel,
+            |remote_branch, status| {
+                let branch = local_name(remote_branch);
+                progress.emit(
+                    "ci",
+                    serde_json::json!({
+                        "branch": branch,
+                        "status": status.to_string(),
+                    }),
+                );
+                match status {
+                    git_stack::forge::Status::Failure => log::info!("{}: CI failed", branch),
+                    _ => log::info!("{}: CI passed", branch),
+                }
+            },
+        )?;
+
+        let mut failed = Vec::new();
+        let mut pending = Vec::new();
+        for (remote_branch, status) in statuses {
+            match status {
+                git_stack::forge::Status::Failure => failed.push(local_name(&remote_branch)),
+                git_stack::forge::Status::Success => {}
+                git_stack::forge::Status::None | git_stack::forge::Status::Pending => {
+                    pending.push(local_name(&remote_branch))
+                }
+            }
+        }
+        if !pending.is_empty() {
+            log::warn!(
+                "CI is still running on {} after {}",
+                pending.join(", "),
+                humantime::format_duration(wait)
+            );
+        }
+        if !failed.is_empty() {
+            eyre::bail!("CI failed on {}", failed.join(", "));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use proc_exit::WithCodeResultExt;
+
+const HOOKS: &[&str] = &["post-commit", "post-checkout", "post-merge"];
+/// Identifies hooks we wrote, so we never clobber or remove anyone else's
+const MARKER: &str = "# Installed by `git stack hooks install`";
+/// Hooks that were there first are moved aside and run ahead of ours
+const CHAINED_SUFFIX: &str = ".pre-git-stack";
+/// Set for the `git stack` we start from a hook, so its own checkouts don't recurse
+const IN_HOOK_ENV: &str = "GIT_STACK_IN_HOOK";
+
+pub fn hooks(
+    args: &crate::args::Args,
+    hooks_args: &crate::args::HooksArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    match &hooks_args.action {
+        crate::args::HooksAction::Install { restack } => {
+            let dir = git_stack::git::hooks_dir(&repo);
+            let in_worktree = repo
+                .workdir()
+                .map(|w| dir.starts_with(w) && !dir.starts_with(repo.path()))
+                .unwrap_or(false);
+            if in_worktree {
+                log::warn!(
+                    "Hooks are in the working tree ({}), so these changes will show up in `git status`",
+                    dir.display()
+                );
+            }
+            for hook in HOOKS {
+                install(&dir, hook, *restack, args.dry_run).with_code(proc_exit::Code::FAILURE)?;
+            }
+        }
+        crate::args::HooksAction::Uninstall => {
+            let dir = git_stack::git::hooks_dir(&repo);
+            for hook in HOOKS {
+                uninstall(&dir, hook, args.dry_run).with_code(proc_exit::Code::FAILURE)?;
+            }
+        }
+        crate::args::HooksAction::Run {
+            hook,
+            restack,
+            hook_args,
+        } => {
+            if std::env::var_os(IN_HOOK_ENV).is_some() {
+                return Ok(());
+            }
+            // Hooks also fire for each step of a rebase, merge, or our own rewrites
+            if repo.state() != git2::RepositoryState::Clean
+                || git_stack::git::Journal::path(&repo).exists()
+            {
+                log::trace!("Skipping {} hook, an operation is in progress", hook);
+                return Ok(());
+            }
+            // The last argument of `post-checkout` is `0` when checking out files, not a branch
+            if hook == "post-checkout" && hook_args.get(2).map(|a| a == "0").unwrap_or(false) {
+                return Ok(());
+            }
+
+            if *restack && hook == "post-commit" {
+                restack_dependents(args.dry_run).with_code(proc_exit::Code::FAILURE)?;
+            } else {
+                crate::stack::warm(args)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn install(dir: &std::path::Path, hook: &str, restack: bool, dry_run: bool) -> eyre::Result<()> {
+    let path = dir.join(hook);
+    let chained = dir.join(format!("{}{}", hook, CHAINED_SUFFIX));
+    match std::fs::read(&path) {
+        Ok(content) if is_ours(&content) => {
+            log::debug!("Updating {}", path.display());
+        }
+        Ok(_) => {
+            if chained.exists() {
+                eyre::bail!(
+                    "Cannot chain to {}, {} is already taken",
+                    path.display(),
+                    chained.display()
+                );
+            }
+            log::info!("Chaining to the existing {} hook", hook);
+            log::trace!("mv {} {}", path.display(), chained.display());
+            if !dry_run {
+                std::fs::rename(&path, &chained)?;
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    log::trace!("Writing {}", path.display());
+    if !dry_run {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&path, script(hook, restack))?;
+        make_executable(&path)?;
+    }
+    log::info!("Installed {} hook", hook);
+    Ok(())
+}
+
+fn uninstall(dir: &std::path::Path, hook: &str, dry_run: bool) -> eyre::Result<()> {
+    let path = dir.join(hook);
+    match std::fs::read(&path) {
+        Ok(content) if is_ours(&content) => {}
+        Ok(_) => {
+            log::debug!(
+                "Leaving {}, it wasn't installed by `git stack`",
+                path.display()
+            );
+            return Ok(());
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    }
+
+    log::trace!("rm {}", path.display());
+    if !dry_run {
+        std::fs::remove_file(&path)?;
+    }
+    let chained = dir.join(format!("{}{}", hook, CHAINED_SUFFIX));
+    if chained.exists() {
+        log::trace!("mv {} {}", chained.display(), path.display());
+        if !dry_run {
+            std::fs::rename(&chained, &path)?;
+        }
+        log::info!("Restored the original {} hook", hook);
+    } else {
+        log::info!("Removed {} hook", hook);
+    }
+    Ok(())
+}
+
+fn is_ours(content: &[u8]) -> bool {
+    use bstr::ByteSlice;
+    content.contains_str(MARKER)
+}
+
+/// `git` ignores the exit status of these hooks, except that `post-checkout`'s becomes
+/// `git checkout`'s, so only a chained hook's failure is passed along
+fn script(hook: &str, restack: bool) -> String {
+    let restack = if restack { " --restack" } else { "" };
+    format!(
+        r#"#!/bin/sh
+{marker}, remove with `git stack hooks uninstall`
+chained="$(dirname "$0")/{hook}{suffix}"
+if [ -x "$chained" ]; then
+    "$chained" "$@" || exit $?
+fi
+git stack hooks run{restack} {hook} -- "$@" || true
+"#,
+        marker = MARKER,
+        hook = hook,
+        suffix = CHAINED_SUFFIX,
+        restack = restack,
+    )
+}
+
+#[cfg(unix)]
+fn make_executable(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &std::path::Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Committing to a lower branch leaves the branches stacked on it behind, so `--rebase` them
+fn restack_dependents(dry_run: bool) -> eyre::Result<()> {
+    let exe = std::env::current_exe()?;
+    let mut cmd = std::process::Command::new(exe);
+    cmd.arg("--rebase")
+        .arg("--stack=dependents")
+        .arg("--format=silent")
+        .env(IN_HOOK_ENV, "1");
+    if dry_run {
+        cmd.arg("--dry-run");
+    }
+    log::trace!("Running {:?}", cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        eyre::bail!("Could not restack, run `git stack --rebase` to see why");
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use std::fmt::Write;
+
+use bstr::ByteSlice;
+use proc_exit::WithCodeResultExt;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 60em; color: #24292f; }
+h1 { font-size: 1.4em; }
+.base { color: #57606a; }
+details { border: 1px solid #d0d7de; border-radius: 6px; margin: 0.5em 0; }
+summary { cursor: pointer; padding: 0.5em; }
+details[open] summary { border-bottom: 1px solid #d0d7de; }
+.id { font-family: monospace; color: #57606a; }
+.branch { font-size: 0.85em; background: #ddf4ff; border-radius: 1em; padding: 0 0.5em; margin-left: 0.5em; }
+.meta { color: #57606a; padding: 0 0.5em; }
+pre { margin: 0; padding: 0.5em; overflow-x: auto; }
+.message { white-space: pre-wrap; }
+.file { font-weight: bold; background: #f6f8fa; }
+.hunk { color: #8250df; background: #f6f8fa; }
+.add { background: #e6ffec; }
+.del { background: #ffebe9; }
+";
+
+/// Write a branch's commits, down to its protected base, as a standalone HTML page
+///
+/// Each commit is collapsed to its summary, expanding to its message and diff, so reviewers can
+/// browse a snapshot of the stack without the repo.
+pub fn export(
+    args: &crate::args::Args,
+    export_args: &crate::args::ExportArgs,
+) -> proc_exit::ExitResult {
+    let exported = crate::stgit::Exported::new(args, export_args.branch.as_deref())?;
+    if exported.commits.is_empty() {
+        log::info!(
+            "Nothing to export, `{}` is at `{}`",
+            exported.branch.name,
+            exported.base.name
+        );
+        return Ok(());
+    }
+
+    let page = render(&exported).with_code(proc_exit::Code::FAILURE)?;
+    let path = export_args.path.as_path();
+    log::trace!("Writing {}", path.display());
+    if !args.dry_run {
+        std::fs::write(path, page).with_code(proc_exit::Code::FAILURE)?;
+    }
+    log::info!(
+        "Exported {} commits from `{}` to {}",
+        exported.commits.len(),
+        exported.branch.name,
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn render(exported: &crate::stgit::Exported) -> Result<String, git2::Error> {
+    let repo = exported.repo.raw();
+    let title = format!("{} on {}", exported.branch.name, exported.base.name);
+
+    let mut page = String::new();
+    page.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    let _ = writeln!(page, "<title>{}</title>", escape(&title));
+    let _ = writeln!(page, "<style>{}</style>", STYLE);
+    page.push_str("</head>\n<body>\n");
+    let _ = writeln!(page, "<h1>{}</h1>", escape(&exported.branch.name));
+    let _ = writeln!(
+        page,
+        "<p class=\"base\">{} commits on <code>{}</code> (<span class=\"id\">{}</span>)</p>",
+        exported.commits.len(),
+        escape(&exported.base.name),
+        short_id(exported.base_id)
+    );
+
+    for id in exported.commits.iter() {
+        let commit = repo.find_commit(*id)?;
+        page.push_str("<details>\n<summary>");
+        let _ = write!(
+            page,
+            "<span class=\"id\">{}</span> {}",
+            short_id(*id),
+            escape(&commit.summary_bytes().unwrap_or_default().to_str_lossy())
+        );
+        if !exported.protected_branches.contains_oid(*id) {
+            for branch in exported.branches.get(*id).into_iter().flatten() {
+                let _ = write!(
+                    page,
+                    "<span class=\"branch\">{}</span>",
+                    escape(&branch.name)
+                );
+            }
+        }
+        page.push_str("</summary>\n");
+
+        let author = exported
+            .repo
+            .mailmap()
+            .and_then(|mailmap| commit.author_with_mailmap(mailmap).ok())
+            .unwrap_or_else(|| commit.author().to_owned());
+        let seconds = author.when().seconds().max(0) as u64;
+        let when = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+        let _ = writeln!(
+            page,
+            "<p class=\"meta\">{} &lt;{}&gt; on {}</p>",
+            escape(&author.name_bytes().to_str_lossy()),
+            escape(&author.email_bytes().to_str_lossy()),
+            humantime::format_rfc3339(when)
+        );
+        let _ = writeln!(
+            page,
+            "<pre class=\"message\">{}</pre>",
+            escape(&commit.message_bytes().trim_end().to_str_lossy())
+        );
+        page.push_str("<pre class=\"diff\">");
+        render_diff(repo, &commit, &mut page)?;
+        page.push_str("</pre>\n</details>\n");
+    }
+
+    page.push_str("</body>\n</html>\n");
+    Ok(page)
+}
+
+/// The commit's diff against its first parent, with a line per `<div>` classed by its origin
+fn render_diff(
+    repo: &git2::Repository,
+    commit: &git2::Commit<'_>,
+    page: &mut String,
+) -> Result<(), git2::Error> {
+    let parent_tree = commit.parent(0)?.tree()?;
+    let tree = commit.tree()?;
+    let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&tree), None)?;
+    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+        let (class, prefix) = match line.origin() {
+            '+' => ("add", "+"),
+            '-' => ("del", "-"),
+            ' ' => ("context", " "),
+            'F' => ("file", ""),
+            'H' => ("hunk", ""),
+            _ => ("context", ""),
+        };
+        let content = line.content().to_str_lossy();
+        for content in content.lines() {
+            let _ = write!(
+                page,
+                "<div class=\"{}\">{}{}</div>",
+                class,
+                prefix,
+                escape(content)
+            );
+        }
+        true
+    })?;
+    Ok(())
+}
+
+fn short_id(id: git2::Oid) -> String {
+    let mut id = id.to_string();
+    id.truncate(7);
+    id
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
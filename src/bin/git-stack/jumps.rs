@@ -0,0 +1,229 @@
+//! Where HEAD was before operations that check out other branches, to get back to
+
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+/// Enough to go back a while, without the file growing forever
+const MAX_JUMPS: usize = 20;
+
+/// Where HEAD was when an operation started
+pub(crate) struct Origin {
+    operation: &'static str,
+    branch: Option<String>,
+    id: git2::Oid,
+    reflog_len: usize,
+}
+
+impl Origin {
+    pub(crate) fn new(repo: &git_stack::git::GitRepo, operation: &'static str) -> Self {
+        Self {
+            operation,
+            branch: repo.head_branch().map(|b| b.name),
+            id: repo.head_commit().id,
+            reflog_len: head_reflog_len(repo.raw()),
+        }
+    }
+
+    /// Go back to the original branch if the operation left HEAD elsewhere, even if it failed
+    ///
+    /// If anything was checked out along the way, the origin is recorded for `git stack back`.
+    pub(crate) fn restore(
+        &self,
+        repo: &mut git_stack::git::GitRepo,
+        git_commands: &crate::progress::GitCommands,
+        dry_run: bool,
+    ) {
+        if dry_run || !checked_out_since(repo.raw(), self.reflog_len) {
+            return;
+        }
+        if let Err(err) = record(repo.raw(), self) {
+            log::debug!("Could not record where HEAD was: {}", err);
+        }
+
+        let branch = match self.branch.as_deref() {
+            Some(branch) => branch,
+            None => return,
+        };
+        if repo.head_branch().map(|b| b.name).as_deref() == Some(branch) {
+            return;
+        }
+        if repo.find_local_branch(branch).is_none() {
+            // e.g. deleted as merged, so wherever we are now is the best there is
+            return;
+        }
+        if git_stack::git::Journal::path(repo.raw()).exists() {
+            log::warn!(
+                "Leaving HEAD for `git stack recover`, run `git stack back` to return to {}",
+                branch
+            );
+            return;
+        }
+        if repo.is_dirty() {
+            log::warn!(
+                "Leaving HEAD as the working tree is dirty, run `git stack back` to return to {}",
+                branch
+            );
+            return;
+        }
+        log::trace!("git switch {}", branch);
+        git_commands.show(&format!("git switch {}", branch));
+        if let Err(err) = repo.switch(branch) {
+            log::warn!(
+                "Could not switch back to {}, run `git stack back`: {}",
+                branch,
+                err
+            );
+        }
+    }
+}
+
+pub fn back(args: &crate::args::Args, back_args: &crate::args::BackArgs) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let jumps = read(&repo).with_code(proc_exit::Code::FAILURE)?;
+
+    if back_args.list {
+        let mut stdout = std::io::stdout();
+        let now = std::time::SystemTime::now();
+        for (i, jump) in jumps.iter().enumerate() {
+            let age = now.duration_since(jump.time).unwrap_or_default();
+            let age = std::time::Duration::from_secs(age.as_secs());
+            writeln!(
+                stdout,
+                "{}: {} ({}) before `{}`, {} ago",
+                i + 1,
+                jump.branch.as_deref().unwrap_or("HEAD"),
+                &jump.id.to_string()[..7],
+                jump.operation,
+                humantime::format_duration(age)
+            )?;
+        }
+        return Ok(());
+    }
+
+    let index = back_args.count.saturating_sub(1);
+    let jump = jumps.get(index).ok_or_else(|| {
+        proc_exit::Code::USAGE_ERR.with_message(format!(
+            "Only {} places to go back to, see `git stack back --list`",
+            jumps.len()
+        ))
+    })?;
+    // The branch may have been rewritten since, so prefer it over the old commit
+    let has_branch = jump
+        .branch
+        .as_deref()
+        .map(|b| repo.find_branch(b, git2::BranchType::Local).is_ok())
+        .unwrap_or(false);
+    let mut cmd = std::process::Command::new("git");
+    cmd.arg("switch");
+    if has_branch {
+        cmd.arg(jump.branch.as_deref().unwrap());
+    } else {
+        cmd.arg("--detach").arg(jump.id.to_string());
+    }
+    log::trace!("{:?}", cmd);
+    if !args.dry_run {
+        let status = cmd.status().with_code(proc_exit::Code::FAILURE)?;
+        if !status.success() {
+            return proc_exit::Code::FAILURE.ok();
+        }
+    }
+    Ok(())
+}
+
+struct Jump {
+    time: std::time::SystemTime,
+    operation: String,
+    branch: Option<String>,
+    id: git2::Oid,
+}
+
+/// Most recent first
+fn read(repo: &git2::Repository) -> Result<Vec<Jump>, std::io::Error> {
+    let content = match std::fs::read_to_string(jumps_path(repo)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut jumps: Vec<_> = content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let secs = fields.next()?.parse().ok()?;
+            let id = git2::Oid::from_str(fields.next()?).ok()?;
+            let branch = fields.next()?;
+            let operation = fields.next()?;
+            Some(Jump {
+                time: std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs),
+                operation: operation.to_owned(),
+                branch: (!branch.is_empty()).then(|| branch.to_owned()),
+                id,
+            })
+        })
+        .collect();
+    jumps.reverse();
+    Ok(jumps)
+}
+
+fn record(repo: &git2::Repository, origin: &Origin) -> Result<(), std::io::Error> {
+    let path = jumps_path(repo);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let line = format!(
+        "{}\t{}\t{}\t{}",
+        secs,
+        origin.id,
+        origin.branch.as_deref().unwrap_or(""),
+        origin.operation
+    );
+    let mut lines: Vec<_> = content
+        .lines()
+        .chain(std::iter::once(line.as_str()))
+        .collect();
+    let excess = lines.len().saturating_sub(MAX_JUMPS);
+    lines.drain(..excess);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::File::create(&path)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn head_reflog_len(repo: &git2::Repository) -> usize {
+    repo.reflog("HEAD").map(|r| r.len()).unwrap_or(0)
+}
+
+/// Whether anything was checked out after HEAD's reflog was `reflog_len` long
+///
+/// Commits and resets also show up in HEAD's reflog, so only checkouts count.
+fn checked_out_since(repo: &git2::Repository, reflog_len: usize) -> bool {
+    let reflog = match repo.reflog("HEAD") {
+        Ok(reflog) => reflog,
+        Err(_) => return false,
+    };
+    let added = reflog.len().saturating_sub(reflog_len);
+    // Newest first
+    reflog.iter().take(added).any(|entry| {
+        entry
+            .message()
+            .map_or(false, |m| m.starts_with("checkout:"))
+    })
+}
+
+/// Per-worktree, like HEAD itself
+fn jumps_path(repo: &git2::Repository) -> std::path::PathBuf {
+    repo.path().join("stack").join("jumps")
+}
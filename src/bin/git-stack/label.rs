@@ -0,0 +1,134 @@
+use proc_exit::WithCodeResultExt;
+
+/// Labels live alongside the branch's other settings, so `git branch -m`/`-D` carry them along
+const LABEL_KEY: &str = "stack-label";
+
+pub fn label(
+    args: &crate::args::Args,
+    label_args: &crate::args::LabelArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    match &label_args.action {
+        crate::args::LabelAction::Add { label, branch } => {
+            let branch = resolve_branch(&repo, branch.as_deref())?;
+            let key = label_key(&branch);
+            log::trace!("git config --add {} {}", key, label);
+            if !args.dry_run {
+                let mut config = local_config(&repo).with_code(proc_exit::Code::FAILURE)?;
+                // Replacing an identical value keeps labels unique
+                config
+                    .set_multivar(&key, &format!("^{}$", regex::escape(label)), label)
+                    .with_code(proc_exit::Code::FAILURE)?;
+            }
+            log::info!("Labeled {} as {}", branch, label);
+        }
+        crate::args::LabelAction::Remove { label, branch } => {
+            let branch = resolve_branch(&repo, branch.as_deref())?;
+            let labels = branch_labels(&repo);
+            if !labels
+                .get(&branch)
+                .into_iter()
+                .flatten()
+                .any(|l| l == label)
+            {
+                return Err(proc_exit::Code::USAGE_ERR
+                    .with_message(format!("`{}` is not labeled `{}`", branch, label)));
+            }
+            let key = label_key(&branch);
+            log::trace!("git config --unset {} {}", key, label);
+            if !args.dry_run {
+                let mut config = local_config(&repo).with_code(proc_exit::Code::FAILURE)?;
+                config
+                    .remove_multivar(&key, &format!("^{}$", regex::escape(label)))
+                    .with_code(proc_exit::Code::FAILURE)?;
+            }
+            log::info!("Removed label {} from {}", label, branch);
+        }
+        crate::args::LabelAction::List => {
+            use std::io::Write;
+            for (branch, labels) in branch_labels(&repo) {
+                writeln!(std::io::stdout(), "{}: {}", branch, labels.join(", "))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Labels for each branch that has any
+pub(crate) fn branch_labels(
+    repo: &git2::Repository,
+) -> std::collections::BTreeMap<String, Vec<String>> {
+    let mut labels = std::collections::BTreeMap::new();
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(err) => {
+            log::debug!("Could not read labels: {}", err);
+            return labels;
+        }
+    };
+    let pattern = format!("^branch\\..*\\.{}$", regex::escape(LABEL_KEY));
+    let entries = match config.entries(Some(&pattern)) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Could not read labels: {}", err);
+            return labels;
+        }
+    };
+    for entry in &entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::debug!("Could not read label: {}", err);
+                continue;
+            }
+        };
+        let branch = entry
+            .name()
+            .and_then(|n| n.strip_prefix("branch."))
+            .and_then(|n| n.strip_suffix(LABEL_KEY))
+            .and_then(|n| n.strip_suffix('.'));
+        if let (Some(branch), Some(value)) = (branch, entry.value()) {
+            let branch_labels: &mut Vec<String> = labels.entry(branch.to_owned()).or_default();
+            if !branch_labels.iter().any(|l| l == value) {
+                branch_labels.push(value.to_owned());
+            }
+        }
+    }
+    labels
+}
+
+pub(crate) fn resolve_branch(
+    repo: &git2::Repository,
+    branch: Option<&str>,
+) -> Result<String, proc_exit::Exit> {
+    match branch {
+        Some(branch) => {
+            repo.find_branch(branch, git2::BranchType::Local)
+                .with_code(proc_exit::Code::USAGE_ERR)?;
+            Ok(branch.to_owned())
+        }
+        None => {
+            let head = repo.head().with_code(proc_exit::Code::USAGE_ERR)?;
+            if !head.is_branch() {
+                return Err(proc_exit::Code::USAGE_ERR
+                    .with_message("Must not be in a detached HEAD state."));
+            }
+            Ok(head
+                .shorthand()
+                .expect("branch names are valid UTF-8")
+                .to_owned())
+        }
+    }
+}
+
+pub(crate) fn label_key(branch: &str) -> String {
+    format!("branch.{}.{}", branch, LABEL_KEY)
+}
+
+pub(crate) fn local_config(repo: &git2::Repository) -> Result<git2::Config, git2::Error> {
+    repo.config()?.open_level(git2::ConfigLevel::Local)
+}
@@ -2,21 +2,58 @@
 #![allow(clippy::let_and_return)]
 #![allow(clippy::if_same_then_else)]
 
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
+use proc_exit::WithCodeResultExt;
 
+mod adopt;
+mod am;
+mod archive;
 mod args;
+mod auth;
+mod backport;
+mod cancel;
 mod config;
+mod conflict;
+mod depend;
+mod focus;
+mod forge;
+mod hooks;
+mod html;
+mod jumps;
+mod label;
+mod metadata;
+mod picker;
+mod pin;
+mod prefetch;
+mod progress;
+mod recover;
+mod serve;
 mod stack;
+mod stgit;
+mod tag;
+mod template;
+mod usage;
+mod verify;
+mod workspace;
 
 fn main() {
-    human_panic::setup_panic!();
+    #[allow(deprecated)]
+    {
+        human_panic::setup_panic!();
+    }
     let result = run();
     proc_exit::exit(result);
 }
 
 fn run() -> proc_exit::ExitResult {
     // clap's `get_matches` uses Failure rather than Usage, so bypass it for `get_matches_safe`.
-    let args = match args::Args::try_parse() {
+    let matches = args::Args::command().try_get_matches();
+    let subcommand = matches
+        .as_ref()
+        .ok()
+        .and_then(|m| m.subcommand_name())
+        .map(|s| s.to_owned());
+    let mut args = match matches.and_then(|m| args::Args::from_arg_matches(&m)) {
         Ok(args) => args,
         Err(e) if e.use_stderr() => {
             let _ = e.print();
@@ -28,20 +65,201 @@ fn run() -> proc_exit::ExitResult {
         }
     };
 
+    if args.check {
+        args.dry_run = true;
+    }
+
     args.color.apply();
     let colored_stdout = concolor::get(concolor::Stream::Stdout).ansi_color();
     let colored_stderr = concolor::get(concolor::Stream::Stderr).ansi_color();
 
-    git_stack::log::init_logging(args.verbose.clone(), colored_stderr);
+    git_stack::log::init_logging(
+        args.verbose.clone(),
+        colored_stderr,
+        &args.log_scope,
+        args.log_file.as_deref(),
+    )
+    .with_code(proc_exit::Code::USAGE_ERR)?;
 
-    if let Some(output_path) = args.dump_config.as_deref() {
-        config::dump_config(&args, output_path)?;
+    let non_interactive = args.non_interactive();
+    if non_interactive {
+        // Anything we spawn inherits these, so nothing can wait on the user
+        std::env::set_var("GIT_TERMINAL_PROMPT", "0");
+        std::env::set_var("GIT_EDITOR", "true");
+    }
+
+    let start = std::time::Instant::now();
+    let result = dispatch(&args, colored_stdout, colored_stderr).map_err(explain_error);
+    usage::record_run(
+        &args,
+        &usage::operation_name(&args, subcommand.as_deref()),
+        start.elapsed(),
+        result.is_ok(),
+    );
+    if non_interactive {
+        // Reported as the last event, rather than alongside it
+        return result.map_err(|err| {
+            let message = err.to_string();
+            if message.is_empty() {
+                err
+            } else {
+                let event =
+                    progress::Progress::event("error", serde_json::json!({ "message": message }));
+                err.with_message(event)
+            }
+        });
+    }
+    result
+}
+
+/// Replace errors the user can't act on with what is actually going on
+fn explain_error(err: proc_exit::Exit) -> proc_exit::Exit {
+    // libgit2 refuses to open repositories using extensions it doesn't know
+    let message = err.to_string();
+    if message.contains("extensions.objectformat") {
+        return proc_exit::Code::USAGE_ERR
+            .with_message("SHA-256 repositories aren't supported yet, as libgit2 can't read them");
+    }
+    if message.contains("extensions.refstorage") {
+        return proc_exit::Code::USAGE_ERR.with_message(
+            "reftable repositories aren't supported yet, as libgit2 can't read them; convert with `git refs migrate --ref-format=files`",
+        );
+    }
+    err
+}
+
+fn dispatch(
+    args: &args::Args,
+    colored_stdout: bool,
+    colored_stderr: bool,
+) -> proc_exit::ExitResult {
+    if let Some(workspace) = args.recurse.as_deref() {
+        workspace::recurse(args, workspace)?;
+    } else if let Some(subcommand) = args.subcommand.as_ref() {
+        match subcommand {
+            args::Subcommand::Fixups(fixups_args) => {
+                stack::fixups(args, fixups_args, colored_stdout, colored_stderr)?;
+            }
+            args::Subcommand::Bot(bot_args) => {
+                stack::bot(args, bot_args)?;
+            }
+            args::Subcommand::Prefetch(prefetch_args) => {
+                prefetch::prefetch(args, prefetch_args)?;
+            }
+            args::Subcommand::Hooks(hooks_args) => {
+                hooks::hooks(args, hooks_args)?;
+            }
+            args::Subcommand::Recover(recover_args) => {
+                recover::recover(args, recover_args)?;
+            }
+            args::Subcommand::Tag(tag_args) => {
+                tag::tag(args, tag_args)?;
+            }
+            args::Subcommand::Archive(archive_args) => {
+                archive::archive(args, archive_args)?;
+            }
+            args::Subcommand::Unarchive(archive_args) => {
+                archive::unarchive(args, archive_args)?;
+            }
+            args::Subcommand::Pin(pin_args) => {
+                pin::pin(args, pin_args)?;
+            }
+            args::Subcommand::Focus(focus_args) => {
+                focus::focus(args, focus_args)?;
+            }
+            args::Subcommand::Label(label_args) => {
+                label::label(args, label_args)?;
+            }
+            args::Subcommand::Depend(depend_args) => {
+                depend::depend(args, depend_args)?;
+            }
+            args::Subcommand::Auth(auth_args) => {
+                auth::auth(args, auth_args)?;
+            }
+            args::Subcommand::RepairMetadata => {
+                metadata::repair(args)?;
+            }
+            args::Subcommand::Verify(verify_args) => {
+                verify::verify(args, verify_args)?;
+            }
+            args::Subcommand::Export(export_args) if export_args.html => {
+                html::export(args, export_args)?;
+            }
+            args::Subcommand::Export(export_args) => {
+                stgit::export(args, export_args)?;
+            }
+            args::Subcommand::Import(import_args) => {
+                stgit::import(args, import_args)?;
+            }
+            args::Subcommand::Am(am_args) => {
+                am::am(args, am_args)?;
+            }
+            args::Subcommand::Backport(backport_args) => {
+                backport::backport(args, backport_args)?;
+            }
+            args::Subcommand::New(new_args) => {
+                template::new(args, new_args)?;
+            }
+            args::Subcommand::Adopt(adopt_args) => {
+                adopt::adopt(args, adopt_args)?;
+            }
+            args::Subcommand::Back(back_args) => {
+                jumps::back(args, back_args)?;
+            }
+            args::Subcommand::Switch(switch_args) => {
+                stack::switch(args, switch_args)?;
+            }
+            args::Subcommand::Diff(diff_args) => {
+                stack::diff(args, diff_args, colored_stdout)?;
+            }
+            args::Subcommand::RangeDiff(range_diff_args) => {
+                stack::range_diff(args, range_diff_args, colored_stdout)?;
+            }
+            args::Subcommand::Submit(submit_args) => {
+                stack::submit(args, submit_args)?;
+            }
+            args::Subcommand::Land(land_args) => {
+                stack::land(args, land_args, colored_stdout, colored_stderr)?;
+            }
+            args::Subcommand::Issues => {
+                stack::issues(args, colored_stdout)?;
+            }
+            args::Subcommand::Changelog(changelog_args) => {
+                stack::changelog(args, changelog_args)?;
+            }
+            args::Subcommand::Stats(stats_args) if stats_args.usage => {
+                usage::show(args)?;
+            }
+            args::Subcommand::Stats(_) => {
+                stack::stats(args, colored_stdout)?;
+            }
+            args::Subcommand::Overview => {
+                stack::overview(args, colored_stdout)?;
+            }
+            args::Subcommand::Prompt => {
+                stack::prompt(args)?;
+            }
+            args::Subcommand::Cleanup(cleanup_args) => {
+                stack::cleanup(args, cleanup_args)?;
+            }
+            args::Subcommand::Serve(serve_args) => {
+                serve::serve(args, serve_args)?;
+            }
+            args::Subcommand::Config(config_args) => {
+                config::config(args, config_args)?;
+            }
+            args::Subcommand::Perf(perf_args) => {
+                stack::perf(args, perf_args)?;
+            }
+        }
+    } else if let Some(output_path) = args.dump_config.as_deref() {
+        config::dump_config(args, output_path)?;
     } else if let Some(ignore) = args.protect.as_deref() {
-        config::protect(&args, ignore)?;
+        config::protect(ignore)?;
     } else if args.protected {
-        config::protected(&args)?;
+        config::protected(args)?;
     } else {
-        stack::stack(&args, colored_stdout, colored_stderr)?;
+        stack::stack(args, colored_stdout, colored_stderr)?;
     }
 
     Ok(())
@@ -0,0 +1,260 @@
+use proc_exit::WithCodeResultExt;
+
+/// Warn about branch metadata that is being ignored, pointing to `git stack repair-metadata`
+pub(crate) fn warn_problems(repo: &git2::Repository) {
+    let problems = problems(repo);
+    if !problems.is_empty() {
+        log::warn!(
+            "Branch metadata is inconsistent, run `git stack repair-metadata`: {}",
+            problems
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
+
+/// Re-infer the parent of branches whose tracked branch is deleted or leads back to themselves,
+/// and drop broken dependencies
+pub fn repair(args: &crate::args::Args) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let ignored =
+        git_stack::git::BranchGlobs::new(repo_config.ignore_branches().iter().map(|s| s.as_str()))
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
+    repair_metadata(&repo, &ignored, args.dry_run).with_code(proc_exit::Code::FAILURE)
+}
+
+/// Problems with branch metadata, as reported by [`warn_problems`]
+pub(crate) fn problems(repo: &git2::Repository) -> Vec<git_stack::graph::EdgeProblem> {
+    let exists = |name: &str| repo.find_branch(name, git2::BranchType::Local).is_ok();
+    git_stack::graph::check_edges(&parent_edges(repo), exists)
+        .into_iter()
+        .chain(git_stack::graph::check_edges(
+            &crate::depend::branch_dependencies(repo),
+            exists,
+        ))
+        .collect()
+}
+
+pub(crate) fn repair_metadata(
+    repo: &git2::Repository,
+    ignored: &git_stack::git::BranchGlobs,
+    dry_run: bool,
+) -> Result<(), git2::Error> {
+    let exists = |name: &str| repo.find_branch(name, git2::BranchType::Local).is_ok();
+
+    let parents = parent_edges(repo);
+    let mut reparent = Vec::new();
+    for problem in git_stack::graph::check_edges(&parents, exists) {
+        log::debug!("{}", problem);
+        match problem {
+            git_stack::graph::EdgeProblem::Missing { branch, .. } => reparent.push(branch),
+            git_stack::graph::EdgeProblem::Cycle(branches) => reparent.extend(branches),
+        }
+    }
+    reparent.sort();
+    reparent.dedup();
+
+    let dependencies = crate::depend::branch_dependencies(repo);
+    let mut undepend = Vec::new();
+    for problem in git_stack::graph::check_edges(&dependencies, exists) {
+        log::debug!("{}", problem);
+        match problem {
+            git_stack::graph::EdgeProblem::Missing { branch, parent } => {
+                undepend.push((branch, parent))
+            }
+            git_stack::graph::EdgeProblem::Cycle(branches) => {
+                let next = branches.iter().cycle().skip(1);
+                undepend.extend(
+                    branches
+                        .iter()
+                        .cloned()
+                        .zip(next.cloned())
+                        .filter(|edge| dependencies.contains(edge)),
+                );
+            }
+        }
+    }
+
+    if reparent.is_empty() && undepend.is_empty() {
+        log::info!("Branch metadata is consistent, nothing to repair");
+        return Ok(());
+    }
+
+    let mut config = crate::label::local_config(repo)?;
+    for branch in reparent.iter() {
+        let old = parents
+            .iter()
+            .find(|(b, _)| b == branch)
+            .map(|(_, p)| p.as_str())
+            .unwrap_or_default();
+        let remote_key = format!("branch.{}.remote", branch);
+        let merge_key = format!("branch.{}.merge", branch);
+        match infer_parent(repo, ignored, branch) {
+            Some(parent) if parent == old => {
+                log::info!("{} tracks {}, as inferred", branch, parent);
+            }
+            Some(parent) => {
+                log::trace!("git config {} .", remote_key);
+                log::trace!("git config {} refs/heads/{}", merge_key, parent);
+                if !dry_run {
+                    config.set_str(&remote_key, ".")?;
+                    config.set_str(&merge_key, &format!("refs/heads/{}", parent))?;
+                }
+                log::info!("{} now tracks {} (was {})", branch, parent, old);
+            }
+            None => {
+                log::trace!("git config --unset {}", remote_key);
+                log::trace!("git config --unset {}", merge_key);
+                if !dry_run {
+                    config.remove(&remote_key)?;
+                    config.remove(&merge_key)?;
+                }
+                log::info!("{} no longer tracks {}", branch, old);
+            }
+        }
+    }
+    for (branch, on) in undepend.iter() {
+        let key = crate::depend::depends_key(branch);
+        log::trace!("git config --unset {} {}", key, on);
+        if !dry_run {
+            config.remove_multivar(&key, &format!("^{}$", regex::escape(on)))?;
+        }
+        log::info!("{} no longer depends on {}", branch, on);
+    }
+
+    Ok(())
+}
+
+/// Whether `branch` was last moved by `git rebase`, whether as the branch being rebased or as one
+/// of the branches `--update-refs` carried along
+pub(crate) fn moved_by_rebase(repo: &git2::Repository, branch: &str) -> bool {
+    let reflog = match repo.reflog(&format!("refs/heads/{}", branch)) {
+        Ok(reflog) => reflog,
+        Err(err) => {
+            log::debug!("Could not read reflog of {}: {}", branch, err);
+            return false;
+        }
+    };
+    let message = reflog
+        .get(0)
+        .and_then(|entry| entry.message().map(|m| m.to_owned()))
+        .unwrap_or_default();
+    message == "rewritten during rebase" || message.starts_with("rebase (finish): ")
+}
+
+/// Have `branch` track the local branch `parent`, like `git branch --set-upstream-to`
+pub(crate) fn set_parent(
+    repo: &git2::Repository,
+    branch: &str,
+    parent: &str,
+) -> Result<(), git2::Error> {
+    let mut config = crate::label::local_config(repo)?;
+    config.set_str(&format!("branch.{}.remote", branch), ".")?;
+    config.set_str(
+        &format!("branch.{}.merge", branch),
+        &format!("refs/heads/{}", parent),
+    )?;
+    Ok(())
+}
+
+/// `(branch, parent)` for local branches tracking another local branch
+fn parent_edges(repo: &git2::Repository) -> Vec<(String, String)> {
+    let mut edges = Vec::new();
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(err) => {
+            log::debug!("Could not read branch config: {}", err);
+            return edges;
+        }
+    };
+    let entries = match config.entries(Some(r"^branch\..*\.merge$")) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Could not read branch config: {}", err);
+            return edges;
+        }
+    };
+    for entry in &entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                log::debug!("Could not read branch config: {}", err);
+                continue;
+            }
+        };
+        let branch = entry
+            .name()
+            .and_then(|n| n.strip_prefix("branch."))
+            .and_then(|n| n.strip_suffix(".merge"));
+        let parent = entry
+            .value()
+            .map(|v| v.strip_prefix("refs/heads/").unwrap_or(v));
+        let (branch, parent) = match (branch, parent) {
+            (Some(branch), Some(parent)) => (branch, parent),
+            _ => continue,
+        };
+        // Same as `GitRepo::upstream_branch`, a missing remote means a local branch
+        let remote = config
+            .get_string(&format!("branch.{}.remote", branch))
+            .unwrap_or_else(|_| ".".to_owned());
+        if remote == "." && repo.find_branch(branch, git2::BranchType::Local).is_ok() {
+            edges.push((branch.to_owned(), parent.to_owned()));
+        }
+    }
+    edges
+}
+
+/// The closest local branch `branch` is descended from, like when there is no metadata
+///
+/// Branches on the same commit are skipped as there is no telling which is the parent, as are
+/// `ignored` branches.
+fn infer_parent(
+    repo: &git2::Repository,
+    ignored: &git_stack::git::BranchGlobs,
+    branch: &str,
+) -> Option<String> {
+    let id = repo
+        .find_branch(branch, git2::BranchType::Local)
+        .ok()?
+        .get()
+        .target()?;
+    let mut candidates: Vec<(String, git2::Oid)> = repo
+        // Unlike `branches()`, only the loose refs under `refs/heads/` are walked, though
+        // `packed-refs` is still read in full
+        .references_glob("refs/heads/*")
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|r| {
+            let name = r.name()?.strip_prefix("refs/heads/")?.to_owned();
+            let candidate_id = r.target()?;
+            Some((name, candidate_id))
+        })
+        .filter(|(name, _)| !ignored.is_match(name))
+        .filter(|(_, candidate_id)| {
+            *candidate_id != id && repo.graph_descendant_of(id, *candidate_id).unwrap_or(false)
+        })
+        .collect();
+    // Be reproducible among branches on the same commit
+    candidates.sort();
+
+    let mut closest: Option<(String, git2::Oid)> = None;
+    for (name, candidate_id) in candidates {
+        let is_closer = match closest.as_ref() {
+            Some((_, closest_id)) => repo
+                .graph_descendant_of(candidate_id, *closest_id)
+                .unwrap_or(false),
+            None => true,
+        };
+        if is_closer {
+            closest = Some((name, candidate_id));
+        }
+    }
+    closest.map(|(name, _)| name)
+}
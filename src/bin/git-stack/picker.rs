@@ -0,0 +1,266 @@
+//! A fuzzy finder over branches, in the style of `skim`/`fzf`
+
+use std::io::Write;
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::{cursor, style, terminal, QueueableCommand};
+
+/// Something to pick, with details shown beside it
+pub struct Candidate {
+    pub name: String,
+    pub context: String,
+}
+
+/// Candidates matching `query`, best first and otherwise in order, with the positions of `name`
+/// that matched
+///
+/// `name` is preferred, falling back to matching `context` too.  Like `fzf`'s smart case, the
+/// match only cares about case when the query has uppercase letters.
+pub fn filter<'c>(candidates: &'c [Candidate], query: &str) -> Vec<(&'c Candidate, Vec<usize>)> {
+    let case_sensitive = query.chars().any(|c| c.is_uppercase());
+    let mut matches: Vec<_> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            if let Some((score, positions)) = fuzzy_match(&candidate.name, query, case_sensitive) {
+                return Some((score, candidate, positions));
+            }
+            let line = format!("{} {}", candidate.name, candidate.context);
+            // Lose to any match on the name alone
+            fuzzy_match(&line, query, case_sensitive)
+                .map(|(score, _)| (score - 1000, candidate, Vec::new()))
+        })
+        .collect();
+    matches.sort_by_key(|(score, _, _)| std::cmp::Reverse(*score));
+    matches
+        .into_iter()
+        .map(|(_, candidate, positions)| (candidate, positions))
+        .collect()
+}
+
+/// Score `query` as a subsequence of `text`, favoring runs and word starts
+fn fuzzy_match(text: &str, query: &str, case_sensitive: bool) -> Option<(i64, Vec<usize>)> {
+    let normalize = |c: char| {
+        if case_sensitive {
+            c
+        } else {
+            c.to_ascii_lowercase()
+        }
+    };
+    let text: Vec<char> = text.chars().map(normalize).collect();
+    let query: Vec<char> = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(normalize)
+        .collect();
+    // Jumping to word starts usually wins (`flt` for `feature-login-tests`) but can miss matches
+    // that taking the leftmost character finds
+    [true, false]
+        .iter()
+        .filter_map(|prefer_word_starts| match_positions(&text, &query, *prefer_word_starts))
+        .map(|positions| (score(&text, &positions), positions))
+        .max_by_key(|(score, _)| *score)
+}
+
+fn match_positions(text: &[char], query: &[char], prefer_word_starts: bool) -> Option<Vec<usize>> {
+    let mut positions = Vec::with_capacity(query.len());
+    let mut start = 0;
+    for q in query {
+        let mut candidates = (start..text.len()).filter(|i| text[*i] == *q);
+        let index = if prefer_word_starts {
+            let leftmost = candidates.clone().next();
+            candidates.find(|i| is_word_start(text, *i)).or(leftmost)?
+        } else {
+            candidates.next()?
+        };
+        positions.push(index);
+        start = index + 1;
+    }
+    Some(positions)
+}
+
+fn score(text: &[char], positions: &[usize]) -> i64 {
+    let mut score = 0;
+    let mut previous = None;
+    for index in positions.iter().copied() {
+        score += 1;
+        if is_word_start(text, index) {
+            score += 8;
+        }
+        match previous {
+            Some(previous) if previous + 1 == index => score += 4,
+            Some(previous) => score -= (index - previous - 1) as i64,
+            None => score -= index as i64,
+        }
+        previous = Some(index);
+    }
+    score
+}
+
+fn is_word_start(text: &[char], index: usize) -> bool {
+    index == 0 || !text[index - 1].is_alphanumeric()
+}
+
+/// Let the user narrow down `candidates` on the terminal, returning their choice
+///
+/// `None` if they cancelled.
+pub fn pick<'c>(candidates: &'c [Candidate], query: &str) -> eyre::Result<Option<&'c Candidate>> {
+    let _guard = Terminal::enter()?;
+    let mut stderr = std::io::stderr();
+    let mut query = query.to_owned();
+    let mut selected = 0;
+    loop {
+        let matches = filter(candidates, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        render(&mut stderr, &query, &matches, candidates.len(), selected)?;
+
+        let key = match crossterm::event::read()? {
+            Event::Key(key) => key,
+            _ => continue,
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key {
+            KeyEvent {
+                code: KeyCode::Enter,
+                ..
+            } => {
+                return Ok(matches.get(selected).map(|(c, _)| *c));
+            }
+            KeyEvent {
+                code: KeyCode::Esc, ..
+            } => return Ok(None),
+            KeyEvent {
+                code: KeyCode::Char('c') | KeyCode::Char('g'),
+                ..
+            } if ctrl => return Ok(None),
+            KeyEvent {
+                code: KeyCode::Up, ..
+            } => selected = selected.saturating_sub(1),
+            KeyEvent {
+                code: KeyCode::Char('p') | KeyCode::Char('k'),
+                ..
+            } if ctrl => selected = selected.saturating_sub(1),
+            KeyEvent {
+                code: KeyCode::Down,
+                ..
+            } => selected += 1,
+            KeyEvent {
+                code: KeyCode::Char('n') | KeyCode::Char('j'),
+                ..
+            } if ctrl => selected += 1,
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                ..
+            } if ctrl => {
+                query.clear();
+                selected = 0;
+            }
+            KeyEvent {
+                code: KeyCode::Backspace,
+                ..
+            } => {
+                query.pop();
+                selected = 0;
+            }
+            KeyEvent {
+                code: KeyCode::Char(c),
+                ..
+            } if !ctrl => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn render(
+    stderr: &mut std::io::Stderr,
+    query: &str,
+    matches: &[(&Candidate, Vec<usize>)],
+    total: usize,
+    selected: usize,
+) -> eyre::Result<()> {
+    let (width, height) = match terminal::size()? {
+        // Some terminals don't say
+        (0, _) | (_, 0) => (80, 24),
+        size => size,
+    };
+    let width = usize::from(width);
+    // Below the query and count, like `fzf --reverse`
+    const HEADER: usize = 2;
+    let rows = usize::from(height).saturating_sub(HEADER);
+    // Keep the selection in view
+    let first = (selected + 1).saturating_sub(rows);
+    let name_width = matches
+        .iter()
+        .map(|(c, _)| c.name.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    stderr
+        .queue(terminal::Clear(terminal::ClearType::All))?
+        .queue(cursor::MoveTo(0, 1))?
+        .queue(style::SetAttribute(style::Attribute::Dim))?
+        .queue(style::Print(format!("  {}/{}", matches.len(), total)))?
+        .queue(style::SetAttribute(style::Attribute::Reset))?;
+    for (row, (candidate, positions)) in matches.iter().skip(first).take(rows).enumerate() {
+        let index = first + row;
+        stderr.queue(cursor::MoveTo(0, (HEADER + row) as u16))?;
+        if index == selected {
+            stderr
+                .queue(style::SetAttribute(style::Attribute::Reverse))?
+                .queue(style::Print("> "))?;
+        } else {
+            stderr.queue(style::Print("  "))?;
+        }
+        let mut used = 2;
+        for (i, c) in candidate.name.chars().enumerate() {
+            if width <= used {
+                break;
+            }
+            if positions.contains(&i) {
+                stderr
+                    .queue(style::SetAttribute(style::Attribute::Bold))?
+                    .queue(style::Print(c))?
+                    .queue(style::SetAttribute(style::Attribute::NormalIntensity))?;
+            } else {
+                stderr.queue(style::Print(c))?;
+            }
+            used += 1;
+        }
+        let padding = name_width - candidate.name.chars().count() + 2;
+        let context: String = std::iter::repeat(' ')
+            .take(padding)
+            .chain(candidate.context.chars())
+            .take(width.saturating_sub(used))
+            .collect();
+        stderr
+            .queue(style::SetAttribute(style::Attribute::Dim))?
+            .queue(style::Print(context))?
+            .queue(style::SetAttribute(style::Attribute::Reset))?;
+    }
+    stderr
+        .queue(cursor::MoveTo(0, 0))?
+        .queue(style::Print(format!("> {}", query)))?;
+    stderr.flush()?;
+    Ok(())
+}
+
+/// Restores the terminal, even if picking fails
+struct Terminal;
+
+impl Terminal {
+    fn enter() -> eyre::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let guard = Self;
+        crossterm::execute!(std::io::stderr(), terminal::EnterAlternateScreen)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(std::io::stderr(), terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
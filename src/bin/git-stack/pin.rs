@@ -0,0 +1,119 @@
+use proc_exit::WithCodeResultExt;
+
+/// Notes marking commits that planning must leave alone
+const PINS_REF: &str = "refs/notes/stack-pins";
+
+pub fn pin(args: &crate::args::Args, pin_args: &crate::args::PinArgs) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let rev = match pin_args.commit.as_deref() {
+        Some(rev) => rev,
+        None => {
+            use std::io::Write;
+            let mut stdout = std::io::stdout();
+            for id in pinned_commits(&repo) {
+                let summary = repo
+                    .find_commit(id)
+                    .map(|c| {
+                        String::from_utf8_lossy(c.summary_bytes().unwrap_or_default()).into_owned()
+                    })
+                    .unwrap_or_default();
+                writeln!(stdout, "{} {}", &id.to_string()[..7], summary)?;
+            }
+            return Ok(());
+        }
+    };
+    let commit = repo
+        .revparse_single(rev)
+        .and_then(|o| o.peel_to_commit())
+        .with_code(proc_exit::Code::USAGE_ERR)?;
+    let id = commit.id();
+    let pinned = repo.find_note(Some(PINS_REF), id).is_ok();
+
+    if pin_args.remove {
+        if !pinned {
+            return Err(proc_exit::Code::USAGE_ERR.with_message(format!("`{}` is not pinned", rev)));
+        }
+        log::trace!("git notes --ref {} remove {}", PINS_REF, id);
+        if !args.dry_run {
+            let signature = repo.signature().with_code(proc_exit::Code::CONFIG_ERR)?;
+            repo.note_delete(id, Some(PINS_REF), &signature, &signature)
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+        log::info!("Unpinned {}", &id.to_string()[..7]);
+    } else {
+        if pinned {
+            log::info!("{} is already pinned", &id.to_string()[..7]);
+            return Ok(());
+        }
+        log::trace!("git notes --ref {} add {}", PINS_REF, id);
+        if !args.dry_run {
+            let signature = repo.signature().with_code(proc_exit::Code::CONFIG_ERR)?;
+            repo.note(
+                &signature,
+                &signature,
+                Some(PINS_REF),
+                id,
+                "pinned\n",
+                false,
+            )
+            .with_code(proc_exit::Code::FAILURE)?;
+        }
+        log::info!("Pinned {}", &id.to_string()[..7]);
+    }
+
+    Ok(())
+}
+
+/// Commits pinned with `git stack pin`
+pub(crate) fn pinned_commits(repo: &git2::Repository) -> Vec<git2::Oid> {
+    let notes = match repo.notes(Some(PINS_REF)) {
+        Ok(notes) => notes,
+        // No pins yet
+        Err(err) if err.code() == git2::ErrorCode::NotFound => return Vec::new(),
+        Err(err) => {
+            log::debug!("Could not read pinned commits: {}", err);
+            return Vec::new();
+        }
+    };
+    notes
+        .filter_map(|note| note.ok())
+        .map(|(_, annotated_id)| annotated_id)
+        .collect()
+}
+
+/// Pin what pinned commits were rewritten to, since notes stay with the old commits
+pub(crate) fn carry_pins(repo: &git2::Repository, rewrites: &[(git2::Oid, git2::Oid)]) {
+    let signature = match repo.signature() {
+        Ok(signature) => signature,
+        Err(err) => {
+            log::debug!("Could not carry over pins: {}", err);
+            return;
+        }
+    };
+    for (old_id, new_id) in rewrites {
+        let note = match repo.find_note(Some(PINS_REF), *old_id) {
+            Ok(note) => note,
+            Err(_) => continue,
+        };
+        log::trace!("git notes --ref {} copy {} {}", PINS_REF, old_id, new_id);
+        let message = note.message().unwrap_or("pinned\n");
+        if let Err(err) = repo.note(
+            &signature,
+            &signature,
+            Some(PINS_REF),
+            *new_id,
+            message,
+            true,
+        ) {
+            log::warn!(
+                "Could not pin {}, run `git stack pin {}`: {}",
+                new_id,
+                new_id,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use eyre::WrapErr;
+use git_stack::remote_log;
+use proc_exit::WithCodeResultExt;
+
+pub fn prefetch(
+    args: &crate::args::Args,
+    prefetch_args: &crate::args::PrefetchArgs,
+) -> proc_exit::ExitResult {
+    remote_log!(trace, "Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    crate::stack::require_online(&repo_config, "`git stack prefetch`")?;
+
+    let mut remotes = vec![repo_config.pull_remote(), repo_config.push_remote()];
+    remotes.dedup();
+
+    let mut success = true;
+    for remote in remotes {
+        if let Err(err) = git_fetch(remote, args.dry_run) {
+            remote_log!(warn, "Skipping fetch of `{}`, {}", remote, err);
+            success = false;
+        }
+    }
+
+    crate::forge::refresh(&repo, &repo_config, args.dry_run);
+
+    if prefetch_args.install {
+        git_maintenance_start(args.dry_run).with_code(proc_exit::Code::FAILURE)?;
+    }
+
+    if !success {
+        return proc_exit::Code::FAILURE.ok();
+    }
+
+    Ok(())
+}
+
+/// Only updates remote-tracking branches, leaving local branches and `FETCH_HEAD` alone
+fn git_fetch(remote: &str, dry_run: bool) -> eyre::Result<()> {
+    remote_log!(debug, "git fetch --quiet --no-write-fetch-head {}", remote);
+    if dry_run {
+        return Ok(());
+    }
+    let status = std::process::Command::new("git")
+        .arg("fetch")
+        .arg("--quiet")
+        .arg("--no-write-fetch-head")
+        .arg(remote)
+        .status()
+        .wrap_err("Could not run `git fetch`")?;
+    if !status.success() {
+        eyre::bail!("`git fetch {}` failed", remote);
+    }
+
+    Ok(())
+}
+
+/// `git maintenance`s `prefetch` task keeps objects local, so our own fetches are quick
+fn git_maintenance_start(dry_run: bool) -> eyre::Result<()> {
+    remote_log!(info, "git maintenance start");
+    if dry_run {
+        return Ok(());
+    }
+    let status = std::process::Command::new("git")
+        .arg("maintenance")
+        .arg("start")
+        .status()
+        .wrap_err("Could not run `git maintenance`")?;
+    if !status.success() {
+        eyre::bail!("`git maintenance start` failed");
+    }
+
+    Ok(())
+}
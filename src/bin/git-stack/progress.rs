@@ -0,0 +1,82 @@
+use std::io::Write;
+
+/// Machine-readable progress for `--non-interactive`
+///
+/// Each event is a JSON object on its own line of stderr.
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    pub fn emit(&self, event: &str, fields: serde_json::Value) {
+        if !self.enabled {
+            return;
+        }
+
+        // We might be exiting due to a broken pipe, just do our best and move on.
+        let _ = writeln!(std::io::stderr(), "{}", Self::event(event, fields));
+    }
+
+    /// The line [`Progress::emit`] writes
+    pub fn event(event: &str, fields: serde_json::Value) -> serde_json::Value {
+        let mut object = match fields {
+            serde_json::Value::Object(object) => object,
+            _ => serde_json::Map::new(),
+        };
+        object.insert("event".to_owned(), event.into());
+        serde_json::Value::Object(object)
+    }
+}
+
+/// Plain `git` equivalents of what we are doing, for `--show-git-commands`
+#[derive(Copy, Clone, Debug)]
+pub struct GitCommands {
+    format: Option<GitCommandsFormat>,
+}
+
+impl GitCommands {
+    pub fn new(format: Option<GitCommandsFormat>) -> Self {
+        Self { format }
+    }
+
+    pub fn show(&self, command: &str) {
+        if self.format == Some(GitCommandsFormat::Shell) {
+            let _ = writeln!(std::io::stderr(), "{}", command);
+        }
+    }
+
+    /// Restacking, as a todo list to hand to `git rebase -i --update-refs`
+    pub fn show_rebase_todo(&self, todo: &[String]) {
+        if self.format == Some(GitCommandsFormat::RebaseTodo) && !todo.is_empty() {
+            let _ = writeln!(std::io::stderr(), "{}", todo.join("\n"));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GitCommandsFormat {
+    Shell,
+    RebaseTodo,
+}
+
+impl GitCommandsFormat {
+    pub fn variants() -> [&'static str; 2] {
+        ["shell", "rebase-todo"]
+    }
+}
+
+impl std::str::FromStr for GitCommandsFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shell" => Ok(GitCommandsFormat::Shell),
+            "rebase-todo" => Ok(GitCommandsFormat::RebaseTodo),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
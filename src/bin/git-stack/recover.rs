@@ -0,0 +1,78 @@
+use itertools::Itertools;
+use proc_exit::WithCodeResultExt;
+
+pub fn recover(
+    args: &crate::args::Args,
+    recover_args: &crate::args::RecoverArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let path = git_stack::git::Journal::path(&repo);
+    if !path.exists() {
+        log::info!("Nothing to recover");
+        return Ok(());
+    }
+    let journal = git_stack::git::Journal::load(&path).map_err(|err| {
+        proc_exit::Code::FAILURE.with_message(format!(
+            "Could not read {}: {}\nTo restore the branches from before the rewrite, run `git branch-stash pop {}`",
+            path.display(),
+            err,
+            crate::stack::STASH_STACK_NAME
+        ))
+    })?;
+    let names = journal.updates.iter().map(|u| u.name.as_str()).join(", ");
+
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    for update in journal.updates.iter() {
+        let (from, to) = if recover_args.rollback {
+            (update.new, update.old)
+        } else {
+            (update.old, update.new)
+        };
+        match to {
+            Some(id) => log::trace!("git update-ref refs/heads/{} {}", update.name, id),
+            None => log::trace!("git branch -D {}", update.name),
+        }
+        log::debug!(
+            "{}: {} -> {}",
+            update.name,
+            from.map(|id| id.to_string()).unwrap_or_default(),
+            to.map(|id| id.to_string()).unwrap_or_default()
+        );
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let res = if recover_args.rollback {
+        journal.rollback(&mut repo)
+    } else {
+        journal.complete(&mut repo)
+    };
+    if let Err(err) = res {
+        let mut message = format!("Could not recover: {}", err.message());
+        if journal
+            .snapshot
+            .as_deref()
+            .map(|p| p.exists())
+            .unwrap_or(false)
+        {
+            message.push_str(&format!(
+                "\nTo restore the branches from before the rewrite, run `git branch-stash pop {}`",
+                crate::stack::STASH_STACK_NAME
+            ));
+        }
+        return Err(proc_exit::Code::FAILURE.with_message(message));
+    }
+    git_stack::git::Journal::remove(&path).with_code(proc_exit::Code::FAILURE)?;
+
+    if recover_args.rollback {
+        log::info!("Rolled back {}", names);
+    } else {
+        log::info!("Finished updating {}", names);
+    }
+
+    Ok(())
+}
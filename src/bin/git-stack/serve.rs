@@ -0,0 +1,405 @@
+use std::io::BufRead;
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+const METHODS: &[&str] = &[
+    "initialize",
+    "shutdown",
+    "exit",
+    "stack/show",
+    "stack/plan",
+    "stack/rebase",
+    "stack/pull",
+    "stack/fixup",
+    "stack/push",
+];
+
+/// Answer JSON-RPC requests framed like the Language Server Protocol
+pub fn serve(
+    args: &crate::args::Args,
+    serve_args: &crate::args::ServeArgs,
+) -> proc_exit::ExitResult {
+    if !serve_args.stdio {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Only `--stdio` is supported"));
+    }
+
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut output = stdout.lock();
+    while let Some(message) = read_message(&mut input).with_code(proc_exit::Code::IO_ERR)? {
+        match dispatch(args, &message) {
+            Dispatch::Respond(response) => {
+                write_message(&mut output, &response).with_code(proc_exit::Code::IO_ERR)?;
+            }
+            Dispatch::Quiet => {}
+            Dispatch::Exit => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum Dispatch {
+    Respond(serde_json::Value),
+    /// Notifications don't get a response
+    Quiet,
+    Exit,
+}
+
+fn dispatch(args: &crate::args::Args, message: &[u8]) -> Dispatch {
+    let request: serde_json::Value = match serde_json::from_slice(message) {
+        Ok(request) => request,
+        Err(err) => {
+            return Dispatch::Respond(error_response(serde_json::Value::Null, PARSE_ERROR, err));
+        }
+    };
+    let method = request
+        .get("method")
+        .and_then(|m| m.as_str())
+        .unwrap_or_default();
+    log::trace!("Received `{}`", method);
+    if method == "exit" {
+        return Dispatch::Exit;
+    }
+    let params = request
+        .get("params")
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    let result = handle(args, method, &params);
+
+    match request.get("id").cloned() {
+        Some(id) => Dispatch::Respond(match result {
+            Ok(result) => serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": result,
+            }),
+            Err((code, message)) => error_response(id, code, message),
+        }),
+        None => Dispatch::Quiet,
+    }
+}
+
+fn handle(
+    args: &crate::args::Args,
+    method: &str,
+    params: &serde_json::Value,
+) -> Result<serde_json::Value, (i64, String)> {
+    match method {
+        "initialize" => Ok(serde_json::json!({
+            "serverInfo": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "methods": METHODS,
+        })),
+        "shutdown" => Ok(serde_json::Value::Null),
+        "stack/show" => crate::stack::query_stacks(args).map_err(internal_error),
+        "stack/plan" => {
+            let rebase = params
+                .get("rebase")
+                .and_then(|r| r.as_bool())
+                .unwrap_or(true);
+            let fixup = match params.get("fixup").and_then(|f| f.as_str()) {
+                Some(fixup) => Some(
+                    fixup
+                        .parse::<git_stack::config::Fixup>()
+                        .map_err(|err| (INVALID_PARAMS, err.to_string()))?,
+                ),
+                None => None,
+            };
+            crate::stack::query_plan(args, rebase, fixup).map_err(internal_error)
+        }
+        "stack/rebase" => run_operation(args, &["--rebase"], params),
+        "stack/pull" => run_operation(args, &["--pull"], params),
+        "stack/fixup" => run_operation(args, &["fixups"], params),
+        "stack/push" => run_operation(args, &["--push"], params),
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method `{}`", method))),
+    }
+}
+
+/// Operations run in a child process so their terminal output can't corrupt the protocol
+///
+/// Without a `yes` (or `confirm`) param, this is a `--dry-run` so the client can show the plan
+/// before anything is rewritten, like the prompts for `stack.confirm` and
+/// `stack.max-rewrite-commits` would.
+fn run_operation(
+    args: &crate::args::Args,
+    operation: &[&str],
+    params: &serde_json::Value,
+) -> Result<serde_json::Value, (i64, String)> {
+    let confirmed = ["yes", "confirm"]
+        .iter()
+        .any(|key| params.get(key).and_then(|v| v.as_bool()).unwrap_or(false));
+
+    let exe = std::env::current_exe().map_err(internal_error)?;
+    let mut cmd = std::process::Command::new(exe);
+    cmd.args(["--non-interactive", "--format", "silent"]);
+    if confirmed {
+        cmd.arg("--yes");
+    } else {
+        cmd.arg("--dry-run");
+    }
+    if let Some(stack) = args.stack {
+        cmd.arg("--stack").arg(stack.to_string());
+    }
+    if let Some(base) = args.base.as_deref() {
+        cmd.arg("--base").arg(base);
+    }
+    if let Some(onto) = args.onto.as_deref() {
+        cmd.arg("--onto").arg(onto);
+    }
+    match args.offline() {
+        Some(true) => {
+            cmd.arg("--offline");
+        }
+        Some(false) => {
+            cmd.arg("--no-offline");
+        }
+        None => {}
+    }
+    match args.keep_going() {
+        Some(true) => {
+            cmd.arg("--keep-going");
+        }
+        Some(false) => {
+            cmd.arg("--no-keep-going");
+        }
+        None => {}
+    }
+    cmd.args(operation);
+    log::trace!("Running {:?}", cmd);
+    let output = cmd
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(internal_error)?;
+
+    // `--non-interactive` reports progress as JSON lines, mixed in with any logging
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let (events, messages): (Vec<_>, Vec<_>) = stderr
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).map_err(|_| line))
+        .partition(|line| line.is_ok());
+    let events: Vec<_> = events.into_iter().filter_map(Result::ok).collect();
+    let plan = events
+        .iter()
+        .find(|event| event.get("event").and_then(|e| e.as_str()) == Some("plan"))
+        .cloned()
+        .unwrap_or(serde_json::Value::Null);
+    Ok(serde_json::json!({
+        "success": output.status.success(),
+        "code": output.status.code(),
+        "applied": confirmed,
+        "plan": plan,
+        "events": events,
+        "messages": messages.into_iter().filter_map(Result::err).collect::<Vec<_>>(),
+    }))
+}
+
+fn internal_error(err: impl std::fmt::Display) -> (i64, String) {
+    (INTERNAL_ERROR, err.to_string())
+}
+
+fn error_response(
+    id: serde_json::Value,
+    code: i64,
+    message: impl std::fmt::Display,
+) -> serde_json::Value {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {
+            "code": code,
+            "message": message.to_string(),
+        },
+    })
+}
+
+/// Larger `Content-Length`s are refused rather than allocated
+const MAX_MESSAGE_LEN: usize = 64 * 1024 * 1024;
+
+/// Read a `Content-Length` framed message, `None` at end of input
+fn read_message(input: &mut impl BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    let mut in_headers = false;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            if in_headers {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "input ended within message headers",
+                ));
+            }
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            if content_length.is_some() {
+                break;
+            }
+            // Tolerate blank lines between messages
+            continue;
+        }
+        in_headers = true;
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let content_length = content_length.expect("checked in loop");
+    if MAX_MESSAGE_LEN < content_length {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "message of {} bytes is over the {} byte limit",
+                content_length, MAX_MESSAGE_LEN
+            ),
+        ));
+    }
+    let mut content = vec![0; content_length];
+    input.read_exact(&mut content)?;
+    Ok(Some(content))
+}
+
+fn write_message(output: &mut impl Write, message: &serde_json::Value) -> std::io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use clap::Parser;
+
+    /// Hands out `data` a few bytes at a time, like a pipe
+    struct Trickle<'d> {
+        data: &'d [u8],
+    }
+
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.data.len()).min(3);
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    fn framed(content: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+    }
+
+    fn args() -> crate::args::Args {
+        crate::args::Args::parse_from(["git-stack", "serve", "--stdio"])
+    }
+
+    #[test]
+    fn round_trip() {
+        let message = serde_json::json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"});
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &message).unwrap();
+        write_message(&mut buffer, &message).unwrap();
+
+        let mut input = std::io::BufReader::new(Trickle { data: &buffer });
+        for _ in 0..2 {
+            let content = read_message(&mut input).unwrap().unwrap();
+            let actual: serde_json::Value = serde_json::from_slice(&content).unwrap();
+            assert_eq!(actual, message);
+        }
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn headers() {
+        let input = format!(
+            "\r\ncontent-type: application/vscode-jsonrpc\r\ncontent-length: 2\r\n\r\n{{}}{}",
+            framed("[]")
+        );
+        let mut input = std::io::BufReader::new(Trickle {
+            data: input.as_bytes(),
+        });
+        assert_eq!(read_message(&mut input).unwrap().unwrap(), b"{}");
+        assert_eq!(read_message(&mut input).unwrap().unwrap(), b"[]");
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized() {
+        let input = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+        let err = read_message(&mut input.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn eof_mid_message() {
+        let input = framed(r#"{"method": "shutdown"}"#);
+        let truncated = &input.as_bytes()[..input.len() - 1];
+        let err =
+            read_message(&mut std::io::BufReader::new(Trickle { data: truncated })).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        let err = read_message(&mut &b"Content-Length: 2\r\n"[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn dispatch_initialize() {
+        let request = br#"{"jsonrpc": "2.0", "id": 7, "method": "initialize"}"#;
+        let response = match dispatch(&args(), request) {
+            Dispatch::Respond(response) => response,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["result"]["serverInfo"]["name"], "git-stack");
+        assert_eq!(response["result"]["methods"], serde_json::json!(METHODS));
+    }
+
+    #[test]
+    fn dispatch_errors() {
+        let args = args();
+        let error_code = |request: &[u8]| match dispatch(&args, request) {
+            Dispatch::Respond(response) => response["error"]["code"].as_i64(),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(error_code(b"{"), Some(PARSE_ERROR));
+        assert_eq!(
+            error_code(br#"{"id": 1, "method": "stack/unknown"}"#),
+            Some(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error_code(br#"{"id": 1, "method": "stack/plan", "params": {"fixup": "bogus"}}"#),
+            Some(INVALID_PARAMS)
+        );
+    }
+
+    #[test]
+    fn dispatch_notifications() {
+        let args = args();
+        assert_eq!(
+            dispatch(&args, br#"{"jsonrpc": "2.0", "method": "shutdown"}"#),
+            Dispatch::Quiet
+        );
+        assert_eq!(
+            dispatch(&args, br#"{"jsonrpc": "2.0", "method": "exit"}"#),
+            Dispatch::Exit
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use git_stack::remote_log;
 use std::io::Write;
 
 use bstr::ByteSlice;
@@ -12,31 +12,90 @@ struct State {
     protected_branches: git_stack::git::Branches,
     head_commit: std::rc::Rc<git_stack::git::Commit>,
     stacks: Vec<StackState>,
+    /// `(dependent, dependency)` branches, see `git stack depend`
+    dependencies: Vec<(String, String)>,
+    /// See `git stack pin`
+    pinned: Vec<git2::Oid>,
 
     rebase: bool,
     pull: bool,
     push: bool,
     fixup: git_stack::config::Fixup,
     repair: bool,
+    fresh_base: git_stack::config::FreshBase,
+    protection_action: git_stack::config::ProtectionAction,
     dry_run: bool,
+    /// Only report whether anything is pending
+    check: bool,
+    verify: bool,
+    /// Shell command to run on each replayed commit
+    exec: Option<String>,
+    /// Shell commands to run once specific commits are replayed
+    exec_at: Vec<(git2::Oid, String)>,
+    /// Restack the remaining stacks after one fails
+    keep_going: bool,
+    /// Set by `SIGINT`, `SIGTERM`, or `--timeout`
+    cancel: git_stack::git::Cancel,
+    /// See `stack.usage-stats`
+    usage_stats: bool,
+    timeout: Option<std::time::Duration>,
+    conflict_report: Option<std::path::PathBuf>,
+    interactive: bool,
+    progress: crate::progress::Progress,
+    git_commands: crate::progress::GitCommands,
+    confirm: git_stack::config::Confirm,
+    yes: bool,
+    max_rewrite_commits: Option<usize>,
+    push_retries: usize,
+    /// See `stack.ci-trigger`
+    ci: Option<crate::forge::Ci>,
+    delete_remote: git_stack::config::DeleteRemote,
+    summary: git_stack::config::Summary,
+    checkpoints: std::collections::BTreeMap<git2::Oid, Vec<String>>,
     snapshot_capacity: Option<usize>,
     protect_commit_count: Option<usize>,
+    max_commits_per_branch: Option<usize>,
+    max_commits_action: git_stack::config::MaxCommitsAction,
     protect_commit_age: std::time::Duration,
     protect_commit_time: std::time::SystemTime,
+    /// Untouched for this long, a stack is called out as stale
+    stale_age: std::time::Duration,
+    /// Untouched for this long, a stack is left out of the tree
+    hide_age: Option<std::time::Duration>,
 
     show_format: git_stack::config::Format,
     show_stacked: bool,
+    show_max_commits: Option<usize>,
+    show_touched_dirs: bool,
+    show_commit_types: bool,
+    show_reviews: bool,
+    show_columns: git_stack::config::Columns,
+    issue_pattern: Option<regex::Regex>,
+    issue_url: Option<String>,
 }
 
 impl State {
     fn new(
-        mut repo: git_stack::git::GitRepo,
+        repo: git_stack::git::GitRepo,
         args: &crate::args::Args,
     ) -> Result<Self, proc_exit::Exit> {
         let repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
             .with_code(proc_exit::Code::CONFIG_ERR)?
             .update(args.to_config());
+        Self::with_config(repo, args, repo_config)
+    }
 
+    fn with_config(
+        mut repo: git_stack::git::GitRepo,
+        args: &crate::args::Args,
+        repo_config: git_stack::config::RepoConfig,
+    ) -> Result<Self, proc_exit::Exit> {
+        let mut cancel = git_stack::git::Cancel::new();
+        if let Some(timeout) = args.timeout {
+            cancel = cancel.with_deadline(std::time::Instant::now() + timeout);
+        }
+        let timeout = args.timeout;
+        let usage_stats = repo_config.usage_stats();
         let mut rebase = args.rebase;
         let pull = args.pull;
         if pull {
@@ -75,23 +134,115 @@ impl State {
             }
         };
         let push = args.push;
+        if pull {
+            require_online(&repo_config, "`--pull`")?;
+        }
+        if push {
+            require_online(&repo_config, "`--push`")?;
+        }
+        let fresh_base = if repo_config.offline() {
+            log::trace!("Offline, not checking if bases are out-of-date");
+            git_stack::config::FreshBase::Ignore
+        } else {
+            repo_config.require_fresh_base()
+        };
+        let protection_action = repo_config.protection_action();
+        if pull {
+            // We're going to the network anyway
+            crate::forge::refresh(repo.raw(), &repo_config, args.dry_run);
+        }
         let protected = git_stack::git::ProtectedBranches::new(
-            repo_config.protected_branches().iter().map(|s| s.as_str()),
+            crate::forge::protected_patterns(repo.raw(), &repo_config)
+                .iter()
+                .map(|s| s.as_str()),
         )
         .with_code(proc_exit::Code::CONFIG_ERR)?;
         let dry_run = args.dry_run;
+        let check = args.check;
+        let verify = args.verify;
+        let exec = args.exec.clone();
+        let keep_going = repo_config.keep_going();
+        let conflict_report = args.conflict_report.clone();
+        let interactive = !args.non_interactive();
+        let progress = crate::progress::Progress::new(!interactive);
+        let git_commands = crate::progress::GitCommands::new(args.show_git_commands);
+        let confirm = repo_config.confirm();
+        let yes = args.yes;
+        let max_rewrite_commits = repo_config.max_rewrite_commits();
+        let push_retries = repo_config.push_retries();
+        let ci = if push {
+            crate::forge::Ci::from_config(repo.raw(), &repo_config)
+                .with_code(proc_exit::Code::CONFIG_ERR)?
+        } else {
+            None
+        };
+        let delete_remote = if repo_config.offline() {
+            git_stack::config::DeleteRemote::Never
+        } else {
+            repo_config.delete_remote()
+        };
+        let summary = repo_config.summary();
         let snapshot_capacity = repo_config.capacity();
         let protect_commit_count = repo_config.protect_commit_count();
         let protect_commit_age = repo_config.protect_commit_age();
+        let stale_age = repo_config.stale_age();
+        let hide_age = repo_config.hide_age();
+        let max_commits_per_branch = repo_config.max_commits_per_branch();
+        let max_commits_action = repo_config.max_commits_action();
         let protect_commit_time = std::time::SystemTime::now() - protect_commit_age;
         let show_format = repo_config.show_format();
         let show_stacked = repo_config.show_stacked();
+        let show_max_commits = repo_config.show_max_commits();
+        let show_touched_dirs = repo_config.show_touched_dirs();
+        let show_commit_types = repo_config.show_commit_types();
+        let show_reviews = repo_config.show_reviews();
+        let show_columns = repo_config.show_columns();
+        let issue_pattern = repo_config
+            .issue_pattern()
+            .map(regex::Regex::new)
+            .transpose()
+            .with_code(proc_exit::Code::CONFIG_ERR)?;
+        let issue_url = repo_config.issue_url().map(|u| u.to_owned());
 
         repo.set_push_remote(repo_config.push_remote());
+        repo.set_push_refspec(repo_config.push_refspec().cloned());
         repo.set_pull_remote(repo_config.pull_remote());
+        repo.set_jobs(repo_config.jobs());
+        repo.set_editor(Some(git_stack::git::Editor::from_repo(repo.raw())));
+        repo.set_squash_message(repo_config.squash_message());
+        repo.set_commit_template(commit_template(repo.raw(), &repo_config)?);
+        repo.set_large_file_threshold(repo_config.large_file_threshold());
+        repo.set_merge_options(repo_config.merge_options());
+        repo.set_empty_commits(
+            repo_config.empty_commits(),
+            Some(Box::new(move |prompt| {
+                yes || self::confirm(prompt, interactive).unwrap_or(false)
+            })),
+        );
+        if repo_config.commit_cache() {
+            repo.open_commit_cache();
+        }
 
-        let branches = git_stack::git::Branches::new(repo.local_branches());
-        let protected_branches = branches.protected(&protected);
+        let ignored = git_stack::git::BranchGlobs::new(
+            repo_config.ignore_branches().iter().map(|s| s.as_str()),
+        )
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+        let branches = git_stack::git::Branches::with_ignore(repo.local_branches(), &ignored);
+        let mut protected_branches = branches.protected(&protected);
+        protected_branches.extend(remote_protected_branches(&repo, &branches, &protected));
+        if !args.unprotect.is_empty() {
+            protected_branches = unprotect(&protected_branches, &args.unprotect);
+        }
+        let mut checkpoints = std::collections::BTreeMap::new();
+        for tag in repo_config.checkpoints() {
+            match repo.find_tag(tag) {
+                Some(id) => checkpoints
+                    .entry(id)
+                    .or_insert_with(Vec::new)
+                    .push(tag.clone()),
+                None => log::debug!("Ignoring checkpoint {}, the tag no longer exists", tag),
+            }
+        }
         let head_commit = repo.head_commit();
         let base = args
             .base
@@ -105,8 +256,45 @@ impl State {
             .map(|name| resolve_explicit_base(&repo, name))
             .transpose()
             .with_code(proc_exit::Code::USAGE_ERR)?;
+        let exec_at = args
+            .exec_at
+            .iter()
+            .map(|(rev, command)| {
+                repo.resolve(rev)
+                    .map(|commit| (commit.id, command.clone()))
+                    .ok_or_else(|| eyre::eyre!("could not find commit {:?}", rev))
+            })
+            .collect::<eyre::Result<Vec<_>>>()
+            .with_code(proc_exit::Code::USAGE_ERR)?;
 
-        let stacks = match (base, onto, repo_config.stack()) {
+        // Only bare invocations are pinned, anything explicit wins
+        let focus = if args.subcommand.is_none()
+            && args.stack.is_none()
+            && base.is_none()
+            && onto.is_none()
+            && args.label.is_empty()
+            && args.select.is_none()
+        {
+            crate::focus::read_focus(repo.raw()).filter(|name| {
+                let exists = repo.find_local_branch(name).is_some();
+                if !exists {
+                    log::warn!(
+                        "Ignoring focus on `{}`, the branch no longer exists (run `git stack focus --clear`)",
+                        name
+                    );
+                }
+                exists
+            })
+        } else {
+            None
+        };
+        // Labeled, selected, and focused branches can be in any stack
+        let stack_mode = if args.label.is_empty() && args.select.is_none() && focus.is_none() {
+            repo_config.stack()
+        } else {
+            git_stack::config::Stack::All
+        };
+        let mut stacks = match (base, onto, stack_mode) {
             (Some(base), Some(onto), git_stack::config::Stack::All) => {
                 vec![StackState {
                     base,
@@ -131,6 +319,10 @@ impl State {
                 }]
             }
             (None, None, git_stack::config::Stack::All) => {
+                // Finding each branch's protected base is the bulk of the per-branch work
+                repo.prefetch_merge_bases(
+                    &branches.merge_base_pairs(&protected_branches.oids().collect::<Vec<_>>()),
+                );
                 let mut stack_branches = std::collections::BTreeMap::new();
                 for (branch_id, branch) in branches.iter() {
                     let base_branch =
@@ -191,26 +383,100 @@ impl State {
             }
         };
 
+        if let Some(scope_path) = repo_config.scope_path() {
+            // Normalize away `./` and trailing slashes for tree lookups
+            let scope_path: std::path::PathBuf = scope_path
+                .components()
+                .filter(|c| matches!(c, std::path::Component::Normal(_)))
+                .collect();
+            for stack in stacks.iter_mut() {
+                stack.branches = git_stack::git::Branches::new(
+                    stack
+                        .branches
+                        .iter()
+                        .filter(|(branch_id, _)| {
+                            repo.touches_path(stack.base.id, *branch_id, &scope_path)
+                        })
+                        .flat_map(|(_, branches)| branches.iter().cloned())
+                        .collect::<Vec<_>>(),
+                );
+            }
+        }
+
+        if !args.label.is_empty() {
+            stacks = filter_labeled(&repo, stacks, &args.label);
+        }
+        if let Some(selector) = args.select.as_ref() {
+            stacks = filter_selected(&repo, stacks, &protected_branches, selector)
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+        if let Some(focus) = focus.as_deref() {
+            log::info!(
+                "Focused on the stack of {} (run `git stack focus --clear` to undo)",
+                focus
+            );
+            stacks = filter_lines(&repo, stacks, |b| b.name == focus);
+        }
+        crate::metadata::warn_problems(repo.raw());
+        let dependencies = crate::depend::branch_dependencies(repo.raw());
+        warn_partial_dependencies(&branches, &stacks, &dependencies);
+        let pinned = crate::pin::pinned_commits(repo.raw());
+
         Ok(Self {
             repo,
             branches,
             protected_branches,
             head_commit,
             stacks,
+            dependencies,
+            pinned,
 
             rebase,
             pull,
             push,
             fixup,
             repair,
+            fresh_base,
+            protection_action,
             dry_run,
+            check,
+            verify,
+            exec,
+            exec_at,
+            keep_going,
+            cancel,
+            usage_stats,
+            timeout,
+            conflict_report,
+            interactive,
+            progress,
+            git_commands,
+            confirm,
+            yes,
+            max_rewrite_commits,
+            push_retries,
+            ci,
+            delete_remote,
+            summary,
+            checkpoints,
             snapshot_capacity,
             protect_commit_count,
+            max_commits_per_branch,
+            max_commits_action,
             protect_commit_age,
+            stale_age,
+            hide_age,
             protect_commit_time,
 
             show_format,
             show_stacked,
+            show_max_commits,
+            show_touched_dirs,
+            show_commit_types,
+            show_reviews,
+            show_columns,
+            issue_pattern,
+            issue_url,
         })
     }
 
@@ -236,11 +502,9 @@ struct StackState {
 
 impl StackState {
     fn update(&mut self, repo: &dyn git_stack::git::Repo) -> eyre::Result<()> {
-        self.base = repo
-            .find_local_branch(self.base.name.as_str())
+        self.base = find_branch(repo, &self.base)
             .ok_or_else(|| eyre::eyre!("can no longer find branch {}", self.base.name))?;
-        self.onto = repo
-            .find_local_branch(self.onto.name.as_str())
+        self.onto = find_branch(repo, &self.onto)
             .ok_or_else(|| eyre::eyre!("can no longer find branch {}", self.onto.name))?;
         self.branches.update(repo);
         Ok(())
@@ -256,6 +520,14 @@ impl StackState {
         }
         graphed_branches
     }
+
+    /// Where each branch forked off of `base`, even if `base` has since been rewritten
+    fn fork_points(&self, repo: &git_stack::git::GitRepo) -> Vec<git2::Oid> {
+        self.branches
+            .oids()
+            .filter_map(|oid| repo.fork_point(&self.base, oid))
+            .collect()
+    }
 }
 
 pub fn stack(
@@ -267,150 +539,3102 @@ pub fn stack(
     let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
     let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
     let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
+
+    apply(state, colored_stdout, colored_stderr)
+}
+
+pub fn fixups(
+    args: &crate::args::Args,
+    fixups_args: &crate::args::FixupsArgs,
+    colored_stdout: bool,
+    colored_stderr: bool,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let fixup = match fixups_args.fixup {
+        Some(fixup) => fixup,
+        None => git_stack::config::RepoConfig::from_all(repo.raw())
+            .with_code(proc_exit::Code::CONFIG_ERR)?
+            .update(args.to_config())
+            .auto_fixup(),
+    };
     let mut state = State::new(repo, args)?;
 
-    if state.pull {
-        // Update status of remote unprotected branches
-        let mut push_branches: Vec<_> = state
-            .stacks
-            .iter()
-            .flat_map(|stack| stack.branches.iter())
-            .filter(|(oid, _)| !state.protected_branches.contains_oid(*oid))
-            .flat_map(|(_, b)| b.iter())
-            .filter_map(|b| b.push_id.map(|_| b.name.as_str()))
-            .collect();
-        push_branches.sort_unstable();
-        if !push_branches.is_empty() {
-            match git_prune_development(&mut state.repo, &push_branches, state.dry_run) {
-                Ok(_) => (),
-                Err(err) => {
-                    log::warn!("Skipping fetch of `{}`, {}", state.repo.push_remote(), err);
-                }
-            }
-        }
+    // Keep the base stable, only moving the fixups around
+    state.rebase = false;
+    state.pull = false;
+    state.push = false;
+    state.repair = false;
+    state.fixup = fixup;
+    if fixup == git_stack::config::Fixup::Ignore {
+        log::warn!("Nothing to do, fixup action is `{}`", fixup);
+    }
 
-        for stack in state.stacks.iter() {
-            if state.protected_branches.contains_oid(stack.onto.id) {
-                match git_fetch_upstream(&mut state.repo, stack.onto.name.as_str()) {
-                    Ok(_) => (),
-                    Err(err) => {
-                        log::warn!("Skipping pull of `{}`, {}", stack.onto.name, err);
-                    }
+    apply(state, colored_stdout, colored_stderr)
+}
+
+pub fn bot(args: &crate::args::Args, bot_args: &crate::args::BotArgs) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    require_online(&repo_config, "`git stack bot`")?;
+    // Nobody is around to say which stack they care about
+    repo_config.stack = Some(git_stack::config::Stack::All);
+    crate::forge::refresh(repo.raw(), &repo_config, args.dry_run);
+    let mut state = State::with_config(repo, args, repo_config)?;
+
+    // Only move stacks onto their updated base, leaving everything else for humans
+    state.rebase = true;
+    state.pull = true;
+    state.push = false;
+    state.fixup = git_stack::config::Fixup::Ignore;
+    state.repair = false;
+    state.interactive = false;
+    // Nobody is around to answer, so the report is where a human reviews what was done
+    state.yes = true;
+    // A conflict in one stack is no reason to leave the others stale
+    state.keep_going = true;
+
+    let origin = crate::jumps::Origin::new(&state.repo, "git stack bot");
+    let mut stash_id = None;
+    let mut report = BotReport::default();
+    let result = restack_stale(&mut state, bot_args, &mut stash_id, &mut report);
+    origin.restore(&mut state.repo, &state.git_commands, state.dry_run);
+    if stash_id.is_some() {
+        state.git_commands.show("git stash pop");
+    }
+    git_stack::git::stash_pop(&mut state.repo, stash_id);
+
+    if let Err(err) = &result {
+        report.error = Some(err.to_string());
+    }
+    write_bot_report(&report)?;
+    result?;
+    if !report.conflicts.is_empty()
+        || report.verified.iter().any(|v| v.status != "ok")
+        || report.push_error.is_some()
+    {
+        return proc_exit::Code::FAILURE.ok();
+    }
+
+    Ok(())
+}
+
+/// Restack (and verify and push) the stacks whose base moved, leaving any stash and writing the
+/// report for the caller
+fn restack_stale(
+    state: &mut State,
+    bot_args: &crate::args::BotArgs,
+    stash_id: &mut Option<git2::Oid>,
+    report: &mut BotReport,
+) -> proc_exit::ExitResult {
+    pull(state)?;
+
+    let stacks = std::mem::take(&mut state.stacks);
+    state.stacks = stacks
+        .into_iter()
+        .filter(|stack| {
+            let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
+            stack.branches.oids().any(|oid| {
+                !state.protected_branches.contains_oid(oid)
+                    && state.repo.merge_base(onto_id, oid) != Some(onto_id)
+            })
+        })
+        .collect();
+    report.stale = state
+        .stacks
+        .iter()
+        .map(|stack| BotStack {
+            onto: stack.onto.name.clone(),
+            branches: stack
+                .branches
+                .iter()
+                .flat_map(|(_, b)| b.iter())
+                .map(|b| b.name.clone())
+                .collect(),
+        })
+        .collect();
+    if state.stacks.is_empty() {
+        log::info!("All stacks are up-to-date");
+        return Ok(());
+    }
+
+    let rewritten = rewrite(state)?;
+    *stash_id = rewritten.stash_id;
+    let mut exclude: std::collections::HashSet<String> = rewritten
+        .failures
+        .iter()
+        .flat_map(|f| std::iter::once(&f.branch).chain(f.blocked.iter()))
+        .cloned()
+        .collect();
+    report.restacked = rewritten
+        .summary
+        .moved_branches
+        .iter()
+        .filter(|b| !exclude.contains(*b))
+        .filter(|b| {
+            !state
+                .protected_branches
+                .iter()
+                .flat_map(|(_, b)| b.iter())
+                .any(|p| &&p.name == b)
+        })
+        .cloned()
+        .collect();
+    report.conflicts = rewritten.failures;
+
+    if let Some(exec) = bot_args.exec.as_deref() {
+        if !state.dry_run {
+            let head_branch = state.repo.head_branch().map(|b| b.name);
+            for branch in report.restacked.iter() {
+                let status = verify_branch(&mut state.repo, branch, exec);
+                if status.is_err() {
+                    exclude.insert(branch.clone());
                 }
-            } else {
-                log::warn!(
-                    "Skipping pull of `{}`, not a protected branch",
-                    stack.onto.name
-                );
+                report.verified.push(Verification {
+                    branch: branch.clone(),
+                    status: status.as_ref().map(|_| "ok").unwrap_or("failed"),
+                    message: status.err().map(|e| e.to_string()),
+                });
+            }
+            if let Some(head_branch) = head_branch {
+                state
+                    .repo
+                    .switch(&head_branch)
+                    .with_code(proc_exit::Code::FAILURE)?;
             }
         }
-        state.update().with_code(proc_exit::Code::FAILURE)?;
     }
 
-    const STASH_STACK_NAME: &str = "git-stack";
-    let mut success = true;
-    let mut backed_up = false;
-    let mut stash_id = None;
-    if state.rebase || state.fixup != git_stack::config::Fixup::Ignore || state.repair {
-        if stash_id.is_none() && !state.dry_run {
-            stash_id = git_stack::git::stash_push(&mut state.repo, "branch-stash");
+    state.update().with_code(proc_exit::Code::FAILURE)?;
+    match push(state, &exclude) {
+        Ok(summary) => {
+            report.pushed = summary.pushed_branches;
+            report.pushed.extend(summary.forced_branches);
         }
-        if state.repo.is_dirty() {
-            let message = "Working tree is dirty, aborting";
-            if state.dry_run {
-                log::error!("{}", message);
-            } else {
-                git_stack::git::stash_pop(&mut state.repo, stash_id);
-                return Err(proc_exit::Code::USAGE_ERR.with_message(message));
-            }
+        Err(err) => {
+            report.push_error = Some(err.to_string());
         }
+    }
+
+    Ok(())
+}
+
+/// What `git stack bot` did and what needs a human
+#[derive(Default, serde::Serialize)]
+struct BotReport {
+    /// Stacks whose base moved
+    stale: Vec<BotStack>,
+    restacked: Vec<String>,
+    conflicts: Vec<crate::conflict::RestackFailure>,
+    verified: Vec<Verification>,
+    pushed: Vec<String>,
+    push_error: Option<String>,
+    /// Why the run stopped early, leaving the rest of the report incomplete
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct BotStack {
+    onto: String,
+    branches: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct Verification {
+    branch: String,
+    status: &'static str,
+    message: Option<String>,
+}
+
+fn write_bot_report(report: &BotReport) -> proc_exit::ExitResult {
+    let report = serde_json::to_string_pretty(report).with_code(proc_exit::Code::FAILURE)?;
+    writeln!(std::io::stdout(), "{}", report)?;
+    Ok(())
+}
+
+/// Run `exec` with `branch` checked out
+fn verify_branch(repo: &mut git_stack::git::GitRepo, branch: &str, exec: &str) -> eyre::Result<()> {
+    repo.switch(branch)?;
+    log::info!("Verifying `{}` with `{}`", branch, exec);
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let status = status_on_stderr(std::process::Command::new(shell).arg(flag).arg(exec))
+        .wrap_err_with(|| format!("Could not run `{}`", exec))?;
+    if !status.success() {
+        eyre::bail!("`{}` failed with {}", exec, status);
+    }
+    Ok(())
+}
+
+/// Time each phase of a run on the current repo, to see where the time goes
+pub fn perf(args: &crate::args::Args, perf_args: &crate::args::PerfArgs) -> proc_exit::ExitResult {
+    let start = std::time::Instant::now();
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut state = State::new(repo, args)?;
+    // Plan as if rebasing, without touching anything
+    state.rebase = true;
+    state.dry_run = true;
+    let load = start.elapsed();
 
-        let mut snapshots = git_stack::stash::Stack::new(STASH_STACK_NAME, &state.repo);
-        snapshots.capacity(state.snapshot_capacity);
-        let mut snapshot = git_stack::stash::Snapshot::from_repo(&state.repo)
+    let phase = std::time::Instant::now();
+    let mut graphs = Vec::with_capacity(state.stacks.len());
+    for stack in state.stacks.iter() {
+        let base_commit = state
+            .repo
+            .find_commit(stack.base.id)
+            .expect("base branch is valid");
+        let mut graph =
+            git_stack::graph::Graph::from_branches(&state.repo, stack.graphed_branches())
+                .with_code(proc_exit::Code::FAILURE)?;
+        graph
+            .insert(&state.repo, git_stack::graph::Node::new(base_commit))
             .with_code(proc_exit::Code::FAILURE)?;
-        snapshot.insert_parent(&state.repo, &state.branches, &state.protected_branches);
-        if !state.dry_run {
-            snapshots.push(snapshot)?;
-            backed_up = true;
+        git_stack::graph::protect_branches(&mut graph, &state.repo, &state.protected_branches);
+        graphs.push(graph);
+    }
+    let graph = phase.elapsed();
+
+    let phase = std::time::Instant::now();
+    for stack in state.stacks.iter() {
+        plan_changes(&state, stack).with_code(proc_exit::Code::FAILURE)?;
+    }
+    let plan = phase.elapsed();
+
+    let phase = std::time::Instant::now();
+    let show_format = match state.show_format {
+        git_stack::config::Format::Silent | git_stack::config::Format::Debug => {
+            git_stack::config::Format::Commits
+        }
+        show_format => show_format,
+    };
+    let mut rendered = 0;
+    for graph in graphs.iter() {
+        rendered += DisplayTree::new(&state.repo, graph)
+            .show(show_format)
+            .stacked(state.show_stacked)
+            .max_commits(state.show_max_commits)
+            .touched_dirs(state.show_touched_dirs)
+            .commit_types(state.show_commit_types)
+            .reviews(state.show_reviews)
+            .issue_pattern(state.issue_pattern.as_ref())
+            .columns(&state.show_columns)
+            .protection(state.protect_commit_count, state.protect_commit_age)
+            .protected_branches(&state.protected_branches)
+            .checkpoints(&state.checkpoints)
+            .to_string()
+            .len();
+    }
+    let display = phase.elapsed();
+    let total = start.elapsed();
+
+    let nodes: usize = graphs.iter().map(|g| g.breadth_first_iter().count()).sum();
+    let mut stdout = std::io::stdout();
+    writeln!(
+        stdout,
+        "{} stacks, {} branches, {} commits, {} bytes rendered",
+        state.stacks.len(),
+        state.branches.iter().map(|(_, b)| b.len()).sum::<usize>(),
+        nodes,
+        rendered
+    )?;
+    for (name, elapsed) in [
+        ("load", load),
+        ("graph", graph),
+        ("plan", plan),
+        ("display", display),
+        ("total", total),
+    ] {
+        writeln!(stdout, "{:<8} {:>10.1?}", name, elapsed)?;
+    }
+
+    if let Some(budget) = perf_args.budget {
+        if budget < total {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "Took {:.1?}, over the budget of {}",
+                total,
+                humantime::format_duration(budget)
+            )));
         }
+    }
 
-        let mut head_branch = state
-            .repo
-            .head_branch()
-            .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
-            .with_code(proc_exit::Code::USAGE_ERR)?
-            .name;
+    Ok(())
+}
 
-        let scripts: Result<Vec<_>, proc_exit::Exit> = state
-            .stacks
-            .iter()
-            .map(|stack| {
-                let script = plan_changes(&state, stack).with_code(proc_exit::Code::FAILURE)?;
-                if script.is_branch_deleted(&head_branch) {
-                    head_branch = stack.onto.name.clone();
-                }
-                Ok(script)
-            })
-            .collect();
-        let scripts = scripts?;
+pub fn issues(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
+    let issue_pattern = state.issue_pattern.as_ref().ok_or_else(|| {
+        proc_exit::Code::CONFIG_ERR
+            .with_message("No issue keys to look for, set `stack.issue-pattern`")
+    })?;
 
-        let mut executor = git_stack::git::Executor::new(&state.repo, state.dry_run);
-        for script in scripts {
-            let results = executor.run_script(&mut state.repo, &script);
-            for (err, name, dependents) in results.iter() {
-                success = false;
-                log::error!("Failed to re-stack branch `{}`: {}", name, err);
-                if !dependents.is_empty() {
-                    log::error!("  Blocked dependents: {}", dependents.iter().join(", "));
+    let palette = if colored_stdout {
+        Palette::colored()
+    } else {
+        Palette::plain()
+    };
+    let development_branches = development_branches(&state);
+    let branches = git_stack::git::unprotected_branches(
+        state.stacks.iter().map(|stack| &stack.branches),
+        &state.protected_branches,
+    );
+
+    let mut issues: std::collections::BTreeMap<String, Vec<&str>> = Default::default();
+    for branch in branches {
+        let protected_base =
+            git_stack::git::find_protected_base(&state.repo, &state.protected_branches, branch.id);
+        let fork_id = protected_base.and_then(|base| state.repo.merge_base(base.id, branch.id));
+        // Only the branch's own commits, not those of the branches it is stacked on
+        let base_id = match development_parent(&state, &development_branches, branch.id, fork_id) {
+            Some(parent) => parent.id,
+            None => match fork_id {
+                Some(fork_id) => fork_id,
+                None => {
+                    log::warn!("Could not find where {} starts, skipping", branch.name);
+                    continue;
                 }
-            }
+            },
+        };
+        let names = std::iter::once(branch.name.as_str());
+        for key in layer_issues(&state.repo, issue_pattern, names, branch.id, base_id) {
+            issues.entry(key).or_default().push(branch.name.as_str());
         }
-        executor
-            .close(&mut state.repo, &head_branch)
-            .with_code(proc_exit::Code::FAILURE)?;
-        state.update().with_code(proc_exit::Code::FAILURE)?;
     }
 
-    if state.push {
-        push(&mut state).with_code(proc_exit::Code::FAILURE)?;
-        state.update().with_code(proc_exit::Code::FAILURE)?;
+    let mut stdout = std::io::stdout();
+    for (key, branches) in issues {
+        write!(
+            stdout,
+            "{} {}",
+            palette.info.paint(&key),
+            branches
+                .iter()
+                .map(|b| palette.good.paint(b).to_string())
+                .join(", ")
+        )?;
+        if let Some(issue_url) = state.issue_url.as_deref() {
+            write!(
+                stdout,
+                " {}",
+                palette.hint.paint(issue_url.replace("{}", &key))
+            )?;
+        }
+        writeln!(stdout)?;
     }
 
-    show(&state, colored_stdout, colored_stderr).with_code(proc_exit::Code::FAILURE)?;
+    Ok(())
+}
+
+pub fn changelog(
+    args: &crate::args::Args,
+    changelog_args: &crate::args::ChangelogArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
 
-    git_stack::git::stash_pop(&mut state.repo, stash_id);
+    let mut seen = std::collections::HashSet::new();
+    let mut commits: Vec<_> = state
+        .stacks
+        .iter()
+        .flat_map(|stack| unmerged_commits(&state, stack))
+        .filter(|c| seen.insert(c.id))
+        .collect();
+    // Oldest first, like they'll land, keeping parents before children on ties
+    commits.reverse();
+    commits.sort_by_key(|c| c.time);
 
-    if backed_up {
-        let palette_stderr = if colored_stderr {
-            Palette::colored()
+    let mut sections: Vec<(String, Vec<String>)> = Vec::new();
+    for commit in commits {
+        if commit.fixup_summary().is_some() || commit.wip_summary().is_some() {
+            continue;
+        }
+        let summary = commit.summary.to_str_lossy();
+        let (kind, breaking, description) = match commit.conventional_type() {
+            Some((kind, breaking)) => {
+                let description = summary.split_once(": ").map(|(_, d)| d).unwrap_or(&summary);
+                (
+                    Some(kind.to_str_lossy().to_lowercase()),
+                    breaking,
+                    description,
+                )
+            }
+            None => (None, false, summary.as_ref()),
+        };
+        let message = state
+            .repo
+            .raw()
+            .find_commit(commit.id)
+            .ok()
+            .map(|c| String::from_utf8_lossy(c.message_bytes()).into_owned())
+            .unwrap_or_default();
+        let section = match changelog_trailer(&message) {
+            Some(section) if section.eq_ignore_ascii_case("skip") => continue,
+            Some(section) => section,
+            None => changelog_section(kind.as_deref()).to_owned(),
+        };
+        let entry = if breaking {
+            format!("- **Breaking:** {}", description)
         } else {
-            Palette::plain()
+            format!("- {}", description)
         };
-        log::info!(
-            "{}",
-            palette_stderr.hint.paint(format_args!(
-                "To undo, run `git branch-stash pop {}`",
-                STASH_STACK_NAME
-            ))
-        );
+        match sections.iter_mut().find(|(s, _)| *s == section) {
+            Some((_, entries)) => entries.push(entry),
+            None => sections.push((section, vec![entry])),
+        }
     }
+    sections.sort_by_key(|(section, _)| changelog_section_order(section));
 
-    if !success {
-        return proc_exit::Code::FAILURE.ok();
+    let mut output = String::new();
+    for (section, entries) in sections {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&format!("#### {}\n\n", section));
+        for entry in entries {
+            output.push_str(&entry);
+            output.push('\n');
+        }
+    }
+
+    match changelog_args.output.as_deref() {
+        Some(path) => std::fs::write(path, &output).with_code(proc_exit::Code::FAILURE)?,
+        None => std::io::stdout().write_all(output.as_bytes())?,
+    }
+
+    Ok(())
+}
+
+pub fn stats(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
+
+    let palette = if colored_stdout {
+        Palette::colored()
+    } else {
+        Palette::plain()
+    };
+    let mut stdout = std::io::stdout();
+    for stack in state.stacks.iter() {
+        let branch_count =
+            git_stack::git::unprotected_branches([&stack.branches], &state.protected_branches)
+                .len();
+        let commits = unmerged_commits(&state, stack);
+        let impact = Impact::new(&state.repo, &commits);
+        write!(
+            stdout,
+            "{}: {} branches, {} commits, {} impact",
+            palette.info.paint(&stack.base.name),
+            branch_count,
+            commits.len(),
+            match impact {
+                Impact::Major => palette.warn.paint(impact),
+                _ => palette.good.paint(impact),
+            }
+        )?;
+        let reviews = ReviewCoverage::from_commits(&state.repo, commits);
+        // Only mailing-list workflows sign off in trailers
+        if !reviews.reviewers.is_empty() {
+            let style = if reviews.is_complete() {
+                palette.good
+            } else {
+                palette.warn
+            };
+            write!(stdout, ", {}", style.paint(&reviews))?;
+        }
+        writeln!(stdout)?;
+    }
+
+    Ok(())
+}
+
+/// One line per stack of branches, for keeping track of many at once
+pub fn overview(args: &crate::args::Args, colored_stdout: bool) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    // The point is to see every stack
+    repo_config.stack = Some(git_stack::config::Stack::All);
+    let state = State::with_config(repo, args, repo_config)?;
+
+    let mut overviews = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut graph =
+            git_stack::graph::Graph::from_branches(&state.repo, stack.graphed_branches())
+                .with_code(proc_exit::Code::FAILURE)?;
+        git_stack::graph::protect_branches(&mut graph, &state.repo, &state.protected_branches);
+        let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
+        for (fork_id, root_id) in stack_roots(&graph) {
+            overviews.push(StackOverview::new(
+                &state,
+                &graph,
+                &stack.onto,
+                onto_id,
+                fork_id,
+                root_id,
+            ));
+        }
+    }
+    overviews.sort_by(|a, b| a.root.cmp(&b.root));
+
+    let palette = if colored_stdout {
+        Palette::colored()
+    } else {
+        Palette::plain()
+    };
+    let mut stdout = std::io::stdout();
+    for overview in overviews {
+        write!(
+            stdout,
+            "{}: {} layers, {} commits",
+            palette.info.paint(&overview.root),
+            overview.layers,
+            overview.commits
+        )?;
+        if 0 < overview.behind {
+            write!(
+                stdout,
+                ", {}",
+                palette
+                    .warn
+                    .paint(format!("{} behind {}", overview.behind, overview.onto))
+            )?;
+        }
+        if 0 < overview.unpushed {
+            write!(
+                stdout,
+                ", {}",
+                palette.hint.paint(format!("{} to push", overview.unpushed))
+            )?;
+        }
+        if overview.conflicts {
+            write!(stdout, ", {}", palette.error.paint("conflicts likely"))?;
+        }
+        if let Some(age) = overview.stale {
+            write!(
+                stdout,
+                ", {}",
+                palette
+                    .warn
+                    .paint(format!("untouched for {}", format_span(age.as_secs())))
+            )?;
+        }
+        writeln!(stdout)?;
+    }
+
+    Ok(())
+}
+
+struct StackOverview {
+    /// The branch closest to the base
+    root: String,
+    /// The most branches stacked on each other
+    layers: usize,
+    commits: usize,
+    onto: String,
+    /// Commits on `onto` that the stack isn't rebased onto yet
+    behind: usize,
+    /// Branches that differ from what was pushed, or were never pushed
+    unpushed: usize,
+    /// Whether rebasing onto `onto` would likely conflict
+    conflicts: bool,
+    /// How long since the stack was last touched, if past `stack.stale-age`
+    stale: Option<std::time::Duration>,
+}
+
+impl StackOverview {
+    fn new(
+        state: &State,
+        graph: &git_stack::graph::Graph,
+        onto: &git_stack::git::Branch,
+        onto_id: git2::Oid,
+        fork_id: git2::Oid,
+        root_id: git2::Oid,
+    ) -> Self {
+        let raw = state.repo.raw();
+        let root = stack_root_name(graph, root_id);
+        let stale = last_touched(raw, graph, root_id)
+            .and_then(|touched| std::time::SystemTime::now().duration_since(touched).ok())
+            .filter(|age| state.stale_age <= *age);
+        let behind = raw
+            .graph_ahead_behind(fork_id, onto_id)
+            .map(|(_, behind)| behind)
+            .unwrap_or(0);
+
+        let mut layers = 0;
+        let mut commits = 0;
+        let mut unpushed = 0;
+        let mut tips = Vec::new();
+        let mut queue = vec![(root_id, 0)];
+        while let Some((node_id, depth)) = queue.pop() {
+            let node = graph.get(node_id).expect("all children exist");
+            commits += 1;
+            let depth = if node.branches.is_empty() {
+                depth
+            } else {
+                depth + 1
+            };
+            layers = layers.max(depth);
+            unpushed += node
+                .branches
+                .iter()
+                .filter(|b| b.push_id != Some(b.id))
+                .count();
+            if node.children.is_empty() {
+                tips.push(node_id);
+            }
+            queue.extend(node.children.iter().map(|child_id| (*child_id, depth)));
+        }
+
+        // Merging each tip is a cheap stand-in for replaying every commit
+        let conflicts = 0 < behind
+            && tips.into_iter().any(|tip_id| {
+                raw.find_commit(tip_id)
+                    .and_then(|tip| {
+                        let onto = raw.find_commit(onto_id)?;
+                        raw.merge_commits(&tip, &onto, None)
+                    })
+                    .map(|index| index.has_conflicts())
+                    .unwrap_or(false)
+            });
+
+        Self {
+            root,
+            layers,
+            commits,
+            onto: onto.name.clone(),
+            behind,
+            unpushed,
+            conflicts,
+            stale,
+        }
+    }
+}
+
+/// Each line of development forking off of protected history, as `(fork_id, root_id)`
+fn stack_roots(graph: &git_stack::graph::Graph) -> Vec<(git2::Oid, git2::Oid)> {
+    graph
+        .breadth_first_iter()
+        .filter(|n| n.action.is_protected())
+        .flat_map(|n| n.children.iter().map(move |c| (n.commit.id, *c)))
+        .filter(|(_, child_id)| {
+            !graph
+                .get(*child_id)
+                .expect("all children exist")
+                .action
+                .is_protected()
+        })
+        .collect()
+}
+
+/// The branch closest to the base of the stack starting at `root_id`
+fn stack_root_name(graph: &git_stack::graph::Graph, root_id: git2::Oid) -> String {
+    git_stack::graph::BreadthFirstIter::new(graph, root_id)
+        .find_map(|n| n.branches.first())
+        .map(|b| b.name.clone())
+        .unwrap_or_else(|| root_id.to_string()[..7].to_owned())
+}
+
+/// When any branch in the stack starting at `root_id` last moved, going by the reflogs
+///
+/// Branches without a reflog fall back to when their commit was made.
+fn last_touched(
+    repo: &git2::Repository,
+    graph: &git_stack::graph::Graph,
+    root_id: git2::Oid,
+) -> Option<std::time::SystemTime> {
+    git_stack::graph::BreadthFirstIter::new(graph, root_id)
+        .flat_map(|n| n.branches.iter().map(move |b| (n, b)))
+        .map(|(node, branch)| {
+            repo.reflog(&format!("refs/heads/{}", branch.name))
+                .ok()
+                .and_then(|reflog| {
+                    let seconds = reflog.get(0)?.committer().when().seconds();
+                    Some(
+                        std::time::UNIX_EPOCH
+                            + std::time::Duration::from_secs(seconds.max(0) as u64),
+                    )
+                })
+                .unwrap_or(node.commit.time)
+        })
+        .max()
+}
+
+/// Load every stack's commits, so `stack.commit-cache` has them ready for the next run
+pub fn warm(args: &crate::args::Args) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    if !repo_config.commit_cache() {
+        log::trace!("Nothing to warm without `stack.commit-cache`");
+        return Ok(());
+    }
+    repo_config.stack = Some(git_stack::config::Stack::All);
+    let state = State::with_config(repo, args, repo_config)?;
+
+    for stack in state.stacks.iter() {
+        git_stack::graph::Graph::from_branches(&state.repo, stack.graphed_branches())
+            .with_code(proc_exit::Code::FAILURE)?;
+    }
+
+    Ok(())
+}
+
+/// One line about HEAD's stack, for shell prompts and status lines
+///
+/// Like `<root branch> <position>/<branches>[ *][ !][ ↓<behind>]`, where `*` is uncommitted
+/// changes, `!` is a conflict or interrupted rewrite, and `↓` is how far behind its base the stack is.
+pub fn prompt(args: &crate::args::Args) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    // Only HEAD's stack matters, which keeps this fast
+    repo_config.stack = Some(git_stack::config::Stack::Dependents);
+    let state = State::with_config(repo, args, repo_config)?;
+
+    let head_branch = match attached_head_branch(&state.repo) {
+        Some(head_branch) => head_branch,
+        // Prompts already show a detached HEAD
+        None => return Ok(()),
+    };
+    let head_id = head_branch.id;
+    let raw = state.repo.raw();
+    let is_ancestor =
+        |id: git2::Oid| id == head_id || raw.graph_descendant_of(head_id, id).unwrap_or(false);
+
+    let mut line = head_branch.name.clone();
+    if let Some(stack) = state.stacks.iter().find(|s| {
+        s.branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .any(|b| b.name == head_branch.name)
+    }) {
+        let branches: Vec<_> = stack
+            .branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .filter(|b| !is_protected(&state.protected_branches, b))
+            .collect();
+        let below: Vec<_> = branches.iter().filter(|b| is_ancestor(b.id)).collect();
+        if !below.is_empty() {
+            let root = below
+                .iter()
+                .min_by_key(|b| {
+                    let ahead = raw.graph_ahead_behind(b.id, stack.base.id).map(|(a, _)| a);
+                    (ahead.unwrap_or(usize::MAX), b.name.clone())
+                })
+                .expect("non-empty");
+            line = format!("{} {}/{}", root.name, below.len(), branches.len());
+        }
+
+        let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
+        let behind = raw
+            .graph_ahead_behind(head_id, onto_id)
+            .map(|(_, behind)| behind)
+            .unwrap_or(0);
+        if state.repo.is_dirty() {
+            line.push_str(" *");
+        }
+        if has_conflicts(raw) {
+            line.push_str(" !");
+        }
+        if 0 < behind {
+            line.push_str(&format!(" ↓{}", behind));
+        }
+    } else {
+        if state.repo.is_dirty() {
+            line.push_str(" *");
+        }
+        if has_conflicts(raw) {
+            line.push_str(" !");
+        }
+    }
+
+    writeln!(std::io::stdout(), "{}", line)?;
+    Ok(())
+}
+
+/// Conflicts to resolve, or a rewrite to finish
+fn has_conflicts(repo: &git2::Repository) -> bool {
+    repo.index().map(|i| i.has_conflicts()).unwrap_or(false)
+        || git_stack::git::Journal::path(repo).exists()
+        || repo.state() != git2::RepositoryState::Clean
+}
+
+/// Check out a development branch from any stack, picked by fuzzy-matching its name
+pub fn switch(
+    args: &crate::args::Args,
+    switch_args: &crate::args::SwitchArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    // The point is to get to the other stacks
+    repo_config.stack = Some(git_stack::config::Stack::All);
+    let state = State::with_config(repo, args, repo_config)?;
+
+    let candidates = switch_candidates(&state);
+    if candidates.is_empty() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("No development branches to switch to"));
+    }
+    let query = switch_args.query.as_deref().unwrap_or("");
+    let mut matches = crate::picker::filter(&candidates, query);
+    // Without the finder, the context is too loose to decide between branches
+    if matches.iter().any(|(_, positions)| !positions.is_empty()) {
+        matches.retain(|(_, positions)| !positions.is_empty());
+    }
+    let exact = candidates.iter().find(|c| c.name == query);
+    let chosen = if let Some(exact) = exact {
+        exact
+    } else if !query.is_empty() && matches.len() == 1 {
+        matches[0].0
+    } else if !args.non_interactive
+        && atty::is(atty::Stream::Stdin)
+        && atty::is(atty::Stream::Stderr)
+    {
+        // The finder is drawn on stderr, so stdout may still be captured
+        match crate::picker::pick(&candidates, query).with_code(proc_exit::Code::FAILURE)? {
+            Some(chosen) => chosen,
+            None => return proc_exit::Code::FAILURE.ok(),
+        }
+    } else if query.is_empty() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message("Not on a terminal, pass part of the branch name to switch to"));
+    } else if matches.is_empty() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("No development branch matches `{}`", query)));
+    } else {
+        let names: Vec<_> = matches.iter().map(|(c, _)| c.name.as_str()).collect();
+        return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+            "`{}` matches several branches: {}",
+            query,
+            names.join(", ")
+        )));
+    };
+
+    if state.repo.head_branch().map(|b| b.name).as_deref() == Some(chosen.name.as_str()) {
+        log::info!("Already on {}", chosen.name);
+        return Ok(());
+    }
+    log::trace!("git switch {}", chosen.name);
+    state
+        .git_commands
+        .show(&format!("git switch {}", chosen.name));
+    if !state.dry_run {
+        // Unlike a forced checkout, this carries uncommitted changes along or refuses
+        let status = std::process::Command::new("git")
+            .arg("switch")
+            .arg(&chosen.name)
+            .status()
+            .with_code(proc_exit::Code::FAILURE)?;
+        if !status.success() {
+            return proc_exit::Code::FAILURE.ok();
+        }
+    }
+    Ok(())
+}
+
+/// Each development branch, stack by stack, with where it sits in its stack
+fn switch_candidates(state: &State) -> Vec<crate::picker::Candidate> {
+    let development_branches = development_branches(state);
+    let raw = state.repo.raw();
+    let mut candidates = Vec::new();
+    for stack in state.stacks.iter() {
+        let mut branches: Vec<_> = stack
+            .branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .filter(|b| !is_protected(&state.protected_branches, b))
+            .collect();
+        // Bottom of the stack first
+        branches.sort_by_key(|b| {
+            let ahead = raw.graph_ahead_behind(b.id, stack.base.id).map(|(a, _)| a);
+            (ahead.unwrap_or(usize::MAX), b.name.clone())
+        });
+        branches.dedup_by_key(|b| b.name.clone());
+        for branch in branches.iter() {
+            let position = branches
+                .iter()
+                .filter(|b| {
+                    b.id == branch.id || raw.graph_descendant_of(branch.id, b.id).unwrap_or(false)
+                })
+                .count();
+            let fork_id = state.repo.merge_base(stack.base.id, branch.id);
+            let parent = development_parent(state, &development_branches, branch.id, fork_id);
+            let mut context = format!("{} {}/{}", stack.base.name, position, branches.len());
+            if let Some(parent) = parent {
+                context.push_str(&format!(", after {}", parent.name));
+            }
+            if let Some(commit) = state.repo.find_commit(branch.id) {
+                context.push_str(&format!(": {}", commit.summary.to_str_lossy()));
+            }
+            candidates.push(crate::picker::Candidate {
+                name: branch.name.clone(),
+                context,
+            });
+        }
+    }
+    candidates
+}
+
+pub fn cleanup(
+    args: &crate::args::Args,
+    cleanup_args: &crate::args::CleanupArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    // Merged branches can be anywhere in the repo
+    repo_config.stack = Some(git_stack::config::Stack::All);
+    if cleanup_args.remote {
+        require_online(&repo_config, "`--remote`")?;
+        repo_config.delete_remote = Some(git_stack::config::DeleteRemote::Always);
+    }
+    let mut state = State::with_config(repo, args, repo_config)?;
+
+    let origin = crate::jumps::Origin::new(&state.repo, "git stack cleanup");
+    let result = delete_merged_branches(&mut state);
+    origin.restore(&mut state.repo, &state.git_commands, state.dry_run);
+    result
+}
+
+fn delete_merged_branches(state: &mut State) -> proc_exit::ExitResult {
+    let head_branch = state.repo.head_branch();
+    let mut merged = Vec::new();
+    for stack in state.stacks.iter() {
+        for branch in stack
+            .branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .filter(|b| !is_protected(&state.protected_branches, b))
+        {
+            if state.repo.is_merged(stack.base.id, branch.id)
+                || state.repo.is_merged(stack.onto.id, branch.id)
+            {
+                merged.push((stack.base.clone(), branch.clone()));
+            }
+        }
+    }
+    if merged.is_empty() {
+        log::info!("No merged branches");
+        return Ok(());
+    }
+
+    let mut deleted = Vec::new();
+    let mut failed = Vec::new();
+    for (base, branch) in merged {
+        if head_branch.as_ref().map(|b| b.name.as_str()) == Some(branch.name.as_str()) {
+            if state.repo.is_dirty() {
+                log::warn!(
+                    "Skipping {}, it is checked out with uncommitted changes",
+                    branch.name
+                );
+                continue;
+            }
+            let base_name = base.local_name();
+            log::trace!("git switch {}", base_name);
+            state
+                .git_commands
+                .show(&format!("git switch {}", base_name));
+            if !state.dry_run {
+                if let Err(err) = state.repo.switch(base_name) {
+                    log::warn!("Skipping {}, could not switch away: {}", branch.name, err);
+                    continue;
+                }
+            }
+        }
+
+        log::trace!("git branch -D {}", branch.name);
+        state
+            .git_commands
+            .show(&format!("git branch -D {}", branch.name));
+        if !state.dry_run {
+            if let Err(err) = state.repo.delete_branch(&branch.name) {
+                log::error!("Could not delete {}: {}", branch.name, err);
+                failed.push(branch.name);
+                continue;
+            }
+        }
+        log::info!(
+            "Deleted {} (was {}), merged into {}",
+            branch.name,
+            &branch.id.to_string()[..7],
+            base.name
+        );
+        deleted.push(branch.name);
+    }
+    failed.extend(delete_remote_branches(state, &deleted));
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(proc_exit::Code::FAILURE
+            .with_message(format!("could not delete {}", failed.join(", "))))
+    }
+}
+
+/// Delete the push-remote counterparts of locally deleted branches, according to
+/// `stack.delete-remote`, returning what couldn't be deleted
+fn delete_remote_branches(state: &mut State, names: &[String]) -> Vec<String> {
+    let mut failed = Vec::new();
+    if state.delete_remote == git_stack::config::DeleteRemote::Never || names.is_empty() {
+        return failed;
+    }
+
+    let remote = state.repo.push_remote().to_owned();
+    for name in names {
+        let (remote_name, remote_ref) = match (
+            state.repo.push_tracking_name(name),
+            state.repo.push_remote_ref(name),
+        ) {
+            (Some(remote_name), Some(remote_ref)) => (remote_name, remote_ref),
+            _ => continue,
+        };
+        if state.repo.find_remote_branch(&remote_name).is_none() {
+            continue;
+        }
+        // The plain branch name, unless `stack.push-refspec` sent it elsewhere
+        let remote_ref = if remote_ref == format!("refs/heads/{}", name) {
+            name.clone()
+        } else {
+            remote_ref
+        };
+        if state.delete_remote == git_stack::config::DeleteRemote::Ask
+            && !state.yes
+            && !state.dry_run
+        {
+            let prompt = format!("Delete {}?", remote_name);
+            match confirm(&prompt, state.interactive) {
+                Ok(true) => {}
+                Ok(false) => {
+                    remote_log!(info, "Keeping {}", remote_name);
+                    continue;
+                }
+                Err(err) => {
+                    remote_log!(debug, "Could not ask to delete {}: {}", remote_name, err);
+                    continue;
+                }
+            }
+        }
+
+        remote_log!(trace, "git push --delete {} {}", remote, remote_ref);
+        state
+            .git_commands
+            .show(&format!("git push --delete {} {}", remote, remote_ref));
+        if !state.dry_run {
+            let status = std::process::Command::new("git")
+                .arg("push")
+                .arg("--delete")
+                .arg(&remote)
+                .arg(&remote_ref)
+                .status();
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(_) => {
+                    failed.push(remote_name);
+                    continue;
+                }
+                Err(err) => {
+                    remote_log!(debug, "`git push` failed with {}", err);
+                    failed.push(remote_name);
+                    continue;
+                }
+            }
+        }
+        remote_log!(info, "Deleted {}", remote_name);
+    }
+
+    // Others may have deleted their merged branches too
+    remote_log!(trace, "git remote prune {}", remote);
+    state
+        .git_commands
+        .show(&format!("git remote prune {}", remote));
+    if !state.dry_run {
+        match std::process::Command::new("git")
+            .arg("remote")
+            .arg("prune")
+            .arg(&remote)
+            .output()
+        {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => {
+                remote_log!(
+                    warn,
+                    "Could not prune {}: {}",
+                    remote,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            Err(err) => {
+                remote_log!(warn, "Could not prune {}: {}", remote, err);
+            }
+        }
+    }
+
+    failed
+}
+
+/// Commits in `stack`'s development branches that aren't on its base yet, newest first
+fn unmerged_commits(state: &State, stack: &StackState) -> Vec<std::rc::Rc<git_stack::git::Commit>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut commits = Vec::new();
+    for branch in git_stack::git::unprotected_branches([&stack.branches], &state.protected_branches)
+    {
+        let merge_base_id = match state.repo.merge_base(stack.base.id, branch.id) {
+            Some(merge_base_id) => merge_base_id,
+            None => {
+                log::warn!("Could not find where {} starts, skipping", branch.name);
+                continue;
+            }
+        };
+        for commit in state
+            .repo
+            .commits_from(branch.id)
+            .take_while(|c| c.id != merge_base_id)
+        {
+            if seen.insert(commit.id) {
+                commits.push(commit);
+            }
+        }
+    }
+    commits
+}
+
+/// Semantic version bump implied by Conventional Commits
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Impact {
+    None,
+    Patch,
+    Minor,
+    Major,
+}
+
+impl Impact {
+    fn new(
+        repo: &git_stack::git::GitRepo,
+        commits: &[std::rc::Rc<git_stack::git::Commit>],
+    ) -> Self {
+        commits
+            .iter()
+            .map(|commit| {
+                let (kind, breaking) = match commit.conventional_type() {
+                    Some((kind, breaking)) => (Some(kind.to_str_lossy().to_lowercase()), breaking),
+                    None => (None, false),
+                };
+                let message = repo
+                    .raw()
+                    .find_commit(commit.id)
+                    .ok()
+                    .map(|c| String::from_utf8_lossy(c.message_bytes()).into_owned())
+                    .unwrap_or_default();
+                if breaking || has_breaking_change_footer(&message) {
+                    Impact::Major
+                } else if kind.as_deref() == Some("feat") {
+                    Impact::Minor
+                } else {
+                    Impact::Patch
+                }
+            })
+            .max()
+            .unwrap_or(Impact::None)
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Impact::None => "none",
+            Impact::Patch => "patch",
+            Impact::Minor => "minor",
+            Impact::Major => "major",
+        }
+    }
+}
+
+impl std::fmt::Display for Impact {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// `BREAKING CHANGE:` / `BREAKING-CHANGE:` footers, per Conventional Commits
+fn has_breaking_change_footer(message: &str) -> bool {
+    message
+        .lines()
+        .skip(1)
+        .any(|line| line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:"))
+}
+
+/// A `Changelog: <section>` trailer, to override the section or `skip` the commit
+fn changelog_trailer(message: &str) -> Option<String> {
+    // Trailers are the last paragraph, never the summary
+    let (_, trailers) = message.trim_end().rsplit_once("\n\n")?;
+    trailers.lines().rev().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case("changelog") {
+            Some(value.trim().to_owned()).filter(|v| !v.is_empty())
+        } else {
+            None
+        }
+    })
+}
+
+fn changelog_section(kind: Option<&str>) -> &'static str {
+    match kind {
+        Some("feat") => "Features",
+        Some("fix") => "Fixes",
+        Some("perf") => "Performance",
+        Some("docs") => "Documentation",
+        _ => "Other",
+    }
+}
+
+/// Known sections first, then custom ones from trailers, then everything else
+fn changelog_section_order(section: &str) -> usize {
+    const KNOWN: &[&str] = &["Features", "Fixes", "Performance", "Documentation"];
+    if section == "Other" {
+        usize::MAX
+    } else {
+        KNOWN
+            .iter()
+            .position(|s| *s == section)
+            .unwrap_or(KNOWN.len())
+    }
+}
+
+pub fn diff(
+    args: &crate::args::Args,
+    diff_args: &crate::args::DiffArgs,
+    colored_stdout: bool,
+) -> proc_exit::ExitResult {
+    if !diff_args.remote {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Nothing to compare, pass `--remote`"));
+    }
+
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
+
+    let palette = if colored_stdout {
+        Palette::colored()
+    } else {
+        Palette::plain()
+    };
+    let development_branches = development_branches(&state);
+    let branches = git_stack::git::unprotected_branches(
+        state.stacks.iter().map(|stack| &stack.branches),
+        &state.protected_branches,
+    );
+
+    let mut stdout = std::io::stdout();
+    for branch in branches {
+        let push_id = match branch.push_id {
+            Some(push_id) => push_id,
+            None => {
+                log::info!("{} has not been pushed", branch.name);
+                continue;
+            }
+        };
+        if push_id == branch.id {
+            log::debug!(
+                "{} is up-to-date with {}",
+                branch.name,
+                state.repo.push_remote()
+            );
+            continue;
+        }
+
+        let protected_base =
+            git_stack::git::find_protected_base(&state.repo, &state.protected_branches, branch.id);
+        let fork_id = protected_base.and_then(|base| state.repo.merge_base(base.id, branch.id));
+        // Only compare the branch's own commits, not those of the branches it is stacked on
+        let (new_base, old_base) =
+            match development_parent(&state, &development_branches, branch.id, fork_id) {
+                Some(parent) => (
+                    Some(parent.id),
+                    state
+                        .repo
+                        .merge_base(push_id, parent.push_id.unwrap_or(parent.id)),
+                ),
+                None => (
+                    fork_id,
+                    protected_base.and_then(|base| {
+                        state
+                            .repo
+                            .merge_base(push_id, base.pull_id.unwrap_or(base.id))
+                    }),
+                ),
+            };
+        let (new_base, old_base) = match (new_base, old_base) {
+            (Some(new_base), Some(old_base)) => (new_base, old_base),
+            _ => {
+                log::warn!("Could not find where {} starts, skipping", branch.name);
+                continue;
+            }
+        };
+
+        writeln!(
+            stdout,
+            "{} {}",
+            palette.highlight.paint(&branch.name),
+            palette.hint.paint(format_args!(
+                "({}..{})",
+                state
+                    .repo
+                    .push_tracking_name(&branch.name)
+                    .unwrap_or_else(|| format!("{}/{}", state.repo.push_remote(), branch.name)),
+                branch.name
+            ))
+        )?;
+        stdout.flush()?;
+        if !git_range_diff(colored_stdout, (old_base, push_id), (new_base, branch.id))
+            .with_code(proc_exit::Code::FAILURE)?
+        {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "Could not compare {} to {}",
+                branch.name,
+                state.repo.push_remote()
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn range_diff(
+    args: &crate::args::Args,
+    range_diff_args: &crate::args::RangeDiffArgs,
+    colored_stdout: bool,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
+
+    // Each rewrite snapshots the branches before it changes them
+    let snapshots = git_stack::stash::Stack::new(STASH_STACK_NAME, &state.repo);
+    let since = range_diff_args.since.max(1);
+    let snapshot_path = snapshots.iter().rev().nth(since - 1).ok_or_else(|| {
+        proc_exit::Code::USAGE_ERR.with_message(format!(
+            "Cannot go back {} rewrites, see `git branch-stash list {}` for what is recorded",
+            since, STASH_STACK_NAME
+        ))
+    })?;
+    log::trace!("Comparing to {}", snapshot_path.display());
+    let snapshot =
+        git_stack::stash::Snapshot::load(&snapshot_path).with_code(proc_exit::Code::FAILURE)?;
+    let before: std::collections::HashMap<_, _> = snapshot
+        .branches
+        .iter()
+        .map(|b| (b.name.as_str(), b))
+        .collect();
+
+    let palette = if colored_stdout {
+        Palette::colored()
+    } else {
+        Palette::plain()
+    };
+    let development_branches = development_branches(&state);
+    let branches = git_stack::git::unprotected_branches(
+        state.stacks.iter().map(|stack| &stack.branches),
+        &state.protected_branches,
+    );
+
+    let mut stdout = std::io::stdout();
+    let mut changed = 0;
+    for branch in branches {
+        let old = match before.get(branch.name.as_str()) {
+            Some(old) => old,
+            None => {
+                log::debug!("{} was created since then", branch.name);
+                continue;
+            }
+        };
+        if old.id == branch.id {
+            continue;
+        }
+        if state.repo.find_commit(old.id).is_none() {
+            log::warn!(
+                "{} was at {} which no longer exists, skipping",
+                branch.name,
+                old.id
+            );
+            continue;
+        }
+
+        let protected_base =
+            git_stack::git::find_protected_base(&state.repo, &state.protected_branches, branch.id);
+        let fork_id = protected_base.and_then(|base| state.repo.merge_base(base.id, branch.id));
+        // Only compare the branch's own commits, not those of the branches it is stacked on
+        let new_base = development_parent(&state, &development_branches, branch.id, fork_id)
+            .map(|parent| parent.id)
+            .or(fork_id);
+        let old_base = old
+            .metadata
+            .get("parent")
+            .and_then(|parent| parent.as_str())
+            .and_then(|parent| before.get(parent))
+            .and_then(|parent| state.repo.merge_base(old.id, parent.id))
+            .or_else(|| {
+                protected_base.and_then(|base| {
+                    state
+                        .repo
+                        .merge_base(old.id, base.pull_id.unwrap_or(base.id))
+                })
+            });
+        let (new_base, old_base) = match (new_base, old_base) {
+            (Some(new_base), Some(old_base)) => (new_base, old_base),
+            _ => {
+                log::warn!("Could not find where {} starts, skipping", branch.name);
+                continue;
+            }
+        };
+
+        if range_diff_args.check {
+            for (old_id, new_id) in
+                content_changes(&state.repo, (old_base, old.id), (new_base, branch.id))
+            {
+                let commit = state.repo.find_commit(new_id).expect("walked above");
+                writeln!(
+                    stdout,
+                    "{}: {} {} {}",
+                    palette.highlight.paint(&branch.name),
+                    palette.warn.paint(format_args!(
+                        "{}..{}",
+                        &old_id.to_string()[..7],
+                        &new_id.to_string()[..7]
+                    )),
+                    palette.hint.paint("changed content"),
+                    commit.summary.to_str_lossy()
+                )?;
+                changed += 1;
+            }
+            continue;
+        }
+
+        writeln!(
+            stdout,
+            "{} {}",
+            palette.highlight.paint(&branch.name),
+            palette.hint.paint(format_args!(
+                "({}..{})",
+                &old.id.to_string()[..7],
+                &branch.id.to_string()[..7]
+            ))
+        )?;
+        stdout.flush()?;
+        if !git_range_diff(colored_stdout, (old_base, old.id), (new_base, branch.id))
+            .with_code(proc_exit::Code::FAILURE)?
+        {
+            return Err(proc_exit::Code::FAILURE
+                .with_message(format!("Could not compare {} to before", branch.name)));
+        }
+    }
+
+    if 0 < changed {
+        return Err(proc_exit::Code::FAILURE.with_message(format!(
+            "{} commits changed content while being restacked",
+            changed
+        )));
+    }
+
+    Ok(())
+}
+
+/// Commits whose patch changed between the `old` and `new` `(base, head)` ranges
+///
+/// Commits are paired up by summary; a restack should only change a commit's parent, so a
+/// different [`content_id`][git_stack::git::GitRepo::content_id] points to a conflict resolution
+/// or other drift.  Unlike a patch-id, it doesn't change when the lines next to the commit's
+/// changes did.
+fn content_changes(
+    repo: &git_stack::git::GitRepo,
+    old: (git2::Oid, git2::Oid),
+    new: (git2::Oid, git2::Oid),
+) -> Vec<(git2::Oid, git2::Oid)> {
+    let mut old_commits: std::collections::HashMap<_, Vec<_>> = Default::default();
+    for commit in repo.commits_from(old.1).take_while(|c| c.id != old.0) {
+        old_commits
+            .entry(commit.summary.clone())
+            .or_default()
+            .push(commit.id);
+    }
+
+    let mut changes = Vec::new();
+    let new_commits: Vec<_> = repo
+        .commits_from(new.1)
+        .take_while(|c| c.id != new.0)
+        .collect();
+    let ids: Vec<_> = old_commits
+        .values()
+        .flatten()
+        .copied()
+        .chain(new_commits.iter().map(|c| c.id))
+        .collect();
+    repo.prefetch_content_ids(&ids);
+    for commit in new_commits.into_iter().rev() {
+        let old_id = match old_commits
+            .get_mut(&commit.summary)
+            .and_then(|ids| ids.pop())
+        {
+            Some(old_id) => old_id,
+            None => continue,
+        };
+        if old_id != commit.id && repo.content_id(old_id) != repo.content_id(commit.id) {
+            changes.push((old_id, commit.id));
+        }
+    }
+    changes
+}
+
+/// `branch.<name>.stack-pr`, the pull request `git stack submit` opened for the branch
+const PR_KEY: &str = "stack-pr";
+
+pub fn submit(
+    args: &crate::args::Args,
+    submit_args: &crate::args::SubmitArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    require_online(&repo_config, "`git stack submit`")?;
+    let mut forge = git_stack::forge::from_config(repo.raw(), &repo_config)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .ok_or_else(|| {
+            proc_exit::Code::CONFIG_ERR.with_message(
+                "`git stack submit` needs `stack.forge` or `stack.forge-command` to be set",
+            )
+        })?;
+    let state = State::with_config(repo, args, repo_config)?;
+
+    let development_branches = development_branches(&state);
+    let branches = git_stack::git::unprotected_branches(
+        state.stacks.iter().map(|stack| &stack.branches),
+        &state.protected_branches,
+    );
+    let mut submissions = Vec::new();
+    for branch in branches {
+        if let Some(number) = pr_number(state.repo.raw(), &branch.name) {
+            log::debug!("{} already has pull request {}", branch.name, number);
+            continue;
+        }
+        let head = match remote_branch_name(&state, branch) {
+            Some(head) => head,
+            None => {
+                log::warn!(
+                    "Skipping {}, it hasn't been pushed (`git stack --push`)",
+                    branch.name
+                );
+                continue;
+            }
+        };
+        let protected_base = match git_stack::git::find_protected_base(
+            &state.repo,
+            &state.protected_branches,
+            branch.id,
+        ) {
+            Some(protected_base) => protected_base,
+            None => {
+                log::warn!("Could not find the base of {}, skipping", branch.name);
+                continue;
+            }
+        };
+        let fork_id = state.repo.merge_base(protected_base.id, branch.id);
+        // Stacked on its parent, so the pull request only shows this layer
+        let (base, base_id) =
+            match development_parent(&state, &development_branches, branch.id, fork_id) {
+                Some(parent) => match remote_branch_name(&state, parent) {
+                    Some(base) => (base, parent.id),
+                    None => {
+                        log::warn!(
+                            "Skipping {}, its parent {} hasn't been pushed (`git stack --push`)",
+                            branch.name,
+                            parent.name
+                        );
+                        continue;
+                    }
+                },
+                None => match fork_id {
+                    Some(fork_id) => (protected_base.local_name().to_owned(), fork_id),
+                    None => {
+                        log::warn!("Could not find where {} starts, skipping", branch.name);
+                        continue;
+                    }
+                },
+            };
+        let commits: Vec<_> = state
+            .repo
+            .commits_from(branch.id)
+            .take_while(|c| c.id != base_id)
+            .collect();
+        let title = match commits.as_slice() {
+            [] => {
+                log::debug!("Skipping {}, it has no commits of its own", branch.name);
+                continue;
+            }
+            [commit] => String::from_utf8_lossy(&commit.summary).into_owned(),
+            _ => branch.name.clone(),
+        };
+        submissions.push(Submission {
+            branch,
+            head,
+            base,
+            base_id,
+            protected_base_id: protected_base.id,
+            commits,
+            title,
+        });
+    }
+
+    // Before opening any, so a stack isn't left half-submitted
+    if let (Some(max), git_stack::config::MaxCommitsAction::Error) =
+        (state.max_commits_per_branch, state.max_commits_action)
+    {
+        let oversized: Vec<_> = submissions
+            .iter()
+            .filter(|s| max < s.commits.len())
+            .map(|s| (s.branch.name.clone(), s.commits.len()))
+            .collect();
+        if !oversized.is_empty() {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "Refusing to submit branches with more than {} commits (`stack.max-commits-per-branch`): {}",
+                max,
+                format_oversized(&oversized)
+            )));
+        }
+    }
+
+    let mut failed = Vec::new();
+    for Submission {
+        branch,
+        head,
+        base,
+        base_id,
+        protected_base_id,
+        commits,
+        title,
+    } in submissions
+    {
+        let draft = commits.iter().any(|c| c.wip_summary().is_some());
+        let body = pr_body(&state, branch, base_id);
+
+        let codeowners = if submit_args.no_assign {
+            None
+        } else {
+            load_codeowners(state.repo.raw(), protected_base_id)
+        };
+        let paths = state.repo.changed_paths(base_id, branch.id);
+        let reviewers = codeowners
+            .as_ref()
+            .map(|codeowners| codeowners.owners_of(paths.iter().map(|p| p.as_str())))
+            .unwrap_or_default();
+
+        if state.dry_run {
+            log::info!(
+                "Would open a pull request for {} onto {}{}",
+                head,
+                base,
+                if reviewers.is_empty() {
+                    String::new()
+                } else {
+                    format!(", for review by {}", reviewers.join(", "))
+                }
+            );
+            continue;
+        }
+        let pr = git_stack::forge::NewPullRequest {
+            head: &head,
+            base: &base,
+            title: &title,
+            body: &body,
+            draft,
+        };
+        let pr = match forge.create_pr(&pr) {
+            Ok(pr) => pr,
+            Err(err) => {
+                log::error!("Could not open a pull request for {}: {}", branch.name, err);
+                failed.push(branch.name.as_str());
+                continue;
+            }
+        };
+        log::info!("{}: {}", branch.name, pr.url);
+        if let Err(err) = set_pr_number(state.repo.raw(), &branch.name, pr.number) {
+            log::warn!("Could not record pull request for {}: {}", branch.name, err);
+        }
+        if !reviewers.is_empty() {
+            match forge.request_reviewers(pr.number, &reviewers) {
+                Ok(requests) => {
+                    if !requests.requested.is_empty() {
+                        log::info!("Requested review from {}", requests.requested.join(", "));
+                    }
+                    for (reviewer, err) in requests.failed {
+                        log::warn!(
+                            "Could not request review from {} for {}: {}",
+                            reviewer,
+                            branch.name,
+                            err
+                        );
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Could not request reviews for {}: {}", branch.name, err);
+                }
+            }
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(proc_exit::Code::FAILURE.with_message(format!(
+            "Could not open pull requests for {}",
+            failed.join(", ")
+        )))
+    }
+}
+
+/// A pull request `git stack submit` is about to open
+struct Submission<'b> {
+    branch: &'b git_stack::git::Branch,
+    head: String,
+    base: String,
+    base_id: git2::Oid,
+    protected_base_id: git2::Oid,
+    /// The branch's own commits
+    commits: Vec<std::rc::Rc<git_stack::git::Commit>>,
+    title: String,
+}
+
+/// How often `git stack land` asks the forge whether the queue merged the pull request
+const LAND_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+pub fn land(
+    args: &crate::args::Args,
+    land_args: &crate::args::LandArgs,
+    colored_stdout: bool,
+    colored_stderr: bool,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let repo_config = git_stack::config::RepoConfig::from_all(repo.raw())
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    require_online(&repo_config, "`git stack land`")?;
+    let mut forge = git_stack::forge::from_config(repo.raw(), &repo_config)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .ok_or_else(|| {
+            proc_exit::Code::CONFIG_ERR.with_message(
+                "`git stack land` needs `stack.forge` or `stack.forge-command` to be set",
+            )
+        })?;
+    let state = State::with_config(repo, args, repo_config)?;
+
+    let development_branches = development_branches(&state);
+    let name = match land_args.branch.as_deref() {
+        Some(name) => name.to_owned(),
+        None => state.repo.head_branch().map(|b| b.name).ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR
+                .with_message("Must not be in a detached HEAD state, or pass the branch to land.")
+        })?,
+    };
+    let mut bottom = development_branches
+        .iter()
+        .flat_map(|(_, b)| b)
+        .find(|b| b.name == name)
+        .ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR
+                .with_message(format!("`{}` is not a development branch", name))
+        })?;
+    let protected_base =
+        git_stack::git::find_protected_base(&state.repo, &state.protected_branches, bottom.id)
+            .ok_or_else(|| {
+                proc_exit::Code::USAGE_ERR
+                    .with_message(format!("Could not find the base of {}", name))
+            })?;
+    let fork_id = state.repo.merge_base(protected_base.id, bottom.id);
+    while let Some(parent) = development_parent(&state, &development_branches, bottom.id, fork_id) {
+        bottom = parent;
+    }
+    let number = pr_number(state.repo.raw(), &bottom.name).ok_or_else(|| {
+        proc_exit::Code::USAGE_ERR.with_message(format!(
+            "{} has no pull request, open one with `git stack submit`",
+            bottom.name
+        ))
+    })?;
+    // Once it's merged, what was stacked on it goes straight onto the base
+    let stacked: Vec<_> = development_branches
+        .iter()
+        .flat_map(|(_, b)| b)
+        .filter(|b| {
+            b.id != bottom.id
+                && development_parent(&state, &development_branches, b.id, fork_id)
+                    .map(|p| p.name == bottom.name)
+                    .unwrap_or(false)
+        })
+        .filter_map(|b| pr_number(state.repo.raw(), &b.name).map(|n| (b.name.clone(), n)))
+        .collect();
+    let base = protected_base.local_name().to_owned();
+
+    if state.dry_run {
+        log::info!(
+            "Would add {} (#{}) to the merge queue, then restack onto {}",
+            bottom.name,
+            number,
+            base
+        );
+        return Ok(());
+    }
+    forge.enqueue(number).map_err(|err| {
+        proc_exit::Code::FAILURE.with_message(format!(
+            "Could not add {} (#{}) to the merge queue: {}",
+            bottom.name, number, err
+        ))
+    })?;
+    log::info!(
+        "Added {} (#{}) to the merge queue, waiting up to {} for it to merge",
+        bottom.name,
+        number,
+        humantime::format_duration(land_args.wait)
+    );
+    let merged = git_stack::forge::wait_for_merge(
+        forge.as_mut(),
+        number,
+        land_args.wait,
+        LAND_POLL_INTERVAL,
+        &state.cancel,
+    )
+    .with_code(proc_exit::Code::FAILURE)?;
+    match merged {
+        git_stack::forge::PullRequestState::Merged => {
+            log::info!("{} (#{}) merged", bottom.name, number);
+        }
+        git_stack::forge::PullRequestState::Queued => {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "{} (#{}) is still in the merge queue after {}",
+                bottom.name,
+                number,
+                humantime::format_duration(land_args.wait)
+            )));
+        }
+        git_stack::forge::PullRequestState::Open => {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "{} (#{}) was removed from the merge queue without merging",
+                bottom.name, number
+            )));
+        }
+        git_stack::forge::PullRequestState::Closed => {
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "{} (#{}) was closed without merging",
+                bottom.name, number
+            )));
+        }
+    }
+    for (branch, number) in stacked {
+        log::debug!("Retargeting {} (#{}) onto {}", branch, number, base);
+        if let Err(err) = forge.update_base(number, &base) {
+            log::warn!(
+                "Could not retarget {} (#{}) onto {}: {}",
+                branch,
+                number,
+                base,
+                err
+            );
+        }
+    }
+    drop(state);
+
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut state = State::new(repo, args)?;
+    state.rebase = true;
+    state.pull = true;
+    apply(state, colored_stdout, colored_stderr)
+}
+
+/// The name `branch` was pushed as, if it has been
+fn remote_branch_name(state: &State, branch: &git_stack::git::Branch) -> Option<String> {
+    branch.push_id?;
+    let remote_ref = state.repo.push_remote_ref(&branch.name)?;
+    remote_ref.strip_prefix("refs/heads/").map(|n| n.to_owned())
+}
+
+/// The pull request `git stack submit` opened for `branch`
+fn pr_number(repo: &git2::Repository, branch: &str) -> Option<u64> {
+    let config = repo.config().ok()?;
+    let number = config
+        .get_i64(&format!("branch.{}.{}", branch, PR_KEY))
+        .ok()?;
+    std::convert::TryFrom::try_from(number).ok()
+}
+
+fn set_pr_number(repo: &git2::Repository, branch: &str, number: u64) -> Result<(), git2::Error> {
+    let mut config = repo.config()?;
+    config.set_i64(&format!("branch.{}.{}", branch, PR_KEY), number as i64)
+}
+
+/// `CODEOWNERS` as of `id`, from wherever the forge looks for it
+fn load_codeowners(repo: &git2::Repository, id: git2::Oid) -> Option<git_stack::forge::Codeowners> {
+    let tree = repo.find_commit(id).ok()?.tree().ok()?;
+    let blob = git_stack::forge::CODEOWNERS_PATHS.iter().find_map(|path| {
+        let entry = tree.get_path(std::path::Path::new(path)).ok()?;
+        entry.to_object(repo).ok()?.into_blob().ok()
+    })?;
+    Some(git_stack::forge::Codeowners::parse(
+        &String::from_utf8_lossy(blob.content()),
+    ))
+}
+
+/// The stacks and their branches, for `git stack serve`
+pub fn query_stacks(args: &crate::args::Args) -> Result<serde_json::Value, proc_exit::Exit> {
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let state = State::new(repo, args)?;
+
+    let development_branches = development_branches(&state);
+    let stacks: Vec<_> = state
+        .stacks
+        .iter()
+        .map(|stack| {
+            let branches: Vec<_> = stack
+                .branches
+                .iter()
+                .flat_map(|(_, b)| b)
+                .filter(|b| !is_protected(&state.protected_branches, b))
+                .map(|branch| {
+                    let summary = state
+                        .repo
+                        .find_commit(branch.id)
+                        .map(|c| c.summary.to_str_lossy().into_owned());
+                    let parent =
+                        git_stack::git::find_base(&state.repo, &development_branches, branch.id)
+                            .or_else(|| {
+                                git_stack::git::find_protected_base(
+                                    &state.repo,
+                                    &state.protected_branches,
+                                    branch.id,
+                                )
+                            });
+                    let base_id =
+                        parent.and_then(|parent| state.repo.merge_base(parent.id, branch.id));
+                    let commit_types = base_id
+                        .map(|base_id| CommitTypes::new(&state.repo, branch.id, base_id).to_json());
+                    let review = base_id.map(|base_id| {
+                        ReviewCoverage::new(&state.repo, branch.id, base_id).to_json()
+                    });
+                    serde_json::json!({
+                        "name": branch.name,
+                        "id": branch.id.to_string(),
+                        "summary": summary,
+                        "parent": parent.map(|parent| parent.name.clone()),
+                        "push_id": branch.push_id.map(|id| id.to_string()),
+                        "commit_types": commit_types,
+                        "review": review,
+                    })
+                })
+                .collect();
+            let impact = Impact::new(&state.repo, &unmerged_commits(&state, stack));
+            serde_json::json!({
+                "base": stack.base.name,
+                "onto": stack.onto.name,
+                "branches": branches,
+                "impact": impact.as_str(),
+            })
+        })
+        .collect();
+
+    Ok(serde_json::json!({
+        "head": state.repo.head_branch().map(|b| b.name),
+        "stacks": stacks,
+    }))
+}
+
+/// What a rewrite would do, for `git stack serve`
+pub fn query_plan(
+    args: &crate::args::Args,
+    rebase: bool,
+    fixup: Option<git_stack::config::Fixup>,
+) -> Result<serde_json::Value, proc_exit::Exit> {
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git_stack::git::GitRepo::new(repo);
+    let mut state = State::new(repo, args)?;
+    state.rebase = rebase;
+    if let Some(fixup) = fixup {
+        state.fixup = fixup;
+    }
+
+    let mut head_branch = attached_head_branch(&state.repo)
+        .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
+        .with_code(proc_exit::Code::USAGE_ERR)?
+        .name;
+    let (_, summary) = plan_rewrite(&state, &mut head_branch)?;
+    Ok(summary.to_json())
+}
+
+fn apply(mut state: State, colored_stdout: bool, colored_stderr: bool) -> proc_exit::ExitResult {
+    let origin = crate::jumps::Origin::new(&state.repo, "git stack");
+    let mut rewritten = Rewrite::default();
+    let result = apply_changes(&mut state, &mut rewritten, colored_stdout, colored_stderr);
+    origin.restore(&mut state.repo, &state.git_commands, state.dry_run);
+    if rewritten.stash_id.is_some() {
+        state.git_commands.show("git stash pop");
+    }
+    git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id.take());
+    result
+}
+
+/// Pull, rewrite, and push, leaving `rewritten`'s stash for the caller
+fn apply_changes(
+    state: &mut State,
+    rewritten: &mut Rewrite,
+    colored_stdout: bool,
+    colored_stderr: bool,
+) -> proc_exit::ExitResult {
+    let rewriting = state.rebase || state.fixup != git_stack::config::Fixup::Ignore || state.repair;
+    if rewriting && !state.dry_run {
+        require_workdir(state)?;
+    }
+    if state.rebase && !state.pull && state.fresh_base != git_stack::config::FreshBase::Ignore {
+        let stale = stale_bases(state);
+        if !stale.is_empty() {
+            let stale = stale.join(", ");
+            match state.fresh_base {
+                git_stack::config::FreshBase::Ignore => unreachable!("checked above"),
+                git_stack::config::FreshBase::Pull => {
+                    log::info!("Pulling out-of-date bases: {}", stale);
+                    state.pull = true;
+                }
+                git_stack::config::FreshBase::Warn => {
+                    log::warn!("Rebasing onto out-of-date bases: {}", stale);
+                }
+                git_stack::config::FreshBase::Error => {
+                    return Err(proc_exit::Code::FAILURE.with_message(format!(
+                        "Bases are out-of-date, run with `--pull`: {}",
+                        stale
+                    )));
+                }
+            }
+        }
+    }
+
+    if state.pull {
+        pull(state)?;
+    }
+
+    let mut success = true;
+    if rewriting {
+        if state.cancel.is_cancelled() {
+            return Err(crate::cancel::exit(
+                &state.cancel,
+                state.timeout,
+                "stopping before restacking",
+            ));
+        }
+        *rewritten = rewrite(state)?;
+        success &= rewritten.failures.is_empty();
+
+        let deleted = rewritten.summary.deleted_branches.clone();
+        let failed = delete_remote_branches(state, &deleted);
+        if !failed.is_empty() {
+            log::error!("Could not delete {}", failed.join(", "));
+            success = false;
+        }
+    }
+
+    let mut pushed = None;
+    if state.push {
+        if state.cancel.is_cancelled() {
+            return Err(crate::cancel::exit(
+                &state.cancel,
+                state.timeout,
+                "stopping before pushing",
+            ));
+        }
+        let mut attempt = 0;
+        loop {
+            match push(state, &Default::default()) {
+                Ok(summary) => {
+                    pushed = Some(summary);
+                    break;
+                }
+                // Only retry when the remote moved on, as re-pulling can catch up with that
+                Err(err)
+                    if err.downcast_ref::<PushRejected>().is_some()
+                        && attempt < state.push_retries
+                        && !state.dry_run
+                        && !state.cancel.is_cancelled() =>
+                {
+                    attempt += 1;
+                    let delay = retry_delay(attempt);
+                    log::warn!(
+                        "{}, retrying ({}/{}) in {}",
+                        err,
+                        attempt,
+                        state.push_retries,
+                        humantime::format_duration(delay)
+                    );
+                    std::thread::sleep(delay);
+
+                    state.update().with_code(proc_exit::Code::FAILURE)?;
+                    pull(state)?;
+                    let retried = rewrite(state)?;
+                    let failed = !retried.failures.is_empty();
+                    rewritten.merge(retried);
+                    if failed {
+                        success = false;
+                        log::warn!("Not retrying push, restacking failed");
+                        break;
+                    }
+                }
+                Err(err) => {
+                    return Err(err).with_code(proc_exit::Code::FAILURE);
+                }
+            }
+        }
+        // Not part of `push`, a CI failure isn't something pulling again would fix
+        if let (Some(ci), Some(summary)) = (state.ci.as_mut(), pushed.as_ref()) {
+            let branches: Vec<_> = summary
+                .pushed_branches
+                .iter()
+                .chain(summary.forced_branches.iter())
+                .cloned()
+                .collect();
+            let res = ci.run(
+                &state.repo,
+                state.progress,
+                &state.cancel,
+                &branches,
+                state.dry_run,
+            );
+            if let Err(err) = res {
+                log::error!("{}", err);
+                success = false;
+            }
+        }
+        state.update().with_code(proc_exit::Code::FAILURE)?;
+    }
+
+    if state.check {
+        let mut pending = Vec::new();
+        if !rewritten.summary.is_empty() {
+            pending.push(rewritten.summary.to_string());
+        }
+        if let Some(pushed) = pushed.filter(|p| !p.is_empty()) {
+            pending.push(pushed.to_string());
+        }
+        if pending.is_empty() {
+            log::info!("All stacks are up-to-date");
+            return Ok(());
+        } else {
+            log::info!("{}", pending.concat().trim_end());
+            return proc_exit::Code::FAILURE.ok();
+        }
+    }
+
+    show(state, colored_stdout, colored_stderr).with_code(proc_exit::Code::FAILURE)?;
+
+    let palette_stderr = if colored_stderr {
+        Palette::colored()
+    } else {
+        Palette::plain()
+    };
+    show_summary(state, rewritten, pushed.as_ref(), &palette_stderr);
+    show_failures(rewritten, &palette_stderr);
+
+    if !success {
+        return proc_exit::Code::FAILURE.ok();
+    }
+
+    Ok(())
+}
+
+pub(crate) const STASH_STACK_NAME: &str = "git-stack";
+
+/// Every failure to restack in one place, as the errors scroll past in a big batch
+fn show_failures(rewritten: &Rewrite, palette: &Palette) {
+    if rewritten.failures.len() < 2 && rewritten.skipped_branches.is_empty() {
+        return;
+    }
+    let mut message = "Failed to restack:\n".to_owned();
+    for failure in rewritten.failures.iter() {
+        message.push_str(&format!(
+            "  {}: {}\n",
+            failure.branch,
+            failure.message.trim_end().replace('\n', "\n  ")
+        ));
+        if !failure.blocked.is_empty() {
+            message.push_str(&format!("    Blocked: {}\n", failure.blocked.join(", ")));
+        }
+    }
+    if !rewritten.skipped_branches.is_empty() {
+        message.push_str(&format!(
+            "Stopped at the first failure, leaving: {}\n",
+            rewritten.skipped_branches.join(", ")
+        ));
+    }
+    log::error!("{}", message.trim_end());
+    if !rewritten.skipped_branches.is_empty() {
+        log::info!(
+            "{}",
+            palette
+                .hint
+                .paint("To restack the other stacks anyway, run with `--keep-going`")
+        );
+    }
+}
+
+/// What was done, per `stack.summary`, and how to undo it
+fn show_summary(state: &State, rewritten: &Rewrite, pushed: Option<&Summary>, palette: &Palette) {
+    let short_id = |id: git2::Oid| id.to_string()[..7].to_owned();
+    let dropped: Vec<_> = rewritten
+        .summary
+        .dropped_commits
+        .iter()
+        .chain(rewritten.emptied_commits.iter())
+        .collect();
+    let pushed: Vec<_> = pushed
+        .map(|summary| {
+            summary
+                .pushed_branches
+                .iter()
+                .cloned()
+                .chain(
+                    summary
+                        .forced_branches
+                        .iter()
+                        .map(|b| format!("{} (forced)", b)),
+                )
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // Nothing was done to summarize
+    let summary = if state.dry_run {
+        git_stack::config::Summary::None
+    } else {
+        state.summary
+    };
+    match summary {
+        git_stack::config::Summary::None => {}
+        git_stack::config::Summary::Short => {
+            let mut parts = Vec::new();
+            if !rewritten.moved_branches.is_empty() {
+                let names = rewritten
+                    .moved_branches
+                    .iter()
+                    .map(|(name, _, _)| name)
+                    .join(", ");
+                parts.push(format!("Restacked {}", names));
+            }
+            if !rewritten.squashed_commits.is_empty() {
+                parts.push(format!(
+                    "squashed {} commits",
+                    rewritten.squashed_commits.len()
+                ));
+            }
+            if !dropped.is_empty() {
+                parts.push(format!("dropped {} commits", dropped.len()));
+            }
+            if !rewritten.whitespace_resolved.is_empty() {
+                parts.push(format!(
+                    "resolved whitespace-only conflicts in {} commits",
+                    rewritten.whitespace_resolved.len()
+                ));
+            }
+            if !pushed.is_empty() {
+                parts.push(format!("pushed {}", pushed.join(", ")));
+            }
+            if !parts.is_empty() {
+                let mut line = parts.join("; ");
+                line[..1].make_ascii_uppercase();
+                log::info!("{}", line);
+            }
+        }
+        git_stack::config::Summary::Full => {
+            let mut message = String::new();
+            if !rewritten.moved_branches.is_empty() {
+                message.push_str("Restacked:\n");
+                for (name, old_id, id) in rewritten.moved_branches.iter() {
+                    let old_id = old_id.map(short_id).unwrap_or_else(|| "(new)".to_owned());
+                    message.push_str(&format!("  {} {} -> {}\n", name, old_id, short_id(*id)));
+                }
+            }
+            for (title, commits) in [
+                (
+                    "Squashed",
+                    rewritten.squashed_commits.iter().collect::<Vec<_>>(),
+                ),
+                ("Dropped", dropped),
+                (
+                    "Resolved whitespace-only conflicts",
+                    rewritten.whitespace_resolved.iter().collect(),
+                ),
+            ] {
+                if !commits.is_empty() {
+                    message.push_str(&format!("{}:\n", title));
+                    for commit in commits {
+                        message.push_str(&format!(
+                            "  {} {}\n",
+                            short_id(commit.id),
+                            commit.summary.to_str_lossy()
+                        ));
+                    }
+                }
+            }
+            if !pushed.is_empty() {
+                message.push_str("Pushed:\n");
+                for branch in pushed.iter() {
+                    message.push_str(&format!("  {}\n", branch));
+                }
+            }
+            if let Some(snapshot) = rewritten.snapshot.as_deref() {
+                message.push_str(&format!("Backed up to {}\n", snapshot.display()));
+            }
+            if !message.is_empty() {
+                log::info!("{}", message.trim_end());
+            }
+        }
+    }
+
+    if rewritten.backed_up {
+        log::info!(
+            "{}",
+            palette.hint.paint(format_args!(
+                "To undo, run `git branch-stash pop {}`",
+                STASH_STACK_NAME
+            ))
+        );
+    }
+}
+
+/// Exponential back-off from 1s, capped at a minute, with up to 50% jitter so racing jobs
+/// spread out
+fn retry_delay(attempt: usize) -> std::time::Duration {
+    let base = std::time::Duration::from_secs(1 << attempt.saturating_sub(1).min(6))
+        .min(std::time::Duration::from_secs(60));
+    // Good enough randomness without another dependency
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    let jitter = base.mul_f64(f64::from(nanos % 1000) / 2000.0);
+    base + jitter
+}
+
+/// Fetch the bases and the remote state of our branches
+fn pull(state: &mut State) -> proc_exit::ExitResult {
+    // Update status of remote unprotected branches
+    let mut push_branches: Vec<_> = state
+        .stacks
+        .iter()
+        .flat_map(|stack| stack.branches.iter())
+        .filter(|(oid, _)| !state.protected_branches.contains_oid(*oid))
+        .flat_map(|(_, b)| b.iter())
+        .filter_map(|b| b.push_id.map(|_| b.name.as_str()))
+        .collect();
+    push_branches.sort_unstable();
+    if !push_branches.is_empty() {
+        match git_prune_development(
+            &mut state.repo,
+            state.git_commands,
+            &push_branches,
+            state.dry_run,
+        ) {
+            Ok(_) => (),
+            Err(err) => {
+                remote_log!(
+                    warn,
+                    "Skipping fetch of `{}`, {}",
+                    state.repo.push_remote(),
+                    err
+                );
+            }
+        }
+    }
+
+    for stack in state.stacks.iter() {
+        if state.protected_branches.contains_oid(stack.onto.id) {
+            match git_fetch_upstream(&mut state.repo, state.git_commands, stack.onto.local_name()) {
+                Ok(_) => (),
+                Err(err) => {
+                    remote_log!(warn, "Skipping pull of `{}`, {}", stack.onto.name, err);
+                }
+            }
+        } else {
+            remote_log!(
+                warn,
+                "Skipping pull of `{}`, not a protected branch",
+                stack.onto.name
+            );
+        }
+    }
+    state.update().with_code(proc_exit::Code::FAILURE)?;
+
+    Ok(())
+}
+
+/// The outcome of [`rewrite`]
+#[derive(Default)]
+struct Rewrite {
+    /// Uncommitted changes to restore once we are done
+    stash_id: Option<git2::Oid>,
+    backed_up: bool,
+    /// The `branch-stash` backup of the branches from before
+    snapshot: Option<std::path::PathBuf>,
+    summary: Summary,
+    failures: Vec<crate::conflict::RestackFailure>,
+    /// Branches not restacked because of an earlier failure, without `--keep-going`
+    skipped_branches: Vec<String>,
+    /// Branches that were moved, with where they used to point
+    moved_branches: Vec<(String, Option<git2::Oid>, git2::Oid)>,
+    squashed_commits: Vec<std::rc::Rc<git_stack::git::Commit>>,
+    /// Commits dropped while rewriting because they became empty
+    emptied_commits: Vec<std::rc::Rc<git_stack::git::Commit>>,
+    /// Commits whose conflicts were only whitespace, per `core.whitespace`
+    whitespace_resolved: Vec<std::rc::Rc<git_stack::git::Commit>>,
+}
+
+impl Rewrite {
+    /// Fold in a later rewrite of the same stacks
+    fn merge(&mut self, other: Rewrite) {
+        self.stash_id = self.stash_id.or(other.stash_id);
+        self.backed_up |= other.backed_up;
+        self.snapshot = self.snapshot.take().or(other.snapshot);
+        self.summary
+            .dropped_commits
+            .extend(other.summary.dropped_commits);
+        self.failures.extend(other.failures);
+        self.skipped_branches.extend(other.skipped_branches);
+        for (name, old_id, id) in other.moved_branches {
+            match self.moved_branches.iter_mut().find(|(n, _, _)| *n == name) {
+                Some(moved) => moved.2 = id,
+                None => self.moved_branches.push((name, old_id, id)),
+            }
+        }
+        self.squashed_commits.extend(other.squashed_commits);
+        self.emptied_commits.extend(other.emptied_commits);
+        self.whitespace_resolved.extend(other.whitespace_resolved);
+    }
+}
+
+/// Rewriting goes through the working tree, so fail before doing anything else
+fn require_workdir(state: &State) -> Result<(), proc_exit::Exit> {
+    if state.repo.raw().is_bare() {
+        let message = "Restacking needs a working tree, which bare repositories don't have";
+        if state.dry_run {
+            log::error!("{}", message);
+        } else {
+            return Err(proc_exit::Code::USAGE_ERR.with_message(message));
+        }
+    }
+    Ok(())
+}
+
+/// `stack.commit-template`, for recording where generated commits came from
+pub(crate) fn commit_template(
+    repo: &git2::Repository,
+    repo_config: &git_stack::config::RepoConfig,
+) -> Result<Option<git_stack::git::CommitTemplate>, proc_exit::Exit> {
+    repo_config
+        .commit_template()
+        .map(|path| {
+            git_stack::git::CommitTemplate::load(repo, path).map_err(|err| {
+                proc_exit::Code::CONFIG_ERR.with_message(format!(
+                    "Invalid `stack.commit-template`: {}",
+                    err.message()
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// The branch HEAD is attached to
+///
+/// A detached HEAD is reported as a branch named `HEAD`, which must never be rewritten or restored.
+pub(crate) fn attached_head_branch(
+    repo: &git_stack::git::GitRepo,
+) -> Option<git_stack::git::Branch> {
+    repo.head_branch()
+        .filter(|b| repo.find_local_branch(&b.name).is_some())
+}
+
+/// Rebase, fixup, and repair the stacks, per `state`
+fn rewrite(state: &mut State) -> Result<Rewrite, proc_exit::Exit> {
+    require_workdir(state)?;
+    let journal_path = git_stack::git::Journal::path(state.repo.raw());
+    if journal_path.exists() {
+        let message =
+            "A previous `git stack` was interrupted while moving branches, run `git stack recover`";
+        if state.dry_run {
+            log::error!("{}", message);
+        } else {
+            return Err(proc_exit::Code::USAGE_ERR.with_message(message));
+        }
+    }
+
+    let mut head_branch = attached_head_branch(&state.repo)
+        .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
+        .with_code(proc_exit::Code::USAGE_ERR)?
+        .name;
+
+    let mut rewritten = Rewrite::default();
+    if !state.dry_run {
+        rewritten.stash_id = git_stack::git::stash_push(&mut state.repo, "branch-stash");
+        if rewritten.stash_id.is_some() {
+            state.git_commands.show("git stash push");
+        }
+    }
+    if state.repo.is_dirty() {
+        let message = "Working tree is dirty, aborting";
+        if state.dry_run {
+            log::error!("{}", message);
+        } else {
+            git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id);
+            return Err(proc_exit::Code::USAGE_ERR.with_message(message));
+        }
+    }
+
+    let (scripts, summary) = plan_rewrite(state, &mut head_branch)?;
+
+    let published = published_commits(state, &scripts);
+    if !published.is_empty() {
+        let mut message =
+            "Refusing to rewrite commits that are already on protected remote branches:".to_owned();
+        for (commit, remote_branch) in published {
+            message.push_str(&format!(
+                "\n  {} {} (on {})",
+                &commit.id.to_string()[..7],
+                commit.summary.to_str_lossy(),
+                remote_branch
+            ));
+        }
+        message.push_str(
+            "\nThey were likely merged while you were working; run `git stack --pull --repair` to restack on top of them",
+        );
+        if state.dry_run {
+            log::error!("{}", message);
+        } else {
+            git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id);
+            return Err(proc_exit::Code::FAILURE.with_message(message));
+        }
+    }
+
+    if !summary.skipped_branches.is_empty() {
+        let skipped = format_skipped(&summary.skipped_branches);
+        match state.protection_action {
+            git_stack::config::ProtectionAction::Skip => unreachable!("not tracked when skipping"),
+            git_stack::config::ProtectionAction::Warn => {
+                log::warn!("Skipping protected branches: {}", skipped);
+            }
+            git_stack::config::ProtectionAction::Error => {
+                let message = format!(
+                    "Refusing to skip protected branches: {}\nSet `stack.protection-action=warn` to skip them anyway",
+                    skipped
+                );
+                if state.dry_run {
+                    log::error!("{}", message);
+                } else {
+                    git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id);
+                    return Err(proc_exit::Code::FAILURE.with_message(message));
+                }
+            }
+        }
+    }
+
+    if !summary.is_empty() {
+        state.progress.emit("plan", summary.to_json());
+    }
+    let replay_count: usize = scripts.iter().map(|s| s.replay_count()).sum();
+    let too_large = state
+        .max_rewrite_commits
+        .map(|max_rewrite_commits| max_rewrite_commits < replay_count)
+        .unwrap_or(false);
+    if !summary.orphaned_tags.is_empty() {
+        log::warn!(
+            "Rewriting will leave behind checkpoints: {}",
+            summary.orphaned_tags.join(", ")
+        );
+    }
+    warn_raw_lfs_files(state, &scripts);
+    if (too_large
+        || !summary.orphaned_tags.is_empty()
+        || needs_confirmation(state.confirm, &summary))
+        && !state.yes
+        && !state.dry_run
+    {
+        let mut prompt = summary.to_string();
+        if too_large {
+            prompt.push_str(&format!(
+                "Rewrite {} commits (`stack.max-rewrite-commits` is {})?",
+                replay_count,
+                state.max_rewrite_commits.unwrap_or(0)
+            ));
+        } else {
+            prompt.push_str("Continue?");
+        }
+        if !confirm(&prompt, state.interactive).with_code(proc_exit::Code::FAILURE)? {
+            git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id);
+            return Err(
+                proc_exit::Code::FAILURE.with_message("Aborting, run with `--yes` to proceed")
+            );
+        }
+    }
+    rewritten.summary = summary;
+
+    let mut snapshots = git_stack::stash::Stack::new(STASH_STACK_NAME, &state.repo);
+    snapshots.capacity(state.snapshot_capacity);
+    let mut snapshot =
+        git_stack::stash::Snapshot::from_repo(&state.repo).with_code(proc_exit::Code::FAILURE)?;
+    snapshot.insert_parent(&state.repo, &state.branches, &state.protected_branches);
+    let mut snapshot_path = None;
+    if !state.dry_run {
+        snapshot_path = Some(snapshots.push(snapshot)?);
+        rewritten.backed_up = true;
+        rewritten.snapshot = snapshot_path.clone();
+    }
+
+    let whitespace_resolved_start = state.repo.whitespace_resolved().len();
+    let mut executor = git_stack::git::Executor::new(&state.repo, state.dry_run);
+    executor.journal(journal_path.clone(), snapshot_path);
+    executor.keep_going(state.keep_going);
+    executor.cancel_on(state.cancel.clone());
+    // Until the branches are updated, so a signal can't leave them half moved
+    let _signals = crate::cancel::CatchSignals::new(&state.cancel);
+    for (stack, script) in state.stacks.iter().zip(scripts) {
+        let picks_start = executor.failed_picks().len();
+        let results = executor.run_script(&mut state.repo, &script);
+        if executor.cancelled() {
+            break;
+        }
+        let failed_picks = &executor.failed_picks()[picks_start..];
+        for (err, name, dependents) in results.iter() {
+            state.progress.emit(
+                "restack",
+                serde_json::json!({
+                    "branch": name,
+                    "status": "failed",
+                    "message": err.message(),
+                    "blocked": dependents,
+                }),
+            );
+            log::error!("Failed to re-stack branch `{}`: {}", name, err);
+            if !dependents.is_empty() {
+                log::error!("  Blocked dependents: {}", dependents.iter().join(", "));
+            }
+            let mut failure = crate::conflict::RestackFailure::new(name, err, dependents);
+            if let Some(pick) = failed_picks.iter().find(|p| p.branch == *name) {
+                let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
+                failure = failure.with_conflict(&state.repo, pick, onto_id);
+            }
+            rewritten.failures.push(failure);
+        }
+    }
+    for name in executor.skipped_branches() {
+        state.progress.emit(
+            "restack",
+            serde_json::json!({
+                "branch": name,
+                "status": "skipped",
+            }),
+        );
+    }
+    if executor.cancelled() {
+        executor.abandon(&state.repo);
+        executor
+            .close(&mut state.repo, &head_branch)
+            .with_code(proc_exit::Code::FAILURE)?;
+        git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id);
+        return Err(crate::cancel::exit(
+            &state.cancel,
+            state.timeout,
+            "no branches were changed",
+        ));
+    }
+    rewritten.skipped_branches = executor.skipped_branches().to_vec();
+    // Leaving out commits of branches that failed later on
+    rewritten.whitespace_resolved = state.repo.whitespace_resolved()[whitespace_resolved_start..]
+        .iter()
+        .filter(|id| executor.rewrites().iter().any(|(old_id, _)| old_id == *id))
+        .filter_map(|id| state.repo.find_commit(*id))
+        .collect();
+    // Otherwise, reported in the summary
+    if state.summary == git_stack::config::Summary::None
+        && !rewritten.whitespace_resolved.is_empty()
+    {
+        let mut message = "Resolved whitespace-only conflicts, per `core.whitespace`:".to_owned();
+        for commit in rewritten.whitespace_resolved.iter() {
+            message.push_str(&format!(
+                "\n  {} {}",
+                &commit.id.to_string()[..7],
+                commit.summary.to_str_lossy()
+            ));
+        }
+        log::info!("{}", message);
+    }
+    if state.summary == git_stack::config::Summary::None && !executor.emptied_commits().is_empty() {
+        let mut message = "Dropped commits that are already applied:".to_owned();
+        for commit in executor.emptied_commits() {
+            message.push_str(&format!(
+                "\n  {} {}",
+                &commit.id.to_string()[..7],
+                commit.summary.to_str_lossy()
+            ));
+        }
+        log::info!("{}", message);
+    }
+    if state.verify {
+        if let Err(err) = executor.verify(&state.repo) {
+            executor.abandon(&state.repo);
+            executor
+                .close(&mut state.repo, &head_branch)
+                .with_code(proc_exit::Code::FAILURE)?;
+            git_stack::git::stash_pop(&mut state.repo, rewritten.stash_id);
+            return Err(proc_exit::Code::FAILURE.with_message(format!(
+                "Aborting, {}; no branches were changed",
+                err.message()
+            )));
+        }
+    }
+    let moved_branches: Vec<_> = executor
+        .moved_branches()
+        .into_iter()
+        .map(|(name, old_id, id)| (name.to_owned(), old_id, id))
+        .collect();
+    if let Err(err) = executor.close(&mut state.repo, &head_branch) {
+        let mut message = format!("Could not update branches: {}", err.message());
+        if journal_path.exists() {
+            message.push_str("\nTo finish (or `--rollback`) the update, run `git stack recover`");
+        }
+        return Err(proc_exit::Code::FAILURE.with_message(message));
+    }
+    crate::pin::carry_pins(state.repo.raw(), executor.rewrites());
+    rewritten.moved_branches = moved_branches;
+    rewritten.squashed_commits = executor.squashed_commits().to_vec();
+    rewritten.emptied_commits = executor.emptied_commits().to_vec();
+    if state.usage_stats && !state.dry_run {
+        let attempted = rewritten.moved_branches.len() + rewritten.failures.len();
+        // Rather than e.g. `--exec` failing
+        let conflicts = rewritten
+            .failures
+            .iter()
+            .filter(|f| f.commit.is_some())
+            .count();
+        if attempted != 0 {
+            crate::usage::record_restacks(state.repo.raw(), attempted, conflicts);
+        }
+    }
+    for command in executor.git_commands() {
+        state.git_commands.show(command);
+    }
+    state.git_commands.show_rebase_todo(executor.rebase_todo());
+    state.update().with_code(proc_exit::Code::FAILURE)?;
+
+    if let Some(path) = state.conflict_report.as_deref() {
+        if !rewritten.failures.is_empty() {
+            crate::conflict::ConflictReport::new(&rewritten.failures)
+                .write(path)
+                .with_code(proc_exit::Code::FAILURE)?;
+            log::info!("Wrote conflict report to {}", path.display());
+        }
+    }
+
+    Ok(rewritten)
+}
+/// What we are about to do, for the user to review
+#[derive(Default)]
+struct Summary {
+    moved_branches: Vec<String>,
+    deleted_branches: Vec<String>,
+    dropped_commits: Vec<std::rc::Rc<git_stack::git::Commit>>,
+    pushed_branches: Vec<String>,
+    forced_branches: Vec<String>,
+    /// Checkpoint tags that will no longer be part of their stack
+    orphaned_tags: Vec<String>,
+    /// Branches left alone because they are implicitly protected, with why
+    skipped_branches: Vec<(String, String)>,
+}
+
+impl Summary {
+    fn is_empty(&self) -> bool {
+        self.moved_branches.is_empty() && self.pushed_branches.is_empty() && !self.is_destructive()
+    }
+
+    fn is_destructive(&self) -> bool {
+        !self.deleted_branches.is_empty()
+            || !self.dropped_commits.is_empty()
+            || !self.forced_branches.is_empty()
+            || !self.orphaned_tags.is_empty()
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.moved_branches.is_empty() {
+            writeln!(f, "Restack: {}", self.moved_branches.join(", "))?;
+        }
+        if !self.deleted_branches.is_empty() {
+            writeln!(f, "Delete: {}", self.deleted_branches.join(", "))?;
+        }
+        for commit in self.dropped_commits.iter() {
+            writeln!(
+                f,
+                "Drop: {} {}",
+                &commit.id.to_string()[..7],
+                commit.summary.to_str_lossy()
+            )?;
+        }
+        if !self.pushed_branches.is_empty() {
+            writeln!(f, "Push: {}", self.pushed_branches.join(", "))?;
+        }
+        if !self.forced_branches.is_empty() {
+            writeln!(f, "Force-push: {}", self.forced_branches.join(", "))?;
+        }
+        if !self.orphaned_tags.is_empty() {
+            writeln!(f, "Leave behind: {}", self.orphaned_tags.join(", "))?;
+        }
+        if !self.skipped_branches.is_empty() {
+            writeln!(f, "Skip: {}", format_skipped(&self.skipped_branches))?;
+        }
+        Ok(())
+    }
+}
+
+impl Summary {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "restack": self.moved_branches,
+            "delete": self.deleted_branches,
+            "drop": self.dropped_commits.iter().map(|c| c.id.to_string()).collect::<Vec<_>>(),
+            "push": self.pushed_branches,
+            "force_push": self.forced_branches,
+            "orphan_tags": self.orphaned_tags,
+            "skip": self.skipped_branches.iter().map(|(branch, reason)| serde_json::json!({
+                "branch": branch,
+                "reason": reason,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Plan each stack's rewrite, tracking where `head_branch` ends up
+fn plan_rewrite(
+    state: &State,
+    head_branch: &mut String,
+) -> Result<(Vec<git_stack::git::Script>, Summary), proc_exit::Exit> {
+    let mut summary = Summary::default();
+    let scripts: Result<Vec<_>, proc_exit::Exit> = state
+        .stacks
+        .iter()
+        .map(|stack| {
+            let Plan {
+                script,
+                dropped_commits,
+                skipped_branches,
+            } = plan_changes(state, stack).with_code(proc_exit::Code::FAILURE)?;
+            if state.protection_action != git_stack::config::ProtectionAction::Skip {
+                summary.skipped_branches.extend(skipped_branches);
+            }
+            if script.is_branch_deleted(head_branch) {
+                *head_branch = stack.onto.name.clone();
+            }
+            summary
+                .moved_branches
+                .extend(script.moved_branches(&state.repo));
+            summary
+                .deleted_branches
+                .extend(script.deleted_branches().into_iter().map(String::from));
+            summary.orphaned_tags.extend(
+                script
+                    .rewritten_commits(&state.repo)
+                    .into_iter()
+                    .chain(dropped_commits.iter().map(|c| c.id))
+                    .filter_map(|id| state.checkpoints.get(&id))
+                    .flatten()
+                    .cloned(),
+            );
+            summary.dropped_commits.extend(dropped_commits);
+            Ok(script)
+        })
+        .collect();
+    let scripts = scripts?;
+
+    Ok((scripts, summary))
+}
+
+/// Point out LFS-tracked files that `scripts` would replay as-is rather than as pointers
+fn warn_raw_lfs_files(state: &State, scripts: &[git_stack::git::Script]) {
+    let repo = state.repo.raw();
+    if !git_stack::git::uses_lfs(repo) {
+        return;
+    }
+    for commit_id in scripts
+        .iter()
+        .flat_map(|script| script.rewritten_commits(&state.repo))
+        .unique()
+    {
+        let raw = match git_stack::git::raw_lfs_files(repo, commit_id) {
+            Ok(raw) => raw,
+            Err(err) => {
+                log::debug!("Could not check {} for LFS files: {}", commit_id, err);
+                continue;
+            }
+        };
+        if !raw.is_empty() {
+            log::warn!(
+                "{} commits LFS-tracked files without LFS, run `git lfs migrate import` to convert them: {}",
+                &commit_id.to_string()[..7],
+                raw.iter().map(|p| p.display()).join(", ")
+            );
+        }
+    }
+}
+
+/// Commits `scripts` would rewrite that are reachable from a protected remote branch
+///
+/// Rewriting them would diverge from upstream.  Planning already protects what we know about, so
+/// this catches what changed under us, like a branch merged mid-flight.
+fn published_commits(
+    state: &State,
+    scripts: &[git_stack::git::Script],
+) -> Vec<(std::rc::Rc<git_stack::git::Commit>, String)> {
+    let mut remote_branches = Vec::new();
+    for branch in state.protected_branches.iter().flat_map(|(_, b)| b) {
+        if branch.is_remote() {
+            remote_branches.push((branch.id, branch.name.clone()));
+        } else if let Some(pull_id) = branch.pull_id {
+            remote_branches.push((
+                pull_id,
+                format!("{}/{}", state.repo.pull_remote(), branch.name),
+            ));
+        }
+    }
+    remote_branches.sort_unstable();
+    remote_branches.dedup_by_key(|(id, _)| *id);
+
+    let mut published = Vec::new();
+    for commit_id in scripts
+        .iter()
+        .flat_map(|script| script.rewritten_commits(&state.repo))
+        .unique()
+    {
+        let remote_branch = remote_branches
+            .iter()
+            .find(|(remote_id, _)| state.repo.merge_base(*remote_id, commit_id) == Some(commit_id));
+        if let Some((_, name)) = remote_branch {
+            if let Some(commit) = state.repo.find_commit(commit_id) {
+                published.push((commit, name.clone()));
+            }
+        }
+    }
+    published
+}
+
+fn needs_confirmation(confirm: git_stack::config::Confirm, summary: &Summary) -> bool {
+    match confirm {
+        git_stack::config::Confirm::Always => !summary.is_empty(),
+        git_stack::config::Confirm::Destructive => summary.is_destructive(),
+        git_stack::config::Confirm::Never => false,
+    }
+}
+
+/// Ask the user a yes/no question, assuming "no" if they can't be asked
+fn confirm(prompt: &str, interactive: bool) -> eyre::Result<bool> {
+    if !interactive {
+        log::debug!("Non-interactive, assuming no to: {}", prompt);
+        return Ok(false);
+    }
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        log::debug!("Not a terminal, assuming no to: {}", prompt);
+        return Ok(false);
     }
 
-    Ok(())
+    let mut stderr = std::io::stderr();
+    write!(stderr, "{} [y/N] ", prompt)?;
+    stderr.flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim().to_ascii_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
+struct Plan {
+    script: git_stack::git::Script,
+    dropped_commits: Vec<std::rc::Rc<git_stack::git::Commit>>,
+    /// Protected branches that rebasing would have otherwise moved, with why they are protected
+    skipped_branches: Vec<(String, String)>,
 }
 
-fn plan_changes(state: &State, stack: &StackState) -> eyre::Result<git_stack::git::Script> {
+fn plan_changes(state: &State, stack: &StackState) -> eyre::Result<Plan> {
     log::trace!("Planning stack changes with base={}", stack.base.name,);
     let graphed_branches = stack.graphed_branches();
     let base_commit = state
         .repo
         .find_commit(stack.base.id)
         .expect("base branch is valid");
-    let mut graph = git_stack::graph::Graph::from_branches(&state.repo, graphed_branches)?;
+    let fork_points = stack.fork_points(&state.repo);
+    let protected_ids = state
+        .protected_branches
+        .iter()
+        .flat_map(|(_, b)| b)
+        .chain([&stack.base, &stack.onto])
+        .flat_map(|b| std::iter::once(b.id).chain(b.pull_id))
+        .chain(fork_points.iter().copied())
+        .collect();
+    let mut graph = git_stack::graph::Graph::from_branches_protected(
+        &state.repo,
+        graphed_branches,
+        protected_ids,
+    )?;
     graph.insert(&state.repo, git_stack::graph::Node::new(base_commit))?;
     for branch in state.protected_branches.iter().flat_map(|(_, b)| b) {
         if let Some(pull_id) = branch.pull_id {
@@ -424,22 +3648,97 @@ fn plan_changes(state: &State, stack: &StackState) -> eyre::Result<git_stack::gi
     git_stack::graph::protect_branches(&mut graph, &state.repo, &state.protected_branches);
     let bases = git_stack::git::Branches::new([stack.base.clone(), stack.onto.clone()]);
     git_stack::graph::protect_branches(&mut graph, &state.repo, &bases);
+    // Don't replay commits that were dropped from the base (e.g. by a force-push)
+    git_stack::graph::protect_commits(&mut graph, &state.repo, fork_points);
+    let mut implicitly_protected = Vec::new();
     if let Some(protect_commit_count) = state.protect_commit_count {
-        git_stack::graph::protect_large_branches(&mut graph, protect_commit_count);
+        let reason = format!("more than {} commits", protect_commit_count);
+        implicitly_protected.extend(
+            git_stack::graph::protect_large_branches(&mut graph, protect_commit_count)
+                .into_iter()
+                .map(|b| (b, reason.clone())),
+        );
     }
-    git_stack::graph::protect_old_branches(
-        &mut graph,
-        state.protect_commit_time,
-        &[state.head_commit.id],
+    let reason = format!(
+        "older than {}",
+        humantime::format_duration(state.protect_commit_age)
+    );
+    implicitly_protected.extend(
+        git_stack::graph::protect_old_branches(
+            &mut graph,
+            state.protect_commit_time,
+            &[state.head_commit.id],
+        )
+        .into_iter()
+        .map(|b| (b, reason.clone())),
     );
     if let Some(user) = state.repo.user() {
-        git_stack::graph::protect_foreign_branches(&mut graph, &user, &[state.head_commit.id]);
+        implicitly_protected.extend(
+            git_stack::graph::protect_foreign_branches(&mut graph, &user, &[state.head_commit.id])
+                .into_iter()
+                .map(|b| (b, "from another user".to_owned())),
+        );
+    }
+    let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
+
+    let mut skipped_branches = Vec::new();
+    if state.rebase {
+        // A protected branch stacked on development branches becomes their base, holding them back
+        let held_back: Vec<_> = stack
+            .branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .filter(|b| b.id != stack.base.id && !state.protected_branches.contains_oid(b.id))
+            .filter(|b| state.repo.merge_base(b.id, stack.base.id) == Some(b.id))
+            .collect();
+        if !held_back.is_empty() {
+            skipped_branches.push((
+                stack.base.name.clone(),
+                "`stack.protected-branch`".to_owned(),
+            ));
+            skipped_branches.extend(
+                held_back
+                    .into_iter()
+                    .map(|b| (b.name.clone(), "under a protected branch".to_owned())),
+            );
+        }
+        for node in graph
+            .breadth_first_iter()
+            .filter(|n| n.action.is_protected() && !n.branches.is_empty())
+        {
+            // Already on `onto` or part of it, so rebasing wouldn't have moved it
+            let merge_base = state.repo.merge_base(node.commit.id, onto_id);
+            if merge_base == Some(onto_id) || merge_base == Some(node.commit.id) {
+                continue;
+            }
+            for branch in node.branches.iter() {
+                if branch.name == stack.base.name || branch.name == stack.onto.name {
+                    continue;
+                }
+                let reason = match implicitly_protected.iter().find(|(b, _)| *b == branch.name) {
+                    Some((_, reason)) => reason.clone(),
+                    None if state
+                        .protected_branches
+                        .iter()
+                        .flat_map(|(_, b)| b)
+                        .any(|b| b.name == branch.name) =>
+                    {
+                        "`stack.protected-branch`".to_owned()
+                    }
+                    None => "under a protected branch".to_owned(),
+                };
+                skipped_branches.push((branch.name.clone(), reason));
+            }
+        }
+        skipped_branches.sort_unstable();
+        skipped_branches.dedup_by(|(lhs, _), (rhs, _)| lhs == rhs);
     }
 
+    git_stack::graph::pin_commits(&mut graph, state.pinned.iter().copied());
+
     let mut dropped_branches = Vec::new();
     if state.rebase {
         log::trace!("Rebasing onto {}", stack.onto.name);
-        let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
         let pull_start_id = stack.onto.id;
         let pull_start_id = state
             .repo
@@ -470,6 +3769,28 @@ fn plan_changes(state: &State, stack: &StackState) -> eyre::Result<git_stack::gi
         git_stack::graph::merge_stacks(&mut graph);
         git_stack::graph::realign_stacks(&mut graph);
     }
+    for (commit_id, command) in state.exec_at.iter() {
+        // Other stacks might have it
+        if graph.get(*commit_id).is_none() {
+            continue;
+        }
+        if !git_stack::graph::exec_at(&mut graph, *commit_id, command) {
+            log::warn!(
+                "Not running `{}`, {} isn't being replayed",
+                command,
+                commit_id
+            );
+        }
+    }
+    if let Some(exec) = state.exec.as_deref() {
+        git_stack::graph::exec(&mut graph, exec);
+    }
+
+    let dropped_commits = graph
+        .breadth_first_iter()
+        .filter(|n| n.action.is_delete())
+        .map(|n| n.commit.clone())
+        .collect();
 
     let mut script = git_stack::graph::to_script(&graph);
     script.commands.extend(
@@ -478,11 +3799,23 @@ fn plan_changes(state: &State, stack: &StackState) -> eyre::Result<git_stack::gi
             .map(git_stack::git::Command::DeleteBranch),
     );
 
-    Ok(script)
+    Ok(Plan {
+        script,
+        dropped_commits,
+        skipped_branches,
+    })
 }
 
-fn push(state: &mut State) -> eyre::Result<()> {
-    let mut graphed_branches = git_stack::git::Branches::new(None.into_iter());
+fn format_skipped(skipped_branches: &[(String, String)]) -> String {
+    skipped_branches
+        .iter()
+        .map(|(branch, reason)| format!("{} ({})", branch, reason))
+        .join(", ")
+}
+
+/// Push ready branches, except those in `exclude`
+fn push(state: &mut State, exclude: &std::collections::HashSet<String>) -> eyre::Result<Summary> {
+    let mut graphed_branches = git_stack::git::Branches::new(None);
     for stack in state.stacks.iter() {
         let stack_graphed_branches = stack.graphed_branches();
         graphed_branches.extend(stack_graphed_branches.into_iter().flat_map(|(_, b)| b));
@@ -506,11 +3839,81 @@ fn push(state: &mut State) -> eyre::Result<()> {
         git_stack::graph::protect_foreign_branches(&mut graph, &user, &[state.head_commit.id]);
     }
 
+    git_stack::graph::mark_dependencies(&mut graph, &state.dependencies);
     git_stack::graph::pushable(&mut graph);
+    let excluded: Vec<_> = graph
+        .breadth_first_iter()
+        .filter(|n| n.branches.iter().any(|b| exclude.contains(&b.name)))
+        .map(|n| n.commit.id)
+        .collect();
+    for id in excluded {
+        graph.get_mut(id).expect("came from graph").pushable = false;
+    }
+    git_stack::graph::hold_dependents(&mut graph);
 
-    git_push(&mut state.repo, &graph, state.dry_run)?;
+    if let (Some(max), git_stack::config::MaxCommitsAction::Error) =
+        (state.max_commits_per_branch, state.max_commits_action)
+    {
+        let pushable: std::collections::HashSet<_> = graph
+            .breadth_first_iter()
+            .filter(|n| n.pushable)
+            .flat_map(|n| n.branches.iter().map(|b| b.name.as_str()))
+            .collect();
+        let oversized: Vec<_> = git_stack::graph::oversized_branches(&graph, max)
+            .into_iter()
+            .filter(|(name, _)| pushable.contains(name.as_str()))
+            .collect();
+        if !oversized.is_empty() {
+            eyre::bail!(
+                "Refusing to push branches with more than {} commits (`stack.max-commits-per-branch`): {}",
+                max,
+                format_oversized(&oversized)
+            );
+        }
+    }
 
-    Ok(())
+    let mut summary = Summary::default();
+    for node in graph.breadth_first_iter().filter(|n| n.pushable) {
+        for branch in node.branches.iter() {
+            let forced = branch
+                .push_id
+                .map(|push_id| state.repo.merge_base(push_id, branch.id) != Some(push_id))
+                .unwrap_or(false);
+            if forced {
+                summary.forced_branches.push(branch.name.clone());
+            } else {
+                summary.pushed_branches.push(branch.name.clone());
+            }
+        }
+    }
+    if !summary.is_empty() {
+        state.progress.emit("plan", summary.to_json());
+    }
+    if needs_confirmation(state.confirm, &summary) && !state.yes && !state.dry_run {
+        let prompt = format!("{}Continue?", summary);
+        if !confirm(&prompt, state.interactive)? {
+            eyre::bail!("Aborting push, run with `--yes` to proceed");
+        }
+    }
+
+    let _signals = crate::cancel::CatchSignals::new(&state.cancel);
+    git_push(
+        &mut state.repo,
+        state.progress,
+        state.git_commands,
+        &state.cancel,
+        &graph,
+        state.dry_run,
+    )?;
+
+    Ok(summary)
+}
+
+fn format_oversized(oversized: &[(String, usize)]) -> String {
+    oversized
+        .iter()
+        .map(|(name, count)| format!("{} ({})", name, count))
+        .join(", ")
 }
 
 fn show(state: &State, colored_stdout: bool, colored_stderr: bool) -> eyre::Result<()> {
@@ -522,6 +3925,44 @@ fn show(state: &State, colored_stdout: bool, colored_stderr: bool) -> eyre::Resu
     let mut empty_stacks = Vec::new();
     let mut old_stacks = Vec::new();
     let mut foreign_stacks = Vec::new();
+    let mut stale_stacks = Vec::new();
+    let mut hidden_stacks = Vec::new();
+
+    // `git rebase --update-refs` can restack branches in a new order, leaving upstreams behind
+    let (rebased, mismatches): (Vec<_>, Vec<_>) =
+        upstream_mismatches(state).into_iter().partition(|m| {
+            m.stacked_on
+                .as_ref()
+                .map(|p| p.remote.is_none())
+                .unwrap_or(false)
+                && crate::metadata::moved_by_rebase(state.repo.raw(), &m.branch)
+        });
+    for mismatch in rebased {
+        let parent = mismatch
+            .stacked_on
+            .as_ref()
+            .expect("partitioned on being stacked on a local branch");
+        log::trace!(
+            "git branch --set-upstream-to {} {}",
+            parent.name,
+            mismatch.branch
+        );
+        if !state.dry_run {
+            crate::metadata::set_parent(state.repo.raw(), &mismatch.branch, &parent.name)?;
+        }
+        log::info!(
+            "{} now tracks {} (was {}), following `git rebase`",
+            mismatch.branch,
+            parent.name,
+            mismatch.upstream
+        );
+    }
+    if !mismatches.is_empty() {
+        log::warn!(
+            "Configured upstreams disagree with the stacks: {}",
+            mismatches.iter().join(", ")
+        );
+    }
 
     let mut graphs = Vec::with_capacity(state.stacks.len());
     for stack in state.stacks.iter() {
@@ -566,6 +4007,16 @@ fn show(state: &State, colored_stdout: bool, colored_stderr: bool) -> eyre::Resu
                 );
             }
         }
+        if let Some(max) = state.max_commits_per_branch {
+            let oversized = git_stack::graph::oversized_branches(&graph, max);
+            if !oversized.is_empty() {
+                log::warn!(
+                    "Branches have more than {} commits, consider splitting them: {}",
+                    max,
+                    format_oversized(&oversized)
+                );
+            }
+        }
         old_stacks.extend(
             git_stack::graph::trim_old_branches(
                 &mut graph,
@@ -582,9 +4033,49 @@ fn show(state: &State, colored_stdout: bool, colored_stderr: bool) -> eyre::Resu
                     .map(|b| format!("{}", palette_stderr.warn.paint(b))),
             );
         }
+        let now = std::time::SystemTime::now();
+        let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
+        for (fork_id, root_id) in stack_roots(&graph) {
+            let age = match last_touched(state.repo.raw(), &graph, root_id)
+                .and_then(|touched| now.duration_since(touched).ok())
+            {
+                Some(age) if state.stale_age <= age => age,
+                _ => continue,
+            };
+            let root = stack_root_name(&graph, root_id);
+            let has_head = git_stack::graph::BreadthFirstIter::new(&graph, root_id)
+                .any(|n| n.commit.id == state.head_commit.id);
+            if state
+                .hide_age
+                .map(|hide_age| hide_age <= age)
+                .unwrap_or(false)
+                && !has_head
+            {
+                graph.remove_child(fork_id, root_id);
+                hidden_stacks.push(format!("{}", palette_stderr.warn.paint(root)));
+                continue;
+            }
+            let behind = state
+                .repo
+                .raw()
+                .graph_ahead_behind(fork_id, onto_id)
+                .map(|(_, behind)| behind)
+                .unwrap_or(0);
+            let mut nudge = format!(
+                "{} (untouched for {}",
+                palette_stderr.warn.paint(root),
+                format_span(age.as_secs())
+            );
+            if 0 < behind {
+                nudge.push_str(&format!(", base moved {} commits", behind));
+            }
+            nudge.push(')');
+            stale_stacks.push(nudge);
+        }
 
         if state.dry_run {
             // Show as-if we performed all mutations
+            git_stack::graph::pin_commits(&mut graph, state.pinned.iter().copied());
             if state.rebase {
                 log::trace!("Rebasing onto {}", stack.onto.name);
                 let onto_id = stack.onto.pull_id.unwrap_or(stack.onto.id);
@@ -651,7 +4142,15 @@ fn show(state: &State, colored_stdout: bool, colored_stderr: bool) -> eyre::Resu
                         .colored(colored_stdout)
                         .show(state.show_format)
                         .stacked(state.show_stacked)
+                        .max_commits(state.show_max_commits)
+                        .touched_dirs(state.show_touched_dirs)
+                        .commit_types(state.show_commit_types)
+                        .reviews(state.show_reviews)
+                        .issue_pattern(state.issue_pattern.as_ref())
+                        .columns(&state.show_columns)
+                        .protection(state.protect_commit_count, state.protect_commit_age)
                         .protected_branches(&state.protected_branches)
+                        .checkpoints(&state.checkpoints)
                 )?;
             }
             git_stack::config::Format::Debug => {
@@ -673,15 +4172,215 @@ fn show(state: &State, colored_stdout: bool, colored_stderr: bool) -> eyre::Resu
     if !foreign_stacks.is_empty() {
         log::info!("Stack from other users: {}", foreign_stacks.join(", "));
     }
+    if !stale_stacks.is_empty() {
+        log::info!("Stale stacks: {}", stale_stacks.join(", "));
+    }
+    if !hidden_stacks.is_empty() {
+        log::info!(
+            "Stacks untouched for more than {}, see `git stack overview`: {}",
+            humantime::format_duration(state.hide_age.unwrap_or_default()),
+            hidden_stacks.join(", ")
+        );
+    }
 
     Ok(())
 }
 
+fn find_branch(
+    repo: &dyn git_stack::git::Repo,
+    branch: &git_stack::git::Branch,
+) -> Option<git_stack::git::Branch> {
+    if branch.is_remote() {
+        repo.find_remote_branch(&branch.name)
+    } else {
+        repo.find_local_branch(&branch.name)
+    }
+}
+
+/// Protected remote-tracking branches on the pull-remote that have no local branch
+/// Warn about dependencies where only one of the branches is in `stacks`
+fn warn_partial_dependencies(
+    branches: &git_stack::git::Branches,
+    stacks: &[StackState],
+    dependencies: &[(String, String)],
+) {
+    let exists = |name: &str| branches.iter().flat_map(|(_, b)| b).any(|b| b.name == name);
+    let selected = |name: &str| {
+        stacks
+            .iter()
+            .flat_map(|s| s.branches.iter())
+            .flat_map(|(_, b)| b)
+            .any(|b| b.name == name)
+    };
+    for (dependent, dependency) in dependencies {
+        if !exists(dependent) || !exists(dependency) {
+            continue;
+        }
+        match (selected(dependent), selected(dependency)) {
+            (true, false) => log::warn!(
+                "{} depends on {}, which is in a stack that was left out",
+                dependent,
+                dependency
+            ),
+            (false, true) => log::warn!(
+                "{} depends on {}, but {}'s stack was left out",
+                dependent,
+                dependency,
+                dependent
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Narrow `stacks` to the branches on the same line as a branch with one of `labels`
+fn filter_labeled(
+    repo: &git_stack::git::GitRepo,
+    stacks: Vec<StackState>,
+    labels: &[String],
+) -> Vec<StackState> {
+    let branch_labels = crate::label::branch_labels(repo.raw());
+    let is_labeled = |branch: &git_stack::git::Branch| {
+        branch_labels
+            .get(&branch.name)
+            .into_iter()
+            .flatten()
+            .any(|l| labels.contains(l))
+    };
+
+    let filtered = filter_lines(repo, stacks, is_labeled);
+    if filtered.is_empty() {
+        log::warn!("No branches are labeled {}", labels.join(", "));
+    }
+    filtered
+}
+
+/// Narrow `stacks` to the branches matching `selector`
+///
+/// Unlike `--label`, only the matching branches are kept, not their whole line.
+fn filter_selected(
+    repo: &git_stack::git::GitRepo,
+    stacks: Vec<StackState>,
+    protected_branches: &git_stack::git::Branches,
+    selector: &git_stack::graph::Selector,
+) -> eyre::Result<Vec<StackState>> {
+    let context = git_stack::graph::SelectContext {
+        user: repo.user().map(|u| u.to_string()),
+        head_branch: repo.head_branch().map(|b| b.name),
+        labels: crate::label::branch_labels(repo.raw()),
+    };
+    let mut filtered = Vec::new();
+    for mut stack in stacks {
+        if stack.branches.is_empty() {
+            continue;
+        }
+        let mut graph = git_stack::graph::Graph::from_branches(repo, stack.graphed_branches())?;
+        git_stack::graph::protect_branches(&mut graph, repo, protected_branches);
+        let selected = git_stack::graph::select_branches(&graph, selector, &context);
+        let branches: Vec<_> = stack
+            .branches
+            .iter()
+            .flat_map(|(_, branches)| branches.iter())
+            .filter(|b| selected.contains(&b.name))
+            .cloned()
+            .collect();
+        if !branches.is_empty() {
+            stack.branches = git_stack::git::Branches::new(branches);
+            filtered.push(stack);
+        }
+    }
+    if filtered.is_empty() {
+        log::warn!("No branches match `{}`", selector);
+    }
+    Ok(filtered)
+}
+
+/// Narrow `stacks` to the branches on the same line as a `selected` branch
+fn filter_lines(
+    repo: &git_stack::git::GitRepo,
+    stacks: Vec<StackState>,
+    selected: impl Fn(&git_stack::git::Branch) -> bool,
+) -> Vec<StackState> {
+    let mut filtered = Vec::new();
+    for mut stack in stacks {
+        let mut kept = std::collections::BTreeMap::new();
+        for (branch_id, branches) in stack.branches.iter() {
+            if !branches.iter().any(&selected) {
+                continue;
+            }
+            let base_id = repo
+                .merge_base(stack.base.id, branch_id)
+                .unwrap_or(stack.base.id);
+            for (_, dependents) in stack.branches.dependents(repo, base_id, branch_id).iter() {
+                kept.extend(dependents.iter().map(|b| (b.name.clone(), b.clone())));
+            }
+        }
+        if !kept.is_empty() {
+            stack.branches = git_stack::git::Branches::new(kept.into_values());
+            filtered.push(stack);
+        }
+    }
+    filtered
+}
+
+/// Drop `names` from `protected_branches`, loudly
+fn unprotect(
+    protected_branches: &git_stack::git::Branches,
+    names: &[String],
+) -> git_stack::git::Branches {
+    for name in names {
+        let is_protected = protected_branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .any(|b| !b.is_remote() && b.name == *name);
+        if is_protected {
+            log::warn!(
+                "Treating protected branch `{}` as a development branch for this run, it may be rewritten",
+                name
+            );
+        } else {
+            log::warn!(
+                "Ignoring `--unprotect {}`, not a protected local branch",
+                name
+            );
+        }
+    }
+    git_stack::git::Branches::new(
+        protected_branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .filter(|b| b.is_remote() || !names.contains(&b.name))
+            .cloned(),
+    )
+}
+
+pub(crate) fn remote_protected_branches<'r>(
+    repo: &'r git_stack::git::GitRepo,
+    branches: &'r git_stack::git::Branches,
+    protected: &'r git_stack::git::ProtectedBranches,
+) -> impl Iterator<Item = git_stack::git::Branch> + 'r {
+    let local_names: std::collections::HashSet<_> = branches
+        .iter()
+        .flat_map(|(_, b)| b.iter())
+        .map(|b| b.name.clone())
+        .collect();
+    repo.remote_branches(repo.pull_remote())
+        .filter(move |b| !local_names.contains(b.local_name()))
+        .filter(move |b| {
+            let is_protected = protected.is_protected(&b.name);
+            if is_protected {
+                log::trace!("Remote branch {} is protected", b.name);
+            }
+            is_protected
+        })
+}
+
 fn resolve_explicit_base(
     repo: &dyn git_stack::git::Repo,
     base: &str,
 ) -> eyre::Result<git_stack::git::Branch> {
     repo.find_local_branch(base)
+        .or_else(|| repo.find_remote_branch(base))
         .ok_or_else(|| eyre::eyre!("could not find branch {:?}", base))
 }
 
@@ -693,34 +4392,251 @@ fn resolve_implicit_base(
 ) -> eyre::Result<git_stack::git::Branch> {
     let branch = git_stack::git::find_protected_base(repo, protected_branches, head_oid)
         .ok_or_else(|| eyre::eyre!("could not find a protected branch to use as a base"))?;
-    log::debug!(
-        "Chose branch {} as the base for {}",
-        branch.name,
-        branches
-            .get(head_oid)
-            .map(|b| b[0].name.clone())
-            .or_else(|| {
-                repo.find_commit(head_oid)?
-                    .summary
-                    .to_str()
-                    .ok()
-                    .map(ToOwned::to_owned)
-            })
-            .unwrap_or_else(|| "target".to_owned())
-    );
+    let head_name = branches
+        .get(head_oid)
+        .map(|b| b[0].name.clone())
+        .or_else(|| {
+            repo.find_commit(head_oid)?
+                .summary
+                .to_str()
+                .ok()
+                .map(ToOwned::to_owned)
+        })
+        .unwrap_or_else(|| "target".to_owned());
+
+    // An explicitly configured upstream wins over guessing between protected branches.  Defer to
+    // the bottom of the stack so all of its branches agree on a base.
+    let fork_id = repo.merge_base(branch.id, head_oid);
+    let upstream = repo
+        .commits_from(head_oid)
+        .take_while(|c| Some(c.id) != fork_id)
+        .flat_map(|c| branches.get(c.id).unwrap_or_default())
+        .filter(|b| !is_protected(protected_branches, b))
+        .last()
+        .and_then(|b| repo.upstream_branch(&b.name))
+        .filter(|upstream| is_protected(protected_branches, upstream))
+        .filter(|upstream| repo.merge_base(upstream.id, head_oid).is_some());
+    if let Some(upstream) = upstream {
+        log::debug!(
+            "Chose upstream {} as the base for {}",
+            upstream.name,
+            head_name
+        );
+        return Ok(upstream);
+    }
+
+    log::debug!("Chose branch {} as the base for {}", branch.name, head_name);
     Ok(branch.clone())
 }
 
-fn git_prune_development(
-    repo: &mut git_stack::git::GitRepo,
-    branches: &[&str],
-    dry_run: bool,
-) -> eyre::Result<()> {
-    if branches.is_empty() {
-        return Ok(());
+fn is_protected(
+    protected_branches: &git_stack::git::Branches,
+    branch: &git_stack::git::Branch,
+) -> bool {
+    protected_branches
+        .get(branch.id)
+        .unwrap_or_default()
+        .iter()
+        .any(|p| p.name == branch.name)
+}
+
+fn development_branches(state: &State) -> git_stack::git::Branches {
+    git_stack::git::Branches::new(
+        state
+            .branches
+            .iter()
+            .flat_map(|(_, b)| b)
+            .filter(|b| !is_protected(&state.protected_branches, b))
+            .cloned(),
+    )
+}
+
+/// The development branch that `branch_id` is stacked on
+///
+/// A development branch only counts as the parent if it is past `fork_id`, where we forked off
+/// the protected base.
+fn development_parent<'b>(
+    state: &State,
+    development_branches: &'b git_stack::git::Branches,
+    branch_id: git2::Oid,
+    fork_id: Option<git2::Oid>,
+) -> Option<&'b git_stack::git::Branch> {
+    git_stack::git::find_base(&state.repo, development_branches, branch_id).filter(|parent| {
+        fork_id.map_or(true, |fork_id| {
+            state.repo.merge_base(parent.id, fork_id) != Some(parent.id)
+        })
+    })
+}
+
+/// Branches whose `branch.<name>.merge` is something other than what they are stacked on
+/// A branch tracking something other than what it is stacked on
+struct UpstreamMismatch {
+    branch: String,
+    upstream: String,
+    /// The development branch it is stacked on, or else its protected base
+    stacked_on: Option<git_stack::git::Branch>,
+}
+
+impl std::fmt::Display for UpstreamMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tracks {} but is stacked on {}",
+            self.branch,
+            self.upstream,
+            self.stacked_on
+                .as_ref()
+                .map(|b| b.name.as_str())
+                .unwrap_or("nothing")
+        )
     }
+}
 
-    let remote = repo.push_remote();
+fn upstream_mismatches(state: &State) -> Vec<UpstreamMismatch> {
+    let development_branches = development_branches(state);
+
+    let mut mismatches = Vec::new();
+    for branch in state
+        .stacks
+        .iter()
+        .flat_map(|stack| stack.branches.iter())
+        .flat_map(|(_, b)| b)
+        .filter(|b| !is_protected(&state.protected_branches, b))
+    {
+        let upstream = match state.repo.upstream_branch(&branch.name) {
+            Some(upstream) => upstream,
+            None => continue,
+        };
+        let protected_base =
+            git_stack::git::find_protected_base(&state.repo, &state.protected_branches, branch.id);
+        let fork_id = protected_base.and_then(|base| state.repo.merge_base(base.id, branch.id));
+        let parent = development_parent(state, &development_branches, branch.id, fork_id);
+        let mismatch = match parent {
+            Some(parent) => parent.local_name() != upstream.local_name(),
+            // Upstream picks between protected bases, see `resolve_implicit_base`
+            None => !is_protected(&state.protected_branches, &upstream),
+        };
+        if mismatch {
+            mismatches.push(UpstreamMismatch {
+                branch: branch.name.clone(),
+                upstream: upstream.name.clone(),
+                stacked_on: parent.or(protected_base).cloned(),
+            });
+        }
+    }
+    mismatches.sort_by(|a, b| a.branch.cmp(&b.branch));
+    mismatches.dedup_by(|a, b| a.branch == b.branch);
+    mismatches
+}
+
+/// Protected bases whose remote has commits we haven't fetched
+fn stale_bases(state: &State) -> Vec<String> {
+    let mut onto_branches: Vec<_> = state
+        .stacks
+        .iter()
+        .filter(|stack| state.protected_branches.contains_oid(stack.onto.id))
+        .map(|stack| &stack.onto)
+        .collect();
+    onto_branches.sort_unstable_by_key(|b| b.name.as_str());
+    onto_branches.dedup_by_key(|b| b.name.as_str());
+    if onto_branches.is_empty() {
+        return Vec::new();
+    }
+
+    let remote = state.repo.pull_remote();
+    let names: Vec<_> = onto_branches.iter().map(|b| b.local_name()).collect();
+    let remote_branches = match git_ls_remote(remote, &names) {
+        Ok(remote_branches) => remote_branches,
+        Err(err) => {
+            log::warn!("Skipping freshness check of `{}`, {}", remote, err);
+            return Vec::new();
+        }
+    };
+
+    onto_branches
+        .into_iter()
+        .filter(|branch| match remote_branches.get(branch.local_name()) {
+            Some(remote_id) => {
+                let local_id = branch.pull_id.unwrap_or(branch.id);
+                log::trace!(
+                    "{}: local={}, {}={}",
+                    branch.name,
+                    local_id,
+                    remote,
+                    remote_id
+                );
+                // Not when only the local branch moved on, like after committing to it
+                *remote_id != local_id
+                    && (state.repo.find_commit(*remote_id).is_none()
+                        || state
+                            .repo
+                            .raw()
+                            .graph_descendant_of(*remote_id, local_id)
+                            .unwrap_or(false))
+            }
+            None => false,
+        })
+        .map(|branch| branch.name.clone())
+        .collect()
+}
+
+/// Fail up front on `operation` rather than partway through, when the network is off limits
+pub(crate) fn require_online(
+    repo_config: &git_stack::config::RepoConfig,
+    operation: &str,
+) -> proc_exit::ExitResult {
+    if repo_config.offline() {
+        return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+            "{} needs the network, which `--offline` (`stack.offline`) rules out",
+            operation
+        )));
+    }
+    Ok(())
+}
+
+/// Run `cmd`, moving what it writes to stdout over to stderr
+///
+/// stdout is kept for reports, like `git stack bot`'s, that get parsed.
+fn status_on_stderr(cmd: &mut std::process::Command) -> std::io::Result<std::process::ExitStatus> {
+    let output = cmd
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::inherit())
+        .output()?;
+    std::io::stderr().write_all(&output.stdout)?;
+    Ok(output.status)
+}
+
+/// Like [`status_on_stderr`] for `git push --porcelain`, also reporting whether the remote
+/// rejected a ref because it moved on
+///
+/// A lease gone stale isn't counted, as that is someone else's push that catching up with the
+/// base would overwrite.
+fn push_on_stderr(
+    cmd: &mut std::process::Command,
+) -> std::io::Result<(std::process::ExitStatus, bool)> {
+    let output = cmd
+        .stdin(std::process::Stdio::inherit())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::inherit())
+        .output()?;
+    std::io::stderr().write_all(&output.stdout)?;
+    let rejected = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with('!'))
+        .any(|line| {
+            let summary = line.rsplit('\t').next().unwrap_or_default();
+            summary.starts_with("[remote rejected]")
+                || summary == "[rejected] (fetch first)"
+                || summary == "[rejected] (non-fast-forward)"
+        });
+    Ok((output.status, rejected))
+}
+
+fn git_ls_remote(
+    remote: &str,
+    branches: &[&str],
+) -> eyre::Result<std::collections::BTreeMap<String, git2::Oid>> {
     let output = std::process::Command::new("git")
         .arg("ls-remote")
         .arg("--heads")
@@ -728,23 +4644,65 @@ fn git_prune_development(
         .args(branches)
         .stdout(std::process::Stdio::piped())
         .spawn()
-        .wrap_err("Could not run `git fetch`")?
+        .wrap_err("Could not run `git ls-remote`")?
         .wait_with_output()?;
     if !output.status.success() {
-        eyre::bail!("Could not run `git fetch`");
+        eyre::bail!("Could not run `git ls-remote`");
     }
-    let stdout = String::from_utf8(output.stdout).wrap_err("Could not run `git fetch`")?;
-    #[allow(clippy::needless_collect)]
-    let remote_branches: Vec<_> = stdout
+    let stdout = String::from_utf8(output.stdout).wrap_err("Could not run `git ls-remote`")?;
+    let remote_branches = stdout
         .lines()
-        .filter_map(|l| l.split_once('\t').map(|s| s.1))
-        .filter_map(|l| l.strip_prefix("refs/heads/"))
+        .filter_map(|l| l.split_once('\t'))
+        .filter_map(|(id, name)| {
+            let name = name.strip_prefix("refs/heads/")?;
+            let id = git2::Oid::from_str(id).ok()?;
+            Some((name.to_owned(), id))
+        })
         .collect();
+    Ok(remote_branches)
+}
 
-    for branch in branches {
-        if !remote_branches.contains(branch) {
+/// Run `git range-diff` between `old` and `new` `(base, head)` ranges, returning whether it succeeded
+fn git_range_diff(
+    colored: bool,
+    old: (git2::Oid, git2::Oid),
+    new: (git2::Oid, git2::Oid),
+) -> eyre::Result<bool> {
+    let status = std::process::Command::new("git")
+        .arg("range-diff")
+        .arg(if colored { "--color" } else { "--no-color" })
+        .arg(format!("{}..{}", old.0, old.1))
+        .arg(format!("{}..{}", new.0, new.1))
+        .status()
+        .wrap_err("Could not run `git range-diff`")?;
+    Ok(status.success())
+}
+
+fn git_prune_development(
+    repo: &mut git_stack::git::GitRepo,
+    git_commands: crate::progress::GitCommands,
+    branches: &[&str],
+    dry_run: bool,
+) -> eyre::Result<()> {
+    if branches.is_empty() {
+        return Ok(());
+    }
+
+    // Where `stack.push-refspec` sent them
+    let pushed_branches: Vec<String> = branches
+        .iter()
+        .filter_map(|branch| repo.push_remote_ref(branch))
+        .filter_map(|remote_ref| remote_ref.strip_prefix("refs/heads/").map(|b| b.to_owned()))
+        .collect();
+    let pushed_branches: Vec<&str> = pushed_branches.iter().map(|b| b.as_str()).collect();
+    let remote = repo.push_remote();
+    let remote_branches = git_ls_remote(remote, &pushed_branches)?;
+
+    for branch in pushed_branches {
+        if !remote_branches.contains_key(branch) {
             let remote_branch = format!("{}/{}", remote, branch);
-            log::info!("Pruning {}", remote_branch);
+            remote_log!(info, "Pruning {}", remote_branch);
+            git_commands.show(&format!("git branch --delete --remotes {}", remote_branch));
             if !dry_run {
                 let mut branch = repo
                     .raw()
@@ -757,9 +4715,14 @@ fn git_prune_development(
     Ok(())
 }
 
-fn git_fetch_upstream(repo: &mut git_stack::git::GitRepo, branch_name: &str) -> eyre::Result<()> {
+fn git_fetch_upstream(
+    repo: &mut git_stack::git::GitRepo,
+    git_commands: crate::progress::GitCommands,
+    branch_name: &str,
+) -> eyre::Result<()> {
     let remote = repo.pull_remote();
-    log::debug!("git fetch {} {}", remote, branch_name);
+    remote_log!(debug, "git fetch {} {}", remote, branch_name);
+    git_commands.show(&format!("git fetch {} {}", remote, branch_name));
     // A little uncertain about some of the weirder authentication needs, just deferring to `git`
     // instead of using `libgit2`
     let status = std::process::Command::new("git")
@@ -777,68 +4740,195 @@ fn git_fetch_upstream(repo: &mut git_stack::git::GitRepo, branch_name: &str) ->
 
 fn git_push(
     repo: &mut git_stack::git::GitRepo,
+    progress: crate::progress::Progress,
+    git_commands: crate::progress::GitCommands,
+    cancel: &git_stack::git::Cancel,
     graph: &git_stack::graph::Graph,
     dry_run: bool,
 ) -> eyre::Result<()> {
-    let mut failed = Vec::new();
+    let mut failed: Vec<PushFailure> = Vec::new();
+    let mut unpushed = Vec::new();
 
-    let mut node_queue = VecDeque::new();
-    node_queue.push_back(graph.root_id());
-    while let Some(current_id) = node_queue.pop_front() {
+    // Dependencies go first, so their dependents' PRs have something to target
+    for current_id in git_stack::graph::push_order(graph) {
         let current = graph.get(current_id).expect("all children exist");
+        if cancel.is_cancelled() {
+            if current.pushable {
+                unpushed.extend(current.branches.iter().map(|b| b.name.clone()));
+            }
+            continue;
+        }
 
-        failed.extend(git_push_node(repo, current, dry_run));
+        failed.extend(git_push_node(
+            repo,
+            progress,
+            git_commands,
+            current,
+            dry_run,
+        ));
+    }
 
-        for child_id in current.children.iter().copied() {
-            node_queue.push_back(child_id);
+    if !unpushed.is_empty() {
+        let mut message = format!("cancelled, leaving {} unpushed", unpushed.join(", "));
+        if !failed.is_empty() {
+            message.push_str(&format!(
+                "; could not push {}",
+                failed.iter().map(|f| &f.branch).join(", ")
+            ));
+        }
+        eyre::bail!(message);
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else if failed.iter().all(|f| f.rejected) {
+        Err(PushRejected {
+            branches: failed.into_iter().map(|f| f.branch).collect(),
         }
+        .into())
+    } else {
+        eyre::bail!(
+            "could not push {}",
+            failed.into_iter().map(|f| f.branch).join(", ")
+        );
     }
+}
+
+struct PushFailure {
+    branch: String,
+    /// The remote refused the update, rather than the push failing to run
+    rejected: bool,
+}
+
+/// The remote refused to update these branches, like when its base moved on since we pulled
+#[derive(Debug)]
+struct PushRejected {
+    branches: Vec<String>,
+}
 
-    if failed.is_empty() {
-        Ok(())
-    } else {
-        eyre::bail!("could not push {}", failed.into_iter().join(", "));
+impl std::fmt::Display for PushRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the remote rejected {}", self.branches.join(", "))
     }
 }
 
+impl std::error::Error for PushRejected {}
+
 fn git_push_node(
     repo: &mut git_stack::git::GitRepo,
+    progress: crate::progress::Progress,
+    git_commands: crate::progress::GitCommands,
     node: &git_stack::graph::Node,
     dry_run: bool,
-) -> Vec<String> {
+) -> Vec<PushFailure> {
     let mut failed = Vec::new();
     for branch in node.branches.iter() {
         if node.pushable {
             let remote = repo.push_remote();
-            log::trace!(
-                "git push --force-with-lease --set-upstream {} {}",
+            // Tracking somewhere other than `<remote>/<branch>` would look like a base to stack on
+            let custom_refspec = repo.push_refspec(&branch.name).is_some();
+            let refspec = match repo.push_refspec_for(&branch.name) {
+                Some(refspec) => refspec,
+                None => {
+                    remote_log!(error, "Could not push {}, `stack.push-refspec` needs `{{user}}` from `user.email`",
+                        branch.name
+                    );
+                    failed.push(PushFailure {
+                        branch: branch.name.clone(),
+                        rejected: false,
+                    });
+                    continue;
+                }
+            };
+            let set_upstream = if custom_refspec {
+                ""
+            } else {
+                " --set-upstream"
+            };
+            remote_log!(
+                trace,
+                "git push --force-with-lease{} {} {}",
+                set_upstream,
                 remote,
-                branch.name
+                refspec
             );
+            git_commands.show(&format!(
+                "git push --force-with-lease{} {} {}",
+                set_upstream, remote, refspec
+            ));
+            // Without the hook `git lfs install` sets up, the server would be missing the files
+            let push_lfs = git_stack::git::uses_lfs(repo.raw())
+                && !git_stack::git::has_lfs_pre_push_hook(repo.raw());
+            if push_lfs {
+                remote_log!(trace, "git lfs push {} {}", remote, branch.name);
+                git_commands.show(&format!("git lfs push {} {}", remote, branch.name));
+            }
+            if !dry_run && push_lfs {
+                let status = status_on_stderr(
+                    std::process::Command::new("git")
+                        .arg("lfs")
+                        .arg("push")
+                        .arg(repo.push_remote())
+                        .arg(&branch.name),
+                );
+                let success = match status {
+                    Ok(status) => status.success(),
+                    Err(err) => {
+                        remote_log!(debug, "`git lfs push` failed with {}", err);
+                        false
+                    }
+                };
+                if !success {
+                    progress.emit(
+                        "push",
+                        serde_json::json!({
+                            "branch": branch.name,
+                            "remote": remote,
+                            "status": "failed",
+                        }),
+                    );
+                    failed.push(PushFailure {
+                        branch: branch.name.clone(),
+                        rejected: false,
+                    });
+                    continue;
+                }
+            }
             if !dry_run {
-                let status = std::process::Command::new("git")
+                let mut command = std::process::Command::new("git");
+                command
                     .arg("push")
-                    .arg("--force-with-lease")
-                    .arg("--set-upstream")
-                    .arg(repo.push_remote())
-                    .arg(&branch.name)
-                    .status();
-                match status {
-                    Ok(status) => {
-                        if !status.success() {
-                            failed.push(branch.name.clone());
-                        }
-                    }
+                    .arg("--porcelain")
+                    .arg("--force-with-lease");
+                if !custom_refspec {
+                    command.arg("--set-upstream");
+                }
+                let status = push_on_stderr(command.arg(repo.push_remote()).arg(&refspec));
+                let (success, rejected) = match status {
+                    Ok((status, rejected)) => (status.success(), rejected),
                     Err(err) => {
-                        log::debug!("`git push` failed with {}", err);
-                        failed.push(branch.name.clone());
+                        remote_log!(debug, "`git push` failed with {}", err);
+                        (false, false)
                     }
+                };
+                progress.emit(
+                    "push",
+                    serde_json::json!({
+                        "branch": branch.name,
+                        "remote": remote,
+                        "status": if success { "ok" } else { "failed" },
+                    }),
+                );
+                if !success {
+                    failed.push(PushFailure {
+                        branch: branch.name.clone(),
+                        rejected,
+                    });
                 }
             }
         } else if node.action.is_protected() {
-            log::debug!("Skipping push of `{}`, protected", branch.name);
+            remote_log!(debug, "Skipping push of `{}`, protected", branch.name);
         } else {
-            log::debug!("Skipping push of `{}`", branch.name);
+            remote_log!(debug, "Skipping push of `{}`", branch.name);
         }
     }
 
@@ -849,9 +4939,18 @@ struct DisplayTree<'r> {
     repo: &'r git_stack::git::GitRepo,
     graph: &'r git_stack::graph::Graph,
     protected_branches: git_stack::git::Branches,
+    checkpoints: std::collections::BTreeMap<git2::Oid, Vec<String>>,
     palette: Palette,
     show: git_stack::config::Format,
     stacked: bool,
+    max_commits: Option<usize>,
+    touched_dirs: bool,
+    commit_types: bool,
+    reviews: bool,
+    issue_pattern: Option<regex::Regex>,
+    columns: git_stack::config::Columns,
+    protect_commit_count: Option<usize>,
+    protect_commit_age: Option<std::time::Duration>,
 }
 
 impl<'r> DisplayTree<'r> {
@@ -860,9 +4959,18 @@ impl<'r> DisplayTree<'r> {
             repo,
             graph,
             protected_branches: Default::default(),
+            checkpoints: Default::default(),
             palette: Palette::plain(),
             show: Default::default(),
             stacked: Default::default(),
+            max_commits: None,
+            touched_dirs: false,
+            commit_types: false,
+            reviews: false,
+            issue_pattern: None,
+            columns: Default::default(),
+            protect_commit_count: None,
+            protect_commit_age: None,
         }
     }
 
@@ -885,10 +4993,59 @@ impl<'r> DisplayTree<'r> {
         self
     }
 
+    pub fn max_commits(mut self, max_commits: Option<usize>) -> Self {
+        self.max_commits = max_commits;
+        self
+    }
+
+    pub fn touched_dirs(mut self, touched_dirs: bool) -> Self {
+        self.touched_dirs = touched_dirs;
+        self
+    }
+
+    pub fn commit_types(mut self, commit_types: bool) -> Self {
+        self.commit_types = commit_types;
+        self
+    }
+
+    pub fn reviews(mut self, reviews: bool) -> Self {
+        self.reviews = reviews;
+        self
+    }
+
+    pub fn issue_pattern(mut self, issue_pattern: Option<&regex::Regex>) -> Self {
+        self.issue_pattern = issue_pattern.cloned();
+        self
+    }
+
+    pub fn columns(mut self, columns: &git_stack::config::Columns) -> Self {
+        self.columns = columns.clone();
+        self
+    }
+
+    /// Flag branches nearing implicit protection
+    pub fn protection(
+        mut self,
+        protect_commit_count: Option<usize>,
+        protect_commit_age: std::time::Duration,
+    ) -> Self {
+        self.protect_commit_count = protect_commit_count;
+        self.protect_commit_age = Some(protect_commit_age);
+        self
+    }
+
     pub fn protected_branches(mut self, protected_branches: &git_stack::git::Branches) -> Self {
         self.protected_branches = protected_branches.clone();
         self
     }
+
+    pub fn checkpoints(
+        mut self,
+        checkpoints: &std::collections::BTreeMap<git2::Oid, Vec<String>>,
+    ) -> Self {
+        self.checkpoints = checkpoints.clone();
+        self
+    }
 }
 
 impl<'r> std::fmt::Display for DisplayTree<'r> {
@@ -902,7 +5059,9 @@ impl<'r> std::fmt::Display for DisplayTree<'r> {
                 let interesting_commit = node.commit.id == head_branch.id
                     || node.commit.id == self.graph.root_id()
                     || node.children.is_empty();
-                let boring_commit = node.branches.is_empty() && node.children.len() == 1;
+                let boring_commit = node.branches.is_empty()
+                    && node.children.len() == 1
+                    && !self.checkpoints.contains_key(&node.commit.id);
                 let protected = node.action.is_protected();
                 interesting_commit || !boring_commit || !protected
             }),
@@ -910,31 +5069,127 @@ impl<'r> std::fmt::Display for DisplayTree<'r> {
                 let interesting_commit = node.commit.id == head_branch.id
                     || node.commit.id == self.graph.root_id()
                     || node.children.is_empty();
-                let boring_commit = node.branches.is_empty() && node.children.len() == 1;
+                let boring_commit = node.branches.is_empty()
+                    && node.children.len() == 1
+                    && !self.checkpoints.contains_key(&node.commit.id);
                 interesting_commit || !boring_commit
             }),
             git_stack::config::Format::Debug => unreachable!("No debug view for tree"),
         };
 
+        let mut guard = RenderGuard::new(self.max_commits);
+        guard.walk(self.graph.root_id());
         let mut tree = node_to_tree(
             self.repo,
             &head_branch,
             self.graph,
             self.graph.root_id(),
             &is_visible,
+            &mut guard,
         );
         if self.stacked {
             tree.linearize();
         } else {
             tree.sort();
         }
+        let mut annotations = Annotations {
+            columns: self.columns.clone(),
+            ..Default::default()
+        };
+        if self.touched_dirs || self.commit_types || self.reviews || self.issue_pattern.is_some() {
+            for (id, base_id) in layer_bases(self.graph) {
+                if self.touched_dirs {
+                    annotations
+                        .touched_dirs
+                        .insert(id, self.repo.touched_dirs(base_id, id));
+                }
+                if self.commit_types {
+                    annotations
+                        .commit_types
+                        .insert(id, CommitTypes::new(self.repo, id, base_id));
+                }
+                if self.reviews {
+                    annotations
+                        .reviews
+                        .insert(id, ReviewCoverage::new(self.repo, id, base_id));
+                }
+                if let Some(issue_pattern) = self.issue_pattern.as_ref() {
+                    let node = self.graph.get(id).expect("layers are in the graph");
+                    let names = node.branches.iter().map(|b| b.name.as_str());
+                    annotations.issues.insert(
+                        id,
+                        layer_issues(self.repo, issue_pattern, names, id, base_id),
+                    );
+                }
+            }
+        }
+        annotations.heat = branch_heat(
+            self.graph,
+            self.protect_commit_count,
+            self.protect_commit_age,
+            std::time::SystemTime::now(),
+        );
         let tree = tree.into_display(
             self.repo,
             &head_branch,
             &self.protected_branches,
+            &self.checkpoints,
+            &annotations,
             &self.palette,
         );
-        tree.fmt(f)
+        tree.fmt(f)?;
+
+        if guard.truncated {
+            log::warn!(
+                "Stopped after showing {} commits, set `stack.show-max-commits=0` to show all",
+                self.max_commits.unwrap_or(0)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Bounds the walk when rendering, in case the graph is huge or malformed
+struct RenderGuard {
+    seen: std::collections::HashSet<git2::Oid>,
+    remaining: Option<usize>,
+    truncated: bool,
+}
+
+impl RenderGuard {
+    fn new(max_commits: Option<usize>) -> Self {
+        Self {
+            seen: Default::default(),
+            remaining: max_commits,
+            truncated: false,
+        }
+    }
+
+    /// Whether `node_id` hasn't been walked before, guarding against cycles
+    fn walk(&mut self, node_id: git2::Oid) -> bool {
+        let first = self.seen.insert(node_id);
+        if !first {
+            log::warn!("Skipping {} in the graph, it was already shown", node_id);
+        }
+        first
+    }
+
+    /// Whether there is room to show another commit
+    fn available(&mut self) -> bool {
+        if self.remaining == Some(0) {
+            self.truncated = true;
+            false
+        } else {
+            true
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        let available = self.available();
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining = remaining.saturating_sub(1);
+        }
+        available
     }
 }
 
@@ -944,14 +5199,25 @@ fn node_to_tree<'r>(
     graph: &'r git_stack::graph::Graph,
     mut node_id: git2::Oid,
     is_visible: &dyn Fn(&git_stack::graph::Node) -> bool,
+    guard: &mut RenderGuard,
 ) -> Tree<'r> {
     for ellide_count in 0.. {
         let node = graph.get(node_id).expect("all children exist");
         // The API requires us to handle 0 or many children, so not checking visibility
         if node.children.len() == 1 && !is_visible(node) {
-            node_id = node.children.iter().copied().next().unwrap();
+            let child_id = node.children.iter().copied().next().unwrap();
+            if !guard.walk(child_id) {
+                guard.take();
+                return Tree {
+                    root: node,
+                    weight: default_weight(node, head_branch) + ellide_count,
+                    stacks: Default::default(),
+                };
+            }
+            node_id = child_id;
             continue;
         }
+        guard.take();
 
         let mut tree = Tree {
             root: node,
@@ -959,7 +5225,7 @@ fn node_to_tree<'r>(
             stacks: Default::default(),
         };
 
-        append_children(&mut tree, repo, head_branch, graph, node, is_visible);
+        append_children(&mut tree, repo, head_branch, graph, node, is_visible, guard);
 
         tree.weight += ellide_count;
 
@@ -976,6 +5242,7 @@ fn append_children<'r>(
     graph: &'r git_stack::graph::Graph,
     mut parent_node: &'r git_stack::graph::Node,
     is_visible: &dyn Fn(&git_stack::graph::Node) -> bool,
+    guard: &mut RenderGuard,
 ) {
     match parent_node.children.len() {
         0 => {}
@@ -983,8 +5250,14 @@ fn append_children<'r>(
             for linear_count in 1.. {
                 let node_id = *parent_node.children.iter().next().unwrap();
                 let node = graph.get(node_id).expect("all children exist");
+                if !guard.walk(node_id) {
+                    break;
+                }
                 match node.children.len() {
                     0 => {
+                        if !guard.take() {
+                            break;
+                        }
                         let child_tree = Tree {
                             root: node,
                             weight: default_weight(node, head_branch),
@@ -999,6 +5272,9 @@ fn append_children<'r>(
                     }
                     1 => {
                         if is_visible(node) {
+                            if !guard.take() {
+                                break;
+                            }
                             let child_tree = Tree {
                                 root: node,
                                 weight: default_weight(node, head_branch),
@@ -1014,8 +5290,11 @@ fn append_children<'r>(
                         continue;
                     }
                     _ => {
+                        if !guard.available() {
+                            break;
+                        }
                         let child_tree =
-                            node_to_tree(repo, head_branch, graph, node_id, is_visible);
+                            node_to_tree(repo, head_branch, graph, node_id, is_visible, guard);
                         tree.weight = tree.weight.max(child_tree.weight + linear_count);
                         if tree.stacks.is_empty() {
                             tree.stacks.push(Vec::new());
@@ -1028,7 +5307,11 @@ fn append_children<'r>(
         }
         _ => {
             for child_id in parent_node.children.iter().copied() {
-                let child_tree = node_to_tree(repo, head_branch, graph, child_id, is_visible);
+                if !guard.walk(child_id) || !guard.available() {
+                    continue;
+                }
+                let child_tree =
+                    node_to_tree(repo, head_branch, graph, child_id, is_visible, guard);
                 tree.weight = tree.weight.max(child_tree.weight + 1);
                 tree.stacks.push(vec![child_tree]);
             }
@@ -1036,6 +5319,319 @@ fn append_children<'r>(
     }
 }
 
+/// Optional details to show, per-branch ones keyed by the branch's commit
+#[derive(Default, Debug)]
+struct Annotations {
+    columns: git_stack::config::Columns,
+    touched_dirs: std::collections::HashMap<git2::Oid, std::rc::Rc<[String]>>,
+    commit_types: std::collections::HashMap<git2::Oid, CommitTypes>,
+    reviews: std::collections::HashMap<git2::Oid, ReviewCoverage>,
+    issues: std::collections::HashMap<git2::Oid, Vec<String>>,
+    heat: std::collections::HashMap<git2::Oid, Vec<(Heat, String)>>,
+}
+
+/// How close a branch is to `stack.protect-commit-age` or `stack.protect-commit-count`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Heat {
+    /// At least 75% of the way there
+    Warm,
+    /// At or past the threshold
+    Hot,
+}
+
+impl Heat {
+    fn new(value: f64, threshold: f64) -> Option<Self> {
+        if threshold <= value {
+            Some(Heat::Hot)
+        } else if threshold * 0.75 <= value {
+            Some(Heat::Warm)
+        } else {
+            None
+        }
+    }
+}
+
+/// Explain which branches are about to be implicitly protected
+///
+/// Mirrors `protect_old_branches` (a stack is old once all of its commits are) and
+/// `protect_large_branches` (a stack is large once more than `count + 1` commits lead up to its
+/// first branch).
+fn branch_heat(
+    graph: &git_stack::graph::Graph,
+    protect_commit_count: Option<usize>,
+    protect_commit_age: Option<std::time::Duration>,
+    now: std::time::SystemTime,
+) -> std::collections::HashMap<git2::Oid, Vec<(Heat, String)>> {
+    let mut heat: std::collections::HashMap<_, Vec<_>> = Default::default();
+
+    let mut stack_roots = Vec::new();
+    let mut queue = vec![graph.root_id()];
+    while let Some(node_id) = queue.pop() {
+        let node = graph.get(node_id).expect("all children exist");
+        for child_id in node.children.iter().copied() {
+            let child = graph.get(child_id).expect("all children exist");
+            if child.action.is_protected() {
+                queue.push(child_id);
+            } else {
+                stack_roots.push(child_id);
+            }
+        }
+    }
+
+    for stack_root in stack_roots {
+        let mut newest = std::time::UNIX_EPOCH;
+        let mut branch_ids = Vec::new();
+        let mut first_branches = Vec::new();
+        let mut queue = vec![(stack_root, 1, true)];
+        while let Some((node_id, commits, first)) = queue.pop() {
+            let node = graph.get(node_id).expect("all children exist");
+            newest = newest.max(node.commit.time);
+            let is_branch = !node.branches.is_empty();
+            if is_branch {
+                branch_ids.push(node_id);
+                if first {
+                    first_branches.push((node_id, commits));
+                }
+            }
+            queue.extend(
+                node.children
+                    .iter()
+                    .map(|child_id| (*child_id, commits + 1, first && !is_branch)),
+            );
+        }
+
+        if let Some(count) = protect_commit_count {
+            // `protect_large_branches` lets through `count + 1` commits without a branch, so
+            // counting the first branch's own commit, `count + 2` is the most left unprotected
+            let limit = count + 2;
+            for (node_id, commits) in first_branches {
+                if let Some(level) = Heat::new(commits as f64, limit as f64) {
+                    heat.entry(node_id).or_default().push((
+                        level,
+                        format!("{} commits, protected past {}", commits, limit),
+                    ));
+                }
+            }
+        }
+        if let Some(max_age) = protect_commit_age {
+            let age = now.duration_since(newest).unwrap_or_default();
+            if let Some(level) = Heat::new(age.as_secs_f64(), max_age.as_secs_f64()) {
+                let message = match level {
+                    Heat::Warm => {
+                        format!("protected in {}", format_span((max_age - age).as_secs()))
+                    }
+                    Heat::Hot => format!("older than {}", format_span(max_age.as_secs())),
+                };
+                for node_id in branch_ids.iter().copied() {
+                    heat.entry(node_id)
+                        .or_default()
+                        .push((level, message.clone()));
+                }
+            }
+        }
+    }
+
+    heat
+}
+
+/// Tally of the Conventional Commit types among a branch's own commits
+#[derive(Default, Debug)]
+struct CommitTypes {
+    counts: std::collections::BTreeMap<String, usize>,
+    breaking: bool,
+}
+
+impl CommitTypes {
+    fn new(repo: &git_stack::git::GitRepo, head_id: git2::Oid, base_id: git2::Oid) -> Self {
+        let mut types = Self::default();
+        for commit in repo.commits_from(head_id).take_while(|c| c.id != base_id) {
+            if commit.fixup_summary().is_some() || commit.wip_summary().is_some() {
+                continue;
+            }
+            if let Some((kind, breaking)) = commit.conventional_type() {
+                *types
+                    .counts
+                    .entry(kind.to_str_lossy().to_lowercase())
+                    .or_default() += 1;
+                types.breaking |= breaking;
+            }
+        }
+        types
+    }
+
+    fn is_empty(&self) -> bool {
+        self.counts.is_empty() && !self.breaking
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "counts": self.counts,
+            "breaking": self.breaking,
+        })
+    }
+}
+
+impl std::fmt::Display for CommitTypes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Most common first
+        let mut counts: Vec<_> = self.counts.iter().collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        let mut items: Vec<_> = counts
+            .into_iter()
+            .map(|(kind, count)| format!("{} x{}", kind, count))
+            .collect();
+        if self.breaking {
+            items.push("breaking!".to_owned());
+        }
+        write!(f, "{}", items.join(", "))
+    }
+}
+
+/// How many of a branch's own commits were signed off with `Reviewed-by:`/`Acked-by:` trailers
+#[derive(Default, Debug)]
+struct ReviewCoverage {
+    commits: usize,
+    reviewed: usize,
+    /// Acked without being reviewed
+    acked: usize,
+    reviewers: std::collections::BTreeSet<String>,
+}
+
+impl ReviewCoverage {
+    fn new(repo: &git_stack::git::GitRepo, head_id: git2::Oid, base_id: git2::Oid) -> Self {
+        Self::from_commits(
+            repo,
+            repo.commits_from(head_id).take_while(|c| c.id != base_id),
+        )
+    }
+
+    fn from_commits(
+        repo: &git_stack::git::GitRepo,
+        commits: impl IntoIterator<Item = std::rc::Rc<git_stack::git::Commit>>,
+    ) -> Self {
+        let mut coverage = Self::default();
+        for commit in commits {
+            // Squashed into a commit that carries its own review
+            if commit.fixup_summary().is_some() {
+                continue;
+            }
+            let message = repo
+                .raw()
+                .find_commit(commit.id)
+                .ok()
+                .map(|c| String::from_utf8_lossy(c.message_bytes()).into_owned())
+                .unwrap_or_default();
+            let review = git_stack::git::Review::from_message(&message);
+            coverage.commits += 1;
+            if review.is_reviewed() {
+                coverage.reviewed += 1;
+            } else if review.is_acked() {
+                coverage.acked += 1;
+            }
+            coverage
+                .reviewers
+                .extend(review.reviewed_by.into_iter().chain(review.acked_by));
+        }
+        coverage
+    }
+
+    fn is_complete(&self) -> bool {
+        self.reviewed + self.acked == self.commits
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "commits": self.commits,
+            "reviewed": self.reviewed,
+            "acked": self.acked,
+            "reviewers": self.reviewers,
+        })
+    }
+}
+
+impl std::fmt::Display for ReviewCoverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "reviewed {}/{}", self.reviewed, self.commits)?;
+        if 0 < self.acked {
+            write!(f, ", acked {}", self.acked)?;
+        }
+        Ok(())
+    }
+}
+
+/// Pull request description, linking the issues `branch`'s own commits mention
+fn pr_body(state: &State, branch: &git_stack::git::Branch, base_id: git2::Oid) -> String {
+    let issue_pattern = match state.issue_pattern.as_ref() {
+        Some(issue_pattern) => issue_pattern,
+        None => return String::new(),
+    };
+    let names = std::iter::once(branch.name.as_str());
+    let issues = layer_issues(&state.repo, issue_pattern, names, branch.id, base_id);
+    if issues.is_empty() {
+        return String::new();
+    }
+
+    let mut body = String::from("Issues:\n");
+    for key in issues {
+        match state.issue_url.as_deref() {
+            Some(issue_url) => {
+                body.push_str(&format!("- [{}]({})\n", key, issue_url.replace("{}", &key)));
+            }
+            None => body.push_str(&format!("- {}\n", key)),
+        }
+    }
+    body
+}
+
+/// Issue keys in the branch names and in the summaries of the commits since `base_id`
+fn layer_issues<'n>(
+    repo: &git_stack::git::GitRepo,
+    issue_pattern: &regex::Regex,
+    names: impl IntoIterator<Item = &'n str>,
+    head_id: git2::Oid,
+    base_id: git2::Oid,
+) -> Vec<String> {
+    let summaries: Vec<_> = repo
+        .commits_from(head_id)
+        .take_while(|c| c.id != base_id)
+        .map(|c| String::from_utf8_lossy(&c.summary).into_owned())
+        .collect();
+    let mut issues: Vec<String> = Vec::new();
+    let names: Vec<&str> = names.into_iter().collect();
+    for text in names
+        .iter()
+        .copied()
+        .chain(summaries.iter().map(|s| s.as_str()))
+    {
+        for key in issue_pattern.find_iter(text) {
+            if !issues.iter().any(|i| i == key.as_str()) {
+                issues.push(key.as_str().to_owned());
+            }
+        }
+    }
+    issues
+}
+
+/// For each branch's commit, the nearest ancestor with a branch or that is protected
+fn layer_bases(graph: &git_stack::graph::Graph) -> Vec<(git2::Oid, git2::Oid)> {
+    let mut layers = Vec::new();
+    let mut queue = vec![(graph.root_id(), graph.root_id())];
+    while let Some((node_id, base_id)) = queue.pop() {
+        let node = graph.get(node_id).expect("all children exist");
+        let is_layer = !node.branches.is_empty() || node.action.is_protected();
+        if node_id != base_id && !node.branches.is_empty() && !node.action.is_protected() {
+            layers.push((node_id, base_id));
+        }
+        let child_base_id = if is_layer { node_id } else { base_id };
+        queue.extend(
+            node.children
+                .iter()
+                .map(|child_id| (*child_id, child_base_id)),
+        );
+    }
+    layers
+}
+
 fn default_weight(node: &git_stack::graph::Node, head_branch: &git_stack::git::Branch) -> Weight {
     if node.action.is_protected() {
         Weight::Protected(0)
@@ -1086,12 +5682,16 @@ impl<'r> Tree<'r> {
         repo: &'r git_stack::git::GitRepo,
         head_branch: &'r git_stack::git::Branch,
         protected_branches: &'r git_stack::git::Branches,
+        checkpoints: &'r std::collections::BTreeMap<git2::Oid, Vec<String>>,
+        annotations: &'r Annotations,
         palette: &'r Palette,
     ) -> termtree::Tree<RenderNode<'r>> {
         let root = RenderNode {
             repo,
             head_branch,
             protected_branches,
+            checkpoints,
+            annotations,
             node: Some(self.root),
             palette,
         };
@@ -1100,6 +5700,8 @@ impl<'r> Tree<'r> {
             repo,
             head_branch,
             protected_branches,
+            checkpoints,
+            annotations,
             node: None,
             palette,
         };
@@ -1112,6 +5714,8 @@ impl<'r> Tree<'r> {
                         repo,
                         head_branch,
                         protected_branches,
+                        checkpoints,
+                        annotations,
                         palette,
                     ));
                 }
@@ -1122,6 +5726,8 @@ impl<'r> Tree<'r> {
                         repo,
                         head_branch,
                         protected_branches,
+                        checkpoints,
+                        annotations,
                         node: Some(child_tree.root),
                         palette,
                     };
@@ -1133,6 +5739,8 @@ impl<'r> Tree<'r> {
                                 repo,
                                 head_branch,
                                 protected_branches,
+                                checkpoints,
+                                annotations,
                                 palette,
                             ));
                         }
@@ -1189,6 +5797,8 @@ struct RenderNode<'r> {
     repo: &'r git_stack::git::GitRepo,
     head_branch: &'r git_stack::git::Branch,
     protected_branches: &'r git_stack::git::Branches,
+    checkpoints: &'r std::collections::BTreeMap<git2::Oid, Vec<String>>,
+    annotations: &'r Annotations,
     node: Option<&'r git_stack::graph::Node>,
     palette: &'r Palette,
 }
@@ -1213,13 +5823,8 @@ impl<'r> std::fmt::Display for RenderNode<'r> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         if let Some(node) = self.node.as_ref() {
             if node.branches.is_empty() {
-                let abbrev_id = self
-                    .repo
-                    .raw()
-                    .find_object(node.commit.id, None)
-                    .unwrap()
-                    .short_id()
-                    .unwrap();
+                let abbrev_id =
+                    format_abbrev_id(self.repo, node.commit.id, &self.annotations.columns);
                 let style = if self.head_branch.id == node.commit.id {
                     self.palette.highlight
                 } else if node.action.is_protected() {
@@ -1230,7 +5835,7 @@ impl<'r> std::fmt::Display for RenderNode<'r> {
                 } else {
                     self.palette.hint
                 };
-                write!(f, "{}", style.paint(abbrev_id.as_str().unwrap()))?;
+                write!(f, "{}", style.paint(abbrev_id))?;
             } else {
                 let mut branches: Vec<_> = node.branches.iter().collect();
                 branches.sort_by_key(|b| {
@@ -1258,12 +5863,71 @@ impl<'r> std::fmt::Display for RenderNode<'r> {
                         })
                         .join(", ")
                 )?;
+                if let Some(heat) = self.annotations.heat.get(&node.commit.id) {
+                    for (level, message) in heat {
+                        let style = match level {
+                            Heat::Warm => self.palette.warn,
+                            Heat::Hot => self.palette.error,
+                        };
+                        write!(f, " {}", style.paint(format!("({})", message)))?;
+                    }
+                }
             }
 
+            if let Some(issues) = self.annotations.issues.get(&node.commit.id) {
+                if !issues.is_empty() {
+                    write!(
+                        f,
+                        " {}",
+                        self.palette
+                            .info
+                            .paint(format!("{{{}}}", issues.join(", ")))
+                    )?;
+                }
+            }
+            if let Some(types) = self.annotations.commit_types.get(&node.commit.id) {
+                if !types.is_empty() {
+                    let style = if types.breaking {
+                        self.palette.warn
+                    } else {
+                        self.palette.hint
+                    };
+                    write!(f, " {}", style.paint(format!("({})", types)))?;
+                }
+            }
+            if let Some(reviews) = self.annotations.reviews.get(&node.commit.id) {
+                if 0 < reviews.commits {
+                    let style = if reviews.is_complete() {
+                        self.palette.good
+                    } else {
+                        self.palette.hint
+                    };
+                    write!(f, " {}", style.paint(format!("({})", reviews)))?;
+                }
+            }
+            if let Some(dirs) = self.annotations.touched_dirs.get(&node.commit.id) {
+                if !dirs.is_empty() {
+                    write!(
+                        f,
+                        " {}",
+                        self.palette.hint.paint(format!("[{}]", dirs.join(", ")))
+                    )?;
+                }
+            }
+            if let Some(tags) = self.checkpoints.get(&node.commit.id) {
+                write!(
+                    f,
+                    " {}",
+                    self.palette
+                        .info
+                        .paint(format!("(tag: {})", tags.join(", ")))
+                )?;
+            }
             write!(
                 f,
-                "{} ",
-                format_commit_status(self.repo, node, self.palette)
+                "{}{} ",
+                format_commit_status(self.repo, node, self.palette),
+                format_columns(self.repo, node, &self.annotations.columns, self.palette),
             )?;
 
             let summary = String::from_utf8_lossy(&node.commit.summary);
@@ -1314,7 +5978,7 @@ fn format_branch_status<'d>(
     // See format_commit_status
     if node.action.is_protected() {
         match commit_relation(repo, branch.id, branch.pull_id) {
-            Some((0, 0)) => format!(""),
+            Some((0, 0)) => String::new(),
             Some((local, 0)) => {
                 format!(" {}", palette.warn.paint(format!("({} ahead)", local)))
             }
@@ -1334,17 +5998,17 @@ fn format_branch_status<'d>(
             }
         }
     } else if node.action.is_delete() {
-        format!("")
+        String::new()
     } else if 1 < repo
         .raw()
         .find_commit(node.commit.id)
         .unwrap()
         .parent_count()
     {
-        format!("")
+        String::new()
     } else {
         if node.branches.is_empty() {
-            format!("")
+            String::new()
         } else {
             let branch = &node.branches[0];
             match commit_relation(repo, branch.id, branch.push_id) {
@@ -1369,7 +6033,7 @@ fn format_branch_status<'d>(
                     if node.pushable {
                         format!(" {}", palette.info.paint("(ready)"))
                     } else {
-                        format!("")
+                        String::new()
                     }
                 }
             }
@@ -1384,7 +6048,7 @@ fn format_commit_status<'d>(
 ) -> String {
     // See format_branch_status
     if node.action.is_protected() {
-        format!("")
+        String::new()
     } else if node.action.is_delete() {
         format!(" {}", palette.error.paint("(drop)"))
     } else if 1 < repo
@@ -1395,8 +6059,102 @@ fn format_commit_status<'d>(
     {
         format!(" {}", palette.error.paint("(merge commit)"))
     } else {
-        format!("")
+        String::new()
+    }
+}
+
+fn format_columns(
+    repo: &git_stack::git::GitRepo,
+    node: &git_stack::graph::Node,
+    columns: &git_stack::config::Columns,
+    palette: &Palette,
+) -> String {
+    let mut rendered = String::new();
+    for column in columns.iter() {
+        let value = match column {
+            git_stack::config::Column::Age => {
+                format_age(node.commit.time, std::time::SystemTime::now())
+            }
+            git_stack::config::Column::Author => node
+                .commit
+                .author
+                .as_deref()
+                .map(author_initials)
+                .unwrap_or_else(|| "?".to_owned()),
+            // Commits without a branch are already labeled by their id
+            git_stack::config::Column::Sha(_) if node.branches.is_empty() => continue,
+            git_stack::config::Column::Sha(_) => format_abbrev_id(repo, node.commit.id, columns),
+        };
+        rendered.push(' ');
+        rendered.push_str(&palette.hint.paint(value).to_string());
     }
+    rendered
+}
+
+fn format_abbrev_id(
+    repo: &git_stack::git::GitRepo,
+    id: git2::Oid,
+    columns: &git_stack::config::Columns,
+) -> String {
+    match columns.sha_len() {
+        Some(len) => {
+            let mut id = id.to_string();
+            id.truncate(len);
+            id
+        }
+        None => repo
+            .raw()
+            .find_object(id, None)
+            .unwrap()
+            .short_id()
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_owned(),
+    }
+}
+
+/// Coarse relative time, along the lines of `git log --date=relative`
+fn format_age(time: std::time::SystemTime, now: std::time::SystemTime) -> String {
+    match now.duration_since(time) {
+        Ok(age) => format!("{} ago", format_span(age.as_secs())),
+        Err(_) => "in the future".to_owned(),
+    }
+}
+
+/// Round `secs` to the largest sensible unit, like `3 days`
+fn format_span(secs: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    const WEEK: u64 = 7 * DAY;
+    const MONTH: u64 = 30 * DAY;
+    const YEAR: u64 = 365 * DAY;
+
+    let (count, unit) = if secs < 90 {
+        (secs, "second")
+    } else if secs < 90 * MINUTE {
+        ((secs + MINUTE / 2) / MINUTE, "minute")
+    } else if secs < 36 * HOUR {
+        ((secs + HOUR / 2) / HOUR, "hour")
+    } else if secs < 14 * DAY {
+        ((secs + DAY / 2) / DAY, "day")
+    } else if secs < 10 * WEEK {
+        ((secs + WEEK / 2) / WEEK, "week")
+    } else if secs < YEAR {
+        ((secs + MONTH / 2) / MONTH, "month")
+    } else {
+        ((secs + YEAR / 2) / YEAR, "year")
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{} {}{}", count, unit, plural)
+}
+
+fn author_initials(name: &str) -> String {
+    name.split_whitespace()
+        .filter_map(|word| word.chars().find(|c| c.is_alphanumeric()))
+        .flat_map(char::to_uppercase)
+        .collect()
 }
 
 fn commit_relation(
@@ -1454,3 +6212,78 @@ impl Palette {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `main` and then `commits` commits up to `feature`
+    fn large_branch(commits: usize) -> git_stack::graph::Graph {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        for i in 0..=commits {
+            let parent_id = repo.head_id();
+            let id = repo.gen_id();
+            repo.push_commit(
+                parent_id,
+                git_stack::git::Commit {
+                    id,
+                    tree_id: id,
+                    summary: bstr::BString::from(i.to_string()),
+                    time: std::time::SystemTime::now(),
+                    author: None,
+                    committer: None,
+                },
+            );
+            let name = if i == 0 { "main" } else { "feature" };
+            if i == 0 || i == commits {
+                repo.mark_branch(git_stack::git::Branch {
+                    name: name.to_owned(),
+                    remote: None,
+                    id,
+                    push_id: None,
+                    pull_id: None,
+                });
+            }
+        }
+
+        let main = repo.find_local_branch("main").unwrap();
+        let mut branches = git_stack::git::Branches::default();
+        branches.insert(main.clone());
+        branches.insert(repo.find_local_branch("feature").unwrap());
+        let mut graph = git_stack::graph::Graph::from_branches(&repo, branches).unwrap();
+        git_stack::graph::protect_branches(
+            &mut graph,
+            &repo,
+            &git_stack::git::Branches::new([main]),
+        );
+        graph
+    }
+
+    #[test]
+    fn heat_limit_matches_protection() {
+        let count = 3;
+        let limit = count + 2;
+
+        let mut graph = large_branch(limit);
+        let feature_id = graph
+            .breadth_first_iter()
+            .find(|n| n.branches.iter().any(|b| b.name == "feature"))
+            .unwrap()
+            .commit
+            .id;
+        let heat = branch_heat(&graph, Some(count), None, std::time::SystemTime::now());
+        assert_eq!(
+            heat[&feature_id],
+            [(
+                Heat::Hot,
+                format!("{} commits, protected past {}", limit, limit)
+            )]
+        );
+        let protected = git_stack::graph::protect_large_branches(&mut graph, count);
+        assert!(protected.is_empty(), "{:?}", protected);
+
+        let mut graph = large_branch(limit + 1);
+        let protected = git_stack::graph::protect_large_branches(&mut graph, count);
+        assert_eq!(protected, ["feature"]);
+    }
+}
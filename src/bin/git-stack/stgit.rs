@@ -0,0 +1,595 @@
+use std::io::Write;
+
+use bstr::ByteSlice;
+use proc_exit::WithCodeResultExt;
+
+/// stgit's default `stgit.namelength`
+const MAX_NAME_LEN: usize = 30;
+const SERIES_FILE: &str = "series";
+const SERIES_HEADER: &str = "# This series applies on GIT commit";
+
+/// Write a branch's commits, down to its protected base, as an stgit patch series
+///
+/// Each single-commit branch becomes a patch named after the branch, so the names survive a round
+/// trip through [`import`].
+pub fn export(
+    args: &crate::args::Args,
+    export_args: &crate::args::ExportArgs,
+) -> proc_exit::ExitResult {
+    if !export_args.stgit {
+        return Err(
+            proc_exit::Code::USAGE_ERR.with_message("Nothing to do, pass `--stgit` or `--html`")
+        );
+    }
+
+    let exported = Exported::new(args, export_args.branch.as_deref())?;
+    let repo = &exported.repo;
+    let branch = &exported.branch;
+    let base_id = exported.base_id;
+    let mut commits = Vec::new();
+    for id in exported.commits.iter() {
+        let commit = repo
+            .raw()
+            .find_commit(*id)
+            .with_code(proc_exit::Code::FAILURE)?;
+        if commit.parent_count() != 1 {
+            return Err(proc_exit::Code::USAGE_ERR.with_message(format!(
+                "Can't export {}, stgit patches can't be merge or root commits",
+                id
+            )));
+        }
+        commits.push(commit);
+    }
+    if commits.is_empty() {
+        log::info!(
+            "Nothing to export, `{}` is at `{}`",
+            branch.name,
+            exported.base.name
+        );
+        return Ok(());
+    }
+
+    let names = patch_names(&commits, &exported.branches, &exported.protected_branches);
+    let mut series = format!("{} {}\n", SERIES_HEADER, base_id);
+    let mut patches = Vec::new();
+    for (commit, name) in commits.iter().zip(names) {
+        let patch = format_patch(repo.raw(), commit).with_code(proc_exit::Code::FAILURE)?;
+        series.push_str(&name);
+        series.push('\n');
+        patches.push((name, patch));
+    }
+
+    let dir = export_args.path.as_path();
+    log::trace!("mkdir -p {}", dir.display());
+    if !args.dry_run {
+        std::fs::create_dir_all(dir).with_code(proc_exit::Code::FAILURE)?;
+    }
+    for (name, patch) in patches.iter() {
+        let path = dir.join(name);
+        log::trace!("Writing {}", path.display());
+        if !args.dry_run {
+            std::fs::write(&path, patch).with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    if !args.dry_run {
+        std::fs::write(dir.join(SERIES_FILE), series).with_code(proc_exit::Code::FAILURE)?;
+    }
+    log::info!(
+        "Exported {} patches from `{}` to {}",
+        patches.len(),
+        branch.name,
+        dir.display()
+    );
+
+    Ok(())
+}
+
+/// A branch's commits, down to its protected base, for `git stack export`
+pub struct Exported {
+    pub repo: git_stack::git::GitRepo,
+    pub branches: git_stack::git::Branches,
+    pub protected_branches: git_stack::git::Branches,
+    pub branch: git_stack::git::Branch,
+    pub base: git_stack::git::Branch,
+    /// Where `branch` forked from `base`
+    pub base_id: git2::Oid,
+    /// Oldest first, following first parents
+    pub commits: Vec<git2::Oid>,
+}
+
+impl Exported {
+    /// `branch`, or the current branch
+    pub fn new(args: &crate::args::Args, branch: Option<&str>) -> Result<Self, proc_exit::Exit> {
+        log::trace!("Initializing");
+        let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+        let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+        let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+            .with_code(proc_exit::Code::CONFIG_ERR)?
+            .update(args.to_config());
+        let protected = git_stack::git::ProtectedBranches::new(
+            crate::forge::protected_patterns(&repo, &repo_config)
+                .iter()
+                .map(|s| s.as_str()),
+        )
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+        let mut repo = git_stack::git::GitRepo::new(repo);
+        repo.set_pull_remote(repo_config.pull_remote());
+        let ignored = git_stack::git::BranchGlobs::new(
+            repo_config.ignore_branches().iter().map(|s| s.as_str()),
+        )
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+        let branches = git_stack::git::Branches::with_ignore(repo.local_branches(), &ignored);
+        let protected_branches = branches.protected(&protected);
+
+        let branch = match branch {
+            Some(name) => repo.find_local_branch(name).ok_or_else(|| {
+                proc_exit::Code::USAGE_ERR.with_message(format!("No branch `{}`", name))
+            })?,
+            None => crate::stack::attached_head_branch(&repo).ok_or_else(|| {
+                proc_exit::Code::USAGE_ERR.with_message("Must not be in a detached HEAD state.")
+            })?,
+        };
+        let base = git_stack::git::find_protected_base(&repo, &protected_branches, branch.id)
+            .ok_or_else(|| {
+                proc_exit::Code::USAGE_ERR.with_message(format!(
+                    "Could not find a protected base for `{}`",
+                    branch.name
+                ))
+            })?
+            .clone();
+        let base_id = repo.merge_base(base.id, branch.id).ok_or_else(|| {
+            proc_exit::Code::USAGE_ERR.with_message(format!(
+                "`{}` has nothing in common with `{}`",
+                branch.name, base.name
+            ))
+        })?;
+
+        let mut commits = Vec::new();
+        let mut id = branch.id;
+        while id != base_id {
+            let commit = repo
+                .raw()
+                .find_commit(id)
+                .with_code(proc_exit::Code::FAILURE)?;
+            commits.push(id);
+            id = commit.parent_id(0).map_err(|_| {
+                proc_exit::Code::USAGE_ERR.with_message(format!("Can't export root commit {}", id))
+            })?;
+        }
+        commits.reverse();
+
+        Ok(Self {
+            repo,
+            branches,
+            protected_branches,
+            branch,
+            base,
+            base_id,
+            commits,
+        })
+    }
+}
+
+/// Recreate an stgit patch series as a stack with a branch per patch
+///
+/// The patches go on top of the commit the series was exported from, when we have it, otherwise
+/// HEAD.  Nothing is checked out.
+pub fn import(
+    args: &crate::args::Args,
+    import_args: &crate::args::ImportArgs,
+) -> proc_exit::ExitResult {
+    if !import_args.stgit {
+        return Err(proc_exit::Code::USAGE_ERR.with_message("Nothing to do, pass `--stgit`"));
+    }
+
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let commit_template = crate::stack::commit_template(&repo, &repo_config)?;
+
+    let dir = import_args.dir.as_path();
+    let series_path = dir.join(SERIES_FILE);
+    let series = std::fs::read_to_string(&series_path).map_err(|err| {
+        proc_exit::Code::USAGE_ERR.with_message(format!(
+            "Could not read {}: {}",
+            series_path.display(),
+            err
+        ))
+    })?;
+
+    let exported_from = series
+        .lines()
+        .find_map(|l| l.strip_prefix(SERIES_HEADER))
+        .and_then(|id| git2::Oid::from_str(id.trim()).ok())
+        .filter(|id| repo.find_commit(*id).is_ok());
+    let mut parent = match exported_from {
+        Some(id) => repo.find_commit(id).with_code(proc_exit::Code::FAILURE)?,
+        None => repo
+            .head()
+            .and_then(|h| h.peel_to_commit())
+            .with_code(proc_exit::Code::USAGE_ERR)?,
+    };
+    log::debug!("Importing onto {}", parent.id());
+
+    let entries: Vec<_> = series
+        .lines()
+        .map(|l| l.split_once('#').map_or(l, |(l, _)| l))
+        .filter_map(|l| {
+            // quilt allows options, like `-p0`, after the name
+            let mut fields = l.split_whitespace();
+            let name = fields.next()?;
+            let strip = fields
+                .filter_map(|f| f.strip_prefix("-p"))
+                .find_map(|n| n.parse().ok())
+                .unwrap_or(1);
+            Some((name, strip))
+        })
+        .collect();
+    let names: Vec<_> = entries.iter().map(|(name, _)| *name).collect();
+    if names.is_empty() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("No patches in {}", series_path.display())));
+    }
+    for name in names.iter() {
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+            return Err(proc_exit::Code::USAGE_ERR
+                .with_message(format!("`{}` is not a valid branch name", name)));
+        }
+        if repo.find_branch(name, git2::BranchType::Local).is_ok() {
+            return Err(proc_exit::Code::USAGE_ERR
+                .with_message(format!("Branch `{}` already exists", name)));
+        }
+    }
+
+    let committer = repo.signature().with_code(proc_exit::Code::CONFIG_ERR)?;
+    let mut imported = Vec::new();
+    for (name, strip) in entries.iter() {
+        let path = dir.join(name);
+        let content = std::fs::read(&path).map_err(|err| {
+            proc_exit::Code::USAGE_ERR.with_message(format!(
+                "Could not read {}: {}",
+                path.display(),
+                err
+            ))
+        })?;
+        let patch = Patch::parse(&content);
+        let tree_id = match patch.diff {
+            Some(diff) => {
+                let diff = git2::Diff::from_buffer(&git_diff(diff, *strip)).map_err(|err| {
+                    proc_exit::Code::USAGE_ERR.with_message(format!(
+                        "Could not parse the diff in {}: {}",
+                        path.display(),
+                        err
+                    ))
+                })?;
+                let mut index = repo
+                    .apply_to_tree(
+                        &parent.tree().with_code(proc_exit::Code::FAILURE)?,
+                        &diff,
+                        None,
+                    )
+                    .map_err(|err| {
+                        proc_exit::Code::FAILURE
+                            .with_message(format!("`{}` does not apply: {}", name, err))
+                    })?;
+                index
+                    .write_tree_to(&repo)
+                    .with_code(proc_exit::Code::FAILURE)?
+            }
+            None => parent.tree_id(),
+        };
+        let tree = repo
+            .find_tree(tree_id)
+            .with_code(proc_exit::Code::FAILURE)?;
+        let author = patch
+            .author(&committer)
+            .with_code(proc_exit::Code::USAGE_ERR)?;
+        let mut message = patch.message.to_str_lossy();
+        if let Some(template) = commit_template.as_ref() {
+            // Each patch is a branch, stacked on the first
+            let provenance = git_stack::git::Provenance {
+                stack: names.first().map(|n| (*n).to_owned()),
+                layer: Some((*name).to_owned()),
+                sources: Vec::new(),
+            };
+            message = template.apply(&message, &provenance).into();
+        }
+        let id = repo
+            .commit(None, &author, &committer, &message, &tree, &[&parent])
+            .with_code(proc_exit::Code::FAILURE)?;
+        log::debug!("{}: {}", name, id);
+        parent = repo.find_commit(id).with_code(proc_exit::Code::FAILURE)?;
+        imported.push((name, id));
+    }
+
+    for (name, id) in imported.iter() {
+        log::trace!("git branch {} {}", name, id);
+        if !args.dry_run {
+            let commit = repo.find_commit(*id).with_code(proc_exit::Code::FAILURE)?;
+            repo.branch(name, &commit, false)
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    let (top, _) = imported.last().expect("checked for patches");
+    log::info!(
+        "Imported {} patches, run `git switch {}` to pick up where you left off",
+        imported.len(),
+        top
+    );
+
+    Ok(())
+}
+
+/// A branch's name when it is the only commit on the branch, otherwise based on the summary
+fn patch_names(
+    commits: &[git2::Commit<'_>],
+    branches: &git_stack::git::Branches,
+    protected_branches: &git_stack::git::Branches,
+) -> Vec<String> {
+    let branch_name = |id: git2::Oid| {
+        if protected_branches.contains_oid(id) {
+            return None;
+        }
+        branches
+            .get(id)
+            .and_then(|b| b.first())
+            .map(|b| b.name.clone())
+    };
+
+    let mut names: Vec<String> = Vec::new();
+    let mut segment_start = 0;
+    for (i, commit) in commits.iter().enumerate() {
+        let tip = branch_name(commit.id());
+        let name = match tip.as_deref() {
+            Some(branch) if i == segment_start => branch.replace('/', "-"),
+            _ => slugify(&commit.summary_bytes().unwrap_or_default().to_str_lossy()),
+        };
+        if tip.is_some() {
+            segment_start = i + 1;
+        }
+
+        let mut unique = name.clone();
+        let mut suffix = 1;
+        while unique == SERIES_FILE || names.contains(&unique) {
+            suffix += 1;
+            unique = format!("{}-{}", name, suffix);
+        }
+        names.push(unique);
+    }
+    names
+}
+
+/// The way stgit names patches after their summary
+pub(crate) fn slugify(summary: &str) -> String {
+    let mut slug = String::new();
+    for c in summary.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(MAX_NAME_LEN);
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "patch".to_owned()
+    } else {
+        slug.to_owned()
+    }
+}
+
+fn format_patch(
+    repo: &git2::Repository,
+    commit: &git2::Commit<'_>,
+) -> Result<Vec<u8>, git2::Error> {
+    let mut patch = Vec::new();
+    let author = commit.author();
+    let _ = writeln!(
+        patch,
+        "From: {} <{}>",
+        author.name_bytes().to_str_lossy(),
+        author.email_bytes().to_str_lossy()
+    );
+    let seconds = author.when().seconds().max(0) as u64;
+    let when = std::time::UNIX_EPOCH + std::time::Duration::from_secs(seconds);
+    let _ = writeln!(patch, "Date: {}", humantime::format_rfc3339(when));
+    let _ = writeln!(patch);
+    patch.extend_from_slice(commit.message_bytes().trim_end());
+    let _ = writeln!(patch);
+    let _ = writeln!(patch, "---");
+
+    let parent_tree = commit.parent(0)?.tree()?;
+    let tree = commit.tree()?;
+    let mut opts = git2::DiffOptions::new();
+    opts.show_binary(true);
+    let diff = repo.diff_tree_to_tree(Some(&parent_tree), Some(&tree), Some(&mut opts))?;
+    diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
+        if let origin @ ('+' | '-' | ' ') = line.origin() {
+            patch.push(origin as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(patch)
+}
+
+/// An stgit (or quilt) patch file: optional headers, the description, and the diff
+///
+/// Besides headers at the top, `stg export` puts the author in its own paragraph after the
+/// summary.
+struct Patch<'p> {
+    author: Option<&'p [u8]>,
+    date: Option<&'p [u8]>,
+    message: Vec<u8>,
+    diff: Option<&'p [u8]>,
+}
+
+impl<'p> Patch<'p> {
+    fn parse(content: &'p [u8]) -> Self {
+        let mut author = None;
+        let mut date = None;
+        let mut header = |line: &'p [u8]| {
+            let line = line.trim_end();
+            if let Some(value) = line
+                .strip_prefix(b"From:")
+                .or_else(|| line.strip_prefix(b"Author:"))
+            {
+                author = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix(b"Date:") {
+                date = Some(value.trim());
+            } else {
+                return false;
+            }
+            true
+        };
+
+        let diff_start = diff_start(content);
+        let body = &content[..diff_start.unwrap_or(content.len())];
+        let mut lines: Vec<_> = body
+            .lines_with_terminator()
+            .skip_while(|line| header(line))
+            .take_while(|line| line.trim_end() != b"---")
+            .collect();
+        // `stg export`'s `<summary>\n\nFrom: <author>\n\n<description>`
+        if lines.len() > 2 && lines[1].trim().is_empty() {
+            let headers = lines[2..]
+                .iter()
+                .take_while(|line| !line.trim().is_empty())
+                .count();
+            if 0 < headers && lines[2..2 + headers].iter().all(|line| header(line)) {
+                lines.drain(1..2 + headers);
+            }
+        }
+        let message = lines.concat().trim().to_owned();
+
+        Self {
+            author,
+            date,
+            message,
+            diff: diff_start.map(|i| &content[i..]),
+        }
+    }
+
+    fn author(
+        &self,
+        committer: &git2::Signature<'_>,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        let (name, email) = match self
+            .author
+            .and_then(|a| a.to_str().ok())
+            .and_then(|a| a.trim_end_matches('>').rsplit_once('<'))
+        {
+            Some((name, email)) => (name.trim().to_owned(), email.trim().to_owned()),
+            None => (
+                committer.name().unwrap_or_default().to_owned(),
+                committer.email().unwrap_or_default().to_owned(),
+            ),
+        };
+        let when = self
+            .date
+            .and_then(|d| d.to_str().ok())
+            .and_then(|d| humantime::parse_rfc3339_weak(d).ok())
+            .and_then(|d| d.duration_since(std::time::UNIX_EPOCH).ok());
+        match when {
+            Some(when) => {
+                git2::Signature::new(&name, &email, &git2::Time::new(when.as_secs() as i64, 0))
+            }
+            None => git2::Signature::now(&name, &email),
+        }
+    }
+}
+
+/// Where the diff starts, whether git's or a traditional one like quilt writes
+fn diff_start(content: &[u8]) -> Option<usize> {
+    let lines: Vec<_> = content.lines_with_terminator().collect();
+    let mut offset = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if line.starts_with(b"diff --git ") {
+            return Some(offset);
+        }
+        if is_traditional_header(&lines[i..]) {
+            // Along with quilt's `Index:` banner
+            let banner = lines[..i]
+                .iter()
+                .rev()
+                .take(2)
+                .take_while(|l| l.starts_with(b"Index: ") || l.starts_with(b"===="))
+                .map(|l| l.len())
+                .sum::<usize>();
+            return Some(offset - banner);
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn is_traditional_header(lines: &[&[u8]]) -> bool {
+    matches!(
+        lines,
+        [old, new, hunk, ..]
+            if old.starts_with(b"--- ") && new.starts_with(b"+++ ") && hunk.starts_with(b"@@ ")
+    )
+}
+
+/// libgit2 only reads git's diffs, so give a traditional one git's file headers, dropping `strip`
+/// leading directories from its paths like `patch -p`
+fn git_diff(diff: &[u8], strip: usize) -> std::borrow::Cow<'_, [u8]> {
+    if diff.starts_with(b"diff --git ") {
+        return std::borrow::Cow::Borrowed(diff);
+    }
+
+    let lines: Vec<_> = diff.lines_with_terminator().collect();
+    let mut git_diff = Vec::with_capacity(diff.len());
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if is_traditional_header(&lines[i..]) {
+            let old = traditional_path(&line[4..], strip);
+            let new = traditional_path(&lines[i + 1][4..], strip);
+            if let Some(path) = new.or(old) {
+                let path = path.as_bstr();
+                let _ = writeln!(git_diff, "diff --git a/{} b/{}", path, path);
+                match (old, new) {
+                    (None, _) => {
+                        let _ = writeln!(git_diff, "new file mode 100644");
+                        let _ = writeln!(git_diff, "--- /dev/null");
+                        let _ = writeln!(git_diff, "+++ b/{}", path);
+                    }
+                    (_, None) => {
+                        let _ = writeln!(git_diff, "deleted file mode 100644");
+                        let _ = writeln!(git_diff, "--- a/{}", path);
+                        let _ = writeln!(git_diff, "+++ /dev/null");
+                    }
+                    _ => {
+                        let _ = writeln!(git_diff, "--- a/{}", path);
+                        let _ = writeln!(git_diff, "+++ b/{}", path);
+                    }
+                }
+            }
+            i += 2;
+            continue;
+        }
+        if !line.starts_with(b"Index: ") && !line.starts_with(b"====") {
+            git_diff.extend_from_slice(line);
+        }
+        i += 1;
+    }
+    std::borrow::Cow::Owned(git_diff)
+}
+
+/// The path in a `---`/`+++` line, without its timestamp, or `None` for `/dev/null`
+fn traditional_path(field: &[u8], strip: usize) -> Option<&[u8]> {
+    let path = field.split_str("\t").next().unwrap_or_default().trim_end();
+    if path == b"/dev/null" {
+        return None;
+    }
+    let mut path = path;
+    for _ in 0..strip {
+        path = path.find_byte(b'/').map_or(path, |i| &path[i + 1..]);
+    }
+    Some(path)
+}
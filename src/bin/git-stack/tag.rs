@@ -0,0 +1,34 @@
+use proc_exit::WithCodeResultExt;
+
+pub fn tag(args: &crate::args::Args, tag_args: &crate::args::TagArgs) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let mut repo_config = git_stack::config::RepoConfig::load_for_write(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?;
+
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    let head_branch = crate::stack::attached_head_branch(&repo)
+        .ok_or_else(|| eyre::eyre!("Must not be in a detached HEAD state."))
+        .with_code(proc_exit::Code::USAGE_ERR)?;
+
+    log::trace!("git tag {} {}", tag_args.name, head_branch.name);
+    if !args.dry_run {
+        repo.tag(&tag_args.name, head_branch.id)
+            .with_code(proc_exit::Code::FAILURE)?;
+    }
+
+    let checkpoints = repo_config.checkpoints.get_or_insert_with(Vec::new);
+    if !checkpoints.contains(&tag_args.name) {
+        checkpoints.push(tag_args.name.clone());
+    }
+    if !args.dry_run {
+        repo_config
+            .write_repo(repo.raw())
+            .with_code(proc_exit::Code::FAILURE)?;
+    }
+    log::info!("Tagged {} as {}", head_branch.name, tag_args.name);
+
+    Ok(())
+}
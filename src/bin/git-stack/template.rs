@@ -0,0 +1,173 @@
+use proc_exit::WithCodeResultExt;
+
+/// Checked in, so a team shares its templates
+const REPO_TEMPLATES: &str = ".git-stack/templates";
+const USER_TEMPLATES: &str = "git-stack/templates";
+
+/// Scaffold a stack of empty branches, `<prefix>/<layer>`, from a template
+///
+/// Each layer tracks the one it is stacked on, so the stack keeps its shape until the layers have
+/// commits of their own.
+pub fn new(args: &crate::args::Args, new_args: &crate::args::NewArgs) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let path = find_template(&repo, &new_args.template).ok_or_else(|| {
+        proc_exit::Code::USAGE_ERR.with_message(format!(
+            "No template `{}` in {} or ~/.config/{}",
+            new_args.template, REPO_TEMPLATES, USER_TEMPLATES
+        ))
+    })?;
+    log::debug!("Using template {}", path.display());
+    let layers = read_template(&path).map_err(|err| {
+        proc_exit::Code::CONFIG_ERR.with_message(format!(
+            "Could not read template {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+    if layers.is_empty() {
+        return Err(proc_exit::Code::CONFIG_ERR.with_message(format!(
+            "Template {} has no `[layer \"<name>\"]` sections",
+            path.display()
+        )));
+    }
+
+    let head = repo.head().with_code(proc_exit::Code::USAGE_ERR)?;
+    let head_branch = head
+        .is_branch()
+        .then(|| head.shorthand().map(|s| s.to_owned()))
+        .flatten();
+    let head_commit = head
+        .peel_to_commit()
+        .with_code(proc_exit::Code::USAGE_ERR)?;
+
+    let branch_name = |layer: &str| format!("{}/{}", new_args.prefix, layer);
+    let mut branches = Vec::new();
+    for (i, layer) in layers.iter().enumerate() {
+        let name = branch_name(&layer.name);
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+            return Err(proc_exit::Code::USAGE_ERR
+                .with_message(format!("`{}` is not a valid branch name", name)));
+        }
+        if repo.find_branch(&name, git2::BranchType::Local).is_ok() {
+            return Err(proc_exit::Code::USAGE_ERR
+                .with_message(format!("Branch `{}` already exists", name)));
+        }
+        let parent = match (layer.parent.as_deref(), i) {
+            (Some(parent), _) => {
+                if !layers[..i].iter().any(|l| l.name == parent) {
+                    return Err(proc_exit::Code::CONFIG_ERR.with_message(format!(
+                        "Layer `{}` is stacked on `{}`, which must be an earlier layer",
+                        layer.name, parent
+                    )));
+                }
+                Some(branch_name(parent))
+            }
+            (None, 0) => head_branch.clone(),
+            (None, _) => Some(branch_name(&layers[i - 1].name)),
+        };
+        branches.push((name, parent, layer.description.as_deref()));
+    }
+
+    for (name, parent, description) in branches.iter() {
+        log::trace!("git branch {} {}", name, head_commit.id());
+        if args.dry_run {
+            continue;
+        }
+        repo.branch(name, &head_commit, false)
+            .with_code(proc_exit::Code::FAILURE)?;
+        let mut config = repo
+            .config()
+            .and_then(|c| c.open_level(git2::ConfigLevel::Local))
+            .with_code(proc_exit::Code::FAILURE)?;
+        if let Some(parent) = parent {
+            config
+                .set_str(&format!("branch.{}.remote", name), ".")
+                .with_code(proc_exit::Code::FAILURE)?;
+            config
+                .set_str(
+                    &format!("branch.{}.merge", name),
+                    &format!("refs/heads/{}", parent),
+                )
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+        if let Some(description) = description {
+            config
+                .set_str(&format!("branch.{}.description", name), description)
+                .with_code(proc_exit::Code::FAILURE)?;
+        }
+    }
+    let (bottom, _, _) = branches.first().expect("checked for layers");
+    log::info!(
+        "Created {} branches from `{}`, run `git switch {}` to start",
+        branches.len(),
+        new_args.template,
+        bottom
+    );
+
+    Ok(())
+}
+
+struct Layer {
+    name: String,
+    description: Option<String>,
+    /// Default: the previous layer
+    parent: Option<String>,
+}
+
+/// `name` as a path, or from the repo's, then the user's, templates
+fn find_template(repo: &git2::Repository, name: &str) -> Option<std::path::PathBuf> {
+    let path = std::path::Path::new(name);
+    if path.is_file() {
+        return Some(path.to_owned());
+    }
+    let user_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config"))
+        });
+    repo.workdir()
+        .map(|dir| dir.join(REPO_TEMPLATES))
+        .into_iter()
+        .chain(user_dir.map(|dir| dir.join(USER_TEMPLATES)))
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Layers, bottom first, from the `[layer "<name>"]` sections of a gitconfig-format file
+fn read_template(path: &std::path::Path) -> Result<Vec<Layer>, git2::Error> {
+    let config = git2::Config::open(path)?;
+    let mut layers: Vec<Layer> = Vec::new();
+    let entries = config.entries(Some(r"^layer\..*\.(description|parent)$"))?;
+    for entry in &entries {
+        let entry = entry?;
+        let key = match entry.name() {
+            Some(key) => key,
+            None => continue,
+        };
+        let (name, field) = match key.strip_prefix("layer.").and_then(|k| k.rsplit_once('.')) {
+            Some(split) => split,
+            None => continue,
+        };
+        let value = entry.value().map(|v| v.to_owned());
+        let index = match layers.iter().position(|l| l.name == name) {
+            Some(index) => index,
+            None => {
+                layers.push(Layer {
+                    name: name.to_owned(),
+                    description: None,
+                    parent: None,
+                });
+                layers.len() - 1
+            }
+        };
+        match field {
+            "description" => layers[index].description = value,
+            "parent" => layers[index].parent = value,
+            _ => {}
+        }
+    }
+    Ok(layers)
+}
@@ -0,0 +1,198 @@
+//! Opt-in counts of how `git stack` is used, kept in the repository and never sent anywhere
+
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+/// What `stack.usage-stats` records
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Usage {
+    /// Seconds since the epoch, when recording started
+    #[serde(default)]
+    since: u64,
+    #[serde(default)]
+    operations: std::collections::BTreeMap<String, Operation>,
+    #[serde(default)]
+    restacks: Restacks,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Operation {
+    runs: u64,
+    failures: u64,
+    total_ms: u64,
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct Restacks {
+    branches: u64,
+    conflicts: u64,
+}
+
+impl Usage {
+    fn load(path: &std::path::Path) -> Self {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    log::debug!("Could not read {}: {}", path.display(), err);
+                }
+                return Self::default();
+            }
+        };
+        serde_json::from_slice(&content).unwrap_or_else(|err| {
+            log::debug!("Ignoring {}: {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    fn save(&self, path: &std::path::Path) -> eyre::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Best-effort, as a failure to count shouldn't fail what was counted
+    fn update(repo: &git2::Repository, f: impl FnOnce(&mut Self)) {
+        let path = usage_path(repo);
+        let mut usage = Self::load(&path);
+        if usage.since == 0 {
+            usage.since = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+        }
+        f(&mut usage);
+        if let Err(err) = usage.save(&path) {
+            log::debug!("Could not record usage in {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// How a run shows up in the stats, e.g. `git stack --pull --push` or `git stack sync`
+pub fn operation_name(args: &crate::args::Args, subcommand: Option<&str>) -> String {
+    let mut name = "git stack".to_owned();
+    if let Some(subcommand) = subcommand {
+        name.push(' ');
+        name.push_str(subcommand);
+        return name;
+    }
+    if args.pull {
+        name.push_str(" --pull");
+    } else if args.rebase {
+        name.push_str(" --rebase");
+    }
+    if args.push {
+        name.push_str(" --push");
+    }
+    if args.check {
+        name.push_str(" --check");
+    }
+    name
+}
+
+/// Count a run of `operation`, if `stack.usage-stats` is set
+pub fn record_run(
+    args: &crate::args::Args,
+    operation: &str,
+    elapsed: std::time::Duration,
+    success: bool,
+) {
+    let cwd = match std::env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(_) => return,
+    };
+    let repo = match git2::Repository::discover(&cwd) {
+        Ok(repo) => repo,
+        Err(_) => return,
+    };
+    let enabled = git_stack::config::RepoConfig::from_all(&repo)
+        .map(|c| c.update(args.to_config()).usage_stats())
+        .unwrap_or(false);
+    if !enabled {
+        return;
+    }
+    Usage::update(&repo, |usage| {
+        let op = usage.operations.entry(operation.to_owned()).or_default();
+        op.runs += 1;
+        if !success {
+            op.failures += 1;
+        }
+        op.total_ms += elapsed.as_millis() as u64;
+    });
+}
+
+/// Count the branches a restack tried to move and how many of them conflicted
+///
+/// Callers check `stack.usage-stats`.
+pub fn record_restacks(repo: &git2::Repository, branches: usize, conflicts: usize) {
+    Usage::update(repo, |usage| {
+        usage.restacks.branches += branches as u64;
+        usage.restacks.conflicts += conflicts as u64;
+    });
+}
+
+/// `git stack stats --self`
+pub fn show(args: &crate::args::Args) -> proc_exit::ExitResult {
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let enabled = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config())
+        .usage_stats();
+
+    let path = usage_path(&repo);
+    if !path.exists() {
+        if enabled {
+            log::info!("Nothing recorded yet");
+        } else {
+            log::info!(
+                "Nothing recorded; to count runs, durations, and conflicts in {}, run `git config stack.usage-stats true`",
+                path.display()
+            );
+        }
+        return Ok(());
+    }
+    if !enabled {
+        log::warn!("`stack.usage-stats` is off, so these are no longer being updated");
+    }
+
+    let usage = Usage::load(&path);
+    let since = std::time::UNIX_EPOCH + std::time::Duration::from_secs(usage.since);
+    let mut stdout = std::io::stdout();
+    writeln!(
+        stdout,
+        "Since {}:",
+        humantime::format_rfc3339_seconds(since)
+    )?;
+    let width = usage.operations.keys().map(|k| k.len()).max().unwrap_or(0);
+    for (name, op) in usage.operations.iter() {
+        let average = std::time::Duration::from_millis(op.total_ms / op.runs.max(1));
+        write!(stdout, "  {:width$}  {} runs", name, op.runs, width = width)?;
+        if op.failures != 0 {
+            write!(stdout, ", {} failed", op.failures)?;
+        }
+        writeln!(
+            stdout,
+            ", {} on average",
+            humantime::format_duration(average)
+        )?;
+    }
+    if usage.restacks.branches != 0 {
+        writeln!(
+            stdout,
+            "Restacked {} branches, {} ({}%) with conflicts",
+            usage.restacks.branches,
+            usage.restacks.conflicts,
+            usage.restacks.conflicts * 100 / usage.restacks.branches
+        )?;
+    }
+
+    Ok(())
+}
+
+fn usage_path(repo: &git2::Repository) -> std::path::PathBuf {
+    repo.path().join("stack").join("usage.json")
+}
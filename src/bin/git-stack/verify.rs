@@ -0,0 +1,274 @@
+use std::io::Write;
+
+use proc_exit::WithCodeResultExt;
+
+/// Something out of sync in what `git stack` keeps alongside the branches
+struct Problem {
+    message: String,
+    repair: Option<Repair>,
+}
+
+enum Repair {
+    /// Re-infer or drop broken branch metadata, like `git stack repair-metadata`
+    Metadata,
+    /// Remove a value from a branch's multivar setting
+    UnsetConfig {
+        key: String,
+        value: String,
+    },
+    DeleteReference(String),
+    ClearFocus,
+    DropCheckpoint(String),
+    RemoveSnapshot(std::path::PathBuf),
+    SaveSnapshot(std::path::PathBuf, git_stack::stash::Snapshot),
+    PruneCommitCache,
+}
+
+pub fn verify(
+    args: &crate::args::Args,
+    verify_args: &crate::args::VerifyArgs,
+) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let cwd = std::env::current_dir().with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo = git2::Repository::discover(&cwd).with_code(proc_exit::Code::USAGE_ERR)?;
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo)
+        .with_code(proc_exit::Code::CONFIG_ERR)?
+        .update(args.to_config());
+    let mut repo = git_stack::git::GitRepo::new(repo);
+    if repo_config.commit_cache() {
+        repo.open_commit_cache();
+    }
+
+    let problems = check(&repo, &repo_config);
+    if problems.is_empty() {
+        log::info!("No problems found");
+        return Ok(());
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut remaining = 0;
+    let mut metadata_repaired = false;
+    for problem in problems.iter() {
+        match problem.repair.as_ref() {
+            Some(repair) if verify_args.repair => {
+                log::trace!("Repairing: {}", problem.message);
+                // One pass fixes all of the metadata
+                let repeat = matches!(repair, Repair::Metadata) && metadata_repaired;
+                metadata_repaired |= matches!(repair, Repair::Metadata);
+                if !args.dry_run && !repeat {
+                    apply(&repo, &repo_config, repair).with_code(proc_exit::Code::FAILURE)?;
+                }
+                log::info!("Repaired: {}", problem.message);
+            }
+            _ => {
+                writeln!(stdout, "{}", problem.message)?;
+                remaining += 1;
+            }
+        }
+    }
+    if remaining == 0 {
+        return Ok(());
+    }
+
+    let repairable = problems.iter().filter(|p| p.repair.is_some()).count();
+    let mut message = format!("{} problem(s) found", remaining);
+    if !verify_args.repair && repairable != 0 {
+        message.push_str(&format!(
+            ", run `git stack verify --repair` to fix {} of them",
+            repairable
+        ));
+    }
+    Err(proc_exit::Code::FAILURE.with_message(message))
+}
+
+fn check(
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+) -> Vec<Problem> {
+    let raw = repo.raw();
+    let exists = |name: &str| raw.find_branch(name, git2::BranchType::Local).is_ok();
+    let mut problems = Vec::new();
+
+    for problem in crate::metadata::problems(raw) {
+        problems.push(Problem {
+            message: format!("Branch metadata: {}", problem),
+            repair: Some(Repair::Metadata),
+        });
+    }
+    // Branch config usually goes along with `git branch -D`, except when it doesn't
+    for (branch, labels) in crate::label::branch_labels(raw) {
+        if exists(&branch) {
+            continue;
+        }
+        for label in labels {
+            problems.push(Problem {
+                message: format!("Label `{}` is on deleted branch {}", label, branch),
+                repair: Some(Repair::UnsetConfig {
+                    key: crate::label::label_key(&branch),
+                    value: label,
+                }),
+            });
+        }
+    }
+    for (branch, on) in crate::depend::branch_dependencies(raw) {
+        if exists(&branch) {
+            continue;
+        }
+        problems.push(Problem {
+            message: format!("Deleted branch {} depends on {}", branch, on),
+            repair: Some(Repair::UnsetConfig {
+                key: crate::depend::depends_key(&branch),
+                value: on,
+            }),
+        });
+    }
+
+    for (branch, archived_id) in crate::archive::archived_branches(raw) {
+        let branch_id = match raw
+            .find_branch(&branch, git2::BranchType::Local)
+            .ok()
+            .and_then(|b| b.get().target())
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        // An archived copy that the live branch has moved past is just stale
+        let stale = archived_id == branch_id
+            || raw
+                .graph_descendant_of(branch_id, archived_id)
+                .unwrap_or(false);
+        problems.push(Problem {
+            message: if stale {
+                format!("{} is both archived and live, though the archive is outdated", branch)
+            } else {
+                format!(
+                    "{} is both archived and live, with different commits; delete one with `git branch -D` or `git update-ref -d {}{}`",
+                    branch,
+                    crate::archive::ARCHIVE_PREFIX,
+                    branch
+                )
+            },
+            repair: stale.then(|| {
+                Repair::DeleteReference(format!("{}{}", crate::archive::ARCHIVE_PREFIX, branch))
+            }),
+        });
+    }
+
+    if let Some(branch) = crate::focus::read_focus(raw) {
+        if !exists(&branch) {
+            problems.push(Problem {
+                message: format!("Focused on deleted branch {}", branch),
+                repair: Some(Repair::ClearFocus),
+            });
+        }
+    }
+    for tag in repo_config.checkpoints() {
+        if repo.find_tag(tag).is_none() {
+            problems.push(Problem {
+                message: format!("Checkpoint {} has no tag", tag),
+                repair: Some(Repair::DropCheckpoint(tag.clone())),
+            });
+        }
+    }
+
+    for stack in git_stack::stash::Stack::all(repo) {
+        for path in stack.iter() {
+            let mut snapshot = match git_stack::stash::Snapshot::load(&path) {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    problems.push(Problem {
+                        message: format!("Snapshot {} is unreadable: {}", path.display(), err),
+                        repair: Some(Repair::RemoveSnapshot(path)),
+                    });
+                    continue;
+                }
+            };
+            let missing: Vec<_> = snapshot
+                .branches
+                .iter()
+                .filter(|b| raw.find_commit(b.id).is_err())
+                .map(|b| b.name.clone())
+                .collect();
+            if !missing.is_empty() {
+                snapshot.branches.retain(|b| !missing.contains(&b.name));
+                problems.push(Problem {
+                    message: format!(
+                        "Snapshot {} refers to commits that no longer exist, for {}",
+                        path.display(),
+                        missing.join(", ")
+                    ),
+                    repair: Some(Repair::SaveSnapshot(path, snapshot)),
+                });
+            }
+        }
+    }
+    if git_stack::git::Journal::path(raw).exists() {
+        problems.push(Problem {
+            message: "A rewrite was interrupted, run `git stack recover`".to_owned(),
+            repair: None,
+        });
+    }
+
+    let stale = repo.prune_commit_cache(true);
+    if stale != 0 {
+        problems.push(Problem {
+            message: format!("{} cached commit(s) don't match the repository", stale),
+            repair: Some(Repair::PruneCommitCache),
+        });
+    }
+
+    problems
+}
+
+fn apply(
+    repo: &git_stack::git::GitRepo,
+    repo_config: &git_stack::config::RepoConfig,
+    repair: &Repair,
+) -> eyre::Result<()> {
+    let raw = repo.raw();
+    match repair {
+        Repair::Metadata => {
+            let ignored = git_stack::git::BranchGlobs::new(
+                repo_config.ignore_branches().iter().map(|s| s.as_str()),
+            )?;
+            crate::metadata::repair_metadata(raw, &ignored, false)?;
+        }
+        Repair::UnsetConfig { key, value } => {
+            log::trace!("git config --unset {} {}", key, value);
+            crate::label::local_config(raw)?
+                .remove_multivar(key, &format!("^{}$", regex::escape(value)))?;
+        }
+        Repair::DeleteReference(name) => {
+            log::trace!("git update-ref -d {}", name);
+            raw.find_reference(name)?.delete()?;
+            if let Some(branch) = name.strip_prefix(crate::archive::ARCHIVE_PREFIX) {
+                crate::archive::forget_config(raw, branch)?;
+            }
+        }
+        Repair::ClearFocus => {
+            let path = crate::focus::focus_path(raw);
+            log::trace!("rm {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+        Repair::DropCheckpoint(tag) => {
+            // Only what the repo's own config has, so nothing else gets copied into it
+            let mut repo_config = git_stack::config::RepoConfig::load_for_write(raw)?;
+            repo_config
+                .checkpoints
+                .get_or_insert_with(Vec::new)
+                .retain(|t| t != tag);
+            repo_config.write_repo(raw)?;
+        }
+        Repair::RemoveSnapshot(path) => {
+            log::trace!("rm {}", path.display());
+            std::fs::remove_file(path)?;
+        }
+        Repair::SaveSnapshot(path, snapshot) => {
+            snapshot.save(path)?;
+        }
+        Repair::PruneCommitCache => {
+            repo.prune_commit_cache(false);
+        }
+    }
+    Ok(())
+}
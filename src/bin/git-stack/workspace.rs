@@ -0,0 +1,116 @@
+use proc_exit::WithCodeResultExt;
+
+/// Run this same `git stack` invocation in each repository of a workspace
+pub fn recurse(args: &crate::args::Args, workspace: &std::path::Path) -> proc_exit::ExitResult {
+    log::trace!("Initializing");
+    let repos = if workspace.is_file() {
+        read_manifest(workspace).with_code(proc_exit::Code::USAGE_ERR)?
+    } else {
+        discover(workspace).with_code(proc_exit::Code::USAGE_ERR)?
+    };
+    if repos.is_empty() {
+        return Err(proc_exit::Code::USAGE_ERR
+            .with_message(format!("No repositories found in {}", workspace.display())));
+    }
+
+    let exe = std::env::current_exe().with_code(proc_exit::Code::FAILURE)?;
+    let repo_args = without_recurse(std::env::args_os().skip(1));
+    let progress = crate::progress::Progress::new(args.non_interactive());
+
+    let mut failed = Vec::new();
+    for repo in repos.iter() {
+        use std::io::Write;
+        writeln!(std::io::stdout(), "## {}", repo.display())?;
+        // Keep our output ordered ahead of the child's
+        std::io::stdout().flush()?;
+
+        let mut cmd = std::process::Command::new(&exe);
+        cmd.args(&repo_args).current_dir(repo);
+        log::trace!("Running {:?} in {}", cmd, repo.display());
+        let status = match cmd.status() {
+            Ok(status) => status,
+            Err(err) => {
+                log::error!("Could not run in {}: {}", repo.display(), err);
+                progress.emit(
+                    "repo",
+                    serde_json::json!({ "path": repo, "success": false, "code": null }),
+                );
+                failed.push(repo);
+                continue;
+            }
+        };
+        progress.emit(
+            "repo",
+            serde_json::json!({
+                "path": repo,
+                "success": status.success(),
+                "code": status.code(),
+            }),
+        );
+        if !status.success() {
+            failed.push(repo);
+        }
+        writeln!(std::io::stdout())?;
+    }
+
+    if failed.is_empty() {
+        log::info!("Finished in {} repositories", repos.len());
+        Ok(())
+    } else {
+        let mut message = format!(
+            "Failed in {} of {} repositories:",
+            failed.len(),
+            repos.len()
+        );
+        for repo in failed {
+            message.push_str(&format!("\n  {}", repo.display()));
+        }
+        Err(proc_exit::Code::FAILURE.with_message(message))
+    }
+}
+
+/// Repositories listed one per line, relative to the manifest, with `#` comments
+fn read_manifest(path: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let root = path.parent().unwrap_or_else(|| std::path::Path::new(""));
+    let content = std::fs::read_to_string(path)?;
+    let repos = content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| root.join(line))
+        .collect();
+    Ok(repos)
+}
+
+/// Repositories under `root`, not looking inside of them or hidden directories
+fn discover(root: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut repos = Vec::new();
+    let mut pending = vec![root.to_owned()];
+    while let Some(dir) = pending.pop() {
+        if dir.join(".git").exists() {
+            repos.push(dir);
+            continue;
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !hidden && entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+    }
+    repos.sort();
+    Ok(repos)
+}
+
+fn without_recurse(mut args: impl Iterator<Item = std::ffi::OsString>) -> Vec<std::ffi::OsString> {
+    let mut remaining = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--recurse" {
+            args.next();
+        } else if !arg.to_string_lossy().starts_with("--recurse=") {
+            remaining.push(arg);
+        }
+    }
+    remaining
+}
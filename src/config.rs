@@ -3,8 +3,11 @@ use std::str::FromStr;
 #[derive(Default, Clone, Debug)]
 pub struct RepoConfig {
     pub protected_branches: Option<Vec<String>>,
+    pub ignore_branches: Option<Vec<String>>,
     pub protect_commit_count: Option<usize>,
     pub protect_commit_age: Option<std::time::Duration>,
+    pub stale_age: Option<std::time::Duration>,
+    pub hide_age: Option<std::time::Duration>,
     pub stack: Option<Stack>,
     pub push_remote: Option<String>,
     pub pull_remote: Option<String>,
@@ -12,13 +15,50 @@ pub struct RepoConfig {
     pub show_stacked: Option<bool>,
     pub auto_fixup: Option<Fixup>,
     pub auto_repair: Option<bool>,
+    pub require_fresh_base: Option<FreshBase>,
+    pub max_rewrite_commits: Option<usize>,
+    pub large_file_threshold: Option<usize>,
+    pub confirm: Option<Confirm>,
+    pub checkpoints: Option<Vec<String>>,
+    pub jobs: Option<usize>,
+    pub commit_cache: Option<bool>,
+    pub show_max_commits: Option<usize>,
+    pub scope_path: Option<String>,
+    pub show_touched_dirs: Option<bool>,
+    pub issue_pattern: Option<String>,
+    pub issue_url: Option<String>,
+    pub show_commit_types: Option<bool>,
+    pub show_reviews: Option<bool>,
+    pub push_retries: Option<usize>,
+    pub delete_remote: Option<DeleteRemote>,
+    pub show_columns: Option<Columns>,
+    pub protection_action: Option<ProtectionAction>,
+    pub forge: Option<Forge>,
+    pub forge_command: Option<String>,
+    pub forge_token: Option<String>,
+    pub offline: Option<bool>,
+    pub keep_going: Option<bool>,
+    pub usage_stats: Option<bool>,
+    pub squash_message: Option<SquashMessage>,
+    pub commit_template: Option<String>,
+    pub empty_commits: Option<EmptyCommits>,
+    pub merge_options: Option<MergeOptions>,
+    pub push_refspec: Option<PushRefspec>,
+    pub ci_trigger: Option<CiTrigger>,
+    pub ci_wait: Option<std::time::Duration>,
+    pub summary: Option<Summary>,
+    pub max_commits_per_branch: Option<usize>,
+    pub max_commits_action: Option<MaxCommitsAction>,
 
     pub capacity: Option<usize>,
 }
 
 static PROTECTED_STACK_FIELD: &str = "stack.protected-branch";
+static IGNORE_BRANCH_FIELD: &str = "stack.ignore-branch";
 static PROTECT_COMMIT_COUNT: &str = "stack.protect-commit-count";
 static PROTECT_COMMIT_AGE: &str = "stack.protect-commit-age";
+static STALE_AGE_FIELD: &str = "stack.stale-age";
+static HIDE_AGE_FIELD: &str = "stack.hide-age";
 static STACK_FIELD: &str = "stack.stack";
 static PUSH_REMOTE_FIELD: &str = "stack.push-remote";
 static PULL_REMOTE_FIELD: &str = "stack.pull-remote";
@@ -26,36 +66,196 @@ static FORMAT_FIELD: &str = "stack.show-format";
 static STACKED_FIELD: &str = "stack.show-stacked";
 static AUTO_FIXUP_FIELD: &str = "stack.auto-fixup";
 static AUTO_REPAIR_FIELD: &str = "stack.auto-repair";
+static REQUIRE_FRESH_BASE_FIELD: &str = "stack.require-fresh-base";
+static MAX_REWRITE_COMMITS_FIELD: &str = "stack.max-rewrite-commits";
+static LARGE_FILE_THRESHOLD_FIELD: &str = "stack.large-file-threshold";
+static CONFIRM_FIELD: &str = "stack.confirm";
+static CHECKPOINT_FIELD: &str = "stack.checkpoint";
+static JOBS_FIELD: &str = "stack.jobs";
+static COMMIT_CACHE_FIELD: &str = "stack.commit-cache";
+static SHOW_MAX_COMMITS_FIELD: &str = "stack.show-max-commits";
+static SCOPE_PATH_FIELD: &str = "stack.scope-path";
+static TOUCHED_DIRS_FIELD: &str = "stack.show-touched-dirs";
+static ISSUE_PATTERN_FIELD: &str = "stack.issue-pattern";
+static ISSUE_URL_FIELD: &str = "stack.issue-url";
+static COMMIT_TYPES_FIELD: &str = "stack.show-commit-types";
+static REVIEWS_FIELD: &str = "stack.show-reviews";
+static PUSH_RETRIES_FIELD: &str = "stack.push-retries";
+static DELETE_REMOTE_FIELD: &str = "stack.delete-remote";
+static SHOW_COLUMNS_FIELD: &str = "stack.show-columns";
+static PROTECTION_ACTION_FIELD: &str = "stack.protection-action";
+static CONFIG_SOURCE_FIELD: &str = "stack.config-source";
+static FORGE_FIELD: &str = "stack.forge";
+static FORGE_COMMAND_FIELD: &str = "stack.forge-command";
+static FORGE_TOKEN_FIELD: &str = "stack.forge-token";
+static OFFLINE_FIELD: &str = "stack.offline";
+static KEEP_GOING_FIELD: &str = "stack.keep-going";
+static USAGE_STATS_FIELD: &str = "stack.usage-stats";
+static SQUASH_MESSAGE_FIELD: &str = "stack.squash-message";
+static COMMIT_TEMPLATE_FIELD: &str = "stack.commit-template";
+static EMPTY_COMMITS_FIELD: &str = "stack.empty-commits";
+static MERGE_OPTIONS_FIELD: &str = "stack.merge-options";
+static PUSH_REFSPEC_FIELD: &str = "stack.push-refspec";
+static CI_TRIGGER_FIELD: &str = "stack.ci-trigger";
+static CI_WAIT_FIELD: &str = "stack.ci-wait";
+static SUMMARY_FIELD: &str = "stack.summary";
+static MAX_COMMITS_PER_BRANCH_FIELD: &str = "stack.max-commits-per-branch";
+static MAX_COMMITS_ACTION_FIELD: &str = "stack.max-commits-action";
 static BACKUP_CAPACITY_FIELD: &str = "branch-stash.capacity";
 
 static DEFAULT_PROTECTED_BRANCHES: [&str; 4] = ["main", "master", "dev", "stable"];
 static DEFAULT_PROTECT_COMMIT_COUNT: usize = 50;
 static DEFAULT_PROTECT_COMMIT_AGE: std::time::Duration =
     std::time::Duration::from_secs(60 * 60 * 24 * 14);
+static DEFAULT_STALE_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 24 * 21);
+static DEFAULT_MAX_REWRITE_COMMITS: usize = 200;
+static DEFAULT_SHOW_MAX_COMMITS: usize = 1000;
 const DEFAULT_CAPACITY: usize = 30;
 
 impl RepoConfig {
     pub fn from_all(repo: &git2::Repository) -> eyre::Result<Self> {
         log::trace!("Loading gitconfig");
-        let default_config = match git2::Config::open_default() {
+        // Open with repository context so `includeIf.gitdir` / `includeIf.onbranch` are evaluated
+        let repo_config = match repo.config() {
             Ok(config) => Some(config),
             Err(err) => {
                 log::debug!("Failed to load git config: {}", err);
                 None
             }
         };
-        let config = Self::from_defaults_internal(default_config.as_ref());
-        let config = if let Some(default_config) = default_config.as_ref() {
-            config.update(Self::from_gitconfig(default_config))
+        let config = Self::from_defaults_internal(repo_config.as_ref());
+        let config = if let Some(repo_config) = repo_config.as_ref() {
+            config.update(Self::from_levels(repo_config, USER_LEVELS))
         } else {
             config
         };
         let config = config.update(Self::from_workdir(repo)?);
-        let config = config.update(Self::from_repo(repo)?);
+        let config = if let Some(repo_config) = repo_config.as_ref() {
+            config.update(Self::from_levels(repo_config, REPO_LEVELS))
+        } else {
+            config.update(Self::from_repo(repo)?)
+        };
         let config = config.update(Self::from_env());
+
+        for diagnostic in Self::diagnose(repo) {
+            log::warn!("{}", diagnostic);
+        }
+
         Ok(config)
     }
 
+    /// Report config values that can't be understood and are being ignored
+    pub fn diagnose(repo: &git2::Repository) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        match repo.config() {
+            Ok(config) => {
+                for level in USER_LEVELS.iter().chain(REPO_LEVELS) {
+                    let path = match level {
+                        git2::ConfigLevel::System => git2::Config::find_system().ok(),
+                        git2::ConfigLevel::XDG => git2::Config::find_xdg().ok(),
+                        git2::ConfigLevel::Global => git2::Config::find_global().ok(),
+                        git2::ConfigLevel::Local => Some(git_dir_config(repo)),
+                        _ => None,
+                    };
+                    if let Ok(config) = config.open_level(*level) {
+                        diagnostics.extend(diagnose_gitconfig(&config, path.as_deref()));
+                    }
+                }
+            }
+            Err(err) => {
+                log::debug!("Failed to load git config: {}", err);
+            }
+        }
+
+        if let Some(workdir) = repo.workdir() {
+            let config_path = workdir.join(".gitconfig");
+            if config_path.exists() {
+                if let Ok(config) = git2::Config::open(&config_path) {
+                    diagnostics.extend(diagnose_gitconfig(&config, Some(&config_path)));
+                }
+            }
+        }
+
+        let params = git_config_env::ConfigParameters::new();
+        diagnostics.extend(diagnose_env(params.iter(), "GIT_CONFIG_PARAMETERS"));
+        let params = git_config_env::ConfigEnv::new();
+        diagnostics.extend(diagnose_env(
+            params.iter().map(|(k, v)| (k, Some(v))),
+            "GIT_CONFIG_COUNT",
+        ));
+        for (var, key, value) in dedicated_env() {
+            diagnostics.extend(diagnose_env(
+                std::iter::once((key.into(), Some(value.into()))),
+                var,
+            ));
+        }
+
+        diagnostics
+    }
+
+    /// Report values in a standalone config file that can't be understood
+    pub fn diagnose_path(path: &std::path::Path) -> Vec<Diagnostic> {
+        match git2::Config::open(path) {
+            Ok(config) => diagnose_gitconfig(&config, Some(path)),
+            Err(err) => {
+                log::debug!("Failed to load {}: {}", path.display(), err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Layer the given levels of `config`, lowest precedence first
+    ///
+    /// Conditional includes are resolved within the level that includes them.
+    fn from_levels(config: &git2::Config, levels: &[git2::ConfigLevel]) -> Self {
+        levels
+            .iter()
+            .filter_map(|level| match config.open_level(*level) {
+                Ok(config) => Some(config),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+                Err(err) => {
+                    log::debug!("Failed to load git config level {:?}: {}", level, err);
+                    None
+                }
+            })
+            .fold(Self::default(), |layered, config| {
+                layered.update(Self::from_gitconfig(&config))
+            })
+    }
+
+    /// The config to update and [`RepoConfig::write_repo`] back
+    ///
+    /// Only what `.git/config` itself sets is loaded, leaving out this run's flags, the other
+    /// levels, and the files it `include.path`s, so writing doesn't copy them in.
+    pub fn load_for_write(repo: &git2::Repository) -> eyre::Result<Self> {
+        let config_path = git_dir_config(repo);
+        log::trace!("Loading {}", config_path.display());
+        if !config_path.exists() {
+            return Ok(Default::default());
+        }
+        // Failing to load must not be mistaken for being empty, as lists are replaced on write
+        let config = git2::Config::open(&config_path)?;
+        let mut values = Vec::new();
+        for entry in &config.entries(Some(r"^(stack|branch-stash)\."))? {
+            let entry = entry?;
+            if entry.include_depth() != 0 {
+                continue;
+            }
+            if let Some(name) = entry.name() {
+                values.push((name.to_owned(), entry.value().map(|v| v.to_owned())));
+            }
+        }
+        Ok(Self::from_env_iter(values.into_iter().map(
+            |(key, value)| {
+                (
+                    std::borrow::Cow::Owned(key),
+                    value.map(std::borrow::Cow::Owned),
+                )
+            },
+        )))
+    }
+
     pub fn from_repo(repo: &git2::Repository) -> eyre::Result<Self> {
         let config_path = git_dir_config(repo);
         log::trace!("Loading {}", config_path.display());
@@ -73,9 +273,13 @@ impl RepoConfig {
     }
 
     pub fn from_workdir(repo: &git2::Repository) -> eyre::Result<Self> {
-        let workdir = repo
-            .workdir()
-            .ok_or_else(|| eyre::eyre!("Cannot read config in bare repository."))?;
+        let workdir = match repo.workdir() {
+            Some(workdir) => workdir,
+            None => {
+                log::trace!("No `.gitconfig` to load in a bare repository");
+                return Ok(Default::default());
+            }
+        };
         let config_path = workdir.join(".gitconfig");
         log::trace!("Loading {}", config_path.display());
         if config_path.exists() {
@@ -91,6 +295,13 @@ impl RepoConfig {
         }
     }
 
+    /// Read a standalone config file, like a shared team fragment
+    pub fn from_path(path: &std::path::Path) -> eyre::Result<Self> {
+        log::trace!("Loading {}", path.display());
+        let config = git2::Config::open(path)?;
+        Ok(Self::from_gitconfig(&config))
+    }
+
     pub fn from_env() -> Self {
         let mut config = Self::default();
 
@@ -102,6 +313,12 @@ impl RepoConfig {
             params.iter().map(|(k, v)| (k, Some(v))),
         ));
 
+        config = config.update(Self::from_env_iter(
+            dedicated_env()
+                .into_iter()
+                .map(|(_, key, value)| (key.into(), Some(value.into()))),
+        ));
+
         config
     }
 
@@ -119,6 +336,13 @@ impl RepoConfig {
                         .get_or_insert_with(Vec::new)
                         .push(value.into_owned());
                 }
+            } else if key == IGNORE_BRANCH_FIELD {
+                if let Some(value) = value {
+                    config
+                        .ignore_branches
+                        .get_or_insert_with(Vec::new)
+                        .push(value.into_owned());
+                }
             } else if key == PROTECT_COMMIT_COUNT {
                 if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
                     config.protect_commit_count = Some(value);
@@ -130,6 +354,20 @@ impl RepoConfig {
                 {
                     config.protect_commit_age = Some(value);
                 }
+            } else if key == STALE_AGE_FIELD {
+                if let Some(value) = value
+                    .as_ref()
+                    .and_then(|v| humantime::parse_duration(v).ok())
+                {
+                    config.stale_age = Some(value);
+                }
+            } else if key == HIDE_AGE_FIELD {
+                if let Some(value) = value
+                    .as_ref()
+                    .and_then(|v| humantime::parse_duration(v).ok())
+                {
+                    config.hide_age = Some(value);
+                }
             } else if key == STACK_FIELD {
                 if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
                     config.stack = Some(value);
@@ -154,6 +392,136 @@ impl RepoConfig {
                 }
             } else if key == AUTO_REPAIR_FIELD {
                 config.auto_repair = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == REQUIRE_FRESH_BASE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.require_fresh_base = Some(value);
+                }
+            } else if key == MAX_REWRITE_COMMITS_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.max_rewrite_commits = Some(value);
+                }
+            } else if key == LARGE_FILE_THRESHOLD_FIELD {
+                if let Some(value) = value.as_deref().and_then(parse_git_int) {
+                    config.large_file_threshold = Some(value.max(0) as usize);
+                }
+            } else if key == CONFIRM_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.confirm = Some(value);
+                }
+            } else if key == CHECKPOINT_FIELD {
+                if let Some(value) = value {
+                    config
+                        .checkpoints
+                        .get_or_insert_with(Vec::new)
+                        .push(value.into_owned());
+                }
+            } else if key == JOBS_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.jobs = Some(value);
+                }
+            } else if key == COMMIT_CACHE_FIELD {
+                config.commit_cache = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == SHOW_MAX_COMMITS_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.show_max_commits = Some(value);
+                }
+            } else if key == SCOPE_PATH_FIELD {
+                if let Some(value) = value {
+                    config.scope_path = Some(value.into_owned());
+                }
+            } else if key == TOUCHED_DIRS_FIELD {
+                config.show_touched_dirs =
+                    Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == ISSUE_PATTERN_FIELD {
+                if let Some(value) = value {
+                    config.issue_pattern = Some(value.into_owned());
+                }
+            } else if key == ISSUE_URL_FIELD {
+                if let Some(value) = value {
+                    config.issue_url = Some(value.into_owned());
+                }
+            } else if key == COMMIT_TYPES_FIELD {
+                config.show_commit_types =
+                    Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == REVIEWS_FIELD {
+                config.show_reviews = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == PUSH_RETRIES_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.push_retries = Some(value);
+                }
+            } else if key == DELETE_REMOTE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.delete_remote = Some(value);
+                }
+            } else if key == SHOW_COLUMNS_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.show_columns = Some(value);
+                }
+            } else if key == PROTECTION_ACTION_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.protection_action = Some(value);
+                }
+            } else if key == FORGE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.forge = Some(value);
+                }
+            } else if key == FORGE_COMMAND_FIELD {
+                if let Some(value) = value {
+                    config.forge_command = Some(value.into_owned());
+                }
+            } else if key == FORGE_TOKEN_FIELD {
+                if let Some(value) = value {
+                    config.forge_token = Some(value.into_owned());
+                }
+            } else if key == OFFLINE_FIELD {
+                config.offline = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == KEEP_GOING_FIELD {
+                config.keep_going = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == USAGE_STATS_FIELD {
+                config.usage_stats = Some(value.as_ref().map(|v| v == "true").unwrap_or(true));
+            } else if key == SQUASH_MESSAGE_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.squash_message = Some(value);
+                }
+            } else if key == COMMIT_TEMPLATE_FIELD {
+                if let Some(value) = value {
+                    config.commit_template = Some(value.into_owned());
+                }
+            } else if key == EMPTY_COMMITS_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.empty_commits = Some(value);
+                }
+            } else if key == MERGE_OPTIONS_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.merge_options = Some(value);
+                }
+            } else if key == PUSH_REFSPEC_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.push_refspec = Some(value);
+                }
+            } else if key == CI_TRIGGER_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.ci_trigger = Some(value);
+                }
+            } else if key == CI_WAIT_FIELD {
+                if let Some(value) = value
+                    .as_ref()
+                    .and_then(|v| humantime::parse_duration(v).ok())
+                {
+                    config.ci_wait = Some(value);
+                }
+            } else if key == SUMMARY_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.summary = Some(value);
+                }
+            } else if key == MAX_COMMITS_PER_BRANCH_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.max_commits_per_branch = Some(value);
+                }
+            } else if key == MAX_COMMITS_ACTION_FIELD {
+                if let Some(value) = value.as_ref().and_then(|v| FromStr::from_str(v).ok()) {
+                    config.max_commits_action = Some(value);
+                }
             } else if key == BACKUP_CAPACITY_FIELD {
                 config.capacity = value.as_deref().and_then(|s| s.parse::<usize>().ok());
             } else {
@@ -184,12 +552,33 @@ impl RepoConfig {
         let mut conf = Self::default();
         conf.protect_commit_count = Some(conf.protect_commit_count().unwrap_or(0));
         conf.protect_commit_age = Some(conf.protect_commit_age());
+        conf.stale_age = Some(conf.stale_age());
+        conf.hide_age = Some(conf.hide_age().unwrap_or_default());
         conf.stack = Some(conf.stack());
         conf.push_remote = Some(conf.push_remote().to_owned());
         conf.pull_remote = Some(conf.pull_remote().to_owned());
         conf.show_format = Some(conf.show_format());
         conf.show_stacked = Some(conf.show_stacked());
         conf.auto_fixup = Some(conf.auto_fixup());
+        conf.require_fresh_base = Some(conf.require_fresh_base());
+        conf.max_rewrite_commits = Some(conf.max_rewrite_commits().unwrap_or(0));
+        conf.large_file_threshold = Some(conf.large_file_threshold().unwrap_or(0));
+        conf.confirm = Some(conf.confirm());
+        conf.show_max_commits = Some(conf.show_max_commits().unwrap_or(0));
+        conf.show_touched_dirs = Some(conf.show_touched_dirs());
+        conf.show_commit_types = Some(conf.show_commit_types());
+        conf.show_reviews = Some(conf.show_reviews());
+        conf.show_columns = Some(conf.show_columns());
+        conf.push_retries = Some(conf.push_retries());
+        conf.delete_remote = Some(conf.delete_remote());
+        conf.protection_action = Some(conf.protection_action());
+        conf.forge = Some(conf.forge());
+        conf.squash_message = Some(conf.squash_message());
+        conf.empty_commits = Some(conf.empty_commits());
+        conf.merge_options = Some(conf.merge_options());
+        conf.summary = Some(conf.summary());
+        conf.max_commits_per_branch = Some(conf.max_commits_per_branch().unwrap_or(0));
+        conf.max_commits_action = Some(conf.max_commits_action());
         conf.capacity = Some(DEFAULT_CAPACITY);
 
         let mut protected_branches: Vec<String> = Vec::new();
@@ -224,6 +613,22 @@ impl RepoConfig {
             })
             .unwrap_or(None);
 
+        let ignore_branches = config
+            .multivar(IGNORE_BRANCH_FIELD, None)
+            .map(|entries| {
+                let entries_ref = &entries;
+                let ignore_branches: Vec<_> = entries_ref
+                    .flat_map(|e| e.into_iter())
+                    .filter_map(|e| e.value().map(|v| v.to_owned()))
+                    .collect();
+                if ignore_branches.is_empty() {
+                    None
+                } else {
+                    Some(ignore_branches)
+                }
+            })
+            .unwrap_or(None);
+
         let protect_commit_count = config
             .get_i64(PROTECT_COMMIT_COUNT)
             .ok()
@@ -232,6 +637,14 @@ impl RepoConfig {
             .get_string(PROTECT_COMMIT_AGE)
             .ok()
             .and_then(|s| humantime::parse_duration(&s).ok());
+        let stale_age = config
+            .get_string(STALE_AGE_FIELD)
+            .ok()
+            .and_then(|s| humantime::parse_duration(&s).ok());
+        let hide_age = config
+            .get_string(HIDE_AGE_FIELD)
+            .ok()
+            .and_then(|s| humantime::parse_duration(&s).ok());
 
         let push_remote = config.get_string(PUSH_REMOTE_FIELD).ok();
         let pull_remote = config.get_string(PULL_REMOTE_FIELD).ok();
@@ -255,6 +668,135 @@ impl RepoConfig {
 
         let auto_repair = config.get_bool(AUTO_REPAIR_FIELD).ok();
 
+        let require_fresh_base = config
+            .get_string(REQUIRE_FRESH_BASE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let max_rewrite_commits = config
+            .get_i64(MAX_REWRITE_COMMITS_FIELD)
+            .ok()
+            .map(|i| i.max(0) as usize);
+        let large_file_threshold = config
+            .get_i64(LARGE_FILE_THRESHOLD_FIELD)
+            .ok()
+            .map(|i| i.max(0) as usize);
+
+        let confirm = config
+            .get_string(CONFIRM_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let checkpoints = config
+            .multivar(CHECKPOINT_FIELD, None)
+            .map(|entries| {
+                let entries_ref = &entries;
+                let checkpoints: Vec<_> = entries_ref
+                    .flat_map(|e| e.into_iter())
+                    .filter_map(|e| e.value().map(|v| v.to_owned()))
+                    .collect();
+                if checkpoints.is_empty() {
+                    None
+                } else {
+                    Some(checkpoints)
+                }
+            })
+            .unwrap_or(None);
+
+        let jobs = config.get_i64(JOBS_FIELD).ok().map(|i| i.max(0) as usize);
+
+        let commit_cache = config.get_bool(COMMIT_CACHE_FIELD).ok();
+
+        let show_max_commits = config
+            .get_i64(SHOW_MAX_COMMITS_FIELD)
+            .ok()
+            .map(|i| i.max(0) as usize);
+
+        let scope_path = config.get_string(SCOPE_PATH_FIELD).ok();
+
+        let show_touched_dirs = config.get_bool(TOUCHED_DIRS_FIELD).ok();
+
+        let issue_pattern = config.get_string(ISSUE_PATTERN_FIELD).ok();
+
+        let issue_url = config.get_string(ISSUE_URL_FIELD).ok();
+
+        let show_commit_types = config.get_bool(COMMIT_TYPES_FIELD).ok();
+        let show_reviews = config.get_bool(REVIEWS_FIELD).ok();
+
+        let push_retries = config
+            .get_i64(PUSH_RETRIES_FIELD)
+            .ok()
+            .map(|i| i.max(0) as usize);
+
+        let delete_remote = config
+            .get_string(DELETE_REMOTE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let show_columns = config
+            .get_string(SHOW_COLUMNS_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let protection_action = config
+            .get_string(PROTECTION_ACTION_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let forge = config
+            .get_string(FORGE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let forge_command = config.get_string(FORGE_COMMAND_FIELD).ok();
+        let forge_token = config.get_string(FORGE_TOKEN_FIELD).ok();
+        let offline = config.get_bool(OFFLINE_FIELD).ok();
+        let keep_going = config.get_bool(KEEP_GOING_FIELD).ok();
+        let usage_stats = config.get_bool(USAGE_STATS_FIELD).ok();
+
+        let squash_message = config
+            .get_string(SQUASH_MESSAGE_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let commit_template = config.get_string(COMMIT_TEMPLATE_FIELD).ok();
+
+        let empty_commits = config
+            .get_string(EMPTY_COMMITS_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let merge_options = config
+            .get_string(MERGE_OPTIONS_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let push_refspec = config
+            .get_string(PUSH_REFSPEC_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let ci_trigger = config
+            .get_string(CI_TRIGGER_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+        let ci_wait = config
+            .get_string(CI_WAIT_FIELD)
+            .ok()
+            .and_then(|s| humantime::parse_duration(&s).ok());
+
+        let summary = config
+            .get_string(SUMMARY_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
+        let max_commits_per_branch = config
+            .get_i64(MAX_COMMITS_PER_BRANCH_FIELD)
+            .ok()
+            .map(|i| i.max(0) as usize);
+
+        let max_commits_action = config
+            .get_string(MAX_COMMITS_ACTION_FIELD)
+            .ok()
+            .and_then(|s| FromStr::from_str(&s).ok());
+
         let capacity = config
             .get_i64(BACKUP_CAPACITY_FIELD)
             .map(|i| i as usize)
@@ -262,8 +804,11 @@ impl RepoConfig {
 
         Self {
             protected_branches,
+            ignore_branches,
             protect_commit_count,
             protect_commit_age,
+            stale_age,
+            hide_age,
             push_remote,
             pull_remote,
             stack,
@@ -271,6 +816,40 @@ impl RepoConfig {
             show_stacked,
             auto_fixup,
             auto_repair,
+            require_fresh_base,
+            max_rewrite_commits,
+            large_file_threshold,
+            confirm,
+            checkpoints,
+            jobs,
+            commit_cache,
+            show_max_commits,
+            scope_path,
+            show_touched_dirs,
+            issue_pattern,
+            issue_url,
+            show_commit_types,
+            show_reviews,
+            push_retries,
+            delete_remote,
+            show_columns,
+            protection_action,
+            forge,
+            forge_command,
+            forge_token,
+            offline,
+            keep_going,
+            usage_stats,
+            squash_message,
+            commit_template,
+            empty_commits,
+            merge_options,
+            push_refspec,
+            ci_trigger,
+            ci_wait,
+            summary,
+            max_commits_per_branch,
+            max_commits_action,
 
             capacity,
         }
@@ -285,14 +864,129 @@ impl RepoConfig {
         Ok(())
     }
 
+    /// Write out every field that is set, replacing any lists
     pub fn to_gitconfig(&self, config: &mut git2::Config) -> eyre::Result<()> {
-        if let Some(protected_branches) = self.protected_branches.as_ref() {
+        fn set_list(config: &mut git2::Config, key: &str, values: &[String]) -> eyre::Result<()> {
             // Ignore errors if there aren't keys to remove
-            let _ = config.remove_multivar(PROTECTED_STACK_FIELD, ".*");
-            for branch in protected_branches {
-                config.set_multivar(PROTECTED_STACK_FIELD, "^$", branch)?;
+            let _ = config.remove_multivar(key, ".*");
+            let mut written = std::collections::HashSet::new();
+            for value in values.iter().filter(|v| written.insert(v.as_str())) {
+                config.set_multivar(key, "^$", value)?;
+            }
+            Ok(())
+        }
+        fn set_display(
+            config: &mut git2::Config,
+            key: &str,
+            value: Option<impl std::fmt::Display>,
+        ) -> eyre::Result<()> {
+            if let Some(value) = value {
+                config.set_str(key, &value.to_string())?;
             }
+            Ok(())
+        }
+        fn set_bool(config: &mut git2::Config, key: &str, value: Option<bool>) -> eyre::Result<()> {
+            if let Some(value) = value {
+                config.set_bool(key, value)?;
+            }
+            Ok(())
+        }
+
+        if let Some(protected_branches) = self.protected_branches.as_ref() {
+            set_list(config, PROTECTED_STACK_FIELD, protected_branches)?;
+        }
+        if let Some(ignore_branches) = self.ignore_branches.as_ref() {
+            set_list(config, IGNORE_BRANCH_FIELD, ignore_branches)?;
+        }
+        set_display(config, PROTECT_COMMIT_COUNT, self.protect_commit_count)?;
+        set_display(
+            config,
+            PROTECT_COMMIT_AGE,
+            self.protect_commit_age.map(humantime::format_duration),
+        )?;
+        set_display(
+            config,
+            STALE_AGE_FIELD,
+            self.stale_age.map(humantime::format_duration),
+        )?;
+        set_display(
+            config,
+            HIDE_AGE_FIELD,
+            self.hide_age.map(humantime::format_duration),
+        )?;
+        set_display(config, STACK_FIELD, self.stack)?;
+        set_display(config, PUSH_REMOTE_FIELD, self.push_remote.as_deref())?;
+        set_display(config, PULL_REMOTE_FIELD, self.pull_remote.as_deref())?;
+        set_display(config, FORMAT_FIELD, self.show_format)?;
+        set_bool(config, STACKED_FIELD, self.show_stacked)?;
+        set_display(config, AUTO_FIXUP_FIELD, self.auto_fixup)?;
+        set_bool(config, AUTO_REPAIR_FIELD, self.auto_repair)?;
+        set_display(config, REQUIRE_FRESH_BASE_FIELD, self.require_fresh_base)?;
+        set_display(config, MAX_REWRITE_COMMITS_FIELD, self.max_rewrite_commits)?;
+        set_display(
+            config,
+            LARGE_FILE_THRESHOLD_FIELD,
+            self.large_file_threshold,
+        )?;
+        set_display(config, CONFIRM_FIELD, self.confirm)?;
+        if let Some(checkpoints) = self.checkpoints.as_ref() {
+            set_list(config, CHECKPOINT_FIELD, checkpoints)?;
         }
+        set_display(config, JOBS_FIELD, self.jobs)?;
+        set_bool(config, COMMIT_CACHE_FIELD, self.commit_cache)?;
+        set_display(config, SHOW_MAX_COMMITS_FIELD, self.show_max_commits)?;
+        set_display(config, SCOPE_PATH_FIELD, self.scope_path.as_deref())?;
+        set_bool(config, TOUCHED_DIRS_FIELD, self.show_touched_dirs)?;
+        set_display(config, ISSUE_PATTERN_FIELD, self.issue_pattern.as_deref())?;
+        set_display(config, ISSUE_URL_FIELD, self.issue_url.as_deref())?;
+        set_bool(config, COMMIT_TYPES_FIELD, self.show_commit_types)?;
+        set_bool(config, REVIEWS_FIELD, self.show_reviews)?;
+        set_display(config, PUSH_RETRIES_FIELD, self.push_retries)?;
+        set_display(config, DELETE_REMOTE_FIELD, self.delete_remote)?;
+        set_display(config, SHOW_COLUMNS_FIELD, self.show_columns.as_ref())?;
+        set_display(config, PROTECTION_ACTION_FIELD, self.protection_action)?;
+        set_display(config, FORGE_FIELD, self.forge)?;
+        set_display(config, FORGE_COMMAND_FIELD, self.forge_command.as_deref())?;
+        set_display(config, FORGE_TOKEN_FIELD, self.forge_token.as_deref())?;
+        set_bool(config, OFFLINE_FIELD, self.offline)?;
+        set_bool(config, KEEP_GOING_FIELD, self.keep_going)?;
+        set_bool(config, USAGE_STATS_FIELD, self.usage_stats)?;
+        set_display(config, SQUASH_MESSAGE_FIELD, self.squash_message)?;
+        set_display(
+            config,
+            COMMIT_TEMPLATE_FIELD,
+            self.commit_template.as_deref(),
+        )?;
+        set_display(config, EMPTY_COMMITS_FIELD, self.empty_commits)?;
+        set_display(config, MERGE_OPTIONS_FIELD, self.merge_options.as_ref())?;
+        set_display(config, PUSH_REFSPEC_FIELD, self.push_refspec.as_ref())?;
+        set_display(config, CI_TRIGGER_FIELD, self.ci_trigger.as_ref())?;
+        set_display(
+            config,
+            CI_WAIT_FIELD,
+            self.ci_wait.map(humantime::format_duration),
+        )?;
+        set_display(config, SUMMARY_FIELD, self.summary)?;
+        set_display(
+            config,
+            MAX_COMMITS_PER_BRANCH_FIELD,
+            self.max_commits_per_branch,
+        )?;
+        set_display(config, MAX_COMMITS_ACTION_FIELD, self.max_commits_action)?;
+        set_display(config, BACKUP_CAPACITY_FIELD, self.capacity)?;
+        Ok(())
+    }
+
+    /// Remember where config written by [`RepoConfig::write_repo`] came from
+    ///
+    /// Sources are listed under `stack.config-source`, in the order they were applied.
+    pub fn record_source(repo: &git2::Repository, source: &str) -> eyre::Result<()> {
+        let config_path = git_dir_config(repo);
+        let mut config = git2::Config::open(&config_path)?;
+        // Re-applying a source moves it to the end
+        let _ =
+            config.remove_multivar(CONFIG_SOURCE_FIELD, &format!("^{}$", regex::escape(source)));
+        config.set_multivar(CONFIG_SOURCE_FIELD, "^$", source)?;
         Ok(())
     }
 
@@ -302,8 +996,15 @@ impl RepoConfig {
             (None, Some(rhs)) => self.protected_branches = Some(rhs),
             (_, _) => (),
         }
+        match (&mut self.ignore_branches, other.ignore_branches) {
+            (Some(lhs), Some(rhs)) => lhs.extend(rhs),
+            (None, Some(rhs)) => self.ignore_branches = Some(rhs),
+            (_, _) => (),
+        }
         self.protect_commit_count = other.protect_commit_count.or(self.protect_commit_count);
         self.protect_commit_age = other.protect_commit_age.or(self.protect_commit_age);
+        self.stale_age = other.stale_age.or(self.stale_age);
+        self.hide_age = other.hide_age.or(self.hide_age);
         self.push_remote = other.push_remote.or(self.push_remote);
         self.pull_remote = other.pull_remote.or(self.pull_remote);
         self.stack = other.stack.or(self.stack);
@@ -311,6 +1012,44 @@ impl RepoConfig {
         self.show_stacked = other.show_stacked.or(self.show_stacked);
         self.auto_fixup = other.auto_fixup.or(self.auto_fixup);
         self.auto_repair = other.auto_repair.or(self.auto_repair);
+        self.require_fresh_base = other.require_fresh_base.or(self.require_fresh_base);
+        self.max_rewrite_commits = other.max_rewrite_commits.or(self.max_rewrite_commits);
+        self.large_file_threshold = other.large_file_threshold.or(self.large_file_threshold);
+        self.confirm = other.confirm.or(self.confirm);
+        match (&mut self.checkpoints, other.checkpoints) {
+            (Some(lhs), Some(rhs)) => lhs.extend(rhs),
+            (None, Some(rhs)) => self.checkpoints = Some(rhs),
+            (_, _) => (),
+        }
+        self.jobs = other.jobs.or(self.jobs);
+        self.commit_cache = other.commit_cache.or(self.commit_cache);
+        self.show_max_commits = other.show_max_commits.or(self.show_max_commits);
+        self.scope_path = other.scope_path.or(self.scope_path);
+        self.show_touched_dirs = other.show_touched_dirs.or(self.show_touched_dirs);
+        self.issue_pattern = other.issue_pattern.or(self.issue_pattern);
+        self.issue_url = other.issue_url.or(self.issue_url);
+        self.show_commit_types = other.show_commit_types.or(self.show_commit_types);
+        self.show_reviews = other.show_reviews.or(self.show_reviews);
+        self.push_retries = other.push_retries.or(self.push_retries);
+        self.delete_remote = other.delete_remote.or(self.delete_remote);
+        self.show_columns = other.show_columns.or(self.show_columns);
+        self.protection_action = other.protection_action.or(self.protection_action);
+        self.forge = other.forge.or(self.forge);
+        self.forge_command = other.forge_command.or(self.forge_command);
+        self.forge_token = other.forge_token.or(self.forge_token);
+        self.offline = other.offline.or(self.offline);
+        self.keep_going = other.keep_going.or(self.keep_going);
+        self.usage_stats = other.usage_stats.or(self.usage_stats);
+        self.squash_message = other.squash_message.or(self.squash_message);
+        self.commit_template = other.commit_template.or(self.commit_template);
+        self.empty_commits = other.empty_commits.or(self.empty_commits);
+        self.merge_options = other.merge_options.or(self.merge_options);
+        self.push_refspec = other.push_refspec.or(self.push_refspec);
+        self.ci_trigger = other.ci_trigger.or(self.ci_trigger);
+        self.ci_wait = other.ci_wait.or(self.ci_wait);
+        self.summary = other.summary.or(self.summary);
+        self.max_commits_per_branch = other.max_commits_per_branch.or(self.max_commits_per_branch);
+        self.max_commits_action = other.max_commits_action.or(self.max_commits_action);
         self.capacity = other.capacity.or(self.capacity);
 
         self
@@ -320,6 +1059,10 @@ impl RepoConfig {
         self.protected_branches.as_deref().unwrap_or(&[])
     }
 
+    pub fn ignore_branches(&self) -> &[String] {
+        self.ignore_branches.as_deref().unwrap_or(&[])
+    }
+
     pub fn protect_commit_count(&self) -> Option<usize> {
         let protect_commit_count = self
             .protect_commit_count
@@ -332,6 +1075,16 @@ impl RepoConfig {
             .unwrap_or(DEFAULT_PROTECT_COMMIT_AGE)
     }
 
+    /// When a stack nobody touched gets called out as stale
+    pub fn stale_age(&self) -> std::time::Duration {
+        self.stale_age.unwrap_or(DEFAULT_STALE_AGE)
+    }
+
+    /// When a stack nobody touched gets left out of the tree
+    pub fn hide_age(&self) -> Option<std::time::Duration> {
+        self.hide_age.filter(|age| !age.is_zero())
+    }
+
     pub fn push_remote(&self) -> &str {
         self.push_remote.as_deref().unwrap_or("origin")
     }
@@ -362,23 +1115,180 @@ impl RepoConfig {
         self.auto_repair.unwrap_or(true)
     }
 
-    pub fn capacity(&self) -> Option<usize> {
-        let capacity = self.capacity.unwrap_or(DEFAULT_CAPACITY);
-        (capacity != 0).then(|| capacity)
+    pub fn require_fresh_base(&self) -> FreshBase {
+        self.require_fresh_base.unwrap_or_default()
     }
-}
 
-impl std::fmt::Display for RepoConfig {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "[{}]", STACK_FIELD.split_once(".").unwrap().0)?;
-        for branch in self.protected_branches() {
-            writeln!(
+    pub fn max_rewrite_commits(&self) -> Option<usize> {
+        let max_rewrite_commits = self
+            .max_rewrite_commits
+            .unwrap_or(DEFAULT_MAX_REWRITE_COMMITS);
+        (max_rewrite_commits != 0).then(|| max_rewrite_commits)
+    }
+
+    /// Replay commits with files of at least this many bytes in a temporary worktree, rather
+    /// than loading them into memory
+    pub fn large_file_threshold(&self) -> Option<usize> {
+        let large_file_threshold = self.large_file_threshold.unwrap_or(0);
+        (large_file_threshold != 0).then(|| large_file_threshold)
+    }
+
+    pub fn confirm(&self) -> Confirm {
+        self.confirm.unwrap_or_default()
+    }
+
+    pub fn checkpoints(&self) -> &[String] {
+        self.checkpoints.as_deref().unwrap_or(&[])
+    }
+
+    /// Threads for independent per-branch queries, `None` for one per CPU
+    pub fn jobs(&self) -> Option<usize> {
+        let jobs = self.jobs.unwrap_or(0);
+        (jobs != 0).then(|| jobs)
+    }
+
+    pub fn commit_cache(&self) -> bool {
+        self.commit_cache.unwrap_or(false)
+    }
+
+    pub fn show_max_commits(&self) -> Option<usize> {
+        let show_max_commits = self.show_max_commits.unwrap_or(DEFAULT_SHOW_MAX_COMMITS);
+        (show_max_commits != 0).then(|| show_max_commits)
+    }
+
+    pub fn scope_path(&self) -> Option<&std::path::Path> {
+        self.scope_path
+            .as_deref()
+            .map(std::path::Path::new)
+            .filter(|p| p.components().next().is_some())
+    }
+
+    pub fn show_touched_dirs(&self) -> bool {
+        self.show_touched_dirs.unwrap_or(false)
+    }
+
+    pub fn issue_pattern(&self) -> Option<&str> {
+        self.issue_pattern.as_deref().filter(|p| !p.is_empty())
+    }
+
+    pub fn issue_url(&self) -> Option<&str> {
+        self.issue_url.as_deref().filter(|u| !u.is_empty())
+    }
+
+    pub fn show_commit_types(&self) -> bool {
+        self.show_commit_types.unwrap_or(false)
+    }
+
+    pub fn show_reviews(&self) -> bool {
+        self.show_reviews.unwrap_or(false)
+    }
+
+    pub fn push_retries(&self) -> usize {
+        self.push_retries.unwrap_or(0)
+    }
+
+    pub fn delete_remote(&self) -> DeleteRemote {
+        self.delete_remote.unwrap_or_default()
+    }
+
+    pub fn show_columns(&self) -> Columns {
+        self.show_columns.clone().unwrap_or_default()
+    }
+
+    pub fn protection_action(&self) -> ProtectionAction {
+        self.protection_action.unwrap_or_default()
+    }
+
+    pub fn forge(&self) -> Forge {
+        self.forge.unwrap_or_default()
+    }
+
+    pub fn forge_command(&self) -> Option<&str> {
+        self.forge_command.as_deref().filter(|c| !c.is_empty())
+    }
+
+    pub fn forge_token(&self) -> Option<&str> {
+        self.forge_token.as_deref().filter(|t| !t.is_empty())
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline.unwrap_or(false)
+    }
+
+    pub fn keep_going(&self) -> bool {
+        self.keep_going.unwrap_or(false)
+    }
+
+    pub fn usage_stats(&self) -> bool {
+        self.usage_stats.unwrap_or(false)
+    }
+
+    pub fn squash_message(&self) -> SquashMessage {
+        self.squash_message.unwrap_or_default()
+    }
+
+    pub fn commit_template(&self) -> Option<&str> {
+        self.commit_template.as_deref().filter(|t| !t.is_empty())
+    }
+
+    pub fn empty_commits(&self) -> EmptyCommits {
+        self.empty_commits.unwrap_or_default()
+    }
+
+    pub fn merge_options(&self) -> MergeOptions {
+        self.merge_options.clone().unwrap_or_default()
+    }
+
+    pub fn push_refspec(&self) -> Option<&PushRefspec> {
+        self.push_refspec.as_ref()
+    }
+
+    pub fn ci_trigger(&self) -> CiTrigger {
+        self.ci_trigger.clone().unwrap_or_default()
+    }
+
+    /// How long `git stack --push` waits on CI, if at all
+    pub fn ci_wait(&self) -> Option<std::time::Duration> {
+        self.ci_wait.filter(|wait| !wait.is_zero())
+    }
+
+    pub fn summary(&self) -> Summary {
+        self.summary.unwrap_or_default()
+    }
+
+    pub fn max_commits_per_branch(&self) -> Option<usize> {
+        self.max_commits_per_branch.filter(|max| *max != 0)
+    }
+
+    pub fn max_commits_action(&self) -> MaxCommitsAction {
+        self.max_commits_action.unwrap_or_default()
+    }
+
+    pub fn capacity(&self) -> Option<usize> {
+        let capacity = self.capacity.unwrap_or(DEFAULT_CAPACITY);
+        (capacity != 0).then(|| capacity)
+    }
+}
+
+impl std::fmt::Display for RepoConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[{}]", STACK_FIELD.split_once(".").unwrap().0)?;
+        for branch in self.protected_branches() {
+            writeln!(
                 f,
                 "\t{}={}",
                 PROTECTED_STACK_FIELD.split_once(".").unwrap().1,
                 branch
             )?;
         }
+        for branch in self.ignore_branches() {
+            writeln!(
+                f,
+                "\t{}={}",
+                IGNORE_BRANCH_FIELD.split_once(".").unwrap().1,
+                branch
+            )?;
+        }
         writeln!(
             f,
             "\t{}={}",
@@ -391,6 +1301,18 @@ impl std::fmt::Display for RepoConfig {
             PROTECT_COMMIT_AGE.split_once(".").unwrap().1,
             humantime::format_duration(self.protect_commit_age())
         )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            STALE_AGE_FIELD.split_once(".").unwrap().1,
+            humantime::format_duration(self.stale_age())
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            HIDE_AGE_FIELD.split_once(".").unwrap().1,
+            humantime::format_duration(self.hide_age().unwrap_or_default())
+        )?;
         writeln!(
             f,
             "\t{}={}",
@@ -433,6 +1355,221 @@ impl std::fmt::Display for RepoConfig {
             AUTO_REPAIR_FIELD.split_once(".").unwrap().1,
             self.auto_repair()
         )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            REQUIRE_FRESH_BASE_FIELD.split_once(".").unwrap().1,
+            self.require_fresh_base()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            MAX_REWRITE_COMMITS_FIELD.split_once(".").unwrap().1,
+            self.max_rewrite_commits().unwrap_or(0)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            LARGE_FILE_THRESHOLD_FIELD.split_once(".").unwrap().1,
+            self.large_file_threshold().unwrap_or(0)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            CONFIRM_FIELD.split_once(".").unwrap().1,
+            self.confirm()
+        )?;
+        for tag in self.checkpoints() {
+            writeln!(
+                f,
+                "\t{}={}",
+                CHECKPOINT_FIELD.split_once(".").unwrap().1,
+                tag
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            JOBS_FIELD.split_once(".").unwrap().1,
+            self.jobs().unwrap_or(0)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            COMMIT_CACHE_FIELD.split_once(".").unwrap().1,
+            self.commit_cache()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            SHOW_MAX_COMMITS_FIELD.split_once(".").unwrap().1,
+            self.show_max_commits().unwrap_or(0)
+        )?;
+        if let Some(issue_pattern) = self.issue_pattern() {
+            writeln!(
+                f,
+                "\t{}={}",
+                ISSUE_PATTERN_FIELD.split_once(".").unwrap().1,
+                issue_pattern
+            )?;
+        }
+        if let Some(issue_url) = self.issue_url() {
+            writeln!(
+                f,
+                "\t{}={}",
+                ISSUE_URL_FIELD.split_once(".").unwrap().1,
+                issue_url
+            )?;
+        }
+        if let Some(scope_path) = self.scope_path.as_deref() {
+            writeln!(
+                f,
+                "\t{}={}",
+                SCOPE_PATH_FIELD.split_once(".").unwrap().1,
+                scope_path
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            TOUCHED_DIRS_FIELD.split_once(".").unwrap().1,
+            self.show_touched_dirs()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            COMMIT_TYPES_FIELD.split_once(".").unwrap().1,
+            self.show_commit_types()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            REVIEWS_FIELD.split_once(".").unwrap().1,
+            self.show_reviews()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PUSH_RETRIES_FIELD.split_once(".").unwrap().1,
+            self.push_retries()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            DELETE_REMOTE_FIELD.split_once(".").unwrap().1,
+            self.delete_remote()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            SHOW_COLUMNS_FIELD.split_once(".").unwrap().1,
+            self.show_columns()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            PROTECTION_ACTION_FIELD.split_once(".").unwrap().1,
+            self.protection_action()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            FORGE_FIELD.split_once(".").unwrap().1,
+            self.forge()
+        )?;
+        if let Some(forge_command) = self.forge_command() {
+            writeln!(
+                f,
+                "\t{}={}",
+                FORGE_COMMAND_FIELD.split_once(".").unwrap().1,
+                forge_command
+            )?;
+        }
+        // `stack.forge-token` is a secret, so it is left out of dumps
+        writeln!(
+            f,
+            "\t{}={}",
+            OFFLINE_FIELD.split_once(".").unwrap().1,
+            self.offline()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            KEEP_GOING_FIELD.split_once(".").unwrap().1,
+            self.keep_going()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            USAGE_STATS_FIELD.split_once(".").unwrap().1,
+            self.usage_stats()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            SQUASH_MESSAGE_FIELD.split_once(".").unwrap().1,
+            self.squash_message()
+        )?;
+        if let Some(commit_template) = self.commit_template() {
+            writeln!(
+                f,
+                "\t{}={}",
+                COMMIT_TEMPLATE_FIELD.split_once(".").unwrap().1,
+                commit_template
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            EMPTY_COMMITS_FIELD.split_once(".").unwrap().1,
+            self.empty_commits()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            MERGE_OPTIONS_FIELD.split_once(".").unwrap().1,
+            self.merge_options()
+        )?;
+        if let Some(push_refspec) = self.push_refspec() {
+            writeln!(
+                f,
+                "\t{}={}",
+                PUSH_REFSPEC_FIELD.split_once(".").unwrap().1,
+                push_refspec
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            CI_TRIGGER_FIELD.split_once(".").unwrap().1,
+            self.ci_trigger()
+        )?;
+        if let Some(ci_wait) = self.ci_wait() {
+            writeln!(
+                f,
+                "\t{}={}",
+                CI_WAIT_FIELD.split_once(".").unwrap().1,
+                humantime::format_duration(ci_wait)
+            )?;
+        }
+        writeln!(
+            f,
+            "\t{}={}",
+            SUMMARY_FIELD.split_once(".").unwrap().1,
+            self.summary()
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            MAX_COMMITS_PER_BRANCH_FIELD.split_once(".").unwrap().1,
+            self.max_commits_per_branch().unwrap_or(0)
+        )?;
+        writeln!(
+            f,
+            "\t{}={}",
+            MAX_COMMITS_ACTION_FIELD.split_once(".").unwrap().1,
+            self.max_commits_action()
+        )?;
         writeln!(f, "[{}]", BACKUP_CAPACITY_FIELD.split_once(".").unwrap().0)?;
         writeln!(
             f,
@@ -444,26 +1581,355 @@ impl std::fmt::Display for RepoConfig {
     }
 }
 
-fn git_dir_config(repo: &git2::Repository) -> std::path::PathBuf {
-    repo.path().join("config")
-}
+const USER_LEVELS: &[git2::ConfigLevel] = &[
+    git2::ConfigLevel::ProgramData,
+    git2::ConfigLevel::System,
+    git2::ConfigLevel::XDG,
+    git2::ConfigLevel::Global,
+];
 
-fn default_branch(config: &git2::Config) -> &str {
-    config.get_str("init.defaultBranch").ok().unwrap_or("main")
+const REPO_LEVELS: &[git2::ConfigLevel] = &[git2::ConfigLevel::Local];
+
+/// Environment variables for CI, where `GIT_CONFIG_*` is awkward to set
+///
+/// List fields accept comma-separated values.
+static ENV_FIELDS: &[(&str, &str)] = &[
+    ("GIT_STACK_PROTECTED", PROTECTED_STACK_FIELD),
+    ("GIT_STACK_IGNORE", IGNORE_BRANCH_FIELD),
+    ("GIT_STACK_PROTECT_COMMIT_COUNT", PROTECT_COMMIT_COUNT),
+    ("GIT_STACK_PROTECT_COMMIT_AGE", PROTECT_COMMIT_AGE),
+    ("GIT_STACK_STALE_AGE", STALE_AGE_FIELD),
+    ("GIT_STACK_HIDE_AGE", HIDE_AGE_FIELD),
+    ("GIT_STACK_STACK", STACK_FIELD),
+    ("GIT_STACK_PUSH_REMOTE", PUSH_REMOTE_FIELD),
+    ("GIT_STACK_PUSH_RETRIES", PUSH_RETRIES_FIELD),
+    ("GIT_STACK_DELETE_REMOTE", DELETE_REMOTE_FIELD),
+    ("GIT_STACK_PULL_REMOTE", PULL_REMOTE_FIELD),
+    ("GIT_STACK_PROTECTION_ACTION", PROTECTION_ACTION_FIELD),
+    ("GIT_STACK_FORGE", FORGE_FIELD),
+    ("GIT_STACK_FORGE_COMMAND", FORGE_COMMAND_FIELD),
+    ("GIT_STACK_FORGE_TOKEN", FORGE_TOKEN_FIELD),
+    ("GIT_STACK_OFFLINE", OFFLINE_FIELD),
+    ("GIT_STACK_KEEP_GOING", KEEP_GOING_FIELD),
+    ("GIT_STACK_USAGE_STATS", USAGE_STATS_FIELD),
+    ("GIT_STACK_FORMAT", FORMAT_FIELD),
+    ("GIT_STACK_SHOW_STACKED", STACKED_FIELD),
+    ("GIT_STACK_SUMMARY", SUMMARY_FIELD),
+    ("GIT_STACK_AUTO_FIXUP", AUTO_FIXUP_FIELD),
+    ("GIT_STACK_SQUASH_MESSAGE", SQUASH_MESSAGE_FIELD),
+    ("GIT_STACK_COMMIT_TEMPLATE", COMMIT_TEMPLATE_FIELD),
+    ("GIT_STACK_EMPTY_COMMITS", EMPTY_COMMITS_FIELD),
+    ("GIT_STACK_MERGE_OPTIONS", MERGE_OPTIONS_FIELD),
+    ("GIT_STACK_PUSH_REFSPEC", PUSH_REFSPEC_FIELD),
+    ("GIT_STACK_CI_TRIGGER", CI_TRIGGER_FIELD),
+    ("GIT_STACK_CI_WAIT", CI_WAIT_FIELD),
+    ("GIT_STACK_AUTO_REPAIR", AUTO_REPAIR_FIELD),
+    ("GIT_STACK_REQUIRE_FRESH_BASE", REQUIRE_FRESH_BASE_FIELD),
+    ("GIT_STACK_MAX_REWRITE_COMMITS", MAX_REWRITE_COMMITS_FIELD),
+    ("GIT_STACK_LARGE_FILE_THRESHOLD", LARGE_FILE_THRESHOLD_FIELD),
+    (
+        "GIT_STACK_MAX_COMMITS_PER_BRANCH",
+        MAX_COMMITS_PER_BRANCH_FIELD,
+    ),
+    ("GIT_STACK_MAX_COMMITS_ACTION", MAX_COMMITS_ACTION_FIELD),
+    ("GIT_STACK_CONFIRM", CONFIRM_FIELD),
+    ("GIT_STACK_CHECKPOINT", CHECKPOINT_FIELD),
+    ("GIT_STACK_JOBS", JOBS_FIELD),
+    ("GIT_STACK_COMMIT_CACHE", COMMIT_CACHE_FIELD),
+    ("GIT_STACK_SHOW_MAX_COMMITS", SHOW_MAX_COMMITS_FIELD),
+    ("GIT_STACK_SCOPE_PATH", SCOPE_PATH_FIELD),
+    ("GIT_STACK_SHOW_TOUCHED_DIRS", TOUCHED_DIRS_FIELD),
+    ("GIT_STACK_SHOW_COMMIT_TYPES", COMMIT_TYPES_FIELD),
+    ("GIT_STACK_SHOW_REVIEWS", REVIEWS_FIELD),
+    ("GIT_STACK_SHOW_COLUMNS", SHOW_COLUMNS_FIELD),
+    ("GIT_STACK_ISSUE_PATTERN", ISSUE_PATTERN_FIELD),
+    ("GIT_STACK_ISSUE_URL", ISSUE_URL_FIELD),
+];
+
+/// `(variable, key, value)` for each of `ENV_FIELDS` that is set
+fn dedicated_env() -> Vec<(&'static str, &'static str, String)> {
+    let mut fields = Vec::new();
+    for (var, key) in ENV_FIELDS {
+        let value = match std::env::var(var) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        if *key == PROTECTED_STACK_FIELD || *key == IGNORE_BRANCH_FIELD || *key == CHECKPOINT_FIELD
+        {
+            fields.extend(
+                value
+                    .split(',')
+                    .map(|v| v.trim())
+                    .filter(|v| !v.is_empty())
+                    .map(|v| (*var, *key, v.to_owned())),
+            );
+        } else {
+            fields.push((*var, *key, value));
+        }
+    }
+    fields
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum Format {
-    Silent,
-    Branches,
-    BranchCommits,
-    Commits,
-    Debug,
+/// A config value that is being ignored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub key: String,
+    pub value: Option<String>,
+    /// Where the value came from, like a file path or an environment variable
+    pub origin: String,
+    pub line: Option<usize>,
+    pub expected: String,
 }
 
-impl Format {
-    pub fn variants() -> [&'static str; 5] {
-        ["silent", "branches", "branch-commits", "commits", "debug"]
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.origin)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        match self.value.as_deref() {
+            Some(value) => write!(f, ": ignoring `{}={}`", self.key, value)?,
+            None => write!(f, ": ignoring `{}`", self.key)?,
+        }
+        write!(f, ", {}", self.expected)
+    }
+}
+
+fn diagnose_gitconfig(config: &git2::Config, path: Option<&std::path::Path>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let entries = match config.entries(Some("^(stack|branch-stash)\\.")) {
+        Ok(entries) => entries,
+        Err(err) => {
+            log::debug!("Failed to read git config: {}", err);
+            return diagnostics;
+        }
+    };
+    for entry in &entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let key = match entry.name() {
+            Some(key) => key,
+            None => continue,
+        };
+        let value = entry.value();
+        let expected = match check_value(key, value) {
+            Some(Ok(())) => continue,
+            Some(Err(expected)) => expected,
+            None if key.starts_with("stack.") => "unknown field".to_owned(),
+            None => continue,
+        };
+        let (origin, line) = match path {
+            Some(path) if entry.include_depth() == 0 => {
+                (path.display().to_string(), find_line(path, key, value))
+            }
+            Some(path) => (format!("{} (included)", path.display()), None),
+            None => (format!("{:?} config", entry.level()), None),
+        };
+        diagnostics.push(Diagnostic {
+            key: key.to_owned(),
+            value: value.map(|v| v.to_owned()),
+            origin,
+            line,
+            expected,
+        });
+    }
+    diagnostics
+}
+
+fn diagnose_env<'s>(
+    iter: impl Iterator<Item = (std::borrow::Cow<'s, str>, Option<std::borrow::Cow<'s, str>>)>,
+    origin: &str,
+) -> Vec<Diagnostic> {
+    iter.filter_map(|(key, value)| {
+        // `from_env_iter` already warns about unknown fields
+        let expected = check_value(&key, value.as_deref())?.err()?;
+        Some(Diagnostic {
+            key: key.into_owned(),
+            value: value.map(|v| v.into_owned()),
+            origin: origin.to_owned(),
+            line: None,
+            expected,
+        })
+    })
+    .collect()
+}
+
+/// Check a value against what its field expects, `None` for unknown fields
+fn check_value(key: &str, value: Option<&str>) -> Option<Result<(), String>> {
+    fn check_enum<T: FromStr<Err = String>>(value: Option<&str>) -> Result<(), String> {
+        match value {
+            Some(value) => T::from_str(value).map(|_| ()),
+            None => Err("expected a value".to_owned()),
+        }
+    }
+
+    let result = if key == PROTECTED_STACK_FIELD
+        || key == IGNORE_BRANCH_FIELD
+        || key == PUSH_REMOTE_FIELD
+        || key == PULL_REMOTE_FIELD
+        || key == CHECKPOINT_FIELD
+        || key == SCOPE_PATH_FIELD
+        || key == ISSUE_URL_FIELD
+        || key == FORGE_COMMAND_FIELD
+        || key == FORGE_TOKEN_FIELD
+        || key == COMMIT_TEMPLATE_FIELD
+        || key == CONFIG_SOURCE_FIELD
+    {
+        match value {
+            Some(_) => Ok(()),
+            None => Err("expected a value".to_owned()),
+        }
+    } else if key == PROTECT_COMMIT_COUNT
+        || key == MAX_REWRITE_COMMITS_FIELD
+        || key == LARGE_FILE_THRESHOLD_FIELD
+        || key == MAX_COMMITS_PER_BRANCH_FIELD
+        || key == JOBS_FIELD
+        || key == SHOW_MAX_COMMITS_FIELD
+        || key == PUSH_RETRIES_FIELD
+        || key == BACKUP_CAPACITY_FIELD
+    {
+        match value.and_then(parse_git_int) {
+            Some(i) if 0 <= i => Ok(()),
+            _ => Err("expected a non-negative integer".to_owned()),
+        }
+    } else if key == ISSUE_PATTERN_FIELD {
+        match value.map(regex::Regex::new) {
+            Some(Ok(_)) => Ok(()),
+            Some(Err(err)) => Err(format!("expected a regex ({})", err)),
+            None => Err("expected a value".to_owned()),
+        }
+    } else if key == PROTECT_COMMIT_AGE
+        || key == STALE_AGE_FIELD
+        || key == HIDE_AGE_FIELD
+        || key == CI_WAIT_FIELD
+    {
+        match value.map(humantime::parse_duration) {
+            Some(Ok(_)) => Ok(()),
+            _ => Err("expected a duration like `2 weeks` or `36h`".to_owned()),
+        }
+    } else if key == STACKED_FIELD
+        || key == AUTO_REPAIR_FIELD
+        || key == COMMIT_CACHE_FIELD
+        || key == TOUCHED_DIRS_FIELD
+        || key == COMMIT_TYPES_FIELD
+        || key == REVIEWS_FIELD
+        || key == OFFLINE_FIELD
+        || key == KEEP_GOING_FIELD
+        || key == USAGE_STATS_FIELD
+    {
+        match value {
+            None => Ok(()),
+            Some(value) if parse_git_bool(value) => Ok(()),
+            Some(_) => Err("expected `true` or `false`".to_owned()),
+        }
+    } else if key == STACK_FIELD {
+        check_enum::<Stack>(value)
+    } else if key == FORMAT_FIELD {
+        check_enum::<Format>(value)
+    } else if key == AUTO_FIXUP_FIELD {
+        check_enum::<Fixup>(value)
+    } else if key == SQUASH_MESSAGE_FIELD {
+        check_enum::<SquashMessage>(value)
+    } else if key == EMPTY_COMMITS_FIELD {
+        check_enum::<EmptyCommits>(value)
+    } else if key == MERGE_OPTIONS_FIELD {
+        check_enum::<MergeOptions>(value)
+    } else if key == PUSH_REFSPEC_FIELD {
+        check_enum::<PushRefspec>(value)
+    } else if key == CI_TRIGGER_FIELD {
+        check_enum::<CiTrigger>(value)
+    } else if key == SUMMARY_FIELD {
+        check_enum::<Summary>(value)
+    } else if key == MAX_COMMITS_ACTION_FIELD {
+        check_enum::<MaxCommitsAction>(value)
+    } else if key == REQUIRE_FRESH_BASE_FIELD {
+        check_enum::<FreshBase>(value)
+    } else if key == CONFIRM_FIELD {
+        check_enum::<Confirm>(value)
+    } else if key == DELETE_REMOTE_FIELD {
+        check_enum::<DeleteRemote>(value)
+    } else if key == PROTECTION_ACTION_FIELD {
+        check_enum::<ProtectionAction>(value)
+    } else if key == FORGE_FIELD {
+        check_enum::<Forge>(value)
+    } else if key == SHOW_COLUMNS_FIELD {
+        check_enum::<Columns>(value)
+    } else {
+        return None;
+    };
+    Some(result)
+}
+
+/// Integers as git accepts them, including `k`, `m`, and `g` suffixes
+fn parse_git_int(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, scale) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(scale)
+}
+
+fn parse_git_bool(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "" | "true" | "yes" | "on" | "false" | "no" | "off"
+    ) || parse_git_int(value).is_some()
+}
+
+/// Best-effort line number of the last assignment of `key`, as git uses the last one
+fn find_line(path: &std::path::Path, key: &str, value: Option<&str>) -> Option<usize> {
+    let (section, name) = key.split_once('.')?;
+    let content = std::fs::read_to_string(path).ok()?;
+    let mut in_section = false;
+    let mut found = None;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.split(']').next().unwrap_or_default().trim();
+            in_section = header.eq_ignore_ascii_case(section);
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let (lhs, rhs) = match line.split_once('=') {
+            Some((lhs, rhs)) => (lhs.trim(), Some(rhs.trim())),
+            None => (line, None),
+        };
+        if lhs.eq_ignore_ascii_case(name)
+            && (value.is_none() || rhs.map(|r| r.trim_matches('"')) == value)
+        {
+            found = Some(i + 1);
+        }
+    }
+    found
+}
+
+fn git_dir_config(repo: &git2::Repository) -> std::path::PathBuf {
+    repo.path().join("config")
+}
+
+fn default_branch(config: &git2::Config) -> &str {
+    config.get_str("init.defaultBranch").ok().unwrap_or("main")
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Silent,
+    Branches,
+    BranchCommits,
+    Commits,
+    Debug,
+}
+
+impl Format {
+    pub fn variants() -> [&'static str; 5] {
+        ["silent", "branches", "branch-commits", "commits", "debug"]
     }
 }
 
@@ -583,3 +2049,719 @@ impl Default for Fixup {
         Fixup::Move
     }
 }
+
+/// What to report once branches have been rewritten or pushed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Summary {
+    None,
+    /// One line of counts
+    Short,
+    /// Each branch, commit, and push
+    Full,
+}
+
+impl Summary {
+    pub fn variants() -> [&'static str; 3] {
+        ["none", "short", "full"]
+    }
+}
+
+impl std::str::FromStr for Summary {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Summary::None),
+            "short" => Ok(Summary::Short),
+            "full" => Ok(Summary::Full),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Summary::None => "none".fmt(f),
+            Summary::Short => "short".fmt(f),
+            Summary::Full => "full".fmt(f),
+        }
+    }
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary::Short
+    }
+}
+
+/// What to do with branches over `stack.max-commits-per-branch`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MaxCommitsAction {
+    Warn,
+    /// Also refuse to push them
+    Error,
+}
+
+impl MaxCommitsAction {
+    pub fn variants() -> [&'static str; 2] {
+        ["warn", "error"]
+    }
+}
+
+impl std::str::FromStr for MaxCommitsAction {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(MaxCommitsAction::Warn),
+            "error" => Ok(MaxCommitsAction::Error),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for MaxCommitsAction {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            MaxCommitsAction::Warn => "warn".fmt(f),
+            MaxCommitsAction::Error => "error".fmt(f),
+        }
+    }
+}
+
+impl Default for MaxCommitsAction {
+    fn default() -> Self {
+        MaxCommitsAction::Warn
+    }
+}
+
+/// How to combine messages when squashing a `squash!` commit
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SquashMessage {
+    /// Keep the message of the commit being squashed into
+    First,
+    /// Use the message of the `squash!` commit, keeping the subject it was squashed into
+    Last,
+    /// Append the body of the `squash!` commit
+    Concat,
+    /// Like `Concat` but reviewed in the editor
+    Editor,
+}
+
+impl SquashMessage {
+    pub fn variants() -> [&'static str; 4] {
+        ["first", "last", "concat", "editor"]
+    }
+}
+
+impl std::str::FromStr for SquashMessage {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(SquashMessage::First),
+            "last" => Ok(SquashMessage::Last),
+            "concat" => Ok(SquashMessage::Concat),
+            "editor" => Ok(SquashMessage::Editor),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for SquashMessage {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            SquashMessage::First => "first".fmt(f),
+            SquashMessage::Last => "last".fmt(f),
+            SquashMessage::Concat => "concat".fmt(f),
+            SquashMessage::Editor => "editor".fmt(f),
+        }
+    }
+}
+
+impl Default for SquashMessage {
+    fn default() -> Self {
+        SquashMessage::Editor
+    }
+}
+
+/// What to do with commits that become empty when replayed, e.g. because they are already upstream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EmptyCommits {
+    Keep,
+    Drop,
+    Ask,
+}
+
+impl EmptyCommits {
+    pub fn variants() -> [&'static str; 3] {
+        ["keep", "drop", "ask"]
+    }
+}
+
+impl std::str::FromStr for EmptyCommits {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(EmptyCommits::Keep),
+            "drop" => Ok(EmptyCommits::Drop),
+            "ask" => Ok(EmptyCommits::Ask),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for EmptyCommits {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            EmptyCommits::Keep => "keep".fmt(f),
+            EmptyCommits::Drop => "drop".fmt(f),
+            EmptyCommits::Ask => "ask".fmt(f),
+        }
+    }
+}
+
+impl Default for EmptyCommits {
+    fn default() -> Self {
+        EmptyCommits::Drop
+    }
+}
+
+/// `git merge -X` strategy options for replaying commits, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions(Vec<MergeOption>);
+
+impl MergeOptions {
+    pub fn new(options: Vec<MergeOption>) -> Self {
+        Self(options)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = MergeOption> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::str::FromStr for MergeOptions {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let options = s
+            .split(',')
+            .map(|o| o.trim())
+            .filter(|o| !o.is_empty())
+            .map(MergeOption::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MergeOptions(options))
+    }
+}
+
+impl std::fmt::Display for MergeOptions {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let options: Vec<_> = self.iter().map(|o| o.to_string()).collect();
+        options.join(",").fmt(f)
+    }
+}
+
+/// The subset of `git merge -X` that libgit2 can do as well
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergeOption {
+    /// Resolve conflicting hunks in favor of the commit being replayed onto
+    Ours,
+    /// Resolve conflicting hunks in favor of the commit being replayed
+    Theirs,
+    IgnoreSpaceChange,
+    IgnoreAllSpace,
+    IgnoreSpaceAtEol,
+    /// Detect renames, with an optional similarity threshold in percent
+    FindRenames(Option<u32>),
+    NoRenames,
+    Patience,
+}
+
+impl MergeOption {
+    pub fn variants() -> [&'static str; 8] {
+        [
+            "ours",
+            "theirs",
+            "ignore-space-change",
+            "ignore-all-space",
+            "ignore-space-at-eol",
+            "find-renames[=<n>]",
+            "no-renames",
+            "patience",
+        ]
+    }
+}
+
+impl std::str::FromStr for MergeOption {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "ours" => Ok(MergeOption::Ours),
+            None if s == "theirs" => Ok(MergeOption::Theirs),
+            None if s == "ignore-space-change" => Ok(MergeOption::IgnoreSpaceChange),
+            None if s == "ignore-all-space" => Ok(MergeOption::IgnoreAllSpace),
+            None if s == "ignore-space-at-eol" => Ok(MergeOption::IgnoreSpaceAtEol),
+            None if s == "find-renames" => Ok(MergeOption::FindRenames(None)),
+            None if s == "no-renames" => Ok(MergeOption::NoRenames),
+            None if s == "patience" => Ok(MergeOption::Patience),
+            // `rename-threshold` is what `git` called it before `find-renames`
+            Some(("find-renames", threshold)) | Some(("rename-threshold", threshold)) => {
+                match threshold.trim().trim_end_matches('%').parse::<u32>() {
+                    Ok(threshold) if threshold <= 100 => {
+                        Ok(MergeOption::FindRenames(Some(threshold)))
+                    }
+                    _ => Err("rename threshold must be a percentage, up to 100".to_owned()),
+                }
+            }
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for MergeOption {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            MergeOption::Ours => "ours".fmt(f),
+            MergeOption::Theirs => "theirs".fmt(f),
+            MergeOption::IgnoreSpaceChange => "ignore-space-change".fmt(f),
+            MergeOption::IgnoreAllSpace => "ignore-all-space".fmt(f),
+            MergeOption::IgnoreSpaceAtEol => "ignore-space-at-eol".fmt(f),
+            MergeOption::FindRenames(None) => "find-renames".fmt(f),
+            MergeOption::FindRenames(Some(threshold)) => write!(f, "find-renames={}", threshold),
+            MergeOption::NoRenames => "no-renames".fmt(f),
+            MergeOption::Patience => "patience".fmt(f),
+        }
+    }
+}
+
+/// Where `git stack --push` sends a branch, like `+refs/heads/{branch}:refs/heads/users/{user}/{branch}`
+///
+/// `{user}` is the part of `user.email` before the `@`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushRefspec {
+    force: bool,
+    src: String,
+    dst: String,
+}
+
+impl PushRefspec {
+    pub fn variables() -> [&'static str; 2] {
+        ["{branch}", "{user}"]
+    }
+
+    /// The ref `branch` is pushed to, if we know `user` when it is needed
+    pub fn remote_ref(&self, branch: &str, user: Option<&str>) -> Option<String> {
+        let dst = self.dst.replace("{branch}", branch);
+        if dst.contains("{user}") {
+            Some(dst.replace("{user}", user?))
+        } else {
+            Some(dst)
+        }
+    }
+
+    /// The refspec for `git push`
+    pub fn refspec(&self, branch: &str, user: Option<&str>) -> Option<String> {
+        let src = self.src.replace("{branch}", branch);
+        let dst = self.remote_ref(branch, user)?;
+        let force = if self.force { "+" } else { "" };
+        Some(format!("{}{}:{}", force, src, dst))
+    }
+}
+
+impl std::str::FromStr for PushRefspec {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        let (force, refspec) = match s.strip_prefix('+') {
+            Some(refspec) => (true, refspec),
+            None => (false, s),
+        };
+        let (src, dst) = refspec
+            .split_once(':')
+            .ok_or_else(|| "expected `<src>:<dst>`".to_owned())?;
+        if src != "{branch}" && src != "refs/heads/{branch}" {
+            return Err("the source must be `refs/heads/{branch}`".to_owned());
+        }
+        if !dst.starts_with("refs/") || !dst.contains("{branch}") {
+            return Err("the destination must be a full ref with `{branch}` in it".to_owned());
+        }
+        let mut rest = dst;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..].find('}').map(|end| start + end + 1);
+            let variable = &rest[start..end.unwrap_or(rest.len())];
+            if !Self::variables().contains(&variable) {
+                return Err(format!(
+                    "unknown `{}`, valid variables: {}",
+                    variable,
+                    Self::variables().join(", ")
+                ));
+            }
+            rest = &rest[end.unwrap_or(rest.len())..];
+        }
+        Ok(Self {
+            force,
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for PushRefspec {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        if self.force {
+            write!(f, "+")?;
+        }
+        write!(f, "{}:{}", self.src, self.dst)
+    }
+}
+
+/// What `git stack --push` asks to run CI on each branch it pushed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CiTrigger {
+    None,
+    /// Whatever the forge runs for a branch, e.g. a GitLab pipeline
+    Forge,
+    /// A GitHub Actions workflow with a `workflow_dispatch` trigger, by file name or ID
+    Workflow(String),
+    /// `POST` the branch and commit as JSON to this URL
+    Webhook(String),
+}
+
+impl CiTrigger {
+    pub fn variants() -> [&'static str; 4] {
+        ["none", "forge", "workflow:<file>", "<http(s) URL>"]
+    }
+}
+
+impl std::str::FromStr for CiTrigger {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let s = s.trim();
+        match s {
+            "none" => Ok(CiTrigger::None),
+            "forge" => Ok(CiTrigger::Forge),
+            _ => {
+                if let Some(workflow) = s.strip_prefix("workflow:") {
+                    if workflow.is_empty() {
+                        return Err("expected a workflow after `workflow:`".to_owned());
+                    }
+                    Ok(CiTrigger::Workflow(workflow.to_owned()))
+                } else if s.starts_with("https://") || s.starts_with("http://") {
+                    Ok(CiTrigger::Webhook(s.to_owned()))
+                } else {
+                    Err(format!("valid values: {}", Self::variants().join(", ")))
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for CiTrigger {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            CiTrigger::None => "none".fmt(f),
+            CiTrigger::Forge => "forge".fmt(f),
+            CiTrigger::Workflow(workflow) => write!(f, "workflow:{}", workflow),
+            CiTrigger::Webhook(url) => url.fmt(f),
+        }
+    }
+}
+
+impl Default for CiTrigger {
+    fn default() -> Self {
+        CiTrigger::None
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FreshBase {
+    Ignore,
+    Pull,
+    Warn,
+    Error,
+}
+
+impl FreshBase {
+    pub fn variants() -> [&'static str; 4] {
+        ["ignore", "pull", "warn", "error"]
+    }
+}
+
+impl std::str::FromStr for FreshBase {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(FreshBase::Ignore),
+            "pull" => Ok(FreshBase::Pull),
+            "warn" => Ok(FreshBase::Warn),
+            "error" => Ok(FreshBase::Error),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for FreshBase {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            FreshBase::Ignore => "ignore".fmt(f),
+            FreshBase::Pull => "pull".fmt(f),
+            FreshBase::Warn => "warn".fmt(f),
+            FreshBase::Error => "error".fmt(f),
+        }
+    }
+}
+
+impl Default for FreshBase {
+    fn default() -> Self {
+        FreshBase::Ignore
+    }
+}
+
+/// What to do when an operation would touch an implicitly protected branch
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProtectionAction {
+    Skip,
+    Warn,
+    Error,
+}
+
+impl ProtectionAction {
+    pub fn variants() -> [&'static str; 3] {
+        ["skip", "warn", "error"]
+    }
+}
+
+impl std::str::FromStr for ProtectionAction {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ProtectionAction::Skip),
+            "warn" => Ok(ProtectionAction::Warn),
+            "error" => Ok(ProtectionAction::Error),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for ProtectionAction {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            ProtectionAction::Skip => "skip".fmt(f),
+            ProtectionAction::Warn => "warn".fmt(f),
+            ProtectionAction::Error => "error".fmt(f),
+        }
+    }
+}
+
+impl Default for ProtectionAction {
+    fn default() -> Self {
+        ProtectionAction::Skip
+    }
+}
+
+/// Where to read server-side branch protection from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Forge {
+    None,
+    Github,
+    Gitlab,
+}
+
+impl Forge {
+    pub fn variants() -> [&'static str; 3] {
+        ["none", "github", "gitlab"]
+    }
+}
+
+impl std::str::FromStr for Forge {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Forge::None),
+            "github" => Ok(Forge::Github),
+            "gitlab" => Ok(Forge::Gitlab),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Forge {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Forge::None => "none".fmt(f),
+            Forge::Github => "github".fmt(f),
+            Forge::Gitlab => "gitlab".fmt(f),
+        }
+    }
+}
+
+impl Default for Forge {
+    fn default() -> Self {
+        Forge::None
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Confirm {
+    Always,
+    Destructive,
+    Never,
+}
+
+impl Confirm {
+    pub fn variants() -> [&'static str; 3] {
+        ["always", "destructive", "never"]
+    }
+}
+
+impl std::str::FromStr for Confirm {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Confirm::Always),
+            "destructive" => Ok(Confirm::Destructive),
+            "never" => Ok(Confirm::Never),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Confirm {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Confirm::Always => "always".fmt(f),
+            Confirm::Destructive => "destructive".fmt(f),
+            Confirm::Never => "never".fmt(f),
+        }
+    }
+}
+
+impl Default for Confirm {
+    fn default() -> Self {
+        Confirm::Never
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DeleteRemote {
+    Ask,
+    Always,
+    Never,
+}
+
+impl DeleteRemote {
+    pub fn variants() -> [&'static str; 3] {
+        ["ask", "always", "never"]
+    }
+}
+
+impl std::str::FromStr for DeleteRemote {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(DeleteRemote::Ask),
+            "always" => Ok(DeleteRemote::Always),
+            "never" => Ok(DeleteRemote::Never),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for DeleteRemote {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            DeleteRemote::Ask => "ask".fmt(f),
+            DeleteRemote::Always => "always".fmt(f),
+            DeleteRemote::Never => "never".fmt(f),
+        }
+    }
+}
+
+impl Default for DeleteRemote {
+    fn default() -> Self {
+        DeleteRemote::Never
+    }
+}
+
+/// Extra details to show for each commit, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Columns(Vec<Column>);
+
+impl Columns {
+    pub fn iter(&self) -> impl Iterator<Item = Column> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// How many hex digits to abbreviate commit ids to, `None` to defer to `core.abbrev`
+    pub fn sha_len(&self) -> Option<usize> {
+        self.iter().find_map(|c| match c {
+            Column::Sha(len) => len,
+            _ => None,
+        })
+    }
+}
+
+impl std::str::FromStr for Columns {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .map(Column::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Columns(columns))
+    }
+}
+
+impl std::fmt::Display for Columns {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let columns: Vec<_> = self.iter().map(|c| c.to_string()).collect();
+        columns.join(",").fmt(f)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// Relative age of the commit, like `3 days ago`
+    Age,
+    /// Initials of the commit's author
+    Author,
+    /// Commit id, with an optional length
+    Sha(Option<usize>),
+}
+
+impl Column {
+    pub fn variants() -> [&'static str; 3] {
+        ["age", "author", "sha[=<len>]"]
+    }
+}
+
+impl std::str::FromStr for Column {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "age" => Ok(Column::Age),
+            None if s == "author" => Ok(Column::Author),
+            None if s == "sha" => Ok(Column::Sha(None)),
+            Some(("sha", len)) => match len.trim().parse::<usize>() {
+                Ok(len) if (4..=40).contains(&len) => Ok(Column::Sha(Some(len))),
+                _ => Err("sha length must be between 4 and 40".to_owned()),
+            },
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Column {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Column::Age => "age".fmt(f),
+            Column::Author => "author".fmt(f),
+            Column::Sha(None) => "sha".fmt(f),
+            Column::Sha(Some(len)) => write!(f, "sha={}", len),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::forge_log;
+use std::io::Write;
+
+/// A token to authenticate with a forge, and where it was found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub secret: String,
+    pub source: TokenSource,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenSource {
+    /// `stack.forge-token`
+    Config,
+    /// An environment variable, e.g. `GITHUB_TOKEN`
+    Env(&'static str),
+    /// A forge CLI's login, e.g. from `gh auth login`
+    CliConfig(std::path::PathBuf),
+    /// `git credential fill`, as used when pushing over HTTPS
+    CredentialHelper,
+}
+
+impl std::fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            TokenSource::Config => write!(f, "`stack.forge-token`"),
+            TokenSource::Env(name) => write!(f, "`${}`", name),
+            TokenSource::CliConfig(path) => write!(f, "{}", path.display()),
+            TokenSource::CredentialHelper => write!(f, "the git credential helper"),
+        }
+    }
+}
+
+/// The first token found for the forge at `base`
+///
+/// In order: `stack.forge-token`, the forge's environment variables, its CLI's login
+/// (`gh`/`glab`), and then git's credential helper.
+pub fn find_token(
+    repo: &git2::Repository,
+    repo_config: &crate::config::RepoConfig,
+    kind: crate::config::Forge,
+    base: &str,
+) -> Option<Token> {
+    if let Some(secret) = repo_config.forge_token() {
+        return Some(Token {
+            secret: secret.to_owned(),
+            source: TokenSource::Config,
+        });
+    }
+
+    let (scheme, host) = base.split_once("://").unwrap_or(("https", base));
+    let env_vars: &[&'static str] = match kind {
+        crate::config::Forge::None => &[],
+        crate::config::Forge::Github => &["GITHUB_TOKEN", "GH_TOKEN"],
+        crate::config::Forge::Gitlab => &["GITLAB_TOKEN"],
+    };
+    for name in env_vars {
+        if let Some(secret) = std::env::var(name).ok().filter(|t| !t.is_empty()) {
+            return Some(Token {
+                secret,
+                source: TokenSource::Env(name),
+            });
+        }
+    }
+
+    let cli_token = match kind {
+        crate::config::Forge::None => None,
+        crate::config::Forge::Github => {
+            let path = cli_config_dir("GH_CONFIG_DIR", "gh").map(|dir| dir.join("hosts.yml"));
+            path.and_then(|path| {
+                let content = std::fs::read_to_string(&path).ok()?;
+                let secret = yaml_value(&content, &[host, "oauth_token"])?;
+                Some((path, secret))
+            })
+        }
+        crate::config::Forge::Gitlab => {
+            let path =
+                cli_config_dir("GLAB_CONFIG_DIR", "glab-cli").map(|dir| dir.join("config.yml"));
+            path.and_then(|path| {
+                let content = std::fs::read_to_string(&path).ok()?;
+                let secret = yaml_value(&content, &["hosts", host, "token"])?;
+                Some((path, secret))
+            })
+        }
+    };
+    if let Some((path, secret)) = cli_token {
+        return Some(Token {
+            secret,
+            source: TokenSource::CliConfig(path),
+        });
+    }
+
+    match credential_fill(repo, scheme, host) {
+        Ok(Some(secret)) => Some(Token {
+            secret,
+            source: TokenSource::CredentialHelper,
+        }),
+        Ok(None) => None,
+        Err(err) => {
+            forge_log!(
+                debug,
+                "Could not ask the credential helper for {}, {}",
+                host,
+                err
+            );
+            None
+        }
+    }
+}
+
+/// `$<env_var>`, falling back to `<name>` in the user's config directory
+fn cli_config_dir(env_var: &str, name: &str) -> Option<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os(env_var) {
+        return Some(dir.into());
+    }
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config"))
+        })
+        .map(|dir| dir.join(name))
+}
+
+/// The password git would use for `host`, without ever prompting for it
+fn credential_fill(
+    repo: &git2::Repository,
+    scheme: &str,
+    host: &str,
+) -> eyre::Result<Option<String>> {
+    let mut cmd = std::process::Command::new("git");
+    cmd.args([
+        "-c",
+        "core.askPass=",
+        "-c",
+        "credential.interactive=false",
+        "credential",
+        "fill",
+    ])
+    .current_dir(repo.path())
+    .env("GIT_TERMINAL_PROMPT", "0")
+    .env_remove("GIT_ASKPASS")
+    .env_remove("SSH_ASKPASS")
+    .stdin(std::process::Stdio::piped())
+    .stdout(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::null());
+    forge_log!(trace, "Running {:?}", cmd);
+    let mut child = cmd.spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        write!(stdin, "protocol={}\nhost={}\n\n", scheme, host)?;
+    }
+    let output = child.wait_with_output()?;
+    // Fails when no helper has anything and it may not prompt
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let stdout = String::from_utf8(output.stdout)?;
+    let password = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("password="))
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_owned());
+    Ok(password)
+}
+
+/// A scalar from nested block mappings, like the `gh`/`glab` config files use
+///
+/// This is just enough YAML to find a token without pulling in a YAML parser.
+fn yaml_value(content: &str, path: &[&str]) -> Option<String> {
+    let mut depth = 0;
+    let mut parent_indent = None;
+    let mut child_indent = None;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        if parent_indent.map(|p| indent <= p).unwrap_or(false) {
+            // Left the mapping without finding the key
+            return None;
+        }
+        if *child_indent.get_or_insert(indent) != indent {
+            continue;
+        }
+        let (key, value) = match trimmed.strip_suffix(':') {
+            Some(key) => (key, ""),
+            None => match trimmed.split_once(": ") {
+                Some(entry) => entry,
+                None => continue,
+            },
+        };
+        if unquote(key) != path[depth] {
+            continue;
+        }
+        depth += 1;
+        if depth == path.len() {
+            let value = unquote(value.trim());
+            return (!value.is_empty()).then(|| value.to_owned());
+        }
+        parent_indent = Some(indent);
+        child_indent = None;
+    }
+    None
+}
+
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    ['"', '\'']
+        .iter()
+        .find_map(|q| s.strip_prefix(*q).and_then(|s| s.strip_suffix(*q)))
+        .unwrap_or(s)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gh_hosts() {
+        let content = "\
+gitlab.example.com:
+    oauth_token: wrong
+github.com:
+    users:
+        me:
+            oauth_token: also-wrong
+    git_protocol: https
+    user: me
+    oauth_token: gho_secret
+";
+        assert_eq!(
+            yaml_value(content, &["github.com", "oauth_token"]).as_deref(),
+            Some("gho_secret")
+        );
+        assert_eq!(yaml_value(content, &["example.com", "oauth_token"]), None);
+    }
+
+    #[test]
+    fn glab_config() {
+        let content = "\
+# What editor glab should run
+editor:
+hosts:
+    gitlab.com:
+        # Your GitLab access token
+        token: \"glpat-secret\"
+        api_host: gitlab.com
+    gitlab.example.com:8443:
+        token:
+check_update: true
+";
+        assert_eq!(
+            yaml_value(content, &["hosts", "gitlab.com", "token"]).as_deref(),
+            Some("glpat-secret")
+        );
+        assert_eq!(
+            yaml_value(content, &["hosts", "gitlab.example.com:8443", "token"]),
+            None
+        );
+        assert_eq!(yaml_value(content, &["hosts", "check_update"]), None);
+    }
+}
@@ -0,0 +1,473 @@
+use crate::forge_log;
+use eyre::WrapErr;
+
+/// Longest we'll wait on a rate limit before giving up on the forge for this run
+const MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+const MAX_ATTEMPTS: usize = 3;
+/// Cached replies not used for this long are dropped
+const MAX_CACHE_AGE: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+/// Most cached replies to keep, dropping the least recently used
+const MAX_CACHE_ENTRIES: usize = 500;
+
+/// Talks to a forge's REST API through `curl`, honoring `git`'s network settings
+///
+/// Replies to `GET`s are kept in `.git/stack/forge-responses.json` to revalidate with their
+/// `ETag`.  Conditional requests answered with `304 Not Modified` don't count against GitHub's
+/// quota.  They are kept per token, as what a forge replies with depends on who is asking.
+pub struct Client {
+    http: crate::git::HttpConfig,
+    auth: Option<String>,
+    cache: ResponseCache,
+    cache_path: std::path::PathBuf,
+}
+
+impl Client {
+    pub fn new(repo: &git2::Repository) -> Self {
+        let cache_path = repo.path().join("stack").join("forge-responses.json");
+        Self {
+            http: crate::git::HttpConfig::from_repo(repo),
+            auth: None,
+            cache: ResponseCache::load(&cache_path),
+            cache_path,
+        }
+    }
+
+    /// The header to authenticate with, e.g. `Authorization: Bearer <token>`
+    pub fn set_auth(&mut self, header: Option<String>) {
+        self.auth = header;
+    }
+
+    /// `GET` `url`, revalidating any cached reply and backing off when rate limited
+    ///
+    /// When the forge asks us to wait longer than [`MAX_BACKOFF`], the cached reply is used until
+    /// then without asking again.
+    pub fn get<T: serde::de::DeserializeOwned>(&mut self, url: &str) -> eyre::Result<T> {
+        let res = self.get_cached(url);
+        self.cache.prune(std::time::SystemTime::now());
+        if let Err(err) = self.cache.save(&self.cache_path) {
+            forge_log!(debug, "Could not save forge responses: {}", err);
+        }
+        let body = res?;
+        serde_json::from_str(&body).wrap_err_with(|| format!("Unexpected reply from {}", url))
+    }
+
+    /// `POST`, `PUT`, or `PATCH` `body` to `url`
+    pub fn send<T: serde::de::DeserializeOwned>(
+        &mut self,
+        method: &str,
+        url: &str,
+        body: &impl serde::Serialize,
+    ) -> eyre::Result<T> {
+        let reply = self.send_raw(method, url, body)?;
+        // e.g. `204 No Content`
+        let reply = if reply.iter().all(|b| b.is_ascii_whitespace()) {
+            &b"null"[..]
+        } else {
+            &reply[..]
+        };
+        serde_json::from_slice(reply).wrap_err_with(|| format!("Unexpected reply from {}", url))
+    }
+
+    /// Like [`Client::send`] for services that may not reply with JSON, e.g. webhooks
+    pub fn send_ignoring_reply(
+        &mut self,
+        method: &str,
+        url: &str,
+        body: &impl serde::Serialize,
+    ) -> eyre::Result<()> {
+        self.send_raw(method, url, body)?;
+        Ok(())
+    }
+
+    fn send_raw(
+        &mut self,
+        method: &str,
+        url: &str,
+        body: &impl serde::Serialize,
+    ) -> eyre::Result<Vec<u8>> {
+        let body = serde_json::to_string(body)?;
+        let mut config = self.auth_config();
+        config.push(("request", method.to_owned()));
+        config.push(("header", "Content-Type: application/json".to_owned()));
+        config.push(("data-binary", body));
+        let response = curl(&self.http, url, &config)?;
+        let rate_limit = RateLimit::from_headers(&response.headers);
+        match response.status {
+            200..=299 => Ok(response.body),
+            403 | 429 if rate_limit.is_limited() => {
+                let wait = rate_limit.wait(std::time::SystemTime::now());
+                eyre::bail!(
+                    "rate limited for another {}",
+                    humantime::format_duration(wait)
+                );
+            }
+            status => {
+                let message = serde_json::from_slice::<serde_json::Value>(&response.body)
+                    .ok()
+                    .and_then(|reply| reply.get("message")?.as_str().map(|m| m.to_owned()))
+                    .unwrap_or_default();
+                eyre::bail!("{} {} failed with HTTP {} {}", method, url, status, message);
+            }
+        }
+    }
+
+    fn get_cached(&mut self, url: &str) -> eyre::Result<String> {
+        let now = std::time::SystemTime::now();
+        let key = cache_key(self.auth.as_deref(), url);
+        if let Some(until) = self.cache.backoff_until() {
+            if now < until {
+                let remaining = humantime::format_duration(round_secs(until.duration_since(now)?));
+                return match self.cache.get(&key, now) {
+                    Some(cached) => {
+                        forge_log!(
+                            debug,
+                            "Rate limited for another {}, reusing the last reply from {}",
+                            remaining,
+                            url
+                        );
+                        Ok(cached.body.clone())
+                    }
+                    None => Err(eyre::eyre!("rate limited for another {}", remaining)),
+                };
+            }
+            self.cache.backoff_until = None;
+        }
+
+        let mut config = self.auth_config();
+        if let Some(cached) = self.cache.get(&key, now) {
+            config.push(("header", format!("If-None-Match: {}", cached.etag)));
+        }
+        for attempt in 1..=MAX_ATTEMPTS {
+            let response = curl(&self.http, url, &config)?;
+            let rate_limit = RateLimit::from_headers(&response.headers);
+            if let Some(remaining) = rate_limit.remaining {
+                forge_log!(trace, "{} requests left until the rate limit", remaining);
+            }
+            match response.status {
+                200..=299 => {
+                    if rate_limit.remaining == Some(0) {
+                        self.cache.set_backoff_until(rate_limit.reset);
+                    }
+                    let etag = response.header("etag").map(|e| e.to_owned());
+                    let body = String::from_utf8(response.body)
+                        .wrap_err_with(|| format!("Unexpected reply from {}", url))?;
+                    match etag {
+                        Some(etag) => {
+                            let cached = CachedResponse {
+                                etag,
+                                body: body.clone(),
+                                used: epoch_secs(now),
+                            };
+                            self.cache.responses.insert(key, cached);
+                        }
+                        None => {
+                            self.cache.responses.remove(&key);
+                        }
+                    }
+                    return Ok(body);
+                }
+                304 => {
+                    forge_log!(trace, "{} is unchanged", url);
+                    let cached = self
+                        .cache
+                        .get(&key, now)
+                        .ok_or_else(|| eyre::eyre!("{} is unchanged but wasn't cached", url))?;
+                    return Ok(cached.body.clone());
+                }
+                403 | 429 if rate_limit.is_limited() => {
+                    let now = std::time::SystemTime::now();
+                    let wait = rate_limit.wait(now);
+                    if wait <= MAX_BACKOFF && attempt < MAX_ATTEMPTS {
+                        forge_log!(
+                            debug,
+                            "Rate limited, retrying in {}",
+                            humantime::format_duration(wait)
+                        );
+                        std::thread::sleep(wait);
+                        continue;
+                    }
+                    self.cache.set_backoff_until(Some(now + wait));
+                    if let Some(cached) = self.cache.get(&key, now) {
+                        forge_log!(
+                            debug,
+                            "Rate limited for {}, reusing the last reply from {}",
+                            humantime::format_duration(wait),
+                            url
+                        );
+                        return Ok(cached.body.clone());
+                    }
+                    eyre::bail!(
+                        "rate limited for another {}",
+                        humantime::format_duration(wait)
+                    );
+                }
+                status => {
+                    eyre::bail!("GET {} failed with HTTP {}", url, status);
+                }
+            }
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    fn auth_config(&self) -> Vec<(&'static str, String)> {
+        self.auth
+            .iter()
+            .map(|auth| ("header", auth.clone()))
+            .collect()
+    }
+}
+
+struct Response {
+    status: u32,
+    /// Lower-cased names
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// `config` is passed as a `curl --config` file on stdin, along with `http`'s, so tokens don't show
+/// up in the process list
+fn curl(
+    http: &crate::git::HttpConfig,
+    url: &str,
+    config: &[(&str, String)],
+) -> eyre::Result<Response> {
+    let mut cmd = std::process::Command::new("curl");
+    // Not `--fail`, the status decides between using the cache and backing off
+    cmd.args(["--silent", "--show-error", "--location", "--include"]);
+    cmd.args(["--config", "-"]);
+    if !http.ssl_verify {
+        cmd.arg("--insecure");
+    }
+    cmd.arg(url)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped());
+    forge_log!(trace, "Running {:?}", cmd);
+    let mut child = cmd.spawn().wrap_err("Could not run `curl`")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        http.write_curl_config(&mut stdin, config)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        eyre::bail!("`curl {}` failed", url);
+    }
+    parse_response(output.stdout).ok_or_else(|| eyre::eyre!("Unexpected reply from {}", url))
+}
+
+/// Split `curl --include` output, keeping only the headers of the last of any redirects
+fn parse_response(mut output: Vec<u8>) -> Option<Response> {
+    let mut status = None;
+    let mut headers = Vec::new();
+    while output.starts_with(b"HTTP/") {
+        let end = output.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let head = String::from_utf8_lossy(&output[..end]).into_owned();
+        output.drain(..end + 4);
+
+        let mut lines = head.split("\r\n");
+        status = lines
+            .next()?
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok());
+        headers = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(n, v)| (n.trim().to_ascii_lowercase(), v.trim().to_owned()))
+            .collect();
+    }
+    Some(Response {
+        status: status?,
+        headers,
+        body: output,
+    })
+}
+
+/// GitHub's `X-RateLimit-*` or GitLab's `RateLimit-*`, and `Retry-After`
+#[derive(Default)]
+struct RateLimit {
+    remaining: Option<u64>,
+    reset: Option<std::time::SystemTime>,
+    retry_after: Option<std::time::Duration>,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &[(String, String)]) -> Self {
+        let get = |names: &[&str]| {
+            headers
+                .iter()
+                .find(|(n, _)| names.contains(&n.as_str()))
+                .and_then(|(_, v)| v.parse::<u64>().ok())
+        };
+        Self {
+            remaining: get(&["x-ratelimit-remaining", "ratelimit-remaining"]),
+            reset: get(&["x-ratelimit-reset", "ratelimit-reset"])
+                .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+            // Only the delay form, HTTP dates aren't worth parsing for this
+            retry_after: get(&["retry-after"]).map(std::time::Duration::from_secs),
+        }
+    }
+
+    fn is_limited(&self) -> bool {
+        self.remaining == Some(0) || self.retry_after.is_some()
+    }
+
+    fn wait(&self, now: std::time::SystemTime) -> std::time::Duration {
+        self.retry_after
+            .or_else(|| self.reset.and_then(|reset| reset.duration_since(now).ok()))
+            .map(round_secs)
+            // Without a hint, a minute is GitHub's recommendation for secondary rate limits
+            .unwrap_or_else(|| std::time::Duration::from_secs(60))
+    }
+}
+
+fn round_secs(duration: std::time::Duration) -> std::time::Duration {
+    std::time::Duration::from_secs(duration.as_secs().max(1))
+}
+
+/// Forge replies by token and URL (see [`cache_key`]), kept to revalidate with their `ETag`, and
+/// when to ask again after being rate limited
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+struct ResponseCache {
+    #[serde(default)]
+    responses: std::collections::BTreeMap<String, CachedResponse>,
+    /// Seconds since the epoch
+    #[serde(default)]
+    backoff_until: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedResponse {
+    etag: String,
+    body: String,
+    /// Seconds since the epoch
+    #[serde(default)]
+    used: u64,
+}
+
+/// Who is asking and for what, without keeping the token itself
+fn cache_key(auth: Option<&str>, url: &str) -> String {
+    match auth.and_then(|auth| git2::Oid::hash_object(git2::ObjectType::Blob, auth.as_bytes()).ok())
+    {
+        Some(id) => format!("{:.12} {}", id.to_string(), url),
+        None => format!("anonymous {}", url),
+    }
+}
+
+fn epoch_secs(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl ResponseCache {
+    fn load(path: &std::path::Path) -> Self {
+        let content = match std::fs::read(path) {
+            Ok(content) => content,
+            Err(err) => {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    forge_log!(debug, "Could not read {}: {}", path.display(), err);
+                }
+                return Self::default();
+            }
+        };
+        serde_json::from_slice(&content).unwrap_or_else(|err| {
+            forge_log!(debug, "Ignoring {}: {}", path.display(), err);
+            Self::default()
+        })
+    }
+
+    fn save(&self, path: &std::path::Path) -> eyre::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// The reply for `key`, marking it as used at `now`
+    fn get(&mut self, key: &str, now: std::time::SystemTime) -> Option<&CachedResponse> {
+        let cached = self.responses.get_mut(key)?;
+        cached.used = epoch_secs(now);
+        Some(cached)
+    }
+
+    /// Drop replies unused for [`MAX_CACHE_AGE`], then all but the [`MAX_CACHE_ENTRIES`] most
+    /// recently used
+    fn prune(&mut self, now: std::time::SystemTime) {
+        let oldest = epoch_secs(now).saturating_sub(MAX_CACHE_AGE.as_secs());
+        self.responses.retain(|_, cached| oldest <= cached.used);
+        if MAX_CACHE_ENTRIES < self.responses.len() {
+            let mut by_use: Vec<_> = self
+                .responses
+                .iter()
+                .map(|(key, cached)| (cached.used, key.clone()))
+                .collect();
+            by_use.sort_unstable_by(|a, b| b.cmp(a));
+            for (_, key) in by_use.split_off(MAX_CACHE_ENTRIES) {
+                self.responses.remove(&key);
+            }
+        }
+    }
+
+    fn backoff_until(&self) -> Option<std::time::SystemTime> {
+        self.backoff_until
+            .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+    }
+
+    fn set_backoff_until(&mut self, until: Option<std::time::SystemTime>) {
+        self.backoff_until = until
+            .and_then(|until| until.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cached(used: u64) -> CachedResponse {
+        CachedResponse {
+            etag: "\"1\"".to_owned(),
+            body: "[]".to_owned(),
+            used,
+        }
+    }
+
+    #[test]
+    fn cache_key_per_token() {
+        let url = "https://api.github.com/repos/o/r/branches";
+        let alice = cache_key(Some("Authorization: Bearer alice"), url);
+        let bob = cache_key(Some("Authorization: Bearer bob"), url);
+        assert_ne!(alice, bob);
+        assert_ne!(alice, cache_key(None, url));
+        assert!(!alice.contains("alice"));
+    }
+
+    #[test]
+    fn prune_expired_and_excess() {
+        let now = std::time::UNIX_EPOCH + MAX_CACHE_AGE * 2;
+        let now_secs = epoch_secs(now);
+        let mut cache = ResponseCache::default();
+        cache.responses.insert("stale".to_owned(), cached(0));
+        for i in 0..=MAX_CACHE_ENTRIES {
+            cache
+                .responses
+                .insert(format!("fresh {}", i), cached(now_secs - i as u64));
+        }
+
+        cache.prune(now);
+        assert_eq!(cache.responses.len(), MAX_CACHE_ENTRIES);
+        assert!(!cache.responses.contains_key("stale"));
+        assert!(cache.responses.contains_key("fresh 0"));
+        assert!(!cache
+            .responses
+            .contains_key(&format!("fresh {}", MAX_CACHE_ENTRIES)));
+    }
+}
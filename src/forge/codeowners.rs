@@ -0,0 +1,187 @@
+/// Where forges look for `CODEOWNERS`, the first found being used
+pub const CODEOWNERS_PATHS: &[&str] = &[
+    ".github/CODEOWNERS",
+    ".gitlab/CODEOWNERS",
+    "CODEOWNERS",
+    "docs/CODEOWNERS",
+];
+
+/// Who owns which paths, from a `CODEOWNERS` file
+///
+/// Within a section, the last matching pattern wins, like GitHub.  GitLab's `[Section]`s are
+/// each matched separately, with a path's owners being those of every section.
+#[derive(Clone, Debug, Default)]
+pub struct Codeowners {
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    section: usize,
+    pattern: ignore::gitignore::Gitignore,
+    owners: Vec<String>,
+}
+
+impl Codeowners {
+    pub fn parse(content: &str) -> Self {
+        let mut rules = Vec::new();
+        let mut section = 0;
+        let mut section_owners: Vec<String> = Vec::new();
+        for line in content.lines() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('^').unwrap_or(line).strip_prefix('[') {
+                // `[Section][approvals] @default-owner`
+                section += 1;
+                section_owners = header
+                    .split_once(']')
+                    .map(|(_, rest)| rest)
+                    .map(|rest| match rest.strip_prefix('[') {
+                        Some(rest) => rest.split_once(']').map_or("", |(_, rest)| rest),
+                        None => rest,
+                    })
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(|o| o.to_owned())
+                    .collect();
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let pattern = fields.next().expect("line isn't empty");
+            let mut owners: Vec<_> = fields.map(|o| o.to_owned()).collect();
+            if owners.is_empty() {
+                owners = section_owners.clone();
+            }
+            let mut builder = ignore::gitignore::GitignoreBuilder::new("");
+            let built = builder.add_line(None, pattern).and_then(|b| b.build());
+            match built {
+                Ok(pattern) => rules.push(Rule {
+                    section,
+                    pattern,
+                    owners,
+                }),
+                Err(err) => {
+                    log::debug!("Ignoring CODEOWNERS pattern `{}`: {}", pattern, err);
+                }
+            }
+        }
+        Self { rules }
+    }
+
+    /// Owners of `path`, relative to the repo root
+    pub fn owners(&self, path: &str) -> Vec<&str> {
+        let mut owners: Vec<&str> = Vec::new();
+        let mut matched_sections = Vec::new();
+        for rule in self.rules.iter().rev() {
+            if matched_sections.contains(&rule.section) {
+                continue;
+            }
+            if rule
+                .pattern
+                .matched_path_or_any_parents(path, false)
+                .is_ignore()
+            {
+                matched_sections.push(rule.section);
+                for owner in &rule.owners {
+                    if !owners.contains(&owner.as_str()) {
+                        owners.push(owner);
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    /// Owners of any of `paths`, in the order they are first found
+    pub fn owners_of<'p>(&self, paths: impl IntoIterator<Item = &'p str>) -> Vec<&str> {
+        let mut owners: Vec<&str> = Vec::new();
+        for path in paths {
+            for owner in self.owners(path) {
+                if !owners.contains(&owner) {
+                    owners.push(owner);
+                }
+            }
+        }
+        owners
+    }
+}
+
+/// `line` up to its first `#` that isn't escaped as `\#`
+fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '#' if !escaped => return &line[..i],
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn last_match_wins() {
+        let codeowners = Codeowners::parse(
+            "\
+# Everything
+*       @org/everyone
+/docs/  @docs-lead # The docs
+*.rs    @rustacean
+",
+        );
+        assert_eq!(codeowners.owners("README.md"), ["@org/everyone"]);
+        assert_eq!(codeowners.owners("docs/guide.md"), ["@docs-lead"]);
+        assert_eq!(codeowners.owners("src/lib.rs"), ["@rustacean"]);
+        assert_eq!(codeowners.owners("docs/example.rs"), ["@rustacean"]);
+    }
+
+    #[test]
+    fn unowned() {
+        let codeowners = Codeowners::parse("/src/ @dev\n/src/generated/\n");
+        assert_eq!(codeowners.owners("src/main.rs"), ["@dev"]);
+        assert!(codeowners.owners("src/generated/api.rs").is_empty());
+        assert!(codeowners.owners("README.md").is_empty());
+    }
+
+    #[test]
+    fn sections() {
+        let codeowners = Codeowners::parse(
+            "\
+* @dev
+[Docs] @writer
+*.md
+^[Security][2] @sec
+/src/auth/ @sec-lead
+",
+        );
+        assert_eq!(codeowners.owners("README.md"), ["@writer", "@dev"]);
+        assert_eq!(
+            codeowners.owners("src/auth/login.rs"),
+            ["@sec-lead", "@dev"]
+        );
+        assert_eq!(codeowners.owners("src/main.rs"), ["@dev"]);
+    }
+
+    #[test]
+    fn owners_of() {
+        let codeowners = Codeowners::parse("*.rs @a @b\n*.md @b @c\n");
+        assert_eq!(
+            codeowners.owners_of(["src/lib.rs", "README.md", "src/main.rs"]),
+            ["@a", "@b", "@c"]
+        );
+    }
+
+    #[test]
+    fn escaped_hash() {
+        let codeowners = Codeowners::parse("\\#notes.md @scribe # Not a comment until here\n");
+        assert_eq!(codeowners.owners("#notes.md"), ["@scribe"]);
+        assert!(codeowners.owners("notes.md").is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::forge_log;
+use eyre::WrapErr;
+
+/// A forge implemented by an external program, `stack.forge-command`
+///
+/// Like `core.editor`, the command is a shell snippet.  It is run with the operation as its
+/// argument (`list-protected`, `create-pr`, `update-base`, `enqueue`, `get-pr-state`,
+/// `request-reviewers`, `get-status`, or `trigger-ci`), a JSON request on stdin, and is expected to
+/// write a JSON reply to stdout.  Every request has the `remote` and its `url`.
+pub struct CommandForge {
+    command: String,
+    remote: String,
+    url: Option<String>,
+}
+
+impl CommandForge {
+    pub fn new(command: String, remote: String, url: Option<String>) -> Self {
+        Self {
+            command,
+            remote,
+            url,
+        }
+    }
+
+    fn run<T: serde::de::DeserializeOwned>(
+        &self,
+        operation: &str,
+        mut request: serde_json::Value,
+    ) -> eyre::Result<T> {
+        use std::io::Write;
+
+        request["remote"] = serde_json::Value::from(self.remote.as_str());
+        request["url"] = serde_json::json!(self.url);
+
+        forge_log!(trace, "{} {}", self.command, operation);
+        let mut child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg(&self.command)
+            .arg(operation)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .wrap_err_with(|| format!("Could not run `{}`", self.command))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            serde_json::to_writer(&mut stdin, &request)?;
+            writeln!(stdin)?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            eyre::bail!(
+                "`{} {}` failed with {}",
+                self.command,
+                operation,
+                output.status
+            );
+        }
+        // Nothing to say is fine for `update-base`, `enqueue`, `request-reviewers`, and
+        // `trigger-ci`
+        let reply = if output.stdout.iter().all(|b| b.is_ascii_whitespace()) {
+            &b"null"[..]
+        } else {
+            &output.stdout[..]
+        };
+        serde_json::from_slice(reply)
+            .wrap_err_with(|| format!("Unexpected reply from `{} {}`", self.command, operation))
+    }
+}
+
+impl super::Forge for CommandForge {
+    fn list_protected(&mut self) -> eyre::Result<Vec<String>> {
+        self.run("list-protected", serde_json::json!({}))
+    }
+
+    fn create_pr(&mut self, pr: &super::NewPullRequest<'_>) -> eyre::Result<super::PullRequest> {
+        self.run("create-pr", serde_json::to_value(pr)?)
+    }
+
+    fn update_base(&mut self, number: u64, base: &str) -> eyre::Result<()> {
+        let _: serde_json::Value = self.run(
+            "update-base",
+            serde_json::json!({ "number": number, "base": base }),
+        )?;
+        Ok(())
+    }
+
+    fn enqueue(&mut self, number: u64) -> eyre::Result<()> {
+        let _: serde_json::Value = self.run("enqueue", serde_json::json!({ "number": number }))?;
+        Ok(())
+    }
+
+    fn get_pr_state(&mut self, number: u64) -> eyre::Result<super::PullRequestState> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            state: String,
+        }
+
+        let reply: Reply = self.run("get-pr-state", serde_json::json!({ "number": number }))?;
+        reply.state.parse().map_err(|err| {
+            eyre::eyre!(
+                "Unexpected state `{}` from `{}`, {}",
+                reply.state,
+                self.command,
+                err
+            )
+        })
+    }
+
+    fn request_reviewers(
+        &mut self,
+        number: u64,
+        reviewers: &[&str],
+    ) -> eyre::Result<super::ReviewRequests> {
+        let _: serde_json::Value = self.run(
+            "request-reviewers",
+            serde_json::json!({ "number": number, "reviewers": reviewers }),
+        )?;
+        Ok(super::ReviewRequests {
+            requested: reviewers.iter().map(|r| (*r).to_owned()).collect(),
+            failed: Vec::new(),
+        })
+    }
+
+    fn get_status(&mut self, branch: &str) -> eyre::Result<super::Status> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            status: String,
+        }
+
+        let reply: Reply = self.run("get-status", serde_json::json!({ "branch": branch }))?;
+        reply.status.parse().map_err(|err| {
+            eyre::eyre!(
+                "Unexpected status `{}` from `{}`, {}",
+                reply.status,
+                self.command,
+                err
+            )
+        })
+    }
+
+    fn trigger_ci(&mut self, run: &super::CiRun<'_>) -> eyre::Result<()> {
+        let _: serde_json::Value = self.run("trigger-ci", serde_json::to_value(run)?)?;
+        Ok(())
+    }
+}
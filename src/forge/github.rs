@@ -0,0 +1,256 @@
+use crate::forge_log;
+
+pub struct Github {
+    client: super::Client,
+    api: String,
+    graphql: String,
+    project: String,
+}
+
+impl Github {
+    /// `base` is where the repo is served from, e.g. `https://github.com` or a GitHub Enterprise
+    /// host
+    ///
+    /// See [`super::find_token`] for the `token`.
+    pub fn new(
+        mut client: super::Client,
+        base: &str,
+        project: String,
+        token: Option<String>,
+    ) -> Self {
+        let (api, graphql) = if base == "https://github.com" {
+            (
+                "https://api.github.com".to_owned(),
+                "https://api.github.com/graphql".to_owned(),
+            )
+        } else {
+            (format!("{}/api/v3", base), format!("{}/api/graphql", base))
+        };
+        client.set_auth(token.map(|t| format!("Authorization: Bearer {}", t)));
+        Self {
+            client,
+            api,
+            graphql,
+            project,
+        }
+    }
+
+    fn repo_url(&self) -> String {
+        format!("{}/repos/{}", self.api, self.project)
+    }
+
+    /// Merge queues are only in GitHub's GraphQL API
+    fn graphql(
+        &mut self,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> eyre::Result<serde_json::Value> {
+        let reply: serde_json::Value = self.client.send(
+            "POST",
+            &self.graphql,
+            &serde_json::json!({ "query": query, "variables": variables }),
+        )?;
+        // Errors are reported with `200 OK`
+        if let Some(errors) = reply.get("errors").and_then(|e| e.as_array()) {
+            let messages: Vec<_> = errors
+                .iter()
+                .filter_map(|e| e.get("message").and_then(|m| m.as_str()))
+                .collect();
+            eyre::bail!("{}", messages.join("; "));
+        }
+        Ok(reply.get("data").cloned().unwrap_or_default())
+    }
+}
+
+impl super::Forge for Github {
+    fn list_protected(&mut self) -> eyre::Result<Vec<String>> {
+        let endpoint = format!("{}/branches?protected=true", self.repo_url());
+        let entries = super::get_all_pages(&mut self.client, &endpoint)?;
+        Ok(entries
+            .iter()
+            .filter_map(|e| e.get("name").and_then(|n| n.as_str()))
+            .map(|n| n.to_owned())
+            .collect())
+    }
+
+    fn create_pr(&mut self, pr: &super::NewPullRequest<'_>) -> eyre::Result<super::PullRequest> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            number: u64,
+            html_url: String,
+        }
+
+        let url = format!("{}/pulls", self.repo_url());
+        let reply: Reply = self.client.send("POST", &url, pr)?;
+        Ok(super::PullRequest {
+            number: reply.number,
+            url: reply.html_url,
+        })
+    }
+
+    fn update_base(&mut self, number: u64, base: &str) -> eyre::Result<()> {
+        let url = format!("{}/pulls/{}", self.repo_url(), number);
+        let _: serde_json::Value =
+            self.client
+                .send("PATCH", &url, &serde_json::json!({ "base": base }))?;
+        Ok(())
+    }
+
+    fn enqueue(&mut self, number: u64) -> eyre::Result<()> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            node_id: String,
+        }
+
+        let url = format!("{}/pulls/{}", self.repo_url(), number);
+        let reply: Reply = self.client.get(&url)?;
+        self.graphql(
+            "mutation($id: ID!) { enqueuePullRequest(input: {pullRequestId: $id}) { clientMutationId } }",
+            serde_json::json!({ "id": reply.node_id }),
+        )?;
+        Ok(())
+    }
+
+    fn get_pr_state(&mut self, number: u64) -> eyre::Result<super::PullRequestState> {
+        let (owner, name) = self
+            .project
+            .split_once('/')
+            .ok_or_else(|| eyre::eyre!("`{}` is not `owner/repo`", self.project))?;
+        let data = self.graphql(
+            "query($owner: String!, $name: String!, $number: Int!) { repository(owner: $owner, name: $name) { pullRequest(number: $number) { state mergeQueueEntry { state } } } }",
+            serde_json::json!({ "owner": owner, "name": name, "number": number }),
+        )?;
+        let pr = &data["repository"]["pullRequest"];
+        let state = match pr["state"].as_str() {
+            Some("MERGED") => super::PullRequestState::Merged,
+            Some("CLOSED") => super::PullRequestState::Closed,
+            Some("OPEN") if !pr["mergeQueueEntry"].is_null() => super::PullRequestState::Queued,
+            Some("OPEN") => super::PullRequestState::Open,
+            _ => eyre::bail!("No pull request {} in {}", number, self.project),
+        };
+        Ok(state)
+    }
+
+    fn request_reviewers(
+        &mut self,
+        number: u64,
+        reviewers: &[&str],
+    ) -> eyre::Result<super::ReviewRequests> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            user: User,
+        }
+        #[derive(serde::Deserialize)]
+        struct User {
+            login: String,
+        }
+
+        let url = format!("{}/pulls/{}", self.repo_url(), number);
+        let reply: Reply = self.client.get(&url)?;
+        let url = format!("{}/pulls/{}/requested_reviewers", self.repo_url(), number);
+        let mut requests = super::ReviewRequests::default();
+        for (reviewer, body) in review_requests(reviewers, &reply.user.login) {
+            // GitHub fails the whole request when any reviewer can't review
+            match self.client.send_ignoring_reply("POST", &url, &body) {
+                Ok(()) => requests.requested.push(reviewer.to_owned()),
+                Err(err) => requests.failed.push((reviewer.to_owned(), err)),
+            }
+        }
+        Ok(requests)
+    }
+
+    fn get_status(&mut self, branch: &str) -> eyre::Result<super::Status> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            state: String,
+            total_count: usize,
+        }
+
+        let url = format!(
+            "{}/commits/{}/status",
+            self.repo_url(),
+            super::percent_encode(branch)
+        );
+        let reply: Reply = self.client.get(&url)?;
+        let status = match reply.state.as_str() {
+            _ if reply.total_count == 0 => super::Status::None,
+            "success" => super::Status::Success,
+            "failure" | "error" => super::Status::Failure,
+            _ => super::Status::Pending,
+        };
+        Ok(status)
+    }
+
+    fn trigger_ci(&mut self, run: &super::CiRun<'_>) -> eyre::Result<()> {
+        let workflow = run.workflow.ok_or_else(|| {
+            eyre::eyre!(
+                "GitHub needs a workflow to dispatch, set `stack.ci-trigger=workflow:<file>`"
+            )
+        })?;
+        let url = format!(
+            "{}/actions/workflows/{}/dispatches",
+            self.repo_url(),
+            super::percent_encode(workflow)
+        );
+        self.client
+            .send_ignoring_reply("POST", &url, &serde_json::json!({ "ref": run.branch }))
+    }
+}
+
+/// The `requested_reviewers` body for each of `reviewers` that GitHub can ask
+///
+/// Authors can't review their own pull requests, and GitHub needs a username or team, not an
+/// email.
+fn review_requests<'r>(reviewers: &[&'r str], author: &str) -> Vec<(&'r str, serde_json::Value)> {
+    let mut requests = Vec::new();
+    for reviewer in reviewers {
+        let body = match reviewer.strip_prefix('@') {
+            // Teams are always of the repo's organization
+            Some(handle) => match handle.split_once('/') {
+                Some((_, team)) => serde_json::json!({ "team_reviewers": [team] }),
+                None if handle.eq_ignore_ascii_case(author) => {
+                    forge_log!(
+                        debug,
+                        "Not requesting review from `{}`, they opened the pull request",
+                        reviewer
+                    );
+                    continue;
+                }
+                None => serde_json::json!({ "reviewers": [handle] }),
+            },
+            None => {
+                forge_log!(
+                    debug,
+                    "Not requesting review from `{}`, GitHub needs a username",
+                    reviewer
+                );
+                continue;
+            }
+        };
+        requests.push((*reviewer, body));
+    }
+    requests
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn review_requests_skip_author() {
+        let requests = review_requests(
+            &["@Alice", "@bob", "@org/docs", "carol@example.com"],
+            "alice",
+        );
+        assert_eq!(
+            requests,
+            [
+                ("@bob", serde_json::json!({ "reviewers": ["bob"] })),
+                (
+                    "@org/docs",
+                    serde_json::json!({ "team_reviewers": ["docs"] })
+                ),
+            ]
+        );
+    }
+}
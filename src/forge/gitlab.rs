@@ -0,0 +1,211 @@
+use crate::forge_log;
+
+pub struct Gitlab {
+    client: super::Client,
+    api: String,
+    project_url: String,
+}
+
+impl Gitlab {
+    /// `base` is where the repo is served from, e.g. `https://gitlab.com`
+    ///
+    /// See [`super::find_token`] for the `token`.
+    pub fn new(
+        mut client: super::Client,
+        base: String,
+        project: &str,
+        token: Option<String>,
+    ) -> Self {
+        // Unlike `PRIVATE-TOKEN`, this also takes the OAuth tokens of `glab` and credential helpers
+        client.set_auth(token.map(|t| format!("Authorization: Bearer {}", t)));
+        let api = format!("{}/api/v4", base);
+        let project_url = format!("{}/projects/{}", api, super::percent_encode(project));
+        Self {
+            client,
+            api,
+            project_url,
+        }
+    }
+}
+
+impl super::Forge for Gitlab {
+    fn list_protected(&mut self) -> eyre::Result<Vec<String>> {
+        let endpoint = format!("{}/protected_branches", self.project_url);
+        let entries = super::get_all_pages(&mut self.client, &endpoint)?;
+        Ok(entries
+            .iter()
+            .filter_map(|e| e.get("name").and_then(|n| n.as_str()))
+            .map(|n| n.to_owned())
+            .collect())
+    }
+
+    fn create_pr(&mut self, pr: &super::NewPullRequest<'_>) -> eyre::Result<super::PullRequest> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            iid: u64,
+            web_url: String,
+        }
+
+        let url = format!("{}/merge_requests", self.project_url);
+        // GitLab marks drafts by their title
+        let title = if pr.draft {
+            format!("Draft: {}", pr.title)
+        } else {
+            pr.title.to_owned()
+        };
+        let request = serde_json::json!({
+            "source_branch": pr.head,
+            "target_branch": pr.base,
+            "title": title,
+            "description": pr.body,
+        });
+        let reply: Reply = self.client.send("POST", &url, &request)?;
+        Ok(super::PullRequest {
+            number: reply.iid,
+            url: reply.web_url,
+        })
+    }
+
+    fn update_base(&mut self, number: u64, base: &str) -> eyre::Result<()> {
+        let url = format!("{}/merge_requests/{}", self.project_url, number);
+        let _: serde_json::Value =
+            self.client
+                .send("PUT", &url, &serde_json::json!({ "target_branch": base }))?;
+        Ok(())
+    }
+
+    fn enqueue(&mut self, number: u64) -> eyre::Result<()> {
+        let url = format!(
+            "{}/merge_trains/merge_requests/{}",
+            self.project_url, number
+        );
+        let _: serde_json::Value = self.client.send(
+            "POST",
+            &url,
+            &serde_json::json!({ "when_pipeline_succeeds": true }),
+        )?;
+        Ok(())
+    }
+
+    fn get_pr_state(&mut self, number: u64) -> eyre::Result<super::PullRequestState> {
+        #[derive(serde::Deserialize)]
+        struct Reply {
+            state: String,
+            #[serde(default)]
+            merge_when_pipeline_succeeds: bool,
+        }
+
+        let url = format!("{}/merge_requests/{}", self.project_url, number);
+        let reply: Reply = self.client.get(&url)?;
+        let state = match reply.state.as_str() {
+            "merged" => super::PullRequestState::Merged,
+            "closed" => super::PullRequestState::Closed,
+            // Being merged
+            "locked" => super::PullRequestState::Queued,
+            _ if reply.merge_when_pipeline_succeeds => super::PullRequestState::Queued,
+            _ => super::PullRequestState::Open,
+        };
+        Ok(state)
+    }
+
+    fn request_reviewers(
+        &mut self,
+        number: u64,
+        reviewers: &[&str],
+    ) -> eyre::Result<super::ReviewRequests> {
+        #[derive(serde::Deserialize)]
+        struct User {
+            id: u64,
+        }
+
+        let mut requests = super::ReviewRequests::default();
+        let mut ids = Vec::new();
+        for reviewer in reviewers {
+            // Groups and emails can't be reviewers
+            let username = match reviewer.strip_prefix('@') {
+                Some(username) if !username.contains('/') => username,
+                _ => {
+                    forge_log!(
+                        debug,
+                        "Not requesting review from `{}`, GitLab needs a username",
+                        reviewer
+                    );
+                    continue;
+                }
+            };
+            let url = format!(
+                "{}/users?username={}",
+                self.api,
+                super::percent_encode(username)
+            );
+            match self.client.get::<Vec<User>>(&url) {
+                Ok(users) => match users.first() {
+                    Some(user) => ids.push((*reviewer, user.id)),
+                    None => requests
+                        .failed
+                        .push(((*reviewer).to_owned(), eyre::eyre!("no such GitLab user"))),
+                },
+                Err(err) => requests.failed.push(((*reviewer).to_owned(), err)),
+            }
+        }
+        if ids.is_empty() {
+            return Ok(requests);
+        }
+        // Unlike GitHub, authors may review their own merge requests
+        let url = format!("{}/merge_requests/{}", self.project_url, number);
+        let body =
+            serde_json::json!({ "reviewer_ids": ids.iter().map(|(_, id)| id).collect::<Vec<_>>() });
+        match self.client.send_ignoring_reply("PUT", &url, &body) {
+            Ok(()) => {
+                requests
+                    .requested
+                    .extend(ids.iter().map(|(reviewer, _)| (*reviewer).to_owned()));
+            }
+            Err(err) => {
+                let err = err.to_string();
+                requests.failed.extend(
+                    ids.iter()
+                        .map(|(reviewer, _)| ((*reviewer).to_owned(), eyre::eyre!("{}", err))),
+                );
+            }
+        }
+        Ok(requests)
+    }
+
+    fn get_status(&mut self, branch: &str) -> eyre::Result<super::Status> {
+        let url = format!(
+            "{}/repository/commits/{}",
+            self.project_url,
+            super::percent_encode(branch)
+        );
+        let reply: serde_json::Value = self.client.get(&url)?;
+        let pipeline_status = reply
+            .get("last_pipeline")
+            .and_then(|p| p.get("status"))
+            .and_then(|s| s.as_str());
+        let status = match pipeline_status {
+            None | Some("skipped") => super::Status::None,
+            Some("success") => super::Status::Success,
+            Some("failed") | Some("canceled") => super::Status::Failure,
+            Some(_) => super::Status::Pending,
+        };
+        Ok(status)
+    }
+
+    fn trigger_ci(&mut self, run: &super::CiRun<'_>) -> eyre::Result<()> {
+        if let Some(workflow) = run.workflow {
+            forge_log!(
+                debug,
+                "Ignoring workflow `{}`, GitLab runs the project's pipeline",
+                workflow
+            );
+        }
+        let url = format!(
+            "{}/pipeline?ref={}",
+            self.project_url,
+            super::percent_encode(run.branch)
+        );
+        let _: serde_json::Value = self.client.send("POST", &url, &serde_json::json!({}))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,371 @@
+mod auth;
+mod client;
+mod codeowners;
+mod command;
+mod github;
+mod gitlab;
+mod webhook;
+
+use crate::forge_log;
+
+pub use auth::*;
+pub use client::*;
+pub use codeowners::*;
+pub use command::*;
+pub use github::*;
+pub use gitlab::*;
+pub use webhook::*;
+
+/// A code review service hosting a repo's pull requests
+///
+/// [`from_config`] picks the implementation: `stack.forge-command` if set, otherwise
+/// `stack.forge`.
+pub trait Forge {
+    /// Patterns for the branches protected on the forge
+    fn list_protected(&mut self) -> eyre::Result<Vec<String>>;
+
+    fn create_pr(&mut self, pr: &NewPullRequest<'_>) -> eyre::Result<PullRequest>;
+
+    /// Retarget pull request `number` to merge into `base`
+    fn update_base(&mut self, number: u64, base: &str) -> eyre::Result<()>;
+
+    /// Add pull request `number` to its base's merge queue (GitHub) or merge train (GitLab)
+    fn enqueue(&mut self, number: u64) -> eyre::Result<()>;
+
+    /// How far pull request `number` is from being merged
+    fn get_pr_state(&mut self, number: u64) -> eyre::Result<PullRequestState>;
+
+    /// Ask `reviewers`, as written in `CODEOWNERS` (`@user`, `@org/team`, or an email), to review
+    /// pull request `number`
+    ///
+    /// One reviewer the forge rejects doesn't keep the others from being asked.  Reviewers the
+    /// forge can't ask, like the pull request's author, are skipped.
+    fn request_reviewers(
+        &mut self,
+        number: u64,
+        reviewers: &[&str],
+    ) -> eyre::Result<ReviewRequests>;
+
+    /// The combined CI status of `branch`'s latest commit
+    fn get_status(&mut self, branch: &str) -> eyre::Result<Status>;
+
+    /// Start CI on `run.branch`, see `stack.ci-trigger`
+    fn trigger_ci(&mut self, run: &CiRun<'_>) -> eyre::Result<()>;
+}
+
+/// A branch that was just pushed, for CI to run on
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct CiRun<'s> {
+    /// As named on the remote
+    pub branch: &'s str,
+    pub commit: String,
+    /// The GitHub Actions workflow to dispatch, from `stack.ci-trigger=workflow:<file>`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<&'s str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct NewPullRequest<'s> {
+    pub head: &'s str,
+    pub base: &'s str,
+    pub title: &'s str,
+    pub body: &'s str,
+    pub draft: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub url: String,
+}
+
+/// What came of [`Forge::request_reviewers`]
+#[derive(Debug, Default)]
+pub struct ReviewRequests {
+    /// Asked to review
+    pub requested: Vec<String>,
+    /// The forge refused to ask these, with why
+    pub failed: Vec<(String, eyre::Report)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// No CI has reported on it
+    None,
+    Pending,
+    Success,
+    Failure,
+}
+
+impl Status {
+    pub fn variants() -> [&'static str; 4] {
+        ["none", "pending", "success", "failure"]
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Status::None),
+            "pending" => Ok(Status::Pending),
+            "success" => Ok(Status::Success),
+            "failure" => Ok(Status::Failure),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            Status::None => "none".fmt(f),
+            Status::Pending => "pending".fmt(f),
+            Status::Success => "success".fmt(f),
+            Status::Failure => "failure".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PullRequestState {
+    Open,
+    /// In a merge queue or merge train
+    Queued,
+    Merged,
+    Closed,
+}
+
+impl PullRequestState {
+    pub fn variants() -> [&'static str; 4] {
+        ["open", "queued", "merged", "closed"]
+    }
+}
+
+impl std::str::FromStr for PullRequestState {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(PullRequestState::Open),
+            "queued" => Ok(PullRequestState::Queued),
+            "merged" => Ok(PullRequestState::Merged),
+            "closed" => Ok(PullRequestState::Closed),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for PullRequestState {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            PullRequestState::Open => "open".fmt(f),
+            PullRequestState::Queued => "queued".fmt(f),
+            PullRequestState::Merged => "merged".fmt(f),
+            PullRequestState::Closed => "closed".fmt(f),
+        }
+    }
+}
+
+/// Poll pull request `number` until it leaves the merge queue, or until `timeout` or `cancel`
+///
+/// Returns the last state seen: `Merged` once it lands, `Open` when it was dropped from the queue,
+/// like for failing CI, and still `Queued` when we gave up waiting.
+pub fn wait_for_merge(
+    forge: &mut dyn Forge,
+    number: u64,
+    timeout: std::time::Duration,
+    interval: std::time::Duration,
+    cancel: &crate::git::Cancel,
+) -> eyre::Result<PullRequestState> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        let state = forge.get_pr_state(number)?;
+        let now = std::time::Instant::now();
+        if state != PullRequestState::Queued || deadline <= now || cancel.is_cancelled() {
+            return Ok(state);
+        }
+        std::thread::sleep(interval.min(deadline - now));
+    }
+}
+
+/// Poll the CI status of `branches` until none are pending, or until `timeout` or `cancel`
+///
+/// `on_done` is called as each branch finishes.  A branch that CI hasn't reported on counts as
+/// pending, its run may not have started yet.  Returns the last status seen for each branch.
+pub fn wait_for_status(
+    forge: &mut dyn Forge,
+    branches: &[&str],
+    timeout: std::time::Duration,
+    interval: std::time::Duration,
+    cancel: &crate::git::Cancel,
+    mut on_done: impl FnMut(&str, Status),
+) -> eyre::Result<Vec<(String, Status)>> {
+    let deadline = std::time::Instant::now() + timeout;
+    let mut statuses: Vec<_> = branches
+        .iter()
+        .map(|b| ((*b).to_owned(), Status::None))
+        .collect();
+    loop {
+        for (branch, status) in statuses.iter_mut() {
+            if matches!(status, Status::Success | Status::Failure) {
+                continue;
+            }
+            *status = forge.get_status(branch)?;
+            if matches!(status, Status::Success | Status::Failure) {
+                on_done(branch, *status);
+            }
+        }
+        let pending = statuses
+            .iter()
+            .any(|(_, s)| matches!(s, Status::None | Status::Pending));
+        let now = std::time::Instant::now();
+        if !pending || deadline <= now || cancel.is_cancelled() {
+            return Ok(statuses);
+        }
+        std::thread::sleep(interval.min(deadline - now));
+    }
+}
+
+/// The forge for `stack.pull-remote`, if one is configured
+pub fn from_config(
+    repo: &git2::Repository,
+    repo_config: &crate::config::RepoConfig,
+) -> eyre::Result<Option<Box<dyn Forge>>> {
+    let remote_name = repo_config.pull_remote();
+    if let Some(command) = repo_config.forge_command() {
+        let url = repo
+            .find_remote(remote_name)
+            .ok()
+            .and_then(|r| r.url().map(|u| u.to_owned()));
+        let forge = CommandForge::new(command.to_owned(), remote_name.to_owned(), url);
+        return Ok(Some(Box::new(forge)));
+    }
+
+    let kind = repo_config.forge();
+    if kind == crate::config::Forge::None {
+        return Ok(None);
+    }
+    let (base, project) = remote_project(repo, remote_name)?;
+    let token = find_token(repo, repo_config, kind, &base);
+    match &token {
+        Some(token) => {
+            forge_log!(debug, "Using the {} token from {}", kind, token.source);
+        }
+        None => {
+            forge_log!(debug, "No {} token found, going unauthenticated", kind);
+        }
+    }
+    let token = token.map(|t| t.secret);
+    let client = Client::new(repo);
+    let forge: Box<dyn Forge> = match kind {
+        crate::config::Forge::None => unreachable!("checked above"),
+        crate::config::Forge::Github => Box::new(Github::new(client, &base, project, token)),
+        crate::config::Forge::Gitlab => Box::new(Gitlab::new(client, base, &project, token)),
+    };
+    Ok(Some(forge))
+}
+
+/// `(base URL, owner/project)` that `remote_name` points to, see [`parse_remote_url`]
+pub fn remote_project(
+    repo: &git2::Repository,
+    remote_name: &str,
+) -> eyre::Result<(String, String)> {
+    let remote = repo
+        .find_remote(remote_name)
+        .map_err(|_| eyre::eyre!("No remote `{}`", remote_name))?;
+    let url = remote
+        .url()
+        .ok_or_else(|| eyre::eyre!("`{}` has no URL", remote_name))?;
+    parse_remote_url(url).ok_or_else(|| eyre::eyre!("Could not parse `{}`", url))
+}
+
+/// `(base URL, owner/project)` from an HTTP(S), SSH, or scp-like remote URL
+///
+/// The forge's API is assumed to be served like the remote is, falling back to HTTPS.
+pub fn parse_remote_url(url: &str) -> Option<(String, String)> {
+    let (base, path) = if let Some((scheme, rest)) = url.split_once("://") {
+        let (authority, path) = rest.split_once('/')?;
+        let host = authority.rsplit('@').next()?;
+        let base = if scheme == "http" || scheme == "https" {
+            format!("{}://{}", scheme, host)
+        } else {
+            // The SSH port isn't the API's port
+            format!("https://{}", host.split(':').next()?)
+        };
+        (base, path)
+    } else {
+        let (authority, path) = url.split_once(':')?;
+        (format!("https://{}", authority.rsplit('@').next()?), path)
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    if base.ends_with("://") || path.is_empty() {
+        return None;
+    }
+    Some((base, path.to_owned()))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+/// Items of a paginated listing, `PER_PAGE` at a time
+fn get_all_pages(client: &mut Client, endpoint: &str) -> eyre::Result<Vec<serde_json::Value>> {
+    const PER_PAGE: usize = 100;
+    let mut items = Vec::new();
+    for page in 1.. {
+        let separator = if endpoint.contains('?') { '&' } else { '?' };
+        let url = format!(
+            "{}{}per_page={}&page={}",
+            endpoint, separator, PER_PAGE, page
+        );
+        let entries: Vec<serde_json::Value> = client.get(&url)?;
+        let done = entries.len() < PER_PAGE;
+        items.extend(entries);
+        if done {
+            break;
+        }
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_remote_urls() {
+        let cases = [
+            (
+                "https://github.com/epage/git-stack.git",
+                Some(("https://github.com", "epage/git-stack")),
+            ),
+            (
+                "git@github.com:epage/git-stack.git",
+                Some(("https://github.com", "epage/git-stack")),
+            ),
+            (
+                "ssh://git@gitlab.example.com:2222/group/sub/project.git",
+                Some(("https://gitlab.example.com", "group/sub/project")),
+            ),
+            (
+                "http://127.0.0.1:8080/o/p/",
+                Some(("http://127.0.0.1:8080", "o/p")),
+            ),
+            ("/srv/git/project.git", None),
+        ];
+        for (url, expected) in cases {
+            let actual = parse_remote_url(url);
+            let expected = expected.map(|(b, p)| (b.to_owned(), p.to_owned()));
+            assert_eq!(actual, expected, "{}", url);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+/// CI started by `POST`ing to a URL, `stack.ci-trigger=<url>`
+///
+/// The body is the [`super::CiRun`] as JSON plus the `remote` the branch was pushed to.  Any reply
+/// is ignored.
+pub struct Webhook {
+    client: super::Client,
+    url: String,
+    remote: String,
+}
+
+impl Webhook {
+    pub fn new(client: super::Client, url: String, remote: String) -> Self {
+        Self {
+            client,
+            url,
+            remote,
+        }
+    }
+
+    pub fn trigger(&mut self, run: &super::CiRun<'_>) -> eyre::Result<()> {
+        let mut request = serde_json::to_value(run)?;
+        request["remote"] = serde_json::Value::from(self.remote.as_str());
+        self.client.send_ignoring_reply("POST", &self.url, &request)
+    }
+}
@@ -17,21 +17,27 @@ impl Branches {
         }
     }
 
+    /// Like [`Branches::new`], leaving out the branches matching `ignore` (`stack.ignore-branch`)
+    pub fn with_ignore(
+        branches: impl IntoIterator<Item = crate::git::Branch>,
+        ignore: &crate::git::BranchGlobs,
+    ) -> Self {
+        Self::new(branches.into_iter().filter(|b| !ignore.is_match(&b.name)))
+    }
+
     pub fn update(&mut self, repo: &dyn crate::git::Repo) {
-        let mut new = Self::new(
-            self.branches
-                .values()
-                .flatten()
-                .filter_map(|b| repo.find_local_branch(&b.name)),
-        );
+        let mut new = Self::new(self.branches.values().flatten().filter_map(|b| {
+            if b.is_remote() {
+                repo.find_remote_branch(&b.name)
+            } else {
+                repo.find_local_branch(&b.name)
+            }
+        }));
         std::mem::swap(&mut new, self);
     }
 
     pub fn insert(&mut self, branch: crate::git::Branch) {
-        self.branches
-            .entry(branch.id)
-            .or_insert_with(Vec::new)
-            .push(branch);
+        self.branches.entry(branch.id).or_default().push(branch);
     }
 
     pub fn extend(&mut self, branches: impl Iterator<Item = crate::git::Branch>) {
@@ -74,7 +80,15 @@ impl Branches {
         self.clone()
     }
 
+    /// Every branch paired with each of `oids`, for [`crate::git::Repo::prefetch_merge_bases`]
+    pub fn merge_base_pairs(&self, oids: &[git2::Oid]) -> Vec<(git2::Oid, git2::Oid)> {
+        self.oids()
+            .flat_map(|branch_oid| oids.iter().map(move |oid| (branch_oid, *oid)))
+            .collect()
+    }
+
     pub fn descendants(&self, repo: &dyn crate::git::Repo, base_oid: git2::Oid) -> Self {
+        repo.prefetch_merge_bases(&self.merge_base_pairs(&[base_oid]));
         let branches = self
             .branches
             .iter()
@@ -112,6 +126,7 @@ impl Branches {
         base_oid: git2::Oid,
         head_oid: git2::Oid,
     ) -> Self {
+        repo.prefetch_merge_bases(&self.merge_base_pairs(&[base_oid, head_oid]));
         let branches = self
             .branches
             .iter()
@@ -164,6 +179,7 @@ impl Branches {
         base_oid: git2::Oid,
         head_oid: git2::Oid,
     ) -> Self {
+        repo.prefetch_merge_bases(&self.merge_base_pairs(&[base_oid, head_oid]));
         let branches = self
             .branches
             .iter()
@@ -247,6 +263,28 @@ impl IntoIterator for Branches {
     }
 }
 
+/// The branches in `branches` that aren't in `protected`, by name, each name once
+pub fn unprotected_branches<'b>(
+    branches: impl IntoIterator<Item = &'b Branches>,
+    protected: &Branches,
+) -> Vec<&'b crate::git::Branch> {
+    let mut unprotected: Vec<_> = branches
+        .into_iter()
+        .flat_map(|branches| branches.iter())
+        .flat_map(|(_, b)| b)
+        .filter(|b| {
+            !protected
+                .get(b.id)
+                .unwrap_or_default()
+                .iter()
+                .any(|p| p.name == b.name)
+        })
+        .collect();
+    unprotected.sort_by_key(|b| b.name.as_str());
+    unprotected.dedup_by_key(|b| b.name.as_str());
+    unprotected
+}
+
 pub fn find_protected_base<'b>(
     repo: &dyn crate::git::Repo,
     protected_branches: &'b Branches,
@@ -23,6 +23,17 @@ impl Script {
         None
     }
 
+    /// The branch closest to the base of the script
+    pub fn base_branch(&self) -> Option<&str> {
+        self.commands
+            .iter()
+            .find_map(|command| match command {
+                Command::CreateBranch(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .or_else(|| self.dependents.iter().find_map(|d| d.base_branch()))
+    }
+
     pub fn dependent_branches(&self) -> Vec<&str> {
         let mut branches = Vec::new();
         for dependent in self.dependents.iter() {
@@ -32,6 +43,107 @@ impl Script {
         branches
     }
 
+    /// Branches that will point to a different commit after running the script
+    pub fn moved_branches(&self, repo: &dyn crate::git::Repo) -> Vec<String> {
+        let mut moved = Vec::new();
+        let mut marks = std::collections::HashMap::new();
+        self.track_changes(repo, None, &mut marks, &mut moved, &mut Vec::new());
+        moved
+    }
+
+    /// Existing commits that will be replaced by new ones after running the script
+    pub fn rewritten_commits(&self, repo: &dyn crate::git::Repo) -> Vec<git2::Oid> {
+        let mut rewritten = Vec::new();
+        let mut marks = std::collections::HashMap::new();
+        self.track_changes(repo, None, &mut marks, &mut Vec::new(), &mut rewritten);
+        rewritten
+    }
+
+    /// `head_oid` is `None` once we are creating new commits
+    fn track_changes(
+        &self,
+        repo: &dyn crate::git::Repo,
+        mut head_oid: Option<git2::Oid>,
+        marks: &mut std::collections::HashMap<git2::Oid, Option<git2::Oid>>,
+        moved: &mut Vec<String>,
+        rewritten: &mut Vec<git2::Oid>,
+    ) {
+        for command in self.commands.iter() {
+            match command {
+                Command::SwitchCommit(oid) => {
+                    head_oid = Some(*oid);
+                }
+                Command::RegisterMark(mark_oid) => {
+                    marks.insert(*mark_oid, head_oid);
+                }
+                Command::SwitchMark(mark_oid) => {
+                    head_oid = marks.get(mark_oid).copied().flatten();
+                }
+                Command::CherryPick(cherry_oid) => {
+                    // Mirror `Repo::cherry_pick` which reuses commits already on top of `head_oid`
+                    let parent_oid = repo
+                        .commits_from(*cherry_oid)
+                        .map(|c| c.id)
+                        .find(|id| id != cherry_oid);
+                    head_oid = head_oid
+                        .filter(|head_oid| parent_oid == Some(*head_oid))
+                        .map(|_| *cherry_oid);
+                    if head_oid.is_none() {
+                        rewritten.push(*cherry_oid);
+                    }
+                }
+                Command::Fixup(squash_oid) => {
+                    // Both the fixup and what it is squashed into are replaced
+                    rewritten.extend(head_oid);
+                    rewritten.push(*squash_oid);
+                    head_oid = None;
+                }
+                Command::CreateBranch(name) => {
+                    let current_oid = repo.find_local_branch(name).map(|b| b.id);
+                    if head_oid.is_none() || current_oid != head_oid {
+                        moved.push(name.clone());
+                    }
+                }
+                Command::DeleteBranch(_) | Command::Exec(_) => {}
+            }
+        }
+
+        for dependent in self.dependents.iter() {
+            dependent.track_changes(repo, head_oid, marks, moved, rewritten);
+        }
+    }
+
+    /// Branches that will be deleted after running the script
+    pub fn deleted_branches(&self) -> Vec<&str> {
+        let mut deleted: Vec<&str> = self
+            .commands
+            .iter()
+            .filter_map(|c| match c {
+                Command::DeleteBranch(name) => Some(name.as_str()),
+                _ => None,
+            })
+            .collect();
+        for dependent in self.dependents.iter() {
+            deleted.extend(dependent.deleted_branches());
+        }
+        deleted
+    }
+
+    /// Number of commits that will be cherry-picked or squashed
+    pub fn replay_count(&self) -> usize {
+        let count = self
+            .commands
+            .iter()
+            .filter(|c| matches!(c, Command::CherryPick(_) | Command::Fixup(_)))
+            .count();
+        count
+            + self
+                .dependents
+                .iter()
+                .map(|d| d.replay_count())
+                .sum::<usize>()
+    }
+
     pub fn is_branch_deleted(&self, branch: &str) -> bool {
         for command in &self.commands {
             if let Command::DeleteBranch(ref current) = command {
@@ -67,6 +179,57 @@ pub enum Command {
     CreateBranch(String),
     /// Mark a branch for deletion
     DeleteBranch(String),
+    /// Run a shell command on the current commit, like `git rebase --exec`
+    Exec(String),
+}
+
+/// A commit that could not be applied while running a [`Script`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailedPick {
+    pub branch: String,
+    /// The commit being applied
+    pub commit_id: git2::Oid,
+    /// What `commit_id` was being applied on top of
+    pub onto_id: git2::Oid,
+}
+
+/// Asks an [`Executor`] to stop before its next commit, e.g. on `SIGINT` or past a deadline
+#[derive(Clone, Debug, Default)]
+pub struct Cancel {
+    requested: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    deadline: Option<std::time::Instant>,
+}
+
+impl Cancel {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Also cancel once `deadline` has passed
+    pub fn with_deadline(mut self, deadline: std::time::Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Set to cancel, e.g. from a signal handler
+    pub fn flag(&self) -> std::sync::Arc<std::sync::atomic::AtomicBool> {
+        self.requested.clone()
+    }
+
+    pub fn request(&self) {
+        self.requested
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.requested.load(std::sync::atomic::Ordering::SeqCst) || self.timed_out()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.deadline
+            .map(|deadline| deadline <= std::time::Instant::now())
+            .unwrap_or(false)
+    }
 }
 
 pub struct Executor {
@@ -74,6 +237,34 @@ pub struct Executor {
     marks: std::collections::HashMap<git2::Oid, git2::Oid>,
     branches: Vec<(git2::Oid, String)>,
     delete_branches: Vec<String>,
+    /// What each branch pointed to when its update was staged
+    old_branches: std::collections::BTreeMap<String, Option<git2::Oid>>,
+    pending_failure: Option<(git2::Oid, git2::Oid)>,
+    /// Whether the last cherry-pick reused the commit as-is, leaving nothing new to [`Command::Exec`]
+    reused_pick: bool,
+    /// What commits made by [`Command::Fixup`] came from, for [`crate::git::CommitTemplate`]
+    provenance: crate::git::Provenance,
+    /// Where to record branch updates before making them, and the backup to note in it
+    journal: Option<(std::path::PathBuf, Option<std::path::PathBuf>)>,
+    journal_written: bool,
+    failed_picks: Vec<FailedPick>,
+    /// Commits left out because they became empty, e.g. as they are already upstream
+    emptied_commits: Vec<std::rc::Rc<crate::git::Commit>>,
+    /// `fixup!` and `squash!` commits squashed into the commits they target
+    squashed_commits: Vec<std::rc::Rc<crate::git::Commit>>,
+    /// Cherry-picked commits and what they became
+    rewrites: Vec<(git2::Oid, git2::Oid)>,
+    git_commands: Vec<String>,
+    /// The same as [`Executor::git_commands`], as `git rebase -i --update-refs` todo lines
+    rebase_todo: Vec<String>,
+    /// Whether to run more scripts after one fails
+    keep_going: bool,
+    failed: bool,
+    /// Branches not attempted because an earlier script failed
+    skipped_branches: Vec<String>,
+    cancel: Cancel,
+    /// Whether [`Executor::cancel_on`]'s [`Cancel`] stopped a script part way
+    cancelled: bool,
     dry_run: bool,
     detached: bool,
 }
@@ -86,30 +277,108 @@ impl Executor {
             marks: Default::default(),
             branches: Default::default(),
             delete_branches: Default::default(),
+            old_branches: Default::default(),
+            pending_failure: None,
+            reused_pick: false,
+            provenance: Default::default(),
+            journal: None,
+            journal_written: false,
+            failed_picks: Default::default(),
+            emptied_commits: Default::default(),
+            squashed_commits: Default::default(),
+            rewrites: Default::default(),
+            git_commands: Default::default(),
+            rebase_todo: Default::default(),
+            keep_going: true,
+            failed: false,
+            skipped_branches: Default::default(),
+            cancel: Default::default(),
+            cancelled: false,
             dry_run,
             detached: false,
         }
     }
 
+    /// Record branch updates in a [`crate::git::Journal`] at `path` before making them
+    ///
+    /// `snapshot` is the `branch-stash` backup of the branches from before the scripts are run.
+    pub fn journal(&mut self, path: std::path::PathBuf, snapshot: Option<std::path::PathBuf>) {
+        self.journal = Some((path, snapshot));
+    }
+
+    /// Whether [`Executor::run_script`] still runs scripts after one fails (the default)
+    ///
+    /// Otherwise, the branches of later scripts are left alone and reported by
+    /// [`Executor::skipped_branches`].
+    pub fn keep_going(&mut self, keep_going: bool) {
+        self.keep_going = keep_going;
+    }
+
+    /// Stop [`Executor::run_script`] between commits once `cancel` is
+    ///
+    /// The script being run fails and later ones are skipped, see [`Executor::cancelled`].
+    pub fn cancel_on(&mut self, cancel: Cancel) {
+        self.cancel = cancel;
+    }
+
     pub fn run_script<'s>(
         &mut self,
         repo: &mut dyn crate::git::Repo,
         script: &'s Script,
+    ) -> Vec<(git2::Error, &'s str, Vec<&'s str>)> {
+        self.provenance.stack = script.base_branch().map(|b| b.to_owned());
+        self.run_dependent_script(repo, script)
+    }
+
+    fn run_dependent_script<'s>(
+        &mut self,
+        repo: &mut dyn crate::git::Repo,
+        script: &'s Script,
     ) -> Vec<(git2::Error, &'s str, Vec<&'s str>)> {
         let mut failures = Vec::new();
         let branch_name = script.branch().unwrap_or("detached");
+        if self.cancelled || (self.failed && !self.keep_going) {
+            log::trace!("Skipping `{}`, an earlier script failed", branch_name);
+            self.skipped_branches.push(branch_name.to_owned());
+            self.skipped_branches.extend(
+                script
+                    .dependent_branches()
+                    .into_iter()
+                    .map(|b| b.to_owned()),
+            );
+            return failures;
+        }
 
         log::trace!("Applying `{}`", branch_name);
         log::trace!("Script: {:#?}", script.commands);
+        let git_commands_start = self.git_commands.len();
+        let rebase_todo_start = self.rebase_todo.len();
+        let branches_start = self.branches.len();
+        let delete_branches_start = self.delete_branches.len();
+        let emptied_commits_start = self.emptied_commits.len();
+        let squashed_commits_start = self.squashed_commits.len();
+        let rewrites_start = self.rewrites.len();
         let res = script
             .commands
             .iter()
-            .try_for_each(|command| self.stage_single(repo, command));
-        match res.and_then(|_| self.commit(repo)) {
+            .enumerate()
+            .try_for_each(|(i, command)| {
+                if self.cancel.is_cancelled() {
+                    self.cancelled = true;
+                    return Err(git2::Error::from_str("cancelled"));
+                }
+                // The branch the commit ends up on
+                self.provenance.layer = script.commands[i..].iter().find_map(|c| match c {
+                    Command::CreateBranch(name) => Some(name.clone()),
+                    _ => None,
+                });
+                self.stage_single(repo, command)
+            });
+        match res {
             Ok(()) => {
                 log::trace!("         `{}` succeeded", branch_name);
                 for dependent in script.dependents.iter() {
-                    failures.extend(self.run_script(repo, dependent));
+                    failures.extend(self.run_dependent_script(repo, dependent));
                 }
                 if !failures.is_empty() {
                     log::trace!("         `{}`'s dependent failed", branch_name);
@@ -117,7 +386,29 @@ impl Executor {
             }
             Err(err) => {
                 log::trace!("         `{}` failed: {}", branch_name, err);
-                self.abandon(repo);
+                // e.g. `SIGINT` also reached a `Command::Exec`
+                if self.cancel.is_cancelled() {
+                    self.cancelled = true;
+                }
+                self.git_commands.truncate(git_commands_start);
+                self.rebase_todo.truncate(rebase_todo_start);
+                self.emptied_commits.truncate(emptied_commits_start);
+                self.squashed_commits.truncate(squashed_commits_start);
+                self.rewrites.truncate(rewrites_start);
+                let failed = format!("# Failed to re-stack `{}`: {}", branch_name, err.message());
+                self.git_commands.push(failed.clone());
+                self.rebase_todo.push(failed);
+                if let Some((onto_id, commit_id)) = self.pending_failure.take() {
+                    self.failed_picks.push(FailedPick {
+                        branch: branch_name.to_owned(),
+                        commit_id,
+                        onto_id,
+                    });
+                }
+                self.branches.truncate(branches_start);
+                self.delete_branches.truncate(delete_branches_start);
+                self.head_oid = repo.head_commit().id;
+                self.failed = true;
                 failures.push((err, branch_name, script.dependent_branches()));
             }
         }
@@ -140,11 +431,21 @@ impl Executor {
                     )
                 })?;
                 log::trace!("git checkout {}  # {}", oid, commit.summary);
+                self.git_commands.push(format!(
+                    "git checkout --detach {}  # {}",
+                    oid, commit.summary
+                ));
+                self.rebase_todo
+                    .push(format!("reset {} # {}", oid, commit.summary));
                 self.head_oid = *oid;
+                self.provenance.sources = vec![*oid];
             }
             Command::RegisterMark(mark_oid) => {
                 let target_oid = self.head_oid;
                 self.marks.insert(*mark_oid, target_oid);
+                self.git_commands
+                    .push(format!("mark_{}=$(git rev-parse HEAD)", mark_oid));
+                self.rebase_todo.push(format!("label mark_{}", mark_oid));
             }
             Command::SwitchMark(mark_oid) => {
                 let oid = *self
@@ -154,7 +455,11 @@ impl Executor {
 
                 let commit = repo.find_commit(oid).unwrap();
                 log::trace!("git checkout {}  # {}", oid, commit.summary);
+                self.git_commands
+                    .push(format!("git checkout --detach \"$mark_{}\"", mark_oid));
+                self.rebase_todo.push(format!("reset mark_{}", mark_oid));
                 self.head_oid = oid;
+                self.provenance.sources.clear();
             }
             Command::CherryPick(cherry_oid) => {
                 let cherry_commit = repo.find_commit(*cherry_oid).ok_or_else(|| {
@@ -169,10 +474,29 @@ impl Executor {
                     cherry_oid,
                     cherry_commit.summary
                 );
+                // `--ff` mirrors `Repo::cherry_pick` reusing commits that don't need to move
+                self.git_commands.push(format!(
+                    "git cherry-pick --ff {}  # {}",
+                    cherry_oid, cherry_commit.summary
+                ));
+                self.rebase_todo
+                    .push(format!("pick {} # {}", cherry_oid, cherry_commit.summary));
                 if self.dry_run {
                     self.head_oid = *cherry_oid;
                 } else {
-                    self.head_oid = repo.cherry_pick(self.head_oid, *cherry_oid)?;
+                    self.pending_failure = Some((self.head_oid, *cherry_oid));
+                    let new_oid = repo.cherry_pick(self.head_oid, *cherry_oid)?;
+                    self.pending_failure = None;
+                    self.reused_pick = new_oid == *cherry_oid;
+                    if new_oid == self.head_oid {
+                        self.emptied_commits.push(cherry_commit);
+                    } else {
+                        if !self.reused_pick {
+                            self.rewrites.push((*cherry_oid, new_oid));
+                        }
+                        self.provenance.sources = vec![*cherry_oid];
+                    }
+                    self.head_oid = new_oid;
                 }
             }
             Command::Fixup(squash_oid) => {
@@ -188,26 +512,113 @@ impl Executor {
                     squash_oid,
                     cherry_commit.summary
                 );
+                self.git_commands.push(format!(
+                    "git cherry-pick --no-commit {}  # {}",
+                    squash_oid, cherry_commit.summary
+                ));
+                if cherry_commit.summary.starts_with(b"squash! ") {
+                    self.git_commands.push("git commit --amend".to_owned());
+                    self.rebase_todo
+                        .push(format!("squash {} # {}", squash_oid, cherry_commit.summary));
+                } else {
+                    self.git_commands
+                        .push("git commit --amend --no-edit".to_owned());
+                    self.rebase_todo
+                        .push(format!("fixup {} # {}", squash_oid, cherry_commit.summary));
+                }
                 if self.dry_run {
                     self.head_oid = *squash_oid;
                 } else {
-                    self.head_oid = repo.squash(*squash_oid, self.head_oid)?;
+                    self.provenance.sources.push(*squash_oid);
+                    self.head_oid = repo.squash(*squash_oid, self.head_oid, &self.provenance)?;
+                    self.reused_pick = false;
+                    self.squashed_commits.push(cherry_commit);
+                }
+            }
+            Command::Exec(command) => {
+                log::trace!("{}", command);
+                self.git_commands.push(command.clone());
+                self.rebase_todo.push(format!("exec {}", command));
+                if self.reused_pick {
+                    log::trace!("Skipping `{}`, {} is unchanged", command, self.head_oid);
+                } else if !self.dry_run {
+                    repo.exec(self.head_oid, command)?;
                 }
             }
             Command::CreateBranch(name) => {
                 let branch_oid = self.head_oid;
+                self.old_branches
+                    .entry(name.to_owned())
+                    .or_insert_with(|| repo.find_local_branch(name).map(|b| b.id));
                 self.branches.push((branch_oid, name.to_owned()));
+                self.git_commands
+                    .push(format!("git branch -f {} HEAD", name));
+                self.rebase_todo
+                    .push(format!("update-ref refs/heads/{}", name));
             }
             Command::DeleteBranch(name) => {
+                self.old_branches
+                    .entry(name.to_owned())
+                    .or_insert_with(|| repo.find_local_branch(name).map(|b| b.id));
                 self.delete_branches.push(name.to_owned());
+                self.git_commands.push(format!("git branch -D {}", name));
+                // Branches are only deleted once everything else succeeded
+                self.rebase_todo
+                    .push(format!("# Once done: git branch -D {}", name));
             }
         }
 
         Ok(())
     }
 
+    /// Check nothing changed under us since the scripts were run, before [`Executor::commit`]
+    ///
+    /// The working tree must still be clean and every branch being moved or deleted must still
+    /// point where it did when its update was staged.
+    pub fn verify(&self, repo: &dyn crate::git::Repo) -> Result<(), git2::Error> {
+        if self.dry_run {
+            return Ok(());
+        }
+        if repo.is_dirty() {
+            return Err(git2::Error::new(
+                git2::ErrorCode::Modified,
+                git2::ErrorClass::Checkout,
+                "working tree changed while restacking",
+            ));
+        }
+        for (name, old_id) in self.old_branches.iter() {
+            let current_id = repo.find_local_branch(name).map(|b| b.id);
+            if current_id != *old_id {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::Modified,
+                    git2::ErrorClass::Reference,
+                    format!("`{}` changed while restacking", name),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Move and delete the branches for all of the scripts run so far
+    ///
+    /// New commits were already written by [`Executor::run_script`], so until this is called, the
+    /// branches are untouched.
     pub fn commit(&mut self, repo: &mut dyn crate::git::Repo) -> Result<(), git2::Error> {
+        self.commit_branches(repo, None)?;
+        self.close_journal();
+        Ok(())
+    }
+
+    fn commit_branches(
+        &mut self,
+        repo: &mut dyn crate::git::Repo,
+        restore_branch: Option<&str>,
+    ) -> Result<(), git2::Error> {
         if !self.branches.is_empty() || !self.delete_branches.is_empty() {
+            if !self.dry_run {
+                self.write_journal(repo, restore_branch)?;
+            }
+
             // In case we are changing the branch HEAD is attached to
             if !self.dry_run {
                 repo.detach()?;
@@ -215,30 +626,133 @@ impl Executor {
             }
 
             for (oid, name) in self.branches.iter() {
-                let commit = repo.find_commit(*oid).unwrap();
-                log::trace!("git checkout {}  # {}", oid, commit.summary);
-                log::trace!("git switch -c {}", name);
-                if !self.dry_run {
-                    repo.branch(name, *oid)?;
-                }
+                log::trace!("git branch -f {} {}", name, oid);
+            }
+            for name in self.delete_branches.iter() {
+                log::trace!("git branch -D {}", name);
             }
-        }
-        self.branches.clear();
-
-        for name in self.delete_branches.iter() {
-            log::trace!("git branch -D {}", name);
             if !self.dry_run {
-                repo.delete_branch(name)?;
+                repo.update_branches(&self.branches, &self.delete_branches)?;
             }
         }
+        self.branches.clear();
         self.delete_branches.clear();
+        self.old_branches.clear();
 
         Ok(())
     }
 
+    fn write_journal(
+        &mut self,
+        repo: &dyn crate::git::Repo,
+        restore_branch: Option<&str>,
+    ) -> Result<(), git2::Error> {
+        let (path, snapshot) = match self.journal.as_ref() {
+            Some(journal) => journal,
+            None => return Ok(()),
+        };
+        let updates = self
+            .branches
+            .iter()
+            .map(|(id, name)| (name, Some(*id)))
+            .chain(self.delete_branches.iter().map(|name| (name, None)))
+            .map(|(name, new)| crate::git::RefUpdate {
+                name: name.clone(),
+                old: repo.find_local_branch(name).map(|b| b.id),
+                new,
+            })
+            .collect();
+        let journal = crate::git::Journal {
+            updates,
+            restore_branch: restore_branch.map(String::from),
+            snapshot: snapshot.clone(),
+        };
+        log::trace!("Writing journal to {}", path.display());
+        journal.save(path).map_err(|err| {
+            git2::Error::new(
+                git2::ErrorCode::GenericError,
+                git2::ErrorClass::Os,
+                format!("could not write {}: {}", path.display(), err),
+            )
+        })?;
+        self.journal_written = true;
+        Ok(())
+    }
+
+    /// The branch updates are done, so there is nothing to recover
+    fn close_journal(&mut self) {
+        if !self.journal_written {
+            return;
+        }
+        if let Some((path, _)) = self.journal.as_ref() {
+            if let Err(err) = crate::git::Journal::remove(path) {
+                log::debug!("Could not remove {}: {}", path.display(), err);
+            }
+        }
+        self.journal_written = false;
+    }
+
+    /// Plain `git` commands equivalent to the scripts run so far
+    ///
+    /// Failed scripts are left out, replaced with a comment.
+    pub fn git_commands(&self) -> &[String] {
+        &self.git_commands
+    }
+
+    /// A `git rebase -i --update-refs` todo list equivalent to the scripts run so far
+    ///
+    /// The rebase has to start from a detached `HEAD`, or `git rebase` will also move the
+    /// checked out branch to wherever the todo list ends.
+    pub fn rebase_todo(&self) -> &[String] {
+        &self.rebase_todo
+    }
+
+    /// Whether a script was stopped part way by [`Executor::cancel_on`]'s [`Cancel`]
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Branches left alone after a failure, when not [`Executor::keep_going`]
+    pub fn skipped_branches(&self) -> &[String] {
+        &self.skipped_branches
+    }
+
+    /// Commits that failed to apply in calls to [`Executor::run_script`]
+    pub fn failed_picks(&self) -> &[FailedPick] {
+        &self.failed_picks
+    }
+
+    /// Commits dropped in calls to [`Executor::run_script`] because they became empty
+    pub fn emptied_commits(&self) -> &[std::rc::Rc<crate::git::Commit>] {
+        &self.emptied_commits
+    }
+
+    /// Commits squashed in calls to [`Executor::run_script`]
+    pub fn squashed_commits(&self) -> &[std::rc::Rc<crate::git::Commit>] {
+        &self.squashed_commits
+    }
+
+    /// Commits cherry-picked in calls to [`Executor::run_script`], with the commits they became
+    pub fn rewrites(&self) -> &[(git2::Oid, git2::Oid)] {
+        &self.rewrites
+    }
+
+    /// Branches staged to move, with where they pointed before and where they will point
+    pub fn moved_branches(&self) -> Vec<(&str, Option<git2::Oid>, git2::Oid)> {
+        self.branches
+            .iter()
+            .map(|(id, name)| {
+                let old_id = self.old_branches.get(name).copied().flatten();
+                (name.as_str(), old_id, *id)
+            })
+            .filter(|(_, old_id, id)| *old_id != Some(*id))
+            .collect()
+    }
+
     pub fn abandon(&mut self, repo: &dyn crate::git::Repo) {
         self.branches.clear();
         self.delete_branches.clear();
+        self.old_branches.clear();
         self.head_oid = repo.head_commit().id;
     }
 
@@ -247,16 +761,27 @@ impl Executor {
         repo: &mut dyn crate::git::Repo,
         restore_branch: &str,
     ) -> Result<(), git2::Error> {
-        assert_eq!(&self.branches, &[]);
-        assert_eq!(self.delete_branches, Vec::<String>::new());
+        // Even if the branches couldn't be moved, don't leave HEAD detached
+        let committed = self.commit_branches(repo, Some(restore_branch));
+        if committed.is_err() {
+            self.abandon(repo);
+        }
         log::trace!("git switch {}", restore_branch);
+        if !self.git_commands.is_empty() {
+            self.git_commands
+                .push(format!("git switch {}", restore_branch));
+        }
         if !self.dry_run {
             if self.detached {
                 repo.switch(restore_branch)?;
             }
             self.head_oid = repo.head_commit().id;
         }
+        // If committing failed part way, the journal is how to recover
+        if committed.is_ok() {
+            self.close_journal();
+        }
 
-        Ok(())
+        committed
     }
 }
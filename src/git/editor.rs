@@ -0,0 +1,445 @@
+/// Where commit messages are edited, resolved the way `git var GIT_EDITOR` resolves it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Editor {
+    pub program: String,
+    /// `commit.verbose`: show the diff being committed below the message
+    pub verbose: bool,
+}
+
+impl Editor {
+    pub fn from_repo(repo: &git2::Repository) -> Self {
+        match repo.config() {
+            Ok(config) => Self::from_gitconfig(&config, |key| std::env::var(key).ok()),
+            Err(err) => {
+                log::debug!("Failed to load git config: {}", err);
+                Self::from_env(None, |key| std::env::var(key).ok())
+            }
+        }
+    }
+
+    pub fn from_gitconfig(config: &git2::Config, env: impl Fn(&str) -> Option<String>) -> Self {
+        let mut editor = Self::from_env(config.get_string("core.editor").ok(), env);
+        // Either a boolean or, like `git commit -vv`, a level
+        editor.verbose = config
+            .get_bool("commit.verbose")
+            .or_else(|_| config.get_i32("commit.verbose").map(|v| 0 < v))
+            .unwrap_or(false);
+        editor
+    }
+
+    fn from_env(core_editor: Option<String>, env: impl Fn(&str) -> Option<String>) -> Self {
+        let program = env("GIT_EDITOR")
+            .into_iter()
+            .chain(core_editor)
+            .chain(env("VISUAL"))
+            .chain(env("EDITOR"))
+            .find(|e| !e.is_empty())
+            .unwrap_or_else(|| "vi".to_owned());
+        Self {
+            program,
+            verbose: false,
+        }
+    }
+
+    /// Whether editing would leave the message as-is, like with `GIT_EDITOR=true`
+    pub fn is_noop(&self) -> bool {
+        matches!(self.program.trim(), ":" | "true")
+    }
+
+    /// Have the user edit `template`, returning the cleaned up message
+    ///
+    /// The file is left at `path` (e.g. `.git/COMMIT_EDITMSG`), like `git commit` does.  `diff`
+    /// is shown below a scissors line when [`Editor::verbose`].
+    pub fn edit(
+        &self,
+        path: &std::path::Path,
+        template: &str,
+        diff: Option<&str>,
+    ) -> Result<String, git2::Error> {
+        if self.is_noop() {
+            return Ok(cleanup_message(template));
+        }
+
+        let mut content = template.to_owned();
+        if let (true, Some(diff)) = (self.verbose, diff) {
+            content.push_str(SCISSORS);
+            content.push_str(diff);
+        }
+        std::fs::write(path, content).map_err(|err| {
+            git2::Error::from_str(&format!("could not write {}: {}", path.display(), err))
+        })?;
+
+        // Like `git`, the editor is a shell snippet with the file appended
+        log::trace!("{} {}", self.program, path.display());
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.program))
+            .arg(&self.program)
+            .arg(path)
+            .status()
+            .map_err(|err| {
+                git2::Error::from_str(&format!("could not run `{}`: {}", self.program, err))
+            })?;
+        if !status.success() {
+            return Err(git2::Error::from_str(&format!(
+                "there was a problem with the editor `{}`",
+                self.program
+            )));
+        }
+
+        let edited = std::fs::read_to_string(path).map_err(|err| {
+            git2::Error::from_str(&format!("could not read {}: {}", path.display(), err))
+        })?;
+        let message = cleanup_message(&edited);
+        if message.is_empty() {
+            return Err(git2::Error::from_str(
+                "aborting due to empty commit message",
+            ));
+        }
+        Ok(message)
+    }
+}
+
+const SCISSORS: &str = "# ------------------------ >8 ------------------------
+# Do not modify or remove the line above.
+# Everything below it will be ignored.
+";
+
+/// Template for combining `squash!` commit `squash_message` into `into_message`
+///
+/// Left unedited, this cleans up to `into_message` followed by the body of `squash_message`.
+pub fn squash_template(into_message: &str, squash_message: &str) -> String {
+    let mut template = String::new();
+    template.push_str("# This is a combination of 2 commits.\n");
+    template.push_str("# This is the 1st commit message:\n\n");
+    template.push_str(into_message.trim_end());
+    template.push_str("\n\n# This is the commit message #2:\n\n");
+    let (subject, body) = squash_message
+        .split_once('\n')
+        .unwrap_or((squash_message, ""));
+    // Redundant once squashed, so offered as context only
+    template.push_str("# ");
+    template.push_str(subject);
+    template.push('\n');
+    let body = body.trim();
+    if !body.is_empty() {
+        template.push('\n');
+        template.push_str(body);
+        template.push('\n');
+    }
+    template.push_str(
+        "
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+",
+    );
+    template
+}
+
+/// The body of `squash!` commit `squash_message` under the subject of `into_message`
+///
+/// Without a body of its own, the `squash!` commit keeps `into_message`.
+pub fn squash_last(into_message: &str, squash_message: &str) -> String {
+    let body = squash_message
+        .split_once('\n')
+        .map(|(_, body)| body.trim())
+        .unwrap_or_default();
+    if body.is_empty() {
+        return into_message.to_owned();
+    }
+    let subject = into_message.lines().next().unwrap_or_default();
+    format!("{}\n\n{}\n", subject, body)
+}
+
+/// Strip comments and surrounding blank lines, like `git commit --cleanup=strip`
+pub fn cleanup_message(message: &str) -> String {
+    let message = match message.find(SCISSORS) {
+        Some(index) => &message[..index],
+        None => message,
+    };
+    let mut cleaned = String::new();
+    let mut blank = false;
+    for line in message
+        .lines()
+        .filter(|l| !l.starts_with('#'))
+        .map(|l| l.trim_end())
+    {
+        if line.is_empty() {
+            blank = !cleaned.is_empty();
+            continue;
+        }
+        if blank {
+            cleaned.push('\n');
+            blank = false;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+/// `stack.commit-template`: provenance added to the messages of commits `git-stack` generates
+///
+/// `{stack}`, `{layer}`, and `{source}` are filled in from [`Provenance`], leaving out lines with
+/// a variable we don't know.  Blank lines and comments are dropped, so it renders as one paragraph,
+/// e.g. of trailers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitTemplate {
+    lines: Vec<String>,
+}
+
+/// Where a generated commit came from, for [`CommitTemplate`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// The branch at the base of the commit's stack
+    pub stack: Option<String>,
+    /// The branch the commit is on
+    pub layer: Option<String>,
+    /// The commits it was made from
+    pub sources: Vec<git2::Oid>,
+}
+
+const TEMPLATE_VARIABLES: [&str; 3] = ["{stack}", "{layer}", "{source}"];
+
+impl CommitTemplate {
+    pub fn new(template: &str) -> Self {
+        let lines = cleanup_message(template)
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_owned())
+            .collect();
+        Self { lines }
+    }
+
+    /// Read the template at `path`
+    ///
+    /// Like `commit.template`, `~/` is the home directory, but relative paths are from the root of
+    /// the worktree, so the template can be checked in.
+    pub fn load(repo: &git2::Repository, path: &str) -> Result<Self, git2::Error> {
+        let path = match path.strip_prefix("~/") {
+            Some(rest) => std::env::var_os("HOME")
+                .map(|home| std::path::Path::new(&home).join(rest))
+                .unwrap_or_else(|| std::path::PathBuf::from(path)),
+            None => {
+                let root = repo.workdir().unwrap_or_else(|| repo.path());
+                root.join(path)
+            }
+        };
+        let template = std::fs::read_to_string(&path).map_err(|err| {
+            git2::Error::from_str(&format!("could not read {}: {}", path.display(), err))
+        })?;
+        Ok(Self::new(&template))
+    }
+
+    pub fn render(&self, provenance: &Provenance) -> String {
+        let sources = provenance
+            .sources
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let values = [
+            provenance.stack.as_deref(),
+            provenance.layer.as_deref(),
+            Some(sources.as_str()).filter(|s| !s.is_empty()),
+        ];
+        let mut rendered = String::new();
+        'lines: for line in self.lines.iter() {
+            let mut line = line.clone();
+            for (variable, value) in TEMPLATE_VARIABLES.iter().zip(values) {
+                if line.contains(variable) {
+                    match value {
+                        Some(value) => line = line.replace(variable, value),
+                        None => continue 'lines,
+                    }
+                }
+            }
+            rendered.push_str(&line);
+            rendered.push('\n');
+        }
+        rendered
+    }
+
+    /// `message` with the template rendered at the end, in place of what an earlier render left
+    ///
+    /// When both end in trailers, the rendered ones join the message's.
+    pub fn apply(&self, message: &str, provenance: &Provenance) -> String {
+        let patterns = self.patterns();
+        let mut lines: Vec<&str> = message.trim_end().lines().collect();
+        while lines.len() > 1
+            && lines
+                .last()
+                .map(|l| patterns.iter().any(|p| p.is_match(l)))
+                .unwrap_or(false)
+        {
+            lines.pop();
+        }
+        while lines.last().map(|l| l.trim().is_empty()).unwrap_or(false) {
+            lines.pop();
+        }
+
+        let mut applied = lines.join("\n");
+        applied.push('\n');
+        let rendered = self.render(provenance);
+        if rendered.is_empty() {
+            return applied;
+        }
+        let trailing = lines
+            .iter()
+            .rev()
+            .take_while(|l| !l.trim().is_empty())
+            .collect::<Vec<_>>();
+        let joins_trailers = trailing.len() < lines.len()
+            && trailing.iter().all(|l| is_trailer(l))
+            && rendered.lines().all(is_trailer);
+        if !joins_trailers {
+            applied.push('\n');
+        }
+        applied.push_str(&rendered);
+        applied
+    }
+
+    /// Lines this template could have rendered, leaving out those with nothing but variables
+    fn patterns(&self) -> Vec<regex::Regex> {
+        self.lines
+            .iter()
+            .filter_map(|line| {
+                let mut pattern = "^".to_owned();
+                let mut literal = String::new();
+                let mut rest = line.as_str();
+                while let Some((index, variable)) = TEMPLATE_VARIABLES
+                    .iter()
+                    .filter_map(|v| rest.find(v).map(|i| (i, v)))
+                    .min()
+                {
+                    literal.push_str(&rest[..index]);
+                    pattern.push_str(&regex::escape(&rest[..index]));
+                    pattern.push_str(".+");
+                    rest = &rest[index + variable.len()..];
+                }
+                literal.push_str(rest);
+                pattern.push_str(&regex::escape(rest));
+                pattern.push('$');
+                if literal.trim().is_empty() {
+                    return None;
+                }
+                regex::Regex::new(&pattern).ok()
+            })
+            .collect()
+    }
+}
+
+fn is_trailer(line: &str) -> bool {
+    line.split_once(": ")
+        .map(|(key, _)| {
+            !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn squash_unedited() {
+        let template = squash_template("Add foo\n\nBecause\n", "squash! Add foo\n\nAnd bar\n");
+        assert_eq!(
+            cleanup_message(&template),
+            "Add foo\n\nBecause\n\nAnd bar\n"
+        );
+    }
+
+    #[test]
+    fn squash_without_body() {
+        let template = squash_template("Add foo\n", "squash! Add foo\n");
+        assert_eq!(cleanup_message(&template), "Add foo\n");
+    }
+
+    #[test]
+    fn squash_last_body() {
+        assert_eq!(
+            squash_last("Add foo\n\nBecause\n", "squash! Add foo\n\nAnd bar\n"),
+            "Add foo\n\nAnd bar\n"
+        );
+        assert_eq!(
+            squash_last("Add foo\n\nBecause\n", "squash! Add foo\n"),
+            "Add foo\n\nBecause\n"
+        );
+    }
+
+    #[test]
+    fn cleanup_drops_diff() {
+        let message = format!("Add foo\n{}diff --git a/foo b/foo\n", SCISSORS);
+        assert_eq!(cleanup_message(&message), "Add foo\n");
+    }
+
+    #[test]
+    fn commit_template_drops_unknown() {
+        let template = CommitTemplate::new(
+            "# Provenance\nStack: {stack}\n\nLayer: {layer}\nSource: {source}\n",
+        );
+        let provenance = Provenance {
+            stack: Some("feature".to_owned()),
+            layer: Some("feature-2".to_owned()),
+            sources: Vec::new(),
+        };
+        assert_eq!(
+            template.render(&provenance),
+            "Stack: feature\nLayer: feature-2\n"
+        );
+    }
+
+    #[test]
+    fn commit_template_replaces_earlier() {
+        let template = CommitTemplate::new("Stack: {stack}\nSource: {source}\n");
+        let first = Provenance {
+            stack: Some("feature".to_owned()),
+            layer: None,
+            sources: vec![git2::Oid::from_str("1111111111111111111111111111111111111111").unwrap()],
+        };
+        let message = template.apply("Add foo\n\nBecause\n", &first);
+        assert_eq!(
+            message,
+            "Add foo\n\nBecause\n\nStack: feature\nSource: 1111111111111111111111111111111111111111\n"
+        );
+
+        let mut second = first.clone();
+        second
+            .sources
+            .push(git2::Oid::from_str("2222222222222222222222222222222222222222").unwrap());
+        assert_eq!(
+            template.apply(&message, &second),
+            "Add foo\n\nBecause\n\nStack: feature\nSource: 1111111111111111111111111111111111111111 2222222222222222222222222222222222222222\n"
+        );
+    }
+
+    #[test]
+    fn commit_template_joins_trailers() {
+        let template = CommitTemplate::new("Stack: {stack}\n");
+        let provenance = Provenance {
+            stack: Some("feature".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            template.apply("Add foo\n\nMessage-Id: <1@example.com>\n", &provenance),
+            "Add foo\n\nMessage-Id: <1@example.com>\nStack: feature\n"
+        );
+        assert_eq!(
+            template.apply("Stack: the subject\n", &provenance),
+            "Stack: the subject\n\nStack: feature\n"
+        );
+    }
+
+    #[test]
+    fn editor_precedence() {
+        let env = |key: &str| match key {
+            "VISUAL" => Some("nano".to_owned()),
+            "EDITOR" => Some("ed".to_owned()),
+            _ => None,
+        };
+        assert_eq!(Editor::from_env(Some("vim".to_owned()), env).program, "vim");
+        assert_eq!(Editor::from_env(None, env).program, "nano");
+        assert_eq!(Editor::from_env(None, |_| None).program, "vi");
+    }
+}
@@ -0,0 +1,51 @@
+/// Branch names matched by gitignore-style globs
+#[derive(Clone, Debug)]
+pub struct BranchGlobs {
+    ignores: ignore::gitignore::Gitignore,
+}
+
+impl BranchGlobs {
+    pub fn new<'p>(patterns: impl IntoIterator<Item = &'p str>) -> eyre::Result<Self> {
+        let mut ignores = ignore::gitignore::GitignoreBuilder::new("");
+        for pattern in patterns {
+            ignores.add_line(None, pattern)?;
+        }
+        let ignores = ignores.build()?;
+        Ok(Self { ignores })
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        let name_match = self.ignores.matched_path_or_any_parents(name, false);
+        match name_match {
+            ignore::Match::None => false,
+            ignore::Match::Ignore(glob) => {
+                log::trace!("{}: matched {:?}", name, glob.original());
+                true
+            }
+            ignore::Match::Whitelist(glob) => {
+                log::trace!("{}: excluded by {:?}", name, glob.original());
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_matches_nothing() {
+        let globs = BranchGlobs::new(None).unwrap();
+        assert!(!globs.is_match("main"));
+    }
+
+    #[test]
+    fn prefix() {
+        let globs = BranchGlobs::new(vec!["wip/", "!wip/keep"]).unwrap();
+        assert!(globs.is_match("wip/spike"));
+        assert!(!globs.is_match("wip/keep"));
+        assert!(!globs.is_match("wip"));
+        assert!(!globs.is_match("feature"));
+    }
+}
@@ -0,0 +1,11 @@
+/// Where `git` looks for hooks, honoring `core.hooksPath` as hook managers like husky set it
+pub fn hooks_dir(repo: &git2::Repository) -> std::path::PathBuf {
+    repo.config()
+        .ok()
+        .and_then(|config| config.get_path("core.hooksPath").ok())
+        .map(|hooks| match repo.workdir() {
+            Some(workdir) if hooks.is_relative() => workdir.join(hooks),
+            _ => hooks,
+        })
+        .unwrap_or_else(|| repo.path().join("hooks"))
+}
@@ -0,0 +1,161 @@
+/// Network settings, resolved the way `git` resolves them
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct HttpConfig {
+    pub proxy: Option<String>,
+    pub ssl_ca_info: Option<std::path::PathBuf>,
+    pub ssl_verify: bool,
+}
+
+impl HttpConfig {
+    pub fn from_repo(repo: &git2::Repository) -> Self {
+        match repo.config() {
+            Ok(config) => Self::from_gitconfig(&config, |key| std::env::var(key).ok()),
+            Err(err) => {
+                log::debug!("Failed to load git config: {}", err);
+                Self::from_env(|key| std::env::var(key).ok())
+            }
+        }
+    }
+
+    pub fn from_gitconfig(config: &git2::Config, env: impl Fn(&str) -> Option<String>) -> Self {
+        let mut http = Self::from_env(env);
+        // Unlike the other settings, `http.proxy` takes precedence over the environment
+        if let Some(proxy) = config
+            .get_string("http.proxy")
+            .ok()
+            .filter(|p| !p.is_empty())
+        {
+            http.proxy = Some(proxy);
+        }
+        if http.ssl_ca_info.is_none() {
+            http.ssl_ca_info = config.get_path("http.sslCAInfo").ok();
+        }
+        if http.ssl_verify {
+            http.ssl_verify = config.get_bool("http.sslVerify").unwrap_or(true);
+        }
+        http
+    }
+
+    fn from_env(env: impl Fn(&str) -> Option<String>) -> Self {
+        let proxy = [
+            "https_proxy",
+            "HTTPS_PROXY",
+            "http_proxy",
+            "all_proxy",
+            "ALL_PROXY",
+        ]
+        .iter()
+        .filter_map(|key| env(key))
+        .find(|p| !p.is_empty());
+        let ssl_ca_info = env("GIT_SSL_CAINFO")
+            .filter(|p| !p.is_empty())
+            .map(std::path::PathBuf::from);
+        let ssl_verify = env("GIT_SSL_NO_VERIFY").is_none();
+        Self {
+            proxy,
+            ssl_ca_info,
+            ssl_verify,
+        }
+    }
+
+    /// Write the settings, followed by `extra`, as a file for `curl --config -`
+    ///
+    /// Unlike the environment, these work the same whatever TLS backend `curl` was built with, and
+    /// `curl` ignores `HTTP_PROXY`.  `git` needs nothing, it looks these up on its own.
+    pub fn write_curl_config(
+        &self,
+        out: &mut impl std::io::Write,
+        extra: &[(&str, String)],
+    ) -> std::io::Result<()> {
+        let proxy = self
+            .proxy
+            .as_deref()
+            .map(|proxy| ("proxy", proxy.to_owned()));
+        let cacert = self
+            .ssl_ca_info
+            .as_deref()
+            .map(|ssl_ca_info| ("cacert", ssl_ca_info.display().to_string()));
+        let extra = extra.iter().map(|(option, value)| (*option, value.clone()));
+        for (option, value) in proxy.into_iter().chain(cacert).chain(extra) {
+            writeln!(out, "{} = \"{}\"", option, quote(&value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Escape for a double-quoted `curl --config` value
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn env<'e>(vars: &'e [(&'e str, &'e str)]) -> impl Fn(&str) -> Option<String> + 'e {
+        move |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| (*v).to_owned())
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let http = HttpConfig::from_env(env(&[]));
+        assert_eq!(http.proxy, None);
+        assert_eq!(http.ssl_ca_info, None);
+        assert!(http.ssl_verify);
+    }
+
+    #[test]
+    fn env_proxy_precedence() {
+        let http = HttpConfig::from_env(env(&[
+            ("ALL_PROXY", "http://all:8080"),
+            ("HTTPS_PROXY", "http://https:8080"),
+        ]));
+        assert_eq!(http.proxy.as_deref(), Some("http://https:8080"));
+    }
+
+    #[test]
+    fn env_ssl() {
+        let http = HttpConfig::from_env(env(&[
+            ("GIT_SSL_CAINFO", "/etc/corp.pem"),
+            ("GIT_SSL_NO_VERIFY", "1"),
+        ]));
+        assert_eq!(
+            http.ssl_ca_info.as_deref(),
+            Some(std::path::Path::new("/etc/corp.pem"))
+        );
+        assert!(!http.ssl_verify);
+    }
+
+    #[test]
+    fn curl_config() {
+        let http = HttpConfig::from_env(env(&[
+            ("https_proxy", "http://proxy:8080"),
+            ("GIT_SSL_CAINFO", "/etc/corp.pem"),
+        ]));
+        let mut config = Vec::new();
+        http.write_curl_config(
+            &mut config,
+            &[("header", "Authorization: \"x\"".to_owned())],
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(config).unwrap(),
+            "proxy = \"http://proxy:8080\"\ncacert = \"/etc/corp.pem\"\nheader = \"Authorization: \\\"x\\\"\"\n"
+        );
+    }
+}
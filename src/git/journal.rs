@@ -0,0 +1,157 @@
+/// Branch updates we are about to make, written before making them
+///
+/// If `git stack` is killed part way through moving branches, this is what `git stack recover`
+/// uses to finish the job or to put everything back.
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Journal {
+    pub updates: Vec<RefUpdate>,
+    /// Branch to check out once the updates are made
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restore_branch: Option<String>,
+    /// `branch-stash` snapshot of the branches from before the rewrite
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<std::path::PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RefUpdate {
+    pub name: String,
+    /// `None` when the branch is being created
+    #[serde(serialize_with = "serialize_oid")]
+    #[serde(deserialize_with = "deserialize_oid")]
+    pub old: Option<git2::Oid>,
+    /// `None` when the branch is being deleted
+    #[serde(serialize_with = "serialize_oid")]
+    #[serde(deserialize_with = "deserialize_oid")]
+    pub new: Option<git2::Oid>,
+}
+
+impl Journal {
+    /// Where the journal for `repo` lives, alongside the commit cache
+    pub fn path(repo: &git2::Repository) -> std::path::PathBuf {
+        repo.path().join("stack").join("journal.json")
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Self, std::io::Error> {
+        let file = std::fs::File::open(path)?;
+        let reader = std::io::BufReader::new(file);
+        let j = serde_json::from_reader(reader)?;
+        Ok(j)
+    }
+
+    /// Write the journal so it is either fully there or not at all
+    pub fn save(&self, path: &std::path::Path) -> Result<(), std::io::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let s = serde_json::to_string_pretty(self)?;
+        let tmp_path = path.with_extension("tmp");
+        {
+            use std::io::Write;
+            let mut file = std::fs::File::create(&tmp_path)?;
+            file.write_all(s.as_bytes())?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn remove(path: &std::path::Path) -> Result<(), std::io::Error> {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    /// Finish moving the branches
+    pub fn complete(&self, repo: &mut dyn crate::git::Repo) -> Result<(), git2::Error> {
+        self.apply(repo, |update| (update.old, update.new))
+    }
+
+    /// Put the branches back where they were
+    pub fn rollback(&self, repo: &mut dyn crate::git::Repo) -> Result<(), git2::Error> {
+        self.apply(repo, |update| (update.new, update.old))
+    }
+
+    fn apply(
+        &self,
+        repo: &mut dyn crate::git::Repo,
+        direction: impl Fn(&RefUpdate) -> (Option<git2::Oid>, Option<git2::Oid>),
+    ) -> Result<(), git2::Error> {
+        let mut branches = Vec::new();
+        let mut deletes = Vec::new();
+        for update in self.updates.iter() {
+            let (from, to) = direction(update);
+            let current = repo.find_local_branch(&update.name).map(|b| b.id);
+            if current == to {
+                log::trace!("No change for {}", update.name);
+                continue;
+            }
+            if current != from {
+                return Err(git2::Error::new(
+                    git2::ErrorCode::Modified,
+                    git2::ErrorClass::Reference,
+                    format!("`{}` was changed by something else", update.name),
+                ));
+            }
+            match to {
+                Some(id) => branches.push((id, update.name.clone())),
+                None => deletes.push(update.name.clone()),
+            }
+        }
+
+        // A detached HEAD might still be reported as a branch named `HEAD`
+        let attached_branch = |repo: &dyn crate::git::Repo| {
+            repo.head_branch()
+                .map(|b| b.name)
+                .filter(|name| repo.find_local_branch(name).is_some())
+        };
+        let head_branch = attached_branch(repo);
+        if let Some(head_branch) = head_branch.as_deref() {
+            // In case we are changing the branch HEAD is attached to
+            if branches.iter().any(|(_, name)| name == head_branch)
+                || deletes.iter().any(|name| name == head_branch)
+            {
+                repo.detach()?;
+            }
+        }
+        if !branches.is_empty() || !deletes.is_empty() {
+            repo.update_branches(&branches, &deletes)?;
+        }
+        // Don't leave HEAD detached, like the interrupted rewrite would have
+        if attached_branch(repo).is_none() {
+            let restore_branch = self
+                .restore_branch
+                .as_deref()
+                .or(head_branch.as_deref())
+                .filter(|name| repo.find_local_branch(name).is_some());
+            if let Some(restore_branch) = restore_branch {
+                repo.switch(restore_branch)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn serialize_oid<S>(id: &Option<git2::Oid>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match id {
+        Some(id) => serializer.serialize_some(&id.to_string()),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn deserialize_oid<'de, D>(deserializer: D) -> Result<Option<git2::Oid>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    let s = Option::<String>::deserialize(deserializer)?;
+    s.map(|s| git2::Oid::from_str(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
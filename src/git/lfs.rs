@@ -0,0 +1,121 @@
+//! Git LFS, which commits small pointers in place of large files and expands them on checkout
+//!
+//! LFS works through `git`'s filters and hooks, which libgit2 doesn't run, so anything we check
+//! out or push on our own has to hand off to `git lfs`.
+
+use bstr::ByteSlice;
+
+const POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/v1";
+/// Pointers are much smaller than this, per the LFS spec
+const MAX_POINTER_SIZE: usize = 1024;
+
+/// Whether any `.gitattributes` we can cheaply find routes files through LFS
+pub fn uses_lfs(repo: &git2::Repository) -> bool {
+    let in_workdir = repo
+        .workdir()
+        .and_then(|workdir| std::fs::read(workdir.join(".gitattributes")).ok());
+    let in_head = || {
+        let tree = repo.head().ok()?.peel_to_tree().ok()?;
+        let entry = tree.get_name(".gitattributes")?;
+        let blob = repo.find_blob(entry.id()).ok()?;
+        Some(blob.content().to_vec())
+    };
+    in_workdir
+        .or_else(in_head)
+        .map(|attributes| attributes.contains_str("filter=lfs"))
+        .unwrap_or(false)
+}
+
+/// Whether `path` is stored as an LFS pointer, according to the current `.gitattributes`
+pub fn is_lfs_tracked(repo: &git2::Repository, path: &std::path::Path) -> bool {
+    repo.get_attr(path, "filter", git2::AttrCheckFlags::default())
+        .ok()
+        .flatten()
+        == Some("lfs")
+}
+
+pub fn is_lfs_pointer(content: &[u8]) -> bool {
+    content.len() < MAX_POINTER_SIZE && content.starts_with(POINTER_PREFIX)
+}
+
+/// LFS-tracked files that `commit_id` adds or changes with their content rather than a pointer
+///
+/// These were committed without LFS installed, and replaying them would bake them into history
+/// again.
+pub fn raw_lfs_files(
+    repo: &git2::Repository,
+    commit_id: git2::Oid,
+) -> Result<Vec<std::path::PathBuf>, git2::Error> {
+    let commit = repo.find_commit(commit_id)?;
+    let parent_tree = commit.parent(0).ok().map(|p| p.tree()).transpose()?;
+    let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+    let odb = repo.odb()?;
+    let mut raw = Vec::new();
+    for delta in diff.deltas() {
+        let file = delta.new_file();
+        let path = match file.path() {
+            Some(path) if !file.id().is_zero() => path,
+            _ => continue,
+        };
+        if !is_lfs_tracked(repo, path) {
+            continue;
+        }
+        // Anything too big to be a pointer isn't one, without loading it
+        let (size, _) = odb.read_header(file.id())?;
+        if MAX_POINTER_SIZE <= size || !is_lfs_pointer(repo.find_blob(file.id())?.content()) {
+            raw.push(path.to_owned());
+        }
+    }
+    Ok(raw)
+}
+
+/// Whether `git push` already uploads LFS objects, through the hook `git lfs install` sets up
+pub fn has_lfs_pre_push_hook(repo: &git2::Repository) -> bool {
+    std::fs::read(super::hooks_dir(repo).join("pre-push"))
+        .map(|hook| hook.contains_str("git lfs pre-push") || hook.contains_str("git-lfs pre-push"))
+        .unwrap_or(false)
+}
+
+/// Expand any pointers left in the working tree with what is in the local LFS store
+///
+/// This doesn't download anything, leaving missing files as pointers like `git lfs checkout`.
+pub fn lfs_checkout(repo: &git2::Repository) {
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir,
+        None => return,
+    };
+    log::trace!("git lfs checkout");
+    match std::process::Command::new("git")
+        .args(["lfs", "checkout"])
+        .current_dir(workdir)
+        .stdin(std::process::Stdio::null())
+        .output()
+    {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => log::warn!(
+            "Could not expand Git LFS files, run `git lfs checkout`: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(err) => log::warn!(
+            "Could not expand Git LFS files, is Git LFS installed? {}",
+            err
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pointer() {
+        assert!(is_lfs_pointer(
+            b"version https://git-lfs.github.com/spec/v1
+oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+size 12345
+"
+        ));
+        assert!(!is_lfs_pointer(b"\x89PNG\r\n\x1a\n"));
+        assert!(!is_lfs_pointer(b""));
+    }
+}
@@ -1,9 +1,23 @@
 mod branches;
 mod commands;
+mod editor;
+mod globs;
+mod hooks;
+mod http;
+mod journal;
+mod lfs;
 mod protect;
 mod repo;
+mod review;
 
 pub use branches::*;
 pub use commands::*;
+pub use editor::*;
+pub use globs::*;
+pub use hooks::*;
+pub use http::*;
+pub use journal::*;
+pub use lfs::*;
 pub use protect::*;
 pub use repo::*;
+pub use review::*;
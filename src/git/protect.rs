@@ -1,31 +1,17 @@
+/// Branches that must not be rewritten, by gitignore-style globs
 #[derive(Clone, Debug)]
 pub struct ProtectedBranches {
-    ignores: ignore::gitignore::Gitignore,
+    globs: crate::git::BranchGlobs,
 }
 
 impl ProtectedBranches {
     pub fn new<'p>(patterns: impl IntoIterator<Item = &'p str>) -> eyre::Result<Self> {
-        let mut ignores = ignore::gitignore::GitignoreBuilder::new("");
-        for pattern in patterns {
-            ignores.add_line(None, pattern)?;
-        }
-        let ignores = ignores.build()?;
-        Ok(Self { ignores })
+        let globs = crate::git::BranchGlobs::new(patterns)?;
+        Ok(Self { globs })
     }
 
     pub fn is_protected(&self, name: &str) -> bool {
-        let name_match = self.ignores.matched_path_or_any_parents(&name, false);
-        match name_match {
-            ignore::Match::None => false,
-            ignore::Match::Ignore(glob) => {
-                log::trace!("{}: ignored {:?}", name, glob.original());
-                true
-            }
-            ignore::Match::Whitelist(glob) => {
-                log::trace!("{}: allowed {:?}", name, glob.original());
-                false
-            }
-        }
+        self.globs.is_match(name)
     }
 }
 
@@ -1,11 +1,19 @@
 use bstr::ByteSlice;
 use itertools::Itertools;
 
+/// `branch.<name>.stack-push-refspec`, see [`GitRepo::set_push_refspec`]
+pub const PUSH_REFSPEC_KEY: &str = "stack-push-refspec";
+
 pub trait Repo {
     fn user(&self) -> Option<std::rc::Rc<str>>;
 
     fn is_dirty(&self) -> bool;
     fn merge_base(&self, one: git2::Oid, two: git2::Oid) -> Option<git2::Oid>;
+    fn fork_point(&self, base: &Branch, head_id: git2::Oid) -> Option<git2::Oid>;
+    /// Compute merge-bases ahead of time so later [`Repo::merge_base`] calls are cheap
+    fn prefetch_merge_bases(&self, pairs: &[(git2::Oid, git2::Oid)]);
+    /// Load the commits of each `(base, head)` range ahead of time, for [`Repo::commits_from`]
+    fn prefetch_commits(&self, ranges: &[(git2::Oid, git2::Oid)]);
 
     fn find_commit(&self, id: git2::Oid) -> Option<std::rc::Rc<Commit>>;
     fn head_commit(&self) -> std::rc::Rc<Commit>;
@@ -15,6 +23,12 @@ pub trait Repo {
         &self,
         head_id: git2::Oid,
     ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_>;
+    /// [`Repo::commits_from`], stopping short of anything reachable from `hide_ids`
+    fn commits_from_hiding(
+        &self,
+        head_id: git2::Oid,
+        hide_ids: &[git2::Oid],
+    ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_>;
     fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -25,14 +39,30 @@ pub trait Repo {
         head_id: git2::Oid,
         cherry_id: git2::Oid,
     ) -> Result<git2::Oid, git2::Error>;
-    fn squash(&mut self, head_id: git2::Oid, into_id: git2::Oid) -> Result<git2::Oid, git2::Error>;
+    /// `provenance` is for [`GitRepo::set_commit_template`]
+    fn squash(
+        &mut self,
+        head_id: git2::Oid,
+        into_id: git2::Oid,
+        provenance: &super::Provenance,
+    ) -> Result<git2::Oid, git2::Error>;
+    /// Run the shell snippet `command` with `head_id` checked out, failing if it does
+    fn exec(&mut self, head_id: git2::Oid, command: &str) -> Result<(), git2::Error>;
 
     fn stash_push(&mut self, message: Option<&str>) -> Result<git2::Oid, git2::Error>;
     fn stash_pop(&mut self, stash_id: git2::Oid) -> Result<(), git2::Error>;
 
     fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error>;
     fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error>;
+    /// Move and delete branches together, locking all of them before changing any
+    fn update_branches(
+        &mut self,
+        branches: &[(git2::Oid, String)],
+        deletes: &[String],
+    ) -> Result<(), git2::Error>;
     fn find_local_branch(&self, name: &str) -> Option<Branch>;
+    fn find_remote_branch(&self, name: &str) -> Option<Branch>;
+    fn upstream_branch(&self, name: &str) -> Option<Branch>;
     fn local_branches(&self) -> Box<dyn Iterator<Item = Branch> + '_>;
     fn detach(&mut self) -> Result<(), git2::Error>;
     fn switch(&mut self, name: &str) -> Result<(), git2::Error>;
@@ -41,11 +71,28 @@ pub trait Repo {
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Branch {
     pub name: String,
+    /// Remote this is a remote-tracking branch for
+    pub remote: Option<String>,
     pub id: git2::Oid,
     pub push_id: Option<git2::Oid>,
     pub pull_id: Option<git2::Oid>,
 }
 
+impl Branch {
+    /// Branch name without the remote prefix
+    pub fn local_name(&self) -> &str {
+        self.remote
+            .as_deref()
+            .and_then(|remote| self.name.strip_prefix(remote))
+            .and_then(|name| name.strip_prefix('/'))
+            .unwrap_or(&self.name)
+    }
+
+    pub fn is_remote(&self) -> bool {
+        self.remote.is_some()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Commit {
     pub id: git2::Oid,
@@ -58,9 +105,13 @@ pub struct Commit {
 
 impl Commit {
     pub fn fixup_summary(&self) -> Option<&bstr::BStr> {
-        self.summary
-            .strip_prefix(b"fixup! ")
+        static FIXUP_PREFIXES: &[&[u8]] = &[b"fixup! ", b"squash! "];
+
+        FIXUP_PREFIXES
+            .iter()
+            .filter_map(|prefix| self.summary.strip_prefix(*prefix))
             .map(ByteSlice::as_bstr)
+            .next()
     }
 
     pub fn wip_summary(&self) -> Option<&bstr::BStr> {
@@ -86,6 +137,25 @@ impl Commit {
         }
     }
 
+    /// The type of a [Conventional Commit](https://www.conventionalcommits.org) summary, like
+    /// `feat` in `feat(parser)!: ...`, and whether it is marked as breaking with `!`
+    pub fn conventional_type(&self) -> Option<(&bstr::BStr, bool)> {
+        let header = &self.summary[..self.summary.find(b": ")?];
+        let (header, breaking) = match header.strip_suffix(b"!") {
+            Some(header) => (header, true),
+            None => (header, false),
+        };
+        let kind = match header.find_byte(b'(') {
+            Some(scope_start) if header.ends_with(b")") => &header[..scope_start],
+            Some(_) => return None,
+            None => header,
+        };
+        if kind.is_empty() || !kind.iter().all(|b| b.is_ascii_alphabetic()) {
+            return None;
+        }
+        Some((kind.as_bstr(), breaking))
+    }
+
     pub fn revert_summary(&self) -> Option<&bstr::BStr> {
         self.summary
             .strip_prefix(b"Revert ")
@@ -94,22 +164,80 @@ impl Commit {
     }
 }
 
+/// Asks the user a yes/no question
+pub type Prompt = Box<dyn Fn(&str) -> bool>;
+
+type TouchedDirsCache = std::collections::HashMap<(git2::Oid, git2::Oid), std::rc::Rc<[String]>>;
+
 pub struct GitRepo {
     repo: git2::Repository,
+    mailmap: Option<git2::Mailmap>,
     push_remote: Option<String>,
     pull_remote: Option<String>,
+    push_refspec: Option<crate::config::PushRefspec>,
+    /// `branch.<name>.stack-push-refspec`
+    push_refspec_overrides: std::collections::HashMap<String, crate::config::PushRefspec>,
+    /// `{user}` in [`crate::config::PushRefspec`]
+    push_user: Option<String>,
+    jobs: Option<usize>,
+    /// For [`GitRepo::set_jobs`] asking for other than rayon's global pool
+    pool: Option<rayon::ThreadPool>,
     commits: std::cell::RefCell<std::collections::HashMap<git2::Oid, std::rc::Rc<Commit>>>,
+    merge_bases:
+        std::cell::RefCell<std::collections::HashMap<(git2::Oid, git2::Oid), Option<git2::Oid>>>,
+    patch_ids: std::cell::RefCell<std::collections::HashMap<git2::Oid, Option<git2::Oid>>>,
+    content_ids: std::cell::RefCell<std::collections::HashMap<git2::Oid, Option<git2::Oid>>>,
     interned_strings: std::cell::RefCell<std::collections::HashSet<std::rc::Rc<str>>>,
+    touched_dirs: std::cell::RefCell<TouchedDirsCache>,
+    commit_cache: Option<sled::Tree>,
+    editor: Option<super::Editor>,
+    squash_message: crate::config::SquashMessage,
+    commit_template: Option<super::CommitTemplate>,
+    large_file_threshold: Option<usize>,
+    /// Where commits touching large files are replayed, kept across them
+    large_file_worktree: std::cell::RefCell<Option<Worktree>>,
+    merge_options: crate::config::MergeOptions,
+    /// Commits [`GitRepo::cherry_pick`] could only replay by ignoring whitespace
+    whitespace_resolved: Vec<git2::Oid>,
+    empty_commits: crate::config::EmptyCommits,
+    prompt: Option<Prompt>,
 }
 
 impl GitRepo {
     pub fn new(repo: git2::Repository) -> Self {
+        let mailmap = match repo.mailmap() {
+            Ok(mailmap) => Some(mailmap),
+            Err(err) => {
+                log::debug!("Could not load mailmap: {}", err);
+                None
+            }
+        };
         Self {
             repo,
+            mailmap,
             push_remote: None,
             pull_remote: None,
+            push_refspec: None,
+            push_refspec_overrides: Default::default(),
+            push_user: None,
+            jobs: None,
+            pool: None,
             commits: Default::default(),
+            merge_bases: Default::default(),
+            patch_ids: Default::default(),
+            content_ids: Default::default(),
             interned_strings: Default::default(),
+            touched_dirs: Default::default(),
+            commit_cache: None,
+            editor: None,
+            squash_message: Default::default(),
+            commit_template: None,
+            large_file_threshold: None,
+            large_file_worktree: Default::default(),
+            merge_options: Default::default(),
+            whitespace_resolved: Default::default(),
+            empty_commits: Default::default(),
+            prompt: None,
         }
     }
 
@@ -121,6 +249,304 @@ impl GitRepo {
         self.pull_remote = Some(remote.to_owned());
     }
 
+    /// Push branches according to `push_refspec` rather than to a branch of the same name
+    ///
+    /// A branch's `branch.<name>.stack-push-refspec` takes precedence, for it and the branches
+    /// stacked on it (going by their upstreams).
+    pub fn set_push_refspec(&mut self, push_refspec: Option<crate::config::PushRefspec>) {
+        self.push_refspec = push_refspec;
+        self.push_refspec_overrides.clear();
+        let config = match self.repo.config() {
+            Ok(config) => config,
+            Err(err) => {
+                log::debug!("Failed to load git config: {}", err);
+                return;
+            }
+        };
+        let pattern = format!("^branch\\..*\\.{}$", regex::escape(PUSH_REFSPEC_KEY));
+        match config.entries(Some(&pattern)) {
+            Ok(entries) => {
+                for entry in &entries {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            log::debug!("Could not read push refspec: {}", err);
+                            continue;
+                        }
+                    };
+                    let (name, value) = match (entry.name(), entry.value()) {
+                        (Some(name), Some(value)) => (name, value),
+                        _ => continue,
+                    };
+                    let branch = name
+                        .strip_prefix("branch.")
+                        .and_then(|n| n.strip_suffix(PUSH_REFSPEC_KEY))
+                        .and_then(|n| n.strip_suffix('.'));
+                    if let Some(branch) = branch {
+                        match value.parse() {
+                            Ok(refspec) => {
+                                self.push_refspec_overrides
+                                    .insert(branch.to_owned(), refspec);
+                            }
+                            Err(err) => log::warn!("Ignoring `{}={}`: {}", name, value, err),
+                        }
+                    }
+                }
+            }
+            Err(err) => log::debug!("Could not read push refspecs: {}", err),
+        }
+        self.push_user = config
+            .get_string("user.email")
+            .ok()
+            .and_then(|email| email.split_once('@').map(|(user, _)| user.to_owned()))
+            .filter(|user| !user.is_empty());
+    }
+
+    /// The refspec `name` is pushed with, if not to a branch of the same name
+    pub fn push_refspec(&self, name: &str) -> Option<&crate::config::PushRefspec> {
+        if !self.push_refspec_overrides.is_empty() {
+            let config = self.repo.config().ok();
+            let mut current = name.to_owned();
+            let mut seen = std::collections::HashSet::new();
+            while seen.insert(current.clone()) {
+                if let Some(refspec) = self.push_refspec_overrides.get(&current) {
+                    return Some(refspec);
+                }
+                // Only local upstreams say what a branch is stacked on
+                let parent = config.as_ref().and_then(|config| {
+                    let remote = config
+                        .get_string(&format!("branch.{}.remote", current))
+                        .ok()?;
+                    let merge = config
+                        .get_string(&format!("branch.{}.merge", current))
+                        .ok()?;
+                    (remote == ".")
+                        .then(|| merge.strip_prefix("refs/heads/").map(|m| m.to_owned()))
+                        .flatten()
+                });
+                match parent {
+                    Some(parent) => current = parent,
+                    None => break,
+                }
+            }
+        }
+        self.push_refspec.as_ref()
+    }
+
+    /// The ref `name` is pushed to, `None` when the refspec needs a `{user}` we don't know
+    pub fn push_remote_ref(&self, name: &str) -> Option<String> {
+        match self.push_refspec(name) {
+            Some(refspec) => refspec.remote_ref(name, self.push_user.as_deref()),
+            None => Some(format!("refs/heads/{}", name)),
+        }
+    }
+
+    /// The `git push` refspec for `name`, `None` when it needs a `{user}` we don't know
+    pub fn push_refspec_for(&self, name: &str) -> Option<String> {
+        match self.push_refspec(name) {
+            Some(refspec) => refspec.refspec(name, self.push_user.as_deref()),
+            None => Some(name.to_owned()),
+        }
+    }
+
+    /// The remote-tracking branch for where `name` is pushed, like `origin/<name>`
+    pub fn push_tracking_name(&self, name: &str) -> Option<String> {
+        let remote_ref = self.push_remote_ref(name)?;
+        let remote_branch = remote_ref.strip_prefix("refs/heads/")?;
+        Some(format!("{}/{}", self.push_remote(), remote_branch))
+    }
+
+    fn push_id(&self, name: &str) -> Option<git2::Oid> {
+        let tracking_name = self.push_tracking_name(name)?;
+        self.repo
+            .find_branch(&tracking_name, git2::BranchType::Remote)
+            .ok()
+            .and_then(|b| b.get().target())
+    }
+
+    /// Threads for prefetching, like [`GitRepo::prefetch_merge_bases`], `None` for one per CPU
+    pub fn set_jobs(&mut self, jobs: Option<usize>) {
+        self.jobs = jobs;
+        self.pool = match jobs {
+            Some(jobs) if 1 < jobs && jobs != rayon::current_num_threads() => {
+                match rayon::ThreadPoolBuilder::new().num_threads(jobs).build() {
+                    Ok(pool) => Some(pool),
+                    Err(err) => {
+                        log::debug!("Using the global thread pool: {}", err);
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+    }
+
+    /// `f` applied to each of `items` across threads, or `None` when too few to be worth it
+    ///
+    /// Items `f` returns `None` for are left out.
+    fn par_map<T, R>(
+        &self,
+        items: &[T],
+        f: impl Fn(&git2::Repository, &T) -> Option<R> + Sync,
+    ) -> Option<Vec<R>>
+    where
+        T: Sync,
+        R: Send,
+    {
+        use rayon::prelude::*;
+
+        let jobs = match (self.jobs, self.pool.as_ref()) {
+            (_, Some(pool)) => pool.current_num_threads(),
+            (Some(jobs), None) => jobs.min(rayon::current_num_threads()),
+            (None, None) => rayon::current_num_threads(),
+        };
+        if items.len() < PARALLEL_THRESHOLD || jobs < 2 {
+            return None;
+        }
+
+        let path = self.repo.path();
+        let run = || {
+            items
+                .par_iter()
+                .filter_map(|item| with_worker_repo(path, |repo| f(repo, item)))
+                .collect()
+        };
+        let results = match self.pool.as_ref() {
+            Some(pool) => pool.install(run),
+            None => run(),
+        };
+        Some(results)
+    }
+
+    /// Have the user edit the messages of `squash!` commits being squashed, like `git rebase`
+    ///
+    /// Without an editor, the messages are combined unedited.
+    pub fn set_editor(&mut self, editor: Option<super::Editor>) {
+        self.editor = editor;
+    }
+
+    /// How [`GitRepo::squash`] combines the messages of `squash!` commits
+    pub fn set_squash_message(&mut self, squash_message: crate::config::SquashMessage) {
+        self.squash_message = squash_message;
+    }
+
+    /// Record where commits made by [`GitRepo::squash`] came from in their messages
+    pub fn set_commit_template(&mut self, commit_template: Option<super::CommitTemplate>) {
+        self.commit_template = commit_template;
+    }
+
+    /// Cherry-pick commits that touch files of at least this many bytes in a temporary worktree
+    ///
+    /// libgit2 merges in memory, so `git` streaming large files to disk keeps memory bounded.
+    pub fn set_large_file_threshold(&mut self, large_file_threshold: Option<usize>) {
+        self.large_file_threshold = large_file_threshold;
+    }
+
+    /// `git rebase -X` options for [`GitRepo::cherry_pick`] and [`GitRepo::squash`]
+    pub fn set_merge_options(&mut self, merge_options: crate::config::MergeOptions) {
+        self.merge_options = merge_options;
+    }
+
+    /// The merge options to retry a conflicting cherry-pick with, to resolve conflicts that
+    /// `core.whitespace` says are only whitespace noise
+    fn whitespace_merge_options(&self) -> Option<crate::config::MergeOptions> {
+        let core_whitespace = self
+            .repo
+            .config()
+            .ok()
+            .and_then(|config| config.get_string("core.whitespace").ok());
+        let extra: Vec<_> = whitespace_merge_options(core_whitespace.as_deref())
+            .into_iter()
+            .filter(|option| !self.merge_options.iter().any(|o| o == *option))
+            .collect();
+        if extra.is_empty() {
+            return None;
+        }
+        Some(crate::config::MergeOptions::new(
+            self.merge_options.iter().chain(extra).collect(),
+        ))
+    }
+
+    /// Commits whose conflicts [`GitRepo::cherry_pick`] resolved by ignoring whitespace
+    pub fn whitespace_resolved(&self) -> &[git2::Oid] {
+        &self.whitespace_resolved
+    }
+
+    fn git2_merge_options(&self) -> git2::MergeOptions {
+        git2_merge_options(&self.merge_options)
+    }
+
+    /// What [`GitRepo::cherry_pick`] does with commits that become empty
+    ///
+    /// With [`crate::config::EmptyCommits::Ask`], `prompt` is asked and, without one, they are
+    /// dropped.
+    pub fn set_empty_commits(
+        &mut self,
+        empty_commits: crate::config::EmptyCommits,
+        prompt: Option<Prompt>,
+    ) {
+        self.empty_commits = empty_commits;
+        self.prompt = prompt;
+    }
+
+    /// Persist parsed commits under `.git/stack/` so later runs can skip re-parsing them
+    ///
+    /// The cache is best-effort; if it can't be opened (e.g. another `git stack` holds it), we
+    /// parse commits as usual.
+    pub fn open_commit_cache(&mut self) {
+        let path = self.repo.path().join("stack").join("commits");
+        let tree = sled::open(&path).and_then(|db| {
+            for stale in STALE_COMMIT_CACHE_TREES {
+                db.drop_tree(stale)?;
+            }
+            db.open_tree(COMMIT_CACHE_TREE)
+        });
+        match tree {
+            Ok(tree) => {
+                self.commit_cache = Some(tree);
+            }
+            Err(err) => {
+                log::debug!("Commit cache at {} is unavailable: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Drop cached commits that don't match the repository, returning how many there were
+    ///
+    /// With `dry_run`, they are only counted.
+    pub fn prune_commit_cache(&self, dry_run: bool) -> usize {
+        let cache = if let Some(cache) = self.commit_cache.as_ref() {
+            cache
+        } else {
+            return 0;
+        };
+        let mut stale = 0;
+        for entry in cache.iter() {
+            let (key, value) = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::debug!("Could not read the commit cache: {}", err);
+                    break;
+                }
+            };
+            let matches = || {
+                let id = git2::Oid::from_bytes(&key).ok()?;
+                let cached: CachedCommit = serde_json::from_slice(&value).ok()?;
+                let commit = self.repo.find_commit(id).ok()?;
+                (commit.tree_id().to_string() == cached.tree_id).then(|| ())
+            };
+            if matches().is_none() {
+                stale += 1;
+                if !dry_run {
+                    if let Err(err) = cache.remove(&key) {
+                        log::debug!("Could not prune the commit cache: {}", err);
+                    }
+                }
+            }
+        }
+        stale
+    }
+
     pub fn push_remote(&self) -> &str {
         self.push_remote.as_deref().unwrap_or("origin")
     }
@@ -133,11 +559,26 @@ impl GitRepo {
         &self.repo
     }
 
+    /// `.mailmap`, for treating an author's different names and emails as one person
+    pub fn mailmap(&self) -> Option<&git2::Mailmap> {
+        self.mailmap.as_ref()
+    }
+
     pub fn user(&self) -> Option<std::rc::Rc<str>> {
-        self.repo
-            .signature()
-            .ok()
-            .and_then(|s| s.name().map(|n| self.intern_string(n)))
+        let signature = self.repo.signature().ok()?;
+        let name = signature.name()?;
+        let email = signature.email().unwrap_or_default();
+        Some(self.mailmap_name(name, email))
+    }
+
+    /// The canonical name for `name <email>`, according to `.mailmap`
+    fn mailmap_name(&self, name: &str, email: &str) -> std::rc::Rc<str> {
+        let resolved = self.mailmap.as_ref().and_then(|mailmap| {
+            let signature = git2::Signature::new(name, email, &git2::Time::new(0, 0)).ok()?;
+            let resolved = mailmap.resolve_signature(&signature).ok()?;
+            resolved.name().map(|n| n.to_owned())
+        });
+        self.intern_string(resolved.as_deref().unwrap_or(name))
     }
 
     pub fn is_dirty(&self) -> bool {
@@ -145,6 +586,10 @@ impl GitRepo {
             log::trace!("Repository status is unclean: {:?}", self.repo.state());
             return true;
         }
+        if self.repo.is_bare() {
+            // No working tree to be dirty
+            return false;
+        }
 
         let status = self
             .repo
@@ -165,34 +610,202 @@ impl GitRepo {
     }
 
     pub fn merge_base(&self, one: git2::Oid, two: git2::Oid) -> Option<git2::Oid> {
-        self.repo.merge_base(one, two).ok()
+        let key = merge_base_key(one, two);
+        if let Some(merge_base) = self.merge_bases.borrow().get(&key) {
+            return *merge_base;
+        }
+        let merge_base = self.repo.merge_base(one, two).ok();
+        self.merge_bases.borrow_mut().insert(key, merge_base);
+        merge_base
+    }
+
+    /// Compute merge-bases in parallel, caching them for [`GitRepo::merge_base`]
+    pub fn prefetch_merge_bases(&self, pairs: &[(git2::Oid, git2::Oid)]) {
+        let mut pending: Vec<_> = {
+            let merge_bases = self.merge_bases.borrow();
+            pairs
+                .iter()
+                .map(|(one, two)| merge_base_key(*one, *two))
+                .filter(|key| !merge_bases.contains_key(key))
+                .collect()
+        };
+        pending.sort_unstable();
+        pending.dedup();
+
+        // Otherwise `merge_base` computes these as needed
+        if let Some(computed) = self.par_map(&pending, |repo, (one, two)| {
+            Some(((*one, *two), repo.merge_base(*one, *two).ok()))
+        }) {
+            log::trace!("Prefetched {} merge-bases", computed.len());
+            self.merge_bases.borrow_mut().extend(computed);
+        }
+    }
+
+    /// Load the commits of each `(base, head)` range in parallel, for [`GitRepo::commits_from`]
+    pub fn prefetch_commits(&self, ranges: &[(git2::Oid, git2::Oid)]) {
+        let mut ranges = ranges.to_vec();
+        ranges.sort_unstable();
+        ranges.dedup();
+
+        let loaded = self.par_map(&ranges, |repo, (base, head)| {
+            let mut revwalk = repo.revwalk().ok()?;
+            revwalk.push(*head).ok()?;
+            revwalk.hide(*base).ok()?;
+            let commits: Vec<_> = revwalk
+                .filter_map(Result::ok)
+                .filter_map(|id| {
+                    let commit = repo.find_commit(id).ok()?;
+                    Some((id, CachedCommit::new(&commit)))
+                })
+                .collect();
+            Some(commits)
+        });
+        if let Some(loaded) = loaded {
+            let mut commits = self.commits.borrow_mut();
+            let mut count = 0;
+            for (id, cached) in loaded.into_iter().flatten() {
+                if commits.contains_key(&id) {
+                    continue;
+                }
+                self.store_cached_commit(id, &cached);
+                if let Some(commit) = self.commit_from_cached(id, cached) {
+                    commits.insert(id, std::rc::Rc::new(commit));
+                    count += 1;
+                }
+            }
+            log::trace!("Prefetched {} commits", count);
+        }
+    }
+
+    /// Compute patch-ids in parallel, caching them for [`GitRepo::patch_id`]
+    pub fn prefetch_patch_ids(&self, ids: &[git2::Oid]) {
+        self.prefetch_diff_ids(&self.patch_ids, ids, PATCH_ID_CONTEXT, "patch-ids");
+    }
+
+    /// Compute content-ids in parallel, caching them for [`GitRepo::content_id`]
+    pub fn prefetch_content_ids(&self, ids: &[git2::Oid]) {
+        self.prefetch_diff_ids(&self.content_ids, ids, 0, "content-ids");
+    }
+
+    fn prefetch_diff_ids(
+        &self,
+        cache: &std::cell::RefCell<std::collections::HashMap<git2::Oid, Option<git2::Oid>>>,
+        ids: &[git2::Oid],
+        context_lines: u32,
+        kind: &str,
+    ) {
+        let mut pending: Vec<_> = {
+            let cache = cache.borrow();
+            ids.iter()
+                .copied()
+                .filter(|id| !cache.contains_key(id))
+                .collect()
+        };
+        pending.sort_unstable();
+        pending.dedup();
+
+        if let Some(computed) = self.par_map(&pending, |repo, id| {
+            Some((*id, compute_patch_id(repo, *id, context_lines)))
+        }) {
+            log::trace!("Prefetched {} {}", computed.len(), kind);
+            cache.borrow_mut().extend(computed);
+        }
+    }
+
+    /// Where `head_id` forked off of `base`, according to `base`'s reflog
+    ///
+    /// Like `git merge-base --fork-point`, this finds the base even if it has since been rewritten.
+    /// All of the reflog entries go into one many-way merge-base, rather than one walk per entry.
+    pub fn fork_point(&self, base: &Branch, head_id: git2::Oid) -> Option<git2::Oid> {
+        let mut refnames = Vec::new();
+        if base.is_remote() {
+            refnames.push(format!("refs/remotes/{}", base.name));
+        } else {
+            refnames.push(format!("refs/heads/{}", base.name));
+            refnames.push(format!("refs/remotes/{}/{}", self.pull_remote(), base.name));
+        }
+
+        // Newest first, like the reflog
+        let mut candidates: Vec<git2::Oid> = Vec::new();
+        for refname in refnames {
+            let reflog = match self.repo.reflog(&refname) {
+                Ok(reflog) => reflog,
+                Err(_) => continue,
+            };
+            for candidate in reflog.iter().map(|entry| entry.id_new()) {
+                if !candidate.is_zero() && !candidates.contains(&candidate) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // The merge-bases of `head_id` and a hypothetical merge of every entry
+        let mut ids = Vec::with_capacity(candidates.len() + 1);
+        ids.push(head_id);
+        ids.extend(candidates.iter().copied());
+        let bases = self.repo.merge_bases_many(&ids).ok()?;
+        candidates
+            .into_iter()
+            .find(|candidate| bases.iter().any(|base| base == candidate))
     }
 
     pub fn find_commit(&self, id: git2::Oid) -> Option<std::rc::Rc<Commit>> {
         let mut commits = self.commits.borrow_mut();
         if let Some(commit) = commits.get(&id) {
             Some(std::rc::Rc::clone(commit))
+        } else if let Some(commit) = self.load_cached_commit(id) {
+            let commit = std::rc::Rc::new(commit);
+            commits.insert(id, std::rc::Rc::clone(&commit));
+            Some(commit)
         } else {
             let commit = self.repo.find_commit(id).ok()?;
-            let summary: bstr::BString = commit.summary_bytes().unwrap().into();
-            let time = std::time::SystemTime::UNIX_EPOCH
-                + std::time::Duration::from_secs(commit.time().seconds().max(0) as u64);
-
-            let author = commit.author().name().map(|n| self.intern_string(n));
-            let committer = commit.author().name().map(|n| self.intern_string(n));
-            let commit = std::rc::Rc::new(Commit {
-                id: commit.id(),
-                tree_id: commit.tree_id(),
-                summary,
-                time,
-                author,
-                committer,
-            });
+            let cached = CachedCommit::new(&commit);
+            self.store_cached_commit(id, &cached);
+            let commit = std::rc::Rc::new(self.commit_from_cached(id, cached)?);
             commits.insert(id, std::rc::Rc::clone(&commit));
             Some(commit)
         }
     }
 
+    fn load_cached_commit(&self, id: git2::Oid) -> Option<Commit> {
+        let cache = self.commit_cache.as_ref()?;
+        let value = cache.get(id.as_bytes()).ok()??;
+        let cached: CachedCommit = serde_json::from_slice(&value).ok()?;
+        self.commit_from_cached(id, cached)
+    }
+
+    fn commit_from_cached(&self, id: git2::Oid, cached: CachedCommit) -> Option<Commit> {
+        Some(Commit {
+            id,
+            tree_id: git2::Oid::from_str(&cached.tree_id).ok()?,
+            summary: cached.summary.into(),
+            time: std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(cached.time),
+            author: self.cached_name(cached.author, cached.author_email),
+            committer: self.cached_name(cached.committer, cached.committer_email),
+        })
+    }
+
+    fn store_cached_commit(&self, id: git2::Oid, cached: &CachedCommit) {
+        let cache = if let Some(cache) = self.commit_cache.as_ref() {
+            cache
+        } else {
+            return;
+        };
+        let value = serde_json::to_vec(cached).expect("always valid");
+        if let Err(err) = cache.insert(id.as_bytes(), value) {
+            log::debug!("Could not cache {}: {}", id, err);
+        }
+    }
+
+    /// Names are cached as committed, so a changed `.mailmap` still applies
+    fn cached_name(&self, name: Option<String>, email: Option<String>) -> Option<std::rc::Rc<str>> {
+        let name = name?;
+        Some(self.mailmap_name(&name, email.as_deref().unwrap_or_default()))
+    }
+
     pub fn head_commit(&self) -> std::rc::Rc<Commit> {
         let head_id = self
             .repo
@@ -210,14 +823,7 @@ impl GitRepo {
         let name = resolved.shorthand()?;
         let id = resolved.target()?;
 
-        let push_id = self
-            .repo
-            .find_branch(
-                &format!("{}/{}", self.push_remote(), name),
-                git2::BranchType::Remote,
-            )
-            .ok()
-            .and_then(|b| b.get().target());
+        let push_id = self.push_id(name);
         let pull_id = self
             .repo
             .find_branch(
@@ -229,6 +835,7 @@ impl GitRepo {
 
         Some(Branch {
             name: name.to_owned(),
+            remote: None,
             id,
             push_id,
             pull_id,
@@ -243,9 +850,22 @@ impl GitRepo {
     pub fn commits_from(
         &self,
         head_id: git2::Oid,
+    ) -> impl Iterator<Item = std::rc::Rc<Commit>> + '_ {
+        self.commits_from_hiding(head_id, &[])
+    }
+
+    pub fn commits_from_hiding(
+        &self,
+        head_id: git2::Oid,
+        hide_ids: &[git2::Oid],
     ) -> impl Iterator<Item = std::rc::Rc<Commit>> + '_ {
         let mut revwalk = self.repo.revwalk().unwrap();
         revwalk.push(head_id).unwrap();
+        for hide_id in hide_ids {
+            if let Err(err) = revwalk.hide(*hide_id) {
+                log::debug!("Could not hide {}: {}", hide_id, err);
+            }
+        }
         revwalk.set_sorting(git2::Sort::TOPOLOGICAL).unwrap();
 
         revwalk
@@ -273,7 +893,11 @@ impl GitRepo {
             Some(&needle_ann_commit),
             parent_ann_commit.as_ref(),
             Some(&haystack_ann_commit),
-            Some(git2::RebaseOptions::new().inmemory(true)),
+            Some(
+                git2::RebaseOptions::new()
+                    .inmemory(true)
+                    .merge_options(self.git2_merge_options()),
+            ),
         )?;
 
         if let Some(op) = rebase.next() {
@@ -313,6 +937,34 @@ impl GitRepo {
         &mut self,
         head_id: git2::Oid,
         cherry_id: git2::Oid,
+    ) -> Result<git2::Oid, git2::Error> {
+        let err = match self.cherry_pick_with(head_id, cherry_id, &self.merge_options) {
+            Err(err) if err.code() == git2::ErrorCode::Unmerged => err,
+            result => return result,
+        };
+        let merge_options = match self.whitespace_merge_options() {
+            Some(merge_options) => merge_options,
+            None => return Err(err),
+        };
+        match self.cherry_pick_with(head_id, cherry_id, &merge_options) {
+            Ok(commit_id) => {
+                log::debug!(
+                    "Resolved whitespace-only conflicts in {} with `-X {}`",
+                    cherry_id,
+                    merge_options
+                );
+                self.whitespace_resolved.push(cherry_id);
+                Ok(commit_id)
+            }
+            Err(_) => Err(err),
+        }
+    }
+
+    fn cherry_pick_with(
+        &self,
+        head_id: git2::Oid,
+        cherry_id: git2::Oid,
+        merge_options: &crate::config::MergeOptions,
     ) -> Result<git2::Oid, git2::Error> {
         let base_id = self
             .commits_from(cherry_id)
@@ -323,6 +975,16 @@ impl GitRepo {
         if base_id == head_id {
             return Ok(cherry_id);
         }
+        if let Some(threshold) = self.large_file_threshold {
+            if self.touches_large_files(base_id, cherry_id, threshold)? {
+                log::debug!(
+                    "Cherry-picking {} in a worktree, as it touches files of {} bytes or more",
+                    cherry_id,
+                    threshold
+                );
+                return self.cherry_pick_worktree(head_id, cherry_id, merge_options);
+            }
+        }
         let base_ann_commit = self.repo.find_annotated_commit(base_id)?;
         let head_ann_commit = self.repo.find_annotated_commit(head_id)?;
         let cherry_ann_commit = self.repo.find_annotated_commit(cherry_id)?;
@@ -331,7 +993,11 @@ impl GitRepo {
             Some(&cherry_ann_commit),
             Some(&base_ann_commit),
             Some(&head_ann_commit),
-            Some(git2::RebaseOptions::new().inmemory(true)),
+            Some(
+                git2::RebaseOptions::new()
+                    .inmemory(true)
+                    .merge_options(git2_merge_options(merge_options)),
+            ),
         )?;
 
         let mut tip_id = head_id;
@@ -363,11 +1029,7 @@ impl GitRepo {
                 ));
             }
 
-            let mut sig = self.repo.signature()?;
-            if let (Some(name), Some(email)) = (sig.name(), sig.email()) {
-                // For simple rebases, preserve the original commit time
-                sig = git2::Signature::new(name, email, &cherry_commit.time())?.to_owned();
-            }
+            let sig = self.cherry_pick_signature(&cherry_commit)?;
             let commit_id = match rebase.commit(None, &sig, None).map_err(|e| {
                 let _ = rebase.abort();
                 e
@@ -377,6 +1039,23 @@ impl GitRepo {
                     if err.class() == git2::ErrorClass::Rebase
                         && err.code() == git2::ErrorCode::Applied
                     {
+                        if self.keep_empty(&cherry_commit) {
+                            log::trace!(
+                                "Keeping {} empty, already applied to {}",
+                                cherry_id,
+                                head_id
+                            );
+                            let tip_commit = self.repo.find_commit(tip_id)?;
+                            return write_commit(
+                                &self.repo,
+                                &cherry_commit.author(),
+                                &sig,
+                                cherry_commit.message_bytes(),
+                                cherry_commit.message_encoding(),
+                                &tip_commit.tree()?,
+                                &[&tip_commit],
+                            );
+                        }
                         log::trace!("Skipping {}, already applied to {}", cherry_id, head_id);
                         return Ok(tip_id);
                     }
@@ -389,10 +1068,190 @@ impl GitRepo {
         Ok(tip_id)
     }
 
+    fn cherry_pick_signature(
+        &self,
+        cherry_commit: &git2::Commit<'_>,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        let mut sig = self.repo.signature()?;
+        if let (Some(name), Some(email)) = (sig.name(), sig.email()) {
+            // For simple rebases, preserve the original commit time
+            sig = git2::Signature::new(name, email, &cherry_commit.time())?.to_owned();
+        }
+        Ok(sig)
+    }
+
+    /// Whether `base_id..cherry_id` adds or changes a file of at least `threshold` bytes
+    ///
+    /// Only object headers are read, so the files themselves aren't loaded.
+    fn touches_large_files(
+        &self,
+        base_id: git2::Oid,
+        cherry_id: git2::Oid,
+        threshold: usize,
+    ) -> Result<bool, git2::Error> {
+        let base_tree = self.repo.find_commit(base_id)?.tree()?;
+        let cherry_tree = self.repo.find_commit(cherry_id)?.tree()?;
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&base_tree), Some(&cherry_tree), None)?;
+        let odb = self.repo.odb()?;
+        for delta in diff.deltas() {
+            let id = delta.new_file().id();
+            if id.is_zero() {
+                continue;
+            }
+            let (size, _) = odb.read_header(id)?;
+            if threshold <= size {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// [`GitRepo::cherry_pick`] a single commit with `git`, in a temporary worktree
+    ///
+    /// The worktree is kept for the commits after it, so only the files each changes are
+    /// checked out.
+    fn cherry_pick_worktree(
+        &self,
+        head_id: git2::Oid,
+        cherry_id: git2::Oid,
+        merge_options: &crate::config::MergeOptions,
+    ) -> Result<git2::Oid, git2::Error> {
+        let tree_id = in_shared_worktree(
+            &self.large_file_worktree,
+            self.repo.path(),
+            head_id,
+            |path| cherry_pick_in(path, cherry_id, merge_options),
+        )?;
+
+        let head_commit = self.repo.find_commit(head_id)?;
+        let cherry_commit = self.repo.find_commit(cherry_id)?;
+        if tree_id == head_commit.tree_id() && !self.keep_empty(&cherry_commit) {
+            log::trace!("Skipping {}, already applied to {}", cherry_id, head_id);
+            return Ok(head_id);
+        }
+        let commit_id = write_commit(
+            &self.repo,
+            &cherry_commit.author(),
+            &self.cherry_pick_signature(&cherry_commit)?,
+            cherry_commit.message_bytes(),
+            cherry_commit.message_encoding(),
+            &self.repo.find_tree(tree_id)?,
+            &[&head_commit],
+        )?;
+        Ok(commit_id)
+    }
+
+    pub fn exec(&mut self, head_id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        log::debug!("Running `{}` on {}", command, head_id);
+        let uses_lfs = super::uses_lfs(&self.repo);
+        in_worktree(self.repo.path(), head_id, |path| {
+            if uses_lfs {
+                super::lfs_checkout(&git2::Repository::open(path)?);
+            }
+            let (shell, flag) = if cfg!(windows) {
+                ("cmd", "/C")
+            } else {
+                ("sh", "-c")
+            };
+            let status = std::process::Command::new(shell)
+                .arg(flag)
+                .arg(command)
+                .current_dir(path)
+                .stdin(std::process::Stdio::null())
+                .status()
+                .map_err(|err| {
+                    git2::Error::from_str(&format!("could not run `{}`: {}", command, err))
+                })?;
+            if !status.success() {
+                return Err(git2::Error::from_str(&format!(
+                    "`{}` failed on {} with {}",
+                    command, head_id, status
+                )));
+            }
+            Ok(())
+        })
+    }
+
+    fn keep_empty(&self, commit: &git2::Commit) -> bool {
+        match self.empty_commits {
+            crate::config::EmptyCommits::Keep => true,
+            crate::config::EmptyCommits::Drop => false,
+            crate::config::EmptyCommits::Ask => {
+                let prompt = format!(
+                    "{} {} is already applied, keep it as an empty commit?",
+                    &commit.id().to_string()[..7],
+                    String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default())
+                );
+                self.prompt.as_ref().map(|p| p(&prompt)).unwrap_or(false)
+            }
+        }
+    }
+
+    /// Paths that conflict when cherry-picking `cherry_id` onto `head_id`
+    pub fn cherry_pick_conflicts(
+        &self,
+        head_id: git2::Oid,
+        cherry_id: git2::Oid,
+    ) -> Result<Vec<std::path::PathBuf>, git2::Error> {
+        let head_commit = self.repo.find_commit(head_id)?;
+        let cherry_commit = self.repo.find_commit(cherry_id)?;
+        let index = self.repo.cherrypick_commit(
+            &cherry_commit,
+            &head_commit,
+            0,
+            Some(&self.git2_merge_options()),
+        )?;
+        let paths = index
+            .conflicts()?
+            .filter_map(Result::ok)
+            .filter_map(|conflict| conflict.our.or(conflict.their).or(conflict.ancestor))
+            .map(|entry| bytes2path(&entry.path).to_owned())
+            .collect();
+        Ok(paths)
+    }
+
+    /// Commits in `head_id` but not in `hide_id` that change any of `paths`, oldest first
+    pub fn commits_touching(
+        &self,
+        head_id: git2::Oid,
+        hide_id: git2::Oid,
+        paths: &[std::path::PathBuf],
+    ) -> Result<Vec<std::rc::Rc<Commit>>, git2::Error> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push(head_id)?;
+        revwalk.hide(hide_id)?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+
+        let mut diff_opts = git2::DiffOptions::new();
+        diff_opts.disable_pathspec_match(true);
+        for path in paths {
+            diff_opts.pathspec(path);
+        }
+
+        let mut touching = Vec::new();
+        for oid in revwalk {
+            let oid = oid?;
+            let commit = self.repo.find_commit(oid)?;
+            let parent_tree = commit.parent(0).ok().map(|p| p.tree()).transpose()?;
+            let diff = self.repo.diff_tree_to_tree(
+                parent_tree.as_ref(),
+                Some(&commit.tree()?),
+                Some(&mut diff_opts),
+            )?;
+            if diff.deltas().len() != 0 {
+                touching.extend(self.find_commit(oid));
+            }
+        }
+        Ok(touching)
+    }
+
     pub fn squash(
         &mut self,
         head_id: git2::Oid,
         into_id: git2::Oid,
+        provenance: &super::Provenance,
     ) -> Result<git2::Oid, git2::Error> {
         // Based on https://www.pygit2.org/recipes/git-cherry-pick.html
         let head_commit = self.repo.find_commit(head_id)?;
@@ -418,9 +1277,12 @@ impl GitRepo {
             &[]
         };
 
-        let mut result_index = self
-            .repo
-            .merge_trees(&base_tree, &into_tree, &head_tree, None)?;
+        let mut result_index = self.repo.merge_trees(
+            &base_tree,
+            &into_tree,
+            &head_tree,
+            Some(&self.git2_merge_options()),
+        )?;
         if result_index.has_conflicts() {
             let conflicts = result_index
                 .conflicts()?
@@ -442,68 +1304,351 @@ impl GitRepo {
                 format!("squash conflicts:\n  {}\n", conflicts),
             ));
         }
-        let result_id = result_index.write_tree_to(&self.repo)?;
-        let result_tree = self.repo.find_tree(result_id)?;
-        let new_id = self.repo.commit(
-            None,
-            &into_commit.author(),
-            &into_commit.committer(),
-            into_commit.message().unwrap(),
-            &result_tree,
-            onto_commits,
-        )?;
-        Ok(new_id)
+        let result_id = result_index.write_tree_to(&self.repo)?;
+        let result_tree = self.repo.find_tree(result_id)?;
+        let into_message = into_commit.message_bytes();
+        let combinable = into_commit.message_encoding() == head_commit.message_encoding();
+        // Messages that aren't UTF-8 are kept byte-for-byte, as we can't edit them
+        let message = match (std::str::from_utf8(into_message), head_commit.message()) {
+            (Ok(into_message), Some(head_message))
+                if combinable && head_message.starts_with("squash! ") =>
+            {
+                let template = super::squash_template(into_message, head_message);
+                let message = match (self.squash_message, self.editor.as_ref()) {
+                    (crate::config::SquashMessage::First, _) => into_message.to_owned(),
+                    (crate::config::SquashMessage::Last, _) => {
+                        super::squash_last(into_message, head_message)
+                    }
+                    (crate::config::SquashMessage::Editor, Some(editor)) => {
+                        let onto_tree = onto_commits.first().map(|c| c.tree()).transpose()?;
+                        let diff = self.squash_diff(onto_tree.as_ref(), &result_tree)?;
+                        let path = self.repo.path().join("COMMIT_EDITMSG");
+                        editor.edit(&path, &template, Some(&diff))?
+                    }
+                    (crate::config::SquashMessage::Concat, _)
+                    | (crate::config::SquashMessage::Editor, None) => {
+                        super::cleanup_message(&template)
+                    }
+                };
+                std::borrow::Cow::Owned(message.into_bytes())
+            }
+            _ => {
+                if head_commit.message_bytes().starts_with(b"squash! ") {
+                    log::warn!(
+                        "Keeping {}'s message, as {}'s can only be combined with it when both are UTF-8",
+                        into_id,
+                        head_id
+                    );
+                }
+                std::borrow::Cow::Borrowed(into_message)
+            }
+        };
+        let message = match (self.commit_template.as_ref(), std::str::from_utf8(&message)) {
+            (Some(template), Ok(text)) => {
+                std::borrow::Cow::Owned(template.apply(text, provenance).into_bytes())
+            }
+            (Some(_), Err(_)) => {
+                log::debug!(
+                    "Leaving {}'s message without provenance, it isn't UTF-8",
+                    into_id
+                );
+                message
+            }
+            (None, _) => message,
+        };
+        let new_id = write_commit(
+            &self.repo,
+            &into_commit.author(),
+            &into_commit.committer(),
+            &message,
+            into_commit.message_encoding(),
+            &result_tree,
+            onto_commits,
+        )?;
+        Ok(new_id)
+    }
+
+    /// The combined change, for `commit.verbose`
+    fn squash_diff(
+        &self,
+        onto_tree: Option<&git2::Tree>,
+        result_tree: &git2::Tree,
+    ) -> Result<String, git2::Error> {
+        let diff = self
+            .repo
+            .diff_tree_to_tree(onto_tree, Some(result_tree), None)?;
+        let mut patch = Vec::new();
+        diff.print(git2::DiffFormat::Patch, |_, _, line| {
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                patch.push(line.origin() as u8);
+            }
+            patch.extend_from_slice(line.content());
+            true
+        })?;
+        Ok(String::from_utf8_lossy(&patch).into_owned())
+    }
+
+    pub fn stash_push(&mut self, message: Option<&str>) -> Result<git2::Oid, git2::Error> {
+        let signature = self.repo.signature()?;
+        self.repo.stash_save2(&signature, message, None)
+    }
+
+    pub fn stash_pop(&mut self, stash_id: git2::Oid) -> Result<(), git2::Error> {
+        let mut index = None;
+        self.repo.stash_foreach(|i, _, id| {
+            if *id == stash_id {
+                index = Some(i);
+                false
+            } else {
+                true
+            }
+        })?;
+        let index = index.ok_or_else(|| {
+            git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                "stash ID not found",
+            )
+        })?;
+        self.repo.stash_pop(index, None)?;
+        if super::uses_lfs(&self.repo) {
+            super::lfs_checkout(&self.repo);
+        }
+        Ok(())
+    }
+
+    pub fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
+        let commit = self.repo.find_commit(id)?;
+        self.repo.branch(name, &commit, true)?;
+        Ok(())
+    }
+
+    pub fn tag(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
+        let commit = self.repo.find_commit(id)?;
+        self.repo.tag_lightweight(name, commit.as_object(), false)?;
+        Ok(())
+    }
+
+    /// The commit tag `name` points to
+    pub fn find_tag(&self, name: &str) -> Option<git2::Oid> {
+        let tag = self
+            .repo
+            .find_reference(&format!("refs/tags/{}", name))
+            .ok()?;
+        tag.peel_to_commit().ok().map(|c| c.id())
+    }
+
+    /// Fingerprint of the change `id` introduces, independent of its parent, like `git patch-id`
+    pub fn patch_id(&self, id: git2::Oid) -> Option<git2::Oid> {
+        if let Some(patch_id) = self.patch_ids.borrow().get(&id) {
+            return *patch_id;
+        }
+        let patch_id = compute_patch_id(&self.repo, id, PATCH_ID_CONTEXT);
+        self.patch_ids.borrow_mut().insert(id, patch_id);
+        patch_id
+    }
+
+    /// Fingerprint of just the lines `id` adds and removes, ignoring the lines around them
+    ///
+    /// Unlike [`GitRepo::patch_id`], this stays the same when `id` is replayed onto a parent that
+    /// changed next to what `id` changes, so it tells a clean restack from a drifted one.
+    pub fn content_id(&self, id: git2::Oid) -> Option<git2::Oid> {
+        if let Some(content_id) = self.content_ids.borrow().get(&id) {
+            return *content_id;
+        }
+        let content_id = compute_patch_id(&self.repo, id, 0);
+        self.content_ids.borrow_mut().insert(id, content_id);
+        content_id
+    }
+
+    /// Whether everything `head` changed since it forked from `base` has landed in `base`
+    ///
+    /// Besides regular merges and fast-forwards, this catches squash-merges by looking for a commit
+    /// on `base` with the same tree as `head` (an up-to-date branch) or the same patch-id as the
+    /// branch's combined diff.
+    pub fn is_merged(&self, base: git2::Oid, head: git2::Oid) -> bool {
+        let merge_base = match self.merge_base(base, head) {
+            Some(merge_base) => merge_base,
+            None => return false,
+        };
+        if merge_base == head {
+            return true;
+        }
+
+        let squash = || -> Option<bool> {
+            let head_tree = self.repo.find_commit(head).ok()?.tree().ok()?;
+            let merge_base_tree = self.repo.find_commit(merge_base).ok()?.tree().ok()?;
+            if head_tree.id() == merge_base_tree.id() {
+                // Nothing to look for
+                return Some(false);
+            }
+            let patch_id = self
+                .repo
+                .diff_tree_to_tree(Some(&merge_base_tree), Some(&head_tree), None)
+                .ok()?
+                .patchid(None)
+                .ok()?;
+            let mut revwalk = self.repo.revwalk().ok()?;
+            revwalk.push(base).ok()?;
+            revwalk.hide(merge_base).ok()?;
+            let landed: Vec<_> = revwalk.filter_map(Result::ok).collect();
+            self.prefetch_patch_ids(&landed);
+            let merged = landed.iter().any(|id| {
+                self.find_commit(*id).map(|c| c.tree_id) == Some(head_tree.id())
+                    || self.patch_id(*id) == Some(patch_id)
+            });
+            Some(merged)
+        };
+        squash().unwrap_or(false)
+    }
+
+    /// Whether `head` changed anything under `path` since it forked from `base`
+    pub fn touches_path(&self, base: git2::Oid, head: git2::Oid, path: &std::path::Path) -> bool {
+        let merge_base = match self.merge_base(base, head) {
+            Some(merge_base) => merge_base,
+            None => return true,
+        };
+        let entry_id = |id: git2::Oid| -> Option<git2::Oid> {
+            let tree = self.repo.find_commit(id).ok()?.tree().ok()?;
+            tree.get_path(path).ok().map(|e| e.id())
+        };
+        entry_id(merge_base) != entry_id(head)
+    }
+
+    /// Paths that differ between `base` and `head`, both sides of a rename included
+    pub fn changed_paths(&self, base: git2::Oid, head: git2::Oid) -> Vec<String> {
+        let tree = |id: git2::Oid| self.repo.find_commit(id).and_then(|c| c.tree()).ok();
+        let diff = match self
+            .repo
+            .diff_tree_to_tree(tree(base).as_ref(), tree(head).as_ref(), None)
+        {
+            Ok(diff) => diff,
+            Err(err) => {
+                log::debug!("Could not diff {}..{}: {}", base, head, err);
+                return Vec::new();
+            }
+        };
+        let mut paths: Vec<String> = Vec::new();
+        for delta in diff.deltas() {
+            for file in [delta.old_file(), delta.new_file()] {
+                if let Some(path) = file.path().and_then(|p| p.to_str()) {
+                    if !paths.iter().any(|p| p == path) {
+                        paths.push(path.to_owned());
+                    }
+                }
+            }
+        }
+        paths
+    }
+
+    /// Top-level directories (`dir/`) that differ between `base` and `head`, with top-level files
+    /// reported as `./`
+    ///
+    /// Only the root trees are compared, so this is cheap, and results are cached by tree.
+    pub fn touched_dirs(&self, base: git2::Oid, head: git2::Oid) -> std::rc::Rc<[String]> {
+        let base_tree = self.find_commit(base).map(|c| c.tree_id);
+        let head_tree = self.find_commit(head).map(|c| c.tree_id);
+        let key = match (base_tree, head_tree) {
+            (Some(base_tree), Some(head_tree)) => (base_tree, head_tree),
+            _ => return std::rc::Rc::from(Vec::new()),
+        };
+        if let Some(touched) = self.touched_dirs.borrow().get(&key) {
+            return touched.clone();
+        }
+
+        let entries = |id: git2::Oid| -> std::collections::BTreeMap<String, (git2::Oid, bool)> {
+            self.repo
+                .find_tree(id)
+                .map(|tree| {
+                    tree.iter()
+                        .filter_map(|e| {
+                            let is_dir = e.kind() == Some(git2::ObjectType::Tree);
+                            Some((e.name()?.to_owned(), (e.id(), is_dir)))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let old = entries(key.0);
+        let new = entries(key.1);
+        let mut touched = std::collections::BTreeSet::new();
+        for (name, entry) in old.iter().chain(new.iter()) {
+            if old.get(name) != new.get(name) {
+                if entry.1 {
+                    touched.insert(format!("{}/", name));
+                } else {
+                    touched.insert("./".to_owned());
+                }
+            }
+        }
+        let touched: std::rc::Rc<[String]> = touched.into_iter().collect::<Vec<_>>().into();
+        self.touched_dirs.borrow_mut().insert(key, touched.clone());
+        touched
     }
 
-    pub fn stash_push(&mut self, message: Option<&str>) -> Result<git2::Oid, git2::Error> {
-        let signature = self.repo.signature()?;
-        self.repo.stash_save2(&signature, message, None)
+    pub fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error> {
+        // HACK: We shouldn't limit ourselves to `Local`
+        let mut branch = self.repo.find_branch(name, git2::BranchType::Local)?;
+        branch.delete()
     }
 
-    pub fn stash_pop(&mut self, stash_id: git2::Oid) -> Result<(), git2::Error> {
-        let mut index = None;
-        self.repo.stash_foreach(|i, _, id| {
-            if *id == stash_id {
-                index = Some(i);
-                false
-            } else {
-                true
+    pub fn update_branches(
+        &mut self,
+        branches: &[(git2::Oid, String)],
+        deletes: &[String],
+    ) -> Result<(), git2::Error> {
+        let refname = |name: &str| format!("refs/heads/{}", name);
+        let names: std::collections::BTreeSet<_> = branches
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .chain(deletes.iter().map(|name| name.as_str()))
+            .collect();
+
+        let mut transaction = self.repo.transaction()?;
+        for name in names {
+            transaction.lock_ref(&refname(name))?;
+        }
+        for (id, name) in branches {
+            transaction.set_target(
+                &refname(name),
+                *id,
+                None,
+                &format!("git-stack: restack {}", name),
+            )?;
+        }
+        for name in deletes {
+            transaction.remove(&refname(name))?;
+        }
+        transaction.commit()?;
+
+        // Like `git branch -D`, don't leave the branch's settings behind
+        for name in deletes {
+            if let Err(err) = self.remove_branch_config(name) {
+                log::debug!("Could not remove config for {}: {}", name, err);
             }
-        })?;
-        let index = index.ok_or_else(|| {
-            git2::Error::new(
-                git2::ErrorCode::NotFound,
-                git2::ErrorClass::Reference,
-                "stash ID not found",
-            )
-        })?;
-        self.repo.stash_pop(index, None)
-    }
+        }
 
-    pub fn branch(&mut self, name: &str, id: git2::Oid) -> Result<(), git2::Error> {
-        let commit = self.repo.find_commit(id)?;
-        self.repo.branch(name, &commit, true)?;
         Ok(())
     }
 
-    pub fn delete_branch(&mut self, name: &str) -> Result<(), git2::Error> {
-        // HACK: We shouldn't limit ourselves to `Local`
-        let mut branch = self.repo.find_branch(name, git2::BranchType::Local)?;
-        branch.delete()
+    fn remove_branch_config(&self, name: &str) -> Result<(), git2::Error> {
+        let mut config = self.repo.config()?.open_level(git2::ConfigLevel::Local)?;
+        let pattern = format!("^branch\\.{}\\.", regex::escape(name));
+        let mut keys = Vec::new();
+        for entry in &config.entries(Some(&pattern))? {
+            keys.extend(entry?.name().map(String::from));
+        }
+        for key in keys {
+            config.remove_multivar(&key, ".*")?;
+        }
+        Ok(())
     }
 
     pub fn find_local_branch(&self, name: &str) -> Option<Branch> {
         let branch = self.repo.find_branch(name, git2::BranchType::Local).ok()?;
         let id = branch.get().target().unwrap();
 
-        let push_id = self
-            .repo
-            .find_branch(
-                &format!("{}/{}", self.push_remote(), name),
-                git2::BranchType::Remote,
-            )
-            .ok()
-            .and_then(|b| b.get().target());
+        let push_id = self.push_id(name);
         let pull_id = self
             .repo
             .find_branch(
@@ -515,39 +1660,77 @@ impl GitRepo {
 
         Some(Branch {
             name: name.to_owned(),
+            remote: None,
             id,
             push_id,
             pull_id,
         })
     }
 
+    pub fn find_remote_branch(&self, name: &str) -> Option<Branch> {
+        let branch = self.repo.find_branch(name, git2::BranchType::Remote).ok()?;
+        let id = branch.get().target()?;
+        let (remote, _) = name.split_once('/')?;
+
+        Some(Branch {
+            name: name.to_owned(),
+            remote: Some(remote.to_owned()),
+            id,
+            push_id: None,
+            pull_id: Some(id),
+        })
+    }
+
+    /// The branch `name` is configured to track (`branch.<name>.merge`)
+    ///
+    /// Tracking a branch of the same name (e.g. `origin/<name>`) is how the branch gets published,
+    /// not what it is based on, so that is ignored.
+    pub fn upstream_branch(&self, name: &str) -> Option<Branch> {
+        let config = self.repo.config().ok()?;
+        let merge = config.get_string(&format!("branch.{}.merge", name)).ok()?;
+        let merge = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+        if merge == name {
+            return None;
+        }
+        let remote = config
+            .get_string(&format!("branch.{}.remote", name))
+            .unwrap_or_else(|_| ".".to_owned());
+        if remote == "." {
+            self.find_local_branch(merge)
+        } else {
+            self.find_local_branch(merge)
+                .filter(|_| remote == self.pull_remote())
+                .or_else(|| self.find_remote_branch(&format!("{}/{}", remote, merge)))
+        }
+    }
+
+    /// Remote-tracking branches for `remote`
+    pub fn remote_branches<'s>(&'s self, remote: &'s str) -> impl Iterator<Item = Branch> + 's {
+        log::trace!("Loading {} branches", remote);
+        self.references_under(format!("refs/remotes/{}/", remote))
+            .flat_map(move |(local_name, reference)| {
+                if local_name == "HEAD" {
+                    return None;
+                }
+                let id = reference.target()?;
+
+                Some(Branch {
+                    name: format!("{}/{}", remote, local_name),
+                    remote: Some(remote.to_owned()),
+                    id,
+                    push_id: None,
+                    pull_id: Some(id),
+                })
+            })
+    }
+
     pub fn local_branches(&self) -> impl Iterator<Item = Branch> + '_ {
         log::trace!("Loading branches");
-        self.repo
-            .branches(Some(git2::BranchType::Local))
-            .into_iter()
-            .flatten()
-            .flat_map(move |branch| {
-                let (branch, _) = branch.ok()?;
-                let name = if let Some(name) = branch.name().ok().flatten() {
-                    name
-                } else {
-                    log::debug!(
-                        "Ignoring non-UTF8 branch {:?}",
-                        branch.name_bytes().unwrap().as_bstr()
-                    );
-                    return None;
-                };
-                let id = branch.get().target().unwrap();
+        self.references_under("refs/heads/".to_owned())
+            .flat_map(move |(name, reference)| {
+                let id = reference.target()?;
 
-                let push_id = self
-                    .repo
-                    .find_branch(
-                        &format!("{}/{}", self.push_remote(), name),
-                        git2::BranchType::Remote,
-                    )
-                    .ok()
-                    .and_then(|b| b.get().target());
+                let push_id = self.push_id(&name);
                 let pull_id = self
                     .repo
                     .find_branch(
@@ -558,7 +1741,8 @@ impl GitRepo {
                     .and_then(|b| b.get().target());
 
                 Some(Branch {
-                    name: name.to_owned(),
+                    name,
+                    remote: None,
                     id,
                     push_id,
                     pull_id,
@@ -566,6 +1750,39 @@ impl GitRepo {
             })
     }
 
+    /// References under `prefix`, with the prefix stripped from their names
+    ///
+    /// Rather than walking every loose ref and filtering, like `git2::Repository::branches` does,
+    /// this only walks the loose refs under `prefix`, so monorepos with many loose tags and
+    /// remote refs don't pay for them.  `packed-refs` is still read in full, only skipping the
+    /// packed refs outside `prefix`.
+    fn references_under(
+        &self,
+        prefix: String,
+    ) -> impl Iterator<Item = (String, git2::Reference<'_>)> + '_ {
+        let references = match self.repo.references_glob(&format!("{}*", prefix)) {
+            Ok(references) => Some(references),
+            Err(err) => {
+                log::debug!("Could not list {}: {}", prefix, err);
+                None
+            }
+        };
+        references.into_iter().flatten().flat_map(move |reference| {
+            let reference = reference.ok()?;
+            let name = if let Some(name) = reference.name() {
+                name
+            } else {
+                log::debug!(
+                    "Ignoring non-UTF8 reference {:?}",
+                    reference.name_bytes().as_bstr()
+                );
+                return None;
+            };
+            let name = name.strip_prefix(prefix.as_str())?.to_owned();
+            Some((name, reference))
+        })
+    }
+
     pub fn detach(&mut self) -> Result<(), git2::Error> {
         let head_id = self
             .repo
@@ -580,12 +1797,19 @@ impl GitRepo {
     }
 
     pub fn switch(&mut self, name: &str) -> Result<(), git2::Error> {
-        // HACK: We shouldn't limit ourselves to `Local`
-        let branch = self.repo.find_branch(name, git2::BranchType::Local)?;
+        // Remote-tracking branches leave us detached, like `git checkout origin/main`
+        let branch = self
+            .repo
+            .find_branch(name, git2::BranchType::Local)
+            .or_else(|_| self.repo.find_branch(name, git2::BranchType::Remote))?;
         self.repo.set_head(branch.get().name().unwrap())?;
         let mut builder = git2::build::CheckoutBuilder::new();
         builder.force();
         self.repo.checkout_head(Some(&mut builder))?;
+        // libgit2 checks out LFS pointers as-is
+        if super::uses_lfs(&self.repo) {
+            super::lfs_checkout(&self.repo);
+        }
         Ok(())
     }
 
@@ -624,6 +1848,18 @@ impl Repo for GitRepo {
         self.merge_base(one, two)
     }
 
+    fn fork_point(&self, base: &Branch, head_id: git2::Oid) -> Option<git2::Oid> {
+        self.fork_point(base, head_id)
+    }
+
+    fn prefetch_merge_bases(&self, pairs: &[(git2::Oid, git2::Oid)]) {
+        self.prefetch_merge_bases(pairs)
+    }
+
+    fn prefetch_commits(&self, ranges: &[(git2::Oid, git2::Oid)]) {
+        self.prefetch_commits(ranges)
+    }
+
     fn find_commit(&self, id: git2::Oid) -> Option<std::rc::Rc<Commit>> {
         self.find_commit(id)
     }
@@ -647,6 +1883,14 @@ impl Repo for GitRepo {
         Box::new(self.commits_from(head_id))
     }
 
+    fn commits_from_hiding(
+        &self,
+        head_id: git2::Oid,
+        hide_ids: &[git2::Oid],
+    ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_> {
+        Box::new(self.commits_from_hiding(head_id, hide_ids))
+    }
+
     fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -663,8 +1907,17 @@ impl Repo for GitRepo {
         self.cherry_pick(head_id, cherry_id)
     }
 
-    fn squash(&mut self, head_id: git2::Oid, into_id: git2::Oid) -> Result<git2::Oid, git2::Error> {
-        self.squash(head_id, into_id)
+    fn squash(
+        &mut self,
+        head_id: git2::Oid,
+        into_id: git2::Oid,
+        provenance: &super::Provenance,
+    ) -> Result<git2::Oid, git2::Error> {
+        self.squash(head_id, into_id, provenance)
+    }
+
+    fn exec(&mut self, head_id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.exec(head_id, command)
     }
 
     fn stash_push(&mut self, message: Option<&str>) -> Result<git2::Oid, git2::Error> {
@@ -683,10 +1936,26 @@ impl Repo for GitRepo {
         self.delete_branch(name)
     }
 
+    fn update_branches(
+        &mut self,
+        branches: &[(git2::Oid, String)],
+        deletes: &[String],
+    ) -> Result<(), git2::Error> {
+        self.update_branches(branches, deletes)
+    }
+
     fn find_local_branch(&self, name: &str) -> Option<Branch> {
         self.find_local_branch(name)
     }
 
+    fn find_remote_branch(&self, name: &str) -> Option<Branch> {
+        self.find_remote_branch(name)
+    }
+
+    fn upstream_branch(&self, name: &str) -> Option<Branch> {
+        self.upstream_branch(name)
+    }
+
     fn local_branches(&self) -> Box<dyn Iterator<Item = Branch> + '_> {
         Box::new(self.local_branches())
     }
@@ -705,6 +1974,7 @@ pub struct InMemoryRepo {
     commits: std::collections::HashMap<git2::Oid, (Option<git2::Oid>, std::rc::Rc<Commit>)>,
     branches: std::collections::HashMap<String, Branch>,
     head_id: Option<git2::Oid>,
+    execs: Vec<(git2::Oid, String)>,
 
     last_id: std::sync::atomic::AtomicUsize,
 }
@@ -715,6 +1985,7 @@ impl InMemoryRepo {
             commits: Default::default(),
             branches: Default::default(),
             head_id: Default::default(),
+            execs: Default::default(),
             last_id: std::sync::atomic::AtomicUsize::new(1),
         }
     }
@@ -801,6 +2072,19 @@ impl InMemoryRepo {
         }
     }
 
+    pub fn commits_from_hiding(
+        &self,
+        head_id: git2::Oid,
+        hide_ids: &[git2::Oid],
+    ) -> impl Iterator<Item = std::rc::Rc<Commit>> + '_ {
+        let hidden: std::collections::HashSet<_> = hide_ids
+            .iter()
+            .flat_map(|hide_id| self.commits_from(*hide_id).map(|c| c.id))
+            .collect();
+        self.commits_from(head_id)
+            .take_while(move |c| !hidden.contains(&c.id))
+    }
+
     pub fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -868,6 +2152,16 @@ impl InMemoryRepo {
         Ok(new_id)
     }
 
+    /// Record that `command` ran on `head_id`, see [`InMemoryRepo::execs`]
+    pub fn exec(&mut self, head_id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.execs.push((head_id, command.to_owned()));
+        Ok(())
+    }
+
+    pub fn execs(&self) -> &[(git2::Oid, String)] {
+        &self.execs
+    }
+
     pub fn stash_push(&mut self, _message: Option<&str>) -> Result<git2::Oid, git2::Error> {
         Err(git2::Error::new(
             git2::ErrorCode::NotFound,
@@ -889,6 +2183,7 @@ impl InMemoryRepo {
             name.to_owned(),
             Branch {
                 name: name.to_owned(),
+                remote: None,
                 id,
                 push_id: None,
                 pull_id: None,
@@ -907,10 +2202,53 @@ impl InMemoryRepo {
         })
     }
 
+    pub fn update_branches(
+        &mut self,
+        branches: &[(git2::Oid, String)],
+        deletes: &[String],
+    ) -> Result<(), git2::Error> {
+        if let Some(name) = deletes
+            .iter()
+            .find(|name| !self.branches.contains_key(*name))
+        {
+            return Err(git2::Error::new(
+                git2::ErrorCode::NotFound,
+                git2::ErrorClass::Reference,
+                format!("could not remove branch {:?}", name),
+            ));
+        }
+        for (id, name) in branches {
+            self.branch(name, *id)?;
+        }
+        for name in deletes {
+            self.delete_branch(name)?;
+        }
+        Ok(())
+    }
+
     pub fn find_local_branch(&self, name: &str) -> Option<Branch> {
         self.branches.get(name).cloned()
     }
 
+    pub fn find_remote_branch(&self, _name: &str) -> Option<Branch> {
+        None
+    }
+
+    pub fn upstream_branch(&self, _name: &str) -> Option<Branch> {
+        None
+    }
+
+    /// Everything is already in memory, there is nothing to gain
+    pub fn prefetch_merge_bases(&self, _pairs: &[(git2::Oid, git2::Oid)]) {}
+
+    /// Everything is already in memory, there is nothing to gain
+    pub fn prefetch_commits(&self, _ranges: &[(git2::Oid, git2::Oid)]) {}
+
+    /// Without a reflog, there is nothing better than the merge-base
+    pub fn fork_point(&self, base: &Branch, head_id: git2::Oid) -> Option<git2::Oid> {
+        self.merge_base(base.pull_id.unwrap_or(base.id), head_id)
+    }
+
     pub fn local_branches(&self) -> impl Iterator<Item = Branch> + '_ {
         self.branches.values().cloned()
     }
@@ -970,6 +2308,18 @@ impl Repo for InMemoryRepo {
         self.merge_base(one, two)
     }
 
+    fn fork_point(&self, base: &Branch, head_id: git2::Oid) -> Option<git2::Oid> {
+        self.fork_point(base, head_id)
+    }
+
+    fn prefetch_merge_bases(&self, pairs: &[(git2::Oid, git2::Oid)]) {
+        self.prefetch_merge_bases(pairs)
+    }
+
+    fn prefetch_commits(&self, ranges: &[(git2::Oid, git2::Oid)]) {
+        self.prefetch_commits(ranges)
+    }
+
     fn find_commit(&self, id: git2::Oid) -> Option<std::rc::Rc<Commit>> {
         self.find_commit(id)
     }
@@ -989,6 +2339,14 @@ impl Repo for InMemoryRepo {
         Box::new(self.commits_from(head_id))
     }
 
+    fn commits_from_hiding(
+        &self,
+        head_id: git2::Oid,
+        hide_ids: &[git2::Oid],
+    ) -> Box<dyn Iterator<Item = std::rc::Rc<Commit>> + '_> {
+        Box::new(self.commits_from_hiding(head_id, hide_ids))
+    }
+
     fn contains_commit(
         &self,
         haystack_id: git2::Oid,
@@ -1005,10 +2363,19 @@ impl Repo for InMemoryRepo {
         self.cherry_pick(head_id, cherry_id)
     }
 
-    fn squash(&mut self, head_id: git2::Oid, into_id: git2::Oid) -> Result<git2::Oid, git2::Error> {
+    fn squash(
+        &mut self,
+        head_id: git2::Oid,
+        into_id: git2::Oid,
+        _provenance: &super::Provenance,
+    ) -> Result<git2::Oid, git2::Error> {
         self.squash(head_id, into_id)
     }
 
+    fn exec(&mut self, head_id: git2::Oid, command: &str) -> Result<(), git2::Error> {
+        self.exec(head_id, command)
+    }
+
     fn head_branch(&self) -> Option<Branch> {
         self.head_branch()
     }
@@ -1029,10 +2396,26 @@ impl Repo for InMemoryRepo {
         self.delete_branch(name)
     }
 
+    fn update_branches(
+        &mut self,
+        branches: &[(git2::Oid, String)],
+        deletes: &[String],
+    ) -> Result<(), git2::Error> {
+        self.update_branches(branches, deletes)
+    }
+
     fn find_local_branch(&self, name: &str) -> Option<Branch> {
         self.find_local_branch(name)
     }
 
+    fn find_remote_branch(&self, name: &str) -> Option<Branch> {
+        self.find_remote_branch(name)
+    }
+
+    fn upstream_branch(&self, name: &str) -> Option<Branch> {
+        self.upstream_branch(name)
+    }
+
     fn local_branches(&self) -> Box<dyn Iterator<Item = Branch> + '_> {
         Box::new(self.local_branches())
     }
@@ -1060,6 +2443,390 @@ fn bytes2path(b: &[u8]) -> &std::path::Path {
     std::path::Path::new(str::from_utf8(b).unwrap())
 }
 
+/// Merge-bases are symmetric, so share a cache entry
+/// Commit `message` as-is, even when it isn't UTF-8, recording its `encoding` like `git commit`
+///
+/// Nothing is updated to point at the commit.
+pub fn write_commit(
+    repo: &git2::Repository,
+    author: &git2::Signature<'_>,
+    committer: &git2::Signature<'_>,
+    message: &[u8],
+    encoding: Option<&str>,
+    tree: &git2::Tree<'_>,
+    parents: &[&git2::Commit<'_>],
+) -> Result<git2::Oid, git2::Error> {
+    match (std::str::from_utf8(message), encoding) {
+        (Ok(message), None) => repo.commit(None, author, committer, message, tree, parents),
+        _ => {
+            // libgit2 only takes UTF-8 messages, so splice ours in after the headers
+            let buffer = repo.commit_create_buffer(author, committer, "", tree, parents)?;
+            let headers = buffer
+                .strip_suffix(b"\n")
+                .expect("headers are always followed by a blank line");
+            let mut content = headers.to_vec();
+            if let Some(encoding) = encoding {
+                content.extend_from_slice(format!("encoding {}\n", encoding).as_bytes());
+            }
+            content.push(b'\n');
+            content.extend_from_slice(message);
+            repo.odb()?.write(git2::ObjectType::Commit, &content)
+        }
+    }
+}
+
+fn git2_merge_options(merge_options: &crate::config::MergeOptions) -> git2::MergeOptions {
+    let mut opts = git2::MergeOptions::new();
+    for option in merge_options.iter() {
+        match option {
+            crate::config::MergeOption::Ours => {
+                opts.file_favor(git2::FileFavor::Ours);
+            }
+            crate::config::MergeOption::Theirs => {
+                opts.file_favor(git2::FileFavor::Theirs);
+            }
+            crate::config::MergeOption::IgnoreSpaceChange => {
+                opts.ignore_whitespace_change(true);
+            }
+            crate::config::MergeOption::IgnoreAllSpace => {
+                opts.ignore_whitespace(true);
+            }
+            crate::config::MergeOption::IgnoreSpaceAtEol => {
+                opts.ignore_whitespace_eol(true);
+            }
+            crate::config::MergeOption::FindRenames(threshold) => {
+                opts.find_renames(true);
+                if let Some(threshold) = threshold {
+                    opts.rename_threshold(threshold);
+                }
+            }
+            crate::config::MergeOption::NoRenames => {
+                opts.find_renames(false);
+            }
+            crate::config::MergeOption::Patience => {
+                opts.patience(true);
+            }
+        }
+    }
+    opts
+}
+
+/// `git merge -X` options ignoring the whitespace errors enabled in `core.whitespace`
+///
+/// Trailing whitespace (and carriage returns, with `cr-at-eol`) is ignored at the end of lines,
+/// and indentation rules ignore changes in the amount of whitespace.
+fn whitespace_merge_options(core_whitespace: Option<&str>) -> Vec<crate::config::MergeOption> {
+    // `git`'s defaults
+    let mut blank_at_eol = true;
+    let mut cr_at_eol = false;
+    let mut space_before_tab = true;
+    let mut indent_with_non_tab = false;
+    let mut tab_in_indent = false;
+    for rule in core_whitespace.unwrap_or_default().split(',') {
+        let rule = rule.trim();
+        let (enabled, rule) = match rule.strip_prefix('-') {
+            Some(rule) => (false, rule),
+            None => (true, rule),
+        };
+        match rule {
+            "blank-at-eol" | "trailing-space" => blank_at_eol = enabled,
+            "cr-at-eol" => cr_at_eol = enabled,
+            "space-before-tab" => space_before_tab = enabled,
+            "indent-with-non-tab" => indent_with_non_tab = enabled,
+            "tab-in-indent" => tab_in_indent = enabled,
+            _ => {}
+        }
+    }
+
+    let mut options = Vec::new();
+    if blank_at_eol || cr_at_eol {
+        options.push(crate::config::MergeOption::IgnoreSpaceAtEol);
+    }
+    if space_before_tab || indent_with_non_tab || tab_in_indent {
+        options.push(crate::config::MergeOption::IgnoreSpaceChange);
+    }
+    options
+}
+
+/// Run `git` in `dir`, returning its output
+///
+/// Hooks are disabled and LFS pointers aren't expanded, as this is plumbing rather than the user
+/// checking something out.
+fn run_git(dir: &std::path::Path, args: &[&std::ffi::OsStr]) -> Result<String, git2::Error> {
+    run_git_with(dir, args, &[], None)
+}
+
+/// [`run_git`] with extra environment variables and `input` on stdin
+fn run_git_with(
+    dir: &std::path::Path,
+    args: &[&std::ffi::OsStr],
+    envs: &[(&str, &std::ffi::OsStr)],
+    input: Option<&[u8]>,
+) -> Result<String, git2::Error> {
+    let mut child = std::process::Command::new("git")
+        .arg("-c")
+        .arg("core.hooksPath=/dev/null")
+        .args(args)
+        .env("GIT_LFS_SKIP_SMUDGE", "1")
+        .envs(envs.iter().copied())
+        .current_dir(dir)
+        .stdin(if input.is_some() {
+            std::process::Stdio::piped()
+        } else {
+            std::process::Stdio::null()
+        })
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|err| git2::Error::from_str(&format!("could not run git: {}", err)))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        use std::io::Write;
+        stdin
+            .write_all(input)
+            .map_err(|err| git2::Error::from_str(&format!("could not write to git: {}", err)))?;
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|err| git2::Error::from_str(&format!("could not run git: {}", err)))?;
+    if !output.status.success() {
+        return Err(git2::Error::from_str(&format!(
+            "`git {}` failed: {}",
+            args.iter().map(|a| a.to_string_lossy()).join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Run `f` in a temporary worktree with `head_id` checked out
+fn in_worktree<R>(
+    git_dir: &std::path::Path,
+    head_id: git2::Oid,
+    f: impl FnOnce(&std::path::Path) -> Result<R, git2::Error>,
+) -> Result<R, git2::Error> {
+    let worktree = Worktree::add(git_dir, "worktree", head_id)?;
+    f(worktree.path())
+}
+
+/// Run `f` in `worktree` with `head_id` checked out, adding the worktree on first use
+///
+/// Replaying a series of commits this way only checks out the files each one changes, rather
+/// than a whole tree per commit.
+fn in_shared_worktree<R>(
+    worktree: &std::cell::RefCell<Option<Worktree>>,
+    git_dir: &std::path::Path,
+    head_id: git2::Oid,
+    f: impl FnOnce(&std::path::Path) -> Result<R, git2::Error>,
+) -> Result<R, git2::Error> {
+    let mut worktree = worktree.borrow_mut();
+    let worktree = match worktree.take() {
+        Some(existing) => {
+            existing.reset(head_id)?;
+            worktree.insert(existing)
+        }
+        None => worktree.insert(Worktree::add(git_dir, "replay", head_id)?),
+    };
+    f(worktree.path())
+}
+
+/// A temporary, detached worktree, removed when dropped
+struct Worktree {
+    git_dir: std::path::PathBuf,
+    path: std::path::PathBuf,
+}
+
+impl Worktree {
+    /// Add a worktree with `head_id` checked out, `name` keeping it apart from others in use
+    fn add(git_dir: &std::path::Path, name: &str, head_id: git2::Oid) -> Result<Self, git2::Error> {
+        let path = git_dir
+            .join("stack")
+            .join(format!("{}-{}", name, std::process::id()));
+        let head = head_id.to_string();
+        run_git(
+            git_dir,
+            &[
+                "worktree".as_ref(),
+                "add".as_ref(),
+                "--detach".as_ref(),
+                "--quiet".as_ref(),
+                path.as_os_str(),
+                head.as_ref(),
+            ],
+        )?;
+        Ok(Self {
+            git_dir: git_dir.to_owned(),
+            path,
+        })
+    }
+
+    fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Check out `head_id`, discarding whatever was left, like a conflicted cherry-pick
+    fn reset(&self, head_id: git2::Oid) -> Result<(), git2::Error> {
+        let head = head_id.to_string();
+        run_git(
+            &self.path,
+            &[
+                "reset".as_ref(),
+                "--quiet".as_ref(),
+                "--hard".as_ref(),
+                head.as_ref(),
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        if let Err(err) = run_git(
+            &self.git_dir,
+            &[
+                "worktree".as_ref(),
+                "remove".as_ref(),
+                "--force".as_ref(),
+                self.path.as_os_str(),
+            ],
+        ) {
+            log::warn!("Could not remove worktree {}: {}", self.path.display(), err);
+        }
+    }
+}
+
+/// Apply `cherry_id` to the worktree at `path`, returning the resulting tree
+fn cherry_pick_in(
+    path: &std::path::Path,
+    cherry_id: git2::Oid,
+    merge_options: &crate::config::MergeOptions,
+) -> Result<git2::Oid, git2::Error> {
+    let cherry = cherry_id.to_string();
+    let strategy_options: Vec<_> = merge_options
+        .iter()
+        .map(|option| format!("--strategy-option={}", option))
+        .collect();
+    let mut args: Vec<&std::ffi::OsStr> = vec!["cherry-pick".as_ref(), "--no-commit".as_ref()];
+    args.extend(strategy_options.iter().map(std::ffi::OsStr::new));
+    args.push(cherry.as_ref());
+    if let Err(err) = run_git(path, &args) {
+        let conflicts = run_git(
+            path,
+            &[
+                "diff".as_ref(),
+                "--name-only".as_ref(),
+                "--diff-filter=U".as_ref(),
+            ],
+        )?;
+        if conflicts.trim().is_empty() {
+            return Err(err);
+        }
+        return Err(git2::Error::new(
+            git2::ErrorCode::Unmerged,
+            git2::ErrorClass::Index,
+            format!(
+                "cherry-pick conflicts:\n  {}\n",
+                conflicts.trim().lines().join("\n  ")
+            ),
+        ));
+    }
+    let tree = run_git(path, &["write-tree".as_ref()])?;
+    git2::Oid::from_str(tree.trim())
+}
+
+fn merge_base_key(one: git2::Oid, two: git2::Oid) -> (git2::Oid, git2::Oid) {
+    if one <= two {
+        (one, two)
+    } else {
+        (two, one)
+    }
+}
+
+/// Bump when [`CachedCommit`] changes so stale entries are ignored
+const COMMIT_CACHE_TREE: &str = "commits-v2";
+
+/// Earlier [`COMMIT_CACHE_TREE`]s, dropped on open
+const STALE_COMMIT_CACHE_TREES: &[&str] = &["commits-v1"];
+
+/// What we derive from a commit, keyed by its (immutable) id
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedCommit {
+    tree_id: String,
+    summary: Vec<u8>,
+    time: u64,
+    author: Option<String>,
+    author_email: Option<String>,
+    committer: Option<String>,
+    committer_email: Option<String>,
+}
+
+impl CachedCommit {
+    fn new(commit: &git2::Commit<'_>) -> Self {
+        let author = commit.author();
+        let committer = commit.committer();
+        Self {
+            tree_id: commit.tree_id().to_string(),
+            summary: commit.summary_bytes().unwrap_or_default().to_vec(),
+            time: commit.time().seconds().max(0) as u64,
+            author: author.name().map(|n| n.to_owned()),
+            author_email: author.email().map(|e| e.to_owned()),
+            committer: committer.name().map(|n| n.to_owned()),
+            committer_email: committer.email().map(|e| e.to_owned()),
+        }
+    }
+}
+
+/// Below this many independent queries, handing them to threads costs more than it saves
+///
+/// Each worker warms up its own object cache, which `cargo bench --bench repo` puts at about
+/// as much as a dozen merge-bases.
+const PARALLEL_THRESHOLD: usize = 16;
+
+/// Run `f` with this thread's handle on the repo at `path`
+///
+/// `git2::Repository` can't be shared between threads, so each worker opens its own, once.
+fn with_worker_repo<R>(
+    path: &std::path::Path,
+    f: impl FnOnce(&git2::Repository) -> Option<R>,
+) -> Option<R> {
+    thread_local! {
+        static WORKER_REPO: std::cell::RefCell<Option<git2::Repository>> =
+            std::cell::RefCell::new(None);
+    }
+    WORKER_REPO.with(|repo| {
+        let mut repo = repo.borrow_mut();
+        if repo.as_ref().map(|r| r.path() != path).unwrap_or(true) {
+            *repo = match git2::Repository::open(path) {
+                Ok(repo) => Some(repo),
+                Err(err) => {
+                    log::debug!("Could not open {} in a worker: {}", path.display(), err);
+                    None
+                }
+            };
+        }
+        f(repo.as_ref()?)
+    })
+}
+
+/// `git diff`'s default, which libgit2 hashes into the patch-id along with the changed lines
+const PATCH_ID_CONTEXT: u32 = 3;
+
+fn compute_patch_id(
+    repo: &git2::Repository,
+    id: git2::Oid,
+    context_lines: u32,
+) -> Option<git2::Oid> {
+    let commit = repo.find_commit(id).ok()?;
+    let parent_tree = commit.parent(0).ok().and_then(|p| p.tree().ok());
+    let tree = commit.tree().ok()?;
+    let mut options = git2::DiffOptions::new();
+    options.context_lines(context_lines);
+    let diff = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), Some(&mut options))
+        .ok()?;
+    diff.patchid(None).ok()
+}
+
 pub fn stash_push(repo: &mut dyn Repo, context: &str) -> Option<git2::Oid> {
     let branch = repo.head_branch();
     let stash_msg = format!(
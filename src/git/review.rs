@@ -0,0 +1,79 @@
+/// Sign-offs from a commit message's `Reviewed-by:` and `Acked-by:` trailers, as used by
+/// mailing-list workflows
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Review {
+    pub reviewed_by: Vec<String>,
+    pub acked_by: Vec<String>,
+}
+
+impl Review {
+    pub fn from_message(message: &str) -> Self {
+        let mut review = Self::default();
+        let trailers = match git2::message_trailers_strs(message) {
+            Ok(trailers) => trailers,
+            Err(err) => {
+                log::debug!("Could not parse trailers: {}", err);
+                return review;
+            }
+        };
+        for (key, value) in trailers.iter() {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            if key.eq_ignore_ascii_case("Reviewed-by") {
+                review.reviewed_by.push(value.to_owned());
+            } else if key.eq_ignore_ascii_case("Acked-by") {
+                review.acked_by.push(value.to_owned());
+            }
+        }
+        review
+    }
+
+    pub fn is_reviewed(&self) -> bool {
+        !self.reviewed_by.is_empty()
+    }
+
+    /// Acked without being reviewed
+    pub fn is_acked(&self) -> bool {
+        self.reviewed_by.is_empty() && !self.acked_by.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trailers() {
+        let review = Review::from_message(
+            "\
+Fix the frobnicator
+
+Reviewed-by: mentioned in the body doesn't count
+
+Signed-off-by: Author <author@example.com>
+Reviewed-by: Alice <alice@example.com>
+acked-by: Bob <bob@example.com>
+",
+        );
+        assert_eq!(
+            review,
+            Review {
+                reviewed_by: vec!["Alice <alice@example.com>".to_owned()],
+                acked_by: vec!["Bob <bob@example.com>".to_owned()],
+            }
+        );
+        assert!(review.is_reviewed());
+        assert!(!review.is_acked());
+    }
+
+    #[test]
+    fn no_trailers() {
+        let review = Review::from_message(
+            "Fix the frobnicator\n\nReviewed-by: nobody, yet\n\nSee the thread for why.\n",
+        );
+        assert!(!review.is_reviewed());
+        assert!(!review.is_acked());
+    }
+}
@@ -1,10 +1,12 @@
 mod actions;
 mod node;
 mod ops;
+mod select;
 
 pub use actions::*;
 pub use node::*;
 pub use ops::*;
+pub use select::*;
 
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
@@ -14,6 +16,10 @@ use std::collections::VecDeque;
 pub struct Graph {
     root_id: git2::Oid,
     nodes: BTreeMap<git2::Oid, Node>,
+    /// Nodes that need nodes outside of their own line, e.g. a branch in another stack
+    dependencies: BTreeMap<git2::Oid, Vec<git2::Oid>>,
+    /// Tips of history that is only graphed where it matters, see [`Graph::from_branches_protected`]
+    protected_ids: Vec<git2::Oid>,
 }
 
 impl Graph {
@@ -21,12 +27,31 @@ impl Graph {
         let root_id = node.commit.id;
         let mut nodes = BTreeMap::new();
         nodes.insert(root_id, node);
-        Self { root_id, nodes }
+        Self {
+            root_id,
+            nodes,
+            dependencies: BTreeMap::new(),
+            protected_ids: Vec::new(),
+        }
     }
 
     pub fn from_branches(
+        repo: &dyn crate::git::Repo,
+        branches: crate::git::Branches,
+    ) -> eyre::Result<Self> {
+        Self::from_branches_protected(repo, branches, Vec::new())
+    }
+
+    /// [`Graph::from_branches`], without walking the history of `protected_ids`
+    ///
+    /// Planning only cares about where development commits attach to protected history, so of
+    /// that, only the root, the tips, and the attach points are graphed (as
+    /// [`Action::Protected`]), each a child of the closest of them it descends from.  This keeps
+    /// the graph small when the protected branches are far ahead of where development forked off.
+    pub fn from_branches_protected(
         repo: &dyn crate::git::Repo,
         mut branches: crate::git::Branches,
+        protected_ids: Vec<git2::Oid>,
     ) -> eyre::Result<Self> {
         if branches.is_empty() {
             eyre::bail!("no branches to graph");
@@ -37,9 +62,22 @@ impl Graph {
         branch_ids.sort_by_key(|id| &branches.get(*id).unwrap()[0].name);
 
         let branch_id = branch_ids.remove(0);
+        // Walking each branch back to the graph is independent of the others, so do it up front
+        let pairs: Vec<_> = branch_ids.iter().map(|id| (branch_id, *id)).collect();
+        repo.prefetch_merge_bases(&pairs);
+        let ranges: Vec<_> = branch_ids
+            .iter()
+            .filter_map(|id| Some((repo.merge_base(branch_id, *id)?, *id)))
+            .collect();
+        repo.prefetch_commits(&ranges);
+
         let branch_commit = repo.find_commit(branch_id).unwrap();
         let root = Node::new(branch_commit).with_branches(&mut branches);
         let mut graph = Self::new(root);
+        graph.protected_ids = protected_ids;
+        if graph.is_protected_history(repo, branch_id) {
+            graph.root_mut().action = Action::Protected;
+        }
 
         for branch_id in branch_ids {
             let branch_commit = repo.find_commit(branch_id).unwrap();
@@ -59,9 +97,22 @@ impl Graph {
                 .merge_base(self.root_id, node_id)
                 .ok_or_else(|| eyre::eyre!("Could not find merge base"))?;
             if merge_base_id != self.root_id {
+                let root_id = self.root_id;
                 let root_action = self.root().action;
-                self.populate(repo, merge_base_id, self.root_id, root_action)?;
-                self.root_id = merge_base_id;
+                if self.is_protected_history(repo, merge_base_id) {
+                    let mut root = Node::new(repo.find_commit(merge_base_id).unwrap());
+                    root.action = Action::Protected;
+                    self.nodes.insert(merge_base_id, root);
+                    self.root_id = merge_base_id;
+                    if root_action.is_protected() {
+                        self.link_protected(repo, root_id)?;
+                    } else {
+                        self.populate(repo, merge_base_id, root_id, root_action)?;
+                    }
+                } else {
+                    self.populate(repo, merge_base_id, root_id, root_action)?;
+                    self.root_id = merge_base_id;
+                }
             }
             if merge_base_id != node_id {
                 self.populate(repo, merge_base_id, node_id, node.action)?;
@@ -85,6 +136,11 @@ impl Graph {
                 }
             }
         }
+        for (dependent_id, dependency_ids) in other.dependencies {
+            for dependency_id in dependency_ids {
+                self.add_dependency(dependent_id, dependency_id);
+            }
+        }
 
         Ok(())
     }
@@ -108,10 +164,34 @@ impl Graph {
         Some(removed)
     }
 
+    /// Record that `dependent_id` needs `dependency_id`, beyond being descended from it
+    pub fn add_dependency(&mut self, dependent_id: git2::Oid, dependency_id: git2::Oid) {
+        let dependency_ids = self.dependencies.entry(dependent_id).or_default();
+        if !dependency_ids.contains(&dependency_id) {
+            dependency_ids.push(dependency_id);
+        }
+    }
+
+    /// What `id` needs, that is still in the graph
+    pub fn dependencies(&self, id: git2::Oid) -> impl Iterator<Item = git2::Oid> + '_ {
+        self.dependencies
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |id| self.nodes.contains_key(id))
+    }
+
     pub fn root(&self) -> &Node {
         self.nodes.get(&self.root_id).expect("root always exists")
     }
 
+    fn root_mut(&mut self) -> &mut Node {
+        self.nodes
+            .get_mut(&self.root_id)
+            .expect("root always exists")
+    }
+
     pub fn root_id(&self) -> git2::Oid {
         self.root_id
     }
@@ -142,14 +222,20 @@ impl Graph {
             "HEAD must be a descendant of base"
         );
 
+        if !self.nodes.contains_key(&head_oid) && self.is_protected_history(repo, head_oid) {
+            return self.insert_protected(repo, head_oid);
+        }
+
         let mut child_id = None;
-        for commit in repo.commits_from(head_oid) {
+        let mut connected = false;
+        for commit in repo.commits_from_hiding(head_oid, &self.protected_ids) {
             match self.nodes.entry(commit.id) {
                 Entry::Occupied(mut o) => {
                     let current = o.get_mut();
                     if let Some(child_id) = child_id {
                         current.children.insert(child_id);
                         // Tapped into previous entries, don't bother going further
+                        connected = true;
                         break;
                     }
 
@@ -163,6 +249,7 @@ impl Graph {
                     }
 
                     if current.commit.id == base_oid {
+                        connected = true;
                         break;
                     }
 
@@ -171,10 +258,103 @@ impl Graph {
             }
         }
 
+        if let (false, Some(child_id)) = (connected, child_id) {
+            // Walked down to protected history, so hang off of where we forked from it
+            let attach_ids: Vec<_> = self
+                .protected_ids
+                .iter()
+                .filter_map(|protected_id| repo.merge_base(child_id, *protected_id))
+                .collect();
+            if let Some(attach_id) = latest(repo, attach_ids) {
+                self.insert_protected(repo, attach_id)?;
+                self.get_mut(attach_id)
+                    .expect("insert_protected added attach_id")
+                    .children
+                    .insert(child_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `id` is reachable from `protected_ids`
+    fn is_protected_history(&self, repo: &dyn crate::git::Repo, id: git2::Oid) -> bool {
+        self.protected_ids
+            .iter()
+            .any(|protected_id| repo.merge_base(id, *protected_id) == Some(id))
+    }
+
+    /// Graph the protected commit `id`, without the protected history leading up to it
+    fn insert_protected(
+        &mut self,
+        repo: &dyn crate::git::Repo,
+        id: git2::Oid,
+    ) -> Result<(), git2::Error> {
+        if self.nodes.contains_key(&id) {
+            return Ok(());
+        }
+
+        let commit = repo
+            .find_commit(id)
+            .ok_or_else(|| git2::Error::from_str("protected commit is missing"))?;
+        let mut node = Node::new(commit);
+        node.action = Action::Protected;
+        self.nodes.insert(id, node);
+        self.link_protected(repo, id)
+    }
+
+    /// Make protected `id` a child of the closest protected node it descends from
+    ///
+    /// That node's children that descend from `id` move under `id`.
+    fn link_protected(
+        &mut self,
+        repo: &dyn crate::git::Repo,
+        id: git2::Oid,
+    ) -> Result<(), git2::Error> {
+        let ancestor_ids: Vec<_> = self
+            .nodes
+            .values()
+            .filter(|node| node.action.is_protected() && node.commit.id != id)
+            .filter_map(|node| repo.merge_base(node.commit.id, id))
+            .filter(|ancestor_id| *ancestor_id != id)
+            .collect();
+        let parent_id = if let Some(parent_id) = latest(repo, ancestor_ids) {
+            parent_id
+        } else {
+            // `id` is the root
+            return Ok(());
+        };
+        // Where `id` forked from another protected node
+        self.insert_protected(repo, parent_id)?;
+
+        let parent = self.get_mut(parent_id).expect("insert_protected added it");
+        let descendant_ids: Vec<_> = parent
+            .children
+            .iter()
+            .copied()
+            .filter(|child_id| repo.merge_base(*child_id, id) == Some(id))
+            .collect();
+        for descendant_id in &descendant_ids {
+            parent.children.remove(descendant_id);
+        }
+        parent.children.insert(id);
+        self.get_mut(id)
+            .expect("id is graphed")
+            .children
+            .extend(descendant_ids);
+
         Ok(())
     }
 }
 
+/// The commit in `ids` that descends from the rest, as far as they are in one line
+fn latest(repo: &dyn crate::git::Repo, ids: Vec<git2::Oid>) -> Option<git2::Oid> {
+    ids.into_iter().fold(None, |latest, id| match latest {
+        Some(latest) if repo.merge_base(latest, id) != Some(latest) => Some(latest),
+        _ => Some(id),
+    })
+}
+
 pub struct BreadthFirstIter<'g> {
     graph: &'g Graph,
     node_queue: VecDeque<git2::Oid>,
@@ -1,31 +1,34 @@
-use std::collections::BTreeSet;
-
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Node {
     pub commit: std::rc::Rc<crate::git::Commit>,
     pub branches: Vec<crate::git::Branch>,
     pub action: crate::graph::Action,
+    /// Shell commands to run, in order, once this commit is replayed, like `exec` lines in a
+    /// `git rebase -i` todo, see [`crate::graph::exec_at`]
+    pub exec: Vec<String>,
+    /// Never squashed, moved, or dropped when planning, e.g. for vendored imports
+    pub pinned: bool,
     pub pushable: bool,
-    pub children: BTreeSet<git2::Oid>,
+    pub children: Children,
 }
 
 impl Node {
     pub fn new(commit: std::rc::Rc<crate::git::Commit>) -> Self {
         let branches = Vec::new();
-        let children = BTreeSet::new();
+        let children = Children::new();
         Self {
             commit,
             branches,
             action: crate::graph::Action::Pick,
+            exec: Vec::new(),
+            pinned: false,
             pushable: false,
             children,
         }
     }
 
     pub fn with_branches(mut self, possible_branches: &mut crate::git::Branches) -> Self {
-        self.branches = possible_branches
-            .remove(self.commit.id)
-            .unwrap_or_else(Vec::new);
+        self.branches = possible_branches.remove(self.commit.id).unwrap_or_default();
         self
     }
 
@@ -40,6 +43,12 @@ impl Node {
             self.action = other.action;
         }
 
+        self.exec.extend(other.exec);
+
+        if other.pinned {
+            self.pinned = true;
+        }
+
         if other.pushable {
             self.pushable = true;
         }
@@ -47,3 +56,100 @@ impl Node {
         self.children.extend(other.children);
     }
 }
+
+/// Child commit ids, in sorted order
+///
+/// Most nodes have zero or one child, so this is a sorted `Vec` rather than a `BTreeSet` to keep
+/// large graphs (e.g. `Stack::All`) small.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Children(Vec<git2::Oid>);
+
+impl Children {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, id: &git2::Oid) -> bool {
+        self.0.binary_search(id).is_ok()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, git2::Oid> {
+        self.0.iter()
+    }
+
+    /// Returns whether `id` was newly inserted
+    pub fn insert(&mut self, id: git2::Oid) -> bool {
+        match self.0.binary_search(&id) {
+            Ok(_) => false,
+            Err(index) => {
+                self.0.insert(index, id);
+                true
+            }
+        }
+    }
+
+    /// Returns whether `id` was present
+    pub fn remove(&mut self, id: &git2::Oid) -> bool {
+        match self.0.binary_search(id) {
+            Ok(index) => {
+                self.0.remove(index);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl Extend<git2::Oid> for Children {
+    fn extend<I: IntoIterator<Item = git2::Oid>>(&mut self, iter: I) {
+        for id in iter {
+            self.insert(id);
+        }
+    }
+}
+
+impl IntoIterator for Children {
+    type Item = git2::Oid;
+    type IntoIter = std::vec::IntoIter<git2::Oid>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Children {
+    type Item = &'a git2::Oid;
+    type IntoIter = std::slice::Iter<'a, git2::Oid>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn children_are_sorted_and_unique() {
+        let one = git2::Oid::from_str("1").unwrap();
+        let two = git2::Oid::from_str("2").unwrap();
+        let mut children = Children::new();
+        assert!(children.insert(two));
+        assert!(children.insert(one));
+        assert!(!children.insert(two));
+        assert_eq!(children.iter().copied().collect::<Vec<_>>(), [one, two]);
+
+        assert!(children.remove(&one));
+        assert!(!children.remove(&one));
+        assert_eq!(children.len(), 1);
+    }
+}
@@ -10,13 +10,23 @@ pub fn protect_branches(
     repo: &dyn crate::git::Repo,
     protected_branches: &crate::git::Branches,
 ) {
-    let root_id = graph.root_id();
-
     let protected_oids: HashSet<_> = protected_branches
         .iter()
         .flat_map(|(_, branches)| branches.iter().map(|b| b.pull_id.unwrap_or(b.id)))
         .collect();
 
+    protect_commits(graph, repo, protected_oids);
+}
+
+/// Protect `protected_oids` and their ancestors
+pub fn protect_commits(
+    graph: &mut Graph,
+    repo: &dyn crate::git::Repo,
+    protected_oids: impl IntoIterator<Item = git2::Oid>,
+) {
+    let root_id = graph.root_id();
+
+    let protected_oids: HashSet<_> = protected_oids.into_iter().collect();
     for protected_oid in protected_oids.into_iter().filter(|protected_oid| {
         repo.merge_base(root_id, *protected_oid)
             .map(|merge_base_oid| merge_base_oid == root_id)
@@ -36,6 +46,33 @@ pub fn protect_branches(
     }
 }
 
+/// Development branches with more than `max` commits of their own, with how many they have
+///
+/// A branch's own commits are those since the branch (or protected commit) it is stacked on.
+pub fn oversized_branches(graph: &Graph, max: usize) -> Vec<(String, usize)> {
+    let mut oversized = Vec::new();
+
+    let mut node_queue = VecDeque::new();
+    node_queue.push_back((graph.root_id(), 0));
+    while let Some((current_id, count)) = node_queue.pop_front() {
+        let current = graph.get(current_id).expect("all children exist");
+        let count = if current.action.is_protected() {
+            0
+        } else if current.branches.is_empty() {
+            count + 1
+        } else {
+            let count = count + 1;
+            if max < count {
+                oversized.extend(current.branches.iter().map(|b| (b.name.clone(), count)));
+            }
+            0
+        };
+        node_queue.extend(current.children.iter().map(|id| (*id, count)));
+    }
+
+    oversized
+}
+
 pub fn protect_large_branches(graph: &mut Graph, max: usize) -> Vec<String> {
     let mut large_branches = Vec::new();
 
@@ -341,8 +378,8 @@ pub fn rebase_development_branches(graph: &mut Graph, new_base_id: git2::Oid) {
 
         if !rebaseable.is_empty() {
             let current = graph.get_mut(current_id).expect("all children exist");
-            for child_id in rebaseable.iter().copied() {
-                current.children.remove(&child_id);
+            for child_id in rebaseable.iter() {
+                current.children.remove(child_id);
             }
             graph
                 .get_mut(new_base_id)
@@ -414,6 +451,172 @@ pub fn pushable(graph: &mut Graph) {
     }
 }
 
+/// Record which branches depend on which, as `(dependent, dependency)` branch names
+///
+/// Dependencies on branches that aren't in the graph are ignored.
+pub fn mark_dependencies(graph: &mut Graph, dependencies: &[(String, String)]) {
+    let branch_ids: std::collections::HashMap<&str, git2::Oid> = graph
+        .breadth_first_iter()
+        .flat_map(|n| {
+            n.branches
+                .iter()
+                .map(move |b| (b.name.as_str(), n.commit.id))
+        })
+        .collect();
+    let edges: Vec<_> = dependencies
+        .iter()
+        .filter_map(|(dependent, dependency)| {
+            let dependent_id = branch_ids.get(dependent.as_str())?;
+            let dependency_id = branch_ids.get(dependency.as_str())?;
+            Some((*dependent_id, *dependency_id))
+        })
+        .collect();
+    for (dependent_id, dependency_id) in edges {
+        graph.add_dependency(dependent_id, dependency_id);
+    }
+}
+
+/// Don't push branches whose dependencies are neither pushed nor about to be
+pub fn hold_dependents(graph: &mut Graph) {
+    // Holding one branch back can hold back the branches depending on it
+    loop {
+        let held: Vec<_> = graph
+            .breadth_first_iter()
+            .filter(|n| n.pushable)
+            .filter_map(|n| {
+                let missing = graph.dependencies(n.commit.id).find(|id| {
+                    let dependency = graph.get(*id).expect("dependencies are in the graph");
+                    let pushed = !dependency.branches.is_empty()
+                        && dependency.branches.iter().all(|b| Some(b.id) == b.push_id);
+                    !(dependency.pushable || pushed || dependency.action.is_protected())
+                })?;
+                Some((n.commit.id, missing))
+            })
+            .collect();
+        if held.is_empty() {
+            break;
+        }
+        for (id, missing) in held {
+            let node = graph.get(id).expect("came from graph");
+            if let (Some(branch), Some(dependency)) = (
+                node.branches.first(),
+                graph.get(missing).and_then(|n| n.branches.first()),
+            ) {
+                log::debug!(
+                    "{} isn't pushable, depends on {} which isn't pushed",
+                    branch.name,
+                    dependency.name
+                );
+            }
+            graph.get_mut(id).expect("came from graph").pushable = false;
+        }
+    }
+}
+
+/// Nodes, breadth-first, except that a node comes after the nodes it depends on
+pub fn push_order(graph: &Graph) -> Vec<git2::Oid> {
+    let mut pending: VecDeque<git2::Oid> =
+        graph.breadth_first_iter().map(|n| n.commit.id).collect();
+    let mut ordered = Vec::with_capacity(pending.len());
+    let mut placed = std::collections::HashSet::new();
+    let mut deferred = 0;
+    while let Some(id) = pending.pop_front() {
+        let ready = graph.dependencies(id).all(|d| placed.contains(&d));
+        // Break cycles by giving up on ordering once nothing else can be placed
+        if ready || pending.len() < deferred {
+            ordered.push(id);
+            placed.insert(id);
+            deferred = 0;
+        } else {
+            pending.push_back(id);
+            deferred += 1;
+        }
+    }
+    ordered
+}
+
+/// A problem with relationships between branches recorded outside of git's history, like
+/// `branch.<name>.merge`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EdgeProblem {
+    /// Branches that, eventually, lead back to themselves
+    Cycle(Vec<String>),
+    /// `branch` refers to `parent`, which doesn't exist
+    Missing { branch: String, parent: String },
+}
+
+impl std::fmt::Display for EdgeProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle(branches) => {
+                write!(f, "{} -> {}", branches.join(" -> "), branches[0])
+            }
+            Self::Missing { branch, parent } => {
+                write!(f, "{} -> {} (deleted)", branch, parent)
+            }
+        }
+    }
+}
+
+/// Find cycles and dangling references in `(branch, parent)` edges
+pub fn check_edges(edges: &[(String, String)], exists: impl Fn(&str) -> bool) -> Vec<EdgeProblem> {
+    let mut problems: Vec<_> = edges
+        .iter()
+        .filter(|(_, parent)| !exists(parent))
+        .map(|(branch, parent)| EdgeProblem::Missing {
+            branch: branch.clone(),
+            parent: parent.clone(),
+        })
+        .collect();
+
+    let mut cycles: Vec<Vec<String>> = Vec::new();
+    let mut done = std::collections::HashSet::new();
+    for (start, _) in edges.iter() {
+        if done.contains(start.as_str()) {
+            continue;
+        }
+        // Depth-first, tracking the path to spot when we loop back onto it
+        let mut path: Vec<&str> = Vec::new();
+        let mut stack: Vec<(&str, usize)> = vec![(start.as_str(), 0)];
+        while let Some((current, depth)) = stack.pop() {
+            path.truncate(depth);
+            if let Some(index) = path.iter().position(|p| *p == current) {
+                let mut cycle: Vec<String> =
+                    path[index..].iter().map(|s| (*s).to_owned()).collect();
+                // Start from the same branch, however the cycle was entered
+                let min = cycle
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, b)| b.as_str())
+                    .map(|(i, _)| i)
+                    .unwrap_or(0);
+                cycle.rotate_left(min);
+                if !cycles.contains(&cycle) {
+                    cycles.push(cycle);
+                }
+                continue;
+            }
+            if done.contains(current) {
+                continue;
+            }
+            path.push(current);
+            let parents = edges
+                .iter()
+                .filter(|(branch, _)| branch == current)
+                .map(|(_, parent)| (parent.as_str(), depth + 1));
+            let before = stack.len();
+            stack.extend(parents);
+            if stack.len() == before {
+                done.insert(current);
+            }
+        }
+        done.insert(start.as_str());
+    }
+    problems.extend(cycles.into_iter().map(EdgeProblem::Cycle));
+
+    problems
+}
+
 /// Quick pass for what is droppable
 ///
 /// We get into this state when a branch is squashed.  The id would be different due to metadata
@@ -471,6 +674,9 @@ fn drop_first_branch_by_tree_id(
         // just be cautious.
         return;
     }
+    if node.pinned {
+        return;
+    }
 
     let is_branch = !node.branches.is_empty();
     let node_tree_id = node.commit.tree_id;
@@ -541,11 +747,79 @@ pub fn drop_merged_branches(
     removed
 }
 
+/// Run `command` after replaying each commit, like `git rebase --exec`
+///
+/// For commits with fixups squashed in, it runs after the last one.
+pub fn exec(graph: &mut Graph, command: &str) {
+    let picked: Vec<_> = graph
+        .breadth_first_iter()
+        .filter(|n| n.action.is_pick())
+        .map(|n| n.commit.id)
+        .collect();
+    for node_id in picked {
+        exec_at(graph, node_id, command);
+    }
+}
+
+/// Run `command` once `node_id` is replayed, like an `exec` line after its `pick` in a
+/// `git rebase -i` todo
+///
+/// Run this after [`fixup`] so the command sees the commit with its fixups squashed in.  Returns
+/// `false` if `node_id` isn't being replayed.
+pub fn exec_at(graph: &mut Graph, node_id: git2::Oid, command: &str) -> bool {
+    let mut current_id = node_id;
+    loop {
+        let node = match graph.get(current_id) {
+            Some(node) => node,
+            None => return false,
+        };
+        if !(node.action.is_pick() || node.action.is_fixup()) {
+            return false;
+        }
+        let fixup_id = node.children.iter().copied().find(|child_id| {
+            graph
+                .get(*child_id)
+                .expect("all children exist")
+                .action
+                .is_fixup()
+        });
+        match fixup_id {
+            Some(fixup_id) => current_id = fixup_id,
+            None => break,
+        }
+    }
+    graph
+        .get_mut(current_id)
+        .expect("all children exist")
+        .exec
+        .push(command.to_owned());
+    true
+}
+
+/// Leave `pinned_ids` alone when planning, see [`Node::pinned`]
+pub fn pin_commits(graph: &mut Graph, pinned_ids: impl IntoIterator<Item = git2::Oid>) {
+    for pinned_id in pinned_ids {
+        if let Some(node) = graph.get_mut(pinned_id) {
+            node.pinned = true;
+        }
+    }
+}
+
+/// Move or squash `fixup!` commits into the commits they target
+///
+/// Pinned commits are neither moved nor squashed into, so fixups for them stay where they are.
 pub fn fixup(graph: &mut Graph, effect: crate::config::Fixup) {
     if effect == crate::config::Fixup::Ignore {
         return;
     }
 
+    // Fixups find their target by summary, so that is all we can go by
+    let pinned_summaries: HashSet<_> = graph
+        .breadth_first_iter()
+        .filter(|n| n.pinned)
+        .map(|n| n.commit.summary.clone())
+        .collect();
+
     let mut protected_queue = VecDeque::new();
     let root_action = graph.root().action;
     if root_action.is_protected() {
@@ -563,7 +837,7 @@ pub fn fixup(graph: &mut Graph, effect: crate::config::Fixup) {
             if child_action.is_protected() || child_action.is_delete() {
                 protected_queue.push_back(child_id);
             } else {
-                fixup_branch(graph, current_id, child_id, effect);
+                fixup_branch(graph, current_id, child_id, effect, &pinned_summaries);
             }
         }
     }
@@ -574,6 +848,7 @@ fn fixup_branch(
     base_id: git2::Oid,
     mut node_id: git2::Oid,
     effect: crate::config::Fixup,
+    pinned_summaries: &HashSet<bstr::BString>,
 ) {
     debug_assert_ne!(effect, crate::config::Fixup::Ignore);
 
@@ -584,7 +859,16 @@ fn fixup_branch(
         .children
         .clone();
     for child_id in node_children {
-        fixup_node(graph, node_id, child_id, effect, &mut outstanding);
+        let mut child_outstanding = Default::default();
+        fixup_node(
+            graph,
+            node_id,
+            child_id,
+            effect,
+            pinned_summaries,
+            &mut child_outstanding,
+        );
+        merge_outstanding(&mut outstanding, child_outstanding);
     }
     if !outstanding.is_empty() {
         let node = graph.get_mut(node_id).expect("all children exist");
@@ -615,6 +899,7 @@ fn fixup_node(
     base_id: git2::Oid,
     node_id: git2::Oid,
     effect: crate::config::Fixup,
+    pinned_summaries: &HashSet<bstr::BString>,
     outstanding: &mut std::collections::BTreeMap<bstr::BString, Vec<git2::Oid>>,
 ) {
     debug_assert_ne!(effect, crate::config::Fixup::Ignore);
@@ -625,7 +910,17 @@ fn fixup_node(
         .children
         .clone();
     for child_id in node_children {
-        fixup_node(graph, node_id, child_id, effect, outstanding);
+        // Fixups can only apply to their own ancestors, not to a sibling stack's commits
+        let mut child_outstanding = Default::default();
+        fixup_node(
+            graph,
+            node_id,
+            child_id,
+            effect,
+            pinned_summaries,
+            &mut child_outstanding,
+        );
+        merge_outstanding(outstanding, child_outstanding);
     }
 
     let mut patch = None;
@@ -634,10 +929,14 @@ fn fixup_node(
         let node = graph.get_mut(node_id).expect("all children exist");
         debug_assert_ne!(node.action, crate::graph::Action::Protected);
         debug_assert_ne!(node.action, crate::graph::Action::Delete);
-        if let Some(summary) = node.commit.fixup_summary() {
+        let fixup_summary = node
+            .commit
+            .fixup_summary()
+            .filter(|summary| !node.pinned && !pinned_summaries.contains(*summary));
+        if let Some(summary) = fixup_summary {
             outstanding
                 .entry(summary.to_owned())
-                .or_insert_with(Default::default)
+                .or_default()
                 .push(node_id);
 
             let mut children = Default::default();
@@ -671,6 +970,15 @@ fn fixup_node(
     }
 }
 
+fn merge_outstanding(
+    outstanding: &mut std::collections::BTreeMap<bstr::BString, Vec<git2::Oid>>,
+    child_outstanding: std::collections::BTreeMap<bstr::BString, Vec<git2::Oid>>,
+) {
+    for (summary, fixup_ids) in child_outstanding {
+        outstanding.entry(summary).or_default().extend(fixup_ids);
+    }
+}
+
 // Does not update references
 fn splice_between(
     graph: &mut Graph,
@@ -747,7 +1055,7 @@ pub fn realign_stacks(graph: &mut Graph) {
 }
 
 fn realign_stack(graph: &mut Graph, node_id: git2::Oid) {
-    let mut children = std::collections::BTreeSet::new();
+    let mut children = crate::graph::Children::new();
 
     let mut current_id = node_id;
     loop {
@@ -814,7 +1122,7 @@ pub fn merge_stacks(graph: &mut Graph) {
 
                 unprotected_children
                     .entry(child.commit.tree_id)
-                    .or_insert_with(Vec::new)
+                    .or_default()
                     .push((child.commit.time, child_id));
             }
         }
@@ -880,7 +1188,7 @@ fn enqueue_merge_stack(
 
         unprotected_children
             .entry(child.commit.tree_id)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push((child.commit.time, child_id));
     }
     if !unprotected_children.is_empty() {
@@ -908,7 +1216,7 @@ pub fn to_script(graph: &Graph) -> crate::git::Script {
                     script
                         .commands
                         .push(crate::git::Command::SwitchCommit(stack_mark));
-                    for branch in child.branches.iter() {
+                    for branch in child.branches.iter().filter(|b| !b.is_remote()) {
                         script
                             .commands
                             .push(crate::git::Command::CreateBranch(branch.name.clone()));
@@ -936,6 +1244,7 @@ fn node_to_script(graph: &Graph, node_id: git2::Oid) -> Option<crate::git::Scrip
             script
                 .commands
                 .push(crate::git::Command::CherryPick(node.commit.id));
+            script.commands.extend(exec_after(node));
             for branch in node.branches.iter() {
                 script
                     .commands
@@ -958,6 +1267,7 @@ fn node_to_script(graph: &Graph, node_id: git2::Oid) -> Option<crate::git::Scrip
             script
                 .commands
                 .push(crate::git::Command::Fixup(node.commit.id));
+            script.commands.extend(exec_after(node));
             // We can't re-target the branches of the commit we are squashing into, so the ops that
             // creates a `Fixup` option has to handle that.
             for branch in node.branches.iter() {
@@ -991,7 +1301,7 @@ fn node_to_script(graph: &Graph, node_id: git2::Oid) -> Option<crate::git::Scrip
                     .commands
                     .push(crate::git::Command::SwitchCommit(stack_mark));
                 // We might be updating protected branches as part of a `pull --rebase`,
-                for branch in node.branches.iter() {
+                for branch in node.branches.iter().filter(|b| !b.is_remote()) {
                     script
                         .commands
                         .push(crate::git::Command::CreateBranch(branch.name.clone()));
@@ -1030,6 +1340,13 @@ fn node_to_script(graph: &Graph, node_id: git2::Oid) -> Option<crate::git::Scrip
     }
 }
 
+/// The node's [`crate::git::Command::Exec`]s, see [`exec_at`]
+fn exec_after(node: &Node) -> impl Iterator<Item = crate::git::Command> + '_ {
+    node.exec
+        .iter()
+        .map(|command| crate::git::Command::Exec(command.clone()))
+}
+
 fn extend_dependents(
     node: &Node,
     script: &mut crate::git::Script,
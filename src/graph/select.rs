@@ -0,0 +1,483 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use crate::graph::Graph;
+use crate::graph::Node;
+
+/// A query for branches in a [`Graph`], e.g. `stack(feature-x) & !pushed() & author(me)`
+///
+/// Terms combine with `&`, `|`, `!`, and parentheses, with `!` binding tightest and `|` loosest.
+/// A bare word is a branch name, which may be a gitignore-style pattern like `feature/*`.  Words
+/// with spaces or special characters can be quoted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Selector {
+    /// `all()`
+    All,
+    /// `name(pattern)`, or just `pattern`
+    Name(String),
+    /// `stack(branch)`: the branches on the same line as `branch`, default the checked-out branch
+    Stack(Option<String>),
+    /// `pushed()`: the push remote is up-to-date with the branch
+    Pushed,
+    /// `author(name)`: any of the branch's own commits are by `name`, `me` being the current user
+    Author(String),
+    /// `label(name)`: the branch carries this label
+    Label(String),
+    Not(Box<Selector>),
+    And(Box<Selector>, Box<Selector>),
+    Or(Box<Selector>, Box<Selector>),
+}
+
+impl Selector {
+    fn functions() -> &'static [&'static str] {
+        &["all", "author", "label", "name", "pushed", "stack"]
+    }
+
+    /// Branch name patterns, to compile once
+    fn patterns(&self) -> Vec<&str> {
+        match self {
+            Selector::Name(pattern) => vec![pattern],
+            Selector::Not(inner) => inner.patterns(),
+            Selector::And(lhs, rhs) | Selector::Or(lhs, rhs) => {
+                let mut patterns = lhs.patterns();
+                patterns.extend(rhs.patterns());
+                patterns
+            }
+            Selector::All
+            | Selector::Stack(_)
+            | Selector::Pushed
+            | Selector::Author(_)
+            | Selector::Label(_) => Vec::new(),
+        }
+    }
+}
+
+impl std::str::FromStr for Selector {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let mut parser = Parser { input: s, pos: 0 };
+        let selector = parser.or()?;
+        if let Some(c) = parser.peek() {
+            return Err(parser.error(format!("unexpected `{}`", c)));
+        }
+        for pattern in selector.patterns() {
+            name_matcher(pattern)
+                .map_err(|err| format!("invalid pattern `{}`: {}", pattern, err))?;
+        }
+        Ok(selector)
+    }
+}
+
+impl std::fmt::Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::All => write!(f, "all()"),
+            Selector::Name(pattern) if is_bare_word(pattern) => write!(f, "{}", pattern),
+            Selector::Name(pattern) => write!(f, "name({:?})", pattern),
+            Selector::Stack(None) => write!(f, "stack()"),
+            Selector::Stack(Some(branch)) => write!(f, "stack({})", Word(branch)),
+            Selector::Pushed => write!(f, "pushed()"),
+            Selector::Author(name) => write!(f, "author({})", Word(name)),
+            Selector::Label(label) => write!(f, "label({})", Word(label)),
+            Selector::Not(inner) => match inner.as_ref() {
+                Selector::And(..) | Selector::Or(..) => write!(f, "!({})", inner),
+                _ => write!(f, "!{}", inner),
+            },
+            Selector::And(lhs, rhs) => {
+                let operand = |s: &Selector| match s {
+                    Selector::Or(..) => format!("({})", s),
+                    _ => s.to_string(),
+                };
+                write!(f, "{} & {}", operand(lhs), operand(rhs))
+            }
+            Selector::Or(lhs, rhs) => write!(f, "{} | {}", lhs, rhs),
+        }
+    }
+}
+
+/// A function argument, quoted when needed to parse back
+struct Word<'s>(&'s str);
+
+impl std::fmt::Display for Word<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if is_bare_word(self.0) {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{:?}", self.0)
+        }
+    }
+}
+
+fn is_bare_word(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '(' | ')' | '&' | '|' | '!' | '"' | '\'')
+}
+
+struct Parser<'s> {
+    input: &'s str,
+    pos: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn or(&mut self) -> Result<Selector, String> {
+        let mut lhs = self.and()?;
+        while self.eat('|') {
+            let rhs = self.and()?;
+            lhs = Selector::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Selector, String> {
+        let mut lhs = self.not()?;
+        while self.eat('&') {
+            let rhs = self.not()?;
+            lhs = Selector::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<Selector, String> {
+        if self.eat('!') {
+            let inner = self.not()?;
+            Ok(Selector::Not(Box::new(inner)))
+        } else {
+            self.term()
+        }
+    }
+
+    fn term(&mut self) -> Result<Selector, String> {
+        if self.eat('(') {
+            let inner = self.or()?;
+            self.expect(')')?;
+            return Ok(inner);
+        }
+
+        // Point errors at the word, not the whitespace before it
+        self.peek();
+        let start = self.pos;
+        let word = self.word()?;
+        if !self.eat('(') {
+            return Ok(Selector::Name(word));
+        }
+        let arg = if self.peek() == Some(')') {
+            None
+        } else {
+            Some(self.word()?)
+        };
+        self.expect(')')?;
+        let selector = match (word.as_str(), arg) {
+            ("all", None) => Selector::All,
+            ("name", Some(pattern)) => Selector::Name(pattern),
+            ("stack", branch) => Selector::Stack(branch),
+            ("pushed", None) => Selector::Pushed,
+            ("author", Some(name)) => Selector::Author(name),
+            ("label", Some(label)) => Selector::Label(label),
+            ("all", Some(_)) | ("pushed", Some(_)) => {
+                return Err(error_at(start, format!("`{}()` takes no argument", word)));
+            }
+            ("name", None) | ("author", None) | ("label", None) => {
+                return Err(error_at(start, format!("`{}()` needs an argument", word)));
+            }
+            _ => {
+                return Err(error_at(
+                    start,
+                    format!(
+                        "unknown function `{}`, expected one of {}",
+                        word,
+                        Selector::functions().join(", ")
+                    ),
+                ));
+            }
+        };
+        Ok(selector)
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                let start = self.pos;
+                self.pos += 1;
+                let rest = &self.input[self.pos..];
+                let len = rest
+                    .find(quote)
+                    .ok_or_else(|| error_at(start, "unterminated string".to_owned()))?;
+                self.pos += len + 1;
+                Ok(rest[..len].to_owned())
+            }
+            Some(c) if is_word_char(c) => {
+                let rest = &self.input[self.pos..];
+                let len = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+                self.pos += len;
+                Ok(rest[..len].to_owned())
+            }
+            Some(c) => Err(self.error(format!("unexpected `{}`", c))),
+            None => Err(self.error("unexpected end".to_owned())),
+        }
+    }
+
+    /// The next character, after any whitespace
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            match self.peek() {
+                Some(found) => Err(self.error(format!("expected `{}`, found `{}`", c, found))),
+                None => Err(self.error(format!("expected `{}`", c))),
+            }
+        }
+    }
+
+    fn error(&self, message: String) -> String {
+        error_at(self.pos, message)
+    }
+}
+
+fn error_at(pos: usize, message: String) -> String {
+    format!("{} at column {}", message, pos + 1)
+}
+
+fn name_matcher(pattern: &str) -> Result<ignore::gitignore::Gitignore, ignore::Error> {
+    let mut builder = ignore::gitignore::GitignoreBuilder::new("");
+    builder.add_line(None, pattern)?;
+    builder.build()
+}
+
+/// What [`select_branches`] needs beyond the graph
+#[derive(Clone, Default, Debug)]
+pub struct SelectContext {
+    /// Who `author(me)` is
+    pub user: Option<String>,
+    /// The checked-out branch, for `stack()`
+    pub head_branch: Option<String>,
+    /// The labels of each branch that has any
+    pub labels: BTreeMap<String, Vec<String>>,
+}
+
+/// Names of the branches in `graph` that match `selector`
+///
+/// Pre-requisites:
+/// - Running `protect_branches`, so protected history isn't part of any branch
+pub fn select_branches(
+    graph: &Graph,
+    selector: &Selector,
+    context: &SelectContext,
+) -> BTreeSet<String> {
+    let mut parents = HashMap::new();
+    for node in graph.breadth_first_iter() {
+        for child_id in node.children.iter() {
+            parents.insert(*child_id, node.commit.id);
+        }
+    }
+    let matchers = selector
+        .patterns()
+        .into_iter()
+        .filter_map(|pattern| Some((pattern, name_matcher(pattern).ok()?)))
+        .collect();
+    let eval = Eval {
+        graph,
+        parents,
+        matchers,
+        context,
+    };
+
+    graph
+        .breadth_first_iter()
+        .flat_map(|node| node.branches.iter().map(move |branch| (node, branch)))
+        .filter(|(node, branch)| eval.matches(selector, node, branch))
+        .map(|(_, branch)| branch.name.clone())
+        .collect()
+}
+
+struct Eval<'g> {
+    graph: &'g Graph,
+    parents: HashMap<git2::Oid, git2::Oid>,
+    matchers: HashMap<&'g str, ignore::gitignore::Gitignore>,
+    context: &'g SelectContext,
+}
+
+impl Eval<'_> {
+    fn matches(&self, selector: &Selector, node: &Node, branch: &crate::git::Branch) -> bool {
+        match selector {
+            Selector::All => true,
+            Selector::Name(pattern) => self
+                .matchers
+                .get(pattern.as_str())
+                .map(|m| {
+                    m.matched_path_or_any_parents(&branch.name, false)
+                        .is_ignore()
+                })
+                .unwrap_or(false),
+            Selector::Stack(target) => {
+                let target = match target.as_deref().or(self.context.head_branch.as_deref()) {
+                    Some(target) => target,
+                    None => return false,
+                };
+                match self.find_branch(target) {
+                    Some(target_id) => self.in_line(node.commit.id, target_id),
+                    None => false,
+                }
+            }
+            Selector::Pushed => branch.push_id == Some(branch.id),
+            Selector::Author(name) => {
+                let name = if name == "me" {
+                    match self.context.user.as_deref() {
+                        Some(user) => user,
+                        None => return false,
+                    }
+                } else {
+                    name.as_str()
+                };
+                self.own_commits(node.commit.id)
+                    .any(|n| n.commit.author.as_deref() == Some(name))
+            }
+            Selector::Label(label) => self
+                .context
+                .labels
+                .get(&branch.name)
+                .map(|labels| labels.contains(label))
+                .unwrap_or(false),
+            Selector::Not(inner) => !self.matches(inner, node, branch),
+            Selector::And(lhs, rhs) => {
+                self.matches(lhs, node, branch) && self.matches(rhs, node, branch)
+            }
+            Selector::Or(lhs, rhs) => {
+                self.matches(lhs, node, branch) || self.matches(rhs, node, branch)
+            }
+        }
+    }
+
+    fn find_branch(&self, name: &str) -> Option<git2::Oid> {
+        self.graph
+            .breadth_first_iter()
+            .find(|n| n.branches.iter().any(|b| b.name == name))
+            .map(|n| n.commit.id)
+    }
+
+    /// `node_id` and its ancestors, newest first
+    fn ancestors(&self, node_id: git2::Oid) -> impl Iterator<Item = &Node> + '_ {
+        std::iter::successors(self.graph.get(node_id), move |n| {
+            self.parents
+                .get(&n.commit.id)
+                .and_then(|id| self.graph.get(*id))
+        })
+    }
+
+    /// Whether `node_id` builds on `target_id` or `target_id` builds on it
+    fn in_line(&self, node_id: git2::Oid, target_id: git2::Oid) -> bool {
+        self.ancestors(node_id).any(|n| n.commit.id == target_id)
+            || self
+                .ancestors(target_id)
+                .any(|n| n.commit.id == node_id && !n.action.is_protected())
+    }
+
+    /// The commits that are only on the branch at `node_id`, newest first
+    fn own_commits(&self, node_id: git2::Oid) -> impl Iterator<Item = &Node> + '_ {
+        self.ancestors(node_id)
+            .enumerate()
+            // Commits from another branch down belong to it
+            .take_while(|(i, n)| !n.action.is_protected() && (*i == 0 || n.branches.is_empty()))
+            .map(|(_, n)| n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Selector {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("a | b & !c"),
+            Selector::Or(
+                Box::new(Selector::Name("a".to_owned())),
+                Box::new(Selector::And(
+                    Box::new(Selector::Name("b".to_owned())),
+                    Box::new(Selector::Not(Box::new(Selector::Name("c".to_owned())))),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            parse("stack(feature-x) & !pushed() & author(me)"),
+            Selector::And(
+                Box::new(Selector::And(
+                    Box::new(Selector::Stack(Some("feature-x".to_owned()))),
+                    Box::new(Selector::Not(Box::new(Selector::Pushed))),
+                )),
+                Box::new(Selector::Author("me".to_owned())),
+            )
+        );
+        assert_eq!(parse("stack( )"), Selector::Stack(None));
+        assert_eq!(
+            parse("author(\"Ed Page\")"),
+            Selector::Author("Ed Page".to_owned())
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in [
+            "(a | b) & !(c & d)",
+            "name(\"with space\") | label(wip)",
+            "stack() & !pushed()",
+            "feature/* & author(\"Ed Page\")",
+        ] {
+            assert_eq!(parse(s).to_string(), s);
+            assert_eq!(parse(&parse(s).to_string()), parse(s));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "stack(a".parse::<Selector>(),
+            Err("expected `)` at column 8".to_owned())
+        );
+        assert_eq!(
+            "a & ".parse::<Selector>(),
+            Err("unexpected end at column 5".to_owned())
+        );
+        assert_eq!(
+            "a b".parse::<Selector>(),
+            Err("unexpected `b` at column 3".to_owned())
+        );
+        assert_eq!(
+            "pushed(a)".parse::<Selector>(),
+            Err("`pushed()` takes no argument at column 1".to_owned())
+        );
+        assert_eq!(
+            "mine()".parse::<Selector>(),
+            Err(
+                "unknown function `mine`, expected one of all, author, label, name, pushed, stack at column 1"
+                    .to_owned()
+            )
+        );
+    }
+}
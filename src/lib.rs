@@ -1,6 +1,7 @@
 #![allow(clippy::collapsible_else_if)]
 
 pub mod config;
+pub mod forge;
 pub mod git;
 pub mod graph;
 pub mod log;
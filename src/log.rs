@@ -1,9 +1,48 @@
 use std::io::Write;
 
-pub fn init_logging(mut level: clap_verbosity_flag::Verbosity, colored: bool) {
+/// Log target for talking to remotes, which is spread across modules
+pub const REMOTE_TARGET: &str = "git_stack::remote";
+/// Log target for talking to forges, like GitHub, which is spread across modules
+pub const FORGE_TARGET: &str = "git_stack::forge";
+
+/// Log to [`REMOTE_TARGET`], e.g. `remote_log!(debug, "git fetch {}", remote)`
+#[macro_export]
+macro_rules! remote_log {
+    ($level:ident, $($arg:tt)+) => {
+        ::log::$level!(target: $crate::log::REMOTE_TARGET, $($arg)+)
+    };
+}
+
+/// Log to [`FORGE_TARGET`], e.g. `forge_log!(debug, "GET {}", url)`
+#[macro_export]
+macro_rules! forge_log {
+    ($level:ident, $($arg:tt)+) => {
+        ::log::$level!(target: $crate::log::FORGE_TARGET, $($arg)+)
+    };
+}
+
+/// `scopes` limit verbosity beyond the default to those subsystems, and `log_file` gets the
+/// verbose output while the terminal keeps its usual output
+pub fn init_logging(
+    mut level: clap_verbosity_flag::Verbosity,
+    colored: bool,
+    scopes: &[LogScope],
+    log_file: Option<&std::path::Path>,
+) -> std::io::Result<()> {
     level.set_default(Some(log::Level::Info));
+    let level = level
+        .log_level()
+        .map(|level| level.to_level_filter())
+        .unwrap_or(log::LevelFilter::Off);
 
-    if let Some(level) = level.log_level() {
+    let mut loggers: Vec<Box<dyn log::Log>> = Vec::new();
+    let term_level = if log_file.is_some() {
+        level.min(log::LevelFilter::Info)
+    } else {
+        level
+    };
+    let mut max_level = log::LevelFilter::Off;
+    if term_level != log::LevelFilter::Off {
         let palette = if colored {
             Palette::colored()
         } else {
@@ -17,9 +56,9 @@ pub fn init_logging(mut level: clap_verbosity_flag::Verbosity, colored: bool) {
             env_logger::WriteStyle::Never
         });
 
-        builder.filter(None, level.to_level_filter());
+        filter_scopes(&mut builder, term_level, scopes);
 
-        if level == log::LevelFilter::Trace || level == log::LevelFilter::Debug {
+        if term_level == log::LevelFilter::Trace || term_level == log::LevelFilter::Debug {
             builder.format_timestamp_secs();
         } else {
             builder.format(move |f, record| match record.level() {
@@ -51,7 +90,153 @@ pub fn init_logging(mut level: clap_verbosity_flag::Verbosity, colored: bool) {
             });
         }
 
-        builder.init();
+        let logger = builder.build();
+        max_level = max_level.max(logger.filter());
+        loggers.push(Box::new(logger));
+    }
+
+    if let Some(log_file) = log_file {
+        let file = std::fs::File::create(log_file)?;
+        let mut builder = env_logger::Builder::new();
+        filter_scopes(&mut builder, level, scopes);
+        // HACK: env_logger's `Target::Pipe` still writes to stderr, so only use it for filtering
+        let filter = builder.build();
+        max_level = max_level.max(filter.filter());
+        loggers.push(Box::new(FileLogger {
+            filter,
+            file: std::sync::Mutex::new(std::io::LineWriter::new(file)),
+        }));
+    }
+
+    if !loggers.is_empty() && log::set_boxed_logger(Box::new(Tee { loggers })).is_ok() {
+        log::set_max_level(max_level);
+    }
+
+    Ok(())
+}
+
+fn filter_scopes(builder: &mut env_logger::Builder, level: log::LevelFilter, scopes: &[LogScope]) {
+    if scopes.is_empty() {
+        builder.filter(None, level);
+    } else {
+        builder.filter(None, level.min(log::LevelFilter::Info));
+        for module in scopes.iter().flat_map(|s| s.modules()) {
+            builder.filter(Some(module), level);
+        }
+    }
+}
+
+/// Send each record to every logger that wants it
+struct Tee {
+    loggers: Vec<Box<dyn log::Log>>,
+}
+
+impl log::Log for Tee {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.loggers.iter().any(|l| l.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record) {
+        for logger in self.loggers.iter() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        for logger in self.loggers.iter() {
+            logger.flush();
+        }
+    }
+}
+
+struct FileLogger {
+    filter: env_logger::Logger,
+    file: std::sync::Mutex<std::io::LineWriter<std::fs::File>>,
+}
+
+impl log::Log for FileLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(
+                file,
+                "[{} {} {}] {}",
+                humantime::format_rfc3339_millis(std::time::SystemTime::now()),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Subsystems whose logging can be made more verbose on its own
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LogScope {
+    Graph,
+    Rewrite,
+    Remote,
+    Forge,
+    Config,
+}
+
+impl LogScope {
+    pub fn variants() -> [&'static str; 5] {
+        ["graph", "rewrite", "remote", "forge", "config"]
+    }
+
+    fn modules(self) -> &'static [&'static str] {
+        match self {
+            LogScope::Graph => &["git_stack::graph"],
+            LogScope::Rewrite => &[
+                "git_stack::git::commands",
+                "git_stack::git::repo",
+                "git_stack::stash",
+                "git_stack::stack",
+            ],
+            // Forges are remotes too
+            LogScope::Remote => &["git_stack::git::http", REMOTE_TARGET, FORGE_TARGET],
+            LogScope::Forge => &[FORGE_TARGET],
+            LogScope::Config => &["git_stack::config"],
+        }
+    }
+}
+
+impl std::str::FromStr for LogScope {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "graph" => Ok(LogScope::Graph),
+            "rewrite" => Ok(LogScope::Rewrite),
+            "remote" => Ok(LogScope::Remote),
+            "forge" => Ok(LogScope::Forge),
+            "config" => Ok(LogScope::Config),
+            _ => Err(format!("valid values: {}", Self::variants().join(", "))),
+        }
+    }
+}
+
+impl std::fmt::Display for LogScope {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            LogScope::Graph => "graph".fmt(f),
+            LogScope::Rewrite => "rewrite".fmt(f),
+            LogScope::Remote => "remote".fmt(f),
+            LogScope::Forge => "forge".fmt(f),
+            LogScope::Config => "config".fmt(f),
+        }
     }
 }
 
@@ -82,7 +82,7 @@ impl Stack {
             }
             None => 0,
         };
-        let last = last_path.as_deref().and_then(|p| Snapshot::load(p).ok());
+        let last = last_path.and_then(|p| Snapshot::load(p).ok());
         if last.as_ref() == Some(&snapshot) {
             let last_path = last_path.unwrap().to_owned();
             log::trace!("Reusing snapshot {}", last_path.display());
@@ -100,7 +100,7 @@ impl Stack {
                 let remove = len - capacity;
                 log::warn!("Too many snapshots, clearing {} oldest", remove);
                 for snapshot_path in &elems[0..remove] {
-                    if let Err(err) = std::fs::remove_file(&snapshot_path) {
+                    if let Err(err) = std::fs::remove_file(snapshot_path) {
                         log::trace!("Failed to remove {}: {}", snapshot_path.display(), err);
                     } else {
                         log::trace!("Removed {}", snapshot_path.display());
@@ -41,6 +41,69 @@ mod test_branches {
         );
     }
 
+    #[test]
+    fn test_with_ignore() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let ignore = BranchGlobs::new(vec!["feature*", "!feature2"]).unwrap();
+        let branches = Branches::with_ignore(repo.local_branches(), &ignore);
+        let mut names: Vec<_> = branches
+            .iter()
+            .flat_map(|(_, b)| b.iter().map(|b| b.name.as_str()))
+            .collect();
+        names.sort_unstable();
+
+        assert_eq!(
+            names,
+            ["base", "feature2", "initial", "master", "off_master"]
+        );
+    }
+
+    #[test]
+    fn test_unprotected_branches() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let branches = Branches::new(repo.local_branches());
+        let protected = branches.protected(&protect());
+        // The same branch graphed in two stacks
+        let stacks = [
+            branches.descendants(&repo, repo.resolve("base").unwrap().id),
+            branches,
+        ];
+        let result = unprotected_branches(&stacks, &protected);
+        let names: Vec<_> = result.iter().map(|b| b.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            ["base", "feature1", "feature2", "initial", "off_master"]
+        );
+    }
+
+    #[test]
+    fn test_unprotected_branches_same_commit() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        // Only the protected name is left out, not everything at its commit
+        let master = repo.find_local_branch("master").unwrap();
+        let mut alias = master.clone();
+        alias.name = "topic".to_owned();
+        let branches = Branches::new([master.clone(), alias]);
+        let protected = Branches::new([master]);
+        let result = unprotected_branches([&branches], &protected);
+        let names: Vec<_> = result.iter().map(|b| b.name.as_str()).collect();
+
+        assert_eq!(names, ["topic"]);
+    }
+
     #[test]
     fn test_descendants() {
         let mut repo = git_stack::git::InMemoryRepo::new();
@@ -0,0 +1,2678 @@
+//! Run `git-stack` end-to-end against throwaway repos
+
+use std::path::Path;
+use std::process::Command;
+
+/// `git`, isolated from the user's and system's config
+fn git(home: &Path, cwd: &Path, args: &[&str]) -> String {
+    let output = isolate(Command::new("git"), home)
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn git_stack(home: &Path, cwd: &Path, args: &[&str]) -> std::process::Output {
+    isolate(Command::new(env!("CARGO_BIN_EXE_git-stack")), home)
+        .args(args)
+        .current_dir(cwd)
+        .output()
+        .unwrap()
+}
+
+fn isolate(mut cmd: Command, home: &Path) -> Command {
+    cmd.env("HOME", home)
+        .env("XDG_CONFIG_HOME", home)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env_remove("GIT_AUTHOR_NAME")
+        .env_remove("GIT_AUTHOR_EMAIL")
+        .env_remove("GIT_COMMITTER_NAME")
+        .env_remove("GIT_COMMITTER_EMAIL");
+    cmd
+}
+
+fn commit_file(home: &Path, cwd: &Path, path: &str, content: &str, message: &str) {
+    std::fs::write(cwd.join(path), content).unwrap();
+    git(home, cwd, &["add", path]);
+    git(home, cwd, &["commit", "-q", "-m", message]);
+}
+
+/// `$HOME` under `temp`, with an identity
+fn home(temp: &Path) -> std::path::PathBuf {
+    let home = temp.join("home");
+    std::fs::create_dir_all(&home).unwrap();
+    // Unlike git, libgit2 ignores `GIT_COMMITTER_NAME` and friends, so both go by config
+    std::fs::write(
+        home.join(".gitconfig"),
+        "[user]\n\tname = Jane Doe\n\temail = jdoe@example.com\n",
+    )
+    .unwrap();
+    home
+}
+
+/// A repo with one commit on `main`
+fn init(home: &Path, repo: &Path) {
+    std::fs::create_dir_all(repo).unwrap();
+    git(home, repo, &["init", "-q", "-b", "main"]);
+    commit_file(home, repo, "shared.txt", "1\n", "Initial");
+}
+
+/// A clone of `origin` with `clean` and `conflict` branches off `main`, after which `origin`'s
+/// `main` moved on, changing the file `conflict` also changes
+fn stale_stacks(temp: &Path) -> std::path::PathBuf {
+    let home = home(temp);
+    let upstream = temp.join("upstream");
+    let origin = temp.join("origin.git");
+    let local = temp.join("local");
+
+    init(&home, &upstream);
+    git(
+        &home,
+        temp,
+        &["clone", "-q", "--bare", "upstream", "origin.git"],
+    );
+    git(&home, temp, &["clone", "-q", "origin.git", "local"]);
+
+    git(&home, &local, &["switch", "-q", "-c", "clean"]);
+    commit_file(&home, &local, "clean.txt", "1\n", "Clean change");
+    git(&home, &local, &["switch", "-q", "-c", "conflict", "main"]);
+    commit_file(&home, &local, "shared.txt", "2\n", "Conflicting change");
+    git(&home, &local, &["switch", "-q", "main"]);
+
+    git(
+        &home,
+        &upstream,
+        &["remote", "add", "origin", origin.to_str().unwrap()],
+    );
+    commit_file(&home, &upstream, "shared.txt", "3\n", "Upstream change");
+    git(&home, &upstream, &["push", "-q", "origin", "main"]);
+
+    local
+}
+
+#[test]
+fn bot_answers_confirmation() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["config", "stack.confirm", "always"]);
+
+    let output = git_stack(&home, &local, &["bot"]);
+    assert!(!output.status.success(), "the conflict needs a human");
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["restacked"], serde_json::json!(["clean"]));
+    assert_eq!(report["conflicts"][0]["branch"], "conflict");
+    assert_eq!(report["error"], serde_json::Value::Null);
+
+    let base = git(&home, &local, &["rev-parse", "origin/main"]);
+    let merge_base = git(&home, &local, &["merge-base", "origin/main", "clean"]);
+    assert_eq!(base, merge_base);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn bot_reports_errors() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["switch", "-q", "--detach", "main"]);
+
+    let output = git_stack(&home, &local, &["bot"]);
+    assert!(!output.status.success());
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["stale"][0]["onto"], "main");
+    assert_eq!(report["error"], "Must not be in a detached HEAD state.");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn max_rewrite_commits_needs_confirmation() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+    git(&home, &local, &["switch", "-q", "clean"]);
+    commit_file(&home, &local, "clean.txt", "2\n", "Another clean change");
+    git(&home, &local, &["fetch", "-q", "origin"]);
+    git(&home, &local, &["config", "stack.max-rewrite-commits", "1"]);
+    let before = git(&home, &local, &["rev-parse", "clean"]);
+
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("run with `--yes` to proceed"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(&home, &local, &["rev-parse", "clean"]), before);
+
+    let output = git_stack(&home, &local, &["--rebase", "--yes"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &local, &["merge-base", "origin/main", "clean"]),
+        git(&home, &local, &["rev-parse", "origin/main"])
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn max_rewrite_commits_allows_small_rewrites() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+    git(&home, &local, &["switch", "-q", "clean"]);
+    git(&home, &local, &["fetch", "-q", "origin"]);
+    git(&home, &local, &["config", "stack.max-rewrite-commits", "1"]);
+
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &local, &["merge-base", "origin/main", "clean"]),
+        git(&home, &local, &["rev-parse", "origin/main"])
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn confirm_always_waits_for_yes() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+    git(&home, &local, &["switch", "-q", "clean"]);
+    git(&home, &local, &["fetch", "-q", "origin"]);
+    git(&home, &local, &["config", "stack.confirm", "always"]);
+    let before = git(&home, &local, &["rev-parse", "clean"]);
+
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("run with `--yes` to proceed"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(&home, &local, &["rev-parse", "clean"]), before);
+
+    let output = git_stack(&home, &local, &["--rebase", "--yes"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &local, &["merge-base", "origin/main", "clean"]),
+        git(&home, &local, &["rev-parse", "origin/main"])
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn confirm_destructive_only_stops_drops() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    let upstream = temp.path().join("upstream");
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+    git(&home, &local, &["config", "stack.confirm", "destructive"]);
+
+    // Restacking alone goes ahead
+    git(&home, &local, &["switch", "-q", "clean"]);
+    git(&home, &local, &["fetch", "-q", "origin"]);
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &local, &["merge-base", "origin/main", "clean"]),
+        git(&home, &local, &["rev-parse", "origin/main"])
+    );
+
+    // Dropping a commit upstream squashed in waits
+    git(
+        &home,
+        &local,
+        &["switch", "-q", "-c", "feature", "origin/main"],
+    );
+    commit_file(&home, &local, "feature.txt", "1\n", "Squashed change");
+    git(&home, &local, &["branch", "squashed"]);
+    commit_file(&home, &local, "feature.txt", "2\n", "Kept change");
+    git(&home, &upstream, &["pull", "-q", "origin", "main"]);
+    commit_file(&home, &upstream, "feature.txt", "1\n", "Squash feature");
+    commit_file(&home, &upstream, "other.txt", "1\n", "Other change");
+    git(&home, &upstream, &["push", "-q", "origin", "main"]);
+    git(&home, &local, &["fetch", "-q", "origin"]);
+    let before = git(&home, &local, &["rev-parse", "feature"]);
+
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("run with `--yes` to proceed"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(&home, &local, &["rev-parse", "feature"]), before);
+
+    let output = git_stack(&home, &local, &["--rebase", "--yes"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(
+            &home,
+            &local,
+            &["log", "--format=%s", "origin/main..feature"]
+        ),
+        "Kept change\n"
+    );
+
+    temp.close().unwrap();
+}
+
+/// Send `requests` to `git stack serve --stdio`, returning the responses
+fn serve(home: &Path, repo: &Path, requests: &[serde_json::Value]) -> Vec<serde_json::Value> {
+    use std::io::Write;
+
+    let mut child = isolate(Command::new(env!("CARGO_BIN_EXE_git-stack")), home)
+        .args(["serve", "--stdio"])
+        .current_dir(repo)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for request in requests
+        .iter()
+        .chain(std::iter::once(&serde_json::json!({"method": "exit"})))
+    {
+        let content = request.to_string();
+        write!(
+            stdin,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )
+        .unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .split("Content-Length: ")
+        .filter(|message| !message.is_empty())
+        .map(|message| serde_json::from_str(message.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect()
+}
+
+#[test]
+fn serve_applies_only_when_confirmed() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["fetch", "-q"]);
+    git(&home, &local, &["switch", "-q", "clean"]);
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+    let before = git(&home, &local, &["rev-parse", "clean"]);
+
+    let responses = serve(
+        &home,
+        &local,
+        &[serde_json::json!({"id": 1, "method": "stack/rebase"})],
+    );
+    let result = &responses[0]["result"];
+    assert_eq!(result["applied"], false, "{}", result);
+    assert_ne!(result["plan"], serde_json::Value::Null, "{}", result);
+    assert_eq!(git(&home, &local, &["rev-parse", "clean"]), before);
+
+    let responses = serve(
+        &home,
+        &local,
+        &[serde_json::json!({"id": 2, "method": "stack/rebase", "params": {"yes": true}})],
+    );
+    let result = &responses[0]["result"];
+    assert_eq!(result["applied"], true, "{}", result);
+    let base = git(&home, &local, &["rev-parse", "origin/main"]);
+    let merge_base = git(&home, &local, &["merge-base", "origin/main", "clean"]);
+    assert_eq!(base, merge_base, "{}", result);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn non_interactive_reports_errors_once() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["switch", "-q", "--detach", "main"]);
+
+    let output = git_stack(&home, &local, &["--non-interactive", "--rebase"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let errors: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.contains("detached HEAD"))
+        .collect();
+    assert_eq!(errors.len(), 1, "{}", stderr);
+    let event: serde_json::Value = serde_json::from_str(errors[0]).unwrap();
+    assert_eq!(event["event"], "error");
+    assert_eq!(event["message"], "Must not be in a detached HEAD state.");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn fresh_base_only_when_remote_is_ahead() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    let upstream = temp.path().join("upstream");
+    git(
+        &home,
+        &local,
+        &["config", "stack.require-fresh-base", "error"],
+    );
+    git(&home, &local, &["switch", "-q", "clean"]);
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("out-of-date"), "{}", stderr);
+
+    // Behind what we fetched, like after a force-push, isn't worth pulling for
+    git(&home, &local, &["fetch", "-q"]);
+    git(
+        &home,
+        &upstream,
+        &["push", "-q", "--force", "origin", "HEAD~:main"],
+    );
+    let output = git_stack(&home, &local, &["--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn prefetch_updates_only_remote_tracking_branches() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    let upstream = temp.path().join("upstream");
+    let main = git(&home, &local, &["rev-parse", "main"]);
+
+    let output = git_stack(&home, &local, &["prefetch"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &local, &["rev-parse", "origin/main"]),
+        git(&home, &upstream, &["rev-parse", "main"])
+    );
+    assert_eq!(git(&home, &local, &["rev-parse", "main"]), main);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn protection_action_reports_protected_branches() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["branch", "-q", "-D", "conflict"]);
+    git(&home, &local, &["switch", "-q", "-c", "release", "clean"]);
+    commit_file(&home, &local, "release.txt", "1\n", "Release");
+    git(
+        &home,
+        &local,
+        &["config", "--add", "stack.protected-branch", "main"],
+    );
+    git(
+        &home,
+        &local,
+        &["config", "--add", "stack.protected-branch", "release"],
+    );
+    git(
+        &home,
+        &local,
+        &["config", "stack.protection-action", "error"],
+    );
+    let before = git(&home, &local, &["rev-parse", "clean"]);
+
+    let output = git_stack(&home, &local, &["--pull", "--stack", "all"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{}", stderr);
+    assert!(
+        stderr.contains("clean (under a protected branch), release (`stack.protected-branch`)"),
+        "{}",
+        stderr
+    );
+    assert_eq!(git(&home, &local, &["rev-parse", "clean"]), before);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn repair_metadata_skips_ignored_parents() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["switch", "-q", "-c", "backup/feature"]);
+    commit_file(&home, &repo, "backup.txt", "1\n", "Backup");
+    git(&home, &repo, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &repo, "feature.txt", "1\n", "Feature");
+    git(&home, &repo, &["config", "branch.feature.remote", "."]);
+    git(
+        &home,
+        &repo,
+        &["config", "branch.feature.merge", "refs/heads/gone"],
+    );
+    git(&home, &repo, &["config", "stack.ignore-branch", "backup/"]);
+
+    let output = git_stack(&home, &repo, &["repair-metadata"]);
+    assert!(output.status.success());
+    let merge = git(&home, &repo, &["config", "branch.feature.merge"]);
+    assert_eq!(merge.trim(), "refs/heads/main");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn adopt_skips_ignored() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    for (branch, parent) in [
+        ("feature", "main"),
+        ("backup/feature", "main"),
+        ("stacked", "backup/feature"),
+    ] {
+        git(&home, &repo, &["branch", branch, "main"]);
+        graphite_parent(&home, &repo, branch, parent);
+    }
+    git(&home, &repo, &["config", "stack.ignore-branch", "backup/"]);
+
+    let output = git_stack(&home, &repo, &["adopt", "--from", "graphite"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "feature is stacked on main\n"
+    );
+    let tracked = git(
+        &home,
+        &repo,
+        &["config", "--get-regexp", r"^branch\..*\.merge$"],
+    );
+    assert_eq!(tracked, "branch.feature.merge refs/heads/main\n");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn adopt_protects_trunk() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["branch", "develop", "main"]);
+    git(&home, &repo, &["branch", "feature", "develop"]);
+    graphite_parent(&home, &repo, "feature", "develop");
+    let trunk_config = repo.join(".git/.graphite_repo_config");
+    std::fs::write(&trunk_config, r#"{"trunk": "develop"}"#).unwrap();
+
+    let output = git_stack(&home, &repo, &["adopt", "--from", "graphite"]);
+    assert!(output.status.success());
+    let protected = git(
+        &home,
+        &repo,
+        &["config", "--get-all", "stack.protected-branch"],
+    );
+    assert_eq!(protected, "develop\n");
+
+    // Already protected, like `main` is by default
+    git(
+        &home,
+        &repo,
+        &["config", "--unset-all", "stack.protected-branch"],
+    );
+    std::fs::write(&trunk_config, r#"{"trunk": "main"}"#).unwrap();
+    let output = git_stack(&home, &repo, &["adopt", "--from", "graphite"]);
+    assert!(output.status.success());
+    let protected = isolate(Command::new("git"), &home)
+        .args(["config", "--get-all", "stack.protected-branch"])
+        .current_dir(&repo)
+        .output()
+        .unwrap();
+    assert!(protected.stdout.is_empty());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn adopt_keeps_existing_upstreams() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    for (branch, upstream) in [("tracking", "main"), ("elsewhere", "release")] {
+        git(&home, &repo, &["branch", branch, "main"]);
+        git(
+            &home,
+            &repo,
+            &["config", &format!("branch.{}.remote", branch), "."],
+        );
+        git(
+            &home,
+            &repo,
+            &[
+                "config",
+                &format!("branch.{}.merge", branch),
+                &format!("refs/heads/{}", upstream),
+            ],
+        );
+        graphite_parent(&home, &repo, branch, "main");
+    }
+
+    let output = git_stack(&home, &repo, &["adopt", "--from", "graphite"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Leaving elsewhere tracking `release`, Graphite stacks it on main"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("Adopted 0 of 2"), "{}", stderr);
+    let merge = git(&home, &repo, &["config", "branch.elsewhere.merge"]);
+    assert_eq!(merge, "refs/heads/release\n");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn adopt_warns_of_restacking() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["switch", "-q", "-c", "parent"]);
+    commit_file(&home, &repo, "parent.txt", "1\n", "Parent");
+    let revision = git(&home, &repo, &["rev-parse", "parent"]);
+    let revision = revision.trim();
+    git(&home, &repo, &["switch", "-q", "-c", "child"]);
+    commit_file(&home, &repo, "child.txt", "1\n", "Child");
+    // Amended since, leaving `child` on the old version
+    git(&home, &repo, &["switch", "-q", "parent"]);
+    git(
+        &home,
+        &repo,
+        &["commit", "-q", "--amend", "-m", "Parent, amended"],
+    );
+    graphite_parent(&home, &repo, "parent", "main");
+    graphite_metadata(
+        &home,
+        &repo,
+        "child",
+        &format!(
+            r#"{{"parentBranchName": "parent", "parentBranchRevision": "{}"}}"#,
+            revision
+        ),
+    );
+
+    let output = git_stack(&home, &repo, &["adopt", "--from", "graphite"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("git rebase --onto parent {} child", revision)),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("parent (on main)"), "{}", stderr);
+
+    temp.close().unwrap();
+}
+
+/// Record `parent` as `branch`'s parent, the way Graphite does
+fn graphite_parent(home: &Path, repo: &Path, branch: &str, parent: &str) {
+    graphite_metadata(
+        home,
+        repo,
+        branch,
+        &format!(r#"{{"parentBranchName": "{}"}}"#, parent),
+    );
+}
+
+fn graphite_metadata(home: &Path, repo: &Path, branch: &str, metadata_json: &str) {
+    let metadata = repo.join("metadata.json");
+    std::fs::write(&metadata, metadata_json).unwrap();
+    let id = git(
+        home,
+        repo,
+        &["hash-object", "-w", metadata.to_str().unwrap()],
+    );
+    std::fs::remove_file(&metadata).unwrap();
+    git(
+        home,
+        repo,
+        &[
+            "update-ref",
+            &format!("refs/branch-metadata/{}", branch),
+            id.trim(),
+        ],
+    );
+}
+
+#[test]
+fn env_overrides_config() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["config", "stack.show-format", "commits"]);
+    git(&home, &repo, &["config", "stack.push-remote", "origin"]);
+    git(&home, &repo, &["config", "stack.protected-branch", "main"]);
+
+    let output = isolate(Command::new(env!("CARGO_BIN_EXE_git-stack")), &home)
+        .env("GIT_STACK_FORMAT", "branches")
+        .env("GIT_STACK_PUSH_REMOTE", "fork")
+        .env("GIT_STACK_PROTECTED", "release,v*")
+        .args(["--dump-config", "-"])
+        .current_dir(&repo)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let config = String::from_utf8_lossy(&output.stdout);
+    assert!(config.contains("\tshow-format=branches\n"), "{}", config);
+    assert!(!config.contains("\tshow-format=commits\n"), "{}", config);
+    assert!(config.contains("\tpush-remote=fork\n"), "{}", config);
+    for branch in ["main", "release", "v*"] {
+        assert!(
+            config.contains(&format!("\tprotected-branch={}\n", branch)),
+            "{}",
+            config
+        );
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn unarchive_restores_config() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["branch", "feature", "main"]);
+    git(
+        &home,
+        &repo,
+        &["branch", "--set-upstream-to=main", "feature"],
+    );
+    git(
+        &home,
+        &repo,
+        &["config", "--add", "branch.feature.stack-depends", "api"],
+    );
+    git(
+        &home,
+        &repo,
+        &["config", "--add", "branch.feature.stack-depends", "db"],
+    );
+    let before = git(
+        &home,
+        &repo,
+        &["config", "--get-regexp", r"^branch\.feature\."],
+    );
+
+    let output = git_stack(&home, &repo, &["archive", "feature"]);
+    assert!(output.status.success());
+    let archived = isolate(Command::new("git"), &home)
+        .args(["config", "--get-regexp", r"^branch\.feature\."])
+        .current_dir(&repo)
+        .output()
+        .unwrap();
+    assert!(archived.stdout.is_empty());
+
+    let output = git_stack(&home, &repo, &["unarchive", "feature"]);
+    assert!(output.status.success());
+    let after = git(
+        &home,
+        &repo,
+        &["config", "--get-regexp", r"^branch\.feature\."],
+    );
+    assert_eq!(before, after);
+    let leftover = git(
+        &home,
+        &repo,
+        &["for-each-ref", "refs/stack-archive-config/"],
+    );
+    assert_eq!(leftover, "");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn range_diff_check_ignores_new_neighbors() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    commit_file(&home, &repo, "lines.txt", "a\nb\nc\nd\ne\nf\n", "Lines");
+    git(&home, &repo, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &repo, "lines.txt", "a\nb\nc\nD\ne\nf\n", "Feature");
+    git(&home, &repo, &["switch", "-q", "main"]);
+    commit_file(&home, &repo, "lines.txt", "a\nB\nc\nd\ne\nf\n", "Neighbor");
+    git(&home, &repo, &["switch", "-q", "feature"]);
+
+    let output = git_stack(&home, &repo, &["--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let lines = std::fs::read_to_string(repo.join("lines.txt")).unwrap();
+    assert_eq!(lines, "a\nB\nc\nD\ne\nf\n");
+
+    let output = git_stack(&home, &repo, &["range-diff", "--check"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(output.stdout.is_empty());
+
+    // Drift, as if a conflict was resolved by hand
+    std::fs::write(repo.join("lines.txt"), "a\nB\nc\nX\ne\nf\n").unwrap();
+    git(
+        &home,
+        &repo,
+        &["commit", "-q", "-a", "--amend", "--no-edit"],
+    );
+    let output = git_stack(&home, &repo, &["range-diff", "--check"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("feature"), "{}", stdout);
+    assert!(stdout.contains("changed content Feature"), "{}", stdout);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn range_diff_since_rewrites() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &repo, "feature.txt", "1\n", "Feature");
+    let short = |rev: &str| {
+        git(&home, &repo, &["rev-parse", "--short=7", rev])
+            .trim()
+            .to_owned()
+    };
+    let original = short("feature");
+    let mut rewritten = Vec::new();
+    for main in ["2", "3"] {
+        git(&home, &repo, &["switch", "-q", "main"]);
+        commit_file(&home, &repo, "main.txt", main, "Main");
+        git(&home, &repo, &["switch", "-q", "feature"]);
+        let output = git_stack(&home, &repo, &["--rebase"]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        rewritten.push(short("feature"));
+    }
+
+    let output = git_stack(&home, &repo, &["range-diff"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!("feature ({}..{})\n", rewritten[0], rewritten[1])),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains(" = "), "the patch is unchanged: {}", stdout);
+
+    let output = git_stack(&home, &repo, &["range-diff", "--since", "2"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!("feature ({}..{})\n", original, rewritten[1])),
+        "{}",
+        stdout
+    );
+
+    temp.close().unwrap();
+}
+
+/// `stack.forge-command` that logs each operation and its request to `forge.log` in `dir`,
+/// numbering pull requests as they are opened
+fn logging_forge(home: &Path, repo: &Path, dir: &Path) -> std::path::PathBuf {
+    let log = dir.join("forge.log");
+    let script = dir.join("forge.sh");
+    std::fs::write(
+        &script,
+        format!(
+            r#"log='{}'
+printf '%s ' "$1" >> "$log"
+cat >> "$log"
+if [ "$1" = create-pr ]; then
+  n=$(grep -c '^create-pr' "$log")
+  printf '{{"number": %s, "url": "https://example.com/%s"}}' "$n" "$n"
+fi
+if [ "$1" = get-pr-state ]; then
+  printf '{{"state": "merged"}}'
+fi
+"#,
+            log.display()
+        ),
+    )
+    .unwrap();
+    git(
+        home,
+        repo,
+        &[
+            "config",
+            "stack.forge-command",
+            &format!("sh '{}'", script.display()),
+        ],
+    );
+    log
+}
+
+#[test]
+fn land_enqueues_then_restacks() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let upstream = temp.path().join("upstream");
+    init(&home, &upstream);
+    git(
+        &home,
+        temp.path(),
+        &["clone", "-q", "--bare", "upstream", "origin.git"],
+    );
+    git(&home, temp.path(), &["clone", "-q", "origin.git", "local"]);
+    let local = temp.path().join("local");
+    git(&home, &local, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &local, "feature.txt", "1\n", "Add feature");
+    git(&home, &local, &["switch", "-q", "-c", "stacked"]);
+    commit_file(&home, &local, "stacked.txt", "1\n", "Add stacked");
+    git(
+        &home,
+        &local,
+        &["push", "-q", "origin", "feature", "stacked"],
+    );
+    git(&home, &local, &["config", "branch.feature.stack-pr", "1"]);
+    git(&home, &local, &["config", "branch.stacked.stack-pr", "2"]);
+    let log = logging_forge(&home, &local, temp.path());
+    // What the merge queue will have done by the time it reports the pull request merged
+    let squashed = git(
+        &home,
+        &local,
+        &[
+            "commit-tree",
+            "feature^{tree}",
+            "-p",
+            "origin/main",
+            "-m",
+            "Add feature (#1)",
+        ],
+    );
+    git(
+        &home,
+        &local,
+        &[
+            "push",
+            "-q",
+            "origin",
+            &format!("{}:refs/heads/main", squashed.trim()),
+        ],
+    );
+
+    let output = git_stack(&home, &local, &["land"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let requests: Vec<(String, serde_json::Value)> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (operation, request) = line.split_once(' ').unwrap();
+            (operation.to_owned(), serde_json::from_str(request).unwrap())
+        })
+        .collect();
+    let operations: Vec<_> = requests
+        .iter()
+        .map(|(operation, request)| {
+            (
+                operation.as_str(),
+                request["number"].clone(),
+                request["base"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        operations,
+        [
+            ("enqueue", serde_json::json!(1), serde_json::Value::Null),
+            (
+                "get-pr-state",
+                serde_json::json!(1),
+                serde_json::Value::Null
+            ),
+            (
+                "update-base",
+                serde_json::json!(2),
+                serde_json::json!("main")
+            ),
+        ]
+    );
+    // Restacked onto the squashed commit
+    assert_eq!(
+        git(&home, &local, &["rev-parse", "stacked~"]),
+        git(&home, &local, &["rev-parse", "origin/main"])
+    );
+    assert_eq!(
+        git(
+            &home,
+            &local,
+            &["log", "--format=%s", "origin/main..stacked"]
+        ),
+        "Add stacked\n"
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn submit_requests_codeowners() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let upstream = temp.path().join("upstream");
+    init(&home, &upstream);
+    std::fs::create_dir_all(upstream.join(".github")).unwrap();
+    commit_file(
+        &home,
+        &upstream,
+        ".github/CODEOWNERS",
+        "* @org/everyone\n*.rs @rustacean\n/docs/ @writer\n",
+        "Owners",
+    );
+    git(
+        &home,
+        temp.path(),
+        &["clone", "-q", "--bare", "upstream", "origin.git"],
+    );
+    git(&home, temp.path(), &["clone", "-q", "origin.git", "local"]);
+    let local = temp.path().join("local");
+    git(&home, &local, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &local, "lib.rs", "1\n", "Add lib");
+    git(&home, &local, &["switch", "-q", "-c", "stacked"]);
+    std::fs::create_dir_all(local.join("docs")).unwrap();
+    commit_file(&home, &local, "docs/guide.md", "1\n", "WIP: Document lib");
+    commit_file(&home, &local, "docs/faq.md", "1\n", "Answer questions");
+    git(
+        &home,
+        &local,
+        &["push", "-q", "origin", "feature", "stacked"],
+    );
+    let log = logging_forge(&home, &local, temp.path());
+
+    let output = git_stack(&home, &local, &["submit"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let requests: Vec<(String, serde_json::Value)> = std::fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (operation, request) = line.split_once(' ').unwrap();
+            (operation.to_owned(), serde_json::from_str(request).unwrap())
+        })
+        .collect();
+    let operations: Vec<_> = requests
+        .iter()
+        .map(|(operation, request)| {
+            (
+                operation.as_str(),
+                request["head"]
+                    .as_str()
+                    .or_else(|| request["number"].as_str()),
+                request["base"].clone(),
+                request["title"].clone(),
+                request["draft"].clone(),
+                request["number"].clone(),
+                request["reviewers"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        operations,
+        [
+            (
+                "create-pr",
+                Some("feature"),
+                serde_json::json!("main"),
+                serde_json::json!("Add lib"),
+                serde_json::json!(false),
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+            ),
+            (
+                "request-reviewers",
+                None,
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+                serde_json::json!(1),
+                serde_json::json!(["@rustacean"]),
+            ),
+            (
+                "create-pr",
+                Some("stacked"),
+                serde_json::json!("feature"),
+                serde_json::json!("stacked"),
+                serde_json::json!(true),
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+            ),
+            (
+                "request-reviewers",
+                None,
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+                serde_json::Value::Null,
+                serde_json::json!(2),
+                serde_json::json!(["@writer"]),
+            ),
+        ]
+    );
+    let recorded = git(&home, &local, &["config", "branch.stacked.stack-pr"]);
+    assert_eq!(recorded, "2\n");
+
+    // Already open
+    std::fs::remove_file(&log).unwrap();
+    git(&home, &local, &["switch", "-q", "-c", "more", "main"]);
+    commit_file(&home, &local, "more.rs", "1\n", "More");
+    git(&home, &local, &["push", "-q", "origin", "more"]);
+    git(&home, &local, &["config", "stack.stack", "all"]);
+    let output = git_stack(&home, &local, &["submit", "--no-assign"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let log = std::fs::read_to_string(&log).unwrap();
+    assert_eq!(log.lines().count(), 1, "{}", log);
+    assert!(log.starts_with("create-pr "), "{}", log);
+    assert!(log.contains(r#""head":"more""#), "{}", log);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn submit_refuses_oversized_branches() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let upstream = temp.path().join("upstream");
+    init(&home, &upstream);
+    git(
+        &home,
+        temp.path(),
+        &["clone", "-q", "--bare", "upstream", "origin.git"],
+    );
+    git(&home, temp.path(), &["clone", "-q", "origin.git", "local"]);
+    let local = temp.path().join("local");
+    git(
+        &home,
+        &local,
+        &["config", "stack.max-commits-per-branch", "1"],
+    );
+    git(
+        &home,
+        &local,
+        &["config", "stack.max-commits-action", "error"],
+    );
+    git(&home, &local, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &local, "lib.rs", "1\n", "Add lib");
+    git(&home, &local, &["switch", "-q", "-c", "stacked"]);
+    commit_file(&home, &local, "a.rs", "1\n", "Add a");
+    commit_file(&home, &local, "b.rs", "1\n", "Add b");
+    git(
+        &home,
+        &local,
+        &["push", "-q", "origin", "feature", "stacked"],
+    );
+    let log = logging_forge(&home, &local, temp.path());
+
+    let output = git_stack(&home, &local, &["submit", "--no-assign"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("stacked (2)"), "{}", stderr);
+    // Not even the branch within the limit
+    assert!(!log.exists());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn submit_links_issues() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let upstream = temp.path().join("upstream");
+    init(&home, &upstream);
+    git(
+        &home,
+        temp.path(),
+        &["clone", "-q", "--bare", "upstream", "origin.git"],
+    );
+    git(&home, temp.path(), &["clone", "-q", "origin.git", "local"]);
+    let local = temp.path().join("local");
+    git(
+        &home,
+        &local,
+        &["config", "stack.issue-pattern", "[A-Z]+-[0-9]+"],
+    );
+    git(
+        &home,
+        &local,
+        &[
+            "config",
+            "stack.issue-url",
+            "https://issues.example.com/browse/{}",
+        ],
+    );
+    git(&home, &local, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &local, "lib.rs", "1\n", "PROJ-12: Add lib");
+    git(&home, &local, &["push", "-q", "origin", "feature"]);
+    let log = logging_forge(&home, &local, temp.path());
+
+    let output = git_stack(&home, &local, &["submit", "--no-assign"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let log = std::fs::read_to_string(&log).unwrap();
+    let (operation, request) = log.lines().next().unwrap().split_once(' ').unwrap();
+    assert_eq!(operation, "create-pr");
+    let request: serde_json::Value = serde_json::from_str(request).unwrap();
+    let body = request["body"].as_str().unwrap();
+    assert!(
+        body.contains("[PROJ-12](https://issues.example.com/browse/PROJ-12)"),
+        "{}",
+        body
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn push_retries_when_base_advances() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let upstream = temp.path().join("upstream");
+    init(&home, &upstream);
+    git(
+        &home,
+        temp.path(),
+        &["clone", "-q", "--bare", "upstream", "origin.git"],
+    );
+    // Like a forge requiring branches to be up to date with `main`
+    std::fs::write(
+        temp.path().join("origin.git/hooks/pre-receive"),
+        r#"#!/bin/sh
+main=$(git rev-parse refs/heads/main)
+while read old new ref; do
+  [ "$ref" = refs/heads/main ] && continue
+  git merge-base --is-ancestor "$main" "$new" || { echo "$ref is behind main" >&2; exit 1; }
+done
+"#,
+    )
+    .unwrap();
+    git(&home, temp.path(), &["clone", "-q", "origin.git", "local"]);
+    let local = temp.path().join("local");
+    git(&home, &local, &["switch", "-q", "-c", "feature"]);
+    commit_file(&home, &local, "feature.txt", "1\n", "Feature");
+    git(&home, &local, &["push", "-q", "-u", "origin", "feature"]);
+    commit_file(&home, &local, "feature.txt", "2\n", "More feature");
+
+    // `main` moves on after we've pulled, just as we are pushing
+    std::fs::write(
+        local.join(".git/hooks/pre-push"),
+        format!(
+            r#"#!/bin/sh
+[ -e ../advanced ] && exit 0
+touch ../advanced
+unset GIT_DIR GIT_WORK_TREE
+cd '{}'
+echo 2 > shared.txt
+git commit -q -am Advance
+git push -q ../origin.git main
+"#,
+            upstream.display()
+        ),
+    )
+    .unwrap();
+    for hook in ["origin.git/hooks/pre-receive", "local/.git/hooks/pre-push"] {
+        use std::os::unix::fs::PermissionsExt;
+        let hook = temp.path().join(hook);
+        std::fs::set_permissions(hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    git(&home, &local, &["config", "stack.push-retries", "1"]);
+
+    let output = git_stack(&home, &local, &["--pull", "--push"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("retrying (1/1)"), "{}", stderr);
+    let main = git(&home, &upstream, &["rev-parse", "main"]);
+    git(
+        &home,
+        temp.path().join("origin.git").as_path(),
+        &["merge-base", "--is-ancestor", main.trim(), "feature"],
+    );
+
+    temp.close().unwrap();
+}
+
+/// Import `tests/fixtures/stgit/<layout>` into a fresh repo, as `(author, message)` per patch
+fn import_stgit(layout: &str) -> Vec<(String, String)> {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/stgit")
+        .join(layout);
+
+    let output = git_stack(
+        &home,
+        &repo,
+        &["import", "--stgit", fixture.to_str().unwrap()],
+    );
+    assert!(
+        output.status.success(),
+        "{}: {}",
+        layout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &repo, &["show", "update-shared:shared.txt"]),
+        "2\n",
+        "{}",
+        layout
+    );
+    assert_eq!(
+        git(&home, &repo, &["show", "add-notes:notes.txt"]),
+        "note\n",
+        "{}",
+        layout
+    );
+    assert_eq!(
+        git(&home, &repo, &["rev-parse", "add-notes~"]),
+        git(&home, &repo, &["rev-parse", "update-shared"]),
+        "{}",
+        layout
+    );
+    let patches = ["update-shared", "add-notes"]
+        .iter()
+        .map(|branch| {
+            let author = git(&home, &repo, &["log", "-1", "--format=%an <%ae>", branch]);
+            let message = git(&home, &repo, &["log", "-1", "--format=%B", branch]);
+            (author.trim().to_owned(), message.trim().to_owned())
+        })
+        .collect();
+
+    temp.close().unwrap();
+    patches
+}
+
+#[test]
+fn import_stgit_export() {
+    let expected = [
+        (
+            "Jane Roe <jroe@example.com>".to_owned(),
+            "Update shared\n\nExplain why it changed.".to_owned(),
+        ),
+        (
+            "Richard Roe <rroe@example.com>".to_owned(),
+            "Add notes".to_owned(),
+        ),
+    ];
+    assert_eq!(import_stgit("stg-export"), expected);
+    assert_eq!(import_stgit("git-stack-export"), expected);
+}
+
+#[test]
+fn import_quilt() {
+    assert_eq!(
+        import_stgit("quilt"),
+        [
+            (
+                "Jane Roe <jroe@example.com>".to_owned(),
+                "Update shared\n\nExplain why it changed.".to_owned(),
+            ),
+            // No author, so it is the committer's
+            (
+                "Jane Doe <jdoe@example.com>".to_owned(),
+                "Add notes".to_owned(),
+            ),
+        ]
+    );
+}
+
+#[test]
+fn am_decodes_mime() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    let mbox = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/am/series.mbox");
+
+    let output = git_stack(
+        &home,
+        &repo,
+        &["am", "--branch", "resume", mbox.to_str().unwrap()],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Base64 patch
+    assert_eq!(
+        git(&home, &repo, &["show", "resume:shared.txt"]),
+        "1\n2\nécrire trois\n"
+    );
+    assert_eq!(
+        git(
+            &home,
+            &repo,
+            &[
+                "log",
+                "--format=%an <%ae> %ad",
+                "--date=iso",
+                "main..resume"
+            ]
+        ),
+        "Renée Dubois <renee@example.com> 2020-03-03 10:00:02 +0100\n\
+         Renée Dubois <renee@example.com> 2020-03-03 10:00:01 +0100\n"
+    );
+    // Encoded-word subject, with the signature trimmed from the base64 body
+    assert_eq!(
+        git(&home, &repo, &["log", "-1", "--format=%B", "resume"]),
+        "Écrire trois\n\n\
+         Write \"three\" in French, with an accent: é.\n\n\
+         Message-Id: <2.1583226000.git.renee@example.com>\n\n"
+    );
+    // Quoted-printable body, with soft line breaks
+    assert_eq!(
+        git(&home, &repo, &["log", "-1", "--format=%B", "resume~"]),
+        "Count to two\n\n\
+         A résumé needs a line long enough that the mailer wraps it with a soft line break, \
+         and a=b.\n\n\
+         Signed-off-by: Renée Dubois <renee@example.com>\n\
+         Message-Id: <1.1583226000.git.renee@example.com>\n\n"
+    );
+    // Cover letter
+    assert_eq!(
+        git(&home, &repo, &["config", "branch.resume.description"]),
+        "Résumé support\n\nTeach shared.txt about résumés, in two steps.\n\n"
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn sha256_repos_are_explained() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    std::fs::create_dir_all(&repo).unwrap();
+    git(
+        &home,
+        &repo,
+        &["init", "-q", "-b", "main", "--object-format=sha256"],
+    );
+    commit_file(&home, &repo, "shared.txt", "1\n", "Initial");
+
+    let output = git_stack(&home, &repo, &[]);
+    assert_eq!(output.status.code(), Some(64));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("SHA-256 repositories aren't supported yet"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("unsupported extension"), "{}", stderr);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn reftable_repos_are_explained() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    // What `git init --ref-format=reftable` records, for gits too old to have it
+    git(
+        &home,
+        &repo,
+        &["config", "core.repositoryformatversion", "1"],
+    );
+    git(
+        &home,
+        &repo,
+        &["config", "extensions.refstorage", "reftable"],
+    );
+
+    let output = git_stack(&home, &repo, &[]);
+    assert_eq!(output.status.code(), Some(64));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("reftable repositories aren't supported yet"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("git refs migrate --ref-format=files"),
+        "{}",
+        stderr
+    );
+
+    temp.close().unwrap();
+}
+
+/// A `feature` branch off `main` with the given commit messages
+fn conventional_commits(
+    temp: &Path,
+    messages: &[&str],
+) -> (std::path::PathBuf, std::path::PathBuf) {
+    let home = home(temp);
+    let repo = temp.join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["switch", "-q", "-c", "feature"]);
+    for (i, message) in messages.iter().enumerate() {
+        commit_file(&home, &repo, "feature.txt", &format!("{}\n", i), message);
+    }
+    (home, repo)
+}
+
+#[test]
+fn changelog_groups_by_type() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (home, repo) = conventional_commits(
+        temp.path(),
+        &[
+            "feat: Add a",
+            "fix(core): Fix b",
+            "feat!: Drop c",
+            "chore: Tidy",
+            "fixup! feat: Add a",
+            "WIP: Stuff",
+            "Skip me\n\nChangelog: skip",
+            "Harden d\n\nChangelog: Security",
+        ],
+    );
+
+    let output = git_stack(&home, &repo, &["changelog", "--output", "CHANGELOG.md"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(repo.join("CHANGELOG.md")).unwrap(),
+        "\
+#### Features
+
+- Add a
+- **Breaking:** Drop c
+
+#### Fixes
+
+- Fix b
+
+#### Security
+
+- Harden d
+
+#### Other
+
+- Tidy
+"
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn stats_estimate_version_impact() {
+    for (messages, impact) in [
+        (&["fix: Fix a", "docs: Explain b"][..], "patch"),
+        (&["fix: Fix a", "feat: Add b"][..], "minor"),
+        (
+            &["feat: Add a", "fix: Drop b\n\nBREAKING CHANGE: b is gone"][..],
+            "major",
+        ),
+        (&["feat!: Drop a"][..], "major"),
+    ] {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let (home, repo) = conventional_commits(temp.path(), messages);
+
+        let output = git_stack(&home, &repo, &["stats"]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!(
+                "main: 1 branches, {} commits, {} impact\n",
+                messages.len(),
+                impact
+            )
+        );
+
+        let responses = serve(
+            &home,
+            &repo,
+            &[serde_json::json!({"id": 1, "method": "stack/show"})],
+        );
+        assert_eq!(responses[0]["result"]["stacks"][0]["impact"], impact);
+
+        temp.close().unwrap();
+    }
+}
+
+#[test]
+fn cleanup_deletes_remote_branches() {
+    for (delete_remote, deleted) in [("always", true), ("ask", false), ("never", false)] {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let local = stale_stacks(temp.path());
+        let home = temp.path().join("home");
+        let upstream = temp.path().join("upstream");
+        let origin = temp.path().join("origin.git");
+        git(&home, &local, &["branch", "other", "main"]);
+        git(&home, &local, &["push", "-q", "origin", "clean", "other"]);
+        git(&home, &local, &["branch", "-q", "-D", "other"]);
+        git(
+            &home,
+            &local,
+            &["config", "stack.delete-remote", delete_remote],
+        );
+        git(&home, &upstream, &["fetch", "-q", "origin", "clean"]);
+        git(
+            &home,
+            &upstream,
+            &["merge", "-q", "--no-edit", "FETCH_HEAD"],
+        );
+        git(
+            &home,
+            &upstream,
+            &["push", "-q", "origin", "main", ":other"],
+        );
+        git(
+            &home,
+            &local,
+            &["pull", "-q", "--ff-only", "origin", "main"],
+        );
+
+        let output = git_stack(&home, &local, &["cleanup"]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(git(&home, &local, &["branch", "--list", "clean"]), "");
+        assert_eq!(
+            git(&home, &origin, &["branch", "--list", "clean"]).is_empty(),
+            deleted,
+            "{}",
+            delete_remote
+        );
+        // Deleted by someone else, so pruned unless `stack.delete-remote` is "never"
+        assert_eq!(
+            git(&home, &local, &["branch", "-r", "--list", "origin/other"]).is_empty(),
+            delete_remote != "never",
+            "{}",
+            delete_remote
+        );
+
+        temp.close().unwrap();
+    }
+}
+
+#[test]
+fn show_columns() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    std::fs::write(repo.join(".mailmap"), "Grace Hopper <ada@example.com>\n").unwrap();
+    git(&home, &repo, &["add", ".mailmap"]);
+    let output = isolate(Command::new("git"), &home)
+        .args(["commit", "-q", "-m", "Add mailmap"])
+        .env("GIT_AUTHOR_NAME", "Ada Lovelace")
+        .env("GIT_AUTHOR_EMAIL", "ada@example.com")
+        .env("GIT_COMMITTER_DATE", "2000-01-01T00:00:00")
+        .current_dir(&repo)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    git(&home, &repo, &["config", "stack.show-format", "commits"]);
+    git(
+        &home,
+        &repo,
+        &["config", "stack.show-columns", "age,author,sha=10"],
+    );
+
+    let output = git_stack(&home, &repo, &[]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let id = git(&home, &repo, &["rev-parse", "--short=10", "HEAD"]);
+    assert!(
+        stdout.contains(&format!(" years ago GH {} Add mailmap\n", id.trim())),
+        "{}",
+        stdout
+    );
+
+    git(&home, &repo, &["config", "stack.show-columns", "sha=4"]);
+    let output = git_stack(&home, &repo, &[]);
+    let id = git(&home, &repo, &["rev-parse", "--short=4", "HEAD"]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout)
+            .contains(&format!("main (no remote) {} Add mailmap\n", id.trim())),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    temp.close().unwrap();
+}
+
+/// `a` (with `a2` stacked on it) and `b` off `main`, which then moved on
+fn two_stacks(temp: &Path) -> (std::path::PathBuf, std::path::PathBuf) {
+    let home = home(temp);
+    let repo = temp.join("repo");
+    init(&home, &repo);
+    for branch in ["a", "b"] {
+        git(&home, &repo, &["switch", "-q", "-c", branch, "main"]);
+        commit_file(
+            &home,
+            &repo,
+            &format!("{}.txt", branch),
+            "1\n",
+            &format!("Add {}", branch),
+        );
+    }
+    git(&home, &repo, &["switch", "-q", "-c", "a2", "a"]);
+    commit_file(&home, &repo, "a2.txt", "1\n", "Add a2");
+    git(&home, &repo, &["switch", "-q", "main"]);
+    commit_file(&home, &repo, "main.txt", "1\n", "Main change");
+    (home, repo)
+}
+
+#[test]
+fn label_selects_stacks() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (home, repo) = two_stacks(temp.path());
+    git(&home, &repo, &["switch", "-q", "b"]);
+
+    let output = git_stack(&home, &repo, &["label", "add", "perf", "a"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(
+            &home,
+            &repo,
+            &["config", "--get-all", "branch.a.stack-label"]
+        ),
+        "perf\n"
+    );
+    let output = git_stack(&home, &repo, &["label", "list"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a: perf\n");
+
+    let b = git(&home, &repo, &["rev-parse", "b"]);
+    let output = git_stack(&home, &repo, &["--label", "perf", "--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for branch in ["a", "a2"] {
+        assert_eq!(
+            git(&home, &repo, &["merge-base", "main", branch]),
+            git(&home, &repo, &["rev-parse", "main"])
+        );
+    }
+    assert_eq!(git(&home, &repo, &["rev-parse", "b"]), b);
+
+    let output = git_stack(&home, &repo, &["label", "remove", "perf", "a"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = git_stack(&home, &repo, &["label", "list"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn focus_pins_the_stack() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (home, repo) = two_stacks(temp.path());
+    git(&home, &repo, &["switch", "-q", "b"]);
+
+    let output = git_stack(&home, &repo, &["focus", "a2"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = git_stack(&home, &repo, &["focus"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "a2\n");
+
+    let b = git(&home, &repo, &["rev-parse", "b"]);
+    let output = git_stack(&home, &repo, &["--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for branch in ["a", "a2"] {
+        assert_eq!(
+            git(&home, &repo, &["merge-base", "main", branch]),
+            git(&home, &repo, &["rev-parse", "main"])
+        );
+    }
+    assert_eq!(git(&home, &repo, &["rev-parse", "b"]), b);
+
+    let output = git_stack(&home, &repo, &["focus", "--clear"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!repo.join(".git/stack/focus").exists());
+    let output = git_stack(&home, &repo, &["--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &repo, &["merge-base", "main", "b"]),
+        git(&home, &repo, &["rev-parse", "main"])
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn recurse_runs_in_each_repo() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let workspace = temp.path().join("workspace");
+    let repos = ["one", "nested/two", ".hidden/three"];
+    for repo in repos {
+        let repo = workspace.join(repo);
+        init(&home, &repo);
+        git(&home, &repo, &["switch", "-q", "-c", "feature"]);
+        commit_file(&home, &repo, "feature.txt", "1\n", "Feature change");
+        git(&home, &repo, &["switch", "-q", "main"]);
+        commit_file(&home, &repo, "main.txt", "1\n", "Main change");
+        git(&home, &repo, &["switch", "-q", "feature"]);
+    }
+    let restacked = |repo: &str| {
+        let repo = workspace.join(repo);
+        git(&home, &repo, &["merge-base", "main", "feature"])
+            == git(&home, &repo, &["rev-parse", "main"])
+    };
+
+    let output = git_stack(&home, &workspace, &["--recurse", ".", "--rebase"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("## ./one\n"), "{}", stdout);
+    assert!(stdout.contains("## ./nested/two\n"), "{}", stdout);
+    assert!(!stdout.contains("three"), "{}", stdout);
+    assert!(restacked("one"));
+    assert!(restacked("nested/two"));
+    assert!(!restacked(".hidden/three"));
+
+    std::fs::write(
+        workspace.join("manifest"),
+        "# Repositories\n.hidden/three\nmissing\n",
+    )
+    .unwrap();
+    let output = git_stack(&home, &workspace, &["--recurse", "manifest", "--rebase"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let events: Vec<serde_json::Value> = stderr
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|event: &serde_json::Value| event["event"] == "repo")
+        .collect();
+    assert_eq!(events.len(), 2, "{}", stderr);
+    assert_eq!(events[0]["path"], ".hidden/three");
+    assert_eq!(events[0]["success"], true);
+    assert_eq!(events[1]["path"], "missing");
+    assert_eq!(events[1]["success"], false);
+    assert!(restacked(".hidden/three"));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn export_html() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(&home, &repo, &["switch", "-q", "-c", "feature"]);
+    commit_file(
+        &home,
+        &repo,
+        "a.txt",
+        "<b>&\n",
+        "Add <a> & more\n\nWith a body",
+    );
+    git(&home, &repo, &["branch", "-q", "layer"]);
+    commit_file(&home, &repo, "b.txt", "1\n", "Add b");
+    let html = temp.path().join("stack.html");
+
+    let output = git_stack(&home, &repo, &["export", "--html", html.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let html = std::fs::read_to_string(html).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"), "{}", html);
+    assert_eq!(html.matches("<details").count(), 2, "{}", html);
+    for expected in [
+        "<title>feature on main</title>",
+        "Add &lt;a&gt; &amp; more",
+        "With a body",
+        "+&lt;b&gt;&amp;",
+        "layer",
+        "Add b",
+    ] {
+        assert!(html.contains(expected), "{} in {}", expected, html);
+    }
+    // Only the branch's own commits
+    assert!(!html.contains("Initial"), "{}", html);
+    // Standalone
+    assert!(
+        !html.contains("<script src") && !html.contains("<link"),
+        "{}",
+        html
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn backport_to_each_release() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    for release in ["release/1.1", "release/1.2"] {
+        git(&home, &repo, &["branch", release]);
+    }
+    git(&home, &repo, &["switch", "-q", "release/1.1"]);
+    commit_file(&home, &repo, "shared.txt", "1.1\n", "Diverge");
+    git(&home, &repo, &["switch", "-q", "-c", "base", "main"]);
+    commit_file(&home, &repo, "base.txt", "1\n", "Base change");
+    git(&home, &repo, &["switch", "-q", "-c", "fix"]);
+    commit_file(&home, &repo, "fix.txt", "1\n", "Fix change");
+    let fix = git(&home, &repo, &["rev-parse", "fix"]);
+
+    let output = git_stack(
+        &home,
+        &repo,
+        &[
+            "backport",
+            "fix",
+            "--to",
+            "release/1.1",
+            "--to",
+            "release/1.2",
+        ],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for release in ["release/1.1", "release/1.2"] {
+        let branch = format!("backport/{}/fix", release);
+        assert_eq!(
+            git(
+                &home,
+                &repo,
+                &["log", "--format=%s", &format!("{}..{}", release, branch)]
+            ),
+            "Fix change\n"
+        );
+        assert!(git(&home, &repo, &["log", "-1", "--format=%B", &branch])
+            .contains(&format!("(cherry picked from commit {})", fix.trim())));
+        assert_eq!(
+            git(
+                &home,
+                &repo,
+                &[
+                    "rev-parse",
+                    "--abbrev-ref",
+                    &format!("{}@{{upstream}}", branch)
+                ]
+            ),
+            format!("{}\n", release)
+        );
+        git(&home, &repo, &["branch", "-q", "-D", &branch]);
+    }
+
+    // A conflict on one release doesn't hold up the other
+    commit_file(&home, &repo, "shared.txt", "fix\n", "Conflicting change");
+    let output = git_stack(
+        &home,
+        &repo,
+        &[
+            "backport",
+            "fix",
+            "--to",
+            "release/1.1",
+            "--to",
+            "release/1.2",
+        ],
+    );
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("conflicts in shared.txt"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(
+            &home,
+            &repo,
+            &["branch", "--list", "backport/release/1.1/fix"]
+        ),
+        ""
+    );
+    assert_eq!(
+        git(
+            &home,
+            &repo,
+            &[
+                "log",
+                "--format=%s",
+                "release/1.2..backport/release/1.2/fix"
+            ]
+        ),
+        "Conflicting change\nFix change\n"
+    );
+    assert_eq!(git(&home, &repo, &["status", "--porcelain"]), "");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn new_from_template() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    std::fs::create_dir_all(repo.join(".git-stack/templates")).unwrap();
+    std::fs::write(
+        repo.join(".git-stack/templates/api-change"),
+        "\
+[layer \"proto\"]
+\tdescription = Protocol changes
+[layer \"server\"]
+\tdescription = Server implementation
+[layer \"client\"]
+\tdescription = Client support
+\tparent = proto
+",
+    )
+    .unwrap();
+
+    let output = git_stack(&home, &repo, &["new", "--template", "api-change", "api"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let head = git(&home, &repo, &["rev-parse", "HEAD"]);
+    for (layer, upstream, description) in [
+        ("proto", "main", "Protocol changes"),
+        ("server", "api/proto", "Server implementation"),
+        ("client", "api/proto", "Client support"),
+    ] {
+        let branch = format!("api/{}", layer);
+        assert_eq!(git(&home, &repo, &["rev-parse", &branch]), head);
+        assert_eq!(
+            git(
+                &home,
+                &repo,
+                &[
+                    "rev-parse",
+                    "--abbrev-ref",
+                    &format!("{}@{{upstream}}", branch)
+                ]
+            ),
+            format!("{}\n", upstream)
+        );
+        assert_eq!(
+            git(
+                &home,
+                &repo,
+                &["config", &format!("branch.{}.description", branch)]
+            ),
+            format!("{}\n", description)
+        );
+    }
+    assert_eq!(git(&home, &repo, &["branch", "--show-current"]), "main\n");
+
+    // Nothing is created when any layer's branch already exists
+    git(&home, &repo, &["branch", "ui/client"]);
+    let output = git_stack(&home, &repo, &["new", "--template", "api-change", "ui"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        git(&home, &repo, &["branch", "--list", "ui/*"]),
+        "  ui/client\n"
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn auth_status_reports_token_source() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    git(
+        &home,
+        &repo,
+        &[
+            "remote",
+            "add",
+            "origin",
+            "https://github.com/owner/repo.git",
+        ],
+    );
+    git(&home, &repo, &["config", "stack.forge", "github"]);
+    let status = |env: &[(&str, &str)]| {
+        let mut cmd = isolate(Command::new(env!("CARGO_BIN_EXE_git-stack")), &home);
+        cmd.args(["auth", "status"])
+            .current_dir(&repo)
+            .env_remove("GITHUB_TOKEN")
+            .env_remove("GH_TOKEN")
+            .env_remove("GH_CONFIG_DIR");
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+        cmd.output().unwrap()
+    };
+
+    let output = status(&[]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("github.com: no github token"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Each source takes precedence over the ones before it
+    git(
+        &home,
+        &repo,
+        &[
+            "config",
+            "credential.helper",
+            "!f() { echo username=jdoe; echo password=helper-secret; }; f",
+        ],
+    );
+    let output = status(&[]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "github.com: using the github token from the git credential helper\n"
+    );
+
+    let hosts = home.join("gh/hosts.yml");
+    std::fs::create_dir_all(hosts.parent().unwrap()).unwrap();
+    std::fs::write(
+        &hosts,
+        "github.com:\n    user: jdoe\n    oauth_token: cli-secret\n",
+    )
+    .unwrap();
+    let output = status(&[]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "github.com: using the github token from {}\n",
+            hosts.display()
+        )
+    );
+
+    let output = status(&[("GH_TOKEN", "env-secret")]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "github.com: using the github token from `$GH_TOKEN`\n"
+    );
+
+    git(
+        &home,
+        &repo,
+        &["config", "stack.forge-token", "config-secret"],
+    );
+    let output = status(&[("GH_TOKEN", "env-secret")]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "github.com: using the github token from `stack.forge-token`\n"
+    );
+
+    // The token itself is never shown
+    let output = git_stack(&home, &repo, &["--dump-config", "-"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("config-secret"), "{}", stdout);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn switch_by_query() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    for branch in ["auth-api", "auth-ui", "billing"] {
+        git(&home, &repo, &["switch", "-q", "-c", branch, "main"]);
+        commit_file(
+            &home,
+            &repo,
+            &format!("{}.txt", branch),
+            "1\n",
+            &format!("Work on {}", branch),
+        );
+    }
+    git(&home, &repo, &["switch", "-q", "main"]);
+    std::fs::write(repo.join("shared.txt"), "dirty\n").unwrap();
+
+    let output = git_stack(&home, &repo, &["switch", "bill"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        git(&home, &repo, &["branch", "--show-current"]),
+        "billing\n"
+    );
+    // Uncommitted changes come along
+    assert_eq!(
+        git(&home, &repo, &["status", "--porcelain"]),
+        " M shared.txt\n"
+    );
+
+    for (query, error) in [
+        ("auth", "`auth` matches several branches: auth-api, auth-ui"),
+        ("zzz", "No development branch matches `zzz`"),
+        ("", "Not on a terminal"),
+    ] {
+        let args: &[&str] = if query.is_empty() {
+            &["switch"]
+        } else {
+            &["switch", query]
+        };
+        let output = git_stack(&home, &repo, args);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(error),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            git(&home, &repo, &["branch", "--show-current"]),
+            "billing\n"
+        );
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn back_returns_to_the_origin() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["switch", "-q", "clean"]);
+    std::fs::write(local.join("clean.txt"), "dirty\n").unwrap();
+
+    // Even though `conflict` failed, we end up where we were
+    let output = git_stack(&home, &local, &["--pull"]);
+    assert!(!output.status.success());
+    assert_eq!(git(&home, &local, &["branch", "--show-current"]), "clean\n");
+    assert_eq!(
+        git(&home, &local, &["status", "--porcelain"]),
+        " M clean.txt\n"
+    );
+
+    git(&home, &local, &["checkout", "-q", "--", "clean.txt"]);
+    git(&home, &local, &["switch", "-q", "main"]);
+    let output = git_stack(&home, &local, &["back", "--list"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1, "{}", stdout);
+    assert!(
+        stdout.starts_with("1: clean (") && stdout.contains("before `git stack`"),
+        "{}",
+        stdout
+    );
+    let output = git_stack(&home, &local, &["back"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(git(&home, &local, &["branch", "--show-current"]), "clean\n");
+
+    let output = git_stack(&home, &local, &["back", "2"]);
+    assert!(!output.status.success());
+    assert_eq!(git(&home, &local, &["branch", "--show-current"]), "clean\n");
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn overview_summarizes_each_stack() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    git(&home, &local, &["push", "-q", "origin", "clean"]);
+    git(&home, &local, &["switch", "-q", "-c", "clean-ui", "clean"]);
+    commit_file(&home, &local, "ui.txt", "1\n", "Clean UI change");
+    git(&home, &local, &["fetch", "-q", "origin"]);
+
+    let output = git_stack(&home, &local, &["overview"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines: Vec<_> = stdout.lines().collect();
+    lines.sort_unstable();
+    assert_eq!(
+        lines,
+        [
+            "clean: 2 layers, 2 commits, 1 behind main, 1 to push",
+            "conflict: 1 layers, 1 commits, 1 behind main, 1 to push, conflicts likely",
+        ]
+    );
+
+    temp.close().unwrap();
+}
+
+/// Pretend `branch` was last moved in 2020
+fn backdate_reflog(repo: &Path, branch: &str) {
+    let path = repo.join(".git/logs/refs/heads").join(branch);
+    let reflog = std::fs::read_to_string(&path).unwrap();
+    let reflog: String = reflog
+        .lines()
+        .map(|line| {
+            let (entry, message) = line.split_once('\t').unwrap();
+            let mut fields: Vec<_> = entry.rsplitn(3, ' ').collect();
+            fields[1] = "1577836800";
+            fields.reverse();
+            format!("{}\t{}\n", fields.join(" "), message)
+        })
+        .collect();
+    std::fs::write(&path, reflog).unwrap();
+}
+
+#[test]
+fn stale_stacks_are_called_out() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (home, repo) = two_stacks(temp.path());
+    backdate_reflog(&repo, "b");
+
+    let output = git_stack(&home, &repo, &["overview"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().any(|line| line.starts_with("b: ")
+            && line.contains(", untouched for ")
+            && line.ends_with(" years")),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.starts_with("a: ") && !line.contains("untouched")),
+        "{}",
+        stdout
+    );
+
+    let output = git_stack(&home, &repo, &["--stack", "all"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Add b"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Stale stacks: b (untouched for ")
+            && stderr.contains(" years, base moved 1 commits)"),
+        "{}",
+        stderr
+    );
+
+    // Hidden, unless it's checked out
+    git(&home, &repo, &["config", "stack.hide-age", "1year"]);
+    let output = git_stack(&home, &repo, &["--stack", "all"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("Add b"), "{}", stdout);
+    assert!(stdout.contains("Add a2"), "{}", stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Stacks untouched for more than 1year"),
+        "{}",
+        stderr
+    );
+    let output = git_stack(&home, &repo, &["overview"]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("b: "),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    git(&home, &repo, &["switch", "-q", "b"]);
+    let output = git_stack(&home, &repo, &["--stack", "all"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Add b"), "{}", stdout);
+
+    temp.close().unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn hooks_chain_and_restack() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = home(temp.path());
+    let repo = temp.path().join("repo");
+    init(&home, &repo);
+    // For the hooks to find `git stack`
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_git-stack")).parent().unwrap();
+    let path = std::env::join_paths(
+        std::iter::once(bin_dir.to_owned())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    // A minute ago, so the commit made through the hook is clearly the newer work
+    let earlier = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - 60;
+    let commit = |path_name: &str, message: &str, date: Option<u64>| {
+        std::fs::write(repo.join(path_name), "1\n").unwrap();
+        git(&home, &repo, &["add", path_name]);
+        let mut cmd = isolate(Command::new("git"), &home);
+        cmd.args(["commit", "-q", "-m", message])
+            .env("PATH", &path)
+            .current_dir(&repo);
+        if let Some(date) = date {
+            cmd.env("GIT_COMMITTER_DATE", format!("@{} +0000", date))
+                .env("GIT_AUTHOR_DATE", format!("@{} +0000", date));
+        }
+        let output = cmd.output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    git(&home, &repo, &["switch", "-q", "-c", "a"]);
+    commit("a.txt", "Add a", Some(earlier));
+    git(&home, &repo, &["switch", "-q", "-c", "a2"]);
+    commit("a2.txt", "Add a2", Some(earlier));
+    git(&home, &repo, &["switch", "-q", "a"]);
+
+    let hooks = repo.join(".git/hooks");
+    std::fs::create_dir_all(&hooks).unwrap();
+    let existing = "#!/bin/sh\necho \"$0\" >> \"$(git rev-parse --git-dir)/existing.log\"\n";
+    std::fs::write(hooks.join("post-commit"), existing).unwrap();
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            hooks.join("post-commit"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+    }
+
+    // Installing again doesn't chain to our own hook
+    for _ in 0..2 {
+        let output = git_stack(&home, &repo, &["hooks", "install", "--restack"]);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    assert_eq!(
+        std::fs::read_to_string(hooks.join("post-commit.pre-git-stack")).unwrap(),
+        existing
+    );
+    for hook in ["post-commit", "post-checkout", "post-merge"] {
+        assert!(
+            std::fs::read_to_string(hooks.join(hook))
+                .unwrap()
+                .contains("git stack hooks run"),
+            "{}",
+            hook
+        );
+    }
+
+    commit("more.txt", "Add more", None);
+    assert_eq!(
+        std::fs::read_to_string(repo.join(".git/existing.log"))
+            .unwrap()
+            .lines()
+            .count(),
+        1
+    );
+    assert_eq!(
+        git(&home, &repo, &["merge-base", "a", "a2"]),
+        git(&home, &repo, &["rev-parse", "a"])
+    );
+    assert_eq!(git(&home, &repo, &["branch", "--show-current"]), "a\n");
+
+    let output = git_stack(&home, &repo, &["hooks", "uninstall"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        std::fs::read_to_string(hooks.join("post-commit")).unwrap(),
+        existing
+    );
+    assert!(!hooks.join("post-commit.pre-git-stack").exists());
+    assert!(!hooks.join("post-checkout").exists());
+    assert!(!hooks.join("post-merge").exists());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn merge_options_resolve_conflicts() {
+    for (config, args, expected) in [
+        (None, &["-X", "theirs"][..], Some("2\n")),
+        (Some("ours"), &[][..], None),
+        (
+            Some("ours"),
+            &["--strategy-option", "theirs"][..],
+            Some("2\n"),
+        ),
+    ] {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let local = stale_stacks(temp.path());
+        let home = temp.path().join("home");
+        git(&home, &local, &["branch", "-q", "-D", "clean"]);
+        if let Some(config) = config {
+            git(&home, &local, &["config", "stack.merge-options", config]);
+        }
+
+        let mut pull = vec!["--pull"];
+        pull.extend(args);
+        let output = git_stack(&home, &local, &pull);
+        assert!(
+            output.status.success(),
+            "{:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            git(&home, &local, &["merge-base", "origin/main", "conflict"]),
+            git(&home, &local, &["rev-parse", "origin/main"])
+        );
+        match expected {
+            // The conflicting change wins
+            Some(expected) => {
+                assert_eq!(
+                    git(&home, &local, &["show", "conflict:shared.txt"]),
+                    expected
+                );
+            }
+            // Upstream's change wins, leaving nothing to replay
+            None => {
+                assert_eq!(
+                    git(&home, &local, &["rev-parse", "conflict"]),
+                    git(&home, &local, &["rev-parse", "origin/main"])
+                );
+            }
+        }
+
+        temp.close().unwrap();
+    }
+}
+
+#[test]
+fn usage_stats_are_opt_in() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let local = stale_stacks(temp.path());
+    let home = temp.path().join("home");
+    let usage = local.join(".git/stack/usage.json");
+
+    let output = git_stack(&home, &local, &[]);
+    assert!(output.status.success());
+    assert!(!usage.exists());
+    let output = git_stack(&home, &local, &["stats", "--self"]);
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Nothing recorded"),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    git(&home, &local, &["config", "stack.usage-stats", "true"]);
+    let output = git_stack(&home, &local, &["--pull"]);
+    assert!(!output.status.success(), "`conflict` conflicts");
+    let output = git_stack(&home, &local, &[]);
+    assert!(output.status.success());
+
+    let recorded: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&usage).unwrap()).unwrap();
+    let operations = &recorded["operations"];
+    assert_eq!(operations["git stack --pull"]["runs"], 1, "{}", recorded);
+    assert_eq!(
+        operations["git stack --pull"]["failures"], 1,
+        "{}",
+        recorded
+    );
+    assert_eq!(operations["git stack"]["runs"], 1, "{}", recorded);
+    assert_eq!(operations["git stack"]["failures"], 0, "{}", recorded);
+    assert_eq!(recorded["restacks"]["conflicts"], 1, "{}", recorded);
+
+    let output = git_stack(&home, &local, &["stats", "--self"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Since "), "{}", stdout);
+    assert!(
+        stdout
+            .lines()
+            .any(|line| line.trim_start().starts_with("git stack --pull")
+                && line.contains(" 1 runs, 1 failed, ")),
+        "{}",
+        stdout
+    );
+    let branches = recorded["restacks"]["branches"].as_u64().unwrap();
+    assert!(
+        stdout.contains(&format!(
+            "Restacked {} branches, 1 ({}%) with conflicts",
+            branches,
+            100 / branches
+        )),
+        "{}",
+        stdout
+    );
+
+    temp.close().unwrap();
+}
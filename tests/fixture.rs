@@ -55,6 +55,7 @@ fn populate_event(
                 if let Some(branch) = tree.branch.as_ref() {
                     let branch = git_stack::git::Branch {
                         name: branch.as_str().to_owned(),
+                        remote: None,
                         id: commit_id,
                         push_id: None,
                         pull_id: None,
@@ -97,6 +97,217 @@ mod test_rebase {
         dbg!(&feature1_branch.id);
         assert!(ancestors.contains(&feature1_branch.id));
     }
+
+    #[test]
+    fn cancel() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(master_branch.clone());
+        graphed_branches.insert(feature1_branch.clone());
+        graphed_branches.insert(feature2_branch.clone());
+
+        let master_commit = repo.find_commit(master_branch.id).unwrap();
+
+        let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+        git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+        git_stack::graph::rebase_development_branches(&mut graph, master_commit.id);
+        let script = git_stack::graph::to_script(&graph);
+
+        let cancel = git_stack::git::Cancel::new();
+        cancel.request();
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        executor.cancel_on(cancel);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result.len(), 1);
+        assert!(executor.cancelled());
+        assert!(executor.moved_branches().is_empty());
+        executor.close(&mut repo, "off_master").unwrap();
+
+        assert_eq!(
+            repo.find_local_branch("feature1").unwrap().id,
+            feature1_branch.id
+        );
+        assert_eq!(
+            repo.find_local_branch("feature2").unwrap().id,
+            feature2_branch.id
+        );
+    }
+
+    #[test]
+    fn git_commands() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(master_branch.clone());
+        graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let master_commit = repo.find_commit(master_branch.id).unwrap();
+
+        let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+        git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+        git_stack::graph::rebase_development_branches(&mut graph, master_commit.id);
+        let script = git_stack::graph::to_script(&graph);
+
+        let mut executor = git_stack::git::Executor::new(&repo, true);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "off_master").unwrap();
+
+        let commands = executor.git_commands();
+        dbg!(commands);
+        assert!(commands[0].starts_with(&format!("git checkout --detach {}", master_commit.id)));
+        assert!(commands.contains(&"git branch -f feature1 HEAD".to_owned()));
+        assert!(commands.contains(&"git branch -f feature2 HEAD".to_owned()));
+        assert_eq!(commands.last().unwrap(), "git switch off_master");
+    }
+
+    #[test]
+    fn rebase_todo() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(master_branch.clone());
+        graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let master_commit = repo.find_commit(master_branch.id).unwrap();
+
+        let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+        git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+        git_stack::graph::rebase_development_branches(&mut graph, master_commit.id);
+        let script = git_stack::graph::to_script(&graph);
+
+        let mut executor = git_stack::git::Executor::new(&repo, true);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "off_master").unwrap();
+
+        let todo = executor.rebase_todo();
+        dbg!(todo);
+        assert!(todo[0].starts_with(&format!("reset {}", master_commit.id)));
+        assert!(todo.contains(&"update-ref refs/heads/feature1".to_owned()));
+        assert!(todo.contains(&"update-ref refs/heads/feature2".to_owned()));
+        // The rebase starts and ends on a detached HEAD, so there is no switching back
+        assert!(todo.iter().all(|line| !line.contains("switch")));
+        let picks = todo.iter().filter(|line| line.starts_with("pick ")).count();
+        let cherry_picks = executor
+            .git_commands()
+            .iter()
+            .filter(|command| command.starts_with("git cherry-pick "))
+            .count();
+        assert_eq!(picks, cherry_picks);
+    }
+
+    #[test]
+    fn from_branches_protected() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(master_branch.clone());
+        graphed_branches.insert(feature2_branch.clone());
+
+        let master_commit = repo.find_commit(master_branch.id).unwrap();
+
+        let full = Graph::from_branches(&repo, graphed_branches.clone()).unwrap();
+        let mut graph =
+            Graph::from_branches_protected(&repo, graphed_branches, vec![master_branch.id])
+                .unwrap();
+        // Only the commit between `base` and `master` is left out
+        assert_eq!(
+            graph.breadth_first_iter().count(),
+            full.breadth_first_iter().count() - 1
+        );
+        assert_eq!(graph.root_id(), base_branch.id);
+        assert!(graph.root().action.is_protected());
+        assert!(graph.root().children.contains(&master_commit.id));
+        assert!(graph.get(master_commit.id).unwrap().action.is_protected());
+        let feature2 = graph.get(feature2_branch.id).unwrap();
+        assert!(!feature2.action.is_protected());
+
+        git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+        git_stack::graph::rebase_development_branches(&mut graph, master_commit.id);
+        let script = git_stack::graph::to_script(&graph);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "off_master").unwrap();
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let ancestors: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.id)
+            .collect();
+        assert!(ancestors.contains(&master_commit.id));
+    }
+
+    #[test]
+    fn from_branches_protected_attach_points() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let initial_branch = repo.find_local_branch("initial").unwrap();
+        let base_branch = repo.find_local_branch("base").unwrap();
+        let master_branch = repo.find_local_branch("master").unwrap();
+        let off_master_branch = repo.find_local_branch("off_master").unwrap();
+
+        // `off_master` is the only development, so `base` is just history between `initial` and
+        // where `off_master` attaches
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(initial_branch.clone());
+        graphed_branches.insert(off_master_branch.clone());
+
+        let graph = Graph::from_branches_protected(&repo, graphed_branches, vec![master_branch.id])
+            .unwrap();
+        assert_eq!(graph.root_id(), initial_branch.id);
+        assert!(graph.get(base_branch.id).is_none());
+        let ids: Vec<_> = graph.breadth_first_iter().map(|n| n.commit.id).collect();
+        assert_eq!(
+            ids,
+            vec![initial_branch.id, master_branch.id, off_master_branch.id]
+        );
+        assert!(graph.root().children.contains(&master_branch.id));
+    }
 }
 
 mod test_fixup {
@@ -216,6 +427,51 @@ mod test_fixup {
         assert_eq!(feature2_commit.summary.to_str(), Ok("feature2 commit"));
     }
 
+    #[test]
+    fn squash_move_after_target() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan =
+            git_fixture::Dag::load(std::path::Path::new("tests/fixtures/squash.yml")).unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(master_branch.clone());
+        graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+
+        let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+        git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+        git_stack::graph::fixup(&mut graph, git_stack::config::Fixup::Move);
+        let script = git_stack::graph::to_script(&graph);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "master").unwrap();
+
+        // `squash!` commits are moved like `fixup!` commits
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let mut commits: Vec<_> = repo
+            .commits_from(feature1_branch.id)
+            .map(|c| c.summary.to_str_lossy().into_owned())
+            .collect();
+        commits.reverse();
+        assert_eq!(
+            commits,
+            &[
+                "master commit",
+                "feature1 commit 1",
+                "squash! feature1 commit 1",
+                "feature1 commit 2",
+            ]
+        );
+    }
+
     #[test]
     fn stray_fixups() {
         let mut repo = git_stack::git::InMemoryRepo::new();
@@ -283,6 +539,209 @@ mod test_fixup {
         let feature2_commit = repo.find_commit(feature2_branch.id).unwrap();
         assert_eq!(feature2_commit.summary.to_str(), Ok("feature2 commit"));
     }
+
+    #[test]
+    fn fixup_of_sibling_stays() {
+        let mut repo = git_stack::git::InMemoryRepo::new();
+        let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup-sibling.yml"))
+            .unwrap();
+        fixture::populate_repo(&mut repo, plan);
+
+        let master_branch = repo.find_local_branch("master").unwrap();
+
+        let mut protected_branches = git_stack::git::Branches::default();
+        protected_branches.insert(master_branch.clone());
+
+        let mut graphed_branches = git_stack::git::Branches::default();
+        graphed_branches.insert(master_branch.clone());
+        graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+        graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+        let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+        git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+        git_stack::graph::fixup(&mut graph, git_stack::config::Fixup::Move);
+        let script = git_stack::graph::to_script(&graph);
+        dbg!(&script);
+
+        let mut executor = git_stack::git::Executor::new(&repo, false);
+        let result = executor.run_script(&mut repo, &script);
+        assert_eq!(result, vec![]);
+        executor.close(&mut repo, "master").unwrap();
+        dbg!(&repo);
+
+        // The fixup's target isn't in feature2, so it is a stray rather than moved into feature1
+        let feature1_branch = repo.find_local_branch("feature1").unwrap();
+        let commits: Vec<_> = repo
+            .commits_from(feature1_branch.id)
+            .map(|c| c.summary.to_str_lossy().into_owned())
+            .collect();
+        assert_eq!(
+            commits,
+            &[
+                "feature1 commit",
+                "shared commit",
+                "fixup! feature1 commit",
+                "master commit"
+            ]
+        );
+
+        let feature2_branch = repo.find_local_branch("feature2").unwrap();
+        let commits: Vec<_> = repo
+            .commits_from(feature2_branch.id)
+            .map(|c| c.summary.to_str_lossy().into_owned())
+            .collect();
+        assert!(commits.contains(&"fixup! feature1 commit".to_owned()));
+    }
+}
+
+#[test]
+fn exec_after_fixups() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let master_branch = repo.find_local_branch("master").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graphed_branches = git_stack::git::Branches::default();
+    graphed_branches.insert(master_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+    let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+    git_stack::graph::fixup(&mut graph, git_stack::config::Fixup::Squash);
+    git_stack::graph::exec(&mut graph, "make test");
+    let script = git_stack::graph::to_script(&graph);
+    dbg!(&script);
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    let result = executor.run_script(&mut repo, &script);
+    assert_eq!(result, vec![]);
+    executor.close(&mut repo, "master").unwrap();
+
+    // Once per commit that is left, and never on a commit before its fixups are squashed in
+    let feature2_branch = repo.find_local_branch("feature2").unwrap();
+    let mut commits: Vec<_> = repo
+        .commits_from(feature2_branch.id)
+        .take_while(|c| c.id != master_branch.id)
+        .map(|c| (c.id, "make test".to_owned()))
+        .collect();
+    commits.reverse();
+    assert_eq!(repo.execs(), commits);
+}
+
+#[test]
+fn exec_at_commit() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let master_branch = repo.find_local_branch("master").unwrap();
+    let feature2_branch = repo.find_local_branch("feature2").unwrap();
+    let target = repo
+        .commits_from(feature2_branch.id)
+        .find(|c| c.summary == "feature1 commit 1")
+        .unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graphed_branches = git_stack::git::Branches::default();
+    graphed_branches.insert(master_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graphed_branches.insert(feature2_branch);
+
+    let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+    git_stack::graph::fixup(&mut graph, git_stack::config::Fixup::Squash);
+    assert!(git_stack::graph::exec_at(
+        &mut graph,
+        target.id,
+        "make lint"
+    ));
+    assert!(!git_stack::graph::exec_at(
+        &mut graph,
+        master_branch.id,
+        "make lint"
+    ));
+    git_stack::graph::exec(&mut graph, "make test");
+    let script = git_stack::graph::to_script(&graph);
+    dbg!(&script);
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    let result = executor.run_script(&mut repo, &script);
+    assert_eq!(result, vec![]);
+    executor.close(&mut repo, "master").unwrap();
+
+    // Interleaved like `exec` lines, after the fixups are squashed in and before the next pick
+    let feature2_branch = repo.find_local_branch("feature2").unwrap();
+    let mut commits: Vec<_> = repo
+        .commits_from(feature2_branch.id)
+        .take_while(|c| c.id != master_branch.id)
+        .map(|c| c.id)
+        .collect();
+    commits.reverse();
+    let mut expected = vec![(commits[0], "make lint".to_owned())];
+    expected.extend(commits.iter().map(|id| (*id, "make test".to_owned())));
+    assert_eq!(repo.execs(), expected);
+}
+
+#[test]
+fn fixup_pinned() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/fixup.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let master_branch = repo.find_local_branch("master").unwrap();
+    let feature2_branch = repo.find_local_branch("feature2").unwrap();
+    let pinned = repo
+        .commits_from(feature2_branch.id)
+        .find(|c| c.summary == "feature1 commit 1")
+        .unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graphed_branches = git_stack::git::Branches::default();
+    graphed_branches.insert(master_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graphed_branches.insert(feature2_branch);
+
+    let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+    git_stack::graph::pin_commits(&mut graph, [pinned.id]);
+    git_stack::graph::fixup(&mut graph, git_stack::config::Fixup::Squash);
+    let script = git_stack::graph::to_script(&graph);
+    dbg!(&script);
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    let result = executor.run_script(&mut repo, &script);
+    assert_eq!(result, vec![]);
+    executor.close(&mut repo, "master").unwrap();
+
+    // Only the fixup of the unpinned commit is squashed
+    let feature2_branch = repo.find_local_branch("feature2").unwrap();
+    let mut commits: Vec<_> = repo
+        .commits_from(feature2_branch.id)
+        .take_while(|c| c.id != master_branch.id)
+        .map(|c| c.summary.to_str_lossy().into_owned())
+        .collect();
+    commits.reverse();
+    assert_eq!(
+        commits,
+        &[
+            "feature1 commit 1",
+            "feature1 commit 2",
+            "fixup! feature1 commit 1",
+            "feature1 commit 3",
+            "fixup! feature1 commit 1",
+            "feature2 commit",
+            "fixup! feature1 commit 1",
+        ]
+    );
 }
 
 #[test]
@@ -364,3 +823,243 @@ fn overflow() {
     assert_eq!(result, vec![]);
     executor.close(&mut repo, "master").unwrap();
 }
+
+#[test]
+fn verify_detects_moved_branch() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let master_branch = repo.find_local_branch("master").unwrap();
+    let feature2_branch = repo.find_local_branch("feature2").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graphed_branches = git_stack::git::Branches::default();
+    graphed_branches.insert(master_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graphed_branches.insert(feature2_branch.clone());
+
+    let mut graph = git_stack::graph::Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+    git_stack::graph::rebase_development_branches(&mut graph, master_branch.id);
+    let script = git_stack::graph::to_script(&graph);
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    let result = executor.run_script(&mut repo, &script);
+    assert_eq!(result, vec![]);
+    executor.verify(&repo).unwrap();
+
+    // Branches aren't moved until the scripts are committed
+    assert_eq!(
+        repo.find_local_branch("feature2").unwrap().id,
+        feature2_branch.id
+    );
+
+    // Something else moved a branch while we were restacking
+    repo.branch("feature1", master_branch.id).unwrap();
+    assert!(executor.verify(&repo).is_err());
+
+    executor.abandon(&repo);
+    executor.close(&mut repo, "master").unwrap();
+    assert_eq!(
+        repo.find_local_branch("feature2").unwrap().id,
+        feature2_branch.id
+    );
+}
+
+#[test]
+fn stop_after_failed_script() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let master_branch = repo.find_local_branch("master").unwrap();
+    let feature1_branch = repo.find_local_branch("feature1").unwrap();
+
+    let broken = git_stack::git::Script {
+        commands: vec![
+            git_stack::git::Command::SwitchCommit(git2::Oid::zero()),
+            git_stack::git::Command::CreateBranch("broken".to_owned()),
+        ],
+        dependents: vec![],
+    };
+    let independent = git_stack::git::Script {
+        commands: vec![
+            git_stack::git::Command::SwitchCommit(master_branch.id),
+            git_stack::git::Command::CreateBranch("feature1".to_owned()),
+        ],
+        dependents: vec![],
+    };
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    executor.keep_going(false);
+    assert_eq!(executor.run_script(&mut repo, &broken).len(), 1);
+    assert_eq!(executor.run_script(&mut repo, &independent), vec![]);
+    assert_eq!(executor.skipped_branches(), ["feature1".to_owned()]);
+    executor.close(&mut repo, "master").unwrap();
+    assert_eq!(
+        repo.find_local_branch("feature1").unwrap().id,
+        feature1_branch.id
+    );
+
+    let mut executor = git_stack::git::Executor::new(&repo, false);
+    assert_eq!(executor.run_script(&mut repo, &broken).len(), 1);
+    assert_eq!(executor.run_script(&mut repo, &independent), vec![]);
+    assert!(executor.skipped_branches().is_empty());
+    executor.close(&mut repo, "master").unwrap();
+    assert_eq!(
+        repo.find_local_branch("feature1").unwrap().id,
+        master_branch.id
+    );
+}
+
+#[test]
+fn select_branches() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let base_branch = repo.find_local_branch("base").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(base_branch.clone());
+    protected_branches.insert(repo.find_local_branch("master").unwrap());
+
+    let mut graphed_branches = protected_branches.clone();
+    graphed_branches.insert(repo.find_local_branch("off_master").unwrap());
+    graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+    let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+
+    let context = git_stack::graph::SelectContext {
+        head_branch: Some("feature1".to_owned()),
+        ..Default::default()
+    };
+    let select = |s: &str| {
+        let selector: git_stack::graph::Selector = s.parse().unwrap();
+        git_stack::graph::select_branches(&graph, &selector, &context)
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(select("stack(feature2)"), ["feature1", "feature2"]);
+    assert_eq!(select("stack() & !feature1"), ["feature2"]);
+    assert_eq!(
+        select("feature* | off_master"),
+        ["feature1", "feature2", "off_master"]
+    );
+    assert_eq!(select("!(stack(feature1) | master | base)"), ["off_master"]);
+    assert!(select("stack(missing)").is_empty());
+}
+
+#[test]
+fn oversized_branches() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let master_branch = repo.find_local_branch("master").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(master_branch.clone());
+
+    let mut graphed_branches = git_stack::git::Branches::default();
+    graphed_branches.insert(master_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("feature1").unwrap());
+    graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+    let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+
+    // `feature2` is stacked on `feature1`, so only its own 3 commits count
+    assert_eq!(
+        git_stack::graph::oversized_branches(&graph, 2),
+        vec![("feature2".to_owned(), 3)]
+    );
+    assert_eq!(git_stack::graph::oversized_branches(&graph, 3), vec![]);
+    assert_eq!(
+        git_stack::graph::oversized_branches(&graph, 0),
+        vec![("feature1".to_owned(), 1), ("feature2".to_owned(), 3)]
+    );
+}
+
+#[test]
+fn dependencies() {
+    let mut repo = git_stack::git::InMemoryRepo::new();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    fixture::populate_repo(&mut repo, plan);
+
+    let base_branch = repo.find_local_branch("base").unwrap();
+    let master_branch = repo.find_local_branch("master").unwrap();
+    let feature1_branch = repo.find_local_branch("feature1").unwrap();
+
+    let mut protected_branches = git_stack::git::Branches::default();
+    protected_branches.insert(base_branch.clone());
+
+    let mut graphed_branches = git_stack::git::Branches::default();
+    graphed_branches.insert(base_branch.clone());
+    graphed_branches.insert(master_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("off_master").unwrap());
+    graphed_branches.insert(feature1_branch.clone());
+    graphed_branches.insert(repo.find_local_branch("feature2").unwrap());
+
+    let mut graph = Graph::from_branches(&repo, graphed_branches).unwrap();
+    git_stack::graph::protect_branches(&mut graph, &repo, &protected_branches);
+    git_stack::graph::pushable(&mut graph);
+    assert!(graph.get(feature1_branch.id).unwrap().pushable);
+
+    // Pushed after what it depends on
+    let mut ordered = graph.clone();
+    git_stack::graph::mark_dependencies(
+        &mut ordered,
+        &[("feature1".to_owned(), "master".to_owned())],
+    );
+    git_stack::graph::hold_dependents(&mut ordered);
+    assert!(ordered.get(feature1_branch.id).unwrap().pushable);
+    let order = git_stack::graph::push_order(&ordered);
+    let position = |id| order.iter().position(|o| *o == id).unwrap();
+    assert!(position(master_branch.id) < position(feature1_branch.id));
+
+    // `off_master` is stacked on `master`, so it isn't about to be pushed
+    let mut held = graph.clone();
+    git_stack::graph::mark_dependencies(
+        &mut held,
+        &[("feature1".to_owned(), "off_master".to_owned())],
+    );
+    git_stack::graph::hold_dependents(&mut held);
+    assert!(!held.get(feature1_branch.id).unwrap().pushable);
+    assert!(held.get(master_branch.id).unwrap().pushable);
+}
+
+#[test]
+fn check_edges() {
+    use git_stack::graph::EdgeProblem;
+
+    let edge = |branch: &str, parent: &str| (branch.to_owned(), parent.to_owned());
+    let exists = |name: &str| name != "deleted";
+
+    let edges = [edge("b", "a"), edge("c", "b")];
+    assert_eq!(git_stack::graph::check_edges(&edges, exists), vec![]);
+
+    let edges = [
+        edge("a", "deleted"),
+        edge("b", "c"),
+        edge("c", "b"),
+        edge("d", "c"),
+        edge("e", "e"),
+    ];
+    assert_eq!(
+        git_stack::graph::check_edges(&edges, exists),
+        vec![
+            EdgeProblem::Missing {
+                branch: "a".to_owned(),
+                parent: "deleted".to_owned()
+            },
+            EdgeProblem::Cycle(vec!["b".to_owned(), "c".to_owned()]),
+            EdgeProblem::Cycle(vec!["e".to_owned()]),
+        ]
+    );
+}
@@ -302,6 +302,231 @@ fn cherry_pick_conflict() {
     temp.close().unwrap();
 }
 
+#[test]
+fn cherry_pick_large_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("off_master").unwrap();
+    let source = repo.find_local_branch("feature1").unwrap();
+    let in_memory_id = repo.cherry_pick(base.id, source.id).unwrap();
+
+    // Every file is large
+    repo.set_large_file_threshold(Some(1));
+    let expected_head = repo.head_commit();
+    let worktree_id = repo.cherry_pick(base.id, source.id).unwrap();
+
+    let in_memory = repo.find_commit(in_memory_id).unwrap();
+    let worktree = repo.find_commit(worktree_id).unwrap();
+    assert_eq!(worktree.tree_id, in_memory.tree_id);
+    assert_eq!(worktree.summary, in_memory.summary);
+    assert_eq!(repo.head_commit().id, expected_head.id);
+    assert!(!repo.is_dirty());
+
+    // The rest of the branch is replayed in the same worktree
+    let rest: Vec<_> = {
+        let mut revwalk = repo.raw().revwalk().unwrap();
+        revwalk
+            .push(repo.find_local_branch("feature2").unwrap().id)
+            .unwrap();
+        revwalk.hide(source.id).unwrap();
+        revwalk
+            .set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)
+            .unwrap();
+        revwalk.map(Result::unwrap).collect()
+    };
+    let mut in_memory_id = in_memory_id;
+    let mut worktree_id = worktree_id;
+    {
+        let mut in_memory = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+        for &cherry_id in &rest {
+            in_memory_id = in_memory.cherry_pick(in_memory_id, cherry_id).unwrap();
+            worktree_id = repo.cherry_pick(worktree_id, cherry_id).unwrap();
+        }
+    }
+    let in_memory = repo.find_commit(in_memory_id).unwrap();
+    let worktree = repo.find_commit(worktree_id).unwrap();
+    assert_eq!(worktree.tree_id, in_memory.tree_id);
+    assert_eq!(repo.raw().worktrees().unwrap().len(), 1);
+
+    drop(repo);
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    assert_eq!(raw.worktrees().unwrap().len(), 0);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn cherry_pick_large_files_conflict() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/conflict.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+    repo.set_large_file_threshold(Some(1));
+
+    let base = repo.find_local_branch("feature1").unwrap();
+    let source = repo.find_local_branch("master").unwrap();
+    let err = repo.cherry_pick(base.id, source.id).unwrap_err();
+    assert_eq!(err.code(), git2::ErrorCode::Unmerged);
+    assert!(!repo.is_dirty());
+
+    // The conflict is cleared before the worktree is reused
+    let other_id = {
+        let raw = repo.raw();
+        let upstream = raw.find_branch("base", git2::BranchType::Local).unwrap();
+        let upstream = upstream.get().peel_to_commit().unwrap();
+        let mut tree = raw.treebuilder(Some(&upstream.tree().unwrap())).unwrap();
+        let blob_id = raw.blob(b"1").unwrap();
+        tree.insert("file_b.txt", blob_id, 0o100644).unwrap();
+        let tree = raw.find_tree(tree.write().unwrap()).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        raw.commit(None, &sig, &sig, "Other", &tree, &[&upstream])
+            .unwrap()
+    };
+    let dest_id = repo.cherry_pick(base.id, other_id).unwrap();
+    {
+        let dest_tree = repo.raw().find_commit(dest_id).unwrap().tree().unwrap();
+        let base_tree = repo.raw().find_commit(base.id).unwrap().tree().unwrap();
+        assert_eq!(
+            dest_tree.get_name("file_a.txt").unwrap().id(),
+            base_tree.get_name("file_a.txt").unwrap().id()
+        );
+        assert!(dest_tree.get_name("file_b.txt").is_some());
+    }
+
+    drop(repo);
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    assert_eq!(raw.worktrees().unwrap().len(), 0);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn cherry_pick_empty() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    // Two commits making the same change
+    let (base, source) = {
+        let raw = repo.raw();
+        let master = raw.find_branch("master", git2::BranchType::Local).unwrap();
+        let master = master.get().peel_to_commit().unwrap();
+        let mut tree = raw.treebuilder(Some(&master.tree().unwrap())).unwrap();
+        let blob_id = raw.blob(b"1").unwrap();
+        tree.insert("file_d.txt", blob_id, 0o100644).unwrap();
+        let tree = raw.find_tree(tree.write().unwrap()).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        let base_id = raw
+            .commit(None, &sig, &sig, "Upstream", &tree, &[&master])
+            .unwrap();
+        let source_id = raw
+            .commit(None, &sig, &sig, "Local", &tree, &[&master])
+            .unwrap();
+        (base_id, source_id)
+    };
+
+    {
+        let dest_id = repo.cherry_pick(base, source).unwrap();
+        assert_eq!(dest_id, base);
+    }
+
+    {
+        repo.set_empty_commits(git_stack::config::EmptyCommits::Keep, None);
+        let dest_id = repo.cherry_pick(base, source).unwrap();
+
+        let source_commit = repo.find_commit(source).unwrap();
+        let base_commit = repo.find_commit(base).unwrap();
+        let dest_commit = repo.find_commit(dest_id).unwrap();
+        assert_ne!(dest_id, base);
+        assert_eq!(dest_commit.summary, source_commit.summary);
+        assert_eq!(dest_commit.tree_id, base_commit.tree_id);
+        let dest_raw = repo.raw().find_commit(dest_id).unwrap();
+        assert_eq!(dest_raw.parent_ids().collect::<Vec<_>>(), vec![base]);
+    }
+
+    {
+        repo.set_empty_commits(
+            git_stack::config::EmptyCommits::Ask,
+            Some(Box::new(|_| false)),
+        );
+        let dest_id = repo.cherry_pick(base, source).unwrap();
+        assert_eq!(dest_id, base);
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn cherry_pick_whitespace_conflict() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let mut repo = GitRepo::new(repo);
+
+    // Trailing whitespace next to a change
+    let (base, source) = {
+        let raw = repo.raw();
+        let master = raw.find_branch("master", git2::BranchType::Local).unwrap();
+        let master = master.get().peel_to_commit().unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        let commit = |parent: &git2::Commit, content: &[u8], message: &str| {
+            let mut tree = raw.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+            let blob_id = raw.blob(content).unwrap();
+            tree.insert("file_d.txt", blob_id, 0o100644).unwrap();
+            let tree = raw.find_tree(tree.write().unwrap()).unwrap();
+            let id = raw
+                .commit(None, &sig, &sig, message, &tree, &[parent])
+                .unwrap();
+            raw.find_commit(id).unwrap()
+        };
+        let fork = commit(&master, b"a\nb\nc\n", "Fork");
+        let base = commit(&fork, b"a \nb  \nc\n", "Whitespace");
+        let source = commit(&fork, b"a\nB\nc\n", "Change");
+        (base.id(), source.id())
+    };
+
+    {
+        let dest_id = repo.cherry_pick(base, source).unwrap();
+        let dest = repo.raw().find_commit(dest_id).unwrap();
+        let blob = dest
+            .tree()
+            .unwrap()
+            .get_name("file_d.txt")
+            .unwrap()
+            .to_object(repo.raw())
+            .unwrap()
+            .peel_to_blob()
+            .unwrap();
+        assert_eq!(blob.content(), b"a\nB\nc\n");
+        assert_eq!(repo.whitespace_resolved(), &[source]);
+    }
+
+    {
+        repo.raw()
+            .config()
+            .unwrap()
+            .set_str("core.whitespace", "-blank-at-eol,-space-before-tab")
+            .unwrap();
+        let err = repo.cherry_pick(base, source).unwrap_err();
+        assert_eq!(err.code(), git2::ErrorCode::Unmerged);
+        assert_eq!(repo.whitespace_resolved(), &[source]);
+    }
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn squash_clean() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -316,7 +541,9 @@ fn squash_clean() {
 
         let base = repo.find_local_branch("master").unwrap();
         let source = repo.find_local_branch("feature1").unwrap();
-        let dest_id = repo.squash(source.id, base.id).unwrap();
+        let dest_id = repo
+            .squash(source.id, base.id, &Default::default())
+            .unwrap();
 
         repo.branch("squashed", dest_id).unwrap();
         assert!(!repo.is_dirty());
@@ -325,6 +552,160 @@ fn squash_clean() {
     temp.close().unwrap();
 }
 
+#[test]
+fn squash_commit_template() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+    temp.child("provenance.txt")
+        .write_str(
+            "# Added to squashed commits\nStack: {stack}\nLayer: {layer}\nSource: {source}\n",
+        )
+        .unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let template = CommitTemplate::load(&raw, "provenance.txt").unwrap();
+    let mut repo = GitRepo::new(raw);
+    repo.set_commit_template(Some(template));
+
+    let base = repo.find_local_branch("master").unwrap();
+    let source = repo.find_local_branch("feature1").unwrap();
+    let provenance = Provenance {
+        stack: Some("feature1".to_owned()),
+        layer: None,
+        sources: vec![base.id, source.id],
+    };
+    let squashed_id = repo.squash(source.id, base.id, &provenance).unwrap();
+
+    let base_message = repo
+        .raw()
+        .find_commit(base.id)
+        .unwrap()
+        .message()
+        .unwrap()
+        .to_owned();
+    let squashed = repo.raw().find_commit(squashed_id).unwrap();
+    assert_eq!(
+        squashed.message().unwrap(),
+        format!(
+            "{}\n\nStack: feature1\nSource: {} {}\n",
+            base_message.trim_end(),
+            base.id,
+            source.id
+        )
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn legacy_encoding() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    // "Café" in Latin-1
+    let message = b"Caf\xe9\n\nNa\xefve body\n";
+    let latin1 = |name: &str| {
+        let id = raw
+            .find_branch(name, git2::BranchType::Local)
+            .unwrap()
+            .get()
+            .target()
+            .unwrap();
+        let commit = raw.find_commit(id).unwrap();
+        let parents: Vec<_> = commit.parents().collect();
+        let parents: Vec<_> = parents.iter().collect();
+        let id = write_commit(
+            &raw,
+            &commit.author(),
+            &commit.committer(),
+            message,
+            Some("ISO-8859-1"),
+            &commit.tree().unwrap(),
+            &parents,
+        )
+        .unwrap();
+        id
+    };
+    let master_id = latin1("master");
+    let feature1_id = latin1("feature1");
+    let assert_preserved = |id| {
+        let commit = raw.find_commit(id).unwrap();
+        assert_eq!(commit.message_bytes(), message);
+        assert_eq!(commit.message_encoding(), Some("ISO-8859-1"));
+    };
+    assert_preserved(master_id);
+    let mut repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+
+    let base = repo.find_local_branch("off_master").unwrap();
+    let picked_id = repo.cherry_pick(base.id, feature1_id).unwrap();
+    assert_ne!(picked_id, feature1_id);
+    assert_preserved(picked_id);
+
+    let source = repo.find_local_branch("feature1").unwrap();
+    let squashed_id = repo
+        .squash(source.id, master_id, &Default::default())
+        .unwrap();
+    assert_preserved(squashed_id);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn push_refspec() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    {
+        let mut config = raw.config().unwrap();
+        config.set_str("user.email", "jdoe@example.com").unwrap();
+        config
+            .set_str(
+                "branch.feature1.stack-push-refspec",
+                "refs/heads/{branch}:refs/heads/team/{branch}",
+            )
+            .unwrap();
+        // `feature2` is stacked on `feature1`
+        config.set_str("branch.feature2.remote", ".").unwrap();
+        config
+            .set_str("branch.feature2.merge", "refs/heads/feature1")
+            .unwrap();
+    }
+    let mut repo = GitRepo::new(raw);
+    assert_eq!(
+        repo.push_remote_ref("master").as_deref(),
+        Some("refs/heads/master")
+    );
+
+    repo.set_push_refspec(Some(
+        "+refs/heads/{branch}:refs/heads/users/{user}/{branch}"
+            .parse()
+            .unwrap(),
+    ));
+    assert_eq!(
+        repo.push_refspec_for("master").as_deref(),
+        Some("+refs/heads/master:refs/heads/users/jdoe/master")
+    );
+    assert_eq!(
+        repo.push_tracking_name("master").as_deref(),
+        Some("origin/users/jdoe/master")
+    );
+    assert_eq!(
+        repo.push_remote_ref("feature1").as_deref(),
+        Some("refs/heads/team/feature1")
+    );
+    assert_eq!(
+        repo.push_remote_ref("feature2").as_deref(),
+        Some("refs/heads/team/feature2")
+    );
+
+    temp.close().unwrap();
+}
+
 #[test]
 fn branch() {
     let temp = assert_fs::TempDir::new().unwrap();
@@ -401,3 +782,887 @@ fn switch() {
 
     temp.close().unwrap();
 }
+
+#[test]
+fn fork_point() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+
+    let old_base = repo.find_local_branch("base").unwrap();
+    let initial = repo.find_local_branch("initial").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    {
+        let actual = repo.fork_point(&old_base, feature1.id).unwrap();
+        assert_eq!(actual, old_base.id);
+    }
+
+    // Rewrite `base` out from under `feature1`
+    raw.reference(
+        "refs/heads/base",
+        initial.id,
+        true,
+        "reset: moving to initial",
+    )
+    .unwrap();
+    let new_base = repo.find_local_branch("base").unwrap();
+
+    {
+        let actual = repo.merge_base(new_base.id, feature1.id).unwrap();
+        assert_eq!(actual, initial.id);
+        let actual = repo.fork_point(&new_base, feature1.id).unwrap();
+        assert_eq!(actual, old_base.id);
+    }
+
+    // Entries `feature1` never saw don't count
+    let master = repo.find_local_branch("master").unwrap();
+    raw.reference(
+        "refs/heads/base",
+        master.id,
+        true,
+        "reset: moving to master",
+    )
+    .unwrap();
+    let moved_base = repo.find_local_branch("base").unwrap();
+
+    {
+        let actual = repo.fork_point(&moved_base, feature1.id).unwrap();
+        assert_eq!(actual, old_base.id);
+        let actual = repo.fork_point(&moved_base, master.id).unwrap();
+        assert_eq!(actual, master.id);
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn upstream_branch() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+
+    assert_eq!(repo.upstream_branch("feature1"), None);
+
+    let mut config = raw.config().unwrap();
+    config.set_str("branch.feature1.remote", ".").unwrap();
+    config
+        .set_str("branch.feature1.merge", "refs/heads/base")
+        .unwrap();
+    let actual = repo.upstream_branch("feature1").unwrap();
+    assert_eq!(actual.name, "base");
+
+    // Publishing a branch isn't the same as basing it on something
+    config.set_str("branch.feature1.remote", "origin").unwrap();
+    config
+        .set_str("branch.feature1.merge", "refs/heads/feature1")
+        .unwrap();
+    assert_eq!(repo.upstream_branch("feature1"), None);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn branches_by_prefix() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let id = raw.head().unwrap().target().unwrap();
+    for name in [
+        "refs/heads/nested/feature",
+        "refs/remotes/origin/feature1",
+        "refs/remotes/origin/nested/feature",
+        "refs/remotes/origin2/feature1",
+        "refs/tags/feature1",
+    ] {
+        raw.reference(name, id, false, "test").unwrap();
+    }
+    raw.reference_symbolic(
+        "refs/remotes/origin/HEAD",
+        "refs/remotes/origin/feature1",
+        false,
+        "test",
+    )
+    .unwrap();
+    let repo = GitRepo::new(raw);
+
+    let mut actual: Vec<_> = repo.local_branches().map(|b| b.name).collect();
+    actual.sort_unstable();
+    assert_eq!(
+        actual,
+        &[
+            "base",
+            "feature1",
+            "feature2",
+            "initial",
+            "master",
+            "nested/feature",
+            "off_master"
+        ]
+    );
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    assert_eq!(feature1.pull_id, Some(id));
+
+    let mut actual: Vec<_> = repo.remote_branches("origin").map(|b| b.name).collect();
+    actual.sort_unstable();
+    assert_eq!(actual, &["origin/feature1", "origin/nested/feature"]);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn mailmap() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let head = raw.head().unwrap().target().unwrap();
+    let email = raw
+        .find_commit(head)
+        .unwrap()
+        .author()
+        .email()
+        .unwrap()
+        .to_owned();
+    let mut config = raw.config().unwrap();
+    config.set_str("user.name", "Old Name").unwrap();
+    config.set_str("user.email", &email).unwrap();
+    temp.child(".mailmap")
+        .write_str(&format!("Canonical Name <{}>\n", email))
+        .unwrap();
+    let repo = GitRepo::new(raw);
+
+    let commit = repo.find_commit(head).unwrap();
+    assert_eq!(commit.author.as_deref(), Some("Canonical Name"));
+    assert_eq!(repo.user().as_deref(), Some("Canonical Name"));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn lfs_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    assert!(!uses_lfs(&repo));
+
+    let attributes = "*.bin filter=lfs diff=lfs merge=lfs -text\n";
+    temp.child(".gitattributes").write_str(attributes).unwrap();
+    assert!(uses_lfs(&repo));
+
+    let pointer = "version https://git-lfs.github.com/spec/v1
+oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+size 12345
+";
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let mut tree = repo.treebuilder(Some(&head.tree().unwrap())).unwrap();
+    for (name, content) in [
+        (".gitattributes", attributes.as_bytes()),
+        ("pointer.bin", pointer.as_bytes()),
+        ("raw.bin", b"\x89PNG\r\n\x1a\n".as_slice()),
+        ("raw.txt", b"not tracked".as_slice()),
+    ] {
+        let blob = repo.blob(content).unwrap();
+        tree.insert(name, blob, 0o100644).unwrap();
+    }
+    let tree = repo.find_tree(tree.write().unwrap()).unwrap();
+    let signature = head.author();
+    let commit_id = repo
+        .commit(None, &signature, &signature, "Assets", &tree, &[&head])
+        .unwrap();
+
+    let actual = git_stack::git::raw_lfs_files(&repo, commit_id).unwrap();
+    assert_eq!(actual, &[std::path::PathBuf::from("raw.bin")]);
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn touches_path() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("base").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    assert!(repo.touches_path(base.id, feature1.id, std::path::Path::new("file_c.txt")));
+    assert!(!repo.touches_path(base.id, feature1.id, std::path::Path::new("file_a.txt")));
+    assert!(!repo.touches_path(base.id, feature1.id, std::path::Path::new("file_b.txt")));
+
+    assert_eq!(&*repo.touched_dirs(base.id, feature1.id), ["./".to_owned()]);
+    assert!(repo.touched_dirs(base.id, base.id).is_empty());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn is_merged() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    // Squash-merge `feature1` into `master`
+    {
+        let master = raw.find_branch("master", git2::BranchType::Local).unwrap();
+        let master = master.get().peel_to_commit().unwrap();
+        let blob = raw.blob(b"1").unwrap();
+        let mut tree = raw.treebuilder(Some(&master.tree().unwrap())).unwrap();
+        tree.insert("file_c.txt", blob, 0o100644).unwrap();
+        let tree = raw.find_tree(tree.write().unwrap()).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        raw.commit(
+            Some("refs/heads/master"),
+            &sig,
+            &sig,
+            "Squashed feature1",
+            &tree,
+            &[&master],
+        )
+        .unwrap();
+    }
+    let repo = GitRepo::new(raw);
+
+    let master = repo.find_local_branch("master").unwrap();
+    let initial = repo.find_local_branch("initial").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+    let off_master = repo.find_local_branch("off_master").unwrap();
+    assert!(repo.is_merged(master.id, initial.id));
+    assert!(repo.is_merged(master.id, feature1.id));
+    assert!(!repo.is_merged(master.id, feature2.id));
+    assert!(!repo.is_merged(master.id, off_master.id));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn conventional_type() {
+    let commit = |summary: &str| Commit {
+        id: git2::Oid::zero(),
+        tree_id: git2::Oid::zero(),
+        summary: bstr::BString::from(summary),
+        time: std::time::SystemTime::now(),
+        author: None,
+        committer: None,
+    };
+    let kind = |summary: &str| {
+        commit(summary)
+            .conventional_type()
+            .map(|(kind, breaking)| (kind.to_string(), breaking))
+    };
+
+    assert_eq!(kind("feat: add thing"), Some(("feat".to_owned(), false)));
+    assert_eq!(
+        kind("fix(parser): off-by-one"),
+        Some(("fix".to_owned(), false))
+    );
+    assert_eq!(
+        kind("refactor!: drop API"),
+        Some(("refactor".to_owned(), true))
+    );
+    assert_eq!(
+        kind("feat(api)!: drop API"),
+        Some(("feat".to_owned(), true))
+    );
+    assert_eq!(kind("Add thing"), None);
+    assert_eq!(kind("PROJ-9: add thing"), None);
+    assert_eq!(kind("feat(api: broken"), None);
+}
+
+#[test]
+fn content_id() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+    let commit = |parent: git2::Oid, content: &str| {
+        let raw = repo.raw();
+        let parent = raw.find_commit(parent).unwrap();
+        let mut tree = raw.treebuilder(Some(&parent.tree().unwrap())).unwrap();
+        let blob_id = raw.blob(content.as_bytes()).unwrap();
+        tree.insert("lines.txt", blob_id, 0o100644).unwrap();
+        let tree = raw.find_tree(tree.write().unwrap()).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        raw.commit(None, &sig, &sig, content, &tree, &[&parent])
+            .unwrap()
+    };
+    let master = repo.find_local_branch("master").unwrap();
+    let base = commit(master.id, "a\nb\nc\nd\ne\n");
+    // The change as first written, then replayed onto a neighboring change
+    let original = commit(base, "a\nb\nC\nd\ne\n");
+    let upstream = commit(base, "a\nB\nc\nd\ne\n");
+    let restacked = commit(upstream, "a\nB\nC\nd\ne\n");
+    // A conflict resolved differently
+    let drifted = commit(upstream, "a\nB\nX\nd\ne\n");
+
+    assert_eq!(repo.content_id(original), repo.content_id(restacked));
+    assert_ne!(repo.content_id(original), repo.content_id(drifted));
+    assert!(repo.content_id(original).is_some());
+    // `patch_id` hashes the neighboring lines in too
+    assert_ne!(repo.patch_id(original), repo.patch_id(restacked));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn commit_cache() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let head_id = {
+        let raw = git2::Repository::discover(temp.path()).unwrap();
+        let head = raw.head().unwrap().peel_to_commit().unwrap();
+        let author = git2::Signature::now("Author", "author@example.com").unwrap();
+        let committer = git2::Signature::now("Committer", "committer@example.com").unwrap();
+        let tree = head.tree().unwrap();
+        raw.commit(
+            Some("HEAD"),
+            &author,
+            &committer,
+            "Applied",
+            &tree,
+            &[&head],
+        )
+        .unwrap()
+    };
+
+    let expected = {
+        let mut repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+        repo.open_commit_cache();
+        repo.head_commit()
+    };
+    assert!(temp.path().join(".git/stack/commits").exists());
+
+    let mut repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+    repo.open_commit_cache();
+    let actual = repo.head_commit();
+    assert_eq!(actual, expected);
+    assert_eq!(actual.id, head_id);
+    assert_eq!(actual.author.as_deref(), Some("Author"));
+    assert_eq!(actual.committer.as_deref(), Some("Committer"));
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let repo_config = git_stack::config::RepoConfig::from_all(&raw).unwrap();
+    assert!(!repo_config.commit_cache());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn prune_commit_cache() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    {
+        let mut repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+        repo.open_commit_cache();
+        repo.head_commit();
+        assert_eq!(repo.prune_commit_cache(true), 0);
+    }
+    {
+        let db = open_commit_cache(temp.path());
+        let tree = db.open_tree("commits-v2").unwrap();
+        tree.insert([0xab; 20], &b"{}"[..]).unwrap();
+        tree.flush().unwrap();
+        let stale = db.open_tree("commits-v1").unwrap();
+        stale.insert([0xcd; 20], &b"{}"[..]).unwrap();
+        stale.flush().unwrap();
+    }
+
+    let mut repo = GitRepo::new(git2::Repository::discover(temp.path()).unwrap());
+    repo.open_commit_cache();
+    assert_eq!(repo.prune_commit_cache(true), 1);
+    assert_eq!(repo.prune_commit_cache(false), 1);
+    assert_eq!(repo.prune_commit_cache(true), 0);
+    repo.head_commit();
+    assert_eq!(repo.prune_commit_cache(true), 0);
+    drop(repo);
+
+    let db = open_commit_cache(temp.path());
+    assert!(!db
+        .tree_names()
+        .iter()
+        .any(|name| &name[..] == b"commits-v1"));
+
+    temp.close().unwrap();
+}
+
+/// sled's background threads can hold the lock for a moment after the last handle is dropped
+fn open_commit_cache(root: &std::path::Path) -> sled::Db {
+    let path = root.join(".git/stack/commits");
+    let mut attempts = 0;
+    loop {
+        match sled::open(&path) {
+            Ok(db) => return db,
+            Err(err) if attempts < 50 => {
+                attempts += 1;
+                eprintln!("Retrying {}: {}", path.display(), err);
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            Err(err) => panic!("{}: {}", path.display(), err),
+        }
+    }
+}
+
+#[test]
+fn config_conditional_include() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let include = temp.child("work.gitconfig");
+    include
+        .write_str("[stack]\n\tprotected-branch = work-*\n\tpush-remote = upstream\n")
+        .unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let workdir = temp.path().canonicalize().unwrap();
+    let mut config = raw.config().unwrap();
+    config
+        .set_str(
+            &format!("includeIf.gitdir:{}/.path", workdir.display()),
+            include.path().to_str().unwrap(),
+        )
+        .unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let repo_config = git_stack::config::RepoConfig::from_all(&raw).unwrap();
+    assert_eq!(repo_config.push_remote(), "upstream");
+    assert!(repo_config
+        .protected_branches()
+        .iter()
+        .any(|b| b == "work-*"));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn config_diagnostics() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let mut config = raw.config().unwrap();
+    config
+        .set_str("stack.protect-commit-age", "2 weeks")
+        .unwrap();
+    config.set_str("stack.show-format", "fancy").unwrap();
+    config.set_str("stack.shwo-stacked", "true").unwrap();
+
+    let diagnostics = git_stack::config::RepoConfig::diagnose(&raw);
+    let keys: Vec<_> = diagnostics.iter().map(|d| d.key.as_str()).collect();
+    assert_eq!(keys, ["stack.show-format", "stack.shwo-stacked"]);
+    assert!(diagnostics.iter().all(|d| d.line.is_some()));
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn load_config_for_write() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    let include_path = temp.path().join("team.gitconfig");
+    std::fs::write(
+        &include_path,
+        "[stack]\n\tprotected-branch = release/*\n\tprotect-commit-count = 5\n",
+    )
+    .unwrap();
+    {
+        let mut config = raw.config().unwrap();
+        config
+            .set_str("include.path", include_path.to_str().unwrap())
+            .unwrap();
+        config
+            .set_multivar("stack.protected-branch", "^$", "main")
+            .unwrap();
+        config
+            .set_str("stack.protect-commit-age", "2 weeks")
+            .unwrap();
+    }
+
+    let mut repo_config = git_stack::config::RepoConfig::load_for_write(&raw).unwrap();
+    assert_eq!(repo_config.protected_branches(), ["main"]);
+    assert_eq!(repo_config.protect_commit_count, None);
+    assert_eq!(
+        repo_config.protect_commit_age(),
+        std::time::Duration::from_secs(60 * 60 * 24 * 14)
+    );
+    repo_config
+        .protected_branches
+        .get_or_insert_with(Vec::new)
+        .push("hotfix".to_owned());
+    repo_config.write_repo(&raw).unwrap();
+
+    let written = std::fs::read_to_string(raw.path().join("config")).unwrap();
+    assert!(written.contains("hotfix"), "{}", written);
+    assert!(!written.contains("release/*"), "{}", written);
+    assert!(!written.contains("protect-commit-count"), "{}", written);
+    let repo_config = git_stack::config::RepoConfig::from_repo(&raw).unwrap();
+    assert_eq!(
+        repo_config.protected_branches(),
+        ["release/*", "main", "hotfix"]
+    );
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn apply_config_fragment() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let raw = git2::Repository::discover(temp.path()).unwrap();
+    raw.config()
+        .unwrap()
+        .set_multivar("stack.protected-branch", "^$", "main")
+        .unwrap();
+    let fragment_path = temp.path().join("team.gitconfig");
+    std::fs::write(
+        &fragment_path,
+        "[stack]\n\tprotected-branch = main\n\tprotected-branch = release/*\n\tprotect-commit-age = 1 week\n",
+    )
+    .unwrap();
+
+    for _ in 0..2 {
+        let fragment = git_stack::config::RepoConfig::from_path(&fragment_path).unwrap();
+        git_stack::config::RepoConfig::load_for_write(&raw)
+            .unwrap()
+            .update(fragment)
+            .write_repo(&raw)
+            .unwrap();
+        git_stack::config::RepoConfig::record_source(&raw, "team.gitconfig").unwrap();
+    }
+
+    let repo_config = git_stack::config::RepoConfig::from_repo(&raw).unwrap();
+    assert_eq!(repo_config.protected_branches(), ["main", "release/*"]);
+    assert_eq!(
+        repo_config.protect_commit_age(),
+        std::time::Duration::from_secs(60 * 60 * 24 * 7)
+    );
+    let config = raw.config().unwrap();
+    let sources: Vec<_> = config
+        .multivar("stack.config-source", None)
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.value().map(|v| v.to_owned()))
+        .collect();
+    assert_eq!(sources, ["team.gitconfig"]);
+    assert!(git_stack::config::RepoConfig::diagnose(&raw).is_empty());
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn cherry_pick_conflicts() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/conflict.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let repo = GitRepo::new(repo);
+
+    let base = repo.find_local_branch("base").unwrap();
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+
+    {
+        let actual = repo.cherry_pick_conflicts(master.id, feature1.id).unwrap();
+        assert_eq!(actual, vec![std::path::PathBuf::from("file_a.txt")]);
+    }
+
+    {
+        let actual = repo.cherry_pick_conflicts(base.id, feature1.id).unwrap();
+        assert_eq!(actual, Vec::<std::path::PathBuf>::new());
+    }
+
+    {
+        let paths = [std::path::PathBuf::from("file_a.txt")];
+        let actual: Vec<_> = repo
+            .commits_touching(master.id, feature1.id, &paths)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.summary.to_string())
+            .collect();
+        assert_eq!(actual, vec!["4".to_owned(), "5".to_owned()]);
+
+        let paths = [std::path::PathBuf::from("file_b.txt")];
+        let actual = repo
+            .commits_touching(master.id, feature1.id, &paths)
+            .unwrap();
+        assert!(actual.is_empty());
+    }
+
+    temp.close().unwrap();
+}
+
+#[test]
+fn journal_recovery() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    let path = Journal::path(&repo);
+    let mut repo = GitRepo::new(repo);
+
+    let master = repo.find_local_branch("master").unwrap();
+    let feature1 = repo.find_local_branch("feature1").unwrap();
+    let feature2 = repo.find_local_branch("feature2").unwrap();
+    let journal = Journal {
+        updates: vec![
+            RefUpdate {
+                name: "feature1".to_owned(),
+                old: Some(feature1.id),
+                new: Some(master.id),
+            },
+            RefUpdate {
+                name: "feature2".to_owned(),
+                old: Some(feature2.id),
+                new: None,
+            },
+        ],
+        restore_branch: Some("master".to_owned()),
+        snapshot: None,
+    };
+    journal.save(&path).unwrap();
+    let journal = Journal::load(&path).unwrap();
+
+    // Interrupted after moving only `feature1`
+    repo.branch("feature1", master.id).unwrap();
+    journal.complete(&mut repo).unwrap();
+    assert_eq!(repo.find_local_branch("feature1").unwrap().id, master.id);
+    assert_eq!(repo.find_local_branch("feature2"), None);
+
+    journal.rollback(&mut repo).unwrap();
+    assert_eq!(repo.find_local_branch("feature1").unwrap().id, feature1.id);
+    assert_eq!(repo.find_local_branch("feature2").unwrap().id, feature2.id);
+
+    // Something else moved the branch, so we can't tell what to do
+    repo.branch("feature1", feature2.id).unwrap();
+    assert!(journal.complete(&mut repo).is_err());
+
+    Journal::remove(&path).unwrap();
+    assert!(!path.exists());
+
+    temp.close().unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn forge_command() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    let requests = temp.child("requests.txt");
+    let script = temp.child("forge.sh");
+    script
+        .write_str(&format!(
+            r#"read -r request
+echo "$1 $request" >> {}
+case "$1" in
+  list-protected) echo '["release/*"]' ;;
+  create-pr) echo '{{"number": 3, "url": "https://example.com/3"}}' ;;
+  get-status) echo '{{"status": "pending"}}' ;;
+esac
+"#,
+            requests.path().display()
+        ))
+        .unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    repo.remote("origin", "https://example.com/project.git")
+        .unwrap();
+    let mut config = repo.config().unwrap();
+    config
+        .set_str(
+            "stack.forge-command",
+            &format!("sh {}", script.path().display()),
+        )
+        .unwrap();
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo).unwrap();
+
+    let mut forge = git_stack::forge::from_config(&repo, &repo_config)
+        .unwrap()
+        .unwrap();
+    assert_eq!(forge.list_protected().unwrap(), vec!["release/*"]);
+    let pr = forge
+        .create_pr(&git_stack::forge::NewPullRequest {
+            head: "feature1",
+            base: "master",
+            title: "Feature",
+            body: "",
+            draft: false,
+        })
+        .unwrap();
+    assert_eq!(pr.number, 3);
+    forge.update_base(3, "base").unwrap();
+    assert_eq!(
+        forge.get_status("feature1").unwrap(),
+        git_stack::forge::Status::Pending
+    );
+    forge
+        .trigger_ci(&git_stack::forge::CiRun {
+            branch: "feature1",
+            commit: "abc123".to_owned(),
+            workflow: Some("ci.yml"),
+        })
+        .unwrap();
+
+    requests.assert(
+        r#"list-protected {"remote":"origin","url":"https://example.com/project.git"}
+create-pr {"base":"master","body":"","draft":false,"head":"feature1","remote":"origin","title":"Feature","url":"https://example.com/project.git"}
+update-base {"base":"base","number":3,"remote":"origin","url":"https://example.com/project.git"}
+get-status {"branch":"feature1","remote":"origin","url":"https://example.com/project.git"}
+trigger-ci {"branch":"feature1","commit":"abc123","remote":"origin","url":"https://example.com/project.git","workflow":"ci.yml"}
+"#,
+    );
+}
+
+#[test]
+fn wait_for_status() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let plan = git_fixture::Dag::load(std::path::Path::new("tests/fixtures/branches.yml")).unwrap();
+    plan.run(temp.path()).unwrap();
+
+    // `feature1` passes on the second poll, `feature2` fails right away
+    let polls = temp.child("polls.txt");
+    let script = temp.child("forge.sh");
+    script
+        .write_str(&format!(
+            r#"read -r request
+case "$request" in
+  *feature1*)
+    echo >> {}
+    if [ "$(wc -l < {})" -ge 2 ]; then echo '{{"status": "success"}}'; else echo '{{"status": "none"}}'; fi ;;
+  *) echo '{{"status": "failure"}}' ;;
+esac
+"#,
+            polls.path().display(),
+            polls.path().display()
+        ))
+        .unwrap();
+
+    let repo = git2::Repository::discover(temp.path()).unwrap();
+    repo.remote("origin", "https://example.com/project.git")
+        .unwrap();
+    let mut config = repo.config().unwrap();
+    config
+        .set_str(
+            "stack.forge-command",
+            &format!("sh {}", script.path().display()),
+        )
+        .unwrap();
+    let repo_config = git_stack::config::RepoConfig::from_all(&repo).unwrap();
+    let mut forge = git_stack::forge::from_config(&repo, &repo_config)
+        .unwrap()
+        .unwrap();
+
+    let mut done = Vec::new();
+    let statuses = git_stack::forge::wait_for_status(
+        forge.as_mut(),
+        &["feature1", "feature2"],
+        std::time::Duration::from_secs(60),
+        std::time::Duration::from_millis(10),
+        &git_stack::git::Cancel::new(),
+        |branch, status| done.push((branch.to_owned(), status)),
+    )
+    .unwrap();
+    let expected = vec![
+        ("feature1".to_owned(), git_stack::forge::Status::Success),
+        ("feature2".to_owned(), git_stack::forge::Status::Failure),
+    ];
+    assert_eq!(statuses, expected);
+    // `feature2` was done before `feature1`
+    assert_eq!(done, expected.into_iter().rev().collect::<Vec<_>>());
+}
+
+#[test]
+fn prefetch_matches_serial() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let raw = git2::Repository::init(temp.path()).unwrap();
+    let signature = git2::Signature::now("Jane Doe", "jdoe@example.com").unwrap();
+    let commit = |parent_id: Option<git2::Oid>, summary: String| {
+        let parent = parent_id.map(|id| raw.find_commit(id).unwrap());
+        let mut tree = raw
+            .treebuilder(parent.as_ref().map(|p| p.tree().unwrap()).as_ref())
+            .unwrap();
+        let blob = raw.blob(summary.as_bytes()).unwrap();
+        tree.insert("file.txt", blob, 0o100644).unwrap();
+        let tree = raw.find_tree(tree.write().unwrap()).unwrap();
+        let parents: Vec<_> = parent.iter().collect();
+        raw.commit(None, &signature, &signature, &summary, &tree, &parents)
+            .unwrap()
+    };
+    let mut base_ids = Vec::new();
+    let mut parent_id = None;
+    for i in 0..40 {
+        let id = commit(parent_id, format!("master {}", i));
+        base_ids.push(id);
+        parent_id = Some(id);
+    }
+    let master_id = parent_id.unwrap();
+    let mut ranges = Vec::new();
+    for (s, base_id) in base_ids.iter().step_by(2).enumerate() {
+        let mut head_id = *base_id;
+        for c in 0..3 {
+            head_id = commit(Some(head_id), format!("stack {} {}", s, c));
+        }
+        ranges.push((*base_id, head_id));
+    }
+    let pairs: Vec<_> = ranges.iter().map(|(_, head)| (master_id, *head)).collect();
+
+    let mut serial = GitRepo::new(git2::Repository::open(temp.path()).unwrap());
+    serial.set_jobs(Some(1));
+    let mut parallel = GitRepo::new(git2::Repository::open(temp.path()).unwrap());
+    parallel.set_jobs(Some(4));
+    parallel.prefetch_merge_bases(&pairs);
+    parallel.prefetch_commits(&ranges);
+    parallel.prefetch_patch_ids(&base_ids);
+
+    for (one, two) in pairs.iter() {
+        assert_eq!(
+            serial.merge_base(*one, *two),
+            parallel.merge_base(*one, *two)
+        );
+    }
+    for (base, head) in ranges.iter() {
+        let walk = |repo: &GitRepo| -> Vec<_> {
+            repo.commits_from(*head)
+                .take_while(|c| c.id != *base)
+                .map(|c| (c.id, c.summary.clone(), c.author.clone()))
+                .collect()
+        };
+        assert_eq!(walk(&serial), walk(&parallel));
+    }
+    for id in base_ids.iter() {
+        assert!(parallel.patch_id(*id).is_some());
+        assert_eq!(serial.patch_id(*id), parallel.patch_id(*id));
+    }
+
+    temp.close().unwrap();
+}